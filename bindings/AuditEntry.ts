@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AuditAction } from "./AuditAction";
+import type { AuditActor } from "./AuditActor";
+
+export interface AuditEntry { id: bigint, time: bigint, action: AuditAction, actor: AuditActor, target: string | null, details: string, }
\ No newline at end of file
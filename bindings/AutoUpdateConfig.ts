@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AutoUpdateMode } from "./AutoUpdateMode";
+import type { MaintenanceWindow } from "./MaintenanceWindow";
+
+export interface AutoUpdateConfig { mode: AutoUpdateMode, maintenance_window: MaintenanceWindow | null, }
\ No newline at end of file
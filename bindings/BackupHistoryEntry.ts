@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { BackupOutcome } from "./BackupOutcome";
+
+export interface BackupHistoryEntry { time: bigint, label: string | null, outcome: BackupOutcome, }
\ No newline at end of file
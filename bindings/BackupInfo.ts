@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface BackupInfo { name: string, creation_time: bigint | null, size: bigint, stored_locally: boolean, stored_remotely: boolean, }
\ No newline at end of file
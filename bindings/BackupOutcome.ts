@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type BackupOutcome = { type: "Succeeded", name: string, } | { type: "Failed", reason: string, } | { type: "Skipped", reason: string, };
\ No newline at end of file
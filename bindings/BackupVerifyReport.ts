@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { BackupIssue } from "./BackupIssue";
+
+export interface BackupVerifyReport { name: string, files_checked: bigint, hashes_checked: boolean, issues: Array<BackupIssue>, }
\ No newline at end of file
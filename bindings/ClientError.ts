@@ -1,4 +1,4 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { ErrorKind } from "./ErrorKind";
 
-export interface ClientError { kind: ErrorKind, causes: Array<String>, }
\ No newline at end of file
+export interface ClientError { kind: ErrorKind, error_code: string, detail: string, causes: Array<String>, field_errors?: Record<string, string>, }
\ No newline at end of file
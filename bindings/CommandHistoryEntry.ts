@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CausedBy } from "./CausedBy";
+
+export interface CommandHistoryEntry { command: string, time: bigint, caused_by: CausedBy, }
\ No newline at end of file
@@ -1,4 +1,4 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { SectionManifest } from "./SectionManifest";
 
-export interface ConfigurableManifest { auto_start: boolean, restart_on_crash: boolean, setting_sections: Record<string, SectionManifest>, }
\ No newline at end of file
+export interface ConfigurableManifest { schema_version: number, auto_start: boolean, restart_on_crash: boolean, setting_sections: Record<string, SectionManifest>, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CrashReport } from "./CrashReport";
+
+export interface CrashInfo { time: bigint, exit_code: number | null, signal: number | null, is_oom: boolean, console_tail: Array<string>, crash_report: CrashReport | null, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Snowflake } from "./Snowflake";
+
+export type EventStreamSignal = { type: "Resync", missed: bigint, last_snowflake: Snowflake | null, };
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { GameRuleType } from "./GameRuleType";
+
+export interface GameRule { name: string, rule_type: GameRuleType, value: string, }
\ No newline at end of file
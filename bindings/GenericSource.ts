@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { BundlePermissions } from "./BundlePermissions";
+import type { GameType } from "./GameType";
+import type { SetupManifest } from "./SetupManifest";
+
+export interface GenericSource { link_to_source: string, game_type: GameType, setup_manifest: SetupManifest, requested_permissions: BundlePermissions, }
\ No newline at end of file
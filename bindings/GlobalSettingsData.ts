@@ -1,3 +1,7 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { HttpTimeouts } from "./HttpTimeouts";
+import type { LoginRateLimitConfig } from "./LoginRateLimitConfig";
+import type { SetupTimeouts } from "./SetupTimeouts";
+import type { ShutdownBehaviour } from "./ShutdownBehaviour";
 
-export interface GlobalSettingsData { core_name: string, safe_mode: boolean, domain: string | null, }
\ No newline at end of file
+export interface GlobalSettingsData { core_name: string, safe_mode: boolean, domain: string | null, timezone: string | null, shutdown_behaviour: ShutdownBehaviour, login_rate_limit: LoginRateLimitConfig, upstream_cache_ttl_secs: bigint, http_timeouts: HttpTimeouts, forge_installer_timeout_secs: bigint, setup_timeouts: SetupTimeouts, block_ram_overcommit: boolean, max_concurrent_starts: number | null, leak_existence: boolean, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstallationComponent } from "./InstallationComponent";
+
+export interface InstallationIssue { component: InstallationComponent, message: string, suggested_fix: string, repairable: boolean, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstanceUuid } from "./InstanceUuid";
+
+export interface InstalledJre { name: string, major_version: bigint | null, verified: boolean, size_bytes: bigint, used_by: Array<InstanceUuid>, }
\ No newline at end of file
@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type InstanceEventKind = "StateTransition" | "InstanceWarning" | "InstanceError" | "InstanceInput" | "InstanceOutput" | "SystemMessage" | "PlayerChange" | "PlayerMessage";
\ No newline at end of file
+export type InstanceEventKind = "StateTransition" | "InstanceWarning" | "InstanceError" | "InstanceDebug" | "InstanceInput" | "InstanceOutput" | "SystemMessage" | "PlayerChange" | "PlayerMessage" | "TickPerformance";
\ No newline at end of file
@@ -1,7 +1,11 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AvailableUpdate } from "./AvailableUpdate";
+import type { CrashInfo } from "./CrashInfo";
 import type { Game } from "./Game";
+import type { InstanceDiskUsage } from "./InstanceDiskUsage";
+import type { InstanceSetupProgress } from "./InstanceSetupProgress";
 import type { InstanceState } from "./InstanceState";
 import type { InstanceUuid } from "./InstanceUuid";
 import type { Player } from "./Player";
 
-export interface InstanceInfo { uuid: InstanceUuid, name: string, game_type: Game, description: string, version: string, port: number, creation_time: bigint, path: string, auto_start: boolean, restart_on_crash: boolean, state: InstanceState, player_count: number | null, max_player_count: number | null, player_list: Array<Player> | null, }
\ No newline at end of file
+export interface InstanceInfo { uuid: InstanceUuid, name: string, game_type: Game, description: string, version: string, port: number, creation_time: bigint, path: string, auto_start: boolean, restart_on_crash: boolean, state: InstanceState, player_count: number | null, max_player_count: number | null, player_list: Array<Player> | null, last_crash: CrashInfo | null, tags: Array<string>, setup_progress: InstanceSetupProgress | null, available_update: AvailableUpdate | null, core_id: string | null, start_queue_position: number | null, uptime_seconds: bigint | null, last_started: bigint | null, disk_usage: InstanceDiskUsage | null, }
\ No newline at end of file
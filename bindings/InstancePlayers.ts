@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstanceUuid } from "./InstanceUuid";
+
+export interface InstancePlayers { instance_uuid: InstanceUuid, instance_name: string, player_count: number, max_player_count: number | null, players: Array<string>, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type LaunchMode = { type: "JavaJar" } | { type: "Script", path: string, args: Array<string>, } | { type: "ForgeRunScript" };
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface LifecycleHooks { before_start: string | null, after_start: string | null, before_stop: string | null, after_stop: string | null, start_despite_failure: boolean, timeout_secs: number | null, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface LoginRateLimitConfig { max_attempts: number, window_secs: bigint, base_lockout_secs: bigint, max_lockout_secs: bigint, }
\ No newline at end of file
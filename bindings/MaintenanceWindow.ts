@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface MaintenanceWindow { start_hour: number, end_hour: number, }
\ No newline at end of file
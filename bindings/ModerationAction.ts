@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { GameMode } from "./GameMode";
+import type { TeleportDestination } from "./TeleportDestination";
+import type { Weather } from "./Weather";
+
+export type ModerationAction = { type: "SetGameMode", player: string, game_mode: GameMode, } | { type: "Teleport", player: string, destination: TeleportDestination, } | { type: "Give", player: string, item: string, count: number, } | { type: "SetTime", ticks: number, } | { type: "SetWeather", weather: Weather, duration_secs: number | null, };
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstanceUuid } from "./InstanceUuid";
+import type { RoleAction } from "./RoleAction";
+
+export interface NewRole { name: string, actions: Array<RoleAction>, instances: Array<InstanceUuid> | null, }
\ No newline at end of file
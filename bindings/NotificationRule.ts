@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { EventLevel } from "./EventLevel";
+import type { EventType } from "./EventType";
+import type { InstanceEventKind } from "./InstanceEventKind";
+import type { NotificationChannel } from "./NotificationChannel";
+
+export interface NotificationRule { channel: NotificationChannel, event_types: Array<EventType> | null, instance_event_types: Array<InstanceEventKind> | null, min_level: EventLevel | null, }
\ No newline at end of file
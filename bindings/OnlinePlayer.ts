@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Player } from "./Player";
+import type { PlayerDetails } from "./PlayerDetails";
+
+export interface OnlinePlayer { player: Player, is_op: boolean | null, is_whitelisted: boolean | null, details: PlayerDetails | null, }
\ No newline at end of file
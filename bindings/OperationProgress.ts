@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CausedBy } from "./CausedBy";
+import type { ProgressionStartValue } from "./ProgressionStartValue";
+import type { Snowflake } from "./Snowflake";
+
+export interface OperationProgress { event_id: Snowflake, progression_name: string, total: number | null, progress: number, message: string | null, inner: ProgressionStartValue | null, caused_by: CausedBy, started_at: bigint, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PaperBuildChannel } from "./PaperBuildChannel";
+
+export interface PaperBuild { build: bigint, channel: PaperBuildChannel, time: string, download_name: string, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface PlayerDetails { position: [number, number, number] | null, dimension: string | null, health: number | null, game_mode: string | null, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstancePlayers } from "./InstancePlayers";
+
+export interface PlayersOnline { total: number, instances: Array<InstancePlayers>, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface PropertyChange { key: string, old_value: string | null, new_value: string | null, }
\ No newline at end of file
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstanceUuid } from "./InstanceUuid";
+import type { RoleAction } from "./RoleAction";
+import type { RoleId } from "./RoleId";
+
+export interface Role { id: RoleId, name: string, actions: Array<RoleAction>, instances: Array<InstanceUuid> | null, builtin: boolean, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type RoleAction = "ViewInstance" | "StartInstance" | "StopInstance" | "AccessConsole" | "ViewConsole" | "AccessSetting" | "ReadResource" | "WriteResource" | "AccessMacro" | "ReadInstanceFile" | "WriteInstanceFile" | "CreateInstance" | "DeleteInstance" | "ReadGlobalFile" | "WriteGlobalFile" | "ManagePermission" | "ManagePlayers" | "BackupInstance" | "ManageOffsiteBackup";
\ No newline at end of file
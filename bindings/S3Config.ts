@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface S3Config { endpoint: string, region: string, bucket: string, prefix: string, access_key_id: string, secret_access_key: string, delete_local_copy: boolean, }
\ No newline at end of file
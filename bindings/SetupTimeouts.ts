@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface SetupTimeouts { setup_timeout_secs: bigint, jre_download_timeout_secs: bigint, jar_download_timeout_secs: bigint, }
\ No newline at end of file
@@ -1,4 +1,4 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { SectionManifestValue } from "./SectionManifestValue";
 
-export interface SetupValue { name: string, description: string | null, auto_start: boolean, restart_on_crash: boolean, setting_sections: Record<string, SectionManifestValue>, }
\ No newline at end of file
+export interface SetupValue { schema_version: number | null, name: string, description: string | null, auto_start: boolean, restart_on_crash: boolean, setting_sections: Record<string, SectionManifestValue>, }
\ No newline at end of file
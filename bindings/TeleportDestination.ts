@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type TeleportDestination = { type: "Coordinates", x: number, y: number, z: number, } | { type: "Player", name: string, };
\ No newline at end of file
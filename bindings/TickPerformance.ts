@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface TickPerformance { tps: number | null, tps_5m: number | null, tps_15m: number | null, mspt: number | null, measured_at: bigint, }
\ No newline at end of file
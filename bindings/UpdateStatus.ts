@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AvailableUpdate } from "./AvailableUpdate";
+
+export interface UpdateStatus { current_version: string, patch: AvailableUpdate | null, major: string | null, }
\ No newline at end of file
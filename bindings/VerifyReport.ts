@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstallationIssue } from "./InstallationIssue";
+
+export interface VerifyReport { issues: Array<InstallationIssue>, }
\ No newline at end of file
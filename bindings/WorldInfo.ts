@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface WorldInfo { level_name: string | null, version: string | null, data_version: number | null, last_played: bigint | null, spawn: [number, number, number] | null, game_rules: Record<string, string>, hardcore: boolean, difficulty: string | null, newer_than_server: boolean, }
\ No newline at end of file
@@ -1,6 +1,9 @@
 pub mod hashed_password;
 pub mod jwt_token;
 pub mod permission;
+pub mod rate_limiter;
+pub mod role;
+pub mod totp;
 pub mod user;
 pub mod user_id;
 pub mod user_secrets;
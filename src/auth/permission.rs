@@ -1,6 +1,7 @@
 use std::collections::HashSet;
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use ts_rs::TS;
 
 use crate::types::InstanceUuid;
@@ -10,7 +11,11 @@ pub struct UserPermission {
     pub can_view_instance: HashSet<InstanceUuid>,
     pub can_start_instance: HashSet<InstanceUuid>,
     pub can_stop_instance: HashSet<InstanceUuid>,
+    // sending commands, which also lets the console be viewed
     pub can_access_instance_console: HashSet<InstanceUuid>,
+    // viewing the console output without sending commands
+    #[serde(default)]
+    pub can_view_instance_console: HashSet<InstanceUuid>,
     pub can_access_instance_setting: HashSet<InstanceUuid>,
     pub can_read_instance_resource: HashSet<InstanceUuid>,
     // unsafe permission, owner exclusive unless explicitly granted
@@ -20,6 +25,14 @@ pub struct UserPermission {
     pub can_read_instance_file: HashSet<InstanceUuid>,
     // unsafe permission, owner exclusive unless explicitly granted
     pub can_write_instance_file: HashSet<InstanceUuid>,
+    #[serde(default)]
+    pub can_manage_instance_players: HashSet<InstanceUuid>,
+    #[serde(default)]
+    pub can_backup_instance: HashSet<InstanceUuid>,
+    // unsafe permission, owner exclusive unless explicitly granted
+    // sending backups to and fetching them from S3
+    #[serde(default)]
+    pub can_manage_offsite_backup: HashSet<InstanceUuid>,
 
     pub can_create_instance: bool,
     pub can_delete_instance: bool,
@@ -37,12 +50,16 @@ impl UserPermission {
             can_start_instance: HashSet::new(),
             can_stop_instance: HashSet::new(),
             can_access_instance_console: HashSet::new(),
+            can_view_instance_console: HashSet::new(),
             can_access_instance_setting: HashSet::new(),
             can_read_instance_resource: HashSet::new(),
             can_write_instance_resource: HashSet::new(),
             can_access_instance_macro: HashSet::new(),
             can_read_instance_file: HashSet::new(),
             can_write_instance_file: HashSet::new(),
+            can_manage_instance_players: HashSet::new(),
+            can_backup_instance: HashSet::new(),
+            can_manage_offsite_backup: HashSet::new(),
             can_create_instance: false,
             can_delete_instance: false,
             can_read_global_file: false,
@@ -57,3 +74,50 @@ impl Default for UserPermission {
         Self::new()
     }
 }
+
+/// Viewing an instance used to show its console too, so permissions saved before viewing the
+/// console was a permission of its own get it for every instance they can view
+pub fn migrate_view_console(permissions: &mut Value) {
+    let permissions = match permissions.as_object_mut() {
+        Some(permissions) => permissions,
+        None => return,
+    };
+    if permissions.contains_key("can_view_instance_console") {
+        return;
+    }
+    if let Some(can_view_instance) = permissions.get("can_view_instance").cloned() {
+        permissions.insert("can_view_instance_console".to_string(), can_view_instance);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_migrate_view_console() {
+        let instance = InstanceUuid::default();
+        let mut saved = serde_json::to_value(UserPermission::default()).unwrap();
+        saved
+            .as_object_mut()
+            .unwrap()
+            .remove("can_view_instance_console");
+        saved["can_view_instance"] = json!([instance]);
+
+        migrate_view_console(&mut saved);
+        let permissions: UserPermission = serde_json::from_value(saved).unwrap();
+        assert!(permissions.can_view_instance_console.contains(&instance));
+
+        // revoked since, left as is
+        let mut permissions = UserPermission::default();
+        permissions.can_view_instance.insert(instance);
+        let mut saved = serde_json::to_value(&permissions).unwrap();
+        migrate_view_console(&mut saved);
+        assert_eq!(
+            serde_json::from_value::<UserPermission>(saved).unwrap(),
+            permissions
+        );
+    }
+}
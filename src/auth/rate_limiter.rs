@@ -0,0 +1,317 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+/// Thresholds for rate limiting failed login attempts
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct LoginRateLimitConfig {
+    /// Failed attempts allowed within the window before locking out
+    pub max_attempts: u32,
+    /// Length of the sliding window in seconds
+    pub window_secs: u64,
+    /// Length of the first lockout in seconds, doubled on every subsequent lockout
+    pub base_lockout_secs: u64,
+    /// Upper bound on the length of a lockout in seconds
+    pub max_lockout_secs: u64,
+}
+
+impl Default for LoginRateLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            window_secs: 60,
+            base_lockout_secs: 30,
+            max_lockout_secs: 3600,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Attempts {
+    failures: VecDeque<Instant>,
+    lockouts: u32,
+    locked_until: Option<Instant>,
+    last_failure: Option<Instant>,
+    /// Attempts that are still being verified, counted towards the limit until they resolve
+    pending: Vec<Instant>,
+}
+
+#[derive(Hash, PartialEq, Eq, Clone)]
+enum AttemptKey {
+    Ip(IpAddr),
+    Username(String),
+}
+
+/// Tracks failed login attempts per IP and per username in memory
+#[derive(Default)]
+pub struct LoginRateLimiter {
+    config: LoginRateLimitConfig,
+    attempts: HashMap<AttemptKey, Attempts>,
+}
+
+impl LoginRateLimiter {
+    pub fn new(config: LoginRateLimitConfig) -> Self {
+        Self {
+            config,
+            attempts: HashMap::new(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: LoginRateLimitConfig) {
+        self.config = config;
+    }
+
+    fn keys(ip: Option<IpAddr>, username: &str) -> Vec<AttemptKey> {
+        let mut keys = vec![AttemptKey::Username(username.to_lowercase())];
+        if let Some(ip) = ip {
+            keys.push(AttemptKey::Ip(ip));
+        }
+        keys
+    }
+
+    /// Returns how long the caller has to wait before trying again, if locked out
+    pub fn check(&self, ip: Option<IpAddr>, username: &str, now: Instant) -> Option<Duration> {
+        Self::keys(ip, username)
+            .iter()
+            .filter_map(|key| self.attempts.get(key)?.locked_until)
+            .filter(|locked_until| *locked_until > now)
+            .max()
+            .map(|locked_until| locked_until - now)
+    }
+
+    /// Registers an attempt before its credentials are verified,
+    /// so that concurrent guesses can't get past the limit while the lock is released.
+    ///
+    /// Returns how long the caller has to wait if the attempt is rejected.
+    /// The attempt has to be resolved with `record_failure` or `record_success` with the same `now`,
+    /// unresolved attempts stop counting once they leave the window
+    pub fn begin_attempt(
+        &mut self,
+        ip: Option<IpAddr>,
+        username: &str,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        self.prune(now);
+        if let Some(retry_after) = self.check(ip, username, now) {
+            return Err(retry_after);
+        }
+        let window = Duration::from_secs(self.config.window_secs);
+        let keys = Self::keys(ip, username);
+        for key in keys.iter() {
+            if let Some(attempts) = self.attempts.get_mut(key) {
+                attempts
+                    .pending
+                    .retain(|pending| now.duration_since(*pending) <= window);
+                if (attempts.failures.len() + attempts.pending.len()) as u32
+                    >= self.config.max_attempts
+                {
+                    return Err(Duration::from_secs(1));
+                }
+            }
+        }
+        for key in keys {
+            self.attempts.entry(key).or_default().pending.push(now);
+        }
+        Ok(())
+    }
+
+    fn resolve_attempt(attempts: &mut Attempts, attempted_at: Instant) {
+        if let Some(idx) = attempts
+            .pending
+            .iter()
+            .position(|pending| *pending == attempted_at)
+        {
+            attempts.pending.remove(idx);
+        }
+    }
+
+    pub fn record_failure(&mut self, ip: Option<IpAddr>, username: &str, now: Instant) {
+        self.prune(now);
+        let window = Duration::from_secs(self.config.window_secs);
+        for key in Self::keys(ip, username) {
+            let attempts = self.attempts.entry(key).or_default();
+            Self::resolve_attempt(attempts, now);
+            while attempts
+                .failures
+                .front()
+                .map(|failure| now.duration_since(*failure) > window)
+                .unwrap_or(false)
+            {
+                attempts.failures.pop_front();
+            }
+            attempts.failures.push_back(now);
+            attempts.last_failure = Some(now);
+            if attempts.failures.len() as u32 >= self.config.max_attempts {
+                let lockout = self
+                    .config
+                    .base_lockout_secs
+                    .saturating_mul(1_u64.checked_shl(attempts.lockouts).unwrap_or(u64::MAX))
+                    .min(self.config.max_lockout_secs);
+                attempts.locked_until = Some(now + Duration::from_secs(lockout));
+                attempts.lockouts = attempts.lockouts.saturating_add(1);
+                attempts.failures.clear();
+            }
+        }
+    }
+
+    /// Clears the username's failures, the IP's failures are left to expire on their own
+    pub fn record_success(&mut self, ip: Option<IpAddr>, username: &str, attempted_at: Instant) {
+        for key in Self::keys(ip, username) {
+            if let Some(attempts) = self.attempts.get_mut(&key) {
+                Self::resolve_attempt(attempts, attempted_at);
+                if let AttemptKey::Username(_) = key {
+                    // other attempts of the username may still be in progress
+                    *attempts = Attempts {
+                        pending: std::mem::take(&mut attempts.pending),
+                        ..Default::default()
+                    };
+                }
+            }
+        }
+    }
+
+    /// Forgets about keys that haven't failed for longer than the longest lockout
+    fn prune(&mut self, now: Instant) {
+        let forget_after =
+            Duration::from_secs(self.config.max_lockout_secs.max(self.config.window_secs));
+        self.attempts.retain(|_, attempts| {
+            attempts
+                .last_failure
+                .map(|last_failure| now.duration_since(last_failure) <= forget_after)
+                .unwrap_or(false)
+                || attempts
+                    .locked_until
+                    .map(|locked_until| locked_until > now)
+                    .unwrap_or(false)
+                || !attempts.pending.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_login_rate_limiter() {
+        let mut limiter = LoginRateLimiter::new(LoginRateLimitConfig {
+            max_attempts: 3,
+            window_secs: 60,
+            base_lockout_secs: 10,
+            max_lockout_secs: 25,
+        });
+        let ip: Option<IpAddr> = Some("127.0.0.1".parse().unwrap());
+        let start = Instant::now();
+
+        for i in 0..2 {
+            limiter.record_failure(ip, "owner", start + Duration::from_secs(i));
+        }
+        assert_eq!(limiter.check(ip, "owner", start), None);
+        limiter.record_failure(ip, "owner", start + Duration::from_secs(2));
+        assert_eq!(
+            limiter.check(ip, "owner", start + Duration::from_secs(2)),
+            Some(Duration::from_secs(10))
+        );
+        // the username is locked out from any ip
+        assert!(limiter
+            .check(None, "Owner", start + Duration::from_secs(2))
+            .is_some());
+        assert_eq!(
+            limiter.check(ip, "owner", start + Duration::from_secs(12)),
+            None
+        );
+
+        // the second lockout is twice as long, the third is capped
+        let start = start + Duration::from_secs(12);
+        for i in 0..3 {
+            limiter.record_failure(ip, "owner", start + Duration::from_secs(i));
+        }
+        assert_eq!(
+            limiter.check(ip, "owner", start + Duration::from_secs(2)),
+            Some(Duration::from_secs(20))
+        );
+        let start = start + Duration::from_secs(22);
+        for i in 0..3 {
+            limiter.record_failure(ip, "owner", start + Duration::from_secs(i));
+        }
+        assert_eq!(
+            limiter.check(ip, "owner", start + Duration::from_secs(2)),
+            Some(Duration::from_secs(25))
+        );
+
+        limiter.record_success(ip, "owner", start);
+        assert_eq!(limiter.check(None, "owner", start), None);
+        // a successful login doesn't lift the lockout on the ip
+        assert!(limiter.check(ip, "owner", start).is_some());
+    }
+
+    #[test]
+    fn test_login_rate_limiter_sliding_window() {
+        let mut limiter = LoginRateLimiter::new(LoginRateLimitConfig {
+            max_attempts: 3,
+            window_secs: 10,
+            base_lockout_secs: 10,
+            max_lockout_secs: 60,
+        });
+        let start = Instant::now();
+        limiter.record_failure(None, "owner", start);
+        limiter.record_failure(None, "owner", start + Duration::from_secs(5));
+        // the first failure has left the window
+        limiter.record_failure(None, "owner", start + Duration::from_secs(11));
+        assert_eq!(
+            limiter.check(None, "owner", start + Duration::from_secs(11)),
+            None
+        );
+        limiter.record_failure(None, "owner", start + Duration::from_secs(12));
+        assert!(limiter
+            .check(None, "owner", start + Duration::from_secs(12))
+            .is_some());
+    }
+
+    #[test]
+    fn test_login_rate_limiter_concurrent_attempts() {
+        let mut limiter = LoginRateLimiter::new(LoginRateLimitConfig {
+            max_attempts: 3,
+            window_secs: 10,
+            base_lockout_secs: 10,
+            max_lockout_secs: 60,
+        });
+        let ip: Option<IpAddr> = Some("127.0.0.1".parse().unwrap());
+        let start = Instant::now();
+        let attempts: Vec<Instant> = (0..3).map(|i| start + Duration::from_millis(i)).collect();
+        for attempted_at in attempts.iter() {
+            assert!(limiter.begin_attempt(ip, "owner", *attempted_at).is_ok());
+        }
+        // attempts still being verified count towards the limit
+        assert!(limiter
+            .begin_attempt(ip, "owner", start + Duration::from_millis(3))
+            .is_err());
+
+        limiter.record_success(ip, "owner", attempts[0]);
+        assert!(limiter
+            .begin_attempt(ip, "owner", start + Duration::from_millis(4))
+            .is_ok());
+        limiter.record_failure(ip, "owner", attempts[1]);
+        limiter.record_failure(ip, "owner", attempts[2]);
+        limiter.record_failure(ip, "owner", start + Duration::from_millis(4));
+        assert!(limiter
+            .check(ip, "owner", start + Duration::from_millis(5))
+            .is_some());
+
+        // an attempt that is never resolved stops counting once it leaves the window
+        let start = start + Duration::from_secs(100);
+        for i in 0..3 {
+            assert!(limiter
+                .begin_attempt(None, "admin", start + Duration::from_millis(i))
+                .is_ok());
+        }
+        assert!(limiter.begin_attempt(None, "admin", start).is_err());
+        assert!(limiter
+            .begin_attempt(None, "admin", start + Duration::from_secs(11))
+            .is_ok());
+    }
+}
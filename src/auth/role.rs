@@ -0,0 +1,251 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::types::InstanceUuid;
+
+use super::user::UserAction;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, TS)]
+#[serde(transparent)]
+#[ts(export)]
+pub struct RoleId(String);
+
+impl Default for RoleId {
+    fn default() -> Self {
+        Self(format!("ROLE_{}", uuid::Uuid::new_v4()))
+    }
+}
+
+impl From<String> for RoleId {
+    fn from(id: String) -> Self {
+        Self(id)
+    }
+}
+
+impl AsRef<str> for RoleId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A `UserAction` without the instance it applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub enum RoleAction {
+    ViewInstance,
+    StartInstance,
+    StopInstance,
+    AccessConsole,
+    ViewConsole,
+    AccessSetting,
+    ReadResource,
+    WriteResource,
+    AccessMacro,
+    ReadInstanceFile,
+    WriteInstanceFile,
+    ManagePlayers,
+    BackupInstance,
+    ManageOffsiteBackup,
+    CreateInstance,
+    DeleteInstance,
+    ReadGlobalFile,
+    WriteGlobalFile,
+    ManagePermission,
+}
+
+impl RoleAction {
+    /// Unsafe and owner exclusive actions, only the owner can grant them through a role
+    pub fn is_unsafe(&self) -> bool {
+        matches!(
+            self,
+            RoleAction::WriteResource
+                | RoleAction::AccessMacro
+                | RoleAction::WriteInstanceFile
+                | RoleAction::ManageOffsiteBackup
+                | RoleAction::WriteGlobalFile
+                | RoleAction::ManagePermission
+        )
+    }
+
+    /// The `UserAction` granted on `instance`, which is ignored for global actions
+    pub fn to_user_action(self, instance: &InstanceUuid) -> UserAction {
+        let instance = instance.clone();
+        match self {
+            RoleAction::ViewInstance => UserAction::ViewInstance(instance),
+            RoleAction::StartInstance => UserAction::StartInstance(instance),
+            RoleAction::StopInstance => UserAction::StopInstance(instance),
+            RoleAction::AccessConsole => UserAction::AccessConsole(instance),
+            RoleAction::ViewConsole => UserAction::ViewConsole(instance),
+            RoleAction::AccessSetting => UserAction::AccessSetting(instance),
+            RoleAction::ReadResource => UserAction::ReadResource(instance),
+            RoleAction::WriteResource => UserAction::WriteResource(instance),
+            RoleAction::AccessMacro => UserAction::AccessMacro(Some(instance)),
+            RoleAction::ReadInstanceFile => UserAction::ReadInstanceFile(instance),
+            RoleAction::WriteInstanceFile => UserAction::WriteInstanceFile(instance),
+            RoleAction::ManagePlayers => UserAction::ManagePlayers(instance),
+            RoleAction::BackupInstance => UserAction::BackupInstance(instance),
+            RoleAction::ManageOffsiteBackup => UserAction::ManageOffsiteBackup(instance),
+            RoleAction::CreateInstance => UserAction::CreateInstance,
+            RoleAction::DeleteInstance => UserAction::DeleteInstance,
+            RoleAction::ReadGlobalFile => UserAction::ReadGlobalFile,
+            RoleAction::WriteGlobalFile => UserAction::WriteGlobalFile,
+            RoleAction::ManagePermission => UserAction::ManagePermission,
+        }
+    }
+
+    /// Splits a `UserAction` into its role action and the instance it's scoped to
+    fn from_user_action(action: &UserAction) -> Option<(RoleAction, Option<&InstanceUuid>)> {
+        Some(match action {
+            UserAction::ViewInstance(uuid) => (RoleAction::ViewInstance, Some(uuid)),
+            UserAction::StartInstance(uuid) => (RoleAction::StartInstance, Some(uuid)),
+            UserAction::StopInstance(uuid) => (RoleAction::StopInstance, Some(uuid)),
+            UserAction::AccessConsole(uuid) => (RoleAction::AccessConsole, Some(uuid)),
+            UserAction::ViewConsole(uuid) => (RoleAction::ViewConsole, Some(uuid)),
+            UserAction::AccessSetting(uuid) => (RoleAction::AccessSetting, Some(uuid)),
+            UserAction::ReadResource(uuid) => (RoleAction::ReadResource, Some(uuid)),
+            UserAction::WriteResource(uuid) => (RoleAction::WriteResource, Some(uuid)),
+            UserAction::AccessMacro(uuid) => (RoleAction::AccessMacro, Some(uuid.as_ref()?)),
+            UserAction::ReadInstanceFile(uuid) => (RoleAction::ReadInstanceFile, Some(uuid)),
+            UserAction::WriteInstanceFile(uuid) => (RoleAction::WriteInstanceFile, Some(uuid)),
+            UserAction::ManagePlayers(uuid) => (RoleAction::ManagePlayers, Some(uuid)),
+            UserAction::BackupInstance(uuid) => (RoleAction::BackupInstance, Some(uuid)),
+            UserAction::ManageOffsiteBackup(uuid) => (RoleAction::ManageOffsiteBackup, Some(uuid)),
+            UserAction::CreateInstance => (RoleAction::CreateInstance, None),
+            UserAction::DeleteInstance => (RoleAction::DeleteInstance, None),
+            UserAction::ReadGlobalFile => (RoleAction::ReadGlobalFile, None),
+            UserAction::WriteGlobalFile => (RoleAction::WriteGlobalFile, None),
+            UserAction::ManagePermission => (RoleAction::ManagePermission, None),
+            // managing users is owner exclusive and can't be granted
+            UserAction::ManageUser => return None,
+        })
+    }
+}
+
+/// A named set of actions that can be assigned to users
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct Role {
+    pub id: RoleId,
+    pub name: String,
+    pub actions: HashSet<RoleAction>,
+    /// Instances the instance specific actions apply to, all instances if `None`
+    pub instances: Option<HashSet<InstanceUuid>>,
+    /// Built-in roles can't be edited or deleted
+    #[serde(default)]
+    pub builtin: bool,
+}
+
+impl Role {
+    pub fn grants(&self, action: &UserAction) -> bool {
+        match RoleAction::from_user_action(action) {
+            Some((role_action, instance)) => {
+                self.actions.contains(&role_action)
+                    && match (instance, &self.instances) {
+                        (Some(instance), Some(instances)) => instances.contains(instance),
+                        _ => true,
+                    }
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_unsafe(&self) -> bool {
+        self.actions.iter().any(RoleAction::is_unsafe)
+    }
+
+    pub fn builtin_roles() -> Vec<Role> {
+        let builtin = |id: &str, name: &str, actions: &[RoleAction]| Role {
+            id: RoleId(id.to_string()),
+            name: name.to_string(),
+            actions: actions.iter().copied().collect(),
+            instances: None,
+            builtin: true,
+        };
+        vec![
+            builtin(
+                "ROLE_ADMIN",
+                "Admin",
+                &[
+                    RoleAction::ViewInstance,
+                    RoleAction::StartInstance,
+                    RoleAction::StopInstance,
+                    RoleAction::AccessConsole,
+                    RoleAction::AccessSetting,
+                    RoleAction::ReadResource,
+                    RoleAction::ReadInstanceFile,
+                    RoleAction::ManagePlayers,
+                    RoleAction::BackupInstance,
+                    RoleAction::CreateInstance,
+                    RoleAction::DeleteInstance,
+                ],
+            ),
+            builtin(
+                "ROLE_OPERATOR",
+                "Operator",
+                &[
+                    RoleAction::ViewInstance,
+                    RoleAction::StartInstance,
+                    RoleAction::StopInstance,
+                    RoleAction::AccessConsole,
+                    RoleAction::ManagePlayers,
+                    RoleAction::BackupInstance,
+                ],
+            ),
+            builtin(
+                "ROLE_READ_ONLY",
+                "ReadOnly",
+                &[
+                    RoleAction::ViewInstance,
+                    RoleAction::ViewConsole,
+                    RoleAction::ReadResource,
+                    RoleAction::ReadInstanceFile,
+                ],
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_role_resolution() {
+        let instance = InstanceUuid::default();
+        let roles = Role::builtin_roles();
+        let operator = roles.iter().find(|role| role.name == "Operator").unwrap();
+        assert!(operator.grants(&UserAction::StartInstance(instance.clone())));
+        assert!(operator.grants(&UserAction::AccessConsole(instance.clone())));
+        assert!(!operator.grants(&UserAction::AccessSetting(instance.clone())));
+        assert!(!operator.grants(&UserAction::CreateInstance));
+        assert!(!operator.grants(&UserAction::ManageUser));
+        let read_only = roles.iter().find(|role| role.name == "ReadOnly").unwrap();
+        assert!(read_only.grants(&UserAction::ViewConsole(instance.clone())));
+        assert!(!read_only.grants(&UserAction::AccessConsole(instance.clone())));
+        assert!(roles.iter().all(|role| !role.is_unsafe()));
+    }
+
+    #[test]
+    fn test_scoped_role_resolution() {
+        let instance = InstanceUuid::from("instance_a".to_string());
+        let other_instance = InstanceUuid::from("instance_b".to_string());
+        let role = Role {
+            id: RoleId::default(),
+            name: "Macro runner".to_string(),
+            actions: [RoleAction::AccessMacro, RoleAction::ReadGlobalFile]
+                .into_iter()
+                .collect(),
+            instances: Some([instance.clone()].into_iter().collect()),
+            builtin: false,
+        };
+        assert!(role.grants(&UserAction::AccessMacro(Some(instance))));
+        assert!(!role.grants(&UserAction::AccessMacro(Some(other_instance))));
+        // global macros aren't instance scoped and can't be granted yet
+        assert!(!role.grants(&UserAction::AccessMacro(None)));
+        // global actions ignore the instance scope
+        assert!(role.grants(&UserAction::ReadGlobalFile));
+        assert!(role.is_unsafe());
+    }
+}
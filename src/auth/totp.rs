@@ -0,0 +1,300 @@
+use std::path::Path;
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, OsRng},
+    AeadCore, Aes256Gcm, Key, Nonce,
+};
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    util::rand_alphanumeric,
+};
+
+use super::hashed_password::{hash_password, HashedPassword};
+
+const TOTP_ISSUER: &str = "Lodestone";
+const RECOVERY_CODE_COUNT: usize = 8;
+const RECOVERY_CODE_LEN: usize = 10;
+
+/// Key used to encrypt TOTP secrets at rest, stored next to the users file
+#[derive(Clone)]
+pub struct TotpKey(Key<Aes256Gcm>);
+
+impl TotpKey {
+    pub async fn load_or_create(path: &Path) -> Result<Self, Error> {
+        if let Ok(encoded) = tokio::fs::read_to_string(path).await {
+            let bytes = base64::decode(encoded.trim()).context("Failed to decode TOTP key")?;
+            if bytes.len() != 32 {
+                return Err(eyre!("TOTP key at {} is corrupted", path.display()).into());
+            }
+            return Ok(Self(*Key::<Aes256Gcm>::from_slice(&bytes)));
+        }
+        let key = Aes256Gcm::generate_key(&mut OsRng);
+        tokio::fs::write(path, base64::encode(key))
+            .await
+            .context(format!("Failed to write TOTP key to {}", path.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+                .await
+                .context(format!(
+                    "Failed to set permissions of TOTP key at {}",
+                    path.display()
+                ))?;
+        }
+        Ok(Self(key))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EncryptedTotpSecret {
+    nonce: String,
+    ciphertext: String,
+}
+
+impl EncryptedTotpSecret {
+    fn encrypt(key: &TotpKey, secret: &[u8]) -> Result<Self, Error> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = Aes256Gcm::new(&key.0)
+            .encrypt(&nonce, secret)
+            .map_err(|_| eyre!("Failed to encrypt TOTP secret"))?;
+        Ok(Self {
+            nonce: base64::encode(nonce),
+            ciphertext: base64::encode(ciphertext),
+        })
+    }
+
+    fn decrypt(&self, key: &TotpKey) -> Result<Vec<u8>, Error> {
+        let nonce = base64::decode(&self.nonce).context("Failed to decode TOTP nonce")?;
+        let ciphertext =
+            base64::decode(&self.ciphertext).context("Failed to decode TOTP secret")?;
+        if nonce.len() != 12 {
+            return Err(eyre!("TOTP nonce is corrupted").into());
+        }
+        Ok(Aes256Gcm::new(&key.0)
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+            .map_err(|_| eyre!("Failed to decrypt TOTP secret"))?)
+    }
+}
+
+/// Two-factor authentication state of a user
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TwoFactor {
+    secret: EncryptedTotpSecret,
+    /// Set once the user proved they can generate codes, 2FA is only enforced after that
+    pub enabled: bool,
+    recovery_codes: Vec<HashedPassword>,
+    /// Time step of the last accepted TOTP code, a code can't be used twice
+    #[serde(default)]
+    last_used_step: Option<u64>,
+}
+
+/// A code that passed `TwoFactor::check`, it's only used up by `TwoFactor::consume`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerifiedCode {
+    /// A code from the authenticator app, identified by its time step
+    Totp(u64),
+    /// A recovery code, identified by its hash
+    Recovery(String),
+}
+
+/// Seconds since the unix epoch, the time TOTP codes are checked against
+pub fn unix_time() -> Result<u64, Error> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("System time is before the unix epoch")?
+        .as_secs())
+}
+
+/// Returned once on enrollment, the secret and recovery codes can't be retrieved again
+#[derive(Serialize, Deserialize, Clone, TS)]
+#[ts(export)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub provisioning_uri: String,
+    pub recovery_codes: Vec<String>,
+}
+
+fn totp(secret: Vec<u8>, username: &str) -> Result<TOTP, Error> {
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        Some(TOTP_ISSUER.to_string()),
+        // the account name of a provisioning uri can't contain a colon
+        username.replace(':', "_"),
+    )
+    .map_err(|e| Error {
+        kind: ErrorKind::Internal,
+        source: eyre!("Failed to create TOTP: {}", e),
+    })
+}
+
+impl TwoFactor {
+    pub fn enroll(key: &TotpKey, username: &str) -> Result<(Self, TotpEnrollment), Error> {
+        let secret = Secret::generate_secret()
+            .to_bytes()
+            .map_err(|e| eyre!("Failed to generate TOTP secret: {}", e))?;
+        let totp = totp(secret.clone(), username)?;
+        let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| rand_alphanumeric(RECOVERY_CODE_LEN))
+            .collect();
+        Ok((
+            Self {
+                secret: EncryptedTotpSecret::encrypt(key, &secret)?,
+                enabled: false,
+                recovery_codes: recovery_codes.iter().map(hash_password).collect(),
+                last_used_step: None,
+            },
+            TotpEnrollment {
+                secret: totp.get_secret_base32(),
+                provisioning_uri: totp.get_url(),
+                recovery_codes,
+            },
+        ))
+    }
+
+    /// Checks a code from the authenticator app at `time`, returning the time step it belongs to.
+    /// A code of a step that was already used is rejected
+    pub fn verify_totp(
+        &self,
+        key: &TotpKey,
+        username: &str,
+        code: &str,
+        time: u64,
+    ) -> Result<Option<u64>, Error> {
+        let mut totp = totp(self.secret.decrypt(key)?, username)?;
+        let skew = totp.skew as u64;
+        // each step is checked on its own to know which one matched
+        totp.skew = 0;
+        let current_step = time / totp.step;
+        Ok((current_step.saturating_sub(skew)..=current_step + skew)
+            .filter(|step| self.last_used_step.map_or(true, |last| *step > last))
+            .find(|step| totp.check(code.trim(), step * totp.step)))
+    }
+
+    /// Checks a code from the authenticator app or a recovery code without using it up.
+    ///
+    /// Recovery codes are hashed, so this is slow and shouldn't be called while holding a lock
+    pub fn check(
+        &self,
+        key: &TotpKey,
+        username: &str,
+        code: &str,
+        time: u64,
+    ) -> Result<Option<VerifiedCode>, Error> {
+        if let Some(step) = self.verify_totp(key, username, code, time)? {
+            return Ok(Some(VerifiedCode::Totp(step)));
+        }
+        let code = code.trim();
+        Ok(self
+            .recovery_codes
+            .iter()
+            .find(|recovery_code| **recovery_code == *code)
+            .map(|recovery_code| VerifiedCode::Recovery(recovery_code.to_string())))
+    }
+
+    /// Uses up a code that passed `check`, fails if it was used up in the meantime
+    pub fn consume(&mut self, code: &VerifiedCode) -> bool {
+        match code {
+            VerifiedCode::Totp(step) => {
+                if self.last_used_step.map_or(false, |last| *step <= last) {
+                    return false;
+                }
+                self.last_used_step = Some(*step);
+                true
+            }
+            VerifiedCode::Recovery(hash) => match self
+                .recovery_codes
+                .iter()
+                .position(|recovery_code| recovery_code.as_ref() == hash)
+            {
+                Some(idx) => {
+                    self.recovery_codes.remove(idx);
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+
+    /// Checks a code from the authenticator app or a recovery code, using it up on success
+    pub fn verify(
+        &mut self,
+        key: &TotpKey,
+        username: &str,
+        code: &str,
+        time: u64,
+    ) -> Result<bool, Error> {
+        Ok(match self.check(key, username, code, time)? {
+            Some(code) => self.consume(&code),
+            None => false,
+        })
+    }
+
+    pub fn remaining_recovery_codes(&self) -> usize {
+        self.recovery_codes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_two_factor() {
+        let temp_dir = tempdir::TempDir::new("test_totp").unwrap();
+        let key_path = temp_dir.path().join("totp_key");
+        let key = TotpKey::load_or_create(&key_path).await.unwrap();
+        let (mut two_factor, enrollment) = TwoFactor::enroll(&key, "test_user").unwrap();
+        assert_eq!(enrollment.recovery_codes.len(), RECOVERY_CODE_COUNT);
+
+        // the key survives a reload
+        let key = TotpKey::load_or_create(&key_path).await.unwrap();
+        let totp = TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            Secret::Encoded(enrollment.secret.clone())
+                .to_bytes()
+                .unwrap(),
+            None,
+            "".to_string(),
+        )
+        .unwrap();
+        let time = 1_700_000_000;
+        let code = totp.generate(time);
+        assert!(!two_factor
+            .verify(&key, "test_user", "000000x", time)
+            .unwrap());
+        assert!(two_factor.verify(&key, "test_user", &code, time).unwrap());
+        // a code can't be replayed while it's still valid
+        assert!(!two_factor
+            .verify(&key, "test_user", &code, time + 1)
+            .unwrap());
+        let next_code = totp.generate(time + 30);
+        assert!(two_factor
+            .verify(&key, "test_user", &next_code, time + 30)
+            .unwrap());
+
+        // recovery codes only work once
+        let recovery_code = enrollment.recovery_codes[0].clone();
+        let verified = two_factor
+            .check(&key, "test_user", &recovery_code, time)
+            .unwrap()
+            .unwrap();
+        assert!(two_factor.consume(&verified));
+        assert!(!two_factor.consume(&verified));
+        assert!(!two_factor
+            .verify(&key, "test_user", &recovery_code, time)
+            .unwrap());
+    }
+}
@@ -1,4 +1,7 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 
 use argon2::{Argon2, PasswordVerifier};
 use color_eyre::eyre::{eyre, Context};
@@ -12,13 +15,17 @@ use crate::{
     error::{Error, ErrorKind},
     event_broadcaster::EventBroadcaster,
     events::{CausedBy, Event, EventInner, UserEvent, UserEventInner},
+    global_settings::leak_existence,
+    notification::NotificationPreferences,
     types::{InstanceUuid, Snowflake},
 };
 
 use super::{
     hashed_password::{hash_password, HashedPassword},
     jwt_token::JwtToken,
-    permission::UserPermission,
+    permission::{migrate_view_console, UserPermission},
+    role::{Role, RoleId},
+    totp::{TotpEnrollment, TotpKey, TwoFactor, VerifiedCode},
     user_id::UserId,
     user_secrets::UserSecret,
 };
@@ -37,6 +44,15 @@ pub struct User {
     pub is_admin: bool,
     pub permissions: UserPermission,
     pub secret: UserSecret,
+    #[serde(default)]
+    pub two_factor: Option<TwoFactor>,
+    #[serde(default)]
+    pub roles: HashSet<RoleId>,
+    #[serde(default)]
+    pub notification_preferences: NotificationPreferences,
+    /// The roles in `roles`, filled in by the users manager so that role edits apply immediately
+    #[serde(skip)]
+    resolved_roles: Vec<Role>,
 }
 
 impl User {
@@ -55,8 +71,19 @@ impl User {
             is_admin,
             permissions,
             secret: UserSecret::default(),
+            two_factor: None,
+            roles: HashSet::new(),
+            notification_preferences: NotificationPreferences::default(),
+            resolved_roles: Vec::new(),
         }
     }
+
+    pub fn two_factor_enabled(&self) -> bool {
+        self.two_factor
+            .as_ref()
+            .map(|two_factor| two_factor.enabled)
+            .unwrap_or(false)
+    }
     fn get_permission_level(&self) -> u8 {
         if self.is_owner {
             u8::MAX
@@ -87,6 +114,7 @@ impl User {
                 || permissions.can_write_global_file
                 || permissions.can_manage_permission
                 || !permissions.can_write_instance_file.is_empty()
+                || !permissions.can_manage_offsite_backup.is_empty()
             {
                 Err(Error {
                     kind: ErrorKind::PermissionDenied,
@@ -106,10 +134,82 @@ impl User {
         }
     }
 
+    /// Checks if `self` is allowed to assign `roles` to `other`
+    pub fn try_assign_roles(&self, other: &User, roles: &[Role]) -> Result<(), Error> {
+        if self.get_permission_level() <= other.get_permission_level() {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("You don't have permission to manage other users' roles"),
+            });
+        }
+        if !self.is_owner && roles.iter().any(Role::is_unsafe) {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!(
+                    "Roles with unsafe and owner exclusive permissions can only be assigned by the owner"
+                ),
+            });
+        }
+        self.try_action(&UserAction::ManagePermission)
+    }
+
+    /// Checks if `self` is allowed to create, edit or delete `role`, held by `members`.
+    ///
+    /// Editing a role changes the permissions of everyone holding it,
+    /// so the requester must already be able to do everything the role grants and outrank its members
+    pub fn try_manage_role(&self, role: &Role, members: &[User]) -> Result<(), Error> {
+        self.try_action(&UserAction::ManagePermission)?;
+        if role.is_unsafe() && !self.is_owner {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!(
+                    "Roles with unsafe and owner exclusive permissions can only be managed by the owner"
+                ),
+            });
+        }
+        if !self.holds_role(role) {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("You can't manage a role granting permissions you don't have"),
+            });
+        }
+        if members.iter().any(|member| {
+            member.uid != self.uid && self.get_permission_level() <= member.get_permission_level()
+        }) {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("You can't manage a role held by users that don't rank below you"),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks if `self` can already perform every action `role` grants
+    fn holds_role(&self, role: &Role) -> bool {
+        // an instance nobody was given anything on, only grants covering every instance apply to it
+        let any_instance = InstanceUuid::default();
+        let instances: Vec<&InstanceUuid> = match &role.instances {
+            Some(instances) => instances.iter().collect(),
+            None => vec![&any_instance],
+        };
+        role.actions.iter().all(|action| {
+            let global_action = action.to_user_action(&any_instance);
+            if global_action.instance_uuid().is_none() {
+                return self.can_perform_action(&global_action);
+            }
+            instances
+                .iter()
+                .all(|instance| self.can_perform_action(&action.to_user_action(instance)))
+        })
+    }
+
     pub fn can_perform_action(&self, action: &UserAction) -> bool {
         if self.is_owner {
             return true;
         }
+        if self.resolved_roles.iter().any(|role| role.grants(action)) {
+            return true;
+        }
         match action {
             UserAction::ViewInstance(instance_id) => {
                 self.is_admin || self.permissions.can_view_instance.contains(instance_id)
@@ -127,6 +227,15 @@ impl User {
                         .can_access_instance_console
                         .contains(instance_id)
             }
+            UserAction::ViewConsole(instance_id) => {
+                self.is_admin
+                    || self
+                        .permissions
+                        .can_view_instance_console
+                        .contains(instance_id)
+                    // whoever can send commands sees what they answer
+                    || self.can_perform_action(&UserAction::AccessConsole(instance_id.clone()))
+            }
             UserAction::AccessSetting(instance_id) => {
                 self.is_admin
                     || self
@@ -160,6 +269,20 @@ impl User {
                         .can_write_instance_file
                         .contains(instance_id)
             }
+            UserAction::ManagePlayers(instance_id) => {
+                self.is_admin
+                    || self
+                        .permissions
+                        .can_manage_instance_players
+                        .contains(instance_id)
+            }
+            UserAction::BackupInstance(instance_id) => {
+                self.is_admin || self.permissions.can_backup_instance.contains(instance_id)
+            }
+            UserAction::ManageOffsiteBackup(instance_id) => self
+                .permissions
+                .can_manage_offsite_backup
+                .contains(instance_id),
             UserAction::AccessMacro(Some(instance_id)) => self
                 .permissions
                 .can_access_instance_macro
@@ -175,9 +298,18 @@ impl User {
         }
     }
 
+    /// Fails with `PermissionDenied` if the user can't perform the action.
+    ///
+    /// Denied instance actions fail with the same `NotFound` as a missing instance instead,
+    /// so that users can't find out which instances exist, unless the core is set to leak their existence
     pub fn try_action(&self, action: &UserAction) -> Result<(), Error> {
         if self.can_perform_action(action) {
             Ok(())
+        } else if action.instance_uuid().is_some() && !leak_existence() {
+            Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })
         } else {
             Err(Error {
                 kind: ErrorKind::PermissionDenied,
@@ -194,6 +326,9 @@ impl User {
                     UserAction::AccessConsole(_) => {
                         eyre!("You don't have permission to access this instance's console")
                     }
+                    UserAction::ViewConsole(_) => {
+                        eyre!("You don't have permission to view this instance's console")
+                    }
                     UserAction::AccessSetting(_) => {
                         eyre!("You don't have permission to access this instance's setting")
                     }
@@ -212,6 +347,15 @@ impl User {
                     UserAction::WriteInstanceFile(_) => {
                         eyre!("You don't have permission to write this instance's file")
                     }
+                    UserAction::ManagePlayers(_) => {
+                        eyre!("You don't have permission to manage this instance's players")
+                    }
+                    UserAction::BackupInstance(_) => {
+                        eyre!("You don't have permission to back up this instance")
+                    }
+                    UserAction::ManageOffsiteBackup(_) => {
+                        eyre!("You don't have permission to manage this instance's offsite backups")
+                    }
                     UserAction::CreateInstance => {
                         eyre!("You don't have permission to create instance")
                     }
@@ -235,8 +379,16 @@ impl User {
 
     pub fn can_view_event(&self, event: impl AsRef<Event>) -> bool {
         match &event.as_ref().event_inner {
-            EventInner::InstanceEvent(event) => {
-                self.can_perform_action(&UserAction::ViewInstance(event.instance_uuid.clone()))
+            EventInner::InstanceEvent(instance_event) => {
+                if event.as_ref().is_event_console_message() {
+                    self.can_perform_action(&UserAction::ViewConsole(
+                        instance_event.instance_uuid.clone(),
+                    ))
+                } else {
+                    self.can_perform_action(&UserAction::ViewInstance(
+                        instance_event.instance_uuid.clone(),
+                    ))
+                }
             }
             EventInner::UserEvent(_event) => self.can_perform_action(&UserAction::ManageUser),
             EventInner::FSEvent(_) => self.can_perform_action(&UserAction::ManageUser),
@@ -267,13 +419,22 @@ pub enum UserAction {
     ViewInstance(InstanceUuid),
     StartInstance(InstanceUuid),
     StopInstance(InstanceUuid),
+    /// Sending commands to the console
     AccessConsole(InstanceUuid),
+    /// Seeing the console output, without sending commands
+    ViewConsole(InstanceUuid),
     AccessSetting(InstanceUuid),
     ReadResource(InstanceUuid),
     WriteResource(InstanceUuid),
     AccessMacro(Option<InstanceUuid>),
     ReadInstanceFile(InstanceUuid),
     WriteInstanceFile(InstanceUuid),
+    ManagePlayers(InstanceUuid),
+    /// Taking a backup on demand
+    BackupInstance(InstanceUuid),
+    /// Setting up where backups are uploaded to, and listing and fetching the uploaded ones.
+    /// The world leaves the machine, so it isn't implied by being an admin
+    ManageOffsiteBackup(InstanceUuid),
 
     // global actions:
     CreateInstance,
@@ -284,6 +445,34 @@ pub enum UserAction {
     ManagePermission,
 }
 
+impl UserAction {
+    /// The instance the action is performed on, `None` for global actions
+    pub fn instance_uuid(&self) -> Option<&InstanceUuid> {
+        match self {
+            UserAction::ViewInstance(uuid)
+            | UserAction::StartInstance(uuid)
+            | UserAction::StopInstance(uuid)
+            | UserAction::AccessConsole(uuid)
+            | UserAction::ViewConsole(uuid)
+            | UserAction::AccessSetting(uuid)
+            | UserAction::ReadResource(uuid)
+            | UserAction::WriteResource(uuid)
+            | UserAction::ReadInstanceFile(uuid)
+            | UserAction::WriteInstanceFile(uuid)
+            | UserAction::ManagePlayers(uuid)
+            | UserAction::BackupInstance(uuid)
+            | UserAction::ManageOffsiteBackup(uuid) => Some(uuid),
+            UserAction::AccessMacro(uuid) => uuid.as_ref(),
+            UserAction::CreateInstance
+            | UserAction::DeleteInstance
+            | UserAction::ReadGlobalFile
+            | UserAction::WriteGlobalFile
+            | UserAction::ManageUser
+            | UserAction::ManagePermission => None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, TS)]
 #[ts(export)]
 pub struct PublicUser {
@@ -292,6 +481,8 @@ pub struct PublicUser {
     pub is_owner: bool,
     pub is_admin: bool,
     pub permissions: UserPermission,
+    pub two_factor_enabled: bool,
+    pub roles: HashSet<RoleId>,
 }
 
 impl From<&User> for PublicUser {
@@ -302,6 +493,8 @@ impl From<&User> for PublicUser {
             is_owner: user.is_owner,
             is_admin: user.is_admin,
             permissions: user.permissions.clone(),
+            two_factor_enabled: user.two_factor_enabled(),
+            roles: user.roles.clone(),
         }
     }
 }
@@ -309,11 +502,13 @@ impl From<&User> for PublicUser {
 impl From<User> for PublicUser {
     fn from(user: User) -> Self {
         PublicUser {
+            two_factor_enabled: user.two_factor_enabled(),
             uid: user.uid,
             username: user.username,
             is_owner: user.is_owner,
             is_admin: user.is_admin,
             permissions: user.permissions,
+            roles: user.roles,
         }
     }
 }
@@ -323,6 +518,8 @@ pub struct UsersManager {
     event_broadcaster: EventBroadcaster,
     users: HashMap<UserId, User>,
     path_to_users: PathBuf,
+    /// Custom roles, the built-in ones are not persisted
+    roles: HashMap<RoleId, Role>,
 }
 
 impl UsersManager {
@@ -335,6 +532,7 @@ impl UsersManager {
             event_broadcaster,
             users,
             path_to_users,
+            roles: HashMap::new(),
         }
     }
     pub async fn load_users(&mut self) -> Result<(), Error> {
@@ -360,7 +558,7 @@ impl UsersManager {
             warn!("No user file found, creating a new one");
             self.users = HashMap::new();
         } else {
-            let users: HashMap<UserId, User> = serde_json::from_reader(
+            let mut users: serde_json::Value = serde_json::from_reader(
                 tokio::fs::File::open(&self.path_to_users)
                     .await
                     .context(format!(
@@ -371,11 +569,171 @@ impl UsersManager {
                     .await,
             )
             .context("Failed to deserialize user json")?;
-            self.users = users;
+            if let Some(users) = users.as_object_mut() {
+                for user in users.values_mut() {
+                    if let Some(permissions) = user.get_mut("permissions") {
+                        migrate_view_console(permissions);
+                    }
+                }
+            }
+            self.users =
+                serde_json::from_value(users).context("Failed to deserialize user json")?;
         }
+        self.load_roles().await
+    }
+
+    fn path_to_roles(&self) -> PathBuf {
+        self.path_to_users.with_file_name("roles.json")
+    }
+
+    async fn load_roles(&mut self) -> Result<(), Error> {
+        let path_to_roles = self.path_to_roles();
+        if !path_to_roles.exists() {
+            self.roles = HashMap::new();
+            return Ok(());
+        }
+        let roles: Vec<Role> =
+            serde_json::from_slice(&tokio::fs::read(&path_to_roles).await.context(format!(
+                "Failed to read role file : {}",
+                path_to_roles.display()
+            ))?)
+            .context("Failed to deserialize role json")?;
+        self.roles = roles
+            .into_iter()
+            .filter(|role| !role.builtin)
+            .map(|role| (role.id.clone(), role))
+            .collect();
         Ok(())
     }
 
+    async fn write_roles_to_file(&self) -> Result<(), Error> {
+        let path_to_roles = self.path_to_roles();
+        tokio::fs::write(
+            &path_to_roles,
+            serde_json::to_string(&self.roles.values().collect::<Vec<_>>())
+                .context("Failed to serialize role json")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write role file : {}",
+            path_to_roles.display()
+        ))?;
+        Ok(())
+    }
+
+    pub fn get_role(&self, role_id: &RoleId) -> Option<Role> {
+        Role::builtin_roles()
+            .into_iter()
+            .find(|role| &role.id == role_id)
+            .or_else(|| self.roles.get(role_id).cloned())
+    }
+
+    pub fn list_roles(&self) -> Vec<Role> {
+        let mut roles = Role::builtin_roles();
+        roles.extend(self.roles.values().cloned());
+        roles
+    }
+
+    pub async fn create_role(&mut self, mut role: Role) -> Result<Role, Error> {
+        role.id = RoleId::default();
+        role.builtin = false;
+        self.roles.insert(role.id.clone(), role.clone());
+        if let Err(e) = self.write_roles_to_file().await {
+            self.roles.remove(&role.id);
+            return Err(e);
+        }
+        Ok(role)
+    }
+
+    fn get_custom_role(&self, role_id: &RoleId) -> Result<Role, Error> {
+        match self.get_role(role_id) {
+            Some(role) if role.builtin => Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Built-in roles can't be modified"),
+            }),
+            Some(role) => Ok(role),
+            None => Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Role not found"),
+            }),
+        }
+    }
+
+    /// Replaces a custom role, the change applies to every user with the role
+    pub async fn update_role(&mut self, role_id: &RoleId, mut role: Role) -> Result<(), Error> {
+        let old_role = self.get_custom_role(role_id)?;
+        role.id = role_id.clone();
+        role.builtin = false;
+        self.roles.insert(role_id.clone(), role);
+        if let Err(e) = self.write_roles_to_file().await {
+            self.roles.insert(role_id.clone(), old_role);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// The users the role is assigned to
+    pub fn role_members(&self, role_id: &RoleId) -> Vec<User> {
+        self.users
+            .values()
+            .filter(|user| user.roles.contains(role_id))
+            .cloned()
+            .map(|user| self.resolve_roles(user))
+            .collect()
+    }
+
+    /// Deletes a custom role and unassigns it from every user
+    pub async fn delete_role(&mut self, role_id: &RoleId) -> Result<(), Error> {
+        let old_role = self.get_custom_role(role_id)?;
+        self.roles.remove(role_id);
+        if let Err(e) = self.write_roles_to_file().await {
+            self.roles.insert(role_id.clone(), old_role);
+            return Err(e);
+        }
+        for user in self.users.values_mut() {
+            user.roles.remove(role_id);
+        }
+        self.write_to_file().await
+    }
+
+    pub async fn set_roles(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        roles: HashSet<RoleId>,
+    ) -> Result<(), Error> {
+        if let Some(role_id) = roles
+            .iter()
+            .find(|role_id| self.get_role(role_id).is_none())
+        {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Role {} not found", role_id.as_ref()),
+            });
+        }
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let old_roles = std::mem::replace(&mut user.roles, roles);
+        if let Err(e) = self.write_to_file().await {
+            if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                user.roles = old_roles;
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Fills in the roles of a user so its permissions can be checked
+    fn resolve_roles(&self, mut user: User) -> User {
+        user.resolved_roles = user
+            .roles
+            .iter()
+            .filter_map(|role_id| self.get_role(role_id))
+            .collect();
+        user
+    }
+
     async fn write_to_file(&self) -> Result<(), Error> {
         let mut file = tokio::fs::File::create(&self.path_to_users)
             .await
@@ -394,7 +752,10 @@ impl UsersManager {
         Ok(())
     }
     pub fn get_user(&self, uid: impl AsRef<UserId>) -> Option<User> {
-        self.users.get(uid.as_ref()).cloned()
+        self.users
+            .get(uid.as_ref())
+            .cloned()
+            .map(|user| self.resolve_roles(user))
     }
     pub async fn add_user(&mut self, user: User, caused_by: CausedBy) -> Result<(), Error> {
         if self.get_user_by_username(&user.username).is_some() {
@@ -598,6 +959,7 @@ impl UsersManager {
             .values()
             .find(|user| user.username == username.as_ref())
             .cloned()
+            .map(|user| self.resolve_roles(user))
     }
 
     pub async fn update_permissions(
@@ -649,7 +1011,7 @@ impl UsersManager {
         if claimed_uid != requester_uid {
             return None;
         }
-        Some(claimed_requester.to_owned())
+        Some(self.resolve_roles(claimed_requester.to_owned()))
     }
 
     pub fn try_auth_or_err(&self, token: &str) -> Result<User, Error> {
@@ -659,26 +1021,249 @@ impl UsersManager {
         })
     }
 
+    /// Logs in a user without two-factor authentication
     pub fn login(
         &self,
         username: impl AsRef<str>,
         password: impl AsRef<str>,
     ) -> Result<JwtToken, Error> {
-        let user = self.get_user_by_username(username).ok_or_else(|| Error {
-            kind: ErrorKind::Unauthorized,
-            source: eyre!("Credential mismatch"),
-        })?;
-        Argon2::default()
-            .verify_password(
-                password.as_ref().as_bytes(),
-                &argon2::PasswordHash::new(user.hashed_psw.as_ref()).unwrap(),
-            )
-            .map_err(|_| Error {
+        let user = self
+            .get_user_by_username(username)
+            .ok_or_else(credential_mismatch)?;
+        verify_password(&user, password.as_ref())?;
+        if user.two_factor_enabled() {
+            return Err(Error {
                 kind: ErrorKind::Unauthorized,
-                source: eyre!("Credential mismatch"),
-            })?;
+                source: eyre!("Two-factor authentication code required"),
+            });
+        }
         user.create_jwt()
     }
+
+    /// Logs in a user, requiring a TOTP or recovery code if they have two-factor authentication enabled
+    pub async fn login_with_two_factor(
+        &mut self,
+        username: impl AsRef<str>,
+        password: impl AsRef<str>,
+        code: Option<impl AsRef<str>>,
+        time: u64,
+    ) -> Result<JwtToken, Error> {
+        let login = Self::check_login(
+            self.get_user_by_username(username),
+            self.totp_key().await?,
+            password.as_ref().to_string(),
+            code.map(|code| code.as_ref().to_string()),
+            time,
+        )
+        .await?;
+        self.complete_login(login).await
+    }
+
+    /// Checks the password and two-factor code of a login against a snapshot of the user.
+    ///
+    /// Hashing is slow, so this runs on the blocking pool and doesn't need the users manager.
+    /// The two-factor code is only used up once the login is completed with `complete_login`
+    pub async fn check_login(
+        user: Option<User>,
+        totp_key: TotpKey,
+        password: String,
+        code: Option<String>,
+        time: u64,
+    ) -> Result<CheckedLogin, Error> {
+        tokio::task::spawn_blocking(move || {
+            let user = user.ok_or_else(credential_mismatch)?;
+            verify_password(&user, &password)?;
+            let code = match user.two_factor.as_ref().filter(|t| t.enabled) {
+                Some(two_factor) => {
+                    let code = code.ok_or_else(|| Error {
+                        kind: ErrorKind::Unauthorized,
+                        source: eyre!("Two-factor authentication code required"),
+                    })?;
+                    Some(
+                        two_factor
+                            .check(&totp_key, &user.username, &code, time)?
+                            .ok_or_else(invalid_two_factor_code)?,
+                    )
+                }
+                None => None,
+            };
+            Ok(CheckedLogin { user, code })
+        })
+        .await
+        .context("Failed to check login")?
+    }
+
+    /// Issues a token for a login checked by `check_login`, using up its two-factor code.
+    /// The login is rejected if the password or two-factor state changed in the meantime
+    pub async fn complete_login(&mut self, login: CheckedLogin) -> Result<JwtToken, Error> {
+        let user = self
+            .get_user(&login.user.uid)
+            .filter(|user| user.hashed_psw.as_ref() == login.user.hashed_psw.as_ref())
+            .ok_or_else(credential_mismatch)?;
+        if let Some(mut two_factor) = user.two_factor.clone().filter(|t| t.enabled) {
+            // a code checked by a concurrent login could have been used up since
+            let used = login
+                .code
+                .map(|code| two_factor.consume(&code))
+                .unwrap_or(false);
+            if !used {
+                return Err(invalid_two_factor_code());
+            }
+            self.set_two_factor(&user.uid, Some(two_factor)).await?;
+        }
+        user.create_jwt()
+    }
+
+    pub async fn totp_key(&self) -> Result<TotpKey, Error> {
+        TotpKey::load_or_create(&self.path_to_users.with_file_name("totp_key")).await
+    }
+
+    async fn set_two_factor(
+        &mut self,
+        uid: &UserId,
+        two_factor: Option<TwoFactor>,
+    ) -> Result<(), Error> {
+        let user = self.users.get_mut(uid).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let old_two_factor = std::mem::replace(&mut user.two_factor, two_factor);
+        if let Err(e) = self.write_to_file().await {
+            if let Some(user) = self.users.get_mut(uid) {
+                user.two_factor = old_two_factor;
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Replaces the notification preferences of a user, `None` resets them to the default
+    pub async fn set_notification_preferences(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        notification_preferences: Option<NotificationPreferences>,
+    ) -> Result<NotificationPreferences, Error> {
+        let notification_preferences = notification_preferences.unwrap_or_default();
+        notification_preferences.validate()?;
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let old_notification_preferences = std::mem::replace(
+            &mut user.notification_preferences,
+            notification_preferences.clone(),
+        );
+        if let Err(e) = self.write_to_file().await {
+            if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                user.notification_preferences = old_notification_preferences;
+            }
+            return Err(e);
+        }
+        Ok(notification_preferences)
+    }
+
+    /// Starts enrolling a user in two-factor authentication,
+    /// it's only enforced once confirmed with `confirm_two_factor`
+    pub async fn enroll_two_factor(
+        &mut self,
+        uid: impl AsRef<UserId>,
+    ) -> Result<TotpEnrollment, Error> {
+        let user = self.get_user(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        if user.two_factor_enabled() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Two-factor authentication is already enabled"),
+            });
+        }
+        let (two_factor, enrollment) = TwoFactor::enroll(&self.totp_key().await?, &user.username)?;
+        self.set_two_factor(&user.uid, Some(two_factor)).await?;
+        Ok(enrollment)
+    }
+
+    pub async fn confirm_two_factor(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        code: impl AsRef<str>,
+        time: u64,
+    ) -> Result<(), Error> {
+        let user = self.get_user(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let mut two_factor = user
+            .two_factor
+            .clone()
+            .filter(|t| !t.enabled)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("No pending two-factor authentication enrollment"),
+            })?;
+        let step = two_factor
+            .verify_totp(&self.totp_key().await?, &user.username, code.as_ref(), time)?
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid two-factor authentication code"),
+            })?;
+        two_factor.consume(&VerifiedCode::Totp(step));
+        two_factor.enabled = true;
+        self.set_two_factor(&user.uid, Some(two_factor)).await
+    }
+
+    pub async fn disable_two_factor(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        code: impl AsRef<str>,
+        time: u64,
+    ) -> Result<(), Error> {
+        let user = self.get_user(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let mut two_factor = user
+            .two_factor
+            .clone()
+            .filter(|t| t.enabled)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Two-factor authentication is not enabled"),
+            })?;
+        if !two_factor.verify(&self.totp_key().await?, &user.username, code.as_ref(), time)? {
+            return Err(invalid_two_factor_code());
+        }
+        self.set_two_factor(&user.uid, None).await
+    }
+}
+
+/// A login whose credentials passed `UsersManager::check_login`
+pub struct CheckedLogin {
+    user: User,
+    code: Option<VerifiedCode>,
+}
+
+fn credential_mismatch() -> Error {
+    Error {
+        kind: ErrorKind::Unauthorized,
+        source: eyre!("Credential mismatch"),
+    }
+}
+
+fn invalid_two_factor_code() -> Error {
+    Error {
+        kind: ErrorKind::Unauthorized,
+        source: eyre!("Invalid two-factor authentication code"),
+    }
+}
+
+fn verify_password(user: &User, password: &str) -> Result<(), Error> {
+    Argon2::default()
+        .verify_password(
+            password.as_bytes(),
+            &argon2::PasswordHash::new(user.hashed_psw.as_ref()).unwrap(),
+        )
+        .map_err(|_| credential_mismatch())
 }
 
 fn decode_token(token: &str, jwt_secret: &UserSecret) -> Option<UserId> {
@@ -829,4 +1414,390 @@ mod tests {
 
         assert!(users_manager.get_user_by_username("test_user1").is_some());
     }
+
+    #[tokio::test]
+    async fn test_two_factor_login() {
+        use super::*;
+        use totp_rs::{Algorithm, Secret, TOTP};
+        // create a temporary folder
+        let temp_dir = tempdir::TempDir::new("test_login").unwrap().into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        let test_user1 = User::new(
+            "test_user1".to_string(),
+            "12345",
+            true,
+            false,
+            UserPermission::default(),
+        );
+
+        users_manager
+            .add_user(test_user1.clone(), CausedBy::System)
+            .await
+            .unwrap();
+
+        let enrollment = users_manager
+            .enroll_two_factor(&test_user1.uid)
+            .await
+            .unwrap();
+        // not enforced until confirmed
+        users_manager.login("test_user1", "12345").unwrap();
+
+        let totp = TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            Secret::Encoded(enrollment.secret).to_bytes().unwrap(),
+            None,
+            "".to_string(),
+        )
+        .unwrap();
+        let time = 1_700_000_000;
+        let code = totp.generate(time);
+        // a code that isn't valid for any step around `time`
+        let wrong_code = ["000000", "000001", "000002", "000003"]
+            .into_iter()
+            .find(|wrong_code| {
+                (time / 30 - 1..=time / 30 + 1).all(|step| totp.generate(step * 30) != *wrong_code)
+            })
+            .unwrap();
+        assert!(users_manager
+            .confirm_two_factor(&test_user1.uid, wrong_code, time)
+            .await
+            .is_err());
+        users_manager
+            .confirm_two_factor(&test_user1.uid, &code, time)
+            .await
+            .unwrap();
+
+        assert!(users_manager.login("test_user1", "12345").is_err());
+        assert!(users_manager
+            .login_with_two_factor("test_user1", "12345", None::<&str>, time)
+            .await
+            .is_err());
+        // the code used to confirm can't be replayed
+        assert!(users_manager
+            .login_with_two_factor("test_user1", "12345", Some(&code), time)
+            .await
+            .is_err());
+        let next_code = totp.generate(time + 30);
+        users_manager
+            .login_with_two_factor("test_user1", "12345", Some(&next_code), time + 30)
+            .await
+            .unwrap();
+        assert!(users_manager
+            .login_with_two_factor("test_user1", "12345", Some(&next_code), time + 30)
+            .await
+            .is_err());
+        users_manager
+            .login_with_two_factor(
+                "test_user1",
+                "12345",
+                Some(&enrollment.recovery_codes[0]),
+                time,
+            )
+            .await
+            .unwrap();
+        assert!(users_manager
+            .login_with_two_factor(
+                "test_user1",
+                "12345",
+                Some(&enrollment.recovery_codes[0]),
+                time,
+            )
+            .await
+            .is_err());
+
+        // a code checked by two logins at once can only be used by one of them
+        let code = totp.generate(time + 60);
+        let check = || async {
+            UsersManager::check_login(
+                users_manager.get_user_by_username("test_user1"),
+                users_manager.totp_key().await.unwrap(),
+                "12345".to_string(),
+                Some(code.clone()),
+                time + 60,
+            )
+            .await
+            .unwrap()
+        };
+        let first = check().await;
+        let second = check().await;
+        users_manager.complete_login(first).await.unwrap();
+        assert!(users_manager.complete_login(second).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_role_resolution() {
+        use super::*;
+        use crate::auth::role::RoleAction;
+        // create a temporary folder
+        let temp_dir = tempdir::TempDir::new("test_login").unwrap().into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        let test_user1 = User::new(
+            "test_user1".to_string(),
+            "12345",
+            false,
+            false,
+            UserPermission::default(),
+        );
+        users_manager
+            .add_user(test_user1.clone(), CausedBy::System)
+            .await
+            .unwrap();
+
+        let instance = InstanceUuid::default();
+        let role = users_manager
+            .create_role(Role {
+                id: RoleId::default(),
+                name: "Console".to_string(),
+                actions: [RoleAction::AccessConsole].into_iter().collect(),
+                instances: Some([instance.clone()].into_iter().collect()),
+                builtin: false,
+            })
+            .await
+            .unwrap();
+        let read_only = Role::builtin_roles()
+            .into_iter()
+            .find(|role| role.name == "ReadOnly")
+            .unwrap();
+        users_manager
+            .set_roles(
+                &test_user1.uid,
+                [role.id.clone(), read_only.id.clone()]
+                    .into_iter()
+                    .collect(),
+            )
+            .await
+            .unwrap();
+
+        let user = users_manager.get_user(&test_user1.uid).unwrap();
+        assert!(user.can_perform_action(&UserAction::AccessConsole(instance.clone())));
+        assert!(!user.can_perform_action(&UserAction::AccessConsole(InstanceUuid::default())));
+        assert!(user.can_perform_action(&UserAction::ViewInstance(InstanceUuid::default())));
+        assert!(!user.can_perform_action(&UserAction::StartInstance(instance.clone())));
+
+        // role edits apply to every member
+        users_manager
+            .update_role(
+                &role.id,
+                Role {
+                    actions: [RoleAction::StartInstance].into_iter().collect(),
+                    ..role.clone()
+                },
+            )
+            .await
+            .unwrap();
+        let user = users_manager.get_user(&test_user1.uid).unwrap();
+        assert!(user.can_perform_action(&UserAction::StartInstance(instance.clone())));
+        assert!(!user.can_perform_action(&UserAction::AccessConsole(instance.clone())));
+
+        assert!(users_manager.delete_role(&read_only.id).await.is_err());
+        users_manager.delete_role(&role.id).await.unwrap();
+        let user = users_manager.get_user(&test_user1.uid).unwrap();
+        assert!(!user.roles.contains(&role.id));
+        assert!(!user.can_perform_action(&UserAction::StartInstance(instance)));
+    }
+
+    #[test]
+    fn test_manage_role_escalation() {
+        use super::*;
+        use crate::auth::role::RoleAction;
+        let manager_permissions = UserPermission {
+            can_manage_permission: true,
+            ..Default::default()
+        };
+        let manager = User::new(
+            "manager".to_string(),
+            "12345",
+            false,
+            true,
+            manager_permissions,
+        );
+        let role = |actions: &[RoleAction], instances: Option<HashSet<InstanceUuid>>| Role {
+            id: RoleId::default(),
+            name: "Custom".to_string(),
+            actions: actions.iter().copied().collect(),
+            instances,
+            builtin: false,
+        };
+        let instance = InstanceUuid::default();
+
+        // a role can't grant what the requester can't do themselves
+        assert!(manager
+            .try_manage_role(&role(&[RoleAction::StartInstance], None), &[])
+            .is_ok());
+        assert!(manager
+            .try_manage_role(&role(&[RoleAction::ReadGlobalFile], None), &[])
+            .is_err());
+        assert!(manager
+            .try_manage_role(
+                &role(
+                    &[RoleAction::ReadGlobalFile],
+                    Some([instance.clone()].into_iter().collect())
+                ),
+                &[]
+            )
+            .is_err());
+        let mut scoped_manager = manager.clone();
+        scoped_manager.is_admin = false;
+        scoped_manager
+            .permissions
+            .can_start_instance
+            .insert(instance.clone());
+        let scoped = role(
+            &[RoleAction::StartInstance],
+            Some([instance.clone()].into_iter().collect()),
+        );
+        assert!(scoped_manager.try_manage_role(&scoped, &[]).is_ok());
+        assert!(scoped_manager
+            .try_manage_role(&role(&[RoleAction::StartInstance], None), &[])
+            .is_err());
+
+        // nor change the permissions of users that don't rank below the requester
+        let safe_role = role(&[RoleAction::ViewInstance], None);
+        let user = User::new(
+            "user".to_string(),
+            "12345",
+            false,
+            false,
+            UserPermission::default(),
+        );
+        let other_admin = User::new(
+            "other_admin".to_string(),
+            "12345",
+            false,
+            true,
+            UserPermission::default(),
+        );
+        assert!(manager
+            .try_manage_role(&safe_role, &[user.clone(), manager.clone()])
+            .is_ok());
+        assert!(manager
+            .try_manage_role(&safe_role, &[user.clone(), other_admin])
+            .is_err());
+        assert!(scoped_manager.try_manage_role(&scoped, &[user]).is_err());
+    }
+
+    #[test]
+    fn test_view_only_console() {
+        use super::*;
+        use crate::events::{InstanceEvent, InstanceEventInner};
+        let instance = InstanceUuid::default();
+        let mut permissions = UserPermission::default();
+        permissions.can_view_instance.insert(instance.clone());
+        permissions
+            .can_view_instance_console
+            .insert(instance.clone());
+        let observer = User::new("observer".to_string(), "12345", false, false, permissions);
+        assert!(observer
+            .try_action(&UserAction::ViewConsole(instance.clone()))
+            .is_ok());
+        // but can't send commands
+        assert!(observer
+            .try_action(&UserAction::AccessConsole(instance.clone()))
+            .is_err());
+
+        let console_output = |instance_uuid: &InstanceUuid| Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: instance_uuid.clone(),
+                instance_name: "test".to_string(),
+                instance_event_inner: InstanceEventInner::InstanceOutput {
+                    message: "[Server thread/INFO]: Done".to_string(),
+                },
+            }),
+            snowflake: Snowflake::default(),
+            details: "".to_string(),
+            caused_by: CausedBy::System,
+        };
+        assert!(observer.can_view_event(console_output(&instance)));
+        assert!(!observer.can_view_event(console_output(&InstanceUuid::default())));
+
+        // viewing the instance alone doesn't show its console
+        let mut permissions = UserPermission::default();
+        permissions.can_view_instance.insert(instance.clone());
+        let viewer = User::new("viewer".to_string(), "12345", false, false, permissions);
+        assert!(!viewer.can_view_event(console_output(&instance)));
+
+        // sending commands implies seeing their output
+        let mut permissions = UserPermission::default();
+        permissions
+            .can_access_instance_console
+            .insert(instance.clone());
+        let operator = User::new("operator".to_string(), "12345", false, false, permissions);
+        assert!(operator.can_perform_action(&UserAction::ViewConsole(instance)));
+    }
+
+    #[test]
+    fn test_offsite_backup_not_implied_by_admin() {
+        use super::*;
+        let instance = InstanceUuid::default();
+        let admin = User::new(
+            "admin".to_string(),
+            "12345",
+            false,
+            true,
+            UserPermission::default(),
+        );
+        assert!(admin
+            .try_action(&UserAction::BackupInstance(instance.clone()))
+            .is_ok());
+        assert!(admin
+            .try_action(&UserAction::ManageOffsiteBackup(instance.clone()))
+            .is_err());
+
+        let mut permissions = UserPermission::default();
+        permissions
+            .can_manage_offsite_backup
+            .insert(instance.clone());
+        let granted = User::new("granted".to_string(), "12345", false, false, permissions);
+        assert!(granted
+            .try_action(&UserAction::ManageOffsiteBackup(instance))
+            .is_ok());
+        assert!(RoleAction::ManageOffsiteBackup.is_unsafe());
+    }
+
+    #[test]
+    fn test_denied_instance_indistinguishable_from_missing() {
+        use super::*;
+        let user = User::new(
+            "test_user1".to_string(),
+            "12345",
+            false,
+            false,
+            UserPermission::default(),
+        );
+        // whether or not these exist, the user can't tell them apart
+        let existing = InstanceUuid::default();
+        let missing = InstanceUuid::default();
+        for uuid in [&existing, &missing] {
+            let e = user
+                .try_action(&UserAction::ViewInstance(uuid.clone()))
+                .unwrap_err();
+            assert!(matches!(e.kind, ErrorKind::NotFound));
+            assert_eq!(e.source.to_string(), "Instance not found");
+            let e = user
+                .try_action(&UserAction::AccessSetting(uuid.clone()))
+                .unwrap_err();
+            assert!(matches!(e.kind, ErrorKind::NotFound));
+        }
+        // global actions have nothing to hide
+        assert!(matches!(
+            user.try_action(&UserAction::CreateInstance)
+                .unwrap_err()
+                .kind,
+            ErrorKind::PermissionDenied
+        ));
+
+        crate::global_settings::set_leak_existence(true);
+        let e = user
+            .try_action(&UserAction::ViewInstance(existing))
+            .unwrap_err();
+        crate::global_settings::set_leak_existence(false);
+        assert!(matches!(e.kind, ErrorKind::PermissionDenied));
+    }
 }
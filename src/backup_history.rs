@@ -0,0 +1,143 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::Context;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::Error;
+
+/// Name of the file the backup history is persisted to, inside the instance directory
+pub const BACKUP_HISTORY_FILE_NAME: &str = ".lodestone_backup_history.json";
+
+/// Number of backup attempts kept in the history of an instance
+pub const MAX_BACKUP_HISTORY_ENTRIES: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, Eq)]
+#[ts(export)]
+#[serde(tag = "type")]
+pub enum BackupOutcome {
+    Succeeded {
+        name: String,
+    },
+    Failed {
+        reason: String,
+    },
+    /// There was nothing to back up, e.g. the server hasn't generated its world yet
+    Skipped {
+        reason: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, Eq)]
+#[ts(export)]
+pub struct BackupHistoryEntry {
+    pub time: i64,
+    /// The operation the backup was taken before, `None` for regular backups
+    pub label: Option<String>,
+    pub outcome: BackupOutcome,
+}
+
+/// A bounded history of the backups attempted for an instance, persisted to disk
+pub struct BackupHistory {
+    path: PathBuf,
+    entries: VecDeque<BackupHistoryEntry>,
+    capacity: usize,
+}
+
+impl BackupHistory {
+    /// Loads the history of the instance, starting empty if there is none or it can't be read
+    pub async fn load(path_to_instance: &Path) -> Self {
+        Self::load_with_capacity(path_to_instance, MAX_BACKUP_HISTORY_ENTRIES).await
+    }
+
+    async fn load_with_capacity(path_to_instance: &Path, capacity: usize) -> Self {
+        let path = path_to_instance.join(BACKUP_HISTORY_FILE_NAME);
+        let mut entries: VecDeque<BackupHistoryEntry> = tokio::fs::read(&path)
+            .await
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default();
+        while entries.len() > capacity {
+            entries.pop_front();
+        }
+        Self {
+            path,
+            entries,
+            capacity,
+        }
+    }
+
+    pub async fn record(
+        &mut self,
+        label: Option<&str>,
+        outcome: BackupOutcome,
+    ) -> Result<(), Error> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        while self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(BackupHistoryEntry {
+            time: chrono::Utc::now().timestamp(),
+            label: label.map(|label| label.to_string()),
+            outcome,
+        });
+        tokio::fs::write(
+            &self.path,
+            serde_json::to_vec(&self.entries).context("Failed to serialize backup history")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write backup history to {}",
+            self.path.display()
+        ))?;
+        Ok(())
+    }
+
+    /// The recorded backups, oldest first
+    pub fn entries(&self) -> Vec<BackupHistoryEntry> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_backup_history_is_bounded_and_persisted() {
+        let temp_dir = tempdir::TempDir::new("test_backup_history").unwrap();
+        let mut history = BackupHistory::load_with_capacity(temp_dir.path(), 2).await;
+        for outcome in [
+            BackupOutcome::Succeeded {
+                name: "backup-1".to_string(),
+            },
+            BackupOutcome::Failed {
+                reason: "disk full".to_string(),
+            },
+            BackupOutcome::Skipped {
+                reason: "no world".to_string(),
+            },
+        ] {
+            history.record(None, outcome).await.unwrap();
+        }
+        history
+            .record(
+                Some("upgrade"),
+                BackupOutcome::Succeeded {
+                    name: "backup-2".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+
+        let reloaded = BackupHistory::load_with_capacity(temp_dir.path(), 2).await;
+        assert_eq!(reloaded.entries(), history.entries());
+        let entries = reloaded.entries();
+        assert_eq!(entries.len(), 2);
+        assert!(matches!(entries[0].outcome, BackupOutcome::Skipped { .. }));
+        assert_eq!(entries[1].label.as_deref(), Some("upgrade"));
+    }
+}
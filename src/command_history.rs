@@ -0,0 +1,147 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::Context;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::Error;
+use crate::events::CausedBy;
+
+/// Name of the file the command history is persisted to, inside the instance directory
+pub const COMMAND_HISTORY_FILE_NAME: &str = ".lodestone_command_history.json";
+
+/// Number of commands kept in the history of an instance
+pub const MAX_COMMAND_HISTORY_ENTRIES: usize = 256;
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[ts(export)]
+pub struct CommandHistoryEntry {
+    pub command: String,
+    pub time: i64,
+    pub caused_by: CausedBy,
+}
+
+/// Whether a command looks like it carries a credential, such commands are never recorded
+pub fn looks_sensitive(command: &str) -> bool {
+    let command = command.trim().to_lowercase();
+    if ["password", "passwd", "secret", "token", "apikey", "api_key"]
+        .iter()
+        .any(|word| command.contains(word))
+    {
+        return true;
+    }
+    // login plugins take the password as an argument
+    let name = command
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches('/');
+    [
+        "login",
+        "register",
+        "reg",
+        "changepassword",
+        "changepw",
+        "authme",
+    ]
+    .contains(&name)
+}
+
+/// A bounded history of the commands sent to an instance, persisted to disk
+pub struct CommandHistory {
+    path: PathBuf,
+    entries: VecDeque<CommandHistoryEntry>,
+    capacity: usize,
+}
+
+impl CommandHistory {
+    /// Loads the history of the instance, starting empty if there is none or it can't be read
+    pub async fn load(path_to_instance: &Path) -> Self {
+        Self::load_with_capacity(path_to_instance, MAX_COMMAND_HISTORY_ENTRIES).await
+    }
+
+    async fn load_with_capacity(path_to_instance: &Path, capacity: usize) -> Self {
+        let path = path_to_instance.join(COMMAND_HISTORY_FILE_NAME);
+        let mut entries: VecDeque<CommandHistoryEntry> = tokio::fs::read(&path)
+            .await
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default();
+        while entries.len() > capacity {
+            entries.pop_front();
+        }
+        Self {
+            path,
+            entries,
+            capacity,
+        }
+    }
+
+    /// Records a command, unless it looks like it contains a password
+    pub async fn record(&mut self, command: &str, caused_by: CausedBy) -> Result<(), Error> {
+        if self.capacity == 0 || looks_sensitive(command) {
+            return Ok(());
+        }
+        while self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(CommandHistoryEntry {
+            command: command.to_string(),
+            time: chrono::Utc::now().timestamp(),
+            caused_by,
+        });
+        tokio::fs::write(
+            &self.path,
+            serde_json::to_vec(&self.entries).context("Failed to serialize command history")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write command history to {}",
+            self.path.display()
+        ))?;
+        Ok(())
+    }
+
+    /// The recorded commands, oldest first
+    pub fn entries(&self) -> Vec<CommandHistoryEntry> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_looks_sensitive() {
+        assert!(looks_sensitive("/login hunter2"));
+        assert!(looks_sensitive("register hunter2 hunter2"));
+        assert!(looks_sensitive("authme changepassword Steve hunter2"));
+        assert!(looks_sensitive("config set db.PASSWORD hunter2"));
+        assert!(!looks_sensitive("say hello"));
+        assert!(!looks_sensitive("whitelist add Steve"));
+        assert!(!looks_sensitive("logins"));
+    }
+
+    #[tokio::test]
+    async fn test_command_history_is_bounded_and_persisted() {
+        let temp_dir = tempdir::TempDir::new("test_command_history").unwrap();
+        let mut history = CommandHistory::load_with_capacity(temp_dir.path(), 2).await;
+        for command in ["say 1", "login hunter2", "say 2", "say 3"] {
+            history.record(command, CausedBy::System).await.unwrap();
+        }
+        let commands = |history: &CommandHistory| {
+            history
+                .entries()
+                .into_iter()
+                .map(|entry| entry.command)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(commands(&history), vec!["say 2", "say 3"]);
+
+        let reloaded = CommandHistory::load_with_capacity(temp_dir.path(), 2).await;
+        assert_eq!(commands(&reloaded), vec!["say 2", "say 3"]);
+        assert_eq!(reloaded.entries()[0].caused_by, CausedBy::System);
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::Context;
+use tokio::io::AsyncWriteExt;
+
+use crate::error::Error;
+use crate::events::Event;
+
+/// Number of console lines kept in memory for an instance that doesn't configure its own
+pub const DEFAULT_CONSOLE_BUFFER_LINES: usize = 1024;
+
+/// Name of the file console output is persisted to, inside the instance directory.
+/// Kept apart from `logs/latest.log`, which is owned by the game server
+pub const CONSOLE_LOG_FILE_NAME: &str = ".lodestone_console.log";
+
+/// Size at which the console log is rotated to `<CONSOLE_LOG_FILE_NAME>.1`
+const MAX_CONSOLE_LOG_BYTES: u64 = 4 * 1024 * 1024;
+
+/// A bounded buffer of the most recent console events of an instance
+pub struct ConsoleBuffer {
+    events: VecDeque<Event>,
+    capacity: usize,
+}
+
+impl ConsoleBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, event: Event) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.events.len() >= self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Changes the capacity of the buffer, dropping the oldest events if it shrinks
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.events.len() > self.capacity {
+            self.events.pop_front();
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Event> {
+        self.events.iter()
+    }
+}
+
+impl Default for ConsoleBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_CONSOLE_BUFFER_LINES)
+    }
+}
+
+/// Console output of an instance persisted to disk, with a single rotated file
+pub struct ConsoleLog {
+    path: PathBuf,
+    file: tokio::fs::File,
+    size: u64,
+    max_size: u64,
+}
+
+impl ConsoleLog {
+    pub async fn open(path_to_instance: &Path) -> Result<Self, Error> {
+        Self::open_with_max_size(path_to_instance, MAX_CONSOLE_LOG_BYTES).await
+    }
+
+    async fn open_with_max_size(path_to_instance: &Path, max_size: u64) -> Result<Self, Error> {
+        let path = path_to_instance.join(CONSOLE_LOG_FILE_NAME);
+        let file = Self::open_file(&path).await?;
+        let size = file
+            .metadata()
+            .await
+            .context("Failed to read console log metadata")?
+            .len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+        })
+    }
+
+    async fn open_file(path: &Path) -> Result<tokio::fs::File, Error> {
+        Ok(tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .context(format!("Failed to open console log at {}", path.display()))?)
+    }
+
+    fn rotated_path(path: &Path) -> PathBuf {
+        let mut rotated = path.as_os_str().to_owned();
+        rotated.push(".1");
+        PathBuf::from(rotated)
+    }
+
+    pub async fn append(&mut self, line: &str) -> Result<(), Error> {
+        if self.size >= self.max_size {
+            tokio::fs::rename(&self.path, Self::rotated_path(&self.path))
+                .await
+                .context("Failed to rotate console log")?;
+            self.file = Self::open_file(&self.path).await?;
+            self.size = 0;
+        }
+        let line = format!("{}\n", line.trim_end_matches(['\r', '\n']));
+        self.file
+            .write_all(line.as_bytes())
+            .await
+            .context("Failed to write to console log")?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Reads at most `lines` of the most recent persisted console output, oldest first
+    pub async fn read_tail(path_to_instance: &Path, lines: usize) -> Vec<String> {
+        let path = path_to_instance.join(CONSOLE_LOG_FILE_NAME);
+        if lines == 0 {
+            return Vec::new();
+        }
+        let mut tail = VecDeque::with_capacity(lines);
+        for path in [Self::rotated_path(&path), path] {
+            let content = match tokio::fs::read(&path).await {
+                Ok(content) => content,
+                Err(_) => continue,
+            };
+            for line in String::from_utf8_lossy(&content).lines() {
+                if tail.len() == lines {
+                    tail.pop_front();
+                }
+                tail.push_back(line.to_string());
+            }
+        }
+        tail.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::InstanceUuid;
+
+    fn output(line: usize) -> Event {
+        Event::new_instance_output(
+            InstanceUuid::default(),
+            "test".to_string(),
+            line.to_string(),
+        )
+    }
+
+    fn lines(buffer: &ConsoleBuffer) -> Vec<String> {
+        buffer
+            .iter()
+            .map(|event| match &event.event_inner {
+                crate::events::EventInner::InstanceEvent(instance_event) => {
+                    match &instance_event.instance_event_inner {
+                        crate::events::InstanceEventInner::InstanceOutput { message } => {
+                            message.clone()
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_console_buffer_truncation() {
+        let mut buffer = ConsoleBuffer::new(3);
+        for i in 0..5 {
+            buffer.push(output(i));
+        }
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(lines(&buffer), vec!["2", "3", "4"]);
+
+        buffer.set_capacity(2);
+        assert_eq!(lines(&buffer), vec!["3", "4"]);
+
+        buffer.set_capacity(4);
+        buffer.push(output(5));
+        assert_eq!(lines(&buffer), vec!["3", "4", "5"]);
+    }
+
+    #[test]
+    fn test_console_buffer_zero_capacity() {
+        let mut buffer = ConsoleBuffer::new(0);
+        buffer.push(output(0));
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_console_log_rotation() {
+        let temp_dir = tempdir::TempDir::new("test_console_log").unwrap();
+        let mut log = ConsoleLog::open_with_max_size(temp_dir.path(), 8)
+            .await
+            .unwrap();
+        for i in 0..5 {
+            log.append(&format!("line{}\n", i)).await.unwrap();
+        }
+        assert!(ConsoleLog::rotated_path(&temp_dir.path().join(CONSOLE_LOG_FILE_NAME)).exists());
+        assert_eq!(
+            ConsoleLog::read_tail(temp_dir.path(), 2).await,
+            vec!["line3", "line4"]
+        );
+        assert_eq!(ConsoleLog::read_tail(temp_dir.path(), 0).await.len(), 0);
+    }
+}
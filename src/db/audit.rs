@@ -0,0 +1,354 @@
+use std::time::Duration;
+
+use color_eyre::eyre::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqlitePool, Row};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tracing::{error, warn};
+use ts_rs::TS;
+
+use crate::{
+    auth::user_id::UserId,
+    error::Error,
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner, UserEventInner},
+    macro_executor::MacroPID,
+    traits::t_server::State,
+    types::InstanceUuid,
+};
+
+/// Audit entries older than this are pruned
+const AUDIT_LOG_RETENTION_DAYS: i64 = 90;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum AuditAction {
+    InstanceCreated,
+    InstanceDeleted,
+    InstanceStarted,
+    InstanceStopped,
+    InstanceConfigChanged,
+    UserCreated,
+    UserDeleted,
+    PermissionChanged,
+    /// A player was moderated, e.g. teleported, or the world was changed through a moderation endpoint
+    PlayerModerated,
+}
+
+/// Who performed an audited action. Unlike `CausedBy`, only ids are kept, no usernames
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+#[serde(tag = "type")]
+pub enum AuditActor {
+    User { user_id: UserId },
+    Instance { instance_uuid: InstanceUuid },
+    Macro { macro_pid: MacroPID },
+    System,
+}
+
+impl From<&CausedBy> for AuditActor {
+    fn from(caused_by: &CausedBy) -> Self {
+        match caused_by {
+            CausedBy::User { user_id, .. } => AuditActor::User {
+                user_id: user_id.clone(),
+            },
+            CausedBy::Instance { instance_uuid } => AuditActor::Instance {
+                instance_uuid: instance_uuid.clone(),
+            },
+            CausedBy::Macro { macro_pid } => AuditActor::Macro {
+                macro_pid: *macro_pid,
+            },
+            CausedBy::System | CausedBy::Unknown => AuditActor::System,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct AuditEntry {
+    pub id: i64,
+    /// Unix timestamp in seconds
+    pub time: i64,
+    pub action: AuditAction,
+    pub actor: AuditActor,
+    /// The instance or user acted upon
+    pub target: Option<String>,
+    pub details: String,
+}
+
+#[derive(Deserialize, Default)]
+pub struct AuditQuery {
+    pub user: Option<UserId>,
+    pub action: Option<AuditAction>,
+    /// Unix timestamp in seconds
+    pub since: Option<i64>,
+}
+
+pub async fn init_audit_log_table(pool: &SqlitePool) -> Result<(), Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS AuditLog (
+            id                  INTEGER     PRIMARY KEY     AUTOINCREMENT,
+            time                BIGINT      NOT NULL,
+            action              TEXT        NOT NULL,
+            actor               TEXT        NOT NULL,
+            actor_user_id       TEXT,
+            target              TEXT,
+            details             TEXT        NOT NULL
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create audit log table")?;
+    Ok(())
+}
+
+pub async fn record_audit_entry(
+    pool: &SqlitePool,
+    action: AuditAction,
+    caused_by: &CausedBy,
+    target: Option<String>,
+    details: impl AsRef<str>,
+) -> Result<(), Error> {
+    let actor = AuditActor::from(caused_by);
+    let actor_user_id = match &actor {
+        AuditActor::User { user_id } => Some(user_id.to_string()),
+        _ => None,
+    };
+    sqlx::query(
+        r#"
+INSERT INTO AuditLog
+(time, action, actor, actor_user_id, target, details)
+VALUES
+(?1, ?2, ?3, ?4, ?5, ?6)
+        "#,
+    )
+    .bind(chrono::Utc::now().timestamp())
+    .bind(serde_json::to_string(&action).context("Failed to serialize audit action")?)
+    .bind(serde_json::to_string(&actor).context("Failed to serialize audit actor")?)
+    .bind(actor_user_id)
+    .bind(target)
+    .bind(details.as_ref())
+    .execute(pool)
+    .await
+    .context("Failed to write audit entry")?;
+    Ok(())
+}
+
+/// Records an audit entry, logging instead of failing the audited action if it can't be written
+pub async fn log_audit_entry(
+    pool: &SqlitePool,
+    action: AuditAction,
+    caused_by: &CausedBy,
+    target: Option<String>,
+    details: impl AsRef<str>,
+) {
+    if let Err(e) = record_audit_entry(pool, action, caused_by, target, details).await {
+        error!("Failed to record audit entry: {}", e);
+    }
+}
+
+pub async fn search_audit_log(
+    pool: &SqlitePool,
+    query: AuditQuery,
+) -> Result<Vec<AuditEntry>, Error> {
+    let action = query
+        .action
+        .map(|action| serde_json::to_string(&action))
+        .transpose()
+        .context("Failed to serialize audit action")?;
+    let rows = sqlx::query(
+        r#"
+SELECT
+id, time, action, actor, target, details
+FROM AuditLog
+WHERE (?1 IS NULL OR actor_user_id = ?1)
+AND (?2 IS NULL OR action = ?2)
+AND (?3 IS NULL OR time >= ?3)
+ORDER BY id DESC"#,
+    )
+    .bind(query.user.map(|user| user.to_string()))
+    .bind(action)
+    .bind(query.since)
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch audit log")?;
+    let mut entries = Vec::new();
+    for row in rows {
+        let action: String = row.get("action");
+        let actor: String = row.get("actor");
+        match (serde_json::from_str(&action), serde_json::from_str(&actor)) {
+            (Ok(action), Ok(actor)) => entries.push(AuditEntry {
+                id: row.get("id"),
+                time: row.get("time"),
+                action,
+                actor,
+                target: row.get("target"),
+                details: row.get("details"),
+            }),
+            _ => error!("Failed to parse audit entry: {} {}", action, actor),
+        }
+    }
+    Ok(entries)
+}
+
+/// Deletes entries older than the retention period, returns the number of entries deleted
+pub async fn prune_audit_log(pool: &SqlitePool) -> Result<u64, Error> {
+    let cutoff = chrono::Utc::now().timestamp() - AUDIT_LOG_RETENTION_DAYS * 24 * 60 * 60;
+    Ok(sqlx::query("DELETE FROM AuditLog WHERE time < ?1")
+        .bind(cutoff)
+        .execute(pool)
+        .await
+        .context("Failed to prune audit log")?
+        .rows_affected())
+}
+
+/// Maps an event to the audited action it represents, if any
+fn audit_action_of(event: &Event) -> Option<(AuditAction, Option<String>)> {
+    match &event.event_inner {
+        EventInner::InstanceEvent(InstanceEvent {
+            instance_uuid,
+            instance_event_inner: InstanceEventInner::StateTransition { to },
+            ..
+        }) => match to {
+            State::Starting => Some((
+                AuditAction::InstanceStarted,
+                Some(instance_uuid.to_string()),
+            )),
+            State::Stopping => Some((
+                AuditAction::InstanceStopped,
+                Some(instance_uuid.to_string()),
+            )),
+            _ => None,
+        },
+        EventInner::UserEvent(user_event) => {
+            let action = match user_event.user_event_inner {
+                UserEventInner::UserCreated => AuditAction::UserCreated,
+                UserEventInner::UserDeleted => AuditAction::UserDeleted,
+                UserEventInner::PermissionChanged { .. } => AuditAction::PermissionChanged,
+                _ => return None,
+            };
+            Some((action, Some(user_event.user_id.to_string())))
+        }
+        _ => None,
+    }
+}
+
+/// Records audited actions from the event stream and prunes old entries daily.
+/// Expects the table to have been created by `init_audit_log_table`
+pub async fn write_audit_log_task(mut event_receiver: Receiver<Event>, sqlite_pool: SqlitePool) {
+    let mut prune_interval = tokio::time::interval(Duration::from_secs(24 * 60 * 60));
+    loop {
+        tokio::select! {
+            _ = prune_interval.tick() => {
+                if let Err(e) = prune_audit_log(&sqlite_pool).await {
+                    error!("Failed to prune audit log: {}", e);
+                }
+            }
+            result = event_receiver.recv() => {
+                let event = match result {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => {
+                        warn!("Audit log lagged behind events, some actions were not recorded");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if let Some((action, target)) = audit_action_of(&event) {
+                    if let Err(e) = record_audit_entry(
+                        &sqlite_pool,
+                        action,
+                        &event.caused_by,
+                        target,
+                        &event.details,
+                    )
+                    .await
+                    {
+                        error!("Failed to record audit entry: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        // every connection to an in memory database gets its own database
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(SqliteConnectOptions::from_str("sqlite::memory:").unwrap())
+            .await
+            .unwrap();
+        init_audit_log_table(&pool).await.unwrap();
+        let user_id = UserId::from("USER_test".to_string());
+        let caused_by = CausedBy::User {
+            user_id: user_id.clone(),
+            user_name: "test".to_string(),
+        };
+        record_audit_entry(
+            &pool,
+            AuditAction::InstanceDeleted,
+            &caused_by,
+            Some("INSTANCE_test".to_string()),
+            "",
+        )
+        .await
+        .unwrap();
+        record_audit_entry(
+            &pool,
+            AuditAction::InstanceStarted,
+            &CausedBy::System,
+            None,
+            "",
+        )
+        .await
+        .unwrap();
+
+        let entries = search_audit_log(
+            &pool,
+            AuditQuery {
+                user: Some(user_id.clone()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, AuditAction::InstanceDeleted);
+        assert_eq!(entries[0].actor, AuditActor::User { user_id });
+
+        let entries = search_audit_log(
+            &pool,
+            AuditQuery {
+                action: Some(AuditAction::InstanceStarted),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].actor, AuditActor::System);
+
+        let entries = search_audit_log(
+            &pool,
+            AuditQuery {
+                since: Some(chrono::Utc::now().timestamp() + 60),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert!(entries.is_empty());
+
+        assert_eq!(prune_audit_log(&pool).await.unwrap(), 0);
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{
     error::Error,
-    events::{Event, EventInner, ProgressionEventInner},
+    events::{Event, EventInner, InstanceEventInner, ProgressionEventInner},
     output_types::ClientEvent,
 };
 
@@ -41,6 +41,12 @@ pub async fn write_event_to_db_task(mut event_receiver: Receiver<Event>, sqlite_
                 continue;
             }
         }
+        // reported too often to be worth keeping
+        if let EventInner::InstanceEvent(ie) = &client_event.event_inner {
+            if let InstanceEventInner::TickPerformance { .. } = ie.instance_event_inner {
+                continue;
+            }
+        }
         let insertion_result = write_client_event(&sqlite_pool, client_event).await;
         if let Err(e) = insertion_result.as_ref() {
             error!("Error inserting into database: {}", e);
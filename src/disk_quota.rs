@@ -0,0 +1,304 @@
+//! Bounds on how much disk the directory of an instance may take up, checked before writing to it
+//! on behalf of a user.
+//!
+//! Walking a large instance directory is slow, so its usage is measured at most once per
+//! [`DISK_USAGE_TTL`] and kept up to date in between by counting what is written through the core
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{eyre, Context};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::util::format_byte;
+
+/// How long a measured usage is trusted before the directory is walked again
+pub const DISK_USAGE_TTL: Duration = Duration::from_secs(60);
+
+static DISK_USAGE_CACHE: Lazy<DiskUsageCache> = Lazy::new(DiskUsageCache::default);
+
+/// The cache shared by every instance of the core
+pub fn disk_usage_cache() -> &'static DiskUsageCache {
+    &DISK_USAGE_CACHE
+}
+
+pub fn mb_to_bytes(mb: u64) -> u64 {
+    mb.saturating_mul(1024 * 1024)
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct InstanceDiskUsage {
+    pub used_bytes: u64,
+    /// Unlimited if `None`
+    pub quota_bytes: Option<u64>,
+}
+
+struct Measurement {
+    bytes: u64,
+    measured_at: Instant,
+}
+
+#[derive(Default)]
+struct Measurements {
+    by_path: HashMap<PathBuf, Measurement>,
+    /// Directories being walked in the background
+    refreshing: HashSet<PathBuf>,
+}
+
+#[derive(Default)]
+pub struct DiskUsageCache {
+    measurements: Mutex<Measurements>,
+}
+
+/// The bytes of the files in `path`, blocks
+fn measure(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.metadata().map(|m| m.len()).unwrap_or(0))
+        .sum()
+}
+
+/// The bytes of the files in `path`
+pub async fn measure_dir(path: &Path) -> Result<u64, Error> {
+    let path = path.to_path_buf();
+    Ok(tokio::task::spawn_blocking(move || measure(&path))
+        .await
+        .map_err(|e| eyre!("Measuring disk usage panicked: {}", e))?)
+}
+
+/// The bytes of the files in each of `paths`, which may be files or directories
+pub async fn measure_paths(paths: &[PathBuf]) -> Result<u64, Error> {
+    let paths = paths.to_vec();
+    Ok(
+        tokio::task::spawn_blocking(move || paths.iter().map(|path| measure(path)).sum())
+            .await
+            .map_err(|e| eyre!("Measuring disk usage panicked: {}", e))?,
+    )
+}
+
+/// The bytes the entries of a `.zip`, `.gz` or `.tgz` archive take up once unpacked, as recorded
+/// in the archive
+fn unpacked_size(archive: &Path) -> Result<u64, Error> {
+    let file = std::fs::File::open(archive)
+        .context(format!("Failed to open file {}", archive.display()))?;
+    match archive.extension().and_then(|ext| ext.to_str()) {
+        Some("zip") => {
+            let mut zip = zip::ZipArchive::new(file)
+                .context(format!("Failed to read archive {}", archive.display()))?;
+            let mut bytes = 0_u64;
+            for i in 0..zip.len() {
+                let entry = zip
+                    .by_index_raw(i)
+                    .context(format!("Failed to read archive {}", archive.display()))?;
+                bytes = bytes.saturating_add(entry.size());
+            }
+            Ok(bytes)
+        }
+        Some("gz") | Some("tgz") => {
+            let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(file));
+            let mut bytes = 0_u64;
+            for entry in tar
+                .entries()
+                .context(format!("Failed to read archive {}", archive.display()))?
+            {
+                let entry =
+                    entry.context(format!("Failed to read archive {}", archive.display()))?;
+                bytes = bytes.saturating_add(entry.header().size().unwrap_or(0));
+            }
+            Ok(bytes)
+        }
+        _ => Err(eyre!("Unsupported extension for {}", archive.display()).into()),
+    }
+}
+
+/// The bytes `archive` takes up once unpacked
+pub async fn measure_unpacked(archive: &Path) -> Result<u64, Error> {
+    let archive = archive.to_path_buf();
+    tokio::task::spawn_blocking(move || unpacked_size(&archive))
+        .await
+        .map_err(|e| eyre!("Measuring archive size panicked: {}", e))?
+}
+
+/// Fails with `DiskQuotaExceeded` if writing `additional` bytes to a directory using `used` bytes
+/// would take it over `quota`. Filling the quota exactly is allowed
+pub fn check_quota(used: u64, additional: u64, quota: u64) -> Result<(), Error> {
+    if used.saturating_add(additional) > quota {
+        return Err(Error {
+            kind: ErrorKind::DiskQuotaExceeded,
+            source: eyre!(
+                "Writing {} would exceed the disk quota of the instance, {} of {} is used",
+                format_byte(additional),
+                format_byte(used),
+                format_byte(quota)
+            ),
+        });
+    }
+    Ok(())
+}
+
+impl DiskUsageCache {
+    fn lock(&self) -> MutexGuard<'_, Measurements> {
+        self.measurements
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn insert(&self, path: &Path, bytes: u64) {
+        self.lock().by_path.insert(
+            path.to_path_buf(),
+            Measurement {
+                bytes,
+                measured_at: Instant::now(),
+            },
+        );
+    }
+
+    /// The usage of `path`, walking it if it wasn't measured within the TTL
+    pub async fn usage(&self, path: &Path) -> Result<u64, Error> {
+        if let Some(measurement) = self.lock().by_path.get(path) {
+            if measurement.measured_at.elapsed() < DISK_USAGE_TTL {
+                return Ok(measurement.bytes);
+            }
+        }
+        let bytes = measure_dir(path).await?;
+        self.insert(path, bytes);
+        Ok(bytes)
+    }
+
+    /// The last measured usage of `path`, without waiting for it to be walked.
+    ///
+    /// A measurement older than the TTL, or none at all, is refreshed in the background, so only
+    /// look up instances that have a quota
+    pub fn cached(&'static self, path: &Path) -> Option<u64> {
+        let mut measurements = self.lock();
+        let cached = measurements.by_path.get(path).map(|measurement| {
+            (
+                measurement.bytes,
+                measurement.measured_at.elapsed() < DISK_USAGE_TTL,
+            )
+        });
+        if !matches!(cached, Some((_, true))) && measurements.refreshing.insert(path.to_path_buf())
+        {
+            let path = path.to_path_buf();
+            tokio::spawn(async move {
+                if let Ok(bytes) = measure_dir(&path).await {
+                    self.insert(&path, bytes);
+                }
+                self.lock().refreshing.remove(&path);
+            });
+        }
+        cached.map(|(bytes, _)| bytes)
+    }
+
+    /// Counts `bytes` written to `path` since it was last measured
+    pub fn add(&self, path: &Path, bytes: u64) {
+        if let Some(measurement) = self.lock().by_path.get_mut(path) {
+            measurement.bytes = measurement.bytes.saturating_add(bytes);
+        }
+    }
+
+    /// Has the next lookup walk `path` again, e.g. after files were moved around in it
+    pub fn invalidate(&self, path: &Path) {
+        self.lock().by_path.remove(path);
+    }
+
+    /// Fails with `DiskQuotaExceeded` if writing `additional` bytes to `path` would take it over
+    /// `quota_bytes`. Nothing is measured if there is no quota
+    pub async fn ensure_fits(
+        &self,
+        path: &Path,
+        quota_bytes: Option<u64>,
+        additional: u64,
+    ) -> Result<(), Error> {
+        match quota_bytes {
+            Some(quota) => check_quota(self.usage(path).await?, additional, quota),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write_up_to_quota_is_allowed() {
+        let temp_dir = tempdir::TempDir::new("test_disk_quota").unwrap();
+        std::fs::write(temp_dir.path().join("server.properties"), vec![b'a'; 600]).unwrap();
+        let cache = DiskUsageCache::default();
+        assert_eq!(cache.usage(temp_dir.path()).await.unwrap(), 600);
+        cache
+            .ensure_fits(temp_dir.path(), Some(1000), 400)
+            .await
+            .unwrap();
+        cache.add(temp_dir.path(), 400);
+        // the quota is full, but writing nothing more is fine
+        cache
+            .ensure_fits(temp_dir.path(), Some(1000), 0)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_write_over_quota_is_rejected() {
+        let temp_dir = tempdir::TempDir::new("test_disk_quota").unwrap();
+        std::fs::create_dir(temp_dir.path().join("mods")).unwrap();
+        std::fs::write(temp_dir.path().join("mods/mod.jar"), vec![b'a'; 600]).unwrap();
+        let cache = DiskUsageCache::default();
+        let e = cache
+            .ensure_fits(temp_dir.path(), Some(1000), 401)
+            .await
+            .unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::DiskQuotaExceeded));
+
+        // writes through the core are counted without walking the directory again
+        cache.add(temp_dir.path(), 400);
+        let e = cache
+            .ensure_fits(temp_dir.path(), Some(1000), 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::DiskQuotaExceeded));
+
+        // no quota, no limit
+        cache
+            .ensure_fits(temp_dir.path(), None, u64::MAX)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_measure_unpacked() {
+        use std::io::Write;
+
+        let temp_dir = tempdir::TempDir::new("test_disk_quota").unwrap();
+        let archive = temp_dir.path().join("world.zip");
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&archive).unwrap());
+        writer
+            .start_file("level.dat", zip::write::FileOptions::default())
+            .unwrap();
+        writer.write_all(&[0; 5000]).unwrap();
+        writer
+            .start_file("region/r.0.0.mca", zip::write::FileOptions::default())
+            .unwrap();
+        writer.write_all(&[0; 3000]).unwrap();
+        writer.finish().unwrap();
+
+        // the zeroes compress well, the unpacked size is what counts
+        assert!(std::fs::metadata(&archive).unwrap().len() < 8000);
+        assert_eq!(measure_unpacked(&archive).await.unwrap(), 8000);
+        assert_eq!(
+            measure_paths(&[archive.clone(), temp_dir.path().to_path_buf()])
+                .await
+                .unwrap(),
+            2 * std::fs::metadata(&archive).unwrap().len()
+        );
+    }
+}
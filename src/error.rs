@@ -3,9 +3,9 @@ use std::fmt::{Display, Formatter};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use color_eyre::Report;
+use indexmap::IndexMap;
 use serde::ser::SerializeStruct;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use thiserror::Error;
 use ts_rs::TS;
 
@@ -18,6 +18,21 @@ pub enum ErrorKind {
     PermissionDenied,
     Unauthorized,
     Internal,
+    /// Uploading to an external store, such as S3, failed
+    FailedToUpload,
+    /// Downloading from an upstream, e.g. a server jar or JRE, failed
+    FailedToDownload,
+    /// The operation needs an RCON connection to the running server, which isn't open
+    RconNotOpen,
+    /// The instance is in a state that doesn't allow the operation, e.g. starting while it's already running
+    InvalidInstanceState,
+    /// The directory of the instance was deleted from under the core
+    InstanceMissing,
+    /// Writing to the instance would take its directory over its disk quota
+    DiskQuotaExceeded,
+    /// The request was made against an older version of the API, e.g. a cached setup manifest,
+    /// and has to be redone against the current one
+    ApiChanged,
 }
 
 #[derive(Error, Debug)]
@@ -27,6 +42,51 @@ pub struct Error {
     pub source: color_eyre::Report,
 }
 
+/// Errors of the individual fields of a request, e.g. of each property of a batch, keyed by the
+/// field. Sent as `field_errors` in the error response when it's the source of an [`Error`]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{}", .0.iter().map(|(field, error)| format!("{field}: {error}")).collect::<Vec<_>>().join("\n"))]
+pub struct FieldErrors(pub IndexMap<String, String>);
+
+impl ErrorKind {
+    /// Stable, machine-readable code for the kind, sent as `error_code` in error responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::UnsupportedOperation => "unsupported_operation",
+            ErrorKind::BadRequest => "bad_request",
+            ErrorKind::PermissionDenied => "permission_denied",
+            ErrorKind::Unauthorized => "unauthorized",
+            ErrorKind::Internal => "internal",
+            ErrorKind::FailedToUpload => "failed_to_upload",
+            ErrorKind::FailedToDownload => "failed_to_download",
+            ErrorKind::RconNotOpen => "rcon_not_open",
+            ErrorKind::InvalidInstanceState => "invalid_instance_state",
+            ErrorKind::InstanceMissing => "instance_missing",
+            ErrorKind::DiskQuotaExceeded => "disk_quota_exceeded",
+            ErrorKind::ApiChanged => "api_changed",
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::UnsupportedOperation => StatusCode::NOT_IMPLEMENTED,
+            ErrorKind::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+            ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorKind::FailedToUpload => StatusCode::BAD_GATEWAY,
+            ErrorKind::FailedToDownload => StatusCode::BAD_GATEWAY,
+            ErrorKind::RconNotOpen => StatusCode::CONFLICT,
+            ErrorKind::InvalidInstanceState => StatusCode::CONFLICT,
+            ErrorKind::InstanceMissing => StatusCode::GONE,
+            ErrorKind::DiskQuotaExceeded => StatusCode::INSUFFICIENT_STORAGE,
+            ErrorKind::ApiChanged => StatusCode::PRECONDITION_FAILED,
+        }
+    }
+}
+
 impl Display for ErrorKind {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -36,6 +96,13 @@ impl Display for ErrorKind {
             ErrorKind::PermissionDenied => write!(f, "Permission Denied"),
             ErrorKind::Unauthorized => write!(f, "Unauthorized"),
             ErrorKind::Internal => write!(f, "Internal Error"),
+            ErrorKind::FailedToUpload => write!(f, "Failed To Upload"),
+            ErrorKind::FailedToDownload => write!(f, "Failed To Download"),
+            ErrorKind::RconNotOpen => write!(f, "RCON Not Open"),
+            ErrorKind::InvalidInstanceState => write!(f, "Invalid Instance State"),
+            ErrorKind::InstanceMissing => write!(f, "Instance Missing"),
+            ErrorKind::DiskQuotaExceeded => write!(f, "Disk Quota Exceeded"),
+            ErrorKind::ApiChanged => write!(f, "API Changed"),
         }
     }
 }
@@ -45,10 +112,18 @@ impl Serialize for Error {
     where
         S: serde::Serializer,
     {
-        let mut state = serializer.serialize_struct("Error", 2)?;
+        let field_errors = self.source.downcast_ref::<FieldErrors>();
+        let mut state =
+            serializer.serialize_struct("Error", 4 + usize::from(field_errors.is_some()))?;
         state.serialize_field("kind", &self.kind)?;
+        state.serialize_field("error_code", self.kind.code())?;
+        // the outermost message, without the chain of causes
+        state.serialize_field("detail", &self.source.to_string())?;
         let vec: Vec<String> = self.source.chain().map(|cause| cause.to_string()).collect();
         state.serialize_field("causes", &vec)?;
+        if let Some(FieldErrors(field_errors)) = field_errors {
+            state.serialize_field("field_errors", field_errors)?;
+        }
         state.end()
     }
 }
@@ -60,20 +135,123 @@ fn test_error_serialization() {
         source: Report::msg("Test"),
     };
     let json = serde_json::to_string(&error).unwrap();
-    assert_eq!(json, r#"{"kind":"NotFound","causes":["Test"]}"#);
+    assert_eq!(
+        json,
+        r#"{"kind":"NotFound","error_code":"not_found","detail":"Test","causes":["Test"]}"#
+    );
+}
+
+#[test]
+fn test_field_errors_serialization() {
+    let mut field_errors = IndexMap::new();
+    field_errors.insert("difficulty".to_string(), "Invalid value".to_string());
+    field_errors.insert("server-port".to_string(), "Out of range".to_string());
+    let error = Error {
+        kind: ErrorKind::BadRequest,
+        source: Report::new(FieldErrors(field_errors)).wrap_err("Invalid properties"),
+    };
+    let json = serde_json::to_value(&error).unwrap();
+    assert_eq!(json["detail"], "Invalid properties");
+    assert_eq!(
+        json["field_errors"],
+        serde_json::json!({
+            "difficulty": "Invalid value",
+            "server-port": "Out of range",
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_error_response() {
+    use axum::body::HttpBody;
+
+    for (kind, code, status) in [
+        (ErrorKind::NotFound, "not_found", StatusCode::NOT_FOUND),
+        (
+            ErrorKind::UnsupportedOperation,
+            "unsupported_operation",
+            StatusCode::NOT_IMPLEMENTED,
+        ),
+        (
+            ErrorKind::BadRequest,
+            "bad_request",
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            ErrorKind::PermissionDenied,
+            "permission_denied",
+            StatusCode::FORBIDDEN,
+        ),
+        (
+            ErrorKind::Unauthorized,
+            "unauthorized",
+            StatusCode::UNAUTHORIZED,
+        ),
+        (
+            ErrorKind::Internal,
+            "internal",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ),
+        (
+            ErrorKind::FailedToUpload,
+            "failed_to_upload",
+            StatusCode::BAD_GATEWAY,
+        ),
+        (
+            ErrorKind::FailedToDownload,
+            "failed_to_download",
+            StatusCode::BAD_GATEWAY,
+        ),
+        (
+            ErrorKind::RconNotOpen,
+            "rcon_not_open",
+            StatusCode::CONFLICT,
+        ),
+        (
+            ErrorKind::InvalidInstanceState,
+            "invalid_instance_state",
+            StatusCode::CONFLICT,
+        ),
+        (
+            ErrorKind::InstanceMissing,
+            "instance_missing",
+            StatusCode::GONE,
+        ),
+        (
+            ErrorKind::DiskQuotaExceeded,
+            "disk_quota_exceeded",
+            StatusCode::INSUFFICIENT_STORAGE,
+        ),
+        (
+            ErrorKind::ApiChanged,
+            "api_changed",
+            StatusCode::PRECONDITION_FAILED,
+        ),
+    ] {
+        let response = Error {
+            kind,
+            source: Report::msg("Cause").wrap_err("Something went wrong"),
+        }
+        .into_response();
+        assert_eq!(response.status(), status);
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_TYPE],
+            "application/json"
+        );
+        let body = response.into_body().data().await.unwrap().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error_code"], code);
+        assert_eq!(body["detail"], "Something went wrong");
+        assert_eq!(
+            body["causes"],
+            serde_json::json!(["Something went wrong", "Cause"])
+        );
+    }
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
-        let status = match self.kind {
-            ErrorKind::NotFound => StatusCode::NOT_FOUND,
-            ErrorKind::UnsupportedOperation => StatusCode::NOT_IMPLEMENTED,
-            ErrorKind::BadRequest => StatusCode::BAD_REQUEST,
-            ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
-            ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
-            ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
-        };
-        (status, json!(self).to_string()).into_response()
+        (self.kind.status_code(), axum::Json(self)).into_response()
     }
 }
 
@@ -1,17 +1,60 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast::{Receiver, Sender};
-use tracing::error;
+use tracing::{error, warn};
 
 use crate::events::Event;
 
+/// Number of events a subscriber can fall behind by before it starts missing events
+pub const DEFAULT_EVENT_CHANNEL_CAPACITY: usize = 512;
+
+#[derive(Debug, Default)]
+struct LagCounters {
+    lag_count: AtomicU64,
+    dropped_events: AtomicU64,
+}
+
+/// How often subscribers fell behind the event channel, since the core started
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventBroadcasterStats {
+    pub capacity: usize,
+    /// Number of times a subscriber fell behind
+    pub lag_count: u64,
+    /// Number of events subscribers missed because they fell behind
+    pub dropped_events: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct EventBroadcaster {
     event_tx: Sender<Event>,
+    capacity: usize,
+    lag_counters: Arc<LagCounters>,
+}
+
+/// Parses a channel capacity, which has to be at least 1
+pub fn parse_event_channel_capacity(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("the event channel capacity must be at least 1".to_string()),
+        Ok(capacity) => Ok(capacity),
+        Err(e) => Err(e.to_string()),
+    }
 }
 
 impl EventBroadcaster {
+    /// A `capacity` of 0 is raised to 1, a channel can't be empty
     pub fn new(capacity: usize) -> (Self, Receiver<Event>) {
+        let capacity = capacity.max(1);
         let (event_tx, rx) = tokio::sync::broadcast::channel(capacity);
-        (Self { event_tx }, rx)
+        (
+            Self {
+                event_tx,
+                capacity,
+                lag_counters: Arc::new(LagCounters::default()),
+            },
+            rx,
+        )
     }
 
     pub fn send(&self, event: Event) {
@@ -20,9 +63,30 @@ impl EventBroadcaster {
         }
     }
 
+    pub fn receiver_count(&self) -> usize {
+        self.event_tx.receiver_count()
+    }
+
     pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<Event> {
         self.event_tx.subscribe()
     }
+
+    /// Records that `subscriber` fell behind and missed `missed` events
+    pub fn record_lag(&self, subscriber: &str, missed: u64) {
+        warn!("{subscriber} lagged behind and missed {missed} events");
+        self.lag_counters.lag_count.fetch_add(1, Ordering::Relaxed);
+        self.lag_counters
+            .dropped_events
+            .fetch_add(missed, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> EventBroadcasterStats {
+        EventBroadcasterStats {
+            capacity: self.capacity,
+            lag_count: self.lag_counters.lag_count.load(Ordering::Relaxed),
+            dropped_events: self.lag_counters.dropped_events.load(Ordering::Relaxed),
+        }
+    }
 }
 
 impl From<EventBroadcaster> for Sender<Event> {
@@ -36,3 +100,43 @@ impl AsRef<Sender<Event>> for EventBroadcaster {
         &self.event_tx
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::InstanceUuid;
+
+    #[test]
+    fn test_zero_capacity() {
+        assert!(parse_event_channel_capacity("0").is_err());
+        assert!(parse_event_channel_capacity("abc").is_err());
+        assert_eq!(parse_event_channel_capacity("16"), Ok(16));
+        assert_eq!(EventBroadcaster::new(0).0.stats().capacity, 1);
+    }
+
+    #[tokio::test]
+    async fn test_lag_is_counted() {
+        let (event_broadcaster, mut rx) = EventBroadcaster::new(2);
+        for i in 0..5 {
+            event_broadcaster.send(Event::new_instance_output(
+                InstanceUuid::default(),
+                "test".to_string(),
+                i.to_string(),
+            ));
+        }
+        match rx.recv().await {
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                event_broadcaster.record_lag("test", missed)
+            }
+            _ => panic!("receiver should have lagged"),
+        }
+        assert_eq!(
+            event_broadcaster.stats(),
+            EventBroadcasterStats {
+                capacity: 2,
+                lag_count: 1,
+                dropped_events: 3,
+            }
+        );
+    }
+}
@@ -9,7 +9,12 @@ use crate::{
     auth::{permission::UserPermission, user_id::UserId},
     macro_executor::MacroPID,
     output_types::ClientEvent,
-    traits::{t_macro::ExitStatus, t_player::Player, t_server::State, InstanceInfo},
+    traits::{
+        t_macro::ExitStatus,
+        t_player::Player,
+        t_server::{State, TickPerformance},
+        InstanceInfo,
+    },
     types::{InstanceUuid, Snowflake, TimeRange},
 };
 
@@ -101,6 +106,10 @@ pub enum InstanceEventInner {
     InstanceError {
         message: String,
     },
+    /// Detail recorded while debug logging of the instance is enabled
+    InstanceDebug {
+        message: String,
+    },
     InstanceInput {
         message: String,
     },
@@ -120,6 +129,10 @@ pub enum InstanceEventInner {
         player: String,
         player_message: String,
     },
+    /// Reported periodically while the server is running
+    TickPerformance {
+        tick_performance: TickPerformance,
+    },
 }
 
 impl AsRef<InstanceEventInner> for InstanceEventInner {
@@ -283,8 +296,15 @@ pub fn new_fs_event(operation: FSOperation, target: FSTarget, caused_by: CausedB
     }
 }
 
+#[derive(Debug)]
 pub struct ProgressionEventID(Snowflake);
 
+impl ProgressionEventID {
+    pub fn snowflake(&self) -> Snowflake {
+        self.0
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
 #[ts(export)]
 pub struct ProgressionEvent {
@@ -351,7 +371,8 @@ pub trait IntoEvent {
     fn into_event(self, caused_by: CausedBy, details: String) -> Event;
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, Eq)]
+/// Ordered by severity
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, Eq, PartialOrd, Ord)]
 #[ts(export)]
 #[derive(sqlx::Type)]
 pub enum EventLevel {
@@ -1,11 +1,64 @@
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
 
 use color_eyre::eyre::Context;
 use serde::{Deserialize, Serialize};
 use tokio::io::AsyncWriteExt;
 use ts_rs::TS;
 
-use crate::{error::Error, event_broadcaster::EventBroadcaster};
+use crate::{
+    auth::rate_limiter::LoginRateLimitConfig,
+    error::Error,
+    event_broadcaster::EventBroadcaster,
+    implementations::minecraft::{
+        setup_deadline::SetupTimeouts, DEFAULT_FORGE_INSTALLER_TIMEOUT_SECS,
+    },
+    start_limiter::start_limiter,
+    upstream_cache::{self, DEFAULT_UPSTREAM_CACHE_TTL_SECS},
+    upstream_http::{self, HttpTimeouts},
+};
+
+/// Mirrors the configured timezone for code without access to the global settings
+static CORE_TIMEZONE: RwLock<chrono_tz::Tz> = RwLock::new(chrono_tz::UTC);
+
+/// The timezone timestamps in names (e.g. of backups) are formatted in
+pub fn core_timezone() -> chrono_tz::Tz {
+    *CORE_TIMEZONE
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn set_core_timezone(timezone: chrono_tz::Tz) {
+    *CORE_TIMEZONE
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = timezone;
+}
+
+/// Mirrors `GlobalSettingsData::leak_existence` for permission checks
+static LEAK_EXISTENCE: AtomicBool = AtomicBool::new(false);
+
+/// Whether users are told they lack permission on an instance, rather than that it doesn't exist
+pub fn leak_existence() -> bool {
+    LEAK_EXISTENCE.load(Ordering::Relaxed)
+}
+
+pub(crate) fn set_leak_existence(leak_existence: bool) {
+    LEAK_EXISTENCE.store(leak_existence, Ordering::Relaxed);
+}
+
+/// What happens to running instances when the core shuts down
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default, TS)]
+#[ts(export)]
+pub enum ShutdownBehaviour {
+    /// Stop every running instance and wait for them to exit
+    #[default]
+    Stop,
+    /// Leave the instances running.
+    /// The core does not reattach to them on restart, they can only be killed from then on
+    Detach,
+}
 
 #[derive(Serialize, Deserialize, Clone, TS)]
 #[ts(export)]
@@ -13,6 +66,43 @@ pub struct GlobalSettingsData {
     pub core_name: String,
     pub safe_mode: bool,
     pub domain: Option<String>,
+    /// IANA name of the timezone used to display timestamps, UTC if not set
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub shutdown_behaviour: ShutdownBehaviour,
+    #[serde(default)]
+    pub login_rate_limit: LoginRateLimitConfig,
+    /// How long responses from upstream APIs (Mojang, Paper, Adoptium) are reused before refetching
+    #[serde(default = "default_upstream_cache_ttl_secs")]
+    pub upstream_cache_ttl_secs: u64,
+    /// Timeouts of requests to upstream APIs and downloads
+    #[serde(default)]
+    pub http_timeouts: HttpTimeouts,
+    /// How long the forge installer may run during setup before it's killed
+    #[serde(default = "default_forge_installer_timeout_secs")]
+    pub forge_installer_timeout_secs: u64,
+    /// How long new instances may take to set up before the setup is aborted
+    #[serde(default)]
+    pub setup_timeouts: SetupTimeouts,
+    /// Refuse to start an instance whose max RAM would commit more RAM than the host has, instead of only warning
+    #[serde(default)]
+    pub block_ram_overcommit: bool,
+    /// How many instances can be starting at once, further starts are queued. Unlimited if not set
+    #[serde(default)]
+    pub max_concurrent_starts: Option<usize>,
+    /// Tell users without access to an instance that they lack permission, instead of that it doesn't exist.
+    /// Off by default so that instances can't be enumerated, only worth enabling for trusted deployments
+    #[serde(default)]
+    pub leak_existence: bool,
+}
+
+fn default_upstream_cache_ttl_secs() -> u64 {
+    DEFAULT_UPSTREAM_CACHE_TTL_SECS
+}
+
+fn default_forge_installer_timeout_secs() -> u64 {
+    DEFAULT_FORGE_INSTALLER_TIMEOUT_SECS
 }
 
 impl Default for GlobalSettingsData {
@@ -21,6 +111,16 @@ impl Default for GlobalSettingsData {
             core_name: format!("{}'s Lodestone Core", whoami::realname()),
             safe_mode: true,
             domain: None,
+            timezone: None,
+            shutdown_behaviour: ShutdownBehaviour::default(),
+            login_rate_limit: LoginRateLimitConfig::default(),
+            upstream_cache_ttl_secs: DEFAULT_UPSTREAM_CACHE_TTL_SECS,
+            http_timeouts: HttpTimeouts::default(),
+            forge_installer_timeout_secs: DEFAULT_FORGE_INSTALLER_TIMEOUT_SECS,
+            setup_timeouts: SetupTimeouts::default(),
+            block_ram_overcommit: false,
+            max_concurrent_starts: None,
+            leak_existence: false,
         }
     }
 }
@@ -78,6 +178,8 @@ impl GlobalSettings {
                 self.path_to_global_settings.display()
             ))?;
         }
+        set_core_timezone(self.timezone());
+        set_leak_existence(self.leak_existence());
         Ok(())
     }
     async fn write_to_file(&self) -> Result<(), Error> {
@@ -146,6 +248,194 @@ impl GlobalSettings {
     pub fn domain(&self) -> Option<String> {
         self.global_settings_data.domain.clone()
     }
+
+    pub async fn set_timezone(&mut self, timezone: Option<chrono_tz::Tz>) -> Result<(), Error> {
+        let old_timezone = self.global_settings_data.timezone.clone();
+        self.global_settings_data.timezone = timezone.map(|tz| tz.name().to_string());
+        match self.write_to_file().await {
+            Ok(_) => {
+                set_core_timezone(self.timezone());
+                Ok(())
+            }
+            Err(e) => {
+                self.global_settings_data.timezone = old_timezone;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn timezone(&self) -> chrono_tz::Tz {
+        self.global_settings_data
+            .timezone
+            .as_deref()
+            .and_then(|tz| tz.parse().ok())
+            .unwrap_or(chrono_tz::UTC)
+    }
+
+    pub async fn set_shutdown_behaviour(
+        &mut self,
+        shutdown_behaviour: ShutdownBehaviour,
+    ) -> Result<(), Error> {
+        let old_shutdown_behaviour = self.global_settings_data.shutdown_behaviour;
+        self.global_settings_data.shutdown_behaviour = shutdown_behaviour;
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.shutdown_behaviour = old_shutdown_behaviour;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn shutdown_behaviour(&self) -> ShutdownBehaviour {
+        self.global_settings_data.shutdown_behaviour
+    }
+
+    pub async fn set_login_rate_limit(
+        &mut self,
+        login_rate_limit: LoginRateLimitConfig,
+    ) -> Result<(), Error> {
+        let old_login_rate_limit = self.global_settings_data.login_rate_limit;
+        self.global_settings_data.login_rate_limit = login_rate_limit;
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.login_rate_limit = old_login_rate_limit;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn login_rate_limit(&self) -> LoginRateLimitConfig {
+        self.global_settings_data.login_rate_limit
+    }
+
+    pub async fn set_upstream_cache_ttl(&mut self, ttl: Duration) -> Result<(), Error> {
+        let old_ttl_secs = self.global_settings_data.upstream_cache_ttl_secs;
+        self.global_settings_data.upstream_cache_ttl_secs = ttl.as_secs();
+        match self.write_to_file().await {
+            Ok(_) => {
+                upstream_cache::set_upstream_cache_ttl(ttl);
+                Ok(())
+            }
+            Err(e) => {
+                self.global_settings_data.upstream_cache_ttl_secs = old_ttl_secs;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn upstream_cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.global_settings_data.upstream_cache_ttl_secs)
+    }
+
+    pub async fn set_http_timeouts(&mut self, http_timeouts: HttpTimeouts) -> Result<(), Error> {
+        let old_http_timeouts = self.global_settings_data.http_timeouts;
+        self.global_settings_data.http_timeouts = http_timeouts;
+        match self.write_to_file().await {
+            Ok(_) => {
+                upstream_http::set_http_timeouts(http_timeouts);
+                Ok(())
+            }
+            Err(e) => {
+                self.global_settings_data.http_timeouts = old_http_timeouts;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn http_timeouts(&self) -> HttpTimeouts {
+        self.global_settings_data.http_timeouts
+    }
+
+    pub async fn set_forge_installer_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        let old_timeout_secs = self.global_settings_data.forge_installer_timeout_secs;
+        self.global_settings_data.forge_installer_timeout_secs = timeout.as_secs();
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.forge_installer_timeout_secs = old_timeout_secs;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn forge_installer_timeout(&self) -> Duration {
+        Duration::from_secs(self.global_settings_data.forge_installer_timeout_secs)
+    }
+
+    pub async fn set_setup_timeouts(&mut self, setup_timeouts: SetupTimeouts) -> Result<(), Error> {
+        let old_setup_timeouts = self.global_settings_data.setup_timeouts;
+        self.global_settings_data.setup_timeouts = setup_timeouts;
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.setup_timeouts = old_setup_timeouts;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn setup_timeouts(&self) -> SetupTimeouts {
+        self.global_settings_data.setup_timeouts
+    }
+
+    pub async fn set_block_ram_overcommit(&mut self, block: bool) -> Result<(), Error> {
+        let old_block = self.global_settings_data.block_ram_overcommit;
+        self.global_settings_data.block_ram_overcommit = block;
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.block_ram_overcommit = old_block;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn block_ram_overcommit(&self) -> bool {
+        self.global_settings_data.block_ram_overcommit
+    }
+
+    pub async fn set_max_concurrent_starts(
+        &mut self,
+        max_concurrent_starts: Option<usize>,
+    ) -> Result<(), Error> {
+        let old_max_concurrent_starts = self.global_settings_data.max_concurrent_starts;
+        self.global_settings_data.max_concurrent_starts = max_concurrent_starts;
+        match self.write_to_file().await {
+            Ok(_) => {
+                start_limiter().set_limit(max_concurrent_starts);
+                Ok(())
+            }
+            Err(e) => {
+                self.global_settings_data.max_concurrent_starts = old_max_concurrent_starts;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn max_concurrent_starts(&self) -> Option<usize> {
+        self.global_settings_data.max_concurrent_starts
+    }
+
+    pub async fn set_leak_existence(&mut self, leak_existence: bool) -> Result<(), Error> {
+        let old_leak_existence = self.global_settings_data.leak_existence;
+        self.global_settings_data.leak_existence = leak_existence;
+        match self.write_to_file().await {
+            Ok(_) => {
+                set_leak_existence(leak_existence);
+                Ok(())
+            }
+            Err(e) => {
+                self.global_settings_data.leak_existence = old_leak_existence;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn leak_existence(&self) -> bool {
+        self.global_settings_data.leak_existence
+    }
 }
 
 impl AsRef<GlobalSettingsData> for GlobalSettings {
@@ -0,0 +1,25 @@
+use axum::{extract::Query, routing::get, Json, Router};
+use axum_auth::AuthBearer;
+
+use crate::{
+    auth::user::UserAction,
+    db::audit::{search_audit_log, AuditEntry, AuditQuery},
+    error::Error,
+    AppState,
+};
+
+pub async fn get_audit_log(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ManageUser)?;
+    Ok(Json(search_audit_log(&state.sqlite_pool, query).await?))
+}
+
+pub fn get_audit_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/audit", get(get_audit_log))
+        .with_state(state)
+}
@@ -1,22 +1,35 @@
 use std::sync::Arc;
 
 use axum::{
-    extract::{ws::WebSocket, Path, Query, WebSocketUpgrade},
-    response::Response,
+    extract::{
+        ws::{Message, WebSocket},
+        Path, Query, WebSocketUpgrade,
+    },
+    response::{
+        sse::{Event as SseEvent, KeepAlive, Sse},
+        Response,
+    },
     routing::get,
     Json, Router,
 };
 use axum_auth::AuthBearer;
 
 use color_eyre::eyre::eyre;
-use futures::{SinkExt, StreamExt};
-use ringbuffer::{AllocRingBuffer, RingBufferExt};
+use futures::{
+    stream::{self, SplitSink},
+    SinkExt, Stream, StreamExt,
+};
+use ringbuffer::RingBufferExt;
 use tracing::{debug, error};
 
+use crate::event_broadcaster::EventBroadcaster;
 use crate::output_types::ClientEvent;
-use crate::types::InstanceUuid;
+use crate::types::{InstanceUuid, Snowflake};
 use crate::{
-    auth::{user::UsersManager, user_id::UserId},
+    auth::{
+        user::{UserAction, UsersManager},
+        user_id::UserId,
+    },
     db::read::search_events,
     error::{Error, ErrorKind},
     events::EventQuery,
@@ -26,8 +39,11 @@ use crate::{
     events::{Event, EventInner, UserEventInner},
     AppState,
 };
-use serde::Deserialize;
-use tokio::sync::{broadcast::Receiver, RwLock};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    RwLock,
+};
 use ts_rs::TS;
 
 use super::util::parse_bearer_token;
@@ -113,29 +129,35 @@ pub async fn get_console_buffer(
             kind: ErrorKind::Unauthorized,
             source: eyre!("Token error"),
         })?;
+    if uuid != "all" {
+        requester.try_action(&UserAction::ViewConsole(uuid.clone()))?;
+    }
     Ok(Json(
         state
             .console_out_buffer
             .lock()
             .await
             .get(&uuid)
-            .unwrap_or(&AllocRingBuffer::new())
-            .iter()
-            .filter(|event| match &event.event_inner {
-                EventInner::InstanceEvent(instance_event) => {
-                    (instance_event.instance_uuid == uuid || uuid == "all")
-                        && requester.can_view_event(event)
-                }
-                _ => false,
+            .map(|buffer| {
+                buffer
+                    .iter()
+                    .filter(|event| match &event.event_inner {
+                        EventInner::InstanceEvent(instance_event) => {
+                            (instance_event.instance_uuid == uuid || uuid == "all")
+                                && requester.can_view_event(event)
+                        }
+                        _ => false,
+                    })
+                    .cloned()
+                    .collect()
             })
-            .cloned()
-            .collect(),
+            .unwrap_or_default(),
     ))
 }
 
 #[derive(Deserialize)]
 pub struct WebsocketQuery {
-    token: String,
+    pub token: String,
 }
 
 pub async fn event_stream(
@@ -167,21 +189,89 @@ pub async fn event_stream(
     let event_receiver = state.event_broadcaster.subscribe();
 
     Ok(ws.on_upgrade(move |socket| {
-        event_stream_ws(socket, event_receiver, query, user.uid, state.users_manager)
+        event_stream_ws(
+            socket,
+            event_receiver,
+            state.event_broadcaster,
+            query,
+            user.uid,
+            state.users_manager,
+        )
     }))
 }
 
+/// Sent on a stream in place of the events it missed after falling behind.
+/// The client should refetch the events after `last_snowflake` to get back in sync
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+#[serde(tag = "type")]
+pub enum EventStreamSignal {
+    Resync {
+        missed: u64,
+        /// The last event received before falling behind
+        last_snowflake: Option<Snowflake>,
+    },
+}
+
+/// Records the lag and returns the signal telling the client to resync
+fn resync_signal(
+    event_broadcaster: &EventBroadcaster,
+    subscriber: &str,
+    missed: u64,
+    last_snowflake: Option<Snowflake>,
+) -> EventStreamSignal {
+    event_broadcaster.record_lag(subscriber, missed);
+    EventStreamSignal::Resync {
+        missed,
+        last_snowflake,
+    }
+}
+
+/// Records the lag and tells the client to resync, returns whether the client is still connected
+async fn send_resync(
+    sender: &mut SplitSink<WebSocket, Message>,
+    event_broadcaster: &EventBroadcaster,
+    subscriber: &str,
+    missed: u64,
+    last_snowflake: Option<Snowflake>,
+) -> bool {
+    let signal = resync_signal(event_broadcaster, subscriber, missed, last_snowflake);
+    match sender
+        .send(Message::Text(serde_json::to_string(&signal).unwrap()))
+        .await
+    {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Failed to send resync signal: {}", e);
+            false
+        }
+    }
+}
+
 async fn event_stream_ws(
     stream: WebSocket,
     mut event_receiver: Receiver<Event>,
+    event_broadcaster: EventBroadcaster,
     query: EventQuery,
     uid: UserId,
     users_manager: Arc<RwLock<UsersManager>>,
 ) {
     let (mut sender, mut receiver) = stream.split();
+    let mut last_snowflake = None;
     loop {
         tokio::select! {
-            Ok(event) = event_receiver.recv() => {
+            result = event_receiver.recv() => {
+                let event = match result {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        if send_resync(&mut sender, &event_broadcaster, "Event stream", missed, last_snowflake).await {
+                            continue;
+                        }
+                        break;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                last_snowflake = Some(event.snowflake);
                 if event.is_event_console_message() {
                     continue;
                 }
@@ -191,7 +281,11 @@ async fn event_stream_ws(
                         break;
                     }
                 };
-                if query.filter(ClientEvent::from(event.clone())) && user.can_view_event(&event) {
+                let client_event = ClientEvent::from(&event);
+                if query.filter(&client_event)
+                    && user.notification_preferences.in_app(&client_event)
+                    && user.can_view_event(&event)
+                {
                     if let Err(e) = sender.send(axum::extract::ws::Message::Text(serde_json::to_string(&event).unwrap())).await {
                         error!("Error sending event to websocket: {}", e);
                         break;
@@ -208,6 +302,99 @@ async fn event_stream_ws(
     }
 }
 
+/// The global event stream as Server-Sent Events, for clients that can't use a websocket.
+///
+/// Events are sent as `event`, and an [`EventStreamSignal`] as `resync` in place of the events
+/// missed after falling behind. As `EventSource` can't set headers, the token is passed in the
+/// `bearer_token` of the filter like for the websocket stream
+pub async fn event_stream_sse(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    query: Query<EventQueryWrapper>,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, axum::Error>>>, Error> {
+    let query: EventQuery = serde_json::from_str(query.filter.as_str()).map_err(|e| {
+        error!("Error deserializing event query: {}", e);
+        Error {
+            kind: ErrorKind::BadRequest,
+            source: e.into(),
+        }
+    })?;
+    let token = query.bearer_token.clone().ok_or(Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Missing token"),
+    })?;
+
+    let user = state
+        .users_manager
+        .read()
+        .await
+        .try_auth(&token)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Token error"),
+        })?;
+    let event_receiver = state.event_broadcaster.subscribe();
+
+    Ok(Sse::new(sse_events(
+        event_receiver,
+        state.event_broadcaster,
+        query,
+        user.uid,
+        state.users_manager,
+    ))
+    .keep_alive(KeepAlive::default()))
+}
+
+/// The events `uid` can view and wants in-app, ending once the user is deleted
+fn sse_events(
+    event_receiver: Receiver<Event>,
+    event_broadcaster: EventBroadcaster,
+    query: EventQuery,
+    uid: UserId,
+    users_manager: Arc<RwLock<UsersManager>>,
+) -> impl Stream<Item = Result<SseEvent, axum::Error>> {
+    let last_snowflake: Option<Snowflake> = None;
+    stream::unfold(
+        (event_receiver, last_snowflake),
+        move |(mut event_receiver, mut last_snowflake)| {
+            let event_broadcaster = event_broadcaster.clone();
+            let query = query.clone();
+            let uid = uid.clone();
+            let users_manager = users_manager.clone();
+            async move {
+                loop {
+                    let event = match event_receiver.recv().await {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(missed)) => {
+                            let signal = resync_signal(
+                                &event_broadcaster,
+                                "Event SSE stream",
+                                missed,
+                                last_snowflake,
+                            );
+                            let sse_event = SseEvent::default().event("resync").json_data(signal);
+                            return Some((sse_event, (event_receiver, last_snowflake)));
+                        }
+                        Err(RecvError::Closed) => return None,
+                    };
+                    last_snowflake = Some(event.snowflake);
+                    if event.is_event_console_message() {
+                        continue;
+                    }
+                    let user = users_manager.read().await.get_user(&uid)?;
+                    let client_event = ClientEvent::from(&event);
+                    if query.filter(&client_event)
+                        && user.notification_preferences.in_app(&client_event)
+                        && user.can_view_event(&event)
+                    {
+                        let sse_event = SseEvent::default().event("event").json_data(&event);
+                        return Some((sse_event, (event_receiver, last_snowflake)));
+                    }
+                }
+            }
+        },
+    )
+}
+
 pub async fn console_stream(
     ws: WebSocketUpgrade,
     axum::extract::State(state): axum::extract::State<AppState>,
@@ -223,24 +410,47 @@ pub async fn console_stream(
             source: eyre!("Token error"),
         })?;
     drop(users_manager);
+    if uuid != "all" {
+        user.try_action(&UserAction::ViewConsole(uuid.clone()))?;
+    }
     let event_receiver = state.event_broadcaster.subscribe();
 
     Ok(ws.on_upgrade(move |socket| {
-        console_stream_ws(socket, event_receiver, user.uid, uuid, state.users_manager)
+        console_stream_ws(
+            socket,
+            event_receiver,
+            state.event_broadcaster,
+            user.uid,
+            uuid,
+            state.users_manager,
+        )
     }))
 }
 
 async fn console_stream_ws(
     stream: WebSocket,
     mut event_receiver: Receiver<Event>,
+    event_broadcaster: EventBroadcaster,
     uid: UserId,
     uuid: InstanceUuid,
     users_manager: Arc<RwLock<UsersManager>>,
 ) {
     let (mut sender, mut receiver) = stream.split();
+    let mut last_snowflake = None;
     loop {
         tokio::select! {
-            Ok(event) = event_receiver.recv() => {
+            result = event_receiver.recv() => {
+                let event = match result {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        if send_resync(&mut sender, &event_broadcaster, "Console stream", missed, last_snowflake).await {
+                            continue;
+                        }
+                        break;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                last_snowflake = Some(event.snowflake);
                 match &event.event_inner {
                     EventInner::InstanceEvent(instance_event) => {
                         let user = match users_manager.read().await.get_user(&uid) {
@@ -289,9 +499,138 @@ async fn console_stream_ws(
 pub fn get_events_routes(state: AppState) -> Router {
     Router::new()
         .route("/events/:uuid/stream", get(event_stream))
+        .route("/events/sse", get(event_stream_sse))
         .route("/events/:uuid/buffer", get(get_event_buffer))
         .route("/events/search", get(get_event_search))
         .route("/instance/:uuid/console/stream", get(console_stream))
         .route("/instance/:uuid/console/buffer", get(get_console_buffer))
         .with_state(state)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use axum::body::{BoxBody, HttpBody};
+    use axum::response::IntoResponse;
+
+    use super::*;
+    use crate::auth::{permission::UserPermission, user::User};
+    use crate::events::CausedBy;
+    use crate::traits::t_server::State;
+
+    async fn users_manager(users: Vec<User>) -> Arc<RwLock<UsersManager>> {
+        let temp_dir = tempdir::TempDir::new("test_event_sse").unwrap().into_path();
+        let (event_broadcaster, _rx) = EventBroadcaster::new(10);
+        let mut users_manager = UsersManager::new(
+            event_broadcaster,
+            HashMap::new(),
+            temp_dir.join("users.json"),
+        );
+        for user in users {
+            users_manager
+                .add_user(user, CausedBy::System)
+                .await
+                .unwrap();
+        }
+        Arc::new(RwLock::new(users_manager))
+    }
+
+    /// Connects `user` to a stream of the events sent on `event_broadcaster`
+    fn connect(
+        event_broadcaster: &EventBroadcaster,
+        user: &User,
+        users_manager: Arc<RwLock<UsersManager>>,
+    ) -> BoxBody {
+        let query: EventQuery = serde_json::from_str("{}").unwrap();
+        Sse::new(sse_events(
+            event_broadcaster.subscribe(),
+            event_broadcaster.clone(),
+            query,
+            user.uid.clone(),
+            users_manager,
+        ))
+        .into_response()
+        .into_body()
+    }
+
+    async fn next_message(body: &mut BoxBody) -> String {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), body.data())
+            .await
+            .expect("no event received")
+            .unwrap()
+            .unwrap();
+        String::from_utf8(chunk.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sse_stream_filters_events() {
+        let owner = User::new(
+            "owner".to_string(),
+            "12345",
+            true,
+            false,
+            UserPermission::default(),
+        );
+        let user = User::new(
+            "user".to_string(),
+            "12345",
+            false,
+            false,
+            UserPermission::default(),
+        );
+        let users_manager = users_manager(vec![owner.clone(), user.clone()]).await;
+        let (event_broadcaster, _rx) = EventBroadcaster::new(10);
+        let mut owner_stream = connect(&event_broadcaster, &owner, users_manager.clone());
+        let mut user_stream = connect(&event_broadcaster, &user, users_manager);
+
+        event_broadcaster.send(Event::new_instance_state_transition(
+            InstanceUuid::from("instance-uuid".to_string()),
+            "test".to_string(),
+            State::Starting,
+        ));
+        let (progression_start, _) =
+            Event::new_progression_event_start("progress", None, None, CausedBy::System);
+        event_broadcaster.send(progression_start);
+
+        let message = next_message(&mut owner_stream).await;
+        assert!(message.starts_with("event: event\n"), "{message}");
+        assert!(message.contains("instance-uuid"), "{message}");
+
+        // the user can't view the instance, so the first event they get is the progression
+        let message = next_message(&mut user_stream).await;
+        assert!(message.starts_with("event: event\n"), "{message}");
+        assert!(!message.contains("instance-uuid"), "{message}");
+        assert!(message.contains("progress"), "{message}");
+    }
+
+    #[tokio::test]
+    async fn test_sse_stream_resyncs_after_lagging() {
+        let owner = User::new(
+            "owner".to_string(),
+            "12345",
+            true,
+            false,
+            UserPermission::default(),
+        );
+        let users_manager = users_manager(vec![owner.clone()]).await;
+        let (event_broadcaster, _rx) = EventBroadcaster::new(1);
+        let mut stream = connect(&event_broadcaster, &owner, users_manager);
+
+        for state in [State::Starting, State::Running, State::Stopping] {
+            event_broadcaster.send(Event::new_instance_state_transition(
+                InstanceUuid::from("instance-uuid".to_string()),
+                "test".to_string(),
+                state,
+            ));
+        }
+
+        let message = next_message(&mut stream).await;
+        assert!(message.starts_with("event: resync\n"), "{message}");
+        assert!(message.contains("\"missed\":2"), "{message}");
+        // the stream carries on from the events still buffered
+        let message = next_message(&mut stream).await;
+        assert!(message.contains("Stopping"), "{message}");
+    }
+}
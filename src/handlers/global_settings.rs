@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use axum::{
     routing::{get, put},
     Json, Router,
@@ -5,7 +7,11 @@ use axum::{
 use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
 
-use crate::{error::ErrorKind, AppState, Error, GlobalSettingsData};
+use crate::{
+    auth::rate_limiter::LoginRateLimitConfig, error::ErrorKind, global_settings::ShutdownBehaviour,
+    implementations::minecraft::setup_deadline::SetupTimeouts, upstream_http::HttpTimeouts,
+    AppState, Error, GlobalSettingsData,
+};
 
 pub async fn get_core_settings(
     axum::extract::State(state): axum::extract::State<AppState>,
@@ -111,11 +117,293 @@ pub async fn change_domain(
     Ok(())
 }
 
+pub async fn change_timezone(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(new_timezone): Json<String>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change core timezone"),
+        });
+    }
+    let new_timezone = if new_timezone.is_empty() {
+        None
+    } else {
+        Some(new_timezone.parse::<chrono_tz::Tz>().map_err(|_| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Unknown timezone {}", new_timezone),
+        })?)
+    };
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_timezone(new_timezone)
+        .await?;
+    Ok(())
+}
+
+pub async fn change_shutdown_behaviour(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(shutdown_behaviour): Json<ShutdownBehaviour>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change core shutdown behaviour"),
+        });
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_shutdown_behaviour(shutdown_behaviour)
+        .await?;
+    Ok(())
+}
+
+pub async fn change_login_rate_limit(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(login_rate_limit): Json<LoginRateLimitConfig>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change core login rate limit"),
+        });
+    }
+    if login_rate_limit.max_attempts == 0 {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("At least one login attempt must be allowed"),
+        });
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_login_rate_limit(login_rate_limit)
+        .await?;
+    state
+        .login_rate_limiter
+        .lock()
+        .await
+        .set_config(login_rate_limit);
+    Ok(())
+}
+
+pub async fn change_upstream_cache_ttl(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(ttl_secs): Json<u64>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change core upstream cache TTL"),
+        });
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_upstream_cache_ttl(Duration::from_secs(ttl_secs))
+        .await?;
+    Ok(())
+}
+
+pub async fn change_http_timeouts(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(http_timeouts): Json<HttpTimeouts>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change core HTTP timeouts"),
+        });
+    }
+    if http_timeouts.connect_timeout_secs == 0 || http_timeouts.read_timeout_secs == 0 {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("HTTP timeouts must be at least one second"),
+        });
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_http_timeouts(http_timeouts)
+        .await?;
+    Ok(())
+}
+
+pub async fn change_forge_installer_timeout(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(timeout_secs): Json<u64>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change core forge installer timeout"),
+        });
+    }
+    if timeout_secs == 0 {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Forge installer timeout must be at least one second"),
+        });
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_forge_installer_timeout(Duration::from_secs(timeout_secs))
+        .await?;
+    Ok(())
+}
+
+pub async fn change_setup_timeouts(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(setup_timeouts): Json<SetupTimeouts>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change core setup timeouts"),
+        });
+    }
+    setup_timeouts.validate()?;
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_setup_timeouts(setup_timeouts)
+        .await?;
+    Ok(())
+}
+
+pub async fn change_block_ram_overcommit(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(block): Json<bool>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change whether RAM overcommit is blocked"),
+        });
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_block_ram_overcommit(block)
+        .await?;
+    Ok(())
+}
+
+pub async fn change_leak_existence(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(leak_existence): Json<bool>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change whether instance existence is revealed"),
+        });
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_leak_existence(leak_existence)
+        .await?;
+    Ok(())
+}
+
+pub async fn change_max_concurrent_starts(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(max_concurrent_starts): Json<Option<usize>>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change how many instances can start at once"),
+        });
+    }
+    if max_concurrent_starts == Some(0) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("At least one instance must be able to start at a time"),
+        });
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_max_concurrent_starts(max_concurrent_starts)
+        .await?;
+    Ok(())
+}
+
 pub fn get_global_settings_routes(state: AppState) -> Router {
     Router::new()
         .route("/global_settings", get(get_core_settings))
         .route("/global_settings/name", put(change_core_name))
         .route("/global_settings/safe_mode", put(change_core_safe_mode))
         .route("/global_settings/domain", put(change_domain))
+        .route("/global_settings/timezone", put(change_timezone))
+        .route(
+            "/global_settings/shutdown_behaviour",
+            put(change_shutdown_behaviour),
+        )
+        .route(
+            "/global_settings/login_rate_limit",
+            put(change_login_rate_limit),
+        )
+        .route(
+            "/global_settings/upstream_cache_ttl",
+            put(change_upstream_cache_ttl),
+        )
+        .route("/global_settings/http_timeouts", put(change_http_timeouts))
+        .route(
+            "/global_settings/forge_installer_timeout",
+            put(change_forge_installer_timeout),
+        )
+        .route(
+            "/global_settings/setup_timeouts",
+            put(change_setup_timeouts),
+        )
+        .route(
+            "/global_settings/block_ram_overcommit",
+            put(change_block_ram_overcommit),
+        )
+        .route(
+            "/global_settings/max_concurrent_starts",
+            put(change_max_concurrent_starts),
+        )
+        .route(
+            "/global_settings/leak_existence",
+            put(change_leak_existence),
+        )
         .with_state(state)
 }
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+
+use axum::{http::StatusCode, routing::get, Json, Router};
+use serde::{Deserialize, Serialize};
+use sysinfo::{DiskExt, SystemExt};
+
+use crate::{
+    event_broadcaster::EventBroadcasterStats,
+    prelude::path_to_instances,
+    traits::t_server::{State, TServer},
+    AppState,
+};
+
+/// Below this much free space in the instances directory, the core is considered not ready
+const MIN_AVAILABLE_DISK_BYTES: u64 = 100 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub instances: HashMap<String, u32>,
+    pub event_broadcaster_healthy: bool,
+    pub event_broadcaster_stats: EventBroadcasterStats,
+    pub database_healthy: bool,
+    /// Free space on the disk holding the instances directory
+    pub available_disk: Option<u64>,
+}
+
+async fn database_healthy(state: &AppState) -> bool {
+    sqlx::query("SELECT 1")
+        .execute(&state.sqlite_pool)
+        .await
+        .is_ok()
+}
+
+fn event_broadcaster_healthy(state: &AppState) -> bool {
+    // the event buffer and db writer tasks should always be subscribed
+    state.event_broadcaster.receiver_count() > 0
+}
+
+async fn available_disk(state: &AppState) -> Option<u64> {
+    let mut sys = state.system.lock().await;
+    sys.refresh_disks_list();
+    let path_to_instances =
+        std::fs::canonicalize(path_to_instances()).unwrap_or_else(|_| path_to_instances().clone());
+    // the disk with the longest mount point that contains the instances directory
+    sys.disks()
+        .iter()
+        .filter(|disk| path_to_instances.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
+
+struct Readiness {
+    database_healthy: bool,
+    event_broadcaster_healthy: bool,
+    available_disk: Option<u64>,
+}
+
+impl Readiness {
+    async fn check(state: &AppState) -> Self {
+        Self {
+            database_healthy: database_healthy(state).await,
+            event_broadcaster_healthy: event_broadcaster_healthy(state),
+            available_disk: available_disk(state).await,
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        self.database_healthy
+            && self.event_broadcaster_healthy
+            && self
+                .available_disk
+                .map(|available| available >= MIN_AVAILABLE_DISK_BYTES)
+                .unwrap_or(true)
+    }
+}
+
+pub async fn get_health(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> (StatusCode, Json<HealthReport>) {
+    let mut instances = HashMap::new();
+    for instance_state in [
+        State::Starting,
+        State::Running,
+        State::Stopping,
+        State::Stopped,
+        State::Error,
+    ] {
+        instances.insert(instance_state.to_string(), 0);
+    }
+    for instance in state.instances.lock().await.values() {
+        *instances
+            .entry(instance.state().await.to_string())
+            .or_insert(0) += 1;
+    }
+    let readiness = Readiness::check(&state).await;
+    let ready = readiness.is_ready();
+    (
+        if ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        },
+        Json(HealthReport {
+            healthy: ready,
+            instances,
+            event_broadcaster_healthy: readiness.event_broadcaster_healthy,
+            event_broadcaster_stats: state.event_broadcaster.stats(),
+            database_healthy: readiness.database_healthy,
+            available_disk: readiness.available_disk,
+        }),
+    )
+}
+
+/// Liveness probe, succeeds as long as the core is able to serve requests
+pub async fn get_liveness() -> StatusCode {
+    StatusCode::OK
+}
+
+/// Readiness probe, fails if a critical subsystem is down
+pub async fn get_readiness(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> StatusCode {
+    if Readiness::check(&state).await.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+pub fn get_health_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/health", get(get_health))
+        .route("/health/live", get(get_liveness))
+        .route("/health/ready", get(get_readiness))
+        .with_state(state)
+}
@@ -1,22 +1,37 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
 use axum::routing::{delete, get, post};
 use axum::Router;
-use axum::{extract::Path, Json};
+use axum::{
+    extract::{Path, Query},
+    Json,
+};
 use axum_auth::AuthBearer;
+use headers::HeaderMap;
 
 use color_eyre::eyre::{eyre, Context};
 use serde::Deserialize;
 use tracing::error;
 
-use crate::auth::user::UserAction;
+use crate::auth::user::{User, UserAction};
+use crate::db::audit::{log_audit_entry, AuditAction};
 use crate::error::{Error, ErrorKind};
-use crate::events::{CausedBy, Event, ProgressionEndValue, ProgressionStartValue};
+use crate::events::{
+    CausedBy, Event, ProgressionEndValue, ProgressionEventID, ProgressionStartValue,
+};
 
 use crate::implementations::generic;
+use crate::implementations::generic::sandbox::BundlePermissions;
 use crate::traits::t_configurable::GameType;
 
 
-use crate::implementations::minecraft::MinecraftInstance;
+use crate::implementations::minecraft::setup::SetupCheckpoint;
+use crate::implementations::minecraft::setup_deadline::SetupDeadline;
+use crate::implementations::minecraft::{MinecraftInstance, SetupConfig};
+use crate::port_manager::DEFAULT_RCON_PORT;
 use crate::prelude::{path_to_instances, GameInstance};
+use crate::remote_core::remote_core_depth;
 use crate::traits::t_configurable::manifest::SetupValue;
 use crate::traits::{t_configurable::TConfigurable, t_server::TServer, InstanceInfo, TInstance};
 
@@ -25,31 +40,90 @@ use crate::{implementations::minecraft, traits::t_server::State, AppState};
 
 use super::instance_setup_configs::HandlerGameType;
 
+#[derive(Deserialize)]
+pub struct InstanceListQuery {
+    /// Only list instances with this tag
+    tag: Option<String>,
+}
+
 pub async fn get_instance_list(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
+    headers: HeaderMap,
+    Query(query): Query<InstanceListQuery>,
 ) -> Result<Json<Vec<InstanceInfo>>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     let mut list_of_configs: Vec<InstanceInfo> = Vec::new();
 
     let instances = state.instances.lock().await;
     for instance in instances.values() {
-        if requester.can_perform_action(&UserAction::ViewInstance(instance.uuid().await)) {
-            list_of_configs.push(instance.get_instance_info().await);
+        if !requester.can_perform_action(&UserAction::ViewInstance(instance.uuid().await)) {
+            continue;
+        }
+        if let Some(tag) = &query.tag {
+            if !instance.tags().await.contains(tag) {
+                continue;
+            }
         }
+        list_of_configs.push(instance.get_instance_info().await);
     }
+    drop(instances);
+    if query.tag.is_none() {
+        list_of_configs.extend(
+            state
+                .pending_instances
+                .list(|uuid, created_by| {
+                    created_by == Some(&requester.uid)
+                        || requester.can_perform_action(&UserAction::ViewInstance(uuid.clone()))
+                })
+                .await,
+        );
+    }
+    list_of_configs.extend(
+        state
+            .remote_cores
+            .instance_list(remote_core_depth(&headers))
+            .await
+            .into_iter()
+            .filter(|info| {
+                requester.can_perform_action(&UserAction::ViewInstance(info.uuid.clone()))
+            })
+            .filter(|info| {
+                query
+                    .tag
+                    .as_ref()
+                    .map(|tag| info.tags.contains(tag))
+                    .unwrap_or(true)
+            }),
+    );
 
     list_of_configs.sort_by(|a, b| a.creation_time.cmp(&b.creation_time));
 
     Ok(Json(list_of_configs))
 }
 
+/// All tags in use by instances the requester can view, for building filters
+pub async fn get_instance_tags_in_use(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<BTreeSet<String>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let mut tags = BTreeSet::new();
+    for instance in state.instances.lock().await.values() {
+        if requester.can_perform_action(&UserAction::ViewInstance(instance.uuid().await)) {
+            tags.extend(instance.tags().await);
+        }
+    }
+    Ok(Json(tags))
+}
+
 pub async fn get_instance_info(
     Path(uuid): Path<InstanceUuid>,
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<InstanceInfo>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
 
     let instances = state.instances.lock().await;
 
@@ -58,7 +132,6 @@ pub async fn get_instance_info(
         source: eyre!("Instance not found"),
     })?;
 
-    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
     Ok(Json(instance.get_instance_info().await))
 }
 
@@ -70,7 +143,6 @@ pub async fn create_minecraft_instance(
 ) -> Result<Json<InstanceUuid>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::CreateInstance)?;
-    let mut perm = requester.permissions;
 
     let mut instance_uuid = InstanceUuid::default();
 
@@ -86,7 +158,31 @@ pub async fn create_minecraft_instance(
 
     let flavour = game_type.try_into()?;
 
-    let setup_config = MinecraftInstance::construct_setup_config(manifest_value, flavour).await?;
+    let mut setup_config =
+        MinecraftInstance::construct_setup_config(manifest_value, flavour).await?;
+    setup_config.forge_installer_timeout_secs = Some(
+        state
+            .global_settings
+            .lock()
+            .await
+            .forge_installer_timeout()
+            .as_secs(),
+    );
+    setup_config.setup_timeouts = Some(state.global_settings.lock().await.setup_timeouts());
+
+    // reserved up front so that instances set up at the same time don't get the same ports
+    let rcon_start = setup_config
+        .server_properties
+        .get("rcon.port")
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(DEFAULT_RCON_PORT);
+    let ports = state
+        .port_manager
+        .lock()
+        .await
+        .allocate_instance_ports(setup_config.port, rcon_start)?;
+    setup_config.rcon_port = ports.rcon;
+    setup_config.query_port = ports.query;
 
     let setup_path = path_to_instances().join(format!(
         "{}-{}",
@@ -94,112 +190,284 @@ pub async fn create_minecraft_instance(
         &instance_uuid.no_prefix()[0..8]
     ));
 
-    tokio::fs::create_dir_all(&setup_path)
+    let dot_lodestone_config = DotLodestoneConfig::new(instance_uuid.clone(), game_type.into());
+
+    let prepared: Result<(), Error> = async {
+        tokio::fs::create_dir_all(&setup_path)
+            .await
+            .context("Failed to create instance directory")?;
+        // write dot lodestone config
+        tokio::fs::write(
+            setup_path.join(".lodestone_config"),
+            serde_json::to_string_pretty(&dot_lodestone_config).unwrap(),
+        )
         .await
-        .context("Failed to create instance directory")?;
+        .context("Failed to write .lodestone_config file")?;
+        SetupCheckpoint::new(setup_config.clone(), Some(requester.uid.clone()))
+            .save(&setup_path)
+            .await?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = prepared {
+        state.port_manager.lock().await.deallocate_ports(&ports);
+        return Err(e);
+    }
 
-    let dot_lodestone_config = DotLodestoneConfig::new(instance_uuid.clone(), game_type.into());
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    let (progression_start_event, event_id) =
+        minecraft_setup_progression_start(&instance_uuid, &setup_config, caused_by);
 
-    // write dot lodestone config
+    // listed with a setting up state until the setup task finishes
+    state
+        .pending_instances
+        .insert(
+            minecraft_setup_info(&instance_uuid, &setup_path, &setup_config),
+            Some(requester.uid.clone()),
+            event_id.snowflake(),
+        )
+        .await;
 
-    tokio::fs::write(
-        setup_path.join(".lodestone_config"),
-        serde_json::to_string_pretty(&dot_lodestone_config).unwrap(),
+    spawn_minecraft_setup(
+        state,
+        requester,
+        instance_uuid.clone(),
+        setup_config,
+        dot_lodestone_config,
+        setup_path,
+        progression_start_event,
+        event_id,
+    );
+    Ok(Json(instance_uuid))
+}
+
+/// Info of a Minecraft instance that is still being set up
+pub fn minecraft_setup_info(
+    uuid: &InstanceUuid,
+    setup_path: &std::path::Path,
+    setup_config: &SetupConfig,
+) -> InstanceInfo {
+    InstanceInfo {
+        uuid: uuid.clone(),
+        name: setup_config.name.clone(),
+        game_type: setup_config.flavour.clone().into(),
+        description: setup_config.description.clone().unwrap_or_default(),
+        version: setup_config.version.clone(),
+        port: setup_config.port,
+        creation_time: chrono::Utc::now().timestamp(),
+        path: setup_path.display().to_string(),
+        auto_start: setup_config.auto_start.unwrap_or(false),
+        restart_on_crash: setup_config.restart_on_crash.unwrap_or(false),
+        state: State::SettingUp,
+        player_count: None,
+        max_player_count: None,
+        player_list: None,
+        last_crash: None,
+        tags: Vec::new(),
+        setup_progress: None,
+        available_update: None,
+        core_id: None,
+        start_queue_position: None,
+        uptime_seconds: None,
+        last_started: None,
+        disk_usage: None,
+    }
+}
+
+fn minecraft_setup_progression_start(
+    uuid: &InstanceUuid,
+    setup_config: &SetupConfig,
+    caused_by: CausedBy,
+) -> (Event, ProgressionEventID) {
+    Event::new_progression_event_start(
+        format!("Setting up Minecraft server {}", setup_config.name),
+        Some(10.0),
+        Some(ProgressionStartValue::InstanceCreation {
+            instance_uuid: uuid.clone(),
+            instance_name: setup_config.name.clone(),
+            port: setup_config.port,
+            flavour: setup_config.flavour.to_string(),
+            game_type: "minecraft".to_string(),
+        }),
+        caused_by,
     )
-    .await
-    .context("Failed to write .lodestone_config file")?;
+}
 
-    tokio::task::spawn({
-        let uuid = instance_uuid.clone();
-        let instance_name = setup_config.name.clone();
-        let event_broadcaster = state.event_broadcaster.clone();
-        let port = setup_config.port;
-        let flavour = setup_config.flavour.clone();
-        let caused_by = CausedBy::User {
-            user_id: requester.uid.clone(),
-            user_name: requester.username.clone(),
-        };
-        async move {
-            let (progression_start_event, event_id) = Event::new_progression_event_start(
-                format!("Setting up Minecraft server {instance_name}"),
-                Some(10.0),
-                Some(ProgressionStartValue::InstanceCreation {
-                    instance_uuid: uuid.clone(),
-                    instance_name: instance_name.clone(),
-                    port,
-                    flavour: flavour.to_string(),
-                    game_type: "minecraft".to_string(),
-                }),
-                caused_by,
-            );
-            event_broadcaster.send(progression_start_event);
-            let minecraft_instance = match minecraft::MinecraftInstance::new(
-                setup_config.clone(),
+/// Sets up a Minecraft instance in the background, from its checkpoint if it's a retry.
+///
+/// The instance directory is kept if the setup fails, so that it can be retried
+#[allow(clippy::too_many_arguments)]
+fn spawn_minecraft_setup(
+    state: AppState,
+    requester: User,
+    uuid: InstanceUuid,
+    setup_config: SetupConfig,
+    dot_lodestone_config: DotLodestoneConfig,
+    setup_path: PathBuf,
+    progression_start_event: Event,
+    event_id: ProgressionEventID,
+) {
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    let mut perm = requester.permissions;
+    let instance_name = setup_config.name.clone();
+    let event_broadcaster = state.event_broadcaster.clone();
+    tokio::task::spawn(async move {
+        event_broadcaster.send(progression_start_event);
+        // a setup that runs out of time fails like any other, dropping it kills the installers
+        let deadline = SetupDeadline::new(setup_config.setup_timeouts.unwrap_or_default());
+        let minecraft_instance = match deadline
+            .run(minecraft::MinecraftInstance::new(
+                setup_config,
                 dot_lodestone_config,
-                setup_path.clone(),
+                setup_path,
                 &event_id,
                 state.event_broadcaster.clone(),
                 state.macro_executor.clone(),
-            )
+                &deadline,
+            ))
             .await
-            {
-                Ok(v) => {
-                    event_broadcaster.send(Event::new_progression_event_end(
-                        event_id,
-                        true,
-                        Some("Instance created successfully"),
-                        Some(ProgressionEndValue::InstanceCreation(
-                            v.get_instance_info().await,
-                        )),
-                    ));
-                    v
-                }
-                Err(e) => {
-                    event_broadcaster.send(Event::new_progression_event_end(
-                        event_id,
-                        false,
-                        Some(&format!("Instance creation failed: {e}")),
-                        None,
-                    ));
-                    crate::util::fs::remove_dir_all(setup_path)
-                        .await
-                        .context("Failed to remove directory after instance creation failed")
-                        .unwrap();
-                    return;
-                }
-            };
-            let mut port_manager = state.port_manager.lock().await;
-            port_manager.add_port(setup_config.port);
-            perm.can_start_instance.insert(uuid.clone());
-            perm.can_stop_instance.insert(uuid.clone());
-            perm.can_view_instance.insert(uuid.clone());
-            perm.can_read_instance_file.insert(uuid.clone());
-            perm.can_write_instance_file.insert(uuid.clone());
-            // ignore errors since we don't care if the permissions update fails
-            let _ = state
-                .users_manager
-                .write()
-                .await
-                .update_permissions(&requester.uid, perm, CausedBy::System)
-                .await
-                .map_err(|e| {
-                    error!("Failed to update permissions: {:?}", e);
-                    e
-                });
-            state
-                .instances
-                .lock()
-                .await
-                .insert(uuid.clone(), minecraft_instance.into());
-        }
+        {
+            Ok(v) => {
+                event_broadcaster.send(Event::new_progression_event_end(
+                    event_id,
+                    true,
+                    Some("Instance created successfully"),
+                    Some(ProgressionEndValue::InstanceCreation(
+                        v.get_instance_info().await,
+                    )),
+                ));
+                log_audit_entry(
+                    &state.sqlite_pool,
+                    AuditAction::InstanceCreated,
+                    &caused_by,
+                    Some(uuid.to_string()),
+                    format!("Created Minecraft instance {instance_name}"),
+                )
+                .await;
+                v
+            }
+            Err(e) => {
+                event_broadcaster.send(Event::new_progression_event_end(
+                    event_id,
+                    false,
+                    Some(&format!("Instance creation failed: {e}")),
+                    None,
+                ));
+                // the ports stay allocated and the directory stays, for a retry to pick up from
+                state
+                    .pending_instances
+                    .fail(&uuid, format!("Setup failed: {e}"))
+                    .await;
+                return;
+            }
+        };
+        perm.can_start_instance.insert(uuid.clone());
+        perm.can_stop_instance.insert(uuid.clone());
+        perm.can_view_instance.insert(uuid.clone());
+        perm.can_view_instance_console.insert(uuid.clone());
+        perm.can_read_instance_file.insert(uuid.clone());
+        perm.can_write_instance_file.insert(uuid.clone());
+        perm.can_manage_instance_players.insert(uuid.clone());
+        perm.can_backup_instance.insert(uuid.clone());
+        perm.can_manage_offsite_backup.insert(uuid.clone());
+        // ignore errors since we don't care if the permissions update fails
+        let _ = state
+            .users_manager
+            .write()
+            .await
+            .update_permissions(&requester.uid, perm, CausedBy::System)
+            .await
+            .map_err(|e| {
+                error!("Failed to update permissions: {:?}", e);
+                e
+            });
+        state
+            .insert_instance(uuid.clone(), minecraft_instance.into())
+            .await;
+        state.pending_instances.remove(&uuid).await;
     });
-    Ok(Json(instance_uuid))
+}
+
+/// Runs the setup of a Minecraft instance that failed again, skipping what it already did
+pub async fn retry_instance_setup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    let info = state
+        .pending_instances
+        .failed(&uuid)
+        .await
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("No failed setup found for this instance"),
+        })?;
+    let setup_path = PathBuf::from(&info.path);
+    let checkpoint = SetupCheckpoint::load(&setup_path)
+        .await?
+        .ok_or_else(|| Error {
+            kind: ErrorKind::InvalidInstanceState,
+            source: eyre!("The setup checkpoint of this instance is missing, delete the instance and create it again"),
+        })?;
+    // the retry carries on the setup as whoever started it
+    if checkpoint.created_by.as_ref() != Some(&requester.uid) && !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Only the user who created this instance or the owner can retry its setup"),
+        });
+    }
+    let setup_config = checkpoint.setup_config;
+    let dot_lodestone_config: DotLodestoneConfig = serde_json::from_str(
+        &tokio::fs::read_to_string(setup_path.join(".lodestone_config"))
+            .await
+            .context("Failed to read .lodestone_config file")?,
+    )
+    .context("Failed to parse .lodestone_config file")?;
+
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    let (progression_start_event, event_id) =
+        minecraft_setup_progression_start(&uuid, &setup_config, caused_by);
+    if !state
+        .pending_instances
+        .retry(&uuid, event_id.snowflake())
+        .await
+    {
+        return Err(Error {
+            kind: ErrorKind::InvalidInstanceState,
+            source: eyre!("The setup of this instance is already being retried"),
+        });
+    }
+    spawn_minecraft_setup(
+        state,
+        requester,
+        uuid,
+        setup_config,
+        dot_lodestone_config,
+        setup_path,
+        progression_start_event,
+        event_id,
+    );
+    Ok(Json(()))
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct GenericSetupConfig {
     url: String,
     setup_value: SetupValue,
+    /// The capabilities of the bundle the user approved, see `/generic_setup_permissions`
+    #[serde(default)]
+    approved_permissions: BundlePermissions,
 }
 
 pub async fn create_generic_instance(
@@ -220,6 +488,9 @@ pub async fn create_generic_instance(
 
     let instance_uuid = instance_uuid;
 
+    let permissions = BundlePermissions::fetch(&setup_config.url).await?;
+    permissions.check_approved(&setup_config.approved_permissions)?;
+
     let setup_path = path_to_instances().join(format!(
         "{}-{}",
         setup_config.setup_value.name,
@@ -246,16 +517,26 @@ pub async fn create_generic_instance(
         setup_path,
         dot_lodestone_config,
         setup_config.setup_value,
+        permissions,
         state.event_broadcaster.clone(),
         state.macro_executor.clone(),
     )
     .await?;
 
     state
-        .instances
-        .lock()
-        .await
-        .insert(instance_uuid.clone(), instance.into());
+        .insert_instance(instance_uuid.clone(), instance.into())
+        .await;
+    log_audit_entry(
+        &state.sqlite_pool,
+        AuditAction::InstanceCreated,
+        &CausedBy::User {
+            user_id: requester.uid.clone(),
+            user_name: requester.username.clone(),
+        },
+        Some(instance_uuid.to_string()),
+        "Created generic instance",
+    )
+    .await;
     Ok(Json(()))
 }
 
@@ -279,11 +560,12 @@ pub async fn delete_instance(
                 source: eyre!("Instance must be stopped before deletion"),
             })
         } else {
+            let instance_name = instance.name().await;
             let (progression_event_start, event_id) = Event::new_progression_event_start(
-                format!("Deleting instance {}", instance.name().await),
+                format!("Deleting instance {}", instance_name),
                 Some(10.0),
                 None,
-                caused_by,
+                caused_by.clone(),
             );
             let event_broadcaster = state.event_broadcaster.clone();
             event_broadcaster.send(progression_event_start);
@@ -306,23 +588,34 @@ pub async fn delete_instance(
                 .port_manager
                 .lock()
                 .await
-                .deallocate(instance.port().await);
+                .deallocate_ports(&instance.ports().await);
             let instance_path = instance.path().await;
             // if instance is generic
             if let GameInstance::GenericInstance(i) = instance {
                 i.destruct().await;
             };
             drop(instances);
-            let res = crate::util::fs::remove_dir_all(instance_path).await;
+            let res =
+                crate::util::fs::remove_dir_all_within(instance_path, path_to_instances()).await;
             match &res {
-                Ok(_) => event_broadcaster.send(Event::new_progression_event_end(
-                    event_id,
-                    true,
-                    Some("Instance deleted successfully"),
-                    Some(ProgressionEndValue::InstanceDelete {
-                        instance_uuid: uuid.clone(),
-                    }),
-                )),
+                Ok(_) => {
+                    event_broadcaster.send(Event::new_progression_event_end(
+                        event_id,
+                        true,
+                        Some("Instance deleted successfully"),
+                        Some(ProgressionEndValue::InstanceDelete {
+                            instance_uuid: uuid.clone(),
+                        }),
+                    ));
+                    log_audit_entry(
+                        &state.sqlite_pool,
+                        AuditAction::InstanceDeleted,
+                        &caused_by,
+                        Some(uuid.to_string()),
+                        format!("Deleted instance {}", instance_name),
+                    )
+                    .await;
+                }
                 Err(e) => {
                     event_broadcaster.send(Event::new_progression_event_end(
                         event_id,
@@ -336,6 +629,29 @@ pub async fn delete_instance(
             }
             res.map(|_| Json(()))
         }
+    } else if let Some(info) = state.pending_instances.remove_failed(&uuid).await {
+        drop(instances);
+        // a failed setup has no instance yet, only its directory and ports
+        let setup_path = PathBuf::from(&info.path);
+        if let Ok(Some(checkpoint)) = SetupCheckpoint::load(&setup_path).await {
+            state
+                .port_manager
+                .lock()
+                .await
+                .deallocate_ports(&checkpoint.ports());
+        }
+        crate::util::fs::remove_dir_all_within(setup_path, path_to_instances())
+            .await
+            .context("Failed to remove the directory of the failed setup")?;
+        log_audit_entry(
+            &state.sqlite_pool,
+            AuditAction::InstanceDeleted,
+            &caused_by,
+            Some(uuid.to_string()),
+            format!("Deleted failed setup of instance {}", info.name),
+        )
+        .await;
+        Ok(Json(()))
     } else {
         Err(Error {
             kind: ErrorKind::NotFound,
@@ -344,15 +660,79 @@ pub async fn delete_instance(
     }
 }
 
+/// Removes an instance whose directory was deleted from under the core, without touching its files
+pub async fn forget_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::DeleteInstance)?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    let mut instances = state.instances.lock().await;
+    let instance = instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    if instance.state().await != State::Missing {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Only an instance whose directory is gone can be forgotten, delete it instead"),
+        });
+    }
+    let instance = instances.remove(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    drop(instances);
+    let instance_name = instance.name().await;
+    state
+        .port_manager
+        .lock()
+        .await
+        .deallocate_ports(&instance.ports().await);
+    // lets the clients drop the instance, as they would after deleting it
+    let (progression_event_start, event_id) = Event::new_progression_event_start(
+        format!("Forgetting instance {}", instance_name),
+        None,
+        None,
+        caused_by.clone(),
+    );
+    state.event_broadcaster.send(progression_event_start);
+    state.event_broadcaster.send(Event::new_progression_event_end(
+        event_id,
+        true,
+        Some("Instance forgotten"),
+        Some(ProgressionEndValue::InstanceDelete {
+            instance_uuid: uuid.clone(),
+        }),
+    ));
+    log_audit_entry(
+        &state.sqlite_pool,
+        AuditAction::InstanceDeleted,
+        &caused_by,
+        Some(uuid.to_string()),
+        format!("Forgot instance {} whose directory was deleted", instance_name),
+    )
+    .await;
+    Ok(Json(()))
+}
+
 pub fn get_instance_routes(state: AppState) -> Router {
     Router::new()
         .route("/instance/list", get(get_instance_list))
+        .route("/instances/tags", get(get_instance_tags_in_use))
         .route(
             "/instance/create/:game_type",
             post(create_minecraft_instance),
         )
         .route("/instance/create_generic", post(create_generic_instance))
         .route("/instance/:uuid", delete(delete_instance))
+        .route("/instance/:uuid/forget", post(forget_instance))
         .route("/instance/:uuid/info", get(get_instance_info))
+        .route("/instance/:uuid/setup/retry", post(retry_instance_setup))
         .with_state(state)
 }
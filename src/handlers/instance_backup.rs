@@ -0,0 +1,290 @@
+use axum::{
+    extract::Path,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::user::UserAction,
+    backup_history::BackupHistoryEntry,
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    s3::S3Object,
+    traits::t_backup::{BackupInfo, BackupVerifyReport, TBackup},
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+/// Queues a backup, returning the id of the progression event to follow it with
+pub async fn backup_instance_now(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Snowflake>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::BackupInstance(uuid.clone()))?;
+    // queuing the backup is quick, so it's done through the map entry
+    if let Some(instance) = state.instances.lock().await.get(&uuid) {
+        return Ok(Json(
+            instance
+                .backup_now(CausedBy::User {
+                    user_id: requester.uid,
+                    user_name: requester.username,
+                })
+                .await?,
+        ));
+    }
+    if !state
+        .pending_instances
+        .list(|pending_uuid, _| pending_uuid == &uuid)
+        .await
+        .is_empty()
+    {
+        return Err(Error {
+            kind: ErrorKind::InvalidInstanceState,
+            source: eyre!("The instance can't be backed up until its setup is done"),
+        });
+    }
+    Err(Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })
+}
+
+pub async fn cancel_instance_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .cancel_backup()
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn pause_instance_backups(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .pause_backups()
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn resume_instance_backups(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .resume_backups()
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_instance_backup_history(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<BackupHistoryEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .lock()
+            .await
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .backup_history()
+            .await?,
+    ))
+}
+
+pub async fn list_instance_backups(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<BackupInfo>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    // every backup is measured, so the instance is cloned rather than holding the lock
+    let instance = state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .clone();
+    Ok(Json(instance.list_backups().await?))
+}
+
+pub async fn list_instance_remote_backups(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<S3Object>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ManageOffsiteBackup(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .lock()
+            .await
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .list_remote_backups()
+            .await?,
+    ))
+}
+
+pub async fn fetch_instance_remote_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ManageOffsiteBackup(uuid.clone()))?;
+    // the download can take a while, so the lock isn't held for it. Clones share the instance
+    let instance = state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .clone();
+    instance.fetch_remote_backup(&name).await?;
+    Ok(Json(()))
+}
+
+pub async fn verify_instance_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<BackupVerifyReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    // every file of the backup is read, so the instance is cloned rather than holding the lock
+    let instance = state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .clone();
+    Ok(Json(instance.verify_backup(&name).await?))
+}
+
+/// Replaces the world with a backup, downloading it first if it's only stored in S3. Only while the
+/// server is stopped. The current world is kept next to it
+pub async fn restore_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, backup_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    // the whole backup is copied, so the instance is cloned rather than holding the lock
+    let instance = state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .clone();
+    instance
+        .restore_backup(
+            &backup_name,
+            CausedBy::User {
+                user_id: requester.uid,
+                user_name: requester.username,
+            },
+        )
+        .await?;
+    Ok(Json(()))
+}
+
+pub fn get_instance_backup_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/backup/now", post(backup_instance_now))
+        .route(
+            "/instance/:uuid/backup/current",
+            delete(cancel_instance_backup),
+        )
+        .route("/instance/:uuid/backup/pause", post(pause_instance_backups))
+        .route(
+            "/instance/:uuid/backup/resume",
+            post(resume_instance_backups),
+        )
+        .route(
+            "/instance/:uuid/backup/history",
+            get(get_instance_backup_history),
+        )
+        .route("/instance/:uuid/backup/list", get(list_instance_backups))
+        .route(
+            "/instance/:uuid/backup/remote",
+            get(list_instance_remote_backups),
+        )
+        .route(
+            "/instance/:uuid/backup/remote/:name/fetch",
+            post(fetch_instance_remote_backup),
+        )
+        .route(
+            "/instance/:uuid/backup/:name/verify",
+            get(verify_instance_backup),
+        )
+        .route("/instance/:uuid/backup/:name/restore", post(restore_backup))
+        .with_state(state)
+}
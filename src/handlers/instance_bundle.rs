@@ -0,0 +1,249 @@
+use axum::{
+    extract::{DefaultBodyLimit, Multipart, Path},
+    routing::post,
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+use tokio::io::AsyncWriteExt;
+use tracing::error;
+
+use crate::{
+    auth::user::{User, UserAction},
+    db::audit::{log_audit_entry, AuditAction},
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event, ProgressionEndValue},
+    implementations::minecraft::{
+        bundle::{read_bundle_manifest, MAX_BUNDLE_BYTES},
+        MinecraftInstance,
+    },
+    prelude::{path_to_instances, path_to_tmp},
+    traits::{t_configurable::TConfigurable, TInstance},
+    types::InstanceUuid,
+    util::{rand_alphanumeric, resolve_path_conflict},
+    AppState,
+};
+
+/// Bundles the instance for moving it to another core, returning the key to download the bundle
+/// with
+pub async fn export_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<String, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    // bundled without holding the lock, clones share the instance
+    let instance = state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .clone();
+    let name = instance.name().await;
+    crate::util::fs::create_dir_all(path_to_tmp()).await?;
+    let dest = resolve_path_conflict(
+        path_to_tmp().join(format!(
+            "{}.lodestone.zip",
+            sanitize_filename::sanitize(&name)
+        )),
+        None,
+    );
+    instance.export_bundle(&dest).await?;
+
+    let key = rand_alphanumeric(32);
+    state.download_urls.lock().await.insert(key.clone(), dest);
+    Ok(key)
+}
+
+/// A bundle unpacks jars and scripts into the new instance, so importing one takes what writing
+/// those through the file manager does on top of creating an instance
+fn check_can_import_bundle(requester: &User) -> Result<(), Error> {
+    requester.try_action(&UserAction::CreateInstance)?;
+    requester.try_action(&UserAction::WriteGlobalFile)
+}
+
+/// Imports an uploaded bundle as a new instance, on ports free on this core and with the JRE its
+/// version needs
+pub async fn import_instance_bundle(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    mut multipart: Multipart,
+) -> Result<Json<InstanceUuid>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    check_can_import_bundle(&requester)?;
+
+    crate::util::fs::create_dir_all(path_to_tmp()).await?;
+    let temp_dir =
+        tempfile::tempdir_in(path_to_tmp()).context("Failed to create temporary directory")?;
+    let path_to_bundle = temp_dir.path().join("bundle.zip");
+    let mut field = multipart
+        .next_field()
+        .await
+        .context("Failed to read the uploaded bundle")?
+        .ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("No bundle was uploaded"),
+        })?;
+    let mut file = crate::util::fs::create(&path_to_bundle).await?;
+    let mut uploaded_bytes = 0_u64;
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .context("Failed to read the uploaded bundle")?
+    {
+        uploaded_bytes += chunk.len() as u64;
+        if uploaded_bytes > MAX_BUNDLE_BYTES {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "The bundle is larger than the {} bytes allowed",
+                    MAX_BUNDLE_BYTES
+                ),
+            });
+        }
+        file.write_all(&chunk)
+            .await
+            .context("Failed to write the uploaded bundle")?;
+    }
+    file.flush()
+        .await
+        .context("Failed to write the uploaded bundle")?;
+    drop(file);
+
+    let manifest = read_bundle_manifest(&path_to_bundle).await?;
+    let uuid = InstanceUuid::default();
+    let path_to_instance = path_to_instances().join(format!(
+        "{}-{}",
+        sanitize_filename::sanitize(&manifest.name),
+        &uuid.no_prefix()[0..8]
+    ));
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    let (progression_start_event, event_id) = Event::new_progression_event_start(
+        format!("Importing instance {}", manifest.name),
+        None,
+        None,
+        caused_by.clone(),
+    );
+    state.event_broadcaster.send(progression_start_event);
+    let instance = match MinecraftInstance::import_bundle(
+        &path_to_bundle,
+        path_to_instance,
+        uuid.clone(),
+        &state.port_manager,
+        state.event_broadcaster.clone(),
+        state.macro_executor.clone(),
+    )
+    .await
+    {
+        Ok(instance) => instance,
+        Err(e) => {
+            state
+                .event_broadcaster
+                .send(Event::new_progression_event_end(
+                    event_id,
+                    false,
+                    Some(&format!("Import failed: {}", e.source)),
+                    None,
+                ));
+            return Err(e);
+        }
+    };
+    state
+        .event_broadcaster
+        .send(Event::new_progression_event_end(
+            event_id,
+            true,
+            Some("Instance imported successfully"),
+            Some(ProgressionEndValue::InstanceCreation(
+                instance.get_instance_info().await,
+            )),
+        ));
+    log_audit_entry(
+        &state.sqlite_pool,
+        AuditAction::InstanceCreated,
+        &caused_by,
+        Some(uuid.to_string()),
+        format!(
+            "Imported Minecraft instance {} ({} {})",
+            manifest.name,
+            manifest.flavour.to_string(),
+            manifest.version
+        ),
+    )
+    .await;
+
+    let mut perm = requester.permissions;
+    perm.can_start_instance.insert(uuid.clone());
+    perm.can_stop_instance.insert(uuid.clone());
+    perm.can_view_instance.insert(uuid.clone());
+    perm.can_view_instance_console.insert(uuid.clone());
+    perm.can_read_instance_file.insert(uuid.clone());
+    perm.can_write_instance_file.insert(uuid.clone());
+    perm.can_manage_instance_players.insert(uuid.clone());
+    perm.can_backup_instance.insert(uuid.clone());
+    perm.can_manage_offsite_backup.insert(uuid.clone());
+    // the instance is imported either way
+    if let Err(e) = state
+        .users_manager
+        .write()
+        .await
+        .update_permissions(&requester.uid, perm, CausedBy::System)
+        .await
+    {
+        error!("Failed to update permissions: {:?}", e);
+    }
+    state.insert_instance(uuid.clone(), instance.into()).await;
+    Ok(Json(uuid))
+}
+
+pub fn get_instance_bundle_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/export", post(export_instance))
+        .route("/instance/import/bundle", post(import_instance_bundle))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::permission::UserPermission;
+
+    #[test]
+    fn test_import_bundle_permission() {
+        let mut permissions = UserPermission::default();
+        permissions.can_create_instance = true;
+        let creator = User::new("creator".to_string(), "12345", false, false, permissions);
+        let e = check_can_import_bundle(&creator).unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::PermissionDenied));
+
+        let mut permissions = UserPermission::default();
+        permissions.can_create_instance = true;
+        permissions.can_write_global_file = true;
+        let trusted = User::new("trusted".to_string(), "12345", false, false, permissions);
+        assert!(check_can_import_bundle(&trusted).is_ok());
+
+        // writing files alone doesn't let an instance be created
+        let mut permissions = UserPermission::default();
+        permissions.can_write_global_file = true;
+        let writer = User::new("writer".to_string(), "12345", false, false, permissions);
+        assert!(check_can_import_bundle(&writer).is_err());
+
+        let owner = User::new(
+            "owner".to_string(),
+            "12345",
+            true,
+            false,
+            UserPermission::default(),
+        );
+        assert!(check_can_import_bundle(&owner).is_ok());
+    }
+}
@@ -1,17 +1,34 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
 use axum::{
-    extract::Path,
-    routing::{get, put},
+    extract::{Path, Query},
+    routing::{get, post, put},
     Json, Router,
 };
 use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
+use indexmap::IndexMap;
+use serde::Deserialize;
 
+use super::instance_server::DowngradeOptions;
 use crate::{
-    auth::user::UserAction,
+    auth::{user::UserAction, user_id::UserId},
+    db::audit::{log_audit_entry, AuditAction},
+    disk_quota::{disk_usage_cache, mb_to_bytes, InstanceDiskUsage},
     error::{Error, ErrorKind},
+    events::CausedBy,
+    implementations::minecraft::hooks::LifecycleHooks,
+    implementations::minecraft::launch::LaunchMode,
+    implementations::minecraft::readiness::ReadinessProbe,
+    implementations::minecraft::util::split_properties,
+    port_manager::InstancePorts,
+    public_url::check_public_url,
+    s3::S3Config,
     traits::t_configurable::{
         manifest::{ConfigurableManifest, ConfigurableValue},
-        TConfigurable,
+        AutoUpdateConfig, AvailableWorld, GameRule, PropertyChange, TConfigurable, UpdateStatus,
+        WorldInfo,
     },
     types::InstanceUuid,
     AppState,
@@ -61,13 +78,306 @@ pub async fn set_instance_setting(
         source: eyre!("Instance not found"),
     })?;
 
+    let old_ports = instance.ports().await;
+    let new_ports = match value.try_as_unsigned_integer() {
+        Ok(port) => old_ports.with_property(&setting_id, &port.to_string()),
+        Err(_) => old_ports,
+    };
+    let mut port_manager = state.port_manager.lock().await;
+    port_manager.check_reallocation(&old_ports, &new_ports)?;
     instance
         .update_configurable(&section_id, &setting_id, value)
         .await?;
+    port_manager.reallocate_ports(&old_ports, &instance.ports().await);
+    drop(port_manager);
+    drop(instances);
+
+    // the value is left out as it may be a secret, such as the RCON password
+    log_audit_entry(
+        &state.sqlite_pool,
+        AuditAction::InstanceConfigChanged,
+        &CausedBy::User {
+            user_id: requester.uid,
+            user_name: requester.username,
+        },
+        Some(uuid.to_string()),
+        format!("Changed setting {section_id}/{setting_id}"),
+    )
+    .await;
+    Ok(Json(()))
+}
+
+pub async fn get_instance_world_info(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<WorldInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    let instances = state.instances.lock().await;
+    let instance = instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(instance.world_info().await?))
+}
+
+pub async fn get_instance_worlds(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<AvailableWorld>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    let instances = state.instances.lock().await;
+    let instance = instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(instance.list_worlds().await?))
+}
+
+pub async fn get_instance_ports(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstancePorts>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    let instances = state.instances.lock().await;
+    let instance = instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(instance.ports().await))
+}
+
+#[derive(Deserialize)]
+pub struct ActiveWorld {
+    pub level_name: String,
+}
 
+pub async fn set_instance_active_world(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(active_world): Json<ActiveWorld>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_active_world(active_world.level_name)
+        .await?;
     Ok(Json(()))
 }
 
+pub async fn get_instance_game_rules(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<GameRule>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .lock()
+            .await
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .game_rules()
+            .await?,
+    ))
+}
+
+pub async fn set_instance_game_rule(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, rule)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(value): Json<String>,
+) -> Result<Json<GameRule>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    let game_rule = state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_game_rule(rule, value)
+        .await?;
+    log_audit_entry(
+        &state.sqlite_pool,
+        AuditAction::InstanceConfigChanged,
+        &CausedBy::User {
+            user_id: requester.uid,
+            user_name: requester.username,
+        },
+        Some(uuid.to_string()),
+        format!("Set gamerule {} to {}", game_rule.name, game_rule.value),
+    )
+    .await;
+    Ok(Json(game_rule))
+}
+
+pub async fn get_instance_raw_properties(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<String>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    let instances = state.instances.lock().await;
+    let instance = instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(instance.raw_properties().await?))
+}
+
+pub async fn set_instance_raw_properties(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(properties): Json<String>,
+) -> Result<Json<Vec<PropertyChange>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    let mut instances = state.instances.lock().await;
+    let instance = instances.get_mut(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let old_ports = instance.ports().await;
+    let new_properties = split_properties(&properties);
+    let new_ports = old_ports.with_properties(
+        new_properties
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str())),
+    );
+    let mut port_manager = state.port_manager.lock().await;
+    port_manager.check_reallocation(&old_ports, &new_ports)?;
+    let changes = instance.set_raw_properties(properties).await?;
+    port_manager.reallocate_ports(&old_ports, &instance.ports().await);
+    drop(port_manager);
+    drop(instances);
+
+    log_property_changes(&state, &uuid, requester.uid, requester.username, &changes).await;
+    Ok(Json(changes))
+}
+
+pub async fn set_instance_properties(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(properties): Json<IndexMap<String, String>>,
+) -> Result<Json<Vec<PropertyChange>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    let mut instances = state.instances.lock().await;
+    let instance = instances.get_mut(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let old_ports = instance.ports().await;
+    let new_ports = old_ports.with_properties(
+        properties
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str())),
+    );
+    let mut port_manager = state.port_manager.lock().await;
+    port_manager.check_reallocation(&old_ports, &new_ports)?;
+    let changes = instance.set_properties(properties).await?;
+    port_manager.reallocate_ports(&old_ports, &instance.ports().await);
+    drop(port_manager);
+    drop(instances);
+
+    log_property_changes(&state, &uuid, requester.uid, requester.username, &changes).await;
+    Ok(Json(changes))
+}
+
+/// Picks up edits made to the config and properties of the instance on disk, e.g. through the
+/// file manager, without restarting it
+pub async fn reload_instance_config(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<PropertyChange>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    // the files are read without holding the lock, clones share the instance
+    let mut instance = state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .clone();
+    // the properties on disk are what the reload would pick up
+    let old_ports = instance.ports().await;
+    let new_ports = match instance.raw_properties().await {
+        Ok(properties) => old_ports.with_properties(
+            split_properties(&properties)
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str())),
+        ),
+        Err(_) => old_ports,
+    };
+    let mut port_manager = state.port_manager.lock().await;
+    port_manager.check_reallocation(&old_ports, &new_ports)?;
+    let changes = instance.reload_config().await?;
+    port_manager.reallocate_ports(&old_ports, &instance.ports().await);
+    drop(port_manager);
+
+    log_property_changes(&state, &uuid, requester.uid, requester.username, &changes).await;
+    Ok(Json(changes))
+}
+
+async fn log_property_changes(
+    state: &AppState,
+    uuid: &InstanceUuid,
+    user_id: UserId,
+    user_name: String,
+    changes: &[PropertyChange],
+) {
+    if changes.is_empty() {
+        return;
+    }
+    // values are left out as they may be secrets, such as the RCON password
+    log_audit_entry(
+        &state.sqlite_pool,
+        AuditAction::InstanceConfigChanged,
+        &CausedBy::User { user_id, user_name },
+        Some(uuid.to_string()),
+        format!(
+            "Changed properties {}",
+            changes
+                .iter()
+                .map(|change| change.key.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    )
+    .await;
+}
+
 pub async fn set_instance_name(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
@@ -85,8 +395,19 @@ pub async fn set_instance_name(
             kind: ErrorKind::NotFound,
             source: eyre!("Instance not found"),
         })?
-        .set_name(new_name)
+        .set_name(new_name.clone())
         .await?;
+    log_audit_entry(
+        &state.sqlite_pool,
+        AuditAction::InstanceConfigChanged,
+        &CausedBy::User {
+            user_id: requester.uid,
+            user_name: requester.username,
+        },
+        Some(uuid.to_string()),
+        format!("Renamed instance to {new_name}"),
+    )
+    .await;
     Ok(Json(()))
 }
 
@@ -112,10 +433,11 @@ pub async fn set_instance_description(
     Ok(Json(()))
 }
 
-pub async fn change_version(
+pub async fn set_instance_min_ram(
     axum::extract::State(state): axum::extract::State<AppState>,
-    Path((uuid, new_version)): Path<(InstanceUuid, String)>,
+    Path(uuid): Path<InstanceUuid>,
     AuthBearer(token): AuthBearer,
+    Json(min_ram): Json<u32>,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
@@ -128,24 +450,944 @@ pub async fn change_version(
             kind: ErrorKind::NotFound,
             source: eyre!("Instance not found"),
         })?
-        .change_version(new_version)
+        .set_min_ram(min_ram)
         .await?;
     Ok(Json(()))
 }
 
-pub fn get_instance_config_routes(state: AppState) -> Router {
-    Router::new()
-        .route(
-            "/instance/:uuid/configurable_manifest",
-            get(get_instance_configurable_manifest),
-        )
-        .route("/instance/:uuid/version/:new_version", put(change_version))
-        .route("/instance/:uuid/settings", get(get_instance_settings))
-        .route(
-            "/instance/:uuid/settings/:section_id/:setting_id",
-            put(set_instance_setting),
-        )
-        .route("/instance/:uuid/name", put(set_instance_name))
-        .route("/instance/:uuid/description", put(set_instance_description))
+pub async fn set_instance_max_ram(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(max_ram): Json<u32>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_max_ram(max_ram)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn set_instance_backup_period(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(backup_period): Json<Option<u32>>,
+) -> Result<Json<Option<u32>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_backup_period(backup_period)
+        .await?;
+    Ok(Json(backup_period))
+}
+
+pub async fn set_instance_max_backups(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(max_backups): Json<Option<u32>>,
+) -> Result<Json<Option<u32>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_max_backups(max_backups)
+        .await?;
+    Ok(Json(max_backups))
+}
+
+pub async fn set_instance_compress_backups(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(enabled): Json<bool>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_compress_backups(enabled)
+        .await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize)]
+pub struct BackupOnStop {
+    pub enabled: bool,
+    /// Seconds since the last backup within which no backup is taken on stop
+    #[serde(default)]
+    pub debounce_secs: Option<u32>,
+}
+
+pub async fn set_instance_backup_on_stop(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(backup_on_stop): Json<BackupOnStop>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_backup_on_stop(backup_on_stop.enabled, backup_on_stop.debounce_secs)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn set_instance_backup_before_risky_ops(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(enabled): Json<bool>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_backup_before_risky_ops(enabled)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn set_instance_process_priority(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(priority): Json<Option<i32>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_process_priority(priority)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn set_instance_cpu_affinity(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(cpu_affinity): Json<Option<u64>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_cpu_affinity(cpu_affinity)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_instance_save_before_stop(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<bool>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .lock()
+            .await
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .save_before_stop()
+            .await,
+    ))
+}
+
+pub async fn set_instance_save_before_stop(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(enabled): Json<bool>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_save_before_stop(enabled)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn set_instance_stop_timeout(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(timeout_secs): Json<Option<u32>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_stop_timeout(timeout_secs)
+        .await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize)]
+pub struct Readiness {
+    pub probe: ReadinessProbe,
+    /// Seconds the server has to become ready before it's killed, no limit if not set
+    #[serde(default)]
+    pub timeout_secs: Option<u32>,
+}
+
+pub async fn set_instance_readiness_probe(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(readiness): Json<Readiness>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_readiness_probe(readiness.probe, readiness.timeout_secs)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn set_instance_oom_max_ram_ceiling(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(ceiling): Json<Option<u32>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_oom_max_ram_ceiling(ceiling)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn set_instance_backup_io_limit(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(bytes_per_sec): Json<Option<u64>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_backup_io_limit(bytes_per_sec)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn set_instance_backup_destination(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(destination): Json<Option<PathBuf>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    // backups to a destination outside the instance write to the host's file system
+    if destination.is_some() {
+        requester.try_action(&UserAction::WriteGlobalFile)?;
+    }
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_backup_destination(destination)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn set_instance_s3_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(s3_backup): Json<Option<S3Config>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ManageOffsiteBackup(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_s3_backup(s3_backup)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn set_instance_console_buffer_lines(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(lines): Json<Option<usize>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    let mut instances = state.instances.lock().await;
+    let instance = instances.get_mut(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    instance.set_console_buffer_lines(lines).await?;
+    let capacity = instance.console_buffer_lines().await;
+    if let Some(buffer) = state.console_out_buffer.lock().await.get_mut(&uuid) {
+        buffer.set_capacity(capacity);
+    }
+    Ok(Json(()))
+}
+
+pub async fn set_instance_persist_console(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(persist_console): Json<bool>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_persist_console(persist_console)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_instance_env(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<HashMap<String, String>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .lock()
+            .await
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .env()
+            .await,
+    ))
+}
+
+pub async fn set_instance_env(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(env): Json<HashMap<String, String>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    let mut keys: Vec<String> = env.keys().cloned().collect();
+    keys.sort();
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_env(env)
+        .await?;
+    log_audit_entry(
+        &state.sqlite_pool,
+        AuditAction::InstanceConfigChanged,
+        &CausedBy::User {
+            user_id: requester.uid,
+            user_name: requester.username,
+        },
+        Some(uuid.to_string()),
+        format!("Changed environment variables to [{}]", keys.join(", ")),
+    )
+    .await;
+    Ok(Json(()))
+}
+
+pub async fn get_instance_tags(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<String>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .lock()
+            .await
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .tags()
+            .await,
+    ))
+}
+
+pub async fn set_instance_tags(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(tags): Json<Vec<String>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_tags(tags)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_instance_auto_update(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<AutoUpdateConfig>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .lock()
+            .await
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .auto_update()
+            .await,
+    ))
+}
+
+pub async fn set_instance_auto_update(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(auto_update): Json<AutoUpdateConfig>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_auto_update(auto_update)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_instance_hooks(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<LifecycleHooks>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .lock()
+            .await
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .lifecycle_hooks()
+            .await,
+    ))
+}
+
+pub async fn set_instance_hooks(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(hooks): Json<LifecycleHooks>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_lifecycle_hooks(hooks)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_instance_launch_mode(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<LaunchMode>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .lock()
+            .await
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .launch_mode()
+            .await,
+    ))
+}
+
+pub async fn set_instance_launch_mode(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(launch_mode): Json<LaunchMode>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_launch_mode(launch_mode)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_instance_crash_report_upload(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Option<String>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .lock()
+            .await
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .crash_report_upload_url()
+            .await,
+    ))
+}
+
+/// Sets the paste API crash reports of the instance are uploaded to, `null` to stop uploading them
+pub async fn set_instance_crash_report_upload(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(url): Json<Option<String>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    // resolved before taking the lock, it's checked again before every upload
+    if let Some(url) = &url {
+        check_public_url(url).await?;
+    }
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_crash_report_upload_url(url)
+        .await?;
+    Ok(Json(()))
+}
+
+/// The current usage of the instance directory against its quota, measured if the cached usage is
+/// stale
+pub async fn get_instance_disk_usage(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstanceDiskUsage>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    let instances = state.instances.lock().await;
+    let instance = instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let path = instance.path().await;
+    let quota_bytes = instance.disk_quota_mb().await.map(mb_to_bytes);
+    drop(instances);
+    Ok(Json(InstanceDiskUsage {
+        used_bytes: disk_usage_cache().usage(&path).await?,
+        quota_bytes,
+    }))
+}
+
+/// Only users who can write anywhere on the host may bound the disk an instance uses
+pub async fn set_instance_disk_quota(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(quota_mb): Json<Option<u64>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    requester.try_action(&UserAction::WriteGlobalFile)?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_disk_quota_mb(quota_mb)
+        .await?;
+    log_audit_entry(
+        &state.sqlite_pool,
+        AuditAction::InstanceConfigChanged,
+        &CausedBy::User {
+            user_id: requester.uid,
+            user_name: requester.username,
+        },
+        Some(uuid.to_string()),
+        match quota_mb {
+            Some(quota_mb) => format!("Set disk quota to {quota_mb} MB"),
+            None => "Removed disk quota".to_string(),
+        },
+    )
+    .await;
+    Ok(Json(()))
+}
+
+pub async fn get_instance_updates(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<UpdateStatus>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .lock()
+            .await
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .check_for_updates()
+            .await?,
+    ))
+}
+
+pub async fn change_version(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, new_version)): Path<(InstanceUuid, String)>,
+    Query(options): Query<DowngradeOptions>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .change_version(new_version.clone(), options.allow_downgrade)
+        .await?;
+    log_audit_entry(
+        &state.sqlite_pool,
+        AuditAction::InstanceConfigChanged,
+        &CausedBy::User {
+            user_id: requester.uid,
+            user_name: requester.username,
+        },
+        Some(uuid.to_string()),
+        format!("Changed version to {new_version}"),
+    )
+    .await;
+    Ok(Json(()))
+}
+
+pub fn get_instance_config_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/configurable_manifest",
+            get(get_instance_configurable_manifest),
+        )
+        .route("/instance/:uuid/version/:new_version", put(change_version))
+        .route("/instance/:uuid/updates", get(get_instance_updates))
+        .route("/instance/:uuid/settings", get(get_instance_settings))
+        .route(
+            "/instance/:uuid/settings/:section_id/:setting_id",
+            put(set_instance_setting),
+        )
+        .route("/instance/:uuid/world/info", get(get_instance_world_info))
+        .route("/instance/:uuid/worlds", get(get_instance_worlds))
+        .route("/instance/:uuid/ports", get(get_instance_ports))
+        .route(
+            "/instance/:uuid/world/active",
+            put(set_instance_active_world),
+        )
+        .route("/instance/:uuid/gamerules", get(get_instance_game_rules))
+        .route(
+            "/instance/:uuid/gamerules/:rule",
+            put(set_instance_game_rule),
+        )
+        .route("/instance/:uuid/properties", put(set_instance_properties))
+        .route("/instance/:uuid/reload", post(reload_instance_config))
+        .route(
+            "/instance/:uuid/properties/raw",
+            get(get_instance_raw_properties).put(set_instance_raw_properties),
+        )
+        .route("/instance/:uuid/name", put(set_instance_name))
+        .route("/instance/:uuid/description", put(set_instance_description))
+        .route("/instance/:uuid/min_ram", put(set_instance_min_ram))
+        .route("/instance/:uuid/max_ram", put(set_instance_max_ram))
+        .route(
+            "/instance/:uuid/oom_max_ram_ceiling",
+            put(set_instance_oom_max_ram_ceiling),
+        )
+        .route(
+            "/instance/:uuid/backup/period",
+            put(set_instance_backup_period),
+        )
+        .route("/instance/:uuid/backup/max", put(set_instance_max_backups))
+        .route(
+            "/instance/:uuid/backup/compress",
+            put(set_instance_compress_backups),
+        )
+        .route(
+            "/instance/:uuid/backup/on_stop",
+            put(set_instance_backup_on_stop),
+        )
+        .route(
+            "/instance/:uuid/backup/before_risky_ops",
+            put(set_instance_backup_before_risky_ops),
+        )
+        .route(
+            "/instance/:uuid/readiness",
+            put(set_instance_readiness_probe),
+        )
+        .route(
+            "/instance/:uuid/process_priority",
+            put(set_instance_process_priority),
+        )
+        .route(
+            "/instance/:uuid/cpu_affinity",
+            put(set_instance_cpu_affinity),
+        )
+        .route(
+            "/instance/:uuid/stop_timeout",
+            put(set_instance_stop_timeout),
+        )
+        .route(
+            "/instance/:uuid/save_before_stop",
+            get(get_instance_save_before_stop).put(set_instance_save_before_stop),
+        )
+        .route(
+            "/instance/:uuid/backup/io_limit",
+            put(set_instance_backup_io_limit),
+        )
+        .route(
+            "/instance/:uuid/backup/destination",
+            put(set_instance_backup_destination),
+        )
+        .route("/instance/:uuid/backup/s3", put(set_instance_s3_backup))
+        .route(
+            "/instance/:uuid/console/buffer_lines",
+            put(set_instance_console_buffer_lines),
+        )
+        .route(
+            "/instance/:uuid/console/persist",
+            put(set_instance_persist_console),
+        )
+        .route(
+            "/instance/:uuid/env",
+            get(get_instance_env).put(set_instance_env),
+        )
+        .route(
+            "/instance/:uuid/tags",
+            get(get_instance_tags).put(set_instance_tags),
+        )
+        .route(
+            "/instance/:uuid/auto_update",
+            get(get_instance_auto_update).put(set_instance_auto_update),
+        )
+        .route(
+            "/instance/:uuid/hooks",
+            get(get_instance_hooks).put(set_instance_hooks),
+        )
+        .route(
+            "/instance/:uuid/disk_quota",
+            get(get_instance_disk_usage).put(set_instance_disk_quota),
+        )
+        .route(
+            "/instance/:uuid/launch_mode",
+            get(get_instance_launch_mode).put(set_instance_launch_mode),
+        )
+        .route(
+            "/instance/:uuid/crash_report_upload",
+            get(get_instance_crash_report_upload).put(set_instance_crash_report_upload),
+        )
         .with_state(state)
 }
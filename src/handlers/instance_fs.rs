@@ -2,8 +2,8 @@ use std::path::PathBuf;
 
 use axum::{
     body::Bytes,
-    extract::{DefaultBodyLimit, Multipart, Path},
-    routing::{delete, get, put},
+    extract::{DefaultBodyLimit, Multipart, Path, Query},
+    routing::{delete, get, post, put},
     Json, Router,
 };
 use axum_auth::AuthBearer;
@@ -17,12 +17,14 @@ use tracing::error;
 use ts_rs::TS;
 use walkdir::WalkDir;
 
+use super::instance_server::DowngradeOptions;
 use crate::{
     auth::user::UserAction,
+    disk_quota::{disk_usage_cache, mb_to_bytes, measure_paths, measure_unpacked},
     error::{Error, ErrorKind},
     events::{new_fs_event, CausedBy, Event, FSOperation, FSTarget, ProgressionEndValue},
     prelude::path_to_tmp,
-    traits::t_configurable::TConfigurable,
+    traits::t_configurable::{TConfigurable, WorldInfo},
     types::InstanceUuid,
     util::{
         format_byte, format_byte_download, list_dir, rand_alphanumeric, resolve_path_conflict,
@@ -47,9 +49,19 @@ static PROTECTED_EXTENSIONS: [&str; 10] = [
 
 static PROTECTED_DIR_NAME: [&str; 1] = ["mods"];
 
+// the config of the instance and its setup checkpoint, which say how the server is launched
+static PROTECTED_FILE_NAME: [&str; 2] =
+    [".lodestone_minecraft_config.json", ".lodestone_setup.json"];
+
 fn is_path_protected(path: impl AsRef<std::path::Path>) -> bool {
     let path = path.as_ref();
-    if path.is_dir() {
+    if path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .map_or(false, |s| PROTECTED_FILE_NAME.contains(&s))
+    {
+        true
+    } else if path.is_dir() {
         path.file_name()
             .and_then(|s| s.to_str().map(|s| PROTECTED_DIR_NAME.contains(&s)))
             .unwrap_or(true)
@@ -151,8 +163,9 @@ async fn write_instance_file(
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
+    let quota = instance.disk_quota_mb().await.map(mb_to_bytes);
     drop(instances);
-    let path = scoped_join_win_safe(root, relative_path)?;
+    let path = scoped_join_win_safe(&root, relative_path)?;
     // if target has a protected extension, or no extension, deny
     if !requester.can_perform_action(&UserAction::WriteGlobalFile) && is_path_protected(&path) {
         return Err(Error {
@@ -160,12 +173,21 @@ async fn write_instance_file(
             source: eyre!("You don't have permission to write to this file"),
         });
     }
+    // the file is overwritten, so only what it grows by counts
+    let growth = (body.len() as u64).saturating_sub(
+        tokio::fs::metadata(&path)
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(0),
+    );
+    disk_usage_cache().ensure_fits(&root, quota, growth).await?;
     let mut file = tokio::fs::File::create(&path)
         .await
         .context("Failed to create file")?;
     file.write_all(&body)
         .await
         .context("Failed to write to file")?;
+    disk_usage_cache().add(&root, growth);
 
     let caused_by = CausedBy::User {
         user_id: requester.uid,
@@ -234,6 +256,7 @@ async fn copy_instance_files(
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
+    let quota = instance.disk_quota_mb().await.map(mb_to_bytes);
     drop(instances);
     // join each path to the root
     let paths_source = relative_paths_source
@@ -241,7 +264,7 @@ async fn copy_instance_files(
         .map(|p| scoped_join_win_safe(root.clone(), p))
         .collect::<Result<Vec<_>, _>>()?;
 
-    let path_dest = scoped_join_win_safe(root, &relative_path_dest)?;
+    let path_dest = scoped_join_win_safe(&root, &relative_path_dest)?;
 
     if !requester.can_perform_action(&UserAction::WriteGlobalFile) && is_path_protected(&path_dest)
     {
@@ -259,6 +282,11 @@ async fn copy_instance_files(
         });
     }
 
+    if quota.is_some() {
+        let copied = measure_paths(&paths_source).await?;
+        disk_usage_cache().ensure_fits(&root, quota, copied).await?;
+    }
+
     let event_broadcaster = state.event_broadcaster.clone();
 
     tokio::task::spawn_blocking(move || {
@@ -331,7 +359,9 @@ async fn copy_instance_files(
             Ok(())
         };
 
-        if let Err(e) = inner() {
+        let result = inner();
+        disk_usage_cache().invalidate(&root);
+        if let Err(e) = result {
             error!("Error copying file(s): {}", e);
             event_broadcaster.send(Event::new_progression_event_end(
                 progression_event_id.unwrap(),
@@ -629,14 +659,20 @@ async fn upload_instance_file(
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
+    let quota = instance.disk_quota_mb().await.map(mb_to_bytes);
     drop(instances);
     let path_to_dir = scoped_join_win_safe(&root, relative_path)?;
-    crate::util::fs::create_dir_all(&path_to_dir).await?;
 
     let total = headers
         .get(CONTENT_LENGTH)
         .and_then(|v| v.to_str().ok())
         .and_then(|v| v.parse::<f64>().ok());
+    // rejected up front if the request is known to be too large, and checked again as it's written
+    // in case the length is missing or wrong
+    disk_usage_cache()
+        .ensure_fits(&root, quota, total.unwrap_or(0.0) as u64)
+        .await?;
+    crate::util::fs::create_dir_all(&path_to_dir).await?;
     let (progression_start_event, event_id) =
         Event::new_progression_event_start("Uploading files", total, None, caused_by.clone());
     state.event_broadcaster.send(progression_start_event);
@@ -684,6 +720,26 @@ async fn upload_instance_file(
                     .map_err(Error::from);
             }
         } {
+            if let Err(e) = disk_usage_cache()
+                .ensure_fits(&root, quota, chunk.len() as u64)
+                .await
+            {
+                tokio::fs::remove_file(&path).await.ok();
+                disk_usage_cache().invalidate(&root);
+                state
+                    .event_broadcaster
+                    .send(Event::new_progression_event_end(
+                        event_id,
+                        false,
+                        Some(&e.source.to_string()),
+                        Some(ProgressionEndValue::FSOperationCompleted {
+                            instance_uuid: uuid.clone(),
+                            success: false,
+                            message: format!("Failed to upload file {name}, {}", e.source),
+                        }),
+                    ));
+                return Err(e);
+            }
             elapsed_bytes += chunk.len() as u64;
             let progression = (elapsed_bytes as f64 / threshold).floor() as u64;
             if progression > last_progression {
@@ -704,7 +760,7 @@ async fn upload_instance_file(
                     ));
             }
             match file.write_all(&chunk).await {
-                Ok(v) => v,
+                Ok(_) => disk_usage_cache().add(&root, chunk.len() as u64),
                 Err(e) => {
                     tokio::fs::remove_file(&path).await.ok();
                     state
@@ -747,6 +803,66 @@ async fn upload_instance_file(
     Ok(Json(()))
 }
 
+/// Replaces the world with an uploaded zip of one, only while the server is stopped
+async fn upload_instance_world(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    Query(options): Query<DowngradeOptions>,
+    AuthBearer(token): AuthBearer,
+    mut multipart: Multipart,
+) -> Result<Json<WorldInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    if !state.instances.lock().await.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+
+    let temp_dir =
+        tempfile::tempdir_in(path_to_tmp()).context("Failed to create temporary directory")?;
+    let path_to_archive = temp_dir.path().join("world.zip");
+    let mut field = multipart
+        .next_field()
+        .await
+        .context("Failed to read the uploaded world")?
+        .ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("No world was uploaded"),
+        })?;
+    let mut file = crate::util::fs::create(&path_to_archive).await?;
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .context("Failed to read the uploaded world")?
+    {
+        file.write_all(&chunk)
+            .await
+            .context("Failed to write the uploaded world")?;
+    }
+    file.flush()
+        .await
+        .context("Failed to write the uploaded world")?;
+    drop(file);
+
+    // the world is extracted and copied, so the lock isn't held for it. Clones share the instance
+    let mut instance = state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .clone();
+    let world_info = instance
+        .replace_world(&path_to_archive, options.allow_downgrade)
+        .await?;
+    Ok(Json(world_info))
+}
+
 pub async fn unzip_instance_file(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
@@ -762,8 +878,9 @@ pub async fn unzip_instance_file(
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
+    let quota = instance.disk_quota_mb().await.map(mb_to_bytes);
     drop(instances);
-    let path_to_zip_file = scoped_join_win_safe(root, &relative_path)?;
+    let path_to_zip_file = scoped_join_win_safe(&root, &relative_path)?;
 
     if let UnzipOption::ToDir(ref dir) = unzip_option {
         if !requester.can_perform_action(&UserAction::WriteGlobalFile) && is_path_protected(dir) {
@@ -773,6 +890,12 @@ pub async fn unzip_instance_file(
             });
         }
     }
+    if quota.is_some() {
+        let unpacked = measure_unpacked(&path_to_zip_file).await?;
+        disk_usage_cache()
+            .ensure_fits(&root, quota, unpacked)
+            .await?;
+    }
     let event_broadcaster = state.event_broadcaster.clone();
     tokio::spawn(async move {
         let (progression_event_start, event_id) = Event::new_progression_event_start(
@@ -787,7 +910,9 @@ pub async fn unzip_instance_file(
 
         event_broadcaster.send(progression_event_start);
 
-        if let Err(e) = unzip_file_async(path_to_zip_file, unzip_option).await {
+        let result = unzip_file_async(path_to_zip_file, unzip_option).await;
+        disk_usage_cache().invalidate(&root);
+        if let Err(e) = result {
             event_broadcaster.send(Event::new_progression_event_end(
                 event_id,
                 false,
@@ -836,6 +961,7 @@ async fn zip_instance_files(
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
+    let quota = instance.disk_quota_mb().await.map(mb_to_bytes);
     drop(instances);
     let ZipRequest {
         mut target_relative_paths,
@@ -857,6 +983,12 @@ async fn zip_instance_files(
         });
     }
 
+    // the archive is compressed, so it won't take up more than what goes into it
+    if quota.is_some() {
+        let zipped = measure_paths(&target_relative_paths).await?;
+        disk_usage_cache().ensure_fits(&root, quota, zipped).await?;
+    }
+
     let event_broadcaster = state.event_broadcaster.clone();
 
     tokio::spawn(async move {
@@ -883,7 +1015,9 @@ async fn zip_instance_files(
         );
         event_broadcaster.send(progression_start_event);
 
-        if let Err(e) = zip_files_async(&target_relative_paths, destination_relative_path).await {
+        let result = zip_files_async(&target_relative_paths, destination_relative_path).await;
+        disk_usage_cache().invalidate(&root);
+        if let Err(e) = result {
             event_broadcaster.send(Event::new_progression_event_end(
                 event_id,
                 false,
@@ -956,6 +1090,7 @@ pub fn get_instance_fs_routes(state: AppState) -> Router {
             "/instance/:uuid/fs/:base64_relative_path/upload",
             put(upload_instance_file),
         )
+        .route("/instance/:uuid/world", post(upload_instance_world))
         .layer(DefaultBodyLimit::disable())
         .route(
             "/instance/:uuid/fs/:base64_relative_path/unzip",
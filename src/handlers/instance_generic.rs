@@ -0,0 +1,113 @@
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+
+use crate::{
+    auth::user::UserAction,
+    db::audit::{log_audit_entry, AuditAction},
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    implementations::generic::{sandbox::BundlePermissions, GenericInstance, GenericSource},
+    prelude::GameInstance,
+    types::InstanceUuid,
+    AppState,
+};
+
+fn not_generic() -> Error {
+    Error {
+        kind: ErrorKind::UnsupportedOperation,
+        source: eyre!("Instance is not a generic instance"),
+    }
+}
+
+fn instance_not_found() -> Error {
+    Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    }
+}
+
+pub async fn get_generic_source(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<GenericSource>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    let (link_to_source, game_type) = match state.instances.lock().await.get(&uuid) {
+        Some(GameInstance::GenericInstance(instance)) => (
+            instance.link_to_source().await?,
+            *instance.dot_lodestone_config().game_type(),
+        ),
+        Some(_) => return Err(not_generic()),
+        None => return Err(instance_not_found()),
+    };
+    // fetched without holding the instance list, the bundle may take a while to answer
+    Ok(Json(
+        GenericInstance::fetch_source(link_to_source, game_type, state.macro_executor.clone())
+            .await?,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct ReloadGenericInstance {
+    /// Needed if the updated bundle requests more than what was approved before
+    pub approved_permissions: Option<BundlePermissions>,
+}
+
+pub async fn reload_generic_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    body: Option<Json<ReloadGenericInstance>>,
+) -> Result<Json<GenericSource>, Error> {
+    let approved_permissions = body.and_then(|Json(body)| body.approved_permissions);
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    // the updated bundle is downloaded, so the lock isn't held for it. Clones share the instance
+    let instance = match state.instances.lock().await.get(&uuid) {
+        Some(GameInstance::GenericInstance(instance)) => instance.clone(),
+        Some(_) => return Err(not_generic()),
+        None => return Err(instance_not_found()),
+    };
+    let (reloaded, source) = instance.reload(approved_permissions).await?;
+    match state.instances.lock().await.get_mut(&uuid) {
+        // the old bundle stops once the last clone of the old instance is dropped
+        Some(entry @ GameInstance::GenericInstance(_)) => {
+            *entry = GameInstance::GenericInstance(reloaded)
+        }
+        _ => {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance was deleted while its bundle was reloading"),
+            })
+        }
+    }
+    log_audit_entry(
+        &state.sqlite_pool,
+        AuditAction::InstanceConfigChanged,
+        &CausedBy::User {
+            user_id: requester.uid,
+            user_name: requester.username,
+        },
+        Some(uuid.to_string()),
+        format!("Reloaded bundle from {}", source.link_to_source),
+    )
+    .await;
+    Ok(Json(source))
+}
+
+pub fn get_instance_generic_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/generic/source", get(get_generic_source))
+        .route(
+            "/instance/:uuid/generic/reload",
+            post(reload_generic_instance),
+        )
+        .with_state(state)
+}
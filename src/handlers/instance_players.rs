@@ -1,19 +1,136 @@
 use std::collections::HashSet;
+use std::time::{Duration, Instant};
 
-use axum::{extract::Path, routing::get, Json, Router};
+use axum::{
+    extract::Path,
+    routing::{get, post, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use tokio::sync::Mutex;
 
 use crate::{
+    auth::user::UserAction,
+    db::audit::{log_audit_entry, AuditAction},
     error::{Error, ErrorKind},
-    traits::t_player::{Player, TPlayerManagement},
+    events::CausedBy,
+    prelude::GameInstance,
+    traits::{
+        t_configurable::TConfigurable,
+        t_player::{
+            GameMode, InstancePlayers, ModerationAction, OnlinePlayer, Player, PlayersOnline,
+            TPlayer, TPlayerManagement, TeleportDestination, Weather,
+        },
+        t_server::{State, TServer},
+    },
     types::InstanceUuid,
     AppState,
 };
 
+/// How long the players online across all instances are reused for, so that every dashboard refresh doesn't query every server
+const PLAYERS_ONLINE_CACHE_TTL: Duration = Duration::from_secs(5);
+/// How long an instance has to report its players before it's left out
+const INSTANCE_PLAYERS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Players online on every running instance, with when they were collected
+static PLAYERS_ONLINE_CACHE: Lazy<Mutex<Option<(Instant, Vec<InstancePlayers>)>>> =
+    Lazy::new(|| Mutex::new(None));
+
+/// The players of one instance, `None` if it isn't running or doesn't report them in time
+async fn instance_players(uuid: InstanceUuid, instance: GameInstance) -> Option<InstancePlayers> {
+    tokio::time::timeout(INSTANCE_PLAYERS_TIMEOUT, async {
+        if instance.state().await != State::Running {
+            return None;
+        }
+        let players = instance.get_player_list().await.ok()?;
+        let max_player_count = instance.get_max_player_count().await.ok();
+        let mut names = players
+            .iter()
+            .map(|player| player.get_name())
+            .collect::<Vec<_>>();
+        names.sort();
+        Some(InstancePlayers {
+            instance_uuid: uuid,
+            instance_name: instance.name().await,
+            player_count: names.len() as u32,
+            max_player_count,
+            players: names,
+        })
+    })
+    .await
+    // the instance can't report its players, which shouldn't hide everyone else's
+    .ok()
+    .flatten()
+}
+
+async fn collect_instance_players(state: &AppState) -> Vec<InstancePlayers> {
+    // queried without holding the lock, a slow server would hold up every other request.
+    // Clones share the instance
+    let instances = state
+        .instances
+        .lock()
+        .await
+        .iter()
+        .map(|(uuid, instance)| (uuid.clone(), instance.clone()))
+        .collect::<Vec<_>>();
+    let mut ret = futures::future::join_all(
+        instances
+            .into_iter()
+            .map(|(uuid, instance)| instance_players(uuid, instance)),
+    )
+    .await
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+    ret.sort_by(|a, b| a.instance_name.cmp(&b.instance_name));
+    ret
+}
+
+pub async fn get_players_online(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<PlayersOnline>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let instance_players = {
+        // held while collecting, so that concurrent refreshes wait for the same result
+        let mut cache = PLAYERS_ONLINE_CACHE.lock().await;
+        match cache.as_ref() {
+            Some((collected_at, instance_players))
+                if collected_at.elapsed() < PLAYERS_ONLINE_CACHE_TTL =>
+            {
+                instance_players.clone()
+            }
+            _ => {
+                let instance_players = collect_instance_players(&state).await;
+                *cache = Some((Instant::now(), instance_players.clone()));
+                instance_players
+            }
+        }
+    };
+    let instances = instance_players
+        .into_iter()
+        .filter(|instance_players| {
+            requester.can_perform_action(&UserAction::ViewInstance(
+                instance_players.instance_uuid.clone(),
+            ))
+        })
+        .collect::<Vec<_>>();
+    Ok(Json(PlayersOnline {
+        total: instances.iter().map(|instance| instance.player_count).sum(),
+        instances,
+    }))
+}
+
 pub async fn get_player_count(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
 ) -> Result<Json<u32>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
     state
         .instances
         .lock()
@@ -31,7 +148,10 @@ pub async fn get_player_count(
 pub async fn get_max_player_count(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
 ) -> Result<Json<u32>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
     state
         .instances
         .lock()
@@ -49,8 +169,11 @@ pub async fn get_max_player_count(
 pub async fn set_max_player_count(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
     Json(count): Json<u32>,
 ) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
     state
         .instances
         .lock()
@@ -68,7 +191,10 @@ pub async fn set_max_player_count(
 pub async fn get_player_list(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
 ) -> Result<Json<HashSet<Player>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
     state
         .instances
         .lock()
@@ -83,13 +209,180 @@ pub async fn get_player_list(
         .map(Json)
 }
 
+pub async fn get_online_players(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<OnlinePlayer>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    // querying each player over RCON can take a while, so the lock isn't held for it.
+    // Clones share the instance
+    let instance = state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .clone();
+    instance.get_online_players().await.map(Json)
+}
+
+/// Performs a moderation action and records it in the audit log, returns the response of the server
+async fn moderate(
+    state: AppState,
+    uuid: InstanceUuid,
+    token: String,
+    action: ModerationAction,
+) -> Result<Json<String>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ManagePlayers(uuid.clone()))?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    let response = state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .moderate(action.clone(), caused_by.clone())
+        .await?;
+    log_audit_entry(
+        &state.sqlite_pool,
+        AuditAction::PlayerModerated,
+        &caused_by,
+        Some(uuid.to_string()),
+        serde_json::to_string(&action).unwrap_or_default(),
+    )
+    .await;
+    Ok(Json(response))
+}
+
+pub async fn set_player_game_mode(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(game_mode): Json<GameMode>,
+) -> Result<Json<String>, Error> {
+    moderate(
+        state,
+        uuid,
+        token,
+        ModerationAction::SetGameMode { player, game_mode },
+    )
+    .await
+}
+
+pub async fn teleport_player(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(destination): Json<TeleportDestination>,
+) -> Result<Json<String>, Error> {
+    moderate(
+        state,
+        uuid,
+        token,
+        ModerationAction::Teleport {
+            player,
+            destination,
+        },
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct GiveItem {
+    item: String,
+    count: u32,
+}
+
+pub async fn give_player_item(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(GiveItem { item, count }): Json<GiveItem>,
+) -> Result<Json<String>, Error> {
+    moderate(
+        state,
+        uuid,
+        token,
+        ModerationAction::Give {
+            player,
+            item,
+            count,
+        },
+    )
+    .await
+}
+
+pub async fn set_world_time(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(ticks): Json<u32>,
+) -> Result<Json<String>, Error> {
+    moderate(state, uuid, token, ModerationAction::SetTime { ticks }).await
+}
+
+#[derive(Deserialize)]
+pub struct WeatherChange {
+    weather: Weather,
+    duration_secs: Option<u32>,
+}
+
+pub async fn set_world_weather(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(WeatherChange {
+        weather,
+        duration_secs,
+    }): Json<WeatherChange>,
+) -> Result<Json<String>, Error> {
+    moderate(
+        state,
+        uuid,
+        token,
+        ModerationAction::SetWeather {
+            weather,
+            duration_secs,
+        },
+    )
+    .await
+}
+
 pub fn get_instance_players_routes(state: AppState) -> Router {
     Router::new()
+        .route("/players/online", get(get_players_online))
         .route("/instance/:uuid/players/count", get(get_player_count))
         .route(
             "/instance/:uuid/players/max",
             get(get_max_player_count).put(set_max_player_count),
         )
         .route("/instance/:uuid/players", get(get_player_list))
+        .route("/instance/:uuid/players/details", get(get_online_players))
+        .route(
+            "/instance/:uuid/players/:player/gamemode",
+            put(set_player_game_mode),
+        )
+        .route(
+            "/instance/:uuid/players/:player/teleport",
+            post(teleport_player),
+        )
+        .route(
+            "/instance/:uuid/players/:player/give",
+            post(give_player_item),
+        )
+        .route("/instance/:uuid/world/time", put(set_world_time))
+        .route("/instance/:uuid/world/weather", put(set_world_weather))
         .with_state(state)
 }
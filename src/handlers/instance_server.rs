@@ -1,5 +1,5 @@
 use axum::{
-    extract::Path,
+    extract::{Path, Query},
     routing::{get, post, put},
     Router,
 };
@@ -8,23 +8,41 @@ use axum::Json;
 use axum_auth::AuthBearer;
 
 use color_eyre::eyre::eyre;
+use serde::Deserialize;
 use serde_json::{json, Value};
+use sysinfo::SystemExt;
+use tracing::{info, warn};
 
 use crate::{
     auth::user::UserAction,
+    command_history::CommandHistoryEntry,
     error::{Error, ErrorKind},
-    events::CausedBy,
-    types::InstanceUuid,
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+    instance_debug::{self, InstanceDebugStatus},
+    types::{InstanceUuid, Snowflake},
 };
 
+use super::system::committed_ram;
+
 use crate::{
-    traits::{t_configurable::TConfigurable, t_server::TServer},
+    traits::{
+        t_configurable::TConfigurable,
+        t_server::{RconTestResult, TServer, TickPerformance, VerifyReport},
+    },
     AppState,
 };
 
+#[derive(Deserialize)]
+pub struct DowngradeOptions {
+    /// Load the world even if it was saved by a newer version of the game, which may corrupt it
+    #[serde(default)]
+    pub allow_downgrade: bool,
+}
+
 pub async fn start_instance(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
+    Query(options): Query<DowngradeOptions>,
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
@@ -33,11 +51,49 @@ pub async fn start_instance(
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
     };
-    let mut instance_list = state.instances.lock().await;
-    let instance = instance_list.get_mut(&uuid).ok_or_else(|| Error {
+    let instance_list = state.instances.lock().await;
+    let instance = instance_list.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
+    if let Some(max_ram) = instance.max_ram().await {
+        let committed = committed_ram(&instance_list).await + max_ram as u64 * 1024 * 1024;
+        let total = {
+            let mut sys = state.system.lock().await;
+            sys.refresh_memory();
+            sys.total_memory()
+        };
+        if committed > total {
+            let message = format!(
+                "Starting this instance commits {} MB of RAM to running instances, more than the {} MB this machine has",
+                committed / 1024 / 1024,
+                total / 1024 / 1024
+            );
+            if state.global_settings.lock().await.block_ram_overcommit() {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!(
+                        "{}. Lower the max RAM of some instances, or allow RAM overcommit in the global settings",
+                        message
+                    ),
+                });
+            }
+            warn!("[{}] {}", instance.name().await, message);
+            state.event_broadcaster.send(Event {
+                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                    instance_name: instance.name().await,
+                    instance_uuid: uuid.clone(),
+                    instance_event_inner: InstanceEventInner::InstanceWarning { message },
+                }),
+                snowflake: Snowflake::default(),
+                details: "".to_string(),
+                caused_by: caused_by.clone(),
+            });
+        }
+    }
+    // the start runs the before start hook, so the lock isn't held for it. Clones share the instance
+    let mut instance = instance.clone();
+    drop(instance_list);
     let port = instance.port().await;
 
     if state.port_manager.lock().await.port_status(port).is_in_use {
@@ -47,7 +103,11 @@ pub async fn start_instance(
         });
     }
 
-    instance.start(caused_by, false).await?;
+    if options.allow_downgrade {
+        instance.start_allowing_downgrade(caused_by, false).await?;
+    } else {
+        instance.start(caused_by, false).await?;
+    }
     Ok(Json(()))
 }
 
@@ -62,17 +122,18 @@ pub async fn stop_instance(
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
     };
-    state
+    // the stop runs the before stop hook, so the lock isn't held for it. Clones share the instance
+    let mut instance = state
         .instances
         .lock()
         .await
-        .get_mut(&uuid)
+        .get(&uuid)
         .ok_or_else(|| Error {
             kind: ErrorKind::NotFound,
             source: eyre!("Instance not found"),
         })?
-        .stop(caused_by, false)
-        .await?;
+        .clone();
+    instance.stop(caused_by, false).await?;
     Ok(Json(()))
 }
 
@@ -89,12 +150,17 @@ pub async fn restart_instance(
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
     };
-    let mut instance_list = state.instances.lock().await;
-    let instance = instance_list.get_mut(&uuid).ok_or_else(|| Error {
-        kind: ErrorKind::NotFound,
-        source: eyre!("Instance not found"),
-    })?;
-
+    // clones share the instance, the lock isn't held while the hooks run
+    let mut instance = state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .clone();
     instance.restart(caused_by, false).await?;
     Ok(Json(()))
 }
@@ -124,6 +190,31 @@ pub async fn kill_instance(
     Ok(Json(json!("ok")))
 }
 
+pub async fn force_stop_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::StopInstance(uuid.clone()))?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    state
+        .instances
+        .lock()
+        .await
+        .get_mut(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .force_stop(caused_by)
+        .await?;
+    Ok(Json(()))
+}
+
 pub async fn send_command(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
@@ -150,18 +241,35 @@ pub async fn send_command(
         .map(|_| Json(()))
 }
 
+pub async fn get_command_history(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<CommandHistoryEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .lock()
+            .await
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .command_history()
+            .await,
+    ))
+}
+
 pub async fn get_instance_state(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
     AuthBearer(token): AuthBearer,
 ) -> Result<Json<Value>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
-    if !requester.can_perform_action(&UserAction::ViewInstance(uuid.clone())) {
-        return Err(Error {
-            kind: ErrorKind::PermissionDenied,
-            source: eyre!("You don't have permission to view this instance"),
-        });
-    }
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
     Ok(Json(json!(
         state
             .instances
@@ -177,13 +285,192 @@ pub async fn get_instance_state(
     )))
 }
 
+pub async fn get_instance_launch_command(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<String>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .lock()
+            .await
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .get_launch_command()
+            .await?,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct DebugToggle {
+    enabled: bool,
+}
+
+pub async fn get_instance_debug(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstanceDebugStatus>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    if !state.instances.lock().await.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(instance_debug::debug_status(&uuid)))
+}
+
+/// Turns debug logging of an instance on or off, it turns itself off after a while
+pub async fn set_instance_debug(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    Query(DebugToggle { enabled }): Query<DebugToggle>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstanceDebugStatus>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    let name = state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .name()
+        .await;
+    info!(
+        "[{}] Debug logging {} by {}",
+        name,
+        if enabled { "enabled" } else { "disabled" },
+        requester.username
+    );
+    Ok(Json(instance_debug::set_debug(&uuid, enabled)))
+}
+
+pub async fn get_instance_tick_performance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<TickPerformance>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .lock()
+            .await
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .tick_performance()
+            .await?,
+    ))
+}
+
+pub async fn test_instance_rcon(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<RconTestResult>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .lock()
+            .await
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .test_rcon()
+            .await?,
+    ))
+}
+
+pub async fn verify_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<VerifyReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .lock()
+            .await
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .verify_installation()
+            .await?,
+    ))
+}
+
+pub async fn repair_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<VerifyReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    // repairing downloads the broken parts again, so the lock isn't held for it.
+    // Clones share the instance
+    let mut instance = state
+        .instances
+        .lock()
+        .await
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .clone();
+    Ok(Json(instance.repair_installation().await?))
+}
+
 pub fn get_instance_server_routes(state: AppState) -> Router {
     Router::new()
         .route("/instance/:uuid/start", put(start_instance))
         .route("/instance/:uuid/stop", put(stop_instance))
         .route("/instance/:uuid/restart", put(restart_instance))
-        .route("/instance/:uuid/kill", put(kill_instance))
+        .route(
+            "/instance/:uuid/kill",
+            put(kill_instance).post(force_stop_instance),
+        )
         .route("/instance/:uuid/console", post(send_command))
+        .route("/instance/:uuid/command/history", get(get_command_history))
         .route("/instance/:uuid/state", get(get_instance_state))
+        .route(
+            "/instance/:uuid/launch_command",
+            get(get_instance_launch_command),
+        )
+        .route(
+            "/instance/:uuid/debug",
+            get(get_instance_debug).put(set_instance_debug),
+        )
+        .route(
+            "/instance/:uuid/performance",
+            get(get_instance_tick_performance),
+        )
+        .route("/instance/:uuid/rcon/test", post(test_instance_rcon))
+        .route("/instance/:uuid/verify", get(verify_instance))
+        .route("/instance/:uuid/repair", post(repair_instance))
         .with_state(state)
 }
@@ -1,9 +1,14 @@
 use crate::error::Error;
 use crate::error::ErrorKind;
 use crate::implementations::generic;
+use crate::implementations::generic::sandbox::BundlePermissions;
 use crate::implementations::minecraft;
+use crate::implementations::minecraft::paper::{get_paper_builds, PaperBuild};
+use crate::implementations::minecraft::quilt::get_quilt_loader_versions;
 use crate::minecraft::FlavourKind;
-use crate::traits::t_configurable::manifest::SetupManifest;
+use crate::traits::t_configurable::manifest::{
+    SectionManifestValue, SettingValidation, SetupManifest,
+};
 use crate::traits::t_configurable::GameType;
 use crate::AppState;
 use axum::extract::Path;
@@ -24,6 +29,8 @@ pub enum HandlerGameType {
     MinecraftFabric,
     MinecraftForge,
     MinecraftPaper,
+    MinecraftSpigot,
+    MinecraftQuilt,
     MinecraftBedrock,
 }
 
@@ -34,6 +41,8 @@ impl From<HandlerGameType> for GameType {
             HandlerGameType::MinecraftFabric => Self::MinecraftJava,
             HandlerGameType::MinecraftForge => Self::MinecraftJava,
             HandlerGameType::MinecraftPaper => Self::MinecraftJava,
+            HandlerGameType::MinecraftSpigot => Self::MinecraftJava,
+            HandlerGameType::MinecraftQuilt => Self::MinecraftJava,
             HandlerGameType::MinecraftBedrock => Self::MinecraftBedrock,
         }
     }
@@ -48,6 +57,8 @@ impl TryFrom<HandlerGameType> for FlavourKind {
             HandlerGameType::MinecraftFabric => Self::Fabric,
             HandlerGameType::MinecraftForge => Self::Forge,
             HandlerGameType::MinecraftPaper => Self::Paper,
+            HandlerGameType::MinecraftSpigot => Self::Spigot,
+            HandlerGameType::MinecraftQuilt => Self::Quilt,
             HandlerGameType::MinecraftBedrock => {
                 return Err(Error {
                     kind: ErrorKind::BadRequest,
@@ -64,6 +75,8 @@ pub async fn get_available_games() -> Json<Vec<HandlerGameType>> {
         HandlerGameType::MinecraftFabric,
         HandlerGameType::MinecraftForge,
         HandlerGameType::MinecraftPaper,
+        HandlerGameType::MinecraftSpigot,
+        HandlerGameType::MinecraftQuilt,
     ])
 }
 
@@ -75,6 +88,31 @@ pub async fn get_setup_manifest(
         .map(Json)
 }
 
+/// Validates the settings of a setup section, reporting each invalid setting rather than failing
+pub async fn validate_setup_section(
+    Path((game_type, section_id)): Path<(HandlerGameType, String)>,
+    Json(section): Json<SectionManifestValue>,
+) -> Result<Json<Vec<SettingValidation>>, Error> {
+    minecraft::MinecraftInstance::setup_manifest(&game_type.try_into()?)
+        .await?
+        .validate_section(&section_id, &section)
+        .map(Json)
+}
+
+/// Builds of a paper version with their channel, newest first
+pub async fn get_paper_build_list(
+    Path(version): Path<String>,
+) -> Result<Json<Vec<PaperBuild>>, Error> {
+    get_paper_builds(&version).await.map(Json)
+}
+
+/// Quilt loaders that support a minecraft version, newest first
+pub async fn get_quilt_loader_list(
+    Path(version): Path<String>,
+) -> Result<Json<Vec<String>>, Error> {
+    get_quilt_loader_versions(&version).await.map(Json)
+}
+
 #[derive(Deserialize)]
 pub struct GenericSetupManifestBody {
     pub url: String,
@@ -89,10 +127,27 @@ pub async fn get_generic_setup_manifest(
         .map(Json)
 }
 
+/// The capabilities the bundle asks for, which have to be approved to create an instance from it
+pub async fn get_generic_setup_permissions(
+    Json(body): Json<GenericSetupManifestBody>,
+) -> Result<Json<BundlePermissions>, Error> {
+    BundlePermissions::fetch(&body.url).await.map(Json)
+}
+
 pub fn get_instance_setup_config_routes(appstate: AppState) -> Router {
     Router::new()
         .route("/games", get(get_available_games))
+        .route("/games/paper/:version/builds", get(get_paper_build_list))
+        .route("/games/quilt/:version/loaders", get(get_quilt_loader_list))
         .route("/setup_manifest/:game_type", get(get_setup_manifest))
+        .route(
+            "/setup_manifest/:game_type/:section_id/validate",
+            put(validate_setup_section),
+        )
         .route("/generic_setup_manifest", put(get_generic_setup_manifest))
+        .route(
+            "/generic_setup_permissions",
+            put(get_generic_setup_permissions),
+        )
         .with_state(appstate)
 }
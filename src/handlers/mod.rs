@@ -1,20 +1,28 @@
 // pub mod jar;
 // pub mod instance;
 // pub mod users;
+pub mod audit;
 pub mod checks;
 pub mod core_info;
 pub mod events;
 pub mod gateway;
 pub mod global_fs;
 pub mod global_settings;
+pub mod health;
 pub mod instance;
+pub mod instance_backup;
+pub mod instance_bundle;
 pub mod instance_config;
 pub mod instance_fs;
+pub mod instance_generic;
 pub mod instance_macro;
 pub mod instance_players;
 pub mod instance_server;
 pub mod instance_setup_configs;
 pub mod monitor;
+pub mod progress;
+pub mod remote_core;
+pub mod roles;
 pub mod setup;
 pub mod system;
 pub mod users;
@@ -1,7 +1,7 @@
 use std::{collections::HashMap, sync::Arc};
 
 use axum::{
-    extract::{ws::WebSocket, Path, WebSocketUpgrade},
+    extract::{ws::WebSocket, Path, Query, WebSocketUpgrade},
     response::Response,
     routing::get,
     Router,
@@ -12,8 +12,10 @@ use ringbuffer::{AllocRingBuffer, RingBufferExt};
 use tokio::sync::Mutex;
 use tracing::error;
 
+use super::{events::WebsocketQuery, util::parse_bearer_token};
 use crate::{
-    error::Error,
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
     prelude::GameInstance,
     traits::{t_server::MonitorReport, t_server::TServer},
     types::InstanceUuid,
@@ -23,15 +25,25 @@ use crate::{
 pub async fn monitor(
     ws: WebSocketUpgrade,
     axum::extract::State(state): axum::extract::State<AppState>,
+    query: Query<WebsocketQuery>,
     Path(uuid): Path<InstanceUuid>,
 ) -> Result<Response, Error> {
+    let users_manager = state.users_manager.read().await;
+    let requester = parse_bearer_token(query.token.as_str())
+        .and_then(|token| users_manager.try_auth(&token))
+        .ok_or_else(|| Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Token error"),
+        })?;
+    drop(users_manager);
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
     let instance = state
         .instances
         .lock()
         .await
         .get(&uuid)
         .ok_or_else(|| Error {
-            kind: crate::error::ErrorKind::NotFound,
+            kind: ErrorKind::NotFound,
             source: eyre!("Instance not found"),
         })?
         .to_owned();
@@ -0,0 +1,45 @@
+use axum::{extract::Path, routing::get, Json, Router};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    error::{Error, ErrorKind},
+    progress_registry::OperationProgress,
+    types::Snowflake,
+    AppState,
+};
+
+// progression events are shown to every user, and so is the progress they add up to
+
+pub async fn get_progress(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<OperationProgress>>, Error> {
+    state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(state.progress_registry.list().await))
+}
+
+pub async fn get_operation_progress(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(event_id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<OperationProgress>, Error> {
+    state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(
+        state
+            .progress_registry
+            .get(&event_id)
+            .await
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("No operation in progress with that id, it may have ended"),
+            })?,
+    ))
+}
+
+pub fn get_progress_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/progress", get(get_progress))
+        .route("/progress/:event_id", get(get_operation_progress))
+        .with_state(state)
+}
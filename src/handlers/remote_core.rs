@@ -0,0 +1,178 @@
+use axum::{
+    extract::Path,
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    remote_core::RemoteCoreInfo,
+    traits::InstanceInfo,
+    types::InstanceUuid,
+    AppState,
+};
+
+#[derive(Deserialize)]
+pub struct AddRemoteCore {
+    name: String,
+    url: String,
+    token: String,
+}
+
+pub async fn list_remote_cores(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<RemoteCoreInfo>>, Error> {
+    state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(state.remote_cores.list().await))
+}
+
+pub async fn add_remote_core(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(remote_core): Json<AddRemoteCore>,
+) -> Result<Json<RemoteCoreInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to manage remote cores"),
+        });
+    }
+    Ok(Json(
+        state
+            .remote_cores
+            .add(remote_core.name, remote_core.url, remote_core.token)
+            .await?,
+    ))
+}
+
+pub async fn remove_remote_core(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(core_id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to manage remote cores"),
+        });
+    }
+    state.remote_cores.remove(&core_id).await?;
+    Ok(Json(()))
+}
+
+pub async fn get_remote_instance_info(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((core_id, uuid)): Path<(String, InstanceUuid)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstanceInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    let info = state
+        .remote_cores
+        .request(
+            &core_id,
+            reqwest::Method::GET,
+            &format!("/instance/{}/info", uuid),
+            None,
+        )
+        .await?;
+    let mut info: InstanceInfo =
+        serde_json::from_value(info).context("Failed to parse instance info of remote core")?;
+    info.core_id = Some(core_id);
+    Ok(Json(info))
+}
+
+pub async fn start_remote_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((core_id, uuid)): Path<(String, InstanceUuid)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Value>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::StartInstance(uuid.clone()))?;
+    Ok(Json(
+        state
+            .remote_cores
+            .request(
+                &core_id,
+                reqwest::Method::PUT,
+                &format!("/instance/{}/start", uuid),
+                None,
+            )
+            .await?,
+    ))
+}
+
+pub async fn stop_remote_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((core_id, uuid)): Path<(String, InstanceUuid)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Value>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::StopInstance(uuid.clone()))?;
+    Ok(Json(
+        state
+            .remote_cores
+            .request(
+                &core_id,
+                reqwest::Method::PUT,
+                &format!("/instance/{}/stop", uuid),
+                None,
+            )
+            .await?,
+    ))
+}
+
+pub async fn send_remote_command(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((core_id, uuid)): Path<(String, InstanceUuid)>,
+    AuthBearer(token): AuthBearer,
+    Json(command): Json<String>,
+) -> Result<Json<Value>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    Ok(Json(
+        state
+            .remote_cores
+            .request(
+                &core_id,
+                reqwest::Method::POST,
+                &format!("/instance/{}/console", uuid),
+                Some(Value::String(command)),
+            )
+            .await?,
+    ))
+}
+
+pub fn get_remote_core_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/remote_cores",
+            get(list_remote_cores).post(add_remote_core),
+        )
+        .route("/remote_cores/:core_id", delete(remove_remote_core))
+        .route(
+            "/remote_cores/:core_id/instance/:uuid/info",
+            get(get_remote_instance_info),
+        )
+        .route(
+            "/remote_cores/:core_id/instance/:uuid/start",
+            put(start_remote_instance),
+        )
+        .route(
+            "/remote_cores/:core_id/instance/:uuid/stop",
+            put(stop_remote_instance),
+        )
+        .route(
+            "/remote_cores/:core_id/instance/:uuid/console",
+            post(send_remote_command),
+        )
+        .with_state(state)
+}
@@ -0,0 +1,104 @@
+use std::collections::HashSet;
+
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    auth::{
+        role::{Role, RoleAction, RoleId},
+        user::UserAction,
+    },
+    error::{Error, ErrorKind},
+    types::InstanceUuid,
+    AppState,
+};
+
+#[derive(Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct NewRole {
+    pub name: String,
+    pub actions: HashSet<RoleAction>,
+    pub instances: Option<HashSet<InstanceUuid>>,
+}
+
+impl From<NewRole> for Role {
+    fn from(new_role: NewRole) -> Self {
+        Role {
+            id: RoleId::default(),
+            name: new_role.name,
+            actions: new_role.actions,
+            instances: new_role.instances,
+            builtin: false,
+        }
+    }
+}
+
+pub async fn get_roles(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<Role>>, Error> {
+    let users_manager = state.users_manager.read().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ManagePermission)?;
+    Ok(Json(users_manager.list_roles()))
+}
+
+pub async fn create_role(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(new_role): Json<NewRole>,
+) -> Result<Json<Role>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    let role = Role::from(new_role);
+    requester.try_manage_role(&role, &[])?;
+    Ok(Json(users_manager.create_role(role).await?))
+}
+
+pub async fn update_role(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(role_id): Path<RoleId>,
+    AuthBearer(token): AuthBearer,
+    Json(new_role): Json<NewRole>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    let members = users_manager.role_members(&role_id);
+    if let Some(old_role) = users_manager.get_role(&role_id) {
+        requester.try_manage_role(&old_role, &members)?;
+    }
+    let role = Role::from(new_role);
+    requester.try_manage_role(&role, &members)?;
+    users_manager.update_role(&role_id, role).await?;
+    Ok(Json(()))
+}
+
+pub async fn delete_role(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(role_id): Path<RoleId>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    let role = users_manager.get_role(&role_id).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Role not found"),
+    })?;
+    requester.try_manage_role(&role, &users_manager.role_members(&role_id))?;
+    users_manager.delete_role(&role_id).await?;
+    Ok(Json(()))
+}
+
+pub fn get_role_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/roles", get(get_roles).post(create_role))
+        .route("/roles/:role_id", put(update_role).delete(delete_role))
+        .with_state(state)
+}
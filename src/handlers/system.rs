@@ -1,11 +1,45 @@
+use std::collections::HashMap;
+
 use axum::{routing::get, Json, Router};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
 use serde::{Deserialize, Serialize};
 use sysinfo::{CpuExt, CpuRefreshKind, DiskExt, SystemExt};
 
 use tokio::time::sleep;
+use tracing::info;
 
+use crate::auth::user::UserAction;
+use crate::error::{Error, ErrorKind};
+use crate::implementations::minecraft::jre::{list_installed_jres, InstalledJre};
+use crate::prelude::{path_to_binaries, GameInstance};
+use crate::traits::t_configurable::TConfigurable;
+use crate::traits::t_server::{State, TServer};
+use crate::types::InstanceUuid;
 use crate::AppState;
 
+/// RAM in bytes promised to instances that aren't stopped, through their max RAM
+pub async fn committed_ram(instances: &HashMap<InstanceUuid, GameInstance>) -> u64 {
+    let mut committed = 0;
+    for instance in instances.values() {
+        if matches!(instance.state().await, State::Stopped | State::Error) {
+            continue;
+        }
+        if let Some(max_ram) = instance.max_ram().await {
+            committed += max_ram as u64 * 1024 * 1024;
+        }
+    }
+    committed
+}
+
+/// How many times over the host's RAM is committed, above 1 the host can run out of memory
+pub fn overcommit_ratio(committed_ram: u64, total_ram: u64) -> f64 {
+    if total_ram == 0 {
+        return 0.0;
+    }
+    committed_ram as f64 / total_ram as f64
+}
+
 // Since MemInfo is not serializable, we need to create a new struct that is serializable.
 #[derive(Serialize, Deserialize)]
 pub struct MemInfo {
@@ -63,10 +97,102 @@ pub async fn get_cpu_info(
     })
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct SystemInfo {
+    total_ram: u64,
+    /// RAM in bytes committed to running instances through their max RAM
+    committed_ram: u64,
+    overcommit_ratio: f64,
+}
+
+pub async fn get_system_info(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> Json<SystemInfo> {
+    let total_ram = {
+        let mut sys = state.system.lock().await;
+        sys.refresh_memory();
+        sys.total_memory()
+    };
+    let committed_ram = committed_ram(&*state.instances.lock().await).await;
+    Json(SystemInfo {
+        total_ram,
+        committed_ram,
+        overcommit_ratio: overcommit_ratio(committed_ram, total_ram),
+    })
+}
+
+/// The JREs in the runtimes directory, with the instances using each
+async fn installed_jres(state: &AppState) -> Vec<InstalledJre> {
+    let path_to_java = path_to_binaries().join("java");
+    let mut jres = list_installed_jres(path_to_binaries()).await;
+    for (uuid, instance) in state.instances.lock().await.iter() {
+        let java = match instance.java_executable().await {
+            Some(java) => java,
+            None => continue,
+        };
+        if let Some(jre) = jres
+            .iter_mut()
+            .find(|jre| java.starts_with(path_to_java.join(&jre.name)))
+        {
+            jre.used_by.push(uuid.clone());
+        }
+    }
+    jres
+}
+
+pub async fn get_installed_jres(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<InstalledJre>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadGlobalFile)?;
+    Ok(Json(installed_jres(&state).await))
+}
+
+/// Removes the JREs no instance uses, returning their names
+pub async fn remove_unused_jres(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<String>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteGlobalFile)?;
+    // a setup may be about to use a JRE no instance uses yet
+    if state
+        .pending_instances
+        .list(|_, _| true)
+        .await
+        .iter()
+        .any(|info| info.state == State::SettingUp)
+    {
+        return Err(Error {
+            kind: ErrorKind::InvalidInstanceState,
+            source: eyre!("Unused JREs can't be removed while an instance is being set up"),
+        });
+    }
+    let mut removed = Vec::new();
+    for jre in installed_jres(&state).await {
+        if !jre.used_by.is_empty() {
+            continue;
+        }
+        let path_to_jre = path_to_binaries().join("java").join(&jre.name);
+        crate::util::fs::remove_dir_all(&path_to_jre)
+            .await
+            .context(format!("Failed to remove JRE {}", path_to_jre.display()))?;
+        info!("Removed unused JRE {}", jre.name);
+        removed.push(jre.name);
+    }
+    Ok(Json(removed))
+}
+
 pub fn get_system_routes(state: AppState) -> Router {
     Router::new()
+        .route("/system/info", get(get_system_info))
         .route("/system/ram", get(get_ram))
         .route("/system/disk", get(get_disk))
         .route("/system/cpu", get(get_cpu_info))
+        .route(
+            "/system/java",
+            get(get_installed_jres).delete(remove_unused_jres),
+        )
         .with_state(state)
 }
@@ -2,16 +2,21 @@ use crate::{
     auth::{
         jwt_token::JwtToken,
         permission::UserPermission,
-        user::{PublicUser, User, UserAction},
+        role::{Role, RoleId},
+        totp::{unix_time, TotpEnrollment},
+        user::{PublicUser, User, UserAction, UsersManager},
         user_id::UserId,
     },
     error::{Error, ErrorKind},
     events::CausedBy,
+    notification::NotificationPreferences,
     AppState,
 };
 
+use std::{collections::HashSet, net::SocketAddr, time::Instant};
+
 use axum::{
-    extract::Path,
+    extract::{ConnectInfo, Path},
     routing::{delete, get, post, put},
     Json, Router,
 };
@@ -127,6 +132,29 @@ pub async fn update_permissions(
     Ok(Json(()))
 }
 
+pub async fn update_roles(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+    Json(roles): Json<HashSet<RoleId>>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    let user = users_manager.get_user(&uid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("User not found"),
+    })?;
+    // the roles being removed matter as much as the ones being added
+    let changed_roles: Vec<Role> = roles
+        .symmetric_difference(&user.roles)
+        .filter_map(|role_id| users_manager.get_role(role_id))
+        .collect();
+    requester.try_assign_roles(&user, &changed_roles)?;
+    users_manager.set_roles(uid, roles).await?;
+    Ok(Json(()))
+}
+
 pub async fn get_self_info(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
@@ -244,16 +272,93 @@ pub struct LoginReply {
     pub user: PublicUser,
 }
 
+#[derive(Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct TwoFactorCode {
+    pub code: String,
+}
+
+/// Checks the credentials of a login, the password is hashed without holding the users manager
+async fn verify_login(
+    state: &AppState,
+    username: &str,
+    password: String,
+    two_factor_code: Option<String>,
+) -> Result<JwtToken, Error> {
+    let (user, totp_key) = {
+        let users_manager = state.users_manager.read().await;
+        (
+            users_manager.get_user_by_username(username),
+            users_manager.totp_key().await?,
+        )
+    };
+    let login =
+        UsersManager::check_login(user, totp_key, password, two_factor_code, unix_time()?).await?;
+    state
+        .users_manager
+        .write()
+        .await
+        .complete_login(login)
+        .await
+}
+
 pub async fn login(
     axum::extract::State(state): axum::extract::State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     AuthBasic((username, password)): AuthBasic,
+    two_factor_code: Option<Json<TwoFactorCode>>,
 ) -> Result<Json<LoginReply>, Error> {
     if let Some(password) = password {
-        let users_manager = state.users_manager.read().await;
+        let ip = Some(addr.ip());
+        let attempted_at = Instant::now();
+        // the limiter isn't held while hashing, so that one slow login doesn't block the others
+        if let Err(retry_after) =
+            state
+                .login_rate_limiter
+                .lock()
+                .await
+                .begin_attempt(ip, &username, attempted_at)
+        {
+            return Err(Error {
+                kind: ErrorKind::Unauthorized,
+                source: eyre!(
+                    "Too many failed login attempts, try again in {} seconds",
+                    retry_after.as_secs().max(1)
+                ),
+            });
+        }
+        let token = match verify_login(
+            &state,
+            &username,
+            password,
+            two_factor_code.map(|Json(two_factor_code)| two_factor_code.code),
+        )
+        .await
+        {
+            Ok(token) => {
+                state
+                    .login_rate_limiter
+                    .lock()
+                    .await
+                    .record_success(ip, &username, attempted_at);
+                token
+            }
+            Err(e) => {
+                state
+                    .login_rate_limiter
+                    .lock()
+                    .await
+                    .record_failure(ip, &username, attempted_at);
+                return Err(e);
+            }
+        };
 
         Ok(Json(LoginReply {
-            token: users_manager.login(&username, &password)?,
-            user: users_manager
+            token,
+            user: state
+                .users_manager
+                .read()
+                .await
                 .get_user_by_username(&username)
                 .ok_or_else(|| Error {
                     kind: ErrorKind::NotFound,
@@ -269,6 +374,82 @@ pub async fn login(
     }
 }
 
+pub async fn enroll_two_factor(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<TotpEnrollment>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    Ok(Json(users_manager.enroll_two_factor(&requester.uid).await?))
+}
+
+pub async fn confirm_two_factor(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(TwoFactorCode { code }): Json<TwoFactorCode>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    users_manager
+        .confirm_two_factor(&requester.uid, code, unix_time()?)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn disable_two_factor(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(TwoFactorCode { code }): Json<TwoFactorCode>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    users_manager
+        .disable_two_factor(&requester.uid, code, unix_time()?)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_notification_preferences(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<NotificationPreferences>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(requester.notification_preferences))
+}
+
+pub async fn set_notification_preferences(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(notification_preferences): Json<NotificationPreferences>,
+) -> Result<Json<NotificationPreferences>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    // resolved before the users are locked, the owner may use webhooks on the local network
+    if !requester.is_owner {
+        notification_preferences.check_webhook_addresses().await?;
+    }
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    Ok(Json(
+        users_manager
+            .set_notification_preferences(&requester.uid, Some(notification_preferences))
+            .await?,
+    ))
+}
+
+/// Resets the notification preferences of the requester to the default
+pub async fn reset_notification_preferences(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<NotificationPreferences>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    Ok(Json(
+        users_manager
+            .set_notification_preferences(&requester.uid, None)
+            .await?,
+    ))
+}
+
 pub async fn get_all_users(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
@@ -296,10 +477,20 @@ pub fn get_user_routes(state: AppState) -> Router {
         .route("/user/:uid", get(get_user_info))
         .route("/user/:uid", delete(delete_user))
         .route("/user/:uid/update_perm", put(update_permissions))
+        .route("/user/:uid/roles", put(update_roles))
         .route("/user/info", get(get_self_info))
         .route("/user/:uid/rename", put(rename_user))
         .route("/user/:uid/password", put(change_password))
         .route("/user/login", post(login))
         .route("/user/logout/:uid", post(logout))
+        .route("/user/two_factor/enroll", post(enroll_two_factor))
+        .route("/user/two_factor/verify", post(confirm_two_factor))
+        .route("/user/two_factor", delete(disable_two_factor))
+        .route("/user/notifications", get(get_notification_preferences))
+        .route("/user/notifications", put(set_notification_preferences))
+        .route(
+            "/user/notifications",
+            delete(reset_notification_preferences),
+        )
         .with_state(state)
 }
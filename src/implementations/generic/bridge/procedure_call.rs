@@ -17,13 +17,14 @@ use crate::error::{Error, ErrorKind};
 use crate::events::CausedBy;
 
 use crate::implementations::generic::player::GenericPlayer;
+use crate::implementations::generic::sandbox::{PROCEDURE_CALL_TIMEOUT, SETUP_PROCEDURE_TIMEOUT};
 
 use crate::traits::t_configurable::manifest::{
     ConfigurableManifest, ConfigurableValue, SetupManifest, SetupValue,
 };
 use crate::traits::t_configurable::Game;
 use crate::traits::t_player::Player;
-use crate::traits::t_server::State;
+use crate::traits::t_server::{State, StopCommand};
 use crate::types::DotLodestoneConfig;
 use crate::MonitorReport;
 
@@ -91,6 +92,7 @@ pub enum ProcedureCallInner {
         caused_by: CausedBy,
     },
     GetState,
+    GetStopCommand,
     SendCommand {
         command: String,
         caused_by: CausedBy,
@@ -146,6 +148,7 @@ pub enum ProcedureCallResultInner {
     ConfigurableManifest(ConfigurableManifest),
     Player(HashSet<GenericPlayer>),
     SetupManifest(SetupManifest),
+    StopCommand(StopCommand),
     Void,
 }
 
@@ -275,6 +278,22 @@ impl TryFrom<ProcedureCallResultInner> for SetupManifest {
     }
 }
 
+impl TryFrom<ProcedureCallResultInner> for StopCommand {
+    type Error = Error;
+    fn try_from(value: ProcedureCallResultInner) -> Result<Self, Self::Error> {
+        match value {
+            ProcedureCallResultInner::StopCommand(s) => Ok(s),
+            _ => Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "ProcedureCallResultInner::StopCommand expected, got {:?}",
+                    value
+                ),
+            }),
+        }
+    }
+}
+
 impl TryFrom<ProcedureCallResultInner> for () {
     type Error = Error;
     fn try_from(value: ProcedureCallResultInner) -> Result<Self, Self::Error> {
@@ -401,22 +420,39 @@ impl ProcedureBridge {
         let id = self
             .procedure_call_id
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let timeout = match inner {
+            ProcedureCallInner::SetupInstance { .. }
+            | ProcedureCallInner::RestoreInstance { .. } => SETUP_PROCEDURE_TIMEOUT,
+            _ => PROCEDURE_CALL_TIMEOUT,
+        };
+        let kind = ProcedureCallKind::from(&inner);
         self.procedure_tx.send(ProcedureCall { id, inner }).unwrap();
-        loop {
-            match self.procedure_result_rx.write().await.recv().await {
-                Some(result) => {
-                    if result.id == id {
-                        return match result.success {
-                            true => Ok(result.inner.unwrap()),
-                            false => Err(result.error.unwrap().into()),
-                        };
+        tokio::time::timeout(timeout, async {
+            loop {
+                match self.procedure_result_rx.write().await.recv().await {
+                    Some(result) => {
+                        if result.id == id {
+                            return match result.success {
+                                true => Ok(result.inner.unwrap()),
+                                false => Err(result.error.unwrap().into()),
+                            };
+                        }
+                    }
+                    None => {
+                        Err(eyre!("ProcedureBridge::call: procedure_result_tx closed"))?;
+                        unreachable!()
                     }
-                }
-                None => {
-                    Err(eyre!("ProcedureBridge::call: procedure_result_tx closed"))?;
-                    unreachable!()
                 }
             }
-        }
+        })
+        .await
+        .map_err(|_| Error {
+            kind: ErrorKind::Internal,
+            source: eyre!(
+                "The generic instance didn't answer {:?} within {} seconds",
+                kind,
+                timeout.as_secs()
+            ),
+        })?
     }
 }
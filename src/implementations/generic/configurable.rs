@@ -137,7 +137,11 @@ impl TConfigurable for GenericInstance {
         })
     }
 
-    async fn change_version(&mut self, _version: String) -> Result<(), Error> {
+    async fn change_version(
+        &mut self,
+        _version: String,
+        _allow_downgrade: bool,
+    ) -> Result<(), Error> {
         Err(Error {
             kind: ErrorKind::UnsupportedOperation,
             source: eyre!("This instance does not support changing version"),
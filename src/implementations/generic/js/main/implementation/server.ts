@@ -1,6 +1,7 @@
 import { CausedBy } from "../libs/bindings/CausedBy.ts";
 import { InstanceState } from "../libs/bindings/InstanceState.ts";
 import { PerformanceReport } from "../libs/bindings/PerformanceReport.ts";
+import { StopCommand } from "../libs/bindings/StopCommand.ts";
 
 
 /**
@@ -41,6 +42,19 @@ export async function stopInstance(caused_by: CausedBy, block: boolean) {
   // implementation below
 }
 
+/**
+ * @returns {StopCommand} - How Lodestone Core should stop the instance gracefully
+ *
+ * If `command` is set, it is sent to the instance with `sendCommand` to stop it,
+ * otherwise `stopInstance` is called.
+ *
+ * If the instance hasn't stopped `timeout_secs` seconds later, it is killed with `killInstance`
+ */
+// deno-lint-ignore require-await
+export async function getStopCommand(): Promise<StopCommand> {
+  return { command: null, timeout_secs: 60 };
+}
+
 /**
  * @param {CausedBy} caused_by - The source that requested this instance to be killed
  * 
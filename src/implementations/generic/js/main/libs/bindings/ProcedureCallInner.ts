@@ -42,6 +42,7 @@ export type ProcedureCallInner =
   | { type: "RestartInstance"; caused_by: CausedBy; block: boolean }
   | { type: "KillInstance"; caused_by: CausedBy }
   | { type: "GetState" }
+  | { type: "GetStopCommand" }
   | { type: "SendCommand"; command: string; caused_by: CausedBy }
   | { type: "Monitor" }
   | { type: "GetPlayerCount" }
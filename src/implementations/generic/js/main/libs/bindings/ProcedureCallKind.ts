@@ -24,6 +24,7 @@ export type ProcedureCallKind =
   | "RestartInstance"
   | "KillInstance"
   | "GetState"
+  | "GetStopCommand"
   | "SendCommand"
   | "Monitor"
   | "GetPlayerCount"
@@ -5,6 +5,7 @@ import type { GenericPlayer } from "./GenericPlayer.ts";
 import type { InstanceState } from "./InstanceState.ts";
 import type { PerformanceReport } from "./PerformanceReport.ts";
 import { SetupManifest } from "./SetupManifest.ts";
+import type { StopCommand } from "./StopCommand.ts";
 
 export type ProcedureCallResultInner =
   | { String: string }
@@ -16,4 +17,5 @@ export type ProcedureCallResultInner =
   | { ConfigurableManifest: ConfigurableManifest }
   | { Player: Array<GenericPlayer> }
   | { SetupManifest: SetupManifest }
+  | { StopCommand: StopCommand }
   | "Void";
@@ -12,6 +12,7 @@ import { ProcedureCall } from "./bindings/ProcedureCall.ts";
 // This is the only code you should edit.
 import {
     getState,
+    getStopCommand,
     killInstance,
     monitor,
     restartInstance,
@@ -182,6 +183,10 @@ async function tServerHandle(procedure: ProcedureCall) {
             await killInstance(procedure.inner.caused_by);
         } else if (procedure.inner.type === "GetState") {
             await getState();
+        } else if (procedure.inner.type === "GetStopCommand") {
+            ret = {
+                StopCommand: await getStopCommand()
+            };
         } else if (procedure.inner.type === "SendCommand") {
             await sendCommand(procedure.inner.command, procedure.inner.caused_by)
         } else if (procedure.inner.type === "Monitor") {
@@ -8,6 +8,7 @@ export function isTServer(inner: ProcedureCallInner): boolean {
       case "KillInstance":
       case "SendCommand":
       case "GetState":
+      case "GetStopCommand":
       case "Monitor":
         return true;
     }
@@ -10,6 +10,7 @@ use crate::traits::t_macro::{HistoryEntry, MacroEntry, TMacro, TaskEntry};
 use super::bridge::procedure_call::{
     emit_result, next_procedure, proc_bridge_ready, ProcedureBridge,
 };
+use super::sandbox::BUNDLE_HEAP_LIMIT_BYTES;
 use super::GenericInstance;
 
 pub struct GenericMainWorkerGenerator {
@@ -44,6 +45,10 @@ impl WorkerOptionGenerator for GenericMainWorkerGenerator {
             ..Default::default()
         }
     }
+
+    fn heap_limit(&self) -> Option<usize> {
+        Some(BUNDLE_HEAP_LIMIT_BYTES)
+    }
 }
 
 #[async_trait]
@@ -1,26 +1,34 @@
-use std::{path::PathBuf, rc::Rc};
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
 
 use async_trait::async_trait;
-use color_eyre::eyre::Context;
-use tracing::error;
+use color_eyre::eyre::{eyre, Context};
+use serde::Serialize;
+use tracing::{error, info, warn};
+use ts_rs::TS;
 use url::Url;
 
 use self::{
     bridge::procedure_call::{emit_result, next_procedure, proc_bridge_ready, ProcedureCallInner},
     r#macro::GenericMainWorkerGenerator,
+    sandbox::{BundlePermissions, BUNDLE_HEAP_LIMIT_BYTES, SETUP_MANIFEST_TIMEOUT},
 };
 use crate::{
-    error::Error,
+    error::{Error, ErrorKind},
     event_broadcaster::EventBroadcaster,
     events::CausedBy,
     macro_executor::{self, MacroExecutor, MacroPID, SpawnResult, WorkerOptionGenerator},
     traits::{
+        t_backup::TBackup,
         t_configurable::{
             manifest::{SetupManifest, SetupValue},
-            TConfigurable,
+            GameType, TConfigurable,
         },
         t_player::TPlayerManagement,
-        t_server::TServer,
+        t_server::{State, TServer},
         InstanceInfo, TInstance,
     },
     types::DotLodestoneConfig,
@@ -32,6 +40,7 @@ pub mod configurable;
 mod r#macro;
 pub mod player;
 pub mod resource;
+pub mod sandbox;
 pub mod server;
 
 #[derive(Clone)]
@@ -41,7 +50,60 @@ pub struct GenericInstance {
     procedure_bridge: bridge::procedure_call::ProcedureBridge,
     core_macro_executor: MacroExecutor,
     path: PathBuf,
-    core_macro_pid: MacroPID,
+    core_macro: Arc<CoreMacro>,
+}
+
+/// The macro running the bundle, shared by the clones of an instance so that
+/// dropping a clone doesn't abort it from under the others
+struct CoreMacro {
+    executor: MacroExecutor,
+    pid: MacroPID,
+}
+
+impl Drop for CoreMacro {
+    fn drop(&mut self) {
+        let _ = self.executor.abort_macro(self.pid).map_err(|e| {
+            error!(
+                "Failed to abort macro when dropping generic instance: {}",
+                e
+            );
+        });
+    }
+}
+
+/// Where a generic instance comes from and what it sets up
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct GenericSource {
+    /// URL of the bundle, whose `mod.ts` is the entrypoint
+    pub link_to_source: String,
+    pub game_type: GameType,
+    pub setup_manifest: SetupManifest,
+    /// Capabilities the bundle asks for, which have to be approved to set it up
+    pub requested_permissions: BundlePermissions,
+}
+
+/// The `run.ts` that boots the bundle at `link_to_source`
+fn bootstrap_module(link_to_source: &str) -> Result<String, Error> {
+    Ok(format!(
+        r#"import {{ run }} from "{}";
+                run();
+            "#,
+        Url::parse(link_to_source)
+            .context("Invalid URL")?
+            .join("mod.ts")
+            .context("Invalid URL")?
+            .as_str()
+    ))
+}
+
+/// The bundle URL a `run.ts` written by [`bootstrap_module`] boots
+fn link_to_source_from_bootstrap(run_ts_content: &str) -> Option<String> {
+    let (_, rest) = run_ts_content.split_once("from \"")?;
+    let (entrypoint, _) = rest.split_once('"')?;
+    entrypoint
+        .strip_suffix("mod.ts")
+        .map(|link_to_source| link_to_source.to_string())
 }
 
 struct InitWorkerGenerator {
@@ -70,14 +132,21 @@ impl WorkerOptionGenerator for InitWorkerGenerator {
             ..Default::default()
         }
     }
+
+    fn heap_limit(&self) -> Option<usize> {
+        Some(BUNDLE_HEAP_LIMIT_BYTES)
+    }
 }
 
 impl GenericInstance {
+    /// `permissions` are what the user approved of the bundle's requested permissions,
+    /// see [`BundlePermissions::check_approved`]
     pub async fn new(
         link_to_source: String,
         path: PathBuf,
         dot_lodestone_config: DotLodestoneConfig,
         setup_value: SetupValue,
+        permissions: BundlePermissions,
         event_broadcaster: EventBroadcaster,
         core_macro_executor: MacroExecutor,
     ) -> Result<Self, Error> {
@@ -86,16 +155,7 @@ impl GenericInstance {
             &path.display()
         ))?;
         let path_to_config = path.join(".lodestone_config");
-        let run_ts_content = format!(
-            r#"import {{ run }} from "{}";
-                run();
-            "#,
-            Url::parse(&link_to_source)
-                .context("Invalid URL")?
-                .join("mod.ts")
-                .context("Invalid URL")?
-                .as_str()
-        );
+        let run_ts_content = bootstrap_module(&link_to_source)?;
 
         let path_to_bootstrap = path.join("run.ts");
         tokio::fs::write(&path_to_bootstrap, run_ts_content)
@@ -114,6 +174,9 @@ impl GenericInstance {
             "Failed to write config to {}",
             &path_to_config.display()
         ))?;
+        permissions
+            .save_approved(dot_lodestone_config.uuid())
+            .await?;
 
         let procedure_bridge = bridge::procedure_call::ProcedureBridge::new();
 
@@ -127,7 +190,7 @@ impl GenericInstance {
                 Vec::new(),
                 CausedBy::System,
                 Box::new(GenericMainWorkerGenerator::new(procedure_bridge.clone())),
-                None,
+                Some(permissions.to_deno_permissions(Some(&path))?),
                 Some(dot_lodestone_config.uuid().clone()),
                 None,
             )
@@ -144,9 +207,12 @@ impl GenericInstance {
             dot_lodestone_config,
             procedure_bridge,
             event_broadcaster,
+            core_macro: Arc::new(CoreMacro {
+                executor: core_macro_executor.clone(),
+                pid: core_macro_pid,
+            }),
             core_macro_executor,
             path,
-            core_macro_pid,
         })
     }
 
@@ -155,6 +221,70 @@ impl GenericInstance {
         dot_lodestone_config: DotLodestoneConfig,
         event_broadcaster: EventBroadcaster,
         core_macro_executor: MacroExecutor,
+    ) -> Result<Self, Error> {
+        let permissions = match BundlePermissions::load_approved(dot_lodestone_config.uuid())
+            .await?
+        {
+            Some(permissions) => permissions,
+            None => Self::grandfather_permissions(&path_to_instance, &dot_lodestone_config).await,
+        };
+        Self::restore_with_permissions(
+            path_to_instance,
+            dot_lodestone_config,
+            permissions,
+            event_broadcaster,
+            core_macro_executor,
+        )
+        .await
+    }
+
+    /// Approves what the bundle of an instance set up before bundles were sandboxed declares.
+    /// It ran with every permission until now, denying them all would break it.
+    /// If the bundle can't be reached it gets none for now, and this is tried again on the next restore
+    async fn grandfather_permissions(
+        path_to_instance: &Path,
+        dot_lodestone_config: &DotLodestoneConfig,
+    ) -> BundlePermissions {
+        let declared = async {
+            let path_to_bootstrap = path_to_instance.join("run.ts");
+            let run_ts_content = tokio::fs::read_to_string(&path_to_bootstrap)
+                .await
+                .context(format!(
+                    "Failed to read bootstrap at {}",
+                    path_to_bootstrap.display()
+                ))?;
+            let link_to_source = link_to_source_from_bootstrap(&run_ts_content)
+                .ok_or_else(|| eyre!("Failed to find the bundle URL"))?;
+            let declared = BundlePermissions::fetch(&link_to_source).await?;
+            declared.save_approved(dot_lodestone_config.uuid()).await?;
+            Ok::<_, Error>(declared)
+        }
+        .await;
+        match declared {
+            Ok(declared) => {
+                info!(
+                    "Approved the permissions the bundle of instance {} declares, as it was set up before bundles were sandboxed",
+                    dot_lodestone_config.uuid()
+                );
+                declared
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to record the permissions of the bundle of instance {}, it runs without any until the next restore: {}",
+                    dot_lodestone_config.uuid(),
+                    e
+                );
+                BundlePermissions::default()
+            }
+        }
+    }
+
+    async fn restore_with_permissions(
+        path_to_instance: PathBuf,
+        dot_lodestone_config: DotLodestoneConfig,
+        permissions: BundlePermissions,
+        event_broadcaster: EventBroadcaster,
+        core_macro_executor: MacroExecutor,
     ) -> Result<Self, Error> {
         let procedure_bridge = bridge::procedure_call::ProcedureBridge::new();
         let SpawnResult {
@@ -167,7 +297,7 @@ impl GenericInstance {
                 Vec::new(),
                 CausedBy::System,
                 Box::new(GenericMainWorkerGenerator::new(procedure_bridge.clone())),
-                None,
+                Some(permissions.to_deno_permissions(Some(&path_to_instance))?),
                 Some(dot_lodestone_config.uuid().clone()),
                 None,
             )
@@ -185,34 +315,47 @@ impl GenericInstance {
             dot_lodestone_config,
             procedure_bridge,
             event_broadcaster,
+            core_macro: Arc::new(CoreMacro {
+                executor: core_macro_executor.clone(),
+                pid: core_macro_pid,
+            }),
             core_macro_executor,
             path: path_to_instance,
-            core_macro_pid,
         })
     }
 
     pub async fn setup_manifest(
         link_to_source: &str,
         macro_executor: MacroExecutor,
+    ) -> Result<SetupManifest, Error> {
+        let requested_permissions = BundlePermissions::fetch(link_to_source).await?;
+        Self::sandboxed_setup_manifest(link_to_source, &requested_permissions, macro_executor).await
+    }
+
+    /// Asks the bundle for its setup manifest before the user approved its permissions,
+    /// so it can only reach the hosts it declared, and has [`SETUP_MANIFEST_TIMEOUT`] to answer
+    async fn sandboxed_setup_manifest(
+        link_to_source: &str,
+        requested_permissions: &BundlePermissions,
+        macro_executor: MacroExecutor,
     ) -> Result<SetupManifest, Error> {
         // create a tempfile
         let temp_dir = tempfile::TempDir::new().context("Failed to create temp dir")?;
         let temp_file_path = temp_dir.path().join("temp.ts");
         let mut temp_file =
             std::fs::File::create(&temp_file_path).context("Failed to create temp file")?;
-        let run_ts_content = format!(
-            r#"import {{ run }} from "{}";
-                run();
-            "#,
-            Url::parse(link_to_source)
-                .context("Invalid URL")?
-                .join("mod.ts")
-                .context("Invalid URL")?
-                .as_str()
-        );
+        let run_ts_content = bootstrap_module(link_to_source)?;
         writeln!(temp_file, "{}", run_ts_content).context("Failed to write to temp file")?;
         let procedure_bridge = bridge::procedure_call::ProcedureBridge::new();
-        macro_executor
+        let network_only = BundlePermissions {
+            net: requested_permissions.net.clone(),
+            ..Default::default()
+        };
+        let SpawnResult {
+            macro_pid,
+            main_module_future,
+            ..
+        } = macro_executor
             .spawn(
                 temp_file_path,
                 Vec::new(),
@@ -220,18 +363,124 @@ impl GenericInstance {
                 Box::new(InitWorkerGenerator {
                     bridge: procedure_bridge.clone(),
                 }),
-                None,
+                Some(network_only.to_deno_permissions(None)?),
                 None,
                 None,
             )
-            .await?
-            .main_module_future
-            .await;
+            .await?;
 
-        procedure_bridge
-            .call(ProcedureCallInner::GetSetupManifest)
-            .await?
-            .try_into()
+        let setup_manifest = tokio::time::timeout(SETUP_MANIFEST_TIMEOUT, async {
+            main_module_future.await;
+            procedure_bridge
+                .call(ProcedureCallInner::GetSetupManifest)
+                .await
+                .and_then(|result| result.try_into())
+        })
+        .await;
+        let _ = macro_executor.abort_macro(macro_pid);
+        setup_manifest.map_err(|_| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "The bundle didn't answer for its setup manifest within {} seconds",
+                SETUP_MANIFEST_TIMEOUT.as_secs()
+            ),
+        })?
+    }
+
+    /// URL of the bundle the instance was created from
+    pub async fn link_to_source(&self) -> Result<String, Error> {
+        let path_to_bootstrap = self.path.join("run.ts");
+        let run_ts_content = tokio::fs::read_to_string(&path_to_bootstrap)
+            .await
+            .context(format!(
+                "Failed to read bootstrap at {}",
+                path_to_bootstrap.display()
+            ))?;
+        link_to_source_from_bootstrap(&run_ts_content).ok_or_else(|| {
+            eyre!(
+                "Failed to find the bundle URL in {}",
+                path_to_bootstrap.display()
+            )
+            .into()
+        })
+    }
+
+    pub fn dot_lodestone_config(&self) -> &DotLodestoneConfig {
+        &self.dot_lodestone_config
+    }
+
+    /// Fetches the setup manifest of the bundle at `link_to_source`
+    pub async fn fetch_source(
+        link_to_source: String,
+        game_type: GameType,
+        macro_executor: MacroExecutor,
+    ) -> Result<GenericSource, Error> {
+        let requested_permissions = BundlePermissions::fetch(&link_to_source).await?;
+        let setup_manifest =
+            Self::sandboxed_setup_manifest(&link_to_source, &requested_permissions, macro_executor)
+                .await?;
+        Ok(GenericSource {
+            link_to_source,
+            game_type,
+            setup_manifest,
+            requested_permissions,
+        })
+    }
+
+    /// Re-fetches the bundle, e.g. after its author published an update, returning the instance running it.
+    ///
+    /// The new bundle has to answer for its setup manifest and restore the instance,
+    /// otherwise the error is returned and this instance is left as is.
+    ///
+    /// The new bundle may only request what the user approved before, unless `approved_permissions` are given
+    pub async fn reload(
+        &self,
+        approved_permissions: Option<BundlePermissions>,
+    ) -> Result<(GenericInstance, GenericSource), Error> {
+        if self.state().await != State::Stopped {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Instance must be stopped before reloading its bundle"),
+            });
+        }
+        let source = Self::fetch_source(
+            self.link_to_source().await?,
+            *self.dot_lodestone_config.game_type(),
+            self.core_macro_executor.clone(),
+        )
+        .await
+        .map_err(|e| Error {
+            kind: ErrorKind::BadRequest,
+            source: e.source.wrap_err("The updated bundle is invalid"),
+        })?;
+        let approved_permissions = match approved_permissions {
+            Some(approved_permissions) => approved_permissions,
+            None => BundlePermissions::load_approved(self.dot_lodestone_config.uuid())
+                .await?
+                .unwrap_or_default(),
+        };
+        source
+            .requested_permissions
+            .check_approved(&approved_permissions)?;
+        let instance = Self::restore_with_permissions(
+            self.path.clone(),
+            self.dot_lodestone_config.clone(),
+            source.requested_permissions.clone(),
+            self.event_broadcaster.clone(),
+            self.core_macro_executor.clone(),
+        )
+        .await
+        .map_err(|e| Error {
+            kind: ErrorKind::BadRequest,
+            source: e
+                .source
+                .wrap_err("The updated bundle failed to restore the instance"),
+        })?;
+        source
+            .requested_permissions
+            .save_approved(self.dot_lodestone_config.uuid())
+            .await?;
+        Ok((instance, source))
     }
 
     /// Will notify the typescript side that the instance is being destructed
@@ -246,19 +495,7 @@ impl GenericInstance {
     }
 }
 
-impl Drop for GenericInstance {
-    fn drop(&mut self) {
-        let _ = self
-            .core_macro_executor
-            .abort_macro(self.core_macro_pid)
-            .map_err(|e| {
-                error!(
-                    "Failed to abort macro when dropping generic instance: {}",
-                    e
-                );
-            });
-    }
-}
+impl TBackup for GenericInstance {}
 
 #[async_trait]
 impl TInstance for GenericInstance {
@@ -278,10 +515,36 @@ impl TInstance for GenericInstance {
             player_count: self.get_player_count().await.ok(),
             max_player_count: self.get_max_player_count().await.ok(),
             player_list: self.get_player_list().await.ok(),
+            last_crash: self.last_crash().await,
+            tags: self.tags().await,
+            setup_progress: None,
+            available_update: None,
+            core_id: None,
+            start_queue_position: None,
+            uptime_seconds: self.uptime_seconds().await,
+            last_started: self.last_started().await,
+            disk_usage: None,
         }
     }
 }
 
+#[cfg(test)]
+mod bootstrap_tests {
+    use super::*;
+
+    #[test]
+    fn test_link_to_source_from_bootstrap() {
+        let link_to_source =
+            "https://raw.githubusercontent.com/CheatCod/generic_instance_test/main/";
+        assert_eq!(
+            link_to_source_from_bootstrap(&bootstrap_module(link_to_source).unwrap()).as_deref(),
+            Some(link_to_source)
+        );
+        assert!(bootstrap_module("not a url").is_err());
+        assert_eq!(link_to_source_from_bootstrap("run();"), None);
+    }
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use std::path::PathBuf;
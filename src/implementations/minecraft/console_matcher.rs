@@ -0,0 +1,247 @@
+//! Awaiting a line of the console of an instance, so that a macro can send a command and read what
+//! the server answered without going through RCON
+
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use fancy_regex::Regex;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+use crate::error::{Error, ErrorKind};
+use crate::event_broadcaster::EventBroadcaster;
+use crate::events::{CausedBy, Event, EventInner, InstanceEventInner};
+use crate::traits::t_server::TServer;
+use crate::types::InstanceUuid;
+
+use super::MinecraftInstance;
+
+/// Compiles a pattern handed in by a macro
+pub fn parse_pattern(pattern: &str) -> Result<Regex, Error> {
+    Regex::new(pattern).map_err(|e| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Invalid console line pattern {}: {}", pattern, e),
+    })
+}
+
+/// The output line of the instance `event` carries, if any
+fn console_line<'a>(event: &'a Event, instance_uuid: &InstanceUuid) -> Option<&'a str> {
+    match &event.event_inner {
+        EventInner::InstanceEvent(instance_event)
+            if &instance_event.instance_uuid == instance_uuid =>
+        {
+            match &instance_event.instance_event_inner {
+                InstanceEventInner::InstanceOutput { message } => Some(message),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Waits for the first output line of the instance matching `pattern` that arrives on
+/// `event_receiver`. Every waiter has a receiver of its own, so a line can satisfy any number of
+/// them at once
+pub async fn wait_for_console_line(
+    event_broadcaster: &EventBroadcaster,
+    mut event_receiver: Receiver<Event>,
+    instance_uuid: &InstanceUuid,
+    pattern: &Regex,
+    timeout: Duration,
+) -> Result<String, Error> {
+    let wait = async {
+        loop {
+            match event_receiver.recv().await {
+                Ok(event) => {
+                    if let Some(line) = console_line(&event, instance_uuid) {
+                        // a pattern that gives up on backtracking doesn't match
+                        if pattern.is_match(line).unwrap_or(false) {
+                            return Ok(line.to_string());
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    event_broadcaster.record_lag("Console line waiter", missed);
+                }
+                Err(RecvError::Closed) => {
+                    return Err(Error {
+                        kind: ErrorKind::Internal,
+                        source: eyre!("Event channel closed while waiting for console output"),
+                    });
+                }
+            }
+        }
+    };
+    match tokio::time::timeout(timeout, wait).await {
+        Ok(result) => result,
+        Err(_) => Err(Error {
+            kind: ErrorKind::Internal,
+            source: eyre!(
+                "No console line matched {} within {} ms",
+                pattern.as_str(),
+                timeout.as_millis()
+            ),
+        }),
+    }
+}
+
+impl MinecraftInstance {
+    /// Waits for the next output line of the server matching `pattern`
+    pub async fn wait_for_console_line(
+        &self,
+        pattern: Regex,
+        timeout: Duration,
+    ) -> Result<String, Error> {
+        let event_receiver = self.event_broadcaster.subscribe();
+        wait_for_console_line(
+            &self.event_broadcaster,
+            event_receiver,
+            &self.uuid,
+            &pattern,
+            timeout,
+        )
+        .await
+    }
+
+    /// Sends `command` and waits for the output line answering it. Listening starts before the
+    /// command is sent, so a quick answer isn't missed
+    pub async fn send_command_and_wait(
+        &self,
+        command: &str,
+        pattern: Regex,
+        timeout: Duration,
+        caused_by: CausedBy,
+    ) -> Result<String, Error> {
+        let event_receiver = self.event_broadcaster.subscribe();
+        self.send_command(command, caused_by).await?;
+        wait_for_console_line(
+            &self.event_broadcaster,
+            event_receiver,
+            &self.uuid,
+            &pattern,
+            timeout,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(event_broadcaster: &EventBroadcaster, instance_uuid: &InstanceUuid, line: &str) {
+        event_broadcaster.send(Event::new_instance_output(
+            instance_uuid.clone(),
+            "test".to_string(),
+            line.to_string(),
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_waits_for_matching_line() {
+        let (event_broadcaster, _rx) = EventBroadcaster::new(64);
+        let instance_uuid = InstanceUuid::default();
+        let other_instance_uuid = InstanceUuid::default();
+        let pattern = parse_pattern(r"There are (\d+) of a max of (\d+) players online").unwrap();
+        let event_receiver = event_broadcaster.subscribe();
+
+        output(
+            &event_broadcaster,
+            &instance_uuid,
+            "[Server thread/INFO]: Saved the game",
+        );
+        // the same answer, but from another instance
+        output(
+            &event_broadcaster,
+            &other_instance_uuid,
+            "[Server thread/INFO]: There are 3 of a max of 20 players online",
+        );
+        output(
+            &event_broadcaster,
+            &instance_uuid,
+            "[Server thread/INFO]: There are 0 of a max of 20 players online",
+        );
+
+        let line = wait_for_console_line(
+            &event_broadcaster,
+            event_receiver,
+            &instance_uuid,
+            &pattern,
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            line,
+            "[Server thread/INFO]: There are 0 of a max of 20 players online"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_waiters_each_get_their_match() {
+        let (event_broadcaster, _rx) = EventBroadcaster::new(64);
+        let instance_uuid = InstanceUuid::default();
+        let waiter = |pattern: &str| {
+            let event_broadcaster = event_broadcaster.clone();
+            let event_receiver = event_broadcaster.subscribe();
+            let instance_uuid = instance_uuid.clone();
+            let pattern = parse_pattern(pattern).unwrap();
+            tokio::spawn(async move {
+                wait_for_console_line(
+                    &event_broadcaster,
+                    event_receiver,
+                    &instance_uuid,
+                    &pattern,
+                    Duration::from_secs(5),
+                )
+                .await
+            })
+        };
+        let seed = waiter(r"Seed: \[-?\d+\]");
+        let time = waiter(r"The time is \d+");
+        let seed_again = waiter(r"^Seed");
+
+        output(&event_broadcaster, &instance_uuid, "The time is 6000");
+        output(
+            &event_broadcaster,
+            &instance_uuid,
+            "Seed: [-4172144997902289642]",
+        );
+
+        assert_eq!(seed.await.unwrap().unwrap(), "Seed: [-4172144997902289642]");
+        assert_eq!(time.await.unwrap().unwrap(), "The time is 6000");
+        assert_eq!(
+            seed_again.await.unwrap().unwrap(),
+            "Seed: [-4172144997902289642]"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_times_out() {
+        let (event_broadcaster, _rx) = EventBroadcaster::new(64);
+        let instance_uuid = InstanceUuid::default();
+        let pattern = parse_pattern("Done").unwrap();
+        let event_receiver = event_broadcaster.subscribe();
+        output(
+            &event_broadcaster,
+            &instance_uuid,
+            "Preparing spawn area: 0%",
+        );
+
+        let e = wait_for_console_line(
+            &event_broadcaster,
+            event_receiver,
+            &instance_uuid,
+            &pattern,
+            Duration::from_millis(100),
+        )
+        .await
+        .unwrap_err();
+        assert!(e.source.to_string().contains("Done"));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let e = parse_pattern("There are (").unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::BadRequest));
+    }
+}
@@ -0,0 +1,313 @@
+//! The crash reports the server writes to `crash-reports` when it crashes, pointed to from the
+//! crash so that they can be shared without digging through the instance files. A server killed
+//! outright (e.g. by the OOM killer) doesn't get to write one
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use color_eyre::eyre::{eyre, Context};
+use serde::Deserialize;
+use tracing::{error, warn};
+
+use crate::error::{Error, ErrorKind};
+use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::public_url::check_public_url;
+use crate::traits::t_server::CrashReport;
+use crate::types::Snowflake;
+use crate::upstream_http;
+use crate::util::list_dir;
+
+use super::MinecraftInstance;
+
+/// Reports larger than this are cut off when they're uploaded
+const MAX_CRASH_REPORT_BYTES: usize = 256 * 1024;
+
+/// What an mclo.gs compatible paste API answers
+#[derive(Deserialize)]
+struct UploadResponse {
+    success: bool,
+    url: Option<String>,
+    error: Option<String>,
+}
+
+/// Fails if `url` isn't an http(s) URL. Where it points is checked with
+/// [`check_public_url`] when it's set and before every upload
+pub fn validate_upload_url(url: &str) -> Result<(), Error> {
+    let parsed = url::Url::parse(url).map_err(|e| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Invalid crash report upload URL {}: {}", url, e),
+    })?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Crash report upload URL {} must be http or https", url),
+        });
+    }
+    Ok(())
+}
+
+/// Cuts `content` off at `MAX_CRASH_REPORT_BYTES`, on a character boundary
+fn truncate_report(mut content: String) -> (String, bool) {
+    if content.len() <= MAX_CRASH_REPORT_BYTES {
+        return (content, false);
+    }
+    let mut end = MAX_CRASH_REPORT_BYTES;
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    content.truncate(end);
+    (content, true)
+}
+
+fn path_to_crash_reports(path_to_instance: &Path) -> PathBuf {
+    path_to_instance.join("crash-reports")
+}
+
+/// The newest crash report in the `crash-reports` directory of the instance that was written at or
+/// after `since`, `None` if the crash didn't produce one
+pub async fn newest_crash_report(
+    path_to_instance: &Path,
+    since: SystemTime,
+) -> Option<CrashReport> {
+    let mut newest: Option<(SystemTime, PathBuf)> = None;
+    for path in list_dir(&path_to_crash_reports(path_to_instance), Some(false))
+        .await
+        .ok()?
+        .into_iter()
+        .filter(|path| path.extension().map_or(false, |ext| ext == "txt"))
+    {
+        let modified = match tokio::fs::metadata(&path)
+            .await
+            .and_then(|metadata| metadata.modified())
+        {
+            Ok(modified) => modified,
+            Err(_) => continue,
+        };
+        if modified >= since && newest.as_ref().map_or(true, |(newest, _)| modified > *newest) {
+            newest = Some((modified, path));
+        }
+    }
+    Some(CrashReport {
+        file_name: newest?.1.file_name()?.to_string_lossy().to_string(),
+        url: None,
+    })
+}
+
+/// The content of `crash_report`, cut off at `MAX_CRASH_REPORT_BYTES`
+pub async fn read_crash_report(
+    path_to_instance: &Path,
+    crash_report: &CrashReport,
+) -> Result<String, Error> {
+    let path = path_to_crash_reports(path_to_instance).join(&crash_report.file_name);
+    let content = tokio::fs::read(&path)
+        .await
+        .context(format!("Failed to read crash report {}", path.display()))?;
+    let (content, truncated) = truncate_report(String::from_utf8_lossy(&content).to_string());
+    Ok(if truncated {
+        format!("{content}\n[Truncated by Lodestone]")
+    } else {
+        content
+    })
+}
+
+/// POSTs `content` to an mclo.gs compatible paste API at `upload_url`, returning the link to it.
+///
+/// Like webhooks, the API must not be on this host or a private network, which is checked right
+/// before the upload and not bypassed through redirects
+pub async fn upload_crash_report(upload_url: &str, content: &str) -> Result<String, Error> {
+    check_public_url(upload_url).await?;
+    let request = upstream_http::client_without_redirects()
+        .post(upload_url)
+        .form(&[("content", content)]);
+    let response: UploadResponse = upstream_http::send(request, upload_url)
+        .await?
+        .json()
+        .await
+        .map_err(|e| Error {
+            kind: ErrorKind::Internal,
+            source: eyre!("Unexpected response from {}: {}", upload_url, e),
+        })?;
+    match response {
+        UploadResponse {
+            success: true,
+            url: Some(url),
+            ..
+        } => Ok(url),
+        UploadResponse { error, .. } => Err(Error {
+            kind: ErrorKind::Internal,
+            source: eyre!(
+                "{} refused the crash report: {}",
+                upload_url,
+                error.unwrap_or_else(|| "no reason given".to_string())
+            ),
+        }),
+    }
+}
+
+impl MinecraftInstance {
+    /// Uploads the crash report of the crash at `crash_time` in the background and links it in the
+    /// recorded crash once it's up
+    pub(super) fn spawn_crash_report_upload(
+        &self,
+        upload_url: String,
+        crash_time: i64,
+        crash_report: CrashReport,
+    ) {
+        let __self = self.clone();
+        tokio::task::spawn(async move {
+            let name = __self.config.lock().await.name.clone();
+            let uploaded = match read_crash_report(&__self.path_to_instance, &crash_report).await {
+                Ok(content) => upload_crash_report(&upload_url, &content).await,
+                Err(e) => Err(e),
+            };
+            let instance_event_inner =
+                match uploaded {
+                    Ok(url) => {
+                        {
+                            let mut config = __self.config.lock().await;
+                            // unless the server crashed again in the meantime
+                            if let Some(last_crash) = config
+                                .last_crash
+                                .as_mut()
+                                .filter(|last_crash| last_crash.time == crash_time)
+                            {
+                                if let Some(report) = last_crash.crash_report.as_mut() {
+                                    report.url = Some(url.clone());
+                                }
+                            }
+                        }
+                        if let Err(e) = __self.write_config_to_file().await {
+                            error!("[{}] Failed to save crash report link: {}", name, e);
+                        }
+                        InstanceEventInner::SystemMessage {
+                            message: format!(
+                                "Crash report {} uploaded to {}",
+                                crash_report.file_name, url
+                            ),
+                        }
+                    }
+                    Err(e) => {
+                        warn!("[{}] Failed to upload crash report: {}", name, e.source);
+                        InstanceEventInner::InstanceWarning {
+                            message: format!(
+                                "Failed to upload crash report {}: {}",
+                                crash_report.file_name, e.source
+                            ),
+                        }
+                    }
+                };
+            __self.event_broadcaster.send(Event {
+                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                    instance_name: name,
+                    instance_uuid: __self.uuid.clone(),
+                    instance_event_inner,
+                }),
+                snowflake: Snowflake::default(),
+                details: "".to_string(),
+                caused_by: CausedBy::System,
+            });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_newest_crash_report() {
+        let temp_dir = tempdir::TempDir::new("test_crash_report").unwrap();
+        let path_to_reports = temp_dir.path().join("crash-reports");
+        std::fs::create_dir_all(&path_to_reports).unwrap();
+        std::fs::write(
+            path_to_reports.join("crash-2023-01-01_00.00.00-server.txt"),
+            "---- Minecraft Crash Report ----\nold",
+        )
+        .unwrap();
+        // mtimes are coarser than the clock
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let since = SystemTime::now();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        std::fs::write(path_to_reports.join("notes.md"), "not a report").unwrap();
+        std::fs::write(
+            path_to_reports.join("crash-2023-06-01_12.00.00-server.txt"),
+            "---- Minecraft Crash Report ----\nnew",
+        )
+        .unwrap();
+
+        let report = newest_crash_report(temp_dir.path(), since).await.unwrap();
+        assert_eq!(report.file_name, "crash-2023-06-01_12.00.00-server.txt");
+        assert_eq!(report.url, None);
+        assert!(read_crash_report(temp_dir.path(), &report)
+            .await
+            .unwrap()
+            .ends_with("new"));
+    }
+
+    #[tokio::test]
+    async fn test_read_large_crash_report() {
+        let temp_dir = tempdir::TempDir::new("test_crash_report").unwrap();
+        let path_to_reports = temp_dir.path().join("crash-reports");
+        std::fs::create_dir_all(&path_to_reports).unwrap();
+        std::fs::write(
+            path_to_reports.join("crash-huge-server.txt"),
+            "a".repeat(2 * MAX_CRASH_REPORT_BYTES),
+        )
+        .unwrap();
+        let report = CrashReport {
+            file_name: "crash-huge-server.txt".to_string(),
+            url: None,
+        };
+        let content = read_crash_report(temp_dir.path(), &report).await.unwrap();
+        assert!(content.len() < MAX_CRASH_REPORT_BYTES + 100);
+        assert!(content.ends_with("[Truncated by Lodestone]"));
+
+        // removed since the crash
+        std::fs::remove_file(path_to_reports.join("crash-huge-server.txt")).unwrap();
+        assert!(read_crash_report(temp_dir.path(), &report).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_no_crash_report() {
+        let temp_dir = tempdir::TempDir::new("test_crash_report").unwrap();
+        // killed outright, the server never got to write one
+        assert!(newest_crash_report(temp_dir.path(), SystemTime::now())
+            .await
+            .is_none());
+
+        // only one left over from an earlier crash
+        let path_to_reports = temp_dir.path().join("crash-reports");
+        std::fs::create_dir_all(&path_to_reports).unwrap();
+        std::fs::write(path_to_reports.join("crash-old-server.txt"), "old").unwrap();
+        let since = SystemTime::now() + Duration::from_secs(60);
+        assert!(newest_crash_report(temp_dir.path(), since).await.is_none());
+    }
+
+    #[test]
+    fn test_truncate_report() {
+        let (content, truncated) = truncate_report("é".repeat(MAX_CRASH_REPORT_BYTES));
+        assert!(truncated);
+        assert!(content.len() <= MAX_CRASH_REPORT_BYTES);
+        assert_eq!(
+            truncate_report("short".to_string()),
+            ("short".to_string(), false)
+        );
+    }
+
+    #[test]
+    fn test_validate_upload_url() {
+        assert!(validate_upload_url("https://api.mclo.gs/1/log").is_ok());
+        assert!(validate_upload_url("ftp://example.com").is_err());
+        assert!(validate_upload_url("not a url").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upload_to_private_address_is_refused() {
+        let e = upload_crash_report("http://127.0.0.1:8080/1/log", "report")
+            .await
+            .unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::BadRequest));
+    }
+}
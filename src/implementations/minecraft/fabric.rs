@@ -4,6 +4,8 @@ use serde_json::Value;
 use ts_rs::TS;
 
 use crate::error::Error;
+use crate::upstream_cache::cached_get_text;
+use crate::upstream_http;
 
 #[derive(Debug, Clone, TS, Serialize, Deserialize, PartialEq)]
 #[ts(export)]
@@ -40,14 +42,8 @@ impl From<FabricInstallerVersion> for String {
 }
 
 pub async fn get_fabric_minecraft_versions() -> Result<Vec<String>, Error> {
-    let http = reqwest::Client::new();
-
     let response: Value = serde_json::from_str(
-        http.get("https://meta.fabricmc.net/v2/versions")
-            .send()
-            .await
-            .context("Failed to get fabric versions")?
-            .text()
+        cached_get_text("https://meta.fabricmc.net/v2/versions")
             .await
             .context("Failed to get fabric versions")?
             .as_str(),
@@ -70,17 +66,8 @@ pub async fn get_fabric_minecraft_versions() -> Result<Vec<String>, Error> {
 }
 
 pub async fn get_fabric_installer_versions() -> Result<Vec<String>, Error> {
-    let http = reqwest::Client::new();
-
     let response: Value = serde_json::from_str(
-        http.get("https://meta.fabricmc.net/v2/versions/installer")
-            .send()
-            .await
-            .context("Failed to get fabric installer versions")?
-            .text()
-            .await
-            .context("Failed to get fabric installer versions")?
-            .as_str(),
+        &upstream_http::get_text("https://meta.fabricmc.net/v2/versions/installer").await?,
     )
     .context("Failed to get fabric installer versions")?;
 
@@ -100,17 +87,8 @@ pub async fn get_fabric_installer_versions() -> Result<Vec<String>, Error> {
 }
 
 pub async fn get_fabric_loader_versions() -> Result<Vec<String>, Error> {
-    let http = reqwest::Client::new();
-
     let response: Value = serde_json::from_str(
-        http.get("https://meta.fabricmc.net/v2/versions/loader")
-            .send()
-            .await
-            .context("Failed to get fabric loader versions")?
-            .text()
-            .await
-            .context("Failed to get fabric loader versions")?
-            .as_str(),
+        &upstream_http::get_text("https://meta.fabricmc.net/v2/versions/loader").await?,
     )
     .context("Failed to get fabric loader versions")?;
 
@@ -0,0 +1,192 @@
+//! Files written to an instance before its first boot, picked per flavour so that supporting a new
+//! flavour's files is a matter of adding a template here
+
+use std::path::Path;
+
+use color_eyre::eyre::Context;
+
+use crate::error::Error;
+
+use super::configurable::ServerPropertySetting;
+use super::{Flavour, SetupConfig};
+
+const EULA_TEMPLATE: &str = "#generated by Lodestone\neula=true";
+
+/// The Forge installer generates this for the JVM arguments, which are set through Lodestone instead
+const FORGE_USER_JVM_ARGS_TEMPLATE: &str =
+    "# Generated by Lodestone\n# This file is ignored by Lodestone\n# Please set arguments using Lodestone";
+
+/// When in the setup a file is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirstRunStage {
+    /// Along with the instance's directories
+    Files,
+    /// After the installer ran, as it would overwrite the file otherwise
+    AfterInstall,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirstRunFile {
+    /// Relative to the instance directory
+    pub name: &'static str,
+    pub contents: String,
+    pub stage: FirstRunStage,
+}
+
+/// The properties a server first boots with.
+///
+/// The allocated ports always take the place of whatever `server_properties` has for them
+pub fn first_run_properties(config: &SetupConfig) -> Result<String, Error> {
+    let mut properties = format!("server-port={}\n", config.port);
+    if let Some(rcon_port) = config.rcon_port {
+        properties.push_str(&format!("rcon.port={}\n", rcon_port));
+    }
+    if let Some(query_port) = config.query_port {
+        properties.push_str(&format!("query.port={}\n", query_port));
+    }
+    for (key, value) in config.server_properties.iter() {
+        if key == "server-port"
+            || (key == "rcon.port" && config.rcon_port.is_some())
+            || (key == "query.port" && config.query_port.is_some())
+        {
+            continue;
+        }
+        properties.push_str(&ServerPropertySetting::from_key_val(key, value)?.to_line());
+        properties.push('\n');
+    }
+    Ok(properties)
+}
+
+/// The files to write for the first boot of the instance set up with `config`
+pub fn first_run_files(config: &SetupConfig) -> Result<Vec<FirstRunFile>, Error> {
+    let mut files = vec![
+        FirstRunFile {
+            name: "eula.txt",
+            contents: EULA_TEMPLATE.to_string(),
+            stage: FirstRunStage::Files,
+        },
+        FirstRunFile {
+            name: "server.properties",
+            contents: first_run_properties(config)?,
+            stage: FirstRunStage::Files,
+        },
+    ];
+    if let Flavour::Forge { .. } = config.flavour {
+        files.push(FirstRunFile {
+            name: "user_jvm_args.txt",
+            contents: FORGE_USER_JVM_ARGS_TEMPLATE.to_string(),
+            stage: FirstRunStage::AfterInstall,
+        });
+    }
+    Ok(files)
+}
+
+pub async fn write_first_run_files<'a>(
+    path_to_instance: &Path,
+    files: impl Iterator<Item = &'a FirstRunFile>,
+) -> Result<(), Error> {
+    for file in files {
+        let path = path_to_instance.join(file.name);
+        tokio::fs::write(&path, &file.contents)
+            .await
+            .context(format!("Could not create {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+
+    use super::*;
+
+    fn setup_config(flavour: Flavour) -> SetupConfig {
+        SetupConfig {
+            name: "test".to_string(),
+            version: "1.20.1".to_string(),
+            flavour,
+            port: 25565,
+            cmd_args: Vec::new(),
+            description: None,
+            min_ram: None,
+            max_ram: None,
+            auto_start: None,
+            restart_on_crash: None,
+            backup_period: None,
+            max_backups: None,
+            forge_installer_timeout_secs: None,
+            server_properties: IndexMap::new(),
+            jre_vendor: None,
+            jre_version_override: None,
+            rcon_port: None,
+            query_port: None,
+            setup_timeouts: None,
+        }
+    }
+
+    fn names(files: &[FirstRunFile], stage: FirstRunStage) -> Vec<&'static str> {
+        files
+            .iter()
+            .filter(|file| file.stage == stage)
+            .map(|file| file.name)
+            .collect()
+    }
+
+    #[test]
+    fn test_first_run_files_per_flavour() {
+        for flavour in [
+            Flavour::Vanilla,
+            Flavour::Paper {
+                build_version: None,
+            },
+            Flavour::Spigot,
+        ] {
+            let files = first_run_files(&setup_config(flavour.clone())).unwrap();
+            assert_eq!(
+                names(&files, FirstRunStage::Files),
+                vec!["eula.txt", "server.properties"],
+                "{:?}",
+                flavour
+            );
+            assert!(names(&files, FirstRunStage::AfterInstall).is_empty());
+        }
+
+        let files = first_run_files(&setup_config(Flavour::Forge {
+            build_version: None,
+        }))
+        .unwrap();
+        assert_eq!(
+            names(&files, FirstRunStage::Files),
+            vec!["eula.txt", "server.properties"]
+        );
+        assert_eq!(
+            names(&files, FirstRunStage::AfterInstall),
+            vec!["user_jvm_args.txt"]
+        );
+    }
+
+    #[test]
+    fn test_first_run_properties() {
+        let mut config = setup_config(Flavour::Vanilla);
+        config.rcon_port = Some(25575);
+        config
+            .server_properties
+            .insert("server-port".to_string(), "1".to_string());
+        config
+            .server_properties
+            .insert("rcon.port".to_string(), "2".to_string());
+        config
+            .server_properties
+            .insert("difficulty".to_string(), "hard".to_string());
+        let properties = first_run_properties(&config).unwrap();
+        assert!(properties.starts_with("server-port=25565\nrcon.port=25575\n"));
+        assert!(properties.contains("difficulty=hard"));
+        assert!(!properties.contains("=1\n"));
+        assert!(!properties.contains("=2\n"));
+
+        config
+            .server_properties
+            .insert("max-players".to_string(), "ten".to_string());
+        assert!(first_run_properties(&config).is_err());
+    }
+}
@@ -1,30 +1,198 @@
-use color_eyre::eyre::Context;
+use std::path::Path;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
 use indexmap::IndexMap;
 use serde_json::Value;
+use tokio::process::Command;
+
+use super::installer::run_installer;
+use crate::error::{Error, ErrorKind};
+use crate::upstream_cache::cached_get_text;
 
-use crate::error::Error;
+/// Default time the forge installer gets to finish before it's killed
+pub const DEFAULT_FORGE_INSTALLER_TIMEOUT_SECS: u64 = 900;
 
 pub async fn get_forge_minecraft_versions() -> Result<Vec<String>, Error> {
-    let http = reqwest::Client::new();
     let response: IndexMap<String, Value> = serde_json::from_str(
-        http.get("https://files.minecraftforge.net/net/minecraftforge/forge/maven-metadata.json")
-            .send()
-            .await
-            .context("Failed to get forge versions, http request failed")?
-            .text()
-            .await
-            .context("Failed to get forge versions, text conversion failed")?
-            .as_str(),
+        cached_get_text(
+            "https://files.minecraftforge.net/net/minecraftforge/forge/maven-metadata.json",
+        )
+        .await
+        .context("Failed to get forge versions")?
+        .as_str(),
     )
     .context("Failed to get forge versions, json is not a map")?;
 
     Ok(response.into_iter().map(|(k, _)| k).rev().collect())
 }
 
+/// A step of the forge installer worth reporting, parsed from its output
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ForgeInstallerStep {
+    DownloadingLibraries,
+    Library(String),
+    BuildingProcessors,
+    Processor(String),
+}
+
+impl ForgeInstallerStep {
+    /// Parses a line of output from the forge (or neoforge) installer
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if line == "Downloading libraries" {
+            Some(Self::DownloadingLibraries)
+        } else if line == "Building Processors" {
+            Some(Self::BuildingProcessors)
+        } else if let Some(library) = line.strip_prefix("Considering library ") {
+            Some(Self::Library(library.to_string()))
+        } else {
+            line.strip_prefix("MainClass: ")
+                .map(|main_class| Self::Processor(main_class.to_string()))
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Self::DownloadingLibraries => "Downloading libraries".to_string(),
+            Self::Library(library) => format!("Downloading library {}", library),
+            Self::BuildingProcessors => "Running processors".to_string(),
+            Self::Processor(main_class) => format!("Running processor {}", main_class),
+        }
+    }
+}
+
+/// Turns installer steps into progress increments adding up to at most `total`.
+///
+/// The installer doesn't say how many libraries or processors there are,
+/// so every step takes a fraction of what's left of its phase's share.
+struct ForgeInstallerProgress {
+    libraries_left: f64,
+    processors_left: f64,
+}
+
+impl ForgeInstallerProgress {
+    const STEP_FRACTION: f64 = 0.05;
+
+    fn new(total: f64) -> Self {
+        Self {
+            libraries_left: total / 2.0,
+            processors_left: total / 2.0,
+        }
+    }
+
+    fn advance(&mut self, step: &ForgeInstallerStep) -> f64 {
+        let left = match step {
+            ForgeInstallerStep::Library(_) => &mut self.libraries_left,
+            ForgeInstallerStep::Processor(_) => &mut self.processors_left,
+            ForgeInstallerStep::BuildingProcessors => {
+                // whatever is left of the libraries' share is done now
+                return std::mem::take(&mut self.libraries_left);
+            }
+            ForgeInstallerStep::DownloadingLibraries => return 0.0,
+        };
+        let increment = *left * Self::STEP_FRACTION;
+        *left -= increment;
+        increment
+    }
+}
+
+/// Runs `forge-installer.jar` in `path_to_instance`, killing it if it doesn't finish within `timeout`.
+///
+/// `on_progress` is called with a message and a progress increment for every step the installer reports,
+/// the increments add up to at most `total_progress`.
+/// `on_heartbeat` is called periodically with the time elapsed, so that the caller can show the install is still going.
+/// The tail of the installer's output is included in the error if it fails.
+pub async fn run_forge_installer(
+    jre: &Path,
+    path_to_instance: &Path,
+    timeout: Duration,
+    total_progress: f64,
+    on_progress: impl Fn(&str, f64),
+    on_heartbeat: impl Fn(Duration),
+) -> Result<(), Error> {
+    let mut progress = ForgeInstallerProgress::new(total_progress);
+    let run = run_installer(
+        "Forge installer",
+        Command::new(jre)
+            .arg("-jar")
+            .arg(path_to_instance.join("forge-installer.jar"))
+            .arg("--installServer")
+            .arg(path_to_instance)
+            .current_dir(path_to_instance),
+        timeout,
+        |line, is_stdout| {
+            if !is_stdout {
+                return;
+            }
+            if let Some(step) = ForgeInstallerStep::parse(line) {
+                on_progress(&step.message(), progress.advance(&step));
+            }
+        },
+        on_heartbeat,
+    )
+    .await?;
+    if run.status.success() {
+        Ok(())
+    } else {
+        Err(Error {
+            kind: ErrorKind::Internal,
+            source: eyre!(
+                "Failed to install forge server, installer exited with {}. Last output:\n{}",
+                run.status,
+                run.output_tail
+            ),
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_forge_installer_progress() {
+        let output = [
+            "Extracting main jar:",
+            "Downloading libraries",
+            "Considering library cpw.mods:securejarhandler:2.1.10",
+            "  File exists: Checksum validated.",
+            "Considering library org.ow2.asm:asm:9.5",
+            "Building Processors",
+            "===============================================================================",
+            "  MainClass: net.minecraftforge.installertools.ConsoleTool",
+            "  MainClass: net.minecraftforge.jarsplitter.ConsoleTool",
+        ];
+        let steps: Vec<ForgeInstallerStep> = output
+            .iter()
+            .filter_map(|line| ForgeInstallerStep::parse(line))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                ForgeInstallerStep::DownloadingLibraries,
+                ForgeInstallerStep::Library("cpw.mods:securejarhandler:2.1.10".to_string()),
+                ForgeInstallerStep::Library("org.ow2.asm:asm:9.5".to_string()),
+                ForgeInstallerStep::BuildingProcessors,
+                ForgeInstallerStep::Processor(
+                    "net.minecraftforge.installertools.ConsoleTool".to_string()
+                ),
+                ForgeInstallerStep::Processor(
+                    "net.minecraftforge.jarsplitter.ConsoleTool".to_string()
+                ),
+            ]
+        );
+
+        let mut progress = ForgeInstallerProgress::new(1.0);
+        let mut total = 0.0;
+        for step in steps.iter().cycle().take(1000) {
+            let increment = progress.advance(step);
+            assert!(increment >= 0.0);
+            total += increment;
+        }
+        assert!(total > 0.5 && total <= 1.0 + f64::EPSILON);
+    }
+
     #[tokio::test]
     async fn test_get_forge_minecraft_versions() {
         let versions = get_forge_minecraft_versions().await.unwrap();
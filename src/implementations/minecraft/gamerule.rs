@@ -0,0 +1,307 @@
+use std::cmp::Ordering;
+use std::path::Path;
+
+use color_eyre::eyre::eyre;
+use indexmap::IndexMap;
+
+use crate::error::{Error, ErrorKind};
+use crate::traits::t_configurable::{GameRule, GameRuleType};
+use crate::traits::t_server::State;
+
+use super::nbt::{read_gzip_nbt_file, write_gzip_nbt_file, Tag};
+use super::update::{compare_dotted_versions, is_release_version};
+use super::MinecraftInstance;
+
+/// Gamerules of the vanilla game, with the release that added them
+const GAME_RULES: &[(&str, GameRuleType, &str)] = &[
+    ("commandBlockOutput", GameRuleType::Bool, "1.4.2"),
+    ("doFireTick", GameRuleType::Bool, "1.4.2"),
+    ("doMobLoot", GameRuleType::Bool, "1.4.2"),
+    ("doMobSpawning", GameRuleType::Bool, "1.4.2"),
+    ("doTileDrops", GameRuleType::Bool, "1.4.2"),
+    ("keepInventory", GameRuleType::Bool, "1.4.2"),
+    ("mobGriefing", GameRuleType::Bool, "1.4.2"),
+    ("doDaylightCycle", GameRuleType::Bool, "1.6.1"),
+    ("naturalRegeneration", GameRuleType::Bool, "1.6.1"),
+    ("logAdminCommands", GameRuleType::Bool, "1.8"),
+    ("randomTickSpeed", GameRuleType::Int, "1.8"),
+    ("reducedDebugInfo", GameRuleType::Bool, "1.8"),
+    ("sendCommandFeedback", GameRuleType::Bool, "1.8"),
+    ("showDeathMessages", GameRuleType::Bool, "1.8"),
+    ("doEntityDrops", GameRuleType::Bool, "1.8.1"),
+    ("spectatorsGenerateChunks", GameRuleType::Bool, "1.8.1"),
+    ("disableElytraMovementCheck", GameRuleType::Bool, "1.9"),
+    ("spawnRadius", GameRuleType::Int, "1.9"),
+    ("doWeatherCycle", GameRuleType::Bool, "1.11"),
+    ("maxEntityCramming", GameRuleType::Int, "1.11"),
+    ("announceAdvancements", GameRuleType::Bool, "1.12"),
+    ("doLimitedCrafting", GameRuleType::Bool, "1.12"),
+    ("maxCommandChainLength", GameRuleType::Int, "1.12"),
+    ("disableRaids", GameRuleType::Bool, "1.14.3"),
+    ("doImmediateRespawn", GameRuleType::Bool, "1.15"),
+    ("doInsomnia", GameRuleType::Bool, "1.15"),
+    ("drowningDamage", GameRuleType::Bool, "1.15"),
+    ("fallDamage", GameRuleType::Bool, "1.15"),
+    ("fireDamage", GameRuleType::Bool, "1.15"),
+    ("doPatrolSpawning", GameRuleType::Bool, "1.15.2"),
+    ("doTraderSpawning", GameRuleType::Bool, "1.15.2"),
+    ("forgiveDeadPlayers", GameRuleType::Bool, "1.16"),
+    ("universalAnger", GameRuleType::Bool, "1.16"),
+    ("freezeDamage", GameRuleType::Bool, "1.17"),
+    ("playersSleepingPercentage", GameRuleType::Int, "1.17"),
+    ("doWardenSpawning", GameRuleType::Bool, "1.19"),
+    ("blockExplosionDropDecay", GameRuleType::Bool, "1.19.3"),
+    ("globalSoundEvents", GameRuleType::Bool, "1.19.3"),
+    ("lavaSourceConversion", GameRuleType::Bool, "1.19.3"),
+    ("mobExplosionDropDecay", GameRuleType::Bool, "1.19.3"),
+    ("snowAccumulationHeight", GameRuleType::Int, "1.19.3"),
+    ("tntExplosionDropDecay", GameRuleType::Bool, "1.19.3"),
+    ("waterSourceConversion", GameRuleType::Bool, "1.19.3"),
+    ("commandModificationBlockLimit", GameRuleType::Int, "1.19.4"),
+    ("doVinesSpread", GameRuleType::Bool, "1.19.4"),
+    ("enderPearlsVanishOnDeath", GameRuleType::Bool, "1.20.2"),
+    ("maxCommandForkCount", GameRuleType::Int, "1.20.2"),
+    (
+        "playersNetherPortalCreativeDelay",
+        GameRuleType::Int,
+        "1.20.3",
+    ),
+    (
+        "playersNetherPortalDefaultDelay",
+        GameRuleType::Int,
+        "1.20.3",
+    ),
+    ("projectilesCanBreakBlocks", GameRuleType::Bool, "1.20.3"),
+    ("spawnChunkRadius", GameRuleType::Int, "1.20.5"),
+    ("disablePlayerMovementCheck", GameRuleType::Bool, "1.21.2"),
+];
+
+/// Gamerules a server of `server_version` has. Snapshots are assumed to have all of them
+fn known_game_rules(server_version: &str) -> impl Iterator<Item = (&'static str, GameRuleType)> {
+    let is_release = is_release_version(server_version);
+    let server_version = server_version.to_string();
+    GAME_RULES
+        .iter()
+        .filter(move |(_, _, added_in)| {
+            !is_release || compare_dotted_versions(&server_version, added_in) != Ordering::Less
+        })
+        .map(|(name, rule_type, _)| (*name, *rule_type))
+}
+
+/// Checks that `rule` exists in `server_version` and that `value` is of its type
+fn validate_game_rule(rule: &str, value: &str, server_version: &str) -> Result<GameRule, Error> {
+    let rule_type = known_game_rules(server_version)
+        .find(|(name, _)| *name == rule)
+        .map(|(_, rule_type)| rule_type)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Unknown gamerule {} for version {}", rule, server_version),
+        })?;
+    let value = value.trim();
+    let value = match rule_type {
+        GameRuleType::Bool => value
+            .parse::<bool>()
+            .map_err(|_| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Gamerule {} expects true or false, got {}", rule, value),
+            })?
+            .to_string(),
+        GameRuleType::Int => value
+            .parse::<i32>()
+            .map_err(|_| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Gamerule {} expects an integer, got {}", rule, value),
+            })?
+            .to_string(),
+    };
+    Ok(GameRule {
+        name: rule.to_string(),
+        rule_type,
+        value,
+    })
+}
+
+/// The value in the server's answer to `gamerule <rule>`, e.g. `Gamerule keepInventory is currently set to: false`
+fn parse_game_rule_query(response: &str) -> Option<String> {
+    response
+        .split_once("is currently set to:")
+        .map(|(_, value)| value.trim().to_string())
+}
+
+/// Sets a gamerule in the `level.dat` of the world at `path_to_world`. This blocks, run it with `spawn_blocking`
+fn write_level_dat_game_rule(path_to_world: &Path, game_rule: &GameRule) -> Result<(), Error> {
+    let path_to_level_dat = path_to_world.join("level.dat");
+    if !path_to_level_dat.is_file() {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("The world hasn't been generated yet"),
+        });
+    }
+    let mut level_dat = read_gzip_nbt_file(&path_to_level_dat)?;
+    let data = match level_dat.get_mut("Data") {
+        Some(Tag::Compound(data)) => data,
+        _ => {
+            return Err(Error {
+                kind: ErrorKind::Internal,
+                source: eyre!("level.dat has no Data compound"),
+            })
+        }
+    };
+    let game_rules = data
+        .entry("GameRules".to_string())
+        .or_insert_with(|| Tag::Compound(IndexMap::new()));
+    match game_rules {
+        // gamerules are stored as strings, whatever their type
+        Tag::Compound(game_rules) => {
+            game_rules.insert(game_rule.name.clone(), Tag::String(game_rule.value.clone()));
+        }
+        _ => {
+            return Err(Error {
+                kind: ErrorKind::Internal,
+                source: eyre!("GameRules in level.dat is not a compound"),
+            })
+        }
+    }
+    write_gzip_nbt_file(&path_to_level_dat, &level_dat)
+}
+
+impl MinecraftInstance {
+    /// Gamerules of the running server over RCON, or of the world's `level.dat` otherwise
+    pub(super) async fn read_game_rules(&self) -> Result<Vec<GameRule>, Error> {
+        let server_version = self.config.lock().await.version.clone();
+        if *self.state.lock().await == State::Running {
+            let mut ret = Vec::new();
+            for (name, rule_type) in known_game_rules(&server_version) {
+                let response = self.query_rcon(&format!("gamerule {name}")).await?;
+                // the server may not have a rule, e.g. if it's an older snapshot
+                if let Some(value) = parse_game_rule_query(&response) {
+                    ret.push(GameRule {
+                        name: name.to_string(),
+                        rule_type,
+                        value,
+                    });
+                }
+            }
+            return Ok(ret);
+        }
+        let world_info = self.read_world_info().await?;
+        Ok(known_game_rules(&server_version)
+            .filter_map(|(name, rule_type)| {
+                Some(GameRule {
+                    name: name.to_string(),
+                    rule_type,
+                    value: world_info.game_rules.get(name)?.clone(),
+                })
+            })
+            .collect())
+    }
+
+    /// Sets a gamerule over RCON if the server is running, or in the world's `level.dat` if it's stopped
+    pub(super) async fn write_game_rule(&self, rule: &str, value: &str) -> Result<GameRule, Error> {
+        let server_version = self.config.lock().await.version.clone();
+        let game_rule = validate_game_rule(rule, value, &server_version)?;
+        let state = *self.state.lock().await;
+        match state {
+            State::Running => {
+                let response = self
+                    .query_rcon(&format!("gamerule {} {}", game_rule.name, game_rule.value))
+                    .await?;
+                if !response.contains("is now set to") {
+                    return Err(Error {
+                        kind: ErrorKind::Internal,
+                        source: eyre!("The server didn't set the gamerule: {}", response),
+                    });
+                }
+            }
+            State::Stopped => {
+                let path_to_world = self.path_to_world().await;
+                let game_rule = game_rule.clone();
+                tokio::task::spawn_blocking(move || {
+                    write_level_dat_game_rule(&path_to_world, &game_rule)
+                })
+                .await
+                .map_err(|e| eyre!("Writing gamerule panicked: {}", e))??;
+            }
+            _ => {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!(
+                        "Cannot change gamerules while the server is {}",
+                        state.to_string().to_lowercase()
+                    ),
+                })
+            }
+        }
+        Ok(game_rule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_game_rule() {
+        let game_rule = validate_game_rule("keepInventory", " true", "1.20.1").unwrap();
+        assert_eq!(game_rule.rule_type, GameRuleType::Bool);
+        assert_eq!(game_rule.value, "true");
+        assert_eq!(
+            validate_game_rule("randomTickSpeed", "3", "1.20.1")
+                .unwrap()
+                .rule_type,
+            GameRuleType::Int
+        );
+
+        // wrong types
+        assert!(validate_game_rule("keepInventory", "1", "1.20.1").is_err());
+        assert!(validate_game_rule("randomTickSpeed", "fast", "1.20.1").is_err());
+        // unknown rules and rules newer than the server
+        assert!(validate_game_rule("keepInventroy", "true", "1.20.1").is_err());
+        assert!(validate_game_rule("freezeDamage", "true", "1.16.5").is_err());
+        assert!(validate_game_rule("freezeDamage", "true", "1.17").is_ok());
+        assert!(validate_game_rule("spawnChunkRadius", "2", "24w09a").is_ok());
+    }
+
+    #[test]
+    fn test_parse_game_rule_query() {
+        assert_eq!(
+            parse_game_rule_query("Gamerule keepInventory is currently set to: false").as_deref(),
+            Some("false")
+        );
+        assert_eq!(
+            parse_game_rule_query("Incorrect argument for command"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_write_level_dat_game_rule() {
+        let temp_dir = tempdir::TempDir::new("test_write_level_dat_game_rule").unwrap();
+        let game_rule = validate_game_rule("keepInventory", "true", "1.20.1").unwrap();
+        assert!(matches!(
+            write_level_dat_game_rule(temp_dir.path(), &game_rule),
+            Err(Error {
+                kind: ErrorKind::NotFound,
+                ..
+            })
+        ));
+
+        let mut data = IndexMap::new();
+        data.insert("LevelName".to_string(), Tag::String("world".to_string()));
+        let mut root = IndexMap::new();
+        root.insert("Data".to_string(), Tag::Compound(data));
+        let path_to_level_dat = temp_dir.path().join("level.dat");
+        write_gzip_nbt_file(&path_to_level_dat, &Tag::Compound(root)).unwrap();
+
+        write_level_dat_game_rule(temp_dir.path(), &game_rule).unwrap();
+        let level_dat = read_gzip_nbt_file(&path_to_level_dat).unwrap();
+        let data = level_dat.get("Data").unwrap();
+        assert_eq!(data.get("LevelName").and_then(Tag::as_str), Some("world"));
+        assert_eq!(
+            data.get("GameRules")
+                .and_then(|game_rules| game_rules.get("keepInventory"))
+                .and_then(Tag::as_str),
+            Some("true")
+        );
+    }
+}
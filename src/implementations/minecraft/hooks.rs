@@ -0,0 +1,315 @@
+//! Macros attached to points of the lifecycle of an instance, e.g. syncing the world before it
+//! starts or sending a notification after it stops
+
+use std::path::Path;
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::macro_executor::{MacroExecutor, MacroPID, SpawnResult, WorkerOptionGenerator};
+use crate::traits::t_macro::{ExitStatus, TaskEntry};
+use crate::types::{InstanceUuid, Snowflake};
+
+use super::r#macro::{resolve_macro_invocation, MinecraftMainWorkerGenerator};
+use super::MinecraftInstance;
+
+/// Default for how long a start or stop waits for the hook running before it
+pub const DEFAULT_HOOK_TIMEOUT_SECS: u32 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub enum LifecycleHook {
+    BeforeStart,
+    /// Once the server is ready
+    AfterStart,
+    BeforeStop,
+    /// Whenever the server exits, including crashes
+    AfterStop,
+}
+
+impl LifecycleHook {
+    /// Whether the transition waits for the hook to finish
+    pub fn is_blocking(&self) -> bool {
+        matches!(self, LifecycleHook::BeforeStart | LifecycleHook::BeforeStop)
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            LifecycleHook::BeforeStart => "before start",
+            LifecycleHook::AfterStart => "after start",
+            LifecycleHook::BeforeStop => "before stop",
+            LifecycleHook::AfterStop => "after stop",
+        }
+    }
+}
+
+/// The macros run at each point of the lifecycle, by name
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct LifecycleHooks {
+    #[serde(default)]
+    pub before_start: Option<String>,
+    #[serde(default)]
+    pub after_start: Option<String>,
+    #[serde(default)]
+    pub before_stop: Option<String>,
+    #[serde(default)]
+    pub after_stop: Option<String>,
+    /// Start the server even if the `before_start` hook fails, instead of aborting the start
+    #[serde(default)]
+    pub start_despite_failure: bool,
+    /// Seconds a start or stop waits for the hook running before it,
+    /// `DEFAULT_HOOK_TIMEOUT_SECS` if not set
+    #[serde(default)]
+    pub timeout_secs: Option<u32>,
+}
+
+impl LifecycleHooks {
+    pub fn macro_name(&self, hook: LifecycleHook) -> Option<&str> {
+        match hook {
+            LifecycleHook::BeforeStart => self.before_start.as_deref(),
+            LifecycleHook::AfterStart => self.after_start.as_deref(),
+            LifecycleHook::BeforeStop => self.before_stop.as_deref(),
+            LifecycleHook::AfterStop => self.after_stop.as_deref(),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs.unwrap_or(DEFAULT_HOOK_TIMEOUT_SECS) as u64)
+    }
+
+    /// Rejects hooks whose macro isn't in `path_to_macros`
+    pub fn validate(&self, path_to_macros: &Path) -> Result<(), Error> {
+        if self.timeout_secs == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Hook timeout must be at least 1 second"),
+            });
+        }
+        for hook in [
+            LifecycleHook::BeforeStart,
+            LifecycleHook::AfterStart,
+            LifecycleHook::BeforeStop,
+            LifecycleHook::AfterStop,
+        ] {
+            if let Some(macro_name) = self.macro_name(hook) {
+                if resolve_macro_invocation(path_to_macros, macro_name).is_none() {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!(
+                            "There is no macro named {} for the {} hook",
+                            macro_name,
+                            hook.describe()
+                        ),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the start goes ahead after the `before_start` hook finished with `result`
+    pub fn gate_start(&self, result: Result<(), Error>) -> Result<(), Error> {
+        match result {
+            Err(e) if !self.start_despite_failure => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Runs the macro `macro_name` and waits for it to exit, failing unless it succeeds within
+/// `timeout`. A macro that times out is aborted
+pub async fn run_hook_macro(
+    macro_executor: &MacroExecutor,
+    path_to_macros: &Path,
+    macro_name: &str,
+    worker_generator: Box<dyn WorkerOptionGenerator>,
+    instance_uuid: Option<InstanceUuid>,
+    timeout: Option<Duration>,
+    task_entries: &Mutex<IndexMap<MacroPID, TaskEntry>>,
+) -> Result<(), Error> {
+    let path_to_macro =
+        resolve_macro_invocation(path_to_macros, macro_name).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("There is no macro named {}", macro_name),
+        })?;
+    let SpawnResult {
+        macro_pid: pid,
+        exit_future,
+        ..
+    } = macro_executor
+        .spawn(
+            path_to_macro,
+            Vec::new(),
+            CausedBy::System,
+            worker_generator,
+            None,
+            instance_uuid,
+            timeout,
+        )
+        .await?;
+    task_entries.lock().await.insert(
+        pid,
+        TaskEntry {
+            pid,
+            name: macro_name.to_string(),
+            creation_time: chrono::Utc::now().timestamp(),
+        },
+    );
+    match exit_future.await {
+        Ok(ExitStatus::Success { .. }) => Ok(()),
+        Ok(ExitStatus::Killed { .. }) => Err(eyre!("Macro {} was killed", macro_name).into()),
+        Ok(ExitStatus::Error { error_msg, .. }) => {
+            Err(eyre!("Macro {} failed: {}", macro_name, error_msg).into())
+        }
+        Err(_) => {
+            let _ = macro_executor.abort_macro(pid);
+            Err(eyre!(
+                "Macro {} didn't finish within {} seconds",
+                macro_name,
+                timeout.unwrap_or_default().as_secs()
+            )
+            .into())
+        }
+    }
+}
+
+impl MinecraftInstance {
+    /// Runs the macro attached to `hook`, if any. Blocking hooks are waited for, the others run in
+    /// the background. Failures are reported as instance errors
+    pub(super) async fn run_hook(&self, hook: LifecycleHook) -> Result<(), Error> {
+        let hooks = self.config.lock().await.hooks.clone();
+        let macro_name = match hooks.macro_name(hook) {
+            Some(macro_name) => macro_name.to_string(),
+            None => return Ok(()),
+        };
+        let instance = self.clone();
+        let run = async move {
+            let result = run_hook_macro(
+                &instance.macro_executor,
+                &instance.path_to_macros,
+                &macro_name,
+                Box::new(MinecraftMainWorkerGenerator::new(instance.clone())),
+                Some(instance.uuid.clone()),
+                hook.is_blocking().then(|| hooks.timeout()),
+                &instance.pid_to_task_entry,
+            )
+            .await;
+            if let Err(e) = &result {
+                instance.report_hook_failure(hook, e).await;
+            }
+            result
+        };
+        if hook.is_blocking() {
+            run.await
+        } else {
+            tokio::spawn(run);
+            Ok(())
+        }
+    }
+
+    async fn report_hook_failure(&self, hook: LifecycleHook, e: &Error) {
+        let name = self.config.lock().await.name.clone();
+        let message = format!("The {} hook failed: {}", hook.describe(), e.source);
+        warn!("[{}] {}", name, message);
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: self.uuid.clone(),
+                instance_name: name,
+                instance_event_inner: InstanceEventInner::InstanceError { message },
+            }),
+            snowflake: Snowflake::default(),
+            details: "".to_string(),
+            caused_by: CausedBy::System,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::event_broadcaster::EventBroadcaster;
+    use crate::macro_executor::TypescriptModuleLoader;
+
+    struct HookWorkerGenerator;
+
+    impl WorkerOptionGenerator for HookWorkerGenerator {
+        fn generate(&self) -> deno_runtime::worker::WorkerOptions {
+            deno_runtime::worker::WorkerOptions {
+                module_loader: Rc::new(TypescriptModuleLoader::default()),
+                ..Default::default()
+            }
+        }
+    }
+
+    async fn run_before_start(hooks: &LifecycleHooks, macro_content: &str) -> Result<(), Error> {
+        let (event_broadcaster, _rx) = EventBroadcaster::new(10);
+        let macro_executor = MacroExecutor::new(event_broadcaster);
+        let temp_dir = tempdir::TempDir::new("test_hooks").unwrap();
+        std::fs::write(temp_dir.path().join("sync_world.ts"), macro_content).unwrap();
+        hooks.validate(temp_dir.path())?;
+        let result = run_hook_macro(
+            &macro_executor,
+            temp_dir.path(),
+            hooks.macro_name(LifecycleHook::BeforeStart).unwrap(),
+            Box::new(HookWorkerGenerator),
+            None,
+            Some(hooks.timeout()),
+            &Mutex::new(IndexMap::new()),
+        )
+        .await;
+        hooks.gate_start(result)
+    }
+
+    fn hooks() -> LifecycleHooks {
+        LifecycleHooks {
+            before_start: Some("sync_world".to_string()),
+            timeout_secs: Some(2),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failing_before_start_hook_aborts_start() {
+        let failing = "throw new Error(\"sync failed\");";
+        assert!(run_before_start(&hooks(), failing).await.is_err());
+
+        let hooks = LifecycleHooks {
+            start_despite_failure: true,
+            ..hooks()
+        };
+        assert!(run_before_start(&hooks, failing).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_before_start_hook_timing_out_aborts_start() {
+        let slow = "await new Promise((resolve) => setTimeout(resolve, 10000));";
+        assert!(run_before_start(&hooks(), slow).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_successful_before_start_hook_starts() {
+        assert!(run_before_start(&hooks(), "console.log(\"synced\");")
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn test_validate_hooks() {
+        let temp_dir = tempdir::TempDir::new("test_validate_hooks").unwrap();
+        assert!(matches!(
+            hooks().validate(temp_dir.path()).unwrap_err().kind,
+            ErrorKind::BadRequest
+        ));
+        assert!(LifecycleHooks::default().validate(temp_dir.path()).is_ok());
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::time::Instant;
+
+use crate::error::{Error, ErrorKind};
+use crate::util::dont_spawn_terminal;
+
+/// Number of installer output lines attached to an error
+const INSTALLER_OUTPUT_TAIL_LINES: usize = 30;
+const INSTALLER_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// An installer that ran to completion, successfully or not
+pub(super) struct InstallerRun {
+    pub status: ExitStatus,
+    /// The last lines of its output, stdout and stderr interleaved
+    pub output_tail: String,
+}
+
+fn push_output_line(output_tail: &mut VecDeque<String>, line: String) {
+    if output_tail.len() == INSTALLER_OUTPUT_TAIL_LINES {
+        output_tail.pop_front();
+    }
+    output_tail.push_back(line);
+}
+
+/// Runs an installer like BuildTools or the forge installer, killing it if it doesn't finish within `timeout`.
+///
+/// `on_line` is called with every line of output, and whether it was printed to stdout.
+/// `on_heartbeat` is called periodically with the time elapsed, so that the caller can show the install is still going.
+/// `name` names the installer in errors, the tail of its output is included if it times out
+pub(super) async fn run_installer(
+    name: &str,
+    command: &mut Command,
+    timeout: Duration,
+    mut on_line: impl FnMut(&str, bool),
+    on_heartbeat: impl Fn(Duration),
+) -> Result<InstallerRun, Error> {
+    let mut installer = dont_spawn_terminal(command)
+        .stderr(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .context(format!("Failed to start {}", name))?;
+    let mut stdout = BufReader::new(
+        installer
+            .stdout
+            .take()
+            .ok_or_else(|| eyre!("Failed to take stdout of {}", name))?,
+    )
+    .lines();
+    let mut stderr = BufReader::new(
+        installer
+            .stderr
+            .take()
+            .ok_or_else(|| eyre!("Failed to take stderr of {}", name))?,
+    )
+    .lines();
+
+    let start = Instant::now();
+    let deadline = tokio::time::sleep_until(start + timeout);
+    tokio::pin!(deadline);
+    let mut heartbeat = tokio::time::interval_at(
+        start + INSTALLER_HEARTBEAT_INTERVAL,
+        INSTALLER_HEARTBEAT_INTERVAL,
+    );
+    let mut output_tail: VecDeque<String> = VecDeque::with_capacity(INSTALLER_OUTPUT_TAIL_LINES);
+    let (mut stdout_done, mut stderr_done) = (false, false);
+    let mut exit_status = None;
+    // keep reading after the installer exits until both pipes are drained
+    loop {
+        if let (Some(status), true, true) = (exit_status, stdout_done, stderr_done) {
+            return Ok(InstallerRun {
+                status,
+                output_tail: Vec::from(output_tail).join("\n"),
+            });
+        }
+        tokio::select! {
+            line = stdout.next_line(), if !stdout_done => match line {
+                Ok(Some(line)) => {
+                    on_line(&line, true);
+                    push_output_line(&mut output_tail, line);
+                }
+                _ => stdout_done = true,
+            },
+            line = stderr.next_line(), if !stderr_done => match line {
+                Ok(Some(line)) => {
+                    on_line(&line, false);
+                    push_output_line(&mut output_tail, line);
+                }
+                _ => stderr_done = true,
+            },
+            status = installer.wait(), if exit_status.is_none() => {
+                exit_status = Some(status.context(format!("Failed to wait for {}", name))?);
+            }
+            _ = heartbeat.tick() => on_heartbeat(start.elapsed()),
+            _ = &mut deadline => {
+                let _ = installer.kill().await;
+                return Err(Error {
+                    kind: ErrorKind::Internal,
+                    source: eyre!(
+                        "{} did not finish within {} seconds and was killed. Last output:\n{}",
+                        name,
+                        timeout.as_secs(),
+                        Vec::from(output_tail).join("\n")
+                    ),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_installer() {
+        let mut lines = Vec::new();
+        let run = run_installer(
+            "Test installer",
+            Command::new("sh")
+                .arg("-c")
+                .arg("echo out; echo err >&2; exit 3"),
+            Duration::from_secs(10),
+            |line, is_stdout| lines.push((line.to_string(), is_stdout)),
+            |_| {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(run.status.code(), Some(3));
+        assert!(lines.contains(&("out".to_string(), true)));
+        assert!(lines.contains(&("err".to_string(), false)));
+        assert!(run.output_tail.contains("out") && run.output_tail.contains("err"));
+
+        let timed_out = run_installer(
+            "Test installer",
+            Command::new("sh").arg("-c").arg("echo started; sleep 10"),
+            Duration::from_millis(500),
+            |_, _| {},
+            |_| {},
+        )
+        .await
+        .unwrap_err();
+        assert!(timed_out.source.to_string().contains("did not finish"));
+    }
+}
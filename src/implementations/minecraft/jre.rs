@@ -0,0 +1,212 @@
+//! The JREs downloaded to the runtimes directory, which instances of the same Java version share
+
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use ts_rs::TS;
+
+use crate::disk_quota::measure_dir;
+use crate::types::InstanceUuid;
+use crate::util::{dont_spawn_terminal, list_dir};
+
+use super::util::{jre_java_path, jre_override_major_version, JreDownload};
+
+const JAVA_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct InstalledJre {
+    /// Name of the directory of the JRE under `path_to_runtimes/java`
+    pub name: String,
+    /// `None` if it couldn't be told from the JRE's release file or name
+    pub major_version: Option<u64>,
+    /// Its java executable is there and runs
+    pub verified: bool,
+    pub size_bytes: u64,
+    /// The instances launched with this JRE
+    pub used_by: Vec<InstanceUuid>,
+}
+
+/// Runs `java -version`, failing with why it didn't run
+pub async fn check_java_runs(java: &Path) -> Result<(), String> {
+    let mut command = Command::new(java);
+    command
+        .arg("-version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    let result = tokio::time::timeout(
+        JAVA_CHECK_TIMEOUT,
+        dont_spawn_terminal(&mut command).status(),
+    )
+    .await;
+    match result {
+        Ok(Ok(status)) if status.success() => Ok(()),
+        Ok(Ok(status)) => Err(format!("exited with {}", status)),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("did not respond within {:?}", JAVA_CHECK_TIMEOUT)),
+    }
+}
+
+/// The major version in a `JAVA_VERSION` of a release file, e.g. `17.0.7` or `1.8.0_372`
+fn release_major_version(release: &str) -> Option<u64> {
+    let version = release
+        .lines()
+        .find_map(|line| line.strip_prefix("JAVA_VERSION="))?
+        .trim()
+        .trim_matches('"');
+    match version.strip_prefix("1.") {
+        Some(legacy) => jre_override_major_version(legacy),
+        None => jre_override_major_version(version),
+    }
+}
+
+/// The major version of the JRE in `path_to_jre`, from the release file JREs ship with, or else
+/// from the name it was installed under, e.g. `jre17` or `zulu-17.0.7`
+async fn jre_major_version(path_to_jre: &Path, java: &Path) -> Option<u64> {
+    // the release file is next to bin, which is nested in the macOS bundles
+    let java_home = java.parent().and_then(|bin| bin.parent());
+    for path_to_release in [Some(path_to_jre), java_home]
+        .into_iter()
+        .flatten()
+        .map(|dir| dir.join("release"))
+    {
+        if let Ok(release) = tokio::fs::read_to_string(&path_to_release).await {
+            if let Some(major_version) = release_major_version(&release) {
+                return Some(major_version);
+            }
+        }
+    }
+    let name = path_to_jre.file_name()?.to_string_lossy().to_string();
+    match name.strip_prefix("jre") {
+        Some(version) => jre_override_major_version(version),
+        None => jre_override_major_version(name.split_once('-')?.1),
+    }
+}
+
+/// The JREs in `path_to_runtimes`, without the instances using them
+pub async fn list_installed_jres(path_to_runtimes: &Path) -> Vec<InstalledJre> {
+    let path_to_java = path_to_runtimes.join("java");
+    let mut jres = Vec::new();
+    for path_to_jre in list_dir(&path_to_java, Some(true))
+        .await
+        .unwrap_or_default()
+    {
+        let name = match path_to_jre.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => continue,
+        };
+        let java = jre_java_path(path_to_runtimes, &name);
+        jres.push(InstalledJre {
+            major_version: jre_major_version(&path_to_jre, &java).await,
+            verified: java.is_file() && check_java_runs(&java).await.is_ok(),
+            size_bytes: measure_dir(&path_to_jre).await.unwrap_or(0),
+            used_by: Vec::new(),
+            name,
+        });
+    }
+    jres.sort_by(|a, b| a.name.cmp(&b.name));
+    jres
+}
+
+/// An installed JRE to use instead of downloading `jre_download`, which is of the same Java version
+/// and verified to run.
+///
+/// `None` if the JRE to download is installed already, as it is used as is
+pub async fn reusable_jre(path_to_runtimes: &Path, jre_download: &JreDownload) -> Option<String> {
+    if jre_java_path(path_to_runtimes, &jre_download.dir_name).is_file() {
+        return None;
+    }
+    list_installed_jres(path_to_runtimes)
+        .await
+        .into_iter()
+        .find(|jre| jre.verified && jre.major_version == Some(jre_download.major_version))
+        .map(|jre| jre.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::implementations::minecraft::util::jre_needs_download;
+
+    /// A JRE whose java exits with `exit_code`
+    #[cfg(unix)]
+    fn install_fake_jre(path_to_runtimes: &Path, name: &str, java_version: &str, exit_code: i32) {
+        use std::os::unix::fs::PermissionsExt;
+        let path_to_jre = path_to_runtimes.join("java").join(name);
+        std::fs::create_dir_all(path_to_jre.join("bin")).unwrap();
+        std::fs::write(
+            path_to_jre.join("release"),
+            format!("IMPLEMENTOR=\"Test\"\nJAVA_VERSION=\"{java_version}\"\n"),
+        )
+        .unwrap();
+        let java = jre_java_path(path_to_runtimes, name);
+        std::fs::create_dir_all(java.parent().unwrap()).unwrap();
+        std::fs::write(&java, format!("#!/bin/sh\nexit {exit_code}\n")).unwrap();
+        std::fs::set_permissions(&java, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    fn temurin(major_version: u64) -> JreDownload {
+        JreDownload {
+            url: "https://api.adoptium.net/jre".to_string(),
+            major_version,
+            dir_name: format!("jre{major_version}"),
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_list_installed_jres() {
+        let temp_dir = tempdir::TempDir::new("test_jres").unwrap();
+        install_fake_jre(temp_dir.path(), "jre17", "17.0.7", 0);
+        install_fake_jre(temp_dir.path(), "zulu-8", "1.8.0_372", 1);
+        let jres = list_installed_jres(temp_dir.path()).await;
+        assert_eq!(jres.len(), 2);
+        assert_eq!(jres[0].name, "jre17");
+        assert_eq!(jres[0].major_version, Some(17));
+        assert!(jres[0].verified);
+        assert!(jres[0].size_bytes > 0);
+        assert_eq!(jres[1].major_version, Some(8));
+        assert!(!jres[1].verified);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_present_verified_jre_skips_download() {
+        let temp_dir = tempdir::TempDir::new("test_jres").unwrap();
+        install_fake_jre(temp_dir.path(), "zulu-17", "17.0.8", 0);
+
+        // the JRE of another vendor is of the same Java version, so it's used instead
+        let reused = reusable_jre(temp_dir.path(), &temurin(17)).await.unwrap();
+        assert_eq!(reused, "zulu-17");
+        assert!(!jre_needs_download(temp_dir.path(), &reused).await.unwrap());
+
+        // the requested JRE itself is there already
+        install_fake_jre(temp_dir.path(), "jre17", "17.0.7", 0);
+        assert_eq!(reusable_jre(temp_dir.path(), &temurin(17)).await, None);
+        assert!(!jre_needs_download(temp_dir.path(), "jre17").await.unwrap());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unverified_or_other_version_jre_is_not_reused() {
+        let temp_dir = tempdir::TempDir::new("test_jres").unwrap();
+        install_fake_jre(temp_dir.path(), "broken-21", "21.0.1", 1);
+        install_fake_jre(temp_dir.path(), "jre17", "17.0.7", 0);
+        assert_eq!(reusable_jre(temp_dir.path(), &temurin(21)).await, None);
+        assert!(jre_needs_download(temp_dir.path(), "jre21").await.unwrap());
+    }
+
+    #[test]
+    fn test_release_major_version() {
+        assert_eq!(
+            release_major_version("JAVA_VERSION=\"17.0.7\"\nOS_NAME=\"Linux\""),
+            Some(17)
+        );
+        assert_eq!(release_major_version("JAVA_VERSION=\"1.8.0_372\""), Some(8));
+        assert_eq!(release_major_version("OS_NAME=\"Linux\""), None);
+    }
+}
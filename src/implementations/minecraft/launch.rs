@@ -0,0 +1,300 @@
+//! How the server process is launched, either as `java -jar` or through a script that starts the
+//! JVM itself, like the run scripts Forge and NeoForge generate
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::util::scoped_join_win_safe;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+#[serde(tag = "type")]
+pub enum LaunchMode {
+    /// `java -jar` with the server jar of the flavour
+    #[default]
+    JavaJar,
+    /// A script in the instance directory, which is passed the JVM arguments through the
+    /// environment
+    Script { path: String, args: Vec<String> },
+    /// The `run.sh` or `run.bat` generated by the Forge and NeoForge installers
+    ForgeRunScript,
+}
+
+fn forge_run_script_name() -> &'static str {
+    match std::env::consts::OS {
+        "windows" => "run.bat",
+        _ => "run.sh",
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &std::fs::Metadata) -> bool {
+    true
+}
+
+impl LaunchMode {
+    /// The script launched instead of java, `None` for `JavaJar`
+    fn script(&self) -> Option<&str> {
+        match self {
+            LaunchMode::JavaJar => None,
+            LaunchMode::Script { path, .. } => Some(path),
+            LaunchMode::ForgeRunScript => Some(forge_run_script_name()),
+        }
+    }
+
+    /// Fails unless the script of the mode is an executable file in the instance directory
+    pub fn validate(&self, path_to_instance: &Path) -> Result<(), Error> {
+        let script = match self.script() {
+            Some(script) => script,
+            None => return Ok(()),
+        };
+        let bad_request = |message: String| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(message),
+        };
+        let path = scoped_join_win_safe(path_to_instance, script)
+            .map_err(|_| bad_request(format!("{} is outside the instance directory", script)))?;
+        let metadata = std::fs::metadata(&path)
+            .map_err(|_| bad_request(format!("Launch script {} does not exist", script)))?;
+        if !metadata.is_file() {
+            return Err(bad_request(format!(
+                "Launch script {} is not a file",
+                script
+            )));
+        }
+        if !is_executable(&metadata) {
+            return Err(bad_request(format!(
+                "Launch script {} is not executable",
+                script
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The program and arguments the server process is started with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchCommand {
+    pub program: PathBuf,
+    pub args: Vec<OsString>,
+    /// Set before the instance's own environment variables, which can override them
+    pub envs: Vec<(String, OsString)>,
+}
+
+impl LaunchCommand {
+    /// `jvm_args` are the RAM and other JVM flags, `jar_args` what `java` is told to run in
+    /// `JavaJar` mode, e.g. `-jar server.jar`, and `program_args` what the server is passed after
+    /// it, e.g. `nogui`.
+    ///
+    /// Scripts get the JVM flags through `JDK_JAVA_OPTIONS`, or `_JAVA_OPTIONS` before Java 9,
+    /// and find the instance's Java first on the `PATH`
+    pub fn new(
+        mode: &LaunchMode,
+        path_to_instance: &Path,
+        java: &Path,
+        jre_major_version: u64,
+        jvm_args: Vec<String>,
+        jar_args: Vec<OsString>,
+        program_args: Vec<String>,
+    ) -> Result<Self, Error> {
+        let (script, script_args) = match mode {
+            LaunchMode::JavaJar => {
+                let mut args: Vec<OsString> = jvm_args.into_iter().map(OsString::from).collect();
+                args.extend(jar_args);
+                args.extend(program_args.into_iter().map(OsString::from));
+                return Ok(Self {
+                    program: java.to_path_buf(),
+                    args,
+                    envs: Vec::new(),
+                });
+            }
+            LaunchMode::Script { path, args } => (path.as_str(), args.clone()),
+            LaunchMode::ForgeRunScript => (forge_run_script_name(), program_args),
+        };
+        mode.validate(path_to_instance)?;
+
+        let java_options_var = if jre_major_version >= 9 {
+            "JDK_JAVA_OPTIONS"
+        } else {
+            "_JAVA_OPTIONS"
+        };
+        let mut envs = vec![(java_options_var.to_string(), jvm_args.join(" ").into())];
+        // `java` alone is looked up on the PATH already
+        if let Some(java_bin) = java.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            let path = std::env::var_os("PATH").unwrap_or_default();
+            let path = std::env::join_paths(
+                std::iter::once(java_bin.to_path_buf()).chain(std::env::split_paths(&path)),
+            )
+            .map_err(|e| eyre!("Failed to add {} to the PATH: {}", java_bin.display(), e))?;
+            envs.push(("PATH".to_string(), path));
+            if let Some(java_home) = java_bin.parent() {
+                envs.push(("JAVA_HOME".to_string(), java_home.as_os_str().to_owned()));
+            }
+        }
+        Ok(Self {
+            program: scoped_join_win_safe(path_to_instance, script)?,
+            args: script_args.into_iter().map(OsString::from).collect(),
+            envs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jvm_args() -> Vec<String> {
+        vec![
+            "-Xmx2048M".to_string(),
+            "-Xms1024M".to_string(),
+            "-XX:+UseG1GC".to_string(),
+        ]
+    }
+
+    fn env<'a>(command: &'a LaunchCommand, key: &str) -> Option<&'a OsString> {
+        command
+            .envs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value)
+    }
+
+    #[cfg(unix)]
+    fn write_script(dir: &Path, name: &str, executable: bool) {
+        use std::os::unix::fs::PermissionsExt;
+        let path = dir.join(name);
+        std::fs::write(&path, "#!/bin/sh\nexec java \"$@\"\n").unwrap();
+        let mode = if executable { 0o755 } else { 0o644 };
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[test]
+    fn test_java_jar_command() {
+        let temp_dir = tempdir::TempDir::new("test_launch").unwrap();
+        let jar = temp_dir.path().join("server.jar");
+        let command = LaunchCommand::new(
+            &LaunchMode::JavaJar,
+            temp_dir.path(),
+            Path::new("/jre17/bin/java"),
+            17,
+            jvm_args(),
+            vec!["-jar".into(), jar.clone().into()],
+            vec!["nogui".to_string()],
+        )
+        .unwrap();
+        assert_eq!(command.program, PathBuf::from("/jre17/bin/java"));
+        let mut expected: Vec<OsString> = jvm_args().into_iter().map(OsString::from).collect();
+        expected.extend(["-jar".into(), jar.into(), "nogui".into()]);
+        assert_eq!(command.args, expected);
+        assert!(command.envs.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_script_command() {
+        let temp_dir = tempdir::TempDir::new("test_launch").unwrap();
+        std::fs::create_dir(temp_dir.path().join("scripts")).unwrap();
+        write_script(&temp_dir.path().join("scripts"), "start.sh", true);
+        let mode = LaunchMode::Script {
+            path: "scripts/start.sh".to_string(),
+            args: vec!["--port".to_string(), "25565".to_string()],
+        };
+        let command = LaunchCommand::new(
+            &mode,
+            temp_dir.path(),
+            Path::new("/jre17/bin/java"),
+            17,
+            jvm_args(),
+            Vec::new(),
+            vec!["nogui".to_string()],
+        )
+        .unwrap();
+        assert_eq!(command.program, temp_dir.path().join("scripts/start.sh"));
+        assert_eq!(
+            command.args,
+            vec![OsString::from("--port"), OsString::from("25565")]
+        );
+        assert_eq!(
+            env(&command, "JDK_JAVA_OPTIONS").unwrap(),
+            "-Xmx2048M -Xms1024M -XX:+UseG1GC"
+        );
+        let path = env(&command, "PATH").unwrap();
+        assert_eq!(
+            std::env::split_paths(path).next().unwrap(),
+            PathBuf::from("/jre17/bin")
+        );
+        assert_eq!(env(&command, "JAVA_HOME").unwrap(), "/jre17");
+
+        // Java 8 doesn't read JDK_JAVA_OPTIONS
+        let command = LaunchCommand::new(
+            &mode,
+            temp_dir.path(),
+            Path::new("java"),
+            8,
+            jvm_args(),
+            Vec::new(),
+            vec!["nogui".to_string()],
+        )
+        .unwrap();
+        assert!(env(&command, "_JAVA_OPTIONS").is_some());
+        assert!(env(&command, "JDK_JAVA_OPTIONS").is_none());
+        assert!(env(&command, "PATH").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_forge_run_script_command() {
+        let temp_dir = tempdir::TempDir::new("test_launch").unwrap();
+        write_script(temp_dir.path(), "run.sh", true);
+        let command = LaunchCommand::new(
+            &LaunchMode::ForgeRunScript,
+            temp_dir.path(),
+            Path::new("/jre17/bin/java"),
+            17,
+            jvm_args(),
+            vec!["-jar".into(), "server.jar".into()],
+            vec!["--nogui".to_string()],
+        )
+        .unwrap();
+        assert_eq!(command.program, temp_dir.path().join("run.sh"));
+        assert_eq!(command.args, vec![OsString::from("--nogui")]);
+        assert!(env(&command, "JDK_JAVA_OPTIONS").is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_validate_script() {
+        let temp_dir = tempdir::TempDir::new("test_launch").unwrap();
+        write_script(temp_dir.path(), "not_executable.sh", false);
+        for path in ["missing.sh", "not_executable.sh", "../outside.sh", "."] {
+            let mode = LaunchMode::Script {
+                path: path.to_string(),
+                args: Vec::new(),
+            };
+            assert!(
+                matches!(
+                    mode.validate(temp_dir.path()).unwrap_err().kind,
+                    ErrorKind::BadRequest
+                ),
+                "{}",
+                path
+            );
+        }
+        // the installer hasn't generated it
+        assert!(LaunchMode::ForgeRunScript
+            .validate(temp_dir.path())
+            .is_err());
+        assert!(LaunchMode::JavaJar.validate(temp_dir.path()).is_ok());
+    }
+}
@@ -67,6 +67,13 @@ pub fn parse_player_left(system_msg: &str) -> Option<String> {
     }
 }
 
+pub fn parse_out_of_memory(line: &str) -> bool {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"java\.lang\.OutOfMemoryError").unwrap();
+    }
+    RE.is_match(line).unwrap()
+}
+
 pub fn parse_server_started(system_msg: &str) -> bool {
     lazy_static! {
         static ref RE: Regex = Regex::new(r#"Done \(.+\)!"#).unwrap();
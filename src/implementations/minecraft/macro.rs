@@ -2,6 +2,7 @@ use std::{
     cell::RefCell,
     path::{Path, PathBuf},
     rc::Rc,
+    time::Duration,
 };
 
 use async_trait::async_trait;
@@ -18,7 +19,7 @@ use crate::{
     },
 };
 
-use super::MinecraftInstance;
+use super::{console_matcher::parse_pattern, MinecraftInstance};
 
 #[op]
 async fn send_stdin(state: Rc<RefCell<OpState>>, cmd: String) -> Result<(), anyhow::Error> {
@@ -34,6 +35,38 @@ async fn send_rcon(state: Rc<RefCell<OpState>>, cmd: String) -> Result<String, a
     Ok(ret)
 }
 
+#[op]
+async fn wait_for_console_line(
+    state: Rc<RefCell<OpState>>,
+    pattern: String,
+    timeout_ms: u64,
+) -> Result<String, anyhow::Error> {
+    let instance = state.borrow().borrow::<MinecraftInstance>().clone();
+    let line = instance
+        .wait_for_console_line(parse_pattern(&pattern)?, Duration::from_millis(timeout_ms))
+        .await?;
+    Ok(line)
+}
+
+#[op]
+async fn send_stdin_and_wait(
+    state: Rc<RefCell<OpState>>,
+    cmd: String,
+    pattern: String,
+    timeout_ms: u64,
+) -> Result<String, anyhow::Error> {
+    let instance = state.borrow().borrow::<MinecraftInstance>().clone();
+    let line = instance
+        .send_command_and_wait(
+            &cmd,
+            parse_pattern(&pattern)?,
+            Duration::from_millis(timeout_ms),
+            CausedBy::Unknown,
+        )
+        .await?;
+    Ok(line)
+}
+
 #[op]
 async fn on_event(
     state: Rc<RefCell<OpState>>,
@@ -158,6 +191,8 @@ impl WorkerOptionGenerator for MinecraftMainWorkerGenerator {
                 send_stdin::decl(),
                 send_rcon::decl(),
                 on_event::decl(),
+                wait_for_console_line::decl(),
+                send_stdin_and_wait::decl(),
             ])
             .state({
                 let instance = self.instance.clone();
@@ -1,66 +1,122 @@
+pub mod args;
+mod backup;
+pub mod bundle;
 pub mod configurable;
+pub mod console_matcher;
+pub mod crash_report;
 pub mod fabric;
+mod first_run;
 mod forge;
+mod gamerule;
+pub mod hooks;
+mod installer;
+pub mod jre;
+pub mod launch;
 mod line_parser;
 pub mod r#macro;
-mod paper;
+mod nbt;
+pub mod paper;
 pub mod player;
 mod players_manager;
+pub mod quilt;
+pub mod ram;
+mod rcon_check;
+pub mod readiness;
+mod reload;
 pub mod resource;
 pub mod server;
+pub mod setup;
+pub mod setup_deadline;
+mod shutdown;
+mod spigot;
+pub mod tick;
+mod update;
 pub mod util;
 mod vanilla;
+mod verify;
 pub mod versions;
+mod world;
 
 use color_eyre::eyre::{eyre, Context, ContextCompat};
 use enum_kinds::EnumKind;
 use indexmap::IndexMap;
 
 use std::collections::HashMap;
-use std::process::Stdio;
-use std::sync::atomic::AtomicBool;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicI64};
 use std::sync::Arc;
+use std::time::Duration;
 use sysinfo::SystemExt;
 use tokio::io::AsyncWriteExt;
-use tokio::process::{Child, Command};
+use tokio::process::Child;
 
+use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::Mutex;
 
 use ::serde::{Deserialize, Serialize};
 use serde_json::to_string_pretty;
 
-use tracing::error;
+use tracing::{error, info, warn};
 
 use tokio;
 use ts_rs::TS;
 
-use crate::error::Error;
+use crate::backup_history::BackupHistory;
+use crate::command_history::CommandHistory;
+use crate::error::{Error, ErrorKind};
 use crate::event_broadcaster::EventBroadcaster;
-use crate::events::{Event, ProgressionEventID};
+use crate::events::{CausedBy, Event, ProgressionEventID};
 use crate::macro_executor::{MacroExecutor, MacroPID};
 use crate::prelude::path_to_binaries;
+use crate::s3::S3Config;
 use crate::traits::t_configurable::PathBuf;
 
+use crate::traits::t_configurable::{AutoUpdateConfig, AvailableUpdate};
+
 use crate::traits::t_configurable::manifest::{
     ConfigurableManifest, ConfigurableValue, ConfigurableValueType, SectionManifest,
-    SettingManifest, SetupManifest, SetupValue,
+    SettingManifest, SetupManifest, SetupValue, MANIFEST_SCHEMA_VERSION,
 };
 
 use crate::traits::t_macro::TaskEntry;
-use crate::traits::t_server::State;
+use crate::traits::t_server::{CrashInfo, State, StateAction, TickPerformance};
 use crate::traits::TInstance;
 use crate::types::{DotLodestoneConfig, InstanceUuid};
-use crate::util::{
-    dont_spawn_terminal, download_file, format_byte, format_byte_download, unzip_file_async,
-    UnzipOption,
+use crate::util::{download_file, find_orphaned_process, format_byte, format_byte_download};
+
+use self::args::{default_program_args, split_cmd_args};
+pub use self::backup::BackupInstruction;
+use self::backup::BackupTask;
+use self::configurable::{
+    server_properties_template, validate_ram, CmdArgSetting, ServerPropertySetting,
+    SERVER_PROPERTIES_PRESETS,
 };
-
-use self::configurable::{CmdArgSetting, ServerPropertySetting};
 use self::fabric::get_fabric_minecraft_versions;
-use self::forge::get_forge_minecraft_versions;
-use self::paper::get_paper_minecraft_versions;
+use self::first_run::{first_run_files, write_first_run_files, FirstRunStage};
+pub use self::forge::DEFAULT_FORGE_INSTALLER_TIMEOUT_SECS;
+use self::forge::{get_forge_minecraft_versions, run_forge_installer};
+use self::hooks::LifecycleHooks;
+use self::jre::reusable_jre;
+use self::launch::LaunchMode;
+use self::paper::{get_paper_builds, get_paper_minecraft_versions, PaperBuildChannel};
 use self::players_manager::PlayersManager;
-use self::util::{get_jre_url, get_server_jar_url, read_properties_from_path};
+use self::quilt::{
+    get_quilt_installer_versions, get_quilt_loader_versions, get_quilt_minecraft_versions,
+    run_quilt_installer, DEFAULT_QUILT_INSTALLER_TIMEOUT_SECS,
+};
+use self::ram::{fit_to_host, host_total_mb, ram_hint, recommended_ram};
+use self::readiness::ReadinessProbe;
+use self::reload::read_restore_config;
+use self::setup::{server_jar_name, SetupCheckpoint, SetupPhase};
+use self::setup_deadline::{SetupDeadline, SetupTimeouts};
+use self::spigot::{
+    get_spigot_minecraft_versions, run_build_tools, DEFAULT_BUILD_TOOLS_TIMEOUT_SECS,
+};
+use self::util::{
+    get_server_jar_url, install_jre, jre_java_path, jre_needs_download, parse_properties_lenient,
+    resolve_jre_download, JreVendor,
+};
 use self::vanilla::get_vanilla_minecraft_versions;
 
 #[derive(Debug, Clone, TS, Serialize, Deserialize, PartialEq)]
@@ -71,6 +127,12 @@ pub struct FabricLoaderVersion(String);
 pub struct FabricInstallerVersion(String);
 #[derive(Debug, Clone, TS, Serialize, Deserialize, PartialEq)]
 #[ts(export)]
+pub struct QuiltLoaderVersion(String);
+#[derive(Debug, Clone, TS, Serialize, Deserialize, PartialEq)]
+#[ts(export)]
+pub struct QuiltInstallerVersion(String);
+#[derive(Debug, Clone, TS, Serialize, Deserialize, PartialEq)]
+#[ts(export)]
 pub struct PaperBuildVersion(i64);
 #[derive(Debug, Clone, TS, Serialize, Deserialize, PartialEq)]
 #[ts(export)]
@@ -93,6 +155,10 @@ pub enum Flavour {
     Forge {
         build_version: Option<ForgeBuildVersion>,
     },
+    Quilt {
+        loader_version: Option<QuiltLoaderVersion>,
+        installer_version: Option<QuiltInstallerVersion>,
+    },
 }
 
 impl From<FlavourKind> for Flavour {
@@ -110,6 +176,10 @@ impl From<FlavourKind> for Flavour {
             FlavourKind::Forge => Flavour::Forge {
                 build_version: None,
             },
+            FlavourKind::Quilt => Flavour::Quilt {
+                loader_version: None,
+                installer_version: None,
+            },
         }
     }
 }
@@ -122,6 +192,7 @@ impl ToString for Flavour {
             Flavour::Paper { .. } => "paper".to_string(),
             Flavour::Spigot => "spigot".to_string(),
             Flavour::Forge { .. } => "forge".to_string(),
+            Flavour::Quilt { .. } => "quilt".to_string(),
         }
     }
 }
@@ -134,6 +205,7 @@ impl ToString for FlavourKind {
             FlavourKind::Paper => "paper".to_string(),
             FlavourKind::Spigot => "spigot".to_string(),
             FlavourKind::Forge => "forge".to_string(),
+            FlavourKind::Quilt => "quilt".to_string(),
         }
     }
 }
@@ -144,6 +216,7 @@ pub struct SetupConfig {
     pub version: String,
     pub flavour: Flavour,
     pub port: u32,
+    /// Split into JVM and server arguments when the instance is created
     pub cmd_args: Vec<String>,
     pub description: Option<String>,
     pub min_ram: Option<u32>,
@@ -151,6 +224,29 @@ pub struct SetupConfig {
     pub auto_start: Option<bool>,
     pub restart_on_crash: Option<bool>,
     pub backup_period: Option<u32>,
+    /// Backups kept before the oldest are deleted, all are kept if not set
+    #[serde(default)]
+    pub max_backups: Option<u32>,
+    /// Seconds the forge installer gets to finish, `DEFAULT_FORGE_INSTALLER_TIMEOUT_SECS` if not set
+    #[serde(default)]
+    pub forge_installer_timeout_secs: Option<u64>,
+    /// Written to `server.properties` before the first boot, `server-port` is always taken from `port`
+    #[serde(default)]
+    pub server_properties: IndexMap<String, String>,
+    /// Vendor of the JRE to download, the auto-detected Temurin build is used if neither this nor the override is set
+    #[serde(default)]
+    pub jre_vendor: Option<JreVendor>,
+    /// Java version or release name to download instead of the one the minecraft version requires
+    #[serde(default)]
+    pub jre_version_override: Option<String>,
+    /// Allocated by the port manager and written to `server.properties`, like `port`
+    #[serde(default)]
+    pub rcon_port: Option<u32>,
+    #[serde(default)]
+    pub query_port: Option<u32>,
+    /// `SetupTimeouts::default()` if not set
+    #[serde(default)]
+    pub setup_timeouts: Option<SetupTimeouts>,
 }
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RestoreConfig {
@@ -158,7 +254,12 @@ pub struct RestoreConfig {
     pub version: String,
     pub flavour: Flavour,
     pub description: String,
-    pub cmd_args: Vec<String>,
+    /// Passed to java before `-jar`, ahead of the RAM flags
+    #[serde(default)]
+    pub jvm_args: Vec<String>,
+    /// Passed to the server after the jar
+    #[serde(default = "default_program_args")]
+    pub program_args: Vec<String>,
     pub java_cmd: Option<String>,
     pub port: u32,
     pub min_ram: u32,
@@ -168,11 +269,94 @@ pub struct RestoreConfig {
     pub backup_period: Option<u32>,
     pub jre_major_version: u64,
     pub has_started: bool,
+    #[serde(default)]
+    pub last_crash: Option<CrashInfo>,
+    /// Unix timestamp of when the server last finished starting, which its uptime counts from
+    #[serde(default)]
+    pub last_started: Option<i64>,
+    /// If set, max RAM is automatically raised up to this many MB after an out of memory crash
+    #[serde(default)]
+    pub oom_max_ram_ceiling: Option<u32>,
+    #[serde(default)]
+    pub console_buffer_lines: Option<usize>,
+    #[serde(default)]
+    pub persist_console: Option<bool>,
+    /// Extra environment variables passed to the server process
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Caps the disk read rate of backups in bytes per second, so they don't lag the server
+    #[serde(default)]
+    pub backup_io_limit: Option<u64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub jre_vendor: Option<JreVendor>,
+    #[serde(default)]
+    pub jre_version_override: Option<String>,
+    #[serde(default)]
+    pub auto_update: AutoUpdateConfig,
+    /// Directory backups are kept in instead of the instance's own resources
+    #[serde(default)]
+    pub backup_destination: Option<PathBuf>,
+    /// Backups are also uploaded here if set
+    #[serde(default)]
+    pub s3_backup: Option<S3Config>,
+    /// Back up the world whenever the server stops, unless it was killed
+    #[serde(default)]
+    pub backup_on_stop: Option<bool>,
+    /// No backup is taken on stop if one was taken this many seconds before,
+    /// `DEFAULT_BACKUP_ON_STOP_DEBOUNCE_SECS` if not set
+    #[serde(default)]
+    pub backup_on_stop_debounce_secs: Option<u32>,
+    /// What counts as the server being ready, the console "Done" line if not set
+    #[serde(default)]
+    pub readiness_probe: Option<ReadinessProbe>,
+    /// The server is killed if it isn't ready this many seconds after launching, never if not set
+    #[serde(default)]
+    pub readiness_timeout_secs: Option<u32>,
+    /// Nice level of the server process
+    #[serde(default)]
+    pub process_priority: Option<i32>,
+    /// Cores the server process may run on, bit `n` for core `n`
+    #[serde(default)]
+    pub cpu_affinity: Option<u64>,
+    /// The server is terminated if it hasn't stopped this many seconds after being asked to,
+    /// `DEFAULT_STOP_TIMEOUT_SECS` if not set
+    #[serde(default)]
+    pub stop_timeout_secs: Option<u32>,
+    /// Back up the world before operations that risk it, like changing the version. On if not set
+    #[serde(default)]
+    pub backup_before_risky_ops: Option<bool>,
+    /// Macros run before and after the server starts and stops
+    #[serde(default)]
+    pub hooks: LifecycleHooks,
+    /// The most disk in MB the instance directory may take up, unlimited if not set
+    #[serde(default)]
+    pub disk_quota_mb: Option<u64>,
+    /// Whether the server is launched with `java -jar` or through a script
+    #[serde(default)]
+    pub launch_mode: LaunchMode,
+    /// Crash reports are POSTed to this mclo.gs compatible paste API, not uploaded if not set
+    #[serde(default)]
+    pub crash_report_upload_url: Option<String>,
+    /// Save the world with `save-all flush` before stopping the server. On if not set
+    #[serde(default)]
+    pub save_before_stop: Option<bool>,
+    /// Backups kept before the oldest are deleted, all are kept if not set
+    #[serde(default)]
+    pub max_backups: Option<u32>,
+    /// Store each backup as a zip archive rather than a directory. Off if not set
+    #[serde(default)]
+    pub compress_backups: Option<bool>,
+    /// Automatic backups are paused until resumed, across restarts of the core
+    #[serde(default)]
+    pub backups_paused: bool,
 }
 
 #[derive(Clone)]
 pub struct MinecraftInstance {
     config: Arc<Mutex<RestoreConfig>>,
+    dot_lodestone_config: Arc<Mutex<DotLodestoneConfig>>,
     uuid: InstanceUuid,
     creation_time: i64,
     state: Arc<Mutex<State>>,
@@ -191,11 +375,29 @@ pub struct MinecraftInstance {
     auto_start: Arc<AtomicBool>,
     restart_on_crash: Arc<AtomicBool>,
     backup_period: Option<u32>,
+    backup_sender: UnboundedSender<BackupInstruction>,
+    backup_in_progress: Arc<AtomicBool>,
+    /// Unix timestamp of the last backup taken or requested on stop, 0 if none since the core started
+    last_backup_at: Arc<AtomicI64>,
+    backup_history: Arc<Mutex<BackupHistory>>,
+    /// Set while the server process is being killed, so that its exit doesn't trigger a backup on stop
+    killed: Arc<AtomicBool>,
+    /// Held while starting, stopping or killing the server, so that those don't interleave
+    lifecycle_lock: Arc<Mutex<()>>,
+    /// Set when the state disagreed with the process on the last reconciliation
+    state_drift_suspected: Arc<AtomicBool>,
+    /// Found by the last update check, cleared once applied
+    available_update: Arc<Mutex<Option<AvailableUpdate>>>,
+    /// Last tick performance measured, queries are rate limited with it
+    tick_performance: Arc<Mutex<Option<TickPerformance>>>,
+    command_history: Arc<Mutex<CommandHistory>>,
     process: Arc<Mutex<Option<Child>>>,
     stdin: Arc<Mutex<Option<tokio::process::ChildStdin>>>,
     system: Arc<Mutex<sysinfo::System>>,
     players_manager: Arc<Mutex<PlayersManager>>,
     configurable_manifest: Arc<Mutex<ConfigurableManifest>>,
+    /// Lines of the properties file that aren't a valid property, written back as they were
+    invalid_property_lines: Arc<Mutex<Vec<String>>>,
     macro_executor: MacroExecutor,
     rcon_conn: Arc<Mutex<Option<rcon::Connection<tokio::net::TcpStream>>>>,
     macro_name_to_last_run: Arc<Mutex<HashMap<String, i64>>>,
@@ -217,8 +419,9 @@ impl MinecraftInstance {
             FlavourKind::Vanilla => get_vanilla_minecraft_versions().await,
             FlavourKind::Fabric => get_fabric_minecraft_versions().await,
             FlavourKind::Paper => get_paper_minecraft_versions().await,
-            FlavourKind::Spigot => todo!(),
+            FlavourKind::Spigot => get_spigot_minecraft_versions().await,
             FlavourKind::Forge => get_forge_minecraft_versions().await,
+            FlavourKind::Quilt => get_quilt_minecraft_versions().await,
         }
         .context("Failed to get minecraft versions")?;
 
@@ -247,12 +450,14 @@ impl MinecraftInstance {
             true,
         );
 
+        let recommended_ram = fit_to_host(recommended_ram(*flavour), host_total_mb());
+
         let min_ram_setting = SettingManifest::new_required_value(
             "min_ram".to_string(),
             "Minimum RAM".to_string(),
             "The minimum amount of RAM to allocate to the server".to_string(),
-            ConfigurableValue::UnsignedInteger(1024),
-            Some(ConfigurableValue::UnsignedInteger(1024)),
+            ConfigurableValue::UnsignedInteger(recommended_ram.min_ram),
+            Some(ConfigurableValue::UnsignedInteger(recommended_ram.min_ram)),
             false,
             true,
         );
@@ -261,11 +466,12 @@ impl MinecraftInstance {
             "max_ram".to_string(),
             "Maximum RAM".to_string(),
             "The maximum amount of RAM to allocate to the server".to_string(),
-            ConfigurableValue::UnsignedInteger(2048),
-            Some(ConfigurableValue::UnsignedInteger(2048)),
+            ConfigurableValue::UnsignedInteger(recommended_ram.max_ram),
+            Some(ConfigurableValue::UnsignedInteger(recommended_ram.max_ram)),
             false,
             true,
-        );
+        )
+        .with_hint(ram_hint(*flavour, recommended_ram));
 
         let command_line_args_setting = SettingManifest::new_optional_value(
             "cmd_args".to_string(),
@@ -278,11 +484,117 @@ impl MinecraftInstance {
             true,
         );
 
+        let server_properties_preset_setting = SettingManifest::new_optional_value(
+            "server_properties_preset".to_string(),
+            "Server Properties Preset".to_string(),
+            "A preset of server properties to generate the world with".to_string(),
+            None,
+            ConfigurableValueType::Enum {
+                options: SERVER_PROPERTIES_PRESETS
+                    .iter()
+                    .map(|(name, _)| name.to_string())
+                    .collect(),
+            },
+            None,
+            false,
+            true,
+        );
+
+        let server_properties_setting = SettingManifest::new_optional_value(
+            "server_properties".to_string(),
+            "Server Properties".to_string(),
+            "Properties to write to server.properties before the first start, one key=value per line. Overrides the preset".to_string(),
+            None,
+            ConfigurableValueType::String { regex: None },
+            None,
+            false,
+            true,
+        );
+
+        let jre_vendor_setting = SettingManifest::new_optional_value(
+            "jre_vendor".to_string(),
+            "Java Vendor".to_string(),
+            "Vendor of the Java runtime to download, Temurin if not set".to_string(),
+            None,
+            ConfigurableValueType::Enum {
+                options: JreVendor::ALL
+                    .iter()
+                    .map(|vendor| vendor.to_string())
+                    .collect(),
+            },
+            None,
+            false,
+            true,
+        );
+
+        let jre_version_override_setting = SettingManifest::new_optional_value(
+            "jre_version_override".to_string(),
+            "Java Version".to_string(),
+            "Java version to download instead of the one this minecraft version requires, e.g. 17 or 17.0.7".to_string(),
+            None,
+            ConfigurableValueType::String { regex: None },
+            None,
+            false,
+            true,
+        );
+
         let mut section_1_map = IndexMap::new();
 
         section_1_map.insert("version".to_string(), version_setting);
         section_1_map.insert("port".to_string(), port_setting);
 
+        if let FlavourKind::Paper = flavour {
+            // builds depend on the version, so they can't be listed as options here
+            let paper_build_setting = SettingManifest::new_optional_value(
+                "paper_build".to_string(),
+                "Paper Build".to_string(),
+                "The paper build to install, the latest stable build if not set. Experimental builds may be unstable".to_string(),
+                None,
+                ConfigurableValueType::UnsignedInteger {
+                    min: Some(1),
+                    max: None,
+                },
+                None,
+                false,
+                true,
+            );
+            section_1_map.insert("paper_build".to_string(), paper_build_setting);
+        }
+
+        if let FlavourKind::Quilt = flavour {
+            // loaders depend on the version, so they can't be listed as options here
+            let quilt_loader_setting = SettingManifest::new_optional_value(
+                "quilt_loader_version".to_string(),
+                "Quilt Loader Version".to_string(),
+                "The quilt loader to install, the latest stable loader for the version if not set"
+                    .to_string(),
+                None,
+                ConfigurableValueType::String { regex: None },
+                None,
+                false,
+                true,
+            );
+            let quilt_installer_setting = SettingManifest::new_optional_value(
+                "quilt_installer_version".to_string(),
+                "Quilt Installer Version".to_string(),
+                "The quilt installer to set the server up with, the latest stable installer if not set".to_string(),
+                None,
+                ConfigurableValueType::Enum {
+                    options: get_quilt_installer_versions()
+                        .await
+                        .context("Failed to get quilt installer versions")?,
+                },
+                None,
+                false,
+                true,
+            );
+            section_1_map.insert("quilt_loader_version".to_string(), quilt_loader_setting);
+            section_1_map.insert(
+                "quilt_installer_version".to_string(),
+                quilt_installer_setting,
+            );
+        }
+
         let mut section_2_map = IndexMap::new();
 
         section_2_map.insert("min_ram".to_string(), min_ram_setting);
@@ -291,6 +603,20 @@ impl MinecraftInstance {
 
         section_2_map.insert("cmd_args".to_string(), command_line_args_setting);
 
+        section_2_map.insert(
+            "server_properties_preset".to_string(),
+            server_properties_preset_setting,
+        );
+
+        section_2_map.insert("server_properties".to_string(), server_properties_setting);
+
+        section_2_map.insert("jre_vendor".to_string(), jre_vendor_setting);
+
+        section_2_map.insert(
+            "jre_version_override".to_string(),
+            jre_version_override_setting,
+        );
+
         let section_1 = SectionManifest::new(
             "section_1".to_string(),
             "Basic Settings".to_string(),
@@ -311,6 +637,7 @@ impl MinecraftInstance {
         sections.insert("section_2".to_string(), section_2);
 
         Ok(SetupManifest {
+            schema_version: MANIFEST_SCHEMA_VERSION,
             setting_sections: sections,
         })
     }
@@ -360,6 +687,8 @@ impl MinecraftInstance {
             .try_as_unsigned_integer()
             .unwrap();
 
+        validate_ram(min_ram, max_ram, host_total_mb())?;
+
         let cmd_args: Vec<String> = setup_value
             .get_unique_setting("cmd_args")
             .unwrap()
@@ -370,6 +699,90 @@ impl MinecraftInstance {
             .map(|s| s.to_string())
             .collect();
 
+        let server_properties = server_properties_template(
+            setup_value
+                .get_unique_setting("server_properties_preset")
+                .and_then(|v| v.get_value())
+                .map(|v| v.try_as_enum().unwrap().as_str()),
+            setup_value
+                .get_unique_setting("server_properties")
+                .and_then(|v| v.get_value())
+                .map(|v| v.try_as_string().unwrap().as_str())
+                .unwrap_or_default(),
+        )?;
+
+        let jre_vendor = setup_value
+            .get_unique_setting("jre_vendor")
+            .and_then(|v| v.get_value())
+            .map(|v| JreVendor::from_str(v.try_as_enum().unwrap()))
+            .transpose()?;
+
+        let jre_version_override = setup_value
+            .get_unique_setting("jre_version_override")
+            .and_then(|v| v.get_value())
+            .map(|v| v.try_as_string().unwrap().trim().to_string())
+            .filter(|v| !v.is_empty());
+
+        let paper_build = setup_value
+            .get_unique_setting("paper_build")
+            .and_then(|v| v.get_value())
+            .map(|v| v.try_as_unsigned_integer().unwrap() as i64);
+
+        let quilt_loader_version = setup_value
+            .get_unique_setting("quilt_loader_version")
+            .and_then(|v| v.get_value())
+            .map(|v| v.try_as_string().unwrap().trim().to_string())
+            .filter(|v| !v.is_empty());
+
+        let quilt_installer_version = setup_value
+            .get_unique_setting("quilt_installer_version")
+            .and_then(|v| v.get_value())
+            .map(|v| QuiltInstallerVersion(v.try_as_enum().unwrap().clone()));
+
+        let flavour = match (flavour, paper_build) {
+            (FlavourKind::Paper, Some(build)) => {
+                if !get_paper_builds(version)
+                    .await?
+                    .iter()
+                    .any(|paper_build| paper_build.build == build)
+                {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!(
+                            "Paper build {} does not exist for version {}",
+                            build,
+                            version
+                        ),
+                    });
+                }
+                Flavour::Paper {
+                    build_version: Some(PaperBuildVersion(build)),
+                }
+            }
+            (FlavourKind::Quilt, _) => {
+                if let Some(loader_version) = &quilt_loader_version {
+                    if !get_quilt_loader_versions(version)
+                        .await?
+                        .contains(loader_version)
+                    {
+                        return Err(Error {
+                            kind: ErrorKind::BadRequest,
+                            source: eyre!(
+                                "Quilt loader {} does not support version {}",
+                                loader_version,
+                                version
+                            ),
+                        });
+                    }
+                }
+                Flavour::Quilt {
+                    loader_version: quilt_loader_version.map(QuiltLoaderVersion),
+                    installer_version: quilt_installer_version,
+                }
+            }
+            (flavour, _) => flavour.into(),
+        };
+
         Ok(SetupConfig {
             name,
             description,
@@ -378,10 +791,18 @@ impl MinecraftInstance {
             min_ram: Some(min_ram),
             max_ram: Some(max_ram),
             cmd_args,
-            flavour: flavour.into(),
+            flavour,
             auto_start: Some(setup_value.auto_start),
             restart_on_crash: Some(setup_value.restart_on_crash),
             backup_period: None,
+            max_backups: None,
+            forge_installer_timeout_secs: None,
+            server_properties,
+            jre_vendor,
+            jre_version_override,
+            rcon_port: None,
+            query_port: None,
+            setup_timeouts: None,
         })
     }
 
@@ -390,8 +811,13 @@ impl MinecraftInstance {
         java_cmd: String,
     ) -> ConfigurableManifest {
         let mut cmd_args_config_map = IndexMap::new();
-        let cmd_args = CmdArgSetting::Args(restore_config.cmd_args.clone());
-        cmd_args_config_map.insert(cmd_args.get_identifier().to_owned(), cmd_args.into());
+        let jvm_args = CmdArgSetting::JvmArgs(restore_config.jvm_args.clone());
+        cmd_args_config_map.insert(jvm_args.get_identifier().to_owned(), jvm_args.into());
+        let program_args = CmdArgSetting::ProgramArgs(restore_config.program_args.clone());
+        cmd_args_config_map.insert(
+            program_args.get_identifier().to_owned(),
+            program_args.into(),
+        );
         let min_ram = CmdArgSetting::MinRam(restore_config.min_ram);
         cmd_args_config_map.insert(min_ram.get_identifier().to_owned(), min_ram.into());
         let max_ram = CmdArgSetting::MaxRam(restore_config.max_ram);
@@ -428,6 +854,7 @@ impl MinecraftInstance {
         ConfigurableManifest::new(false, false, setting_sections)
     }
 
+    /// Sets up the instance, `deadline` bounds the steps and is told which one is running
     pub async fn new(
         config: SetupConfig,
         dot_lodestone_config: DotLodestoneConfig,
@@ -435,9 +862,9 @@ impl MinecraftInstance {
         progression_event_id: &ProgressionEventID,
         event_broadcaster: EventBroadcaster,
         macro_executor: MacroExecutor,
+        deadline: &SetupDeadline,
     ) -> Result<MinecraftInstance, Error> {
         let path_to_config = path_to_instance.join(".lodestone_minecraft_config.json");
-        let path_to_eula = path_to_instance.join("eula.txt");
         let path_to_macros = path_to_instance.join("macros");
         let path_to_resources = path_to_instance.join("resources");
         let path_to_properties = path_to_instance.join("server.properties");
@@ -445,89 +872,133 @@ impl MinecraftInstance {
 
         let uuid = dot_lodestone_config.uuid().to_owned();
 
-        // Step 1: Create Directories
-        event_broadcaster.send(Event::new_progression_event_update(
-            progression_event_id,
-            "1/4: Creating directories",
-            1.0,
-        ));
-        tokio::fs::create_dir_all(&path_to_instance)
-            .await
-            .and(tokio::fs::create_dir_all(&path_to_macros).await)
-            .and(tokio::fs::create_dir_all(&path_to_resources.join("mods")).await)
-            .and(tokio::fs::create_dir_all(&path_to_resources.join("worlds")).await)
-            .and(tokio::fs::create_dir_all(&path_to_resources.join("defaults")).await)
-            .and(tokio::fs::write(&path_to_eula, "#generated by Lodestone\neula=true").await)
-            .and(
-                tokio::fs::write(&path_to_properties, format!("server-port={}", config.port)).await,
-            )
-            .context("Could not create some files or directories for instance")
-            .map_err(|e| {
-                error!("{e}");
-                e
-            })?;
+        if config.max_backups == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("At least one backup must be kept"),
+            });
+        }
 
-        // Step 2: Download JRE
-        let (url, jre_major_version) = get_jre_url(config.version.as_str())
-            .await
-            .context("Could not get JRE URL")?;
-        if !path_to_runtimes
-            .join("java")
-            .join(format!("jre{}", jre_major_version))
-            .exists()
-        {
-            let downloaded = download_file(
-                &url,
-                &path_to_runtimes.join("java"),
-                None,
-                {
-                    let event_broadcaster = event_broadcaster.clone();
-                    &move |dl| {
-                        if let Some(total) = dl.total {
-                            event_broadcaster.send(Event::new_progression_event_update(
-                                progression_event_id,
-                                format!(
-                                    "2/4: Downloading JRE {}",
-                                    format_byte_download(dl.downloaded, total)
-                                ),
-                                (dl.step as f64 / total as f64) * 4.0,
-                            ));
-                        }
-                    }
-                },
-                true,
-            )
-            .await?;
+        let first_run_files = first_run_files(&config)?;
+        let files_at = |stage: FirstRunStage| {
+            first_run_files
+                .iter()
+                .filter(move |file| file.stage == stage)
+        };
+
+        // a retry of a failed setup picks up from its checkpoint
+        let mut checkpoint = match SetupCheckpoint::load(&path_to_instance).await? {
+            Some(checkpoint) => checkpoint,
+            None => SetupCheckpoint::new(config.clone(), None),
+        };
 
-            let unzipped_content = unzip_file_async(
-                &downloaded,
-                UnzipOption::ToDir(path_to_runtimes.join("java")),
+        let mut jre_download = deadline
+            .step(
+                SetupPhase::Jre,
+                resolve_jre_download(
+                    config.version.as_str(),
+                    config.jre_vendor,
+                    config.jre_version_override.as_deref(),
+                ),
             )
             .await?;
-            if unzipped_content.len() != 1 {
-                return Err(eyre!(
-                    "Expected only one file in the JRE archive, got {}",
-                    unzipped_content.len()
-                )
-                .into());
+        // unless a specific JRE was asked for, one of the same Java version another instance
+        // downloaded is shared instead of downloading another
+        if config.jre_vendor.is_none() && config.jre_version_override.is_none() {
+            if let Some(installed) = reusable_jre(&path_to_runtimes, &jre_download).await {
+                info!(
+                    "[{}] Using the installed JRE {} instead of downloading {}",
+                    config.name, installed, jre_download.dir_name
+                );
+                jre_download.dir_name = installed;
             }
+        }
+        let jre_major_version = jre_download.major_version;
+        let jre = jre_java_path(&path_to_runtimes, &jre_download.dir_name);
+
+        let valid_phases = checkpoint
+            .valid_phases(
+                &path_to_instance,
+                &config.flavour,
+                &files_at(FirstRunStage::Files)
+                    .map(|file| file.name)
+                    .collect::<Vec<_>>(),
+                &jre,
+            )
+            .await;
+        let phases_to_run = checkpoint.phases_to_run(|phase| valid_phases.contains(&phase));
 
-            tokio::fs::remove_file(&downloaded).await.context(format!(
-                "Could not remove downloaded JRE file {}",
-                downloaded.display()
-            ))?;
+        // Step 1: Create Directories
+        deadline.enter(SetupPhase::Files);
+        if phases_to_run.contains(&SetupPhase::Files) {
+            event_broadcaster.send(Event::new_progression_event_update(
+                progression_event_id,
+                "1/4: Creating directories",
+                1.0,
+            ));
+            tokio::fs::create_dir_all(&path_to_instance)
+                .await
+                .and(tokio::fs::create_dir_all(&path_to_macros).await)
+                .and(tokio::fs::create_dir_all(&path_to_resources.join("mods")).await)
+                .and(tokio::fs::create_dir_all(&path_to_resources.join("worlds")).await)
+                .and(tokio::fs::create_dir_all(&path_to_resources.join("defaults")).await)
+                .context("Could not create some directories for instance")
+                .map_err(|e| {
+                    error!("{e}");
+                    e
+                })?;
+            write_first_run_files(&path_to_instance, files_at(FirstRunStage::Files)).await?;
+            checkpoint
+                .complete(SetupPhase::Files, &path_to_instance)
+                .await?;
+        } else {
+            event_broadcaster.send(Event::new_progression_event_update(
+                progression_event_id,
+                "1/4: Directories already created",
+                1.0,
+            ));
+        }
 
-            tokio::fs::rename(
-                unzipped_content.iter().last().unwrap(),
-                path_to_runtimes
-                    .join("java")
-                    .join(format!("jre{}", jre_major_version)),
-            )
-            .await
-            .context(format!(
-                "Could not rename JRE directory {}",
-                unzipped_content.iter().last().unwrap().display()
-            ))?;
+        // Step 2: Download JRE
+        let download_jre = if phases_to_run.contains(&SetupPhase::Jre) {
+            let path_to_jre = path_to_runtimes.join("java").join(&jre_download.dir_name);
+            // a JRE without its java executable is broken, download it again
+            if path_to_jre.exists() && !jre.is_file() {
+                crate::util::fs::remove_dir_all(&path_to_jre)
+                    .await
+                    .context(format!(
+                        "Failed to remove broken JRE {}",
+                        path_to_jre.display()
+                    ))?;
+            }
+            jre_needs_download(&path_to_runtimes, &jre_download.dir_name).await?
+        } else {
+            false
+        };
+        if download_jre {
+            let event_broadcaster = event_broadcaster.clone();
+            deadline
+                .step(
+                    SetupPhase::Jre,
+                    install_jre(
+                        &jre_download.url,
+                        &jre_download.dir_name,
+                        &path_to_runtimes,
+                        &move |dl| {
+                            if let Some(total) = dl.total {
+                                event_broadcaster.send(Event::new_progression_event_update(
+                                    progression_event_id,
+                                    format!(
+                                        "2/4: Downloading JRE {}",
+                                        format_byte_download(dl.downloaded, total)
+                                    ),
+                                    (dl.step as f64 / total as f64) * 4.0,
+                                ));
+                            }
+                        },
+                    ),
+                )
+                .await?;
         } else {
             event_broadcaster.send(Event::new_progression_event_update(
                 progression_event_id,
@@ -535,105 +1006,199 @@ impl MinecraftInstance {
                 4.0,
             ));
         }
+        checkpoint
+            .complete(SetupPhase::Jre, &path_to_instance)
+            .await?;
 
         // Step 3: Download server.jar
         let flavour_name = config.flavour.to_string();
-        let (jar_url, flavour) = get_server_jar_url(config.version.as_str(), &config.flavour)
-            .await
-            .ok_or_else({
-                || {
-                    eyre!(
-                        "Could not find a {} server.jar for version {}",
-                        flavour_name,
-                        config.version
-                    )
-                }
-            })?;
-        let jar_name = match flavour {
-            Flavour::Forge { .. } => "forge-installer.jar",
-            _ => "server.jar",
-        };
-
-        download_file(
-            jar_url.as_str(),
-            &path_to_instance,
-            Some(jar_name),
+        let flavour = if phases_to_run.contains(&SetupPhase::ServerJar) {
+            let (jar_url, flavour) = deadline
+                .step(
+                    SetupPhase::ServerJar,
+                    get_server_jar_url(config.version.as_str(), &config.flavour),
+                )
+                .await?;
+            if let Flavour::Paper {
+                build_version: Some(PaperBuildVersion(build)),
+            } = &config.flavour
             {
-                let event_broadcaster = event_broadcaster.clone();
-                &move |dl| {
-                    if let Some(total) = dl.total {
-                        event_broadcaster.send(Event::new_progression_event_update(
-                            progression_event_id,
-                            format!(
-                                "3/4: Downloading {} {} {}",
-                                flavour_name,
-                                jar_name,
-                                format_byte_download(dl.downloaded, total),
-                            ),
-                            (dl.step as f64 / total as f64) * 3.0,
-                        ));
-                    } else {
-                        event_broadcaster.send(Event::new_progression_event_update(
-                            progression_event_id,
-                            format!(
-                                "3/4: Downloading {} {} {}",
-                                flavour_name,
-                                jar_name,
-                                format_byte(dl.downloaded),
-                            ),
-                            0.0,
-                        ));
-                    }
+                let experimental = get_paper_builds(&config.version).await.map(|builds| {
+                    builds.iter().any(|paper_build| {
+                        paper_build.build == *build
+                            && paper_build.channel == PaperBuildChannel::Experimental
+                    })
+                });
+                if let Ok(true) = experimental {
+                    event_broadcaster.send(Event::new_progression_event_update(
+                        progression_event_id,
+                        format!(
+                            "3/4: Warning: paper build {} is experimental and may be unstable",
+                            build
+                        ),
+                        0.0,
+                    ));
                 }
-            },
-            true,
-        )
-        .await?;
-        let jre = path_to_runtimes
-            .join("java")
-            .join(format!("jre{}", jre_major_version))
-            .join(if std::env::consts::OS == "macos" {
-                "Contents/Home/bin"
-            } else {
-                "bin"
-            })
-            .join("java");
+            }
+            let jar_name = server_jar_name(&flavour);
+
+            deadline
+                .step(
+                    SetupPhase::ServerJar,
+                    download_file(
+                        jar_url.as_str(),
+                        &path_to_instance,
+                        Some(jar_name),
+                        {
+                            let event_broadcaster = event_broadcaster.clone();
+                            &move |dl| {
+                                if let Some(total) = dl.total {
+                                    event_broadcaster.send(Event::new_progression_event_update(
+                                        progression_event_id,
+                                        format!(
+                                            "3/4: Downloading {} {} {}",
+                                            flavour_name,
+                                            jar_name,
+                                            format_byte_download(dl.downloaded, total),
+                                        ),
+                                        (dl.step as f64 / total as f64) * 3.0,
+                                    ));
+                                } else {
+                                    event_broadcaster.send(Event::new_progression_event_update(
+                                        progression_event_id,
+                                        format!(
+                                            "3/4: Downloading {} {} {}",
+                                            flavour_name,
+                                            jar_name,
+                                            format_byte(dl.downloaded),
+                                        ),
+                                        0.0,
+                                    ));
+                                }
+                            }
+                        },
+                        true,
+                    ),
+                )
+                .await?;
+            checkpoint
+                .record_server_jar(flavour.clone(), &path_to_instance)
+                .await?;
+            flavour
+        } else {
+            event_broadcaster.send(Event::new_progression_event_update(
+                progression_event_id,
+                format!("3/4: {} server already downloaded", flavour_name),
+                3.0,
+            ));
+            checkpoint
+                .flavour
+                .clone()
+                .unwrap_or_else(|| config.flavour.clone())
+        };
+        let install = phases_to_run.contains(&SetupPhase::Install);
+        deadline.enter(SetupPhase::Install);
         // Step 3 (part 2): Forge Setup
-        if let Flavour::Forge { .. } = flavour.clone() {
+        if let (Flavour::Forge { .. }, true) = (flavour.clone(), install) {
             event_broadcaster.send(Event::new_progression_event_update(
                 progression_event_id,
                 "3/4: Installing Forge Server",
-                1.0,
+                0.0,
             ));
 
-            if !dont_spawn_terminal(
-                Command::new(&jre)
-                    .arg("-jar")
-                    .arg(&path_to_instance.join("forge-installer.jar"))
-                    .arg("--installServer")
-                    .arg(&path_to_instance)
-                    .current_dir(&path_to_instance),
+            run_forge_installer(
+                &jre,
+                &path_to_instance,
+                Duration::from_secs(
+                    config
+                        .forge_installer_timeout_secs
+                        .unwrap_or(DEFAULT_FORGE_INSTALLER_TIMEOUT_SECS),
+                ),
+                1.0,
+                |message, progress| {
+                    event_broadcaster.send(Event::new_progression_event_update(
+                        progression_event_id,
+                        format!("3/4: Installing Forge Server: {}", message),
+                        progress,
+                    ));
+                },
+                |elapsed| {
+                    event_broadcaster.send(Event::new_progression_event_update(
+                        progression_event_id,
+                        format!(
+                            "3/4: Installing Forge Server ({}s elapsed)",
+                            elapsed.as_secs()
+                        ),
+                        0.0,
+                    ));
+                },
             )
-            .stderr(Stdio::null())
-            .stdout(Stdio::null())
-            .stdin(Stdio::null())
-            .spawn()
-            .context("Failed to start forge-installer.jar")?
-            .wait()
-            .await
-            .context("forge-installer.jar failed")?
-            .success()
-            {
-                return Err(eyre!("Failed to install forge server").into());
-            }
+            .await?;
+        }
+        // Step 3 (part 2): Quilt Setup
+        if let (Flavour::Quilt { loader_version, .. }, true) = (&flavour, install) {
+            let loader_version = loader_version
+                .as_ref()
+                .ok_or_else(|| eyre!("Quilt loader version not found"))?;
+            event_broadcaster.send(Event::new_progression_event_update(
+                progression_event_id,
+                "3/4: Installing Quilt Server",
+                0.0,
+            ));
+            run_quilt_installer(
+                &jre,
+                &path_to_instance,
+                &config.version,
+                loader_version,
+                Duration::from_secs(DEFAULT_QUILT_INSTALLER_TIMEOUT_SECS),
+            )
+            .await?;
+            event_broadcaster.send(Event::new_progression_event_update(
+                progression_event_id,
+                "3/4: Installed Quilt Server",
+                1.0,
+            ));
+        }
+        // Step 3 (part 2): Spigot Setup
+        if let (Flavour::Spigot, true) = (&flavour, install) {
+            event_broadcaster.send(Event::new_progression_event_update(
+                progression_event_id,
+                "3/4: Building Spigot Server",
+                0.0,
+            ));
 
-            tokio::fs::write(
-                &path_to_instance.join("user_jvm_args.txt"),
-                "# Generated by Lodestone\n# This file is ignored by Lodestone\n# Please set arguments using Lodestone",
+            run_build_tools(
+                &jre,
+                &path_to_instance,
+                &config.version,
+                Duration::from_secs(DEFAULT_BUILD_TOOLS_TIMEOUT_SECS),
+                1.0,
+                |message, progress| {
+                    event_broadcaster.send(Event::new_progression_event_update(
+                        progression_event_id,
+                        format!("3/4: Building Spigot Server: {}", message),
+                        progress,
+                    ));
+                },
+                |elapsed| {
+                    event_broadcaster.send(Event::new_progression_event_update(
+                        progression_event_id,
+                        format!(
+                            "3/4: Building Spigot Server ({}s elapsed)",
+                            elapsed.as_secs()
+                        ),
+                        0.0,
+                    ));
+                },
             )
-            .await
-            .context("Could not create user_jvm_args.txt")?;
+            .await?;
         }
+        if install {
+            write_first_run_files(&path_to_instance, files_at(FirstRunStage::AfterInstall)).await?;
+        }
+        checkpoint
+            .complete(SetupPhase::Install, &path_to_instance)
+            .await?;
 
         // Step 4: Finishing Up
         event_broadcaster.send(Event::new_progression_event_update(
@@ -642,20 +1207,55 @@ impl MinecraftInstance {
             1.0,
         ));
 
+        let (jvm_args, program_args) = split_cmd_args(&config.cmd_args);
+        let default_ram = fit_to_host(
+            recommended_ram(FlavourKind::from(&flavour)),
+            host_total_mb(),
+        );
         let restore_config = RestoreConfig {
             name: config.name,
             version: config.version,
             flavour,
             description: config.description.unwrap_or_default(),
-            cmd_args: config.cmd_args,
+            jvm_args,
+            program_args,
             port: config.port,
-            min_ram: config.min_ram.unwrap_or(2048),
-            max_ram: config.max_ram.unwrap_or(4096),
+            min_ram: config.min_ram.unwrap_or(default_ram.min_ram),
+            max_ram: config.max_ram.unwrap_or(default_ram.max_ram),
             auto_start: config.auto_start.unwrap_or(false),
             restart_on_crash: config.restart_on_crash.unwrap_or(false),
             backup_period: config.backup_period,
             jre_major_version,
             has_started: false,
+            last_crash: None,
+            last_started: None,
+            oom_max_ram_ceiling: None,
+            console_buffer_lines: None,
+            persist_console: None,
+            env: HashMap::new(),
+            backup_io_limit: None,
+            tags: Vec::new(),
+            jre_vendor: config.jre_vendor,
+            jre_version_override: config.jre_version_override,
+            auto_update: AutoUpdateConfig::default(),
+            backup_destination: None,
+            s3_backup: None,
+            backup_on_stop: None,
+            backup_on_stop_debounce_secs: None,
+            readiness_probe: None,
+            readiness_timeout_secs: None,
+            process_priority: None,
+            cpu_affinity: None,
+            stop_timeout_secs: None,
+            backup_before_risky_ops: None,
+            hooks: LifecycleHooks::default(),
+            disk_quota_mb: None,
+            launch_mode: LaunchMode::default(),
+            crash_report_upload_url: None,
+            save_before_stop: None,
+            max_backups: config.max_backups,
+            compress_backups: None,
+            backups_paused: false,
             java_cmd: Some(jre.to_string_lossy().to_string()),
         };
         // create config file
@@ -670,30 +1270,27 @@ impl MinecraftInstance {
             "Failed to write config file at {}",
             &path_to_config.display()
         ))?;
-        MinecraftInstance::restore(
-            path_to_instance,
+        let instance = MinecraftInstance::restore(
+            path_to_instance.clone(),
             dot_lodestone_config,
             event_broadcaster,
             macro_executor,
         )
-        .await
+        .await?;
+        if let Err(e) = SetupCheckpoint::remove(&path_to_instance).await {
+            warn!("{}", e);
+        }
+        Ok(instance)
     }
 
     pub async fn restore(
         path_to_instance: PathBuf,
-        dot_lodestone_config: DotLodestoneConfig,
+        mut dot_lodestone_config: DotLodestoneConfig,
         event_broadcaster: EventBroadcaster,
         macro_executor: MacroExecutor,
     ) -> Result<MinecraftInstance, Error> {
         let path_to_config = path_to_instance.join(".lodestone_minecraft_config.json");
-        let restore_config: RestoreConfig =
-            serde_json::from_reader(std::fs::File::open(&path_to_config).context(format!(
-                "Failed to open config file at {}",
-                &path_to_config.display()
-            ))?)
-            .context(
-                "Failed to deserialize config from string. Was the config file modified manually?",
-            )?;
+        let restore_config = read_restore_config(&path_to_config)?;
         let path_to_macros = path_to_instance.join("macros");
         let path_to_resources = path_to_instance.join("resources");
         let path_to_properties = path_to_instance.join("server.properties");
@@ -722,18 +1319,79 @@ impl MinecraftInstance {
             java_path.to_string_lossy().to_string(),
         )));
 
+        let mut system = sysinfo::System::new_all();
+        // reconcile the pid left behind by the last session
+        if let Some(pid) = dot_lodestone_config.pid() {
+            if find_orphaned_process(&mut system, pid, &path_to_instance).is_some() {
+                warn!(
+                    "[{}] Server process (PID {}) from a previous session is still running. Kill the instance to terminate it",
+                    restore_config.name, pid
+                );
+            } else {
+                dot_lodestone_config.set_pid(None);
+                let _ = Self::write_dot_lodestone_config(&path_to_instance, &dot_lodestone_config)
+                    .await
+                    .map_err(|e| {
+                        error!("[{}] Failed to clear stale pid: {}", restore_config.name, e);
+                    });
+            }
+        }
+
+        let state = Arc::new(Mutex::new(State::Stopped));
+        let backup_period = restore_config.backup_period;
+        let max_backups = restore_config.max_backups;
+        let backups_paused = restore_config.backups_paused;
+        let auto_start = restore_config.auto_start;
+        let restart_on_crash = restore_config.restart_on_crash;
+        let config = Arc::new(Mutex::new(restore_config));
+        let rcon_conn = Arc::new(Mutex::new(None));
+        let (backup_sender, backup_rx) = tokio::sync::mpsc::unbounded_channel();
+        let backup_in_progress = Arc::new(AtomicBool::new(false));
+        let last_backup_at = Arc::new(AtomicI64::new(0));
+        let backup_history = Arc::new(Mutex::new(BackupHistory::load(&path_to_instance).await));
+        tokio::spawn(
+            BackupTask {
+                uuid: dot_lodestone_config.uuid().clone(),
+                path_to_instance: path_to_instance.clone(),
+                path_to_properties: path_to_properties.clone(),
+                path_to_resources: path_to_resources.clone(),
+                state: state.clone(),
+                config: config.clone(),
+                rcon_conn: rcon_conn.clone(),
+                event_broadcaster: event_broadcaster.clone(),
+                in_progress: backup_in_progress.clone(),
+                last_backup_at: last_backup_at.clone(),
+                history: backup_history.clone(),
+            }
+            .run(backup_rx, backup_period, max_backups),
+        );
+        if backups_paused {
+            let _ = backup_sender.send(BackupInstruction::Pause);
+        }
+
         let mut instance = MinecraftInstance {
-            state: Arc::new(Mutex::new(State::Stopped)),
+            state,
             uuid: dot_lodestone_config.uuid().clone(),
             creation_time: dot_lodestone_config.creation_time(),
-            auto_start: Arc::new(AtomicBool::new(restore_config.auto_start)),
-            restart_on_crash: Arc::new(AtomicBool::new(restore_config.restart_on_crash)),
-            backup_period: restore_config.backup_period,
+            dot_lodestone_config: Arc::new(Mutex::new(dot_lodestone_config.clone())),
+            auto_start: Arc::new(AtomicBool::new(auto_start)),
+            restart_on_crash: Arc::new(AtomicBool::new(restart_on_crash)),
+            backup_period,
+            backup_sender,
+            backup_in_progress,
+            last_backup_at,
+            backup_history,
+            killed: Arc::new(AtomicBool::new(false)),
+            lifecycle_lock: Arc::new(Mutex::new(())),
+            state_drift_suspected: Arc::new(AtomicBool::new(false)),
+            available_update: Arc::new(Mutex::new(None)),
+            tick_performance: Arc::new(Mutex::new(None)),
+            command_history: Arc::new(Mutex::new(CommandHistory::load(&path_to_instance).await)),
             players_manager: Arc::new(Mutex::new(PlayersManager::new(
                 event_broadcaster.clone(),
                 dot_lodestone_config.uuid().clone(),
             ))),
-            config: Arc::new(Mutex::new(restore_config)),
+            config,
             path_to_instance,
             path_to_config,
             path_to_properties,
@@ -743,10 +1401,11 @@ impl MinecraftInstance {
             event_broadcaster,
             path_to_runtimes,
             process: Arc::new(Mutex::new(None)),
-            system: Arc::new(Mutex::new(sysinfo::System::new_all())),
+            system: Arc::new(Mutex::new(system)),
             stdin: Arc::new(Mutex::new(None)),
-            rcon_conn: Arc::new(Mutex::new(None)),
+            rcon_conn,
             configurable_manifest,
+            invalid_property_lines: Arc::new(Mutex::new(Vec::new())),
             macro_name_to_last_run: Arc::new(Mutex::new(HashMap::new())),
             pid_to_task_entry: Arc::new(Mutex::new(IndexMap::new())),
         };
@@ -758,6 +1417,7 @@ impl MinecraftInstance {
     }
 
     async fn write_config_to_file(&self) -> Result<(), Error> {
+        self.ensure_not_missing().await?;
         tokio::fs::write(
             &self.path_to_config,
             to_string_pretty(&*self.config.lock().await)
@@ -771,28 +1431,120 @@ impl MinecraftInstance {
         Ok(())
     }
 
+    async fn write_dot_lodestone_config(
+        path_to_instance: &Path,
+        dot_lodestone_config: &DotLodestoneConfig,
+    ) -> Result<(), Error> {
+        let path = path_to_instance.join(".lodestone_config");
+        tokio::fs::write(
+            &path,
+            to_string_pretty(dot_lodestone_config)
+                .context("Failed to serialize config to string, this is a bug, please report it")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write .lodestone_config to file at {}",
+            path.display()
+        ))?;
+        Ok(())
+    }
+
+    /// Records the pid of the server process in `.lodestone_config`,
+    /// so that a process outliving the backend can be found on the next restore
+    async fn persist_pid(&self, pid: Option<u32>) -> Result<(), Error> {
+        let mut lock = self.dot_lodestone_config.lock().await;
+        lock.set_pid(pid);
+        Self::write_dot_lodestone_config(&self.path_to_instance, &lock).await
+    }
+
+    /// Fails with `InstanceMissing` if the directory of the instance or its `.lodestone_config` was
+    /// deleted from under the core, marking the instance as missing unless its server is still up.
+    /// An instance whose directory is back is stopped again
+    pub(super) async fn ensure_not_missing(&self) -> Result<(), Error> {
+        let is_missing = !self.path_to_instance.join(".lodestone_config").is_file();
+        let action = if is_missing {
+            StateAction::DirectoryGone
+        } else {
+            StateAction::DirectoryRestored
+        };
+        // fails if there's nothing to mark, e.g. the server is up or the directory was never gone
+        let transitioned_to = {
+            let mut state = self.state.lock().await;
+            state.try_transition(action, None).ok().map(|_| *state)
+        };
+        if let Some(new_state) = transitioned_to {
+            let name = self.config.lock().await.name.clone();
+            if is_missing {
+                warn!(
+                    "[{}] The directory of the instance at {} is gone, marking it as missing",
+                    name,
+                    self.path_to_instance.display()
+                );
+            }
+            self.event_broadcaster
+                .send(Event::new_instance_state_transition(
+                    self.uuid.clone(),
+                    name,
+                    new_state,
+                ));
+        }
+        if is_missing {
+            return Err(Error {
+                kind: ErrorKind::InstanceMissing,
+                source: eyre!(
+                    "The directory of the instance at {} was deleted, forget the instance to remove it",
+                    self.path_to_instance.display()
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Total memory of the machine in MB
+    pub(super) async fn host_total_mb(&self) -> u32 {
+        let mut sys = self.system.lock().await;
+        sys.refresh_memory();
+        (sys.total_memory() / 1024 / 1024) as u32
+    }
+
+    /// Returns the pid of a server process left over from a previous session, if it is still alive
+    async fn orphaned_pid(&self) -> Option<sysinfo::Pid> {
+        if self.process.lock().await.is_some() {
+            return None;
+        }
+        let pid = self.dot_lodestone_config.lock().await.pid()?;
+        find_orphaned_process(&mut *self.system.lock().await, pid, &self.path_to_instance)
+    }
+
     async fn read_properties(&mut self) -> Result<(), Error> {
-        let properties = read_properties_from_path(&self.path_to_properties).await?;
+        // only a file that can't be read at all is an error, bad lines are kept aside
+        let properties = tokio::fs::read_to_string(&self.path_to_properties)
+            .await
+            .context(format!(
+                "Failed to read properties file at {}",
+                self.path_to_properties.display()
+            ))?;
+        let (settings, invalid_lines) = parse_properties_lenient(&properties);
+        let name = self.config.lock().await.name.clone();
+        for invalid_line in &invalid_lines {
+            warn!(
+                "[{}] Keeping invalid line of server.properties as is ({}): {}",
+                name, invalid_line.reason, invalid_line.line
+            );
+        }
         let mut lock = self.configurable_manifest.lock().await;
-        for (key, value) in properties.iter() {
+        for setting in settings {
+            let key = setting.get_identifier();
             let _ = lock
-                .set_setting(
-                    ServerPropertySetting::get_section_id(),
-                    match ServerPropertySetting::from_key_val(key, value) {
-                        Ok(v) => v.into(),
-                        Err(e) => {
-                            error!(
-                                "Failed to parse property {} with value {}: {}",
-                                key, value, e
-                            );
-                            continue;
-                        }
-                    },
-                )
+                .set_setting(ServerPropertySetting::get_section_id(), setting.into())
                 .map_err(|e| {
-                    error!("Failed to set property {} to {}: {}", key, value, e);
+                    error!("Failed to set property {}: {}", key, e);
                 });
         }
+        *self.invalid_property_lines.lock().await = invalid_lines
+            .into_iter()
+            .map(|invalid_line| invalid_line.line)
+            .collect();
         Ok(())
     }
 
@@ -805,15 +1557,12 @@ impl MinecraftInstance {
                 &self.path_to_properties.display()
             ))?;
         let mut setting_str = "".to_string();
-        for (key, value) in self
-            .configurable_manifest
-            .lock()
-            .await
+        let manifest = self.configurable_manifest.lock().await;
+        let settings = manifest
             .get_section(ServerPropertySetting::get_section_id())
             .unwrap()
-            .all_settings()
-            .iter()
-        {
+            .all_settings();
+        for (key, value) in settings.iter() {
             // print the key and value separated by a =
             // println!("{}={}", key, value);
             setting_str.push_str(&format!(
@@ -825,6 +1574,17 @@ impl MinecraftInstance {
                     .to_string()
             ));
         }
+        for line in self.invalid_property_lines.lock().await.iter() {
+            // a valid value set since takes the place of the invalid one
+            if let Some((key, _)) = line.split_once('=') {
+                if settings.contains_key(key.trim()) {
+                    continue;
+                }
+            }
+            setting_str.push_str(line);
+            setting_str.push('\n');
+        }
+        drop(manifest);
         file.write_all(setting_str.as_bytes())
             .await
             .context(format!(
@@ -849,8 +1609,19 @@ impl MinecraftInstance {
             .get_section(CmdArgSetting::get_section_id())
             .unwrap()
             .all_settings();
-        config_lock.cmd_args = configurable_map
-            .get(CmdArgSetting::Args(Default::default()).get_identifier())
+        config_lock.jvm_args = configurable_map
+            .get(CmdArgSetting::JvmArgs(Default::default()).get_identifier())
+            .expect("Programming error, value is not set")
+            .get_value()
+            .expect("Programming error, value is not set")
+            .clone()
+            .try_as_string()
+            .expect("Programming error, value is not a string")
+            .split(' ')
+            .map(|s| s.to_string())
+            .collect();
+        config_lock.program_args = configurable_map
+            .get(CmdArgSetting::ProgramArgs(Default::default()).get_identifier())
             .expect("Programming error, value is not set")
             .get_value()
             .expect("Programming error, value is not set")
@@ -893,6 +1664,13 @@ impl MinecraftInstance {
     }
 
     pub async fn send_rcon(&self, cmd: &str) -> Result<String, Error> {
+        let a = self.query_rcon(cmd).await?;
+        self.record_command(cmd, CausedBy::Unknown).await;
+        Ok(a)
+    }
+
+    /// Sends an RCON command without recording it in the command history
+    async fn query_rcon(&self, cmd: &str) -> Result<String, Error> {
         let a = self
             .rcon_conn
             .clone()
@@ -907,6 +1685,18 @@ impl MinecraftInstance {
             .context("Failed to send rcon command")?;
         Ok(a)
     }
+
+    async fn record_command(&self, command: &str, caused_by: CausedBy) {
+        if let Err(e) = self
+            .command_history
+            .lock()
+            .await
+            .record(command, caused_by)
+            .await
+        {
+            warn!("Failed to record command: {}", e);
+        }
+    }
 }
 
 impl TInstance for MinecraftInstance {}
@@ -0,0 +1,353 @@
+//! A reader and writer for the NBT format Minecraft stores worlds in, e.g. `level.dat`
+
+use std::io::{Read, Write};
+use std::path::Path;
+
+use color_eyre::eyre::{eyre, Context};
+use indexmap::IndexMap;
+
+use crate::error::Error;
+
+/// Compounds and lists nested deeper than this are rejected, so a malicious file can't overflow the stack
+const MAX_DEPTH: usize = 512;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(IndexMap<String, Tag>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    /// The tag named `key` if this is a compound
+    pub fn get(&self, key: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(tags) => tags.get(key),
+            _ => None,
+        }
+    }
+
+    /// The value of an integer tag of any width, booleans are stored as bytes
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Tag::Byte(v) => Some(*v as i64),
+            Tag::Short(v) => Some(*v as i64),
+            Tag::Int(v) => Some(*v as i64),
+            Tag::Long(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Tag::String(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// The tag named `key` if this is a compound, for modifying it
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Tag> {
+        match self {
+            Tag::Compound(tags) => tags.get_mut(key),
+            _ => None,
+        }
+    }
+
+    fn type_id(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => 1,
+            Tag::Short(_) => 2,
+            Tag::Int(_) => 3,
+            Tag::Long(_) => 4,
+            Tag::Float(_) => 5,
+            Tag::Double(_) => 6,
+            Tag::ByteArray(_) => 7,
+            Tag::String(_) => 8,
+            Tag::List(_) => 9,
+            Tag::Compound(_) => 10,
+            Tag::IntArray(_) => 11,
+            Tag::LongArray(_) => 12,
+        }
+    }
+}
+
+struct Reader<R> {
+    inner: R,
+}
+
+impl<R: Read> Reader<R> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut buf = [0; N];
+        self.inner
+            .read_exact(&mut buf)
+            .context("Unexpected end of NBT data")?;
+        Ok(buf)
+    }
+
+    /// A length prefix, which must not be negative
+    fn len(&mut self) -> Result<usize, Error> {
+        let len = i32::from_be_bytes(self.bytes()?);
+        usize::try_from(len).map_err(|_| eyre!("Negative length {} in NBT data", len).into())
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let len = u16::from_be_bytes(self.bytes()?) as usize;
+        let mut buf = vec![0; len];
+        self.inner
+            .read_exact(&mut buf)
+            .context("Unexpected end of NBT data")?;
+        // java's modified UTF-8 only differs from UTF-8 for characters a world rarely contains
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+
+    /// Reads `len` elements, growing the vector as they are read rather than trusting the length
+    fn array<T>(
+        &mut self,
+        mut read: impl FnMut(&mut Self) -> Result<T, Error>,
+    ) -> Result<Vec<T>, Error> {
+        let len = self.len()?;
+        let mut ret = Vec::new();
+        for _ in 0..len {
+            ret.push(read(self)?);
+        }
+        Ok(ret)
+    }
+
+    fn payload(&mut self, tag_type: u8, depth: usize) -> Result<Tag, Error> {
+        if depth > MAX_DEPTH {
+            return Err(eyre!("NBT data is nested too deeply").into());
+        }
+        Ok(match tag_type {
+            1 => Tag::Byte(i8::from_be_bytes(self.bytes()?)),
+            2 => Tag::Short(i16::from_be_bytes(self.bytes()?)),
+            3 => Tag::Int(i32::from_be_bytes(self.bytes()?)),
+            4 => Tag::Long(i64::from_be_bytes(self.bytes()?)),
+            5 => Tag::Float(f32::from_be_bytes(self.bytes()?)),
+            6 => Tag::Double(f64::from_be_bytes(self.bytes()?)),
+            7 => Tag::ByteArray(self.array(|r| Ok(i8::from_be_bytes(r.bytes()?)))?),
+            8 => Tag::String(self.string()?),
+            9 => {
+                let element_type = u8::from_be_bytes(self.bytes()?);
+                Tag::List(self.array(|r| r.payload(element_type, depth + 1))?)
+            }
+            10 => {
+                let mut tags = IndexMap::new();
+                loop {
+                    let tag_type = u8::from_be_bytes(self.bytes()?);
+                    if tag_type == 0 {
+                        break;
+                    }
+                    let name = self.string()?;
+                    tags.insert(name, self.payload(tag_type, depth + 1)?);
+                }
+                Tag::Compound(tags)
+            }
+            11 => Tag::IntArray(self.array(|r| Ok(i32::from_be_bytes(r.bytes()?)))?),
+            12 => Tag::LongArray(self.array(|r| Ok(i64::from_be_bytes(r.bytes()?)))?),
+            _ => return Err(eyre!("Unknown NBT tag type {}", tag_type).into()),
+        })
+    }
+}
+
+struct Writer<W> {
+    inner: W,
+}
+
+impl<W: Write> Writer<W> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.inner
+            .write_all(bytes)
+            .context("Failed to write NBT data")?;
+        Ok(())
+    }
+
+    fn len(&mut self, len: usize) -> Result<(), Error> {
+        let len = i32::try_from(len).map_err(|_| eyre!("NBT array is too long"))?;
+        self.bytes(&len.to_be_bytes())
+    }
+
+    fn string(&mut self, string: &str) -> Result<(), Error> {
+        let len = u16::try_from(string.len())
+            .map_err(|_| eyre!("NBT string is too long: {} bytes", string.len()))?;
+        self.bytes(&len.to_be_bytes())?;
+        self.bytes(string.as_bytes())
+    }
+
+    fn payload(&mut self, tag: &Tag) -> Result<(), Error> {
+        match tag {
+            Tag::Byte(v) => self.bytes(&v.to_be_bytes()),
+            Tag::Short(v) => self.bytes(&v.to_be_bytes()),
+            Tag::Int(v) => self.bytes(&v.to_be_bytes()),
+            Tag::Long(v) => self.bytes(&v.to_be_bytes()),
+            Tag::Float(v) => self.bytes(&v.to_be_bytes()),
+            Tag::Double(v) => self.bytes(&v.to_be_bytes()),
+            Tag::ByteArray(values) => {
+                self.len(values.len())?;
+                values.iter().try_for_each(|v| self.bytes(&v.to_be_bytes()))
+            }
+            Tag::String(v) => self.string(v),
+            Tag::List(tags) => {
+                // an empty list has no elements to take the type of, the game writes it as a list of end tags
+                let element_type = tags.first().map(Tag::type_id).unwrap_or(0);
+                if tags.iter().any(|tag| tag.type_id() != element_type) {
+                    return Err(eyre!("NBT list elements must all be of the same type").into());
+                }
+                self.bytes(&[element_type])?;
+                self.len(tags.len())?;
+                tags.iter().try_for_each(|tag| self.payload(tag))
+            }
+            Tag::Compound(tags) => {
+                for (name, tag) in tags {
+                    self.bytes(&[tag.type_id()])?;
+                    self.string(name)?;
+                    self.payload(tag)?;
+                }
+                self.bytes(&[0])
+            }
+            Tag::IntArray(values) => {
+                self.len(values.len())?;
+                values.iter().try_for_each(|v| self.bytes(&v.to_be_bytes()))
+            }
+            Tag::LongArray(values) => {
+                self.len(values.len())?;
+                values.iter().try_for_each(|v| self.bytes(&v.to_be_bytes()))
+            }
+        }
+    }
+}
+
+/// Reads uncompressed NBT data, whose root must be a compound
+pub fn read_nbt(data: impl Read) -> Result<Tag, Error> {
+    let mut reader = Reader { inner: data };
+    let root_type = u8::from_be_bytes(reader.bytes()?);
+    if root_type != 10 {
+        return Err(eyre!("NBT root is not a compound but of type {}", root_type).into());
+    }
+    // the root's name is always empty in practice
+    reader.string()?;
+    reader.payload(root_type, 0)
+}
+
+/// Writes uncompressed NBT data with `root` as its unnamed root compound
+pub fn write_nbt(root: &Tag, data: impl Write) -> Result<(), Error> {
+    if !matches!(root, Tag::Compound(_)) {
+        return Err(eyre!("NBT root must be a compound").into());
+    }
+    let mut writer = Writer { inner: data };
+    writer.bytes(&[root.type_id()])?;
+    writer.string("")?;
+    writer.payload(root)
+}
+
+/// Reads a gzip compressed NBT file, such as `level.dat`
+pub fn read_gzip_nbt_file(path: &Path) -> Result<Tag, Error> {
+    let file =
+        std::fs::File::open(path).context(format!("Failed to open file at {}", path.display()))?;
+    read_nbt(flate2::read::GzDecoder::new(std::io::BufReader::new(file))).map_err(|e| {
+        e.source
+            .wrap_err(format!("Failed to read NBT at {}", path.display()))
+            .into()
+    })
+}
+
+/// Replaces a gzip compressed NBT file, writing to a temporary file first
+/// so that the file isn't left truncated if writing fails
+pub fn write_gzip_nbt_file(path: &Path, root: &Tag) -> Result<(), Error> {
+    let path_to_tmp = path.with_extension("dat.tmp");
+    let file = std::fs::File::create(&path_to_tmp).context(format!(
+        "Failed to create file at {}",
+        path_to_tmp.display()
+    ))?;
+    let mut encoder = flate2::write::GzEncoder::new(
+        std::io::BufWriter::new(file),
+        flate2::Compression::default(),
+    );
+    write_nbt(root, &mut encoder)?;
+    encoder
+        .finish()
+        .and_then(|mut writer| writer.flush())
+        .context(format!("Failed to write NBT to {}", path_to_tmp.display()))?;
+    std::fs::rename(&path_to_tmp, path).context(format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_nbt() {
+        let mut data = vec![10, 0, 0];
+        // Data: { Version: { Name: "1.20.1" }, hardcore: 1b, Pos: [1.0d, 2.0d] }
+        data.extend([10, 0, 4]);
+        data.extend(b"Data");
+        data.extend([10, 0, 7]);
+        data.extend(b"Version");
+        data.extend([8, 0, 4]);
+        data.extend(b"Name");
+        data.extend([0, 6]);
+        data.extend(b"1.20.1");
+        data.push(0);
+        data.extend([1, 0, 8]);
+        data.extend(b"hardcore");
+        data.push(1);
+        data.extend([9, 0, 3]);
+        data.extend(b"Pos");
+        data.extend([6, 0, 0, 0, 2]);
+        data.extend(1.0f64.to_be_bytes());
+        data.extend(2.0f64.to_be_bytes());
+        data.extend([0, 0]);
+
+        let root = read_nbt(&data[..]).unwrap();
+        let level = root.get("Data").unwrap();
+        assert_eq!(
+            level
+                .get("Version")
+                .and_then(|version| version.get("Name"))
+                .and_then(Tag::as_str),
+            Some("1.20.1")
+        );
+        assert_eq!(level.get("hardcore").and_then(Tag::as_i64), Some(1));
+        assert_eq!(
+            level.get("Pos"),
+            Some(&Tag::List(vec![Tag::Double(1.0), Tag::Double(2.0)]))
+        );
+
+        // truncated data and negative lengths are errors
+        assert!(read_nbt(&data[..data.len() - 3]).is_err());
+        assert!(read_nbt(&[10, 0, 0, 7, 0, 1, b'a', 255, 255, 255, 255][..]).is_err());
+        assert!(read_nbt(&[8, 0, 0][..]).is_err());
+
+        // what is read is written back byte for byte
+        let mut written = Vec::new();
+        write_nbt(&root, &mut written).unwrap();
+        assert_eq!(written, data);
+    }
+
+    #[test]
+    fn test_write_gzip_nbt_file() {
+        let temp_dir = tempdir::TempDir::new("test_write_gzip_nbt_file").unwrap();
+        let path = temp_dir.path().join("level.dat");
+        let mut data = IndexMap::new();
+        data.insert("LevelName".to_string(), Tag::String("world".to_string()));
+        data.insert("Empty".to_string(), Tag::List(Vec::new()));
+        data.insert("Longs".to_string(), Tag::LongArray(vec![1, -2]));
+        let mut root = IndexMap::new();
+        root.insert("Data".to_string(), Tag::Compound(data));
+        let root = Tag::Compound(root);
+
+        write_gzip_nbt_file(&path, &root).unwrap();
+        assert_eq!(read_gzip_nbt_file(&path).unwrap(), root);
+        assert!(!path.with_extension("dat.tmp").exists());
+        assert!(write_nbt(&Tag::Int(1), Vec::new()).is_err());
+    }
+}
@@ -1,19 +1,37 @@
+use std::time::Duration;
+
 use color_eyre::eyre::{eyre, Context, ContextCompat};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use ts_rs::TS;
 
-use crate::error::Error;
+use crate::error::{Error, ErrorKind};
+use crate::upstream_cache::{cached_get_text, cached_get_text_with_ttl};
 
-pub async fn get_paper_minecraft_versions() -> Result<Vec<String>, Error> {
-    let http = reqwest::Client::new();
+/// New paper builds come out several times a day, so the build list isn't cached for long
+const PAPER_BUILDS_CACHE_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, TS)]
+#[serde(rename_all = "lowercase")]
+#[ts(export)]
+pub enum PaperBuildChannel {
+    Default,
+    Experimental,
+}
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct PaperBuild {
+    pub build: i64,
+    pub channel: PaperBuildChannel,
+    pub time: String,
+    pub download_name: String,
+}
+
+pub async fn get_paper_minecraft_versions() -> Result<Vec<String>, Error> {
     let response: Value = serde_json::from_str(
-        http.get("https://api.papermc.io/v2/projects/paper")
-            .send()
-            .await
-            .context("Failed to get paper versions")?
-            .text()
-            .await
-            .context("Failed to get paper versions")?
+        cached_get_text("https://api.papermc.io/v2/projects/paper")
+            .await?
             .as_str(),
     )
     .context("Failed to get paper versions, response is not valid json")?;
@@ -39,6 +57,60 @@ pub async fn get_paper_minecraft_versions() -> Result<Vec<String>, Error> {
     Ok(versions)
 }
 
+/// Builds of a paper version, newest first
+pub async fn get_paper_builds(version: &str) -> Result<Vec<PaperBuild>, Error> {
+    let response: Value = serde_json::from_str(
+        cached_get_text_with_ttl(
+            &format!(
+                "https://api.papermc.io/v2/projects/paper/versions/{}/builds/",
+                version
+            ),
+            PAPER_BUILDS_CACHE_TTL,
+        )
+        .await
+        .map_err(|e| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Failed to get paper builds for version {}: {}", version, e),
+        })?
+        .as_str(),
+    )
+    .context("Failed to get paper builds, response is not valid json")?;
+
+    let mut builds = response
+        .get("builds")
+        .context("Failed to get paper builds, response does not contain builds")?
+        .as_array()
+        .context("Failed to get paper builds, builds is not an array")?
+        .iter()
+        .map(|build| {
+            Some(PaperBuild {
+                build: build.get("build")?.as_i64()?,
+                channel: serde_json::from_value(build.get("channel")?.clone()).ok()?,
+                time: build.get("time")?.as_str()?.to_string(),
+                download_name: build
+                    .get("downloads")?
+                    .get("application")?
+                    .get("name")?
+                    .as_str()?
+                    .to_string(),
+            })
+        })
+        .collect::<Option<Vec<PaperBuild>>>()
+        .context("Failed to get paper builds, a build is malformed. PaperMC API changed?")?;
+
+    builds.sort_by(|a, b| b.build.cmp(&a.build));
+
+    Ok(builds)
+}
+
+/// The build used when none is selected, experimental builds are never picked automatically
+pub fn latest_stable_paper_build(builds: &[PaperBuild]) -> Option<&PaperBuild> {
+    builds
+        .iter()
+        .filter(|build| build.channel == PaperBuildChannel::Default)
+        .max_by_key(|build| build.build)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -52,4 +124,29 @@ mod test {
         assert!(versions.contains(&"1.16.2".to_string()));
         assert!(versions.contains(&"1.16.1".to_string()));
     }
+
+    #[test]
+    fn test_latest_stable_paper_build() {
+        let build = |build: i64, channel: PaperBuildChannel| PaperBuild {
+            build,
+            channel,
+            time: "2023-01-01T00:00:00.000Z".to_string(),
+            download_name: format!("paper-1.20.1-{}.jar", build),
+        };
+        let builds = vec![
+            build(3, PaperBuildChannel::Experimental),
+            build(2, PaperBuildChannel::Default),
+            build(1, PaperBuildChannel::Default),
+        ];
+        assert_eq!(latest_stable_paper_build(&builds).unwrap().build, 2);
+        assert!(latest_stable_paper_build(&builds[..1]).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_get_paper_builds() {
+        let builds = get_paper_builds("1.19").await.unwrap();
+        assert!(builds.windows(2).all(|pair| pair[0].build > pair[1].build));
+        assert_eq!(latest_stable_paper_build(&builds).unwrap().build, 81);
+        assert!(get_paper_builds("1.19.3bruh").await.is_err());
+    }
 }
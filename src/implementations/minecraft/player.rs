@@ -1,9 +1,14 @@
 use async_trait::async_trait;
 
+use color_eyre::eyre::eyre;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-use crate::traits::t_player::Player;
+use crate::error::ErrorKind;
+use crate::events::CausedBy;
+use crate::traits::t_player::{
+    GameMode, ModerationAction, OnlinePlayer, Player, PlayerDetails, TeleportDestination, Weather,
+};
 use crate::traits::t_player::{TPlayer, TPlayerManagement};
 use crate::Error;
 
@@ -69,4 +74,389 @@ impl TPlayerManagement for MinecraftInstance {
     async fn get_player_list(&self) -> Result<HashSet<Player>, Error> {
         Ok(self.players_manager.lock().await.clone().into())
     }
+
+    async fn get_online_players(&self) -> Result<Vec<OnlinePlayer>, Error> {
+        let players: Vec<MinecraftPlayer> = self
+            .players_manager
+            .lock()
+            .await
+            .as_ref()
+            .iter()
+            .cloned()
+            .collect();
+        let ops = self.read_listed_players("ops.json").await;
+        let whitelist = self.read_listed_players("whitelist.json").await;
+        let rcon_connected = self.rcon_conn.lock().await.is_some();
+        let mut online_players = Vec::with_capacity(players.len());
+        for player in players {
+            let details = if rcon_connected {
+                Some(self.query_player_details(&player.name).await)
+            } else {
+                None
+            };
+            online_players.push(OnlinePlayer {
+                is_op: ops.as_ref().map(|ops| is_listed(ops, &player)),
+                is_whitelisted: whitelist
+                    .as_ref()
+                    .map(|whitelist| is_listed(whitelist, &player)),
+                details,
+                player: Player::MinecraftPlayer(player),
+            });
+        }
+        online_players.sort_by(|a, b| a.player.get_name().cmp(&b.player.get_name()));
+        Ok(online_players)
+    }
+
+    async fn moderate(
+        &self,
+        action: ModerationAction,
+        caused_by: CausedBy,
+    ) -> Result<String, Error> {
+        let command = moderation_command(&action)?;
+        if self.rcon_conn.lock().await.is_none() {
+            return Err(Error {
+                kind: ErrorKind::RconNotOpen,
+                source: eyre!("The server must be running with RCON connected"),
+            });
+        }
+        let response = self.query_rcon(&command).await?;
+        self.record_command(&command, caused_by).await;
+        Ok(response)
+    }
+}
+
+/// Vanilla refuses to teleport further than this on the x and z axes
+const MAX_HORIZONTAL_COORDINATE: f64 = 30_000_000.0;
+const MAX_VERTICAL_COORDINATE: f64 = 20_000_000.0;
+/// The largest stack `give` accepts
+const MAX_GIVE_COUNT: u32 = 6400;
+
+fn bad_request(message: String) -> Error {
+    Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!(message),
+    }
+}
+
+fn validate_player_name(name: &str) -> Result<(), Error> {
+    if name.is_empty()
+        || name.len() > 16
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(bad_request(format!("Invalid player name {:?}", name)));
+    }
+    Ok(())
+}
+
+/// Checks the syntax of a namespaced item ID such as `minecraft:diamond_sword`, not that the item exists
+fn validate_item_id(item: &str) -> Result<(), Error> {
+    let (namespace, path) = item.split_once(':').unwrap_or(("minecraft", item));
+    let valid = !namespace.is_empty()
+        && !path.is_empty()
+        && namespace
+            .chars()
+            .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '_' | '-' | '.'))
+        && path
+            .chars()
+            .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '_' | '-' | '.' | '/'));
+    if !valid {
+        return Err(bad_request(format!("Invalid item ID {:?}", item)));
+    }
+    Ok(())
+}
+
+fn validate_coordinate(axis: &str, value: f64, max: f64) -> Result<(), Error> {
+    if !value.is_finite() || value.abs() > max {
+        return Err(bad_request(format!(
+            "{} coordinate must be between -{} and {}",
+            axis, max, max
+        )));
+    }
+    Ok(())
+}
+
+/// The command performing a moderation action, after validating it
+fn moderation_command(action: &ModerationAction) -> Result<String, Error> {
+    Ok(match action {
+        ModerationAction::SetGameMode { player, game_mode } => {
+            validate_player_name(player)?;
+            let game_mode = match game_mode {
+                GameMode::Survival => "survival",
+                GameMode::Creative => "creative",
+                GameMode::Adventure => "adventure",
+                GameMode::Spectator => "spectator",
+            };
+            format!("gamemode {} {}", game_mode, player)
+        }
+        ModerationAction::Teleport {
+            player,
+            destination,
+        } => {
+            validate_player_name(player)?;
+            match destination {
+                TeleportDestination::Coordinates { x, y, z } => {
+                    validate_coordinate("x", *x, MAX_HORIZONTAL_COORDINATE)?;
+                    validate_coordinate("y", *y, MAX_VERTICAL_COORDINATE)?;
+                    validate_coordinate("z", *z, MAX_HORIZONTAL_COORDINATE)?;
+                    format!("tp {} {} {} {}", player, x, y, z)
+                }
+                TeleportDestination::Player { name } => {
+                    validate_player_name(name)?;
+                    format!("tp {} {}", player, name)
+                }
+            }
+        }
+        ModerationAction::Give {
+            player,
+            item,
+            count,
+        } => {
+            validate_player_name(player)?;
+            validate_item_id(item)?;
+            if *count == 0 || *count > MAX_GIVE_COUNT {
+                return Err(bad_request(format!(
+                    "Count must be between 1 and {}",
+                    MAX_GIVE_COUNT
+                )));
+            }
+            format!("give {} {} {}", player, item, count)
+        }
+        ModerationAction::SetTime { ticks } => {
+            if *ticks > i32::MAX as u32 {
+                return Err(bad_request("Time is too large".to_string()));
+            }
+            format!("time set {}", ticks)
+        }
+        ModerationAction::SetWeather {
+            weather,
+            duration_secs,
+        } => {
+            let weather = match weather {
+                Weather::Clear => "clear",
+                Weather::Rain => "rain",
+                Weather::Thunder => "thunder",
+            };
+            match duration_secs {
+                Some(0) => return Err(bad_request("Duration must be positive".to_string())),
+                Some(duration_secs) => format!("weather {} {}", weather, duration_secs),
+                None => format!("weather {}", weather),
+            }
+        }
+    })
+}
+
+/// An entry of `ops.json` or `whitelist.json`
+#[derive(Deserialize)]
+struct ListedPlayer {
+    uuid: String,
+    name: String,
+}
+
+fn is_listed(list: &[ListedPlayer], player: &MinecraftPlayer) -> bool {
+    list.iter().any(|listed| match &player.uuid {
+        Some(uuid) => listed.uuid.replace('-', "") == uuid.replace('-', ""),
+        None => listed.name.eq_ignore_ascii_case(&player.name),
+    })
+}
+
+/// The value in the response to `data get entity`, e.g. `Steve has the following entity data: 20.0f`
+fn entity_data(response: &str) -> Option<&str> {
+    response
+        .split_once("entity data: ")
+        .map(|(_, data)| data.trim())
+}
+
+fn parse_nbt_number(data: &str) -> Option<f64> {
+    data.trim()
+        .trim_end_matches(['b', 's', 'l', 'f', 'd', 'B', 'S', 'L', 'F', 'D'])
+        .parse()
+        .ok()
+}
+
+/// Parses a position such as `[12.5d, 64.0d, -3.2d]`
+fn parse_nbt_position(data: &str) -> Option<(f64, f64, f64)> {
+    let coordinates = data
+        .strip_prefix('[')?
+        .strip_suffix(']')?
+        .split(',')
+        .map(parse_nbt_number)
+        .collect::<Option<Vec<_>>>()?;
+    match coordinates[..] {
+        [x, y, z] => Some((x, y, z)),
+        _ => None,
+    }
+}
+
+fn game_mode_name(id: f64) -> Option<String> {
+    match id as i64 {
+        0 => Some("survival"),
+        1 => Some("creative"),
+        2 => Some("adventure"),
+        3 => Some("spectator"),
+        _ => None,
+    }
+    .map(str::to_string)
+}
+
+impl MinecraftInstance {
+    async fn read_listed_players(&self, file_name: &str) -> Option<Vec<ListedPlayer>> {
+        let content = tokio::fs::read(self.path_to_instance.join(file_name))
+            .await
+            .ok()?;
+        serde_json::from_slice(&content).ok()
+    }
+
+    async fn query_entity_data(&self, player_name: &str, path: &str) -> Option<String> {
+        let response = self
+            .query_rcon(&format!("data get entity {} {}", player_name, path))
+            .await
+            .ok()?;
+        entity_data(&response).map(str::to_string)
+    }
+
+    /// Fields the server doesn't report, e.g. because `data get` isn't available before 1.13, are left empty
+    async fn query_player_details(&self, player_name: &str) -> PlayerDetails {
+        PlayerDetails {
+            position: self
+                .query_entity_data(player_name, "Pos")
+                .await
+                .and_then(|data| parse_nbt_position(&data)),
+            dimension: self
+                .query_entity_data(player_name, "Dimension")
+                .await
+                .map(|data| data.trim_matches('"').to_string()),
+            health: self
+                .query_entity_data(player_name, "Health")
+                .await
+                .and_then(|data| parse_nbt_number(&data))
+                .map(|health| health as f32),
+            game_mode: self
+                .query_entity_data(player_name, "playerGameType")
+                .await
+                .and_then(|data| parse_nbt_number(&data))
+                .and_then(game_mode_name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_entity_data() {
+        let data = entity_data("Steve has the following entity data: [12.5d, 64.0d, -3.25d]");
+        assert_eq!(data, Some("[12.5d, 64.0d, -3.25d]"));
+        assert_eq!(parse_nbt_position(data.unwrap()), Some((12.5, 64.0, -3.25)));
+        assert_eq!(parse_nbt_position("[1.0d, 2.0d]"), None);
+        assert_eq!(
+            entity_data("Steve has the following entity data: 20.0f").and_then(parse_nbt_number),
+            Some(20.0)
+        );
+        assert_eq!(
+            entity_data("Steve has the following entity data: 1")
+                .and_then(parse_nbt_number)
+                .and_then(game_mode_name),
+            Some("creative".to_string())
+        );
+        assert_eq!(entity_data("No entity was found"), None);
+    }
+
+    #[test]
+    fn test_is_listed() {
+        let list = vec![ListedPlayer {
+            uuid: "069a79f4-44e9-4726-a5be-fca90e38aaf5".to_string(),
+            name: "Notch".to_string(),
+        }];
+        assert!(is_listed(
+            &list,
+            &MinecraftPlayer::new(
+                "Notch".to_string(),
+                Some("069a79f444e94726a5befca90e38aaf5".to_string())
+            )
+        ));
+        assert!(is_listed(
+            &list,
+            &MinecraftPlayer::new("notch".to_string(), None)
+        ));
+        assert!(!is_listed(
+            &list,
+            &MinecraftPlayer::new("Steve".to_string(), None)
+        ));
+    }
+
+    #[test]
+    fn test_moderation_command() {
+        let teleport = |destination| ModerationAction::Teleport {
+            player: "Steve".to_string(),
+            destination,
+        };
+        assert_eq!(
+            moderation_command(&teleport(TeleportDestination::Coordinates {
+                x: 10.5,
+                y: 64.0,
+                z: -3.0
+            }))
+            .unwrap(),
+            "tp Steve 10.5 64 -3"
+        );
+        assert_eq!(
+            moderation_command(&teleport(TeleportDestination::Player {
+                name: "Alex".to_string()
+            }))
+            .unwrap(),
+            "tp Steve Alex"
+        );
+        assert!(
+            moderation_command(&teleport(TeleportDestination::Coordinates {
+                x: 40_000_000.0,
+                y: 64.0,
+                z: 0.0
+            }))
+            .is_err()
+        );
+        assert!(
+            moderation_command(&teleport(TeleportDestination::Coordinates {
+                x: f64::NAN,
+                y: 64.0,
+                z: 0.0
+            }))
+            .is_err()
+        );
+        // a name that would inject another command
+        assert!(moderation_command(&teleport(TeleportDestination::Player {
+            name: "Alex\nop Steve".to_string()
+        }))
+        .is_err());
+
+        let give = |item: &str, count| ModerationAction::Give {
+            player: "Steve".to_string(),
+            item: item.to_string(),
+            count,
+        };
+        assert_eq!(
+            moderation_command(&give("minecraft:diamond", 5)).unwrap(),
+            "give Steve minecraft:diamond 5"
+        );
+        assert!(moderation_command(&give("diamond", 5)).is_ok());
+        assert!(moderation_command(&give("Diamond Sword", 1)).is_err());
+        assert!(moderation_command(&give("minecraft:diamond", 0)).is_err());
+
+        assert_eq!(
+            moderation_command(&ModerationAction::SetGameMode {
+                player: "Steve".to_string(),
+                game_mode: GameMode::Creative
+            })
+            .unwrap(),
+            "gamemode creative Steve"
+        );
+        assert_eq!(
+            moderation_command(&ModerationAction::SetWeather {
+                weather: Weather::Rain,
+                duration_secs: Some(600)
+            })
+            .unwrap(),
+            "weather rain 600"
+        );
+    }
 }
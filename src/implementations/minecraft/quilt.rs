@@ -0,0 +1,314 @@
+use std::path::Path;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use serde_json::Value;
+use tokio::process::Command;
+
+use super::installer::run_installer;
+use super::{Flavour, MinecraftInstance, QuiltInstallerVersion, QuiltLoaderVersion};
+use crate::error::{Error, ErrorKind};
+use crate::upstream_cache::cached_get_text;
+use crate::util::download_file;
+
+const QUILT_META_URL: &str = "https://meta.quiltmc.org/v3";
+/// Downloaded in place of a server jar, quilt servers are set up by running it
+pub const QUILT_INSTALLER_JAR: &str = "quilt-installer.jar";
+/// What the installer leaves behind to start the server with
+pub const QUILT_SERVER_LAUNCH_JAR: &str = "quilt-server-launch.jar";
+/// Default time the quilt installer gets to finish before it's killed
+pub const DEFAULT_QUILT_INSTALLER_TIMEOUT_SECS: u64 = 600;
+
+fn quilt_installer_url(installer_version: &str) -> String {
+    format!(
+        "https://maven.quiltmc.org/repository/release/org/quiltmc/quilt-installer/{0}/quilt-installer-{0}.jar",
+        installer_version
+    )
+}
+
+/// Quilt marks pre-releases with a suffix, e.g. `0.20.0-beta.5`
+fn is_stable_version(version: &str) -> bool {
+    !version.contains('-')
+}
+
+async fn get_quilt_meta(path: &str) -> Result<Value, Error> {
+    Ok(serde_json::from_str(
+        cached_get_text(&format!("{}/{}", QUILT_META_URL, path))
+            .await
+            .context(format!("Failed to get quilt {}", path))?
+            .as_str(),
+    )
+    .context(format!("Failed to parse quilt {}", path))?)
+}
+
+/// The `version` of every entry of a listing from the quilt meta API, newest first
+fn parse_versions(listing: &Value) -> Result<Vec<String>, Error> {
+    listing
+        .as_array()
+        .ok_or_else(|| eyre!("Failed to get quilt versions. Response is not an array"))?
+        .iter()
+        .map(|item| {
+            item["version"]
+                .as_str()
+                .ok_or_else(|| {
+                    eyre!("Failed to get quilt versions. Version string is not a string").into()
+                })
+                .map(|version| version.to_string())
+        })
+        .collect()
+}
+
+/// The loader versions in the listing of loaders for a minecraft version, newest first
+fn parse_loader_versions(listing: &Value) -> Result<Vec<String>, Error> {
+    listing
+        .as_array()
+        .ok_or_else(|| eyre!("Failed to get quilt loaders. Response is not an array"))?
+        .iter()
+        .map(|item| {
+            item["loader"]["version"]
+                .as_str()
+                .ok_or_else(|| {
+                    eyre!("Failed to get quilt loaders. Loader version is not a string").into()
+                })
+                .map(|version| version.to_string())
+        })
+        .collect()
+}
+
+pub async fn get_quilt_minecraft_versions() -> Result<Vec<String>, Error> {
+    parse_versions(&get_quilt_meta("versions/game").await?)
+}
+
+/// The loaders that support minecraft `version`, newest first
+pub async fn get_quilt_loader_versions(version: &str) -> Result<Vec<String>, Error> {
+    parse_loader_versions(&get_quilt_meta(&format!("versions/loader/{}", version)).await?)
+}
+
+pub async fn get_quilt_installer_versions() -> Result<Vec<String>, Error> {
+    parse_versions(&get_quilt_meta("versions/installer").await?)
+}
+
+/// Resolves the versions that aren't given to the newest stable ones for minecraft `version`
+async fn resolve_quilt_versions(
+    version: &str,
+    quilt_loader_version: &Option<QuiltLoaderVersion>,
+    quilt_installer_version: &Option<QuiltInstallerVersion>,
+) -> Option<(QuiltLoaderVersion, QuiltInstallerVersion)> {
+    let loader_version = match quilt_loader_version {
+        Some(QuiltLoaderVersion(l)) => l.clone(),
+        None => get_quilt_loader_versions(version)
+            .await
+            .ok()?
+            .into_iter()
+            .find(|version| is_stable_version(version))?,
+    };
+    let installer_version = match quilt_installer_version {
+        Some(QuiltInstallerVersion(i)) => i.clone(),
+        None => get_quilt_installer_versions()
+            .await
+            .ok()?
+            .into_iter()
+            .find(|version| is_stable_version(version))?,
+    };
+    Some((
+        QuiltLoaderVersion(loader_version),
+        QuiltInstallerVersion(installer_version),
+    ))
+}
+
+/// There is no quilt server jar to download, this is the URL of the installer that sets the server
+/// up. Versions that aren't given are resolved to the newest stable ones
+pub async fn get_quilt_jar_url(
+    version: &str,
+    quilt_loader_version: &Option<QuiltLoaderVersion>,
+    quilt_installer_version: &Option<QuiltInstallerVersion>,
+) -> Option<(String, Flavour)> {
+    let (loader_version, installer_version) =
+        resolve_quilt_versions(version, quilt_loader_version, quilt_installer_version).await?;
+    Some((
+        quilt_installer_url(&installer_version.0),
+        Flavour::Quilt {
+            loader_version: Some(loader_version),
+            installer_version: Some(installer_version),
+        },
+    ))
+}
+
+/// Runs `quilt-installer.jar` in `path_to_instance` to install quilt `loader_version` for minecraft
+/// `version`, downloading the vanilla server jar along with it. The server is then started with
+/// `quilt-server-launch.jar`.
+///
+/// The installer is killed if it doesn't finish within `timeout`, the tail of its output is
+/// included in the error if it fails. `quilt-installer.jar` is removed afterwards either way
+pub async fn run_quilt_installer(
+    jre: &Path,
+    path_to_instance: &Path,
+    version: &str,
+    loader_version: &QuiltLoaderVersion,
+    timeout: Duration,
+) -> Result<(), Error> {
+    let result =
+        run_quilt_installer_in(jre, path_to_instance, version, loader_version, timeout).await;
+    let _ = tokio::fs::remove_file(path_to_instance.join(QUILT_INSTALLER_JAR)).await;
+    result
+}
+
+async fn run_quilt_installer_in(
+    jre: &Path,
+    path_to_instance: &Path,
+    version: &str,
+    loader_version: &QuiltLoaderVersion,
+    timeout: Duration,
+) -> Result<(), Error> {
+    let mut install_dir = std::ffi::OsString::from("--install-dir=");
+    install_dir.push(path_to_instance);
+    let run = run_installer(
+        "Quilt installer",
+        Command::new(jre)
+            .arg("-jar")
+            .arg(path_to_instance.join(QUILT_INSTALLER_JAR))
+            .arg("install")
+            .arg("server")
+            .arg(version)
+            .arg(&loader_version.0)
+            .arg("--download-server")
+            .arg(install_dir)
+            .current_dir(path_to_instance),
+        timeout,
+        |_, _| {},
+        |_| {},
+    )
+    .await?;
+    if !run.status.success() {
+        return Err(Error {
+            kind: ErrorKind::Internal,
+            source: eyre!(
+                "Failed to install quilt server, installer exited with {}. Last output:\n{}",
+                run.status,
+                run.output_tail
+            ),
+        });
+    }
+    if !path_to_instance.join(QUILT_SERVER_LAUNCH_JAR).is_file() {
+        return Err(Error {
+            kind: ErrorKind::Internal,
+            source: eyre!(
+                "Quilt installer finished but {} is missing. Last output:\n{}",
+                QUILT_SERVER_LAUNCH_JAR,
+                run.output_tail
+            ),
+        });
+    }
+    Ok(())
+}
+
+impl MinecraftInstance {
+    /// Installs quilt for minecraft `version` over the current install, with the newest stable
+    /// loader and installer. The installer downloads the new vanilla server jar along with it
+    pub(super) async fn change_quilt_version(&self, version: &str) -> Result<(), Error> {
+        let (loader_version, installer_version) = resolve_quilt_versions(version, &None, &None)
+            .await
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Cannot get a quilt loader for version {}", version),
+            })?;
+        self.backup_before("version-change").await?;
+        download_file(
+            &quilt_installer_url(&installer_version.0),
+            &self.path_to_instance,
+            Some(QUILT_INSTALLER_JAR),
+            &|_| {},
+            true,
+        )
+        .await?;
+        let jre = self.java_path(&self.config.lock().await);
+        run_quilt_installer(
+            &jre,
+            &self.path_to_instance,
+            version,
+            &loader_version,
+            Duration::from_secs(DEFAULT_QUILT_INSTALLER_TIMEOUT_SECS),
+        )
+        .await?;
+        {
+            let mut config = self.config.lock().await;
+            config.version = version.to_string();
+            config.flavour = Flavour::Quilt {
+                loader_version: Some(loader_version),
+                installer_version: Some(installer_version),
+            };
+        }
+        self.write_config_to_file().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_versions() {
+        let listing: Value = serde_json::from_str(
+            r#"[
+                {"url": "https://maven.quiltmc.org/repository/release/org/quiltmc/quilt-installer/0.7.0/quilt-installer-0.7.0.jar", "maven": "org.quiltmc:quilt-installer:0.7.0", "version": "0.7.0"},
+                {"url": "https://maven.quiltmc.org/repository/release/org/quiltmc/quilt-installer/0.6.0/quilt-installer-0.6.0.jar", "maven": "org.quiltmc:quilt-installer:0.6.0", "version": "0.6.0"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            parse_versions(&listing).unwrap(),
+            vec!["0.7.0".to_string(), "0.6.0".to_string()]
+        );
+        assert!(parse_versions(&Value::Null).is_err());
+    }
+
+    #[test]
+    fn test_parse_loader_versions() {
+        let listing: Value = serde_json::from_str(
+            r#"[
+                {"loader": {"separator": ".", "build": 5, "maven": "org.quiltmc:quilt-loader:0.20.0-beta.5", "version": "0.20.0-beta.5"}},
+                {"loader": {"separator": ".", "build": 2, "maven": "org.quiltmc:quilt-loader:0.19.2", "version": "0.19.2"}},
+                {"loader": {"separator": ".", "build": 1, "maven": "org.quiltmc:quilt-loader:0.19.1", "version": "0.19.1"}}
+            ]"#,
+        )
+        .unwrap();
+        let loaders = parse_loader_versions(&listing).unwrap();
+        assert_eq!(loaders, vec!["0.20.0-beta.5", "0.19.2", "0.19.1"]);
+        assert_eq!(
+            loaders
+                .into_iter()
+                .find(|version| is_stable_version(version)),
+            Some("0.19.2".to_string())
+        );
+        assert!(parse_loader_versions(&Value::Null).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_installer_removed_after_failure() {
+        let temp_dir = tempdir::TempDir::new("test_quilt_installer").unwrap();
+        let path = temp_dir.path();
+        tokio::fs::write(path.join(QUILT_INSTALLER_JAR), "installer")
+            .await
+            .unwrap();
+        let error = run_quilt_installer(
+            Path::new("false"),
+            path,
+            "1.20.1",
+            &QuiltLoaderVersion("0.19.2".to_string()),
+            Duration::from_secs(10),
+        )
+        .await
+        .unwrap_err();
+        assert!(error.source.to_string().contains("Failed to install"));
+        assert!(!path.join(QUILT_INSTALLER_JAR).exists());
+    }
+
+    #[test]
+    fn test_quilt_installer_url() {
+        assert_eq!(
+            quilt_installer_url("0.7.0"),
+            "https://maven.quiltmc.org/repository/release/org/quiltmc/quilt-installer/0.7.0/quilt-installer-0.7.0.jar"
+        );
+    }
+}
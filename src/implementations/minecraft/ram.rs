@@ -0,0 +1,127 @@
+//! The RAM new instances are set up with. Modded servers load far more than vanilla ones, so a
+//! single default leaves Forge servers running out of memory on their first start
+
+use sysinfo::SystemExt;
+
+use super::FlavourKind;
+
+/// Left to the OS and the core when fitting a recommendation to the host, in MB
+const HOST_RESERVED_MB: u32 = 1024;
+
+/// What a recommendation is never cut below, however little memory the host has, in MB
+const MIN_RECOMMENDED_MB: u32 = 512;
+
+/// RAM in MB
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecommendedRam {
+    pub min_ram: u32,
+    pub max_ram: u32,
+}
+
+/// What a server of `flavour` needs to run comfortably with a handful of players.
+///
+/// Modpacks aren't looked at: reading what a pack needs from its metadata isn't implemented, so
+/// a large pack gets the Forge recommendation and only the hint says it may need more
+pub fn recommended_ram(flavour: FlavourKind) -> RecommendedRam {
+    let (min_ram, max_ram) = match flavour {
+        FlavourKind::Vanilla => (1024, 2048),
+        FlavourKind::Paper | FlavourKind::Spigot => (1024, 3072),
+        FlavourKind::Fabric | FlavourKind::Quilt => (2048, 4096),
+        FlavourKind::Forge => (2048, 6144),
+    };
+    RecommendedRam { min_ram, max_ram }
+}
+
+/// Cuts `recommended` down so that it leaves the host some memory of its own
+pub fn fit_to_host(recommended: RecommendedRam, host_total_mb: u32) -> RecommendedRam {
+    let max_ram = recommended.max_ram.min(
+        host_total_mb
+            .saturating_sub(HOST_RESERVED_MB)
+            .max(MIN_RECOMMENDED_MB),
+    );
+    RecommendedRam {
+        min_ram: recommended.min_ram.min(max_ram),
+        max_ram,
+    }
+}
+
+/// Shown next to the max RAM setting of the setup
+pub fn ram_hint(flavour: FlavourKind, recommended: RecommendedRam) -> String {
+    let reason = match flavour {
+        FlavourKind::Vanilla => "for a vanilla server",
+        FlavourKind::Paper | FlavourKind::Spigot => "for a server with a few plugins",
+        FlavourKind::Fabric | FlavourKind::Quilt => "for a server with a few mods",
+        FlavourKind::Forge => "for a modded server, large modpacks may need 8192 MB or more",
+    };
+    format!("{} MB is recommended {}", recommended.max_ram, reason)
+}
+
+/// Total memory of the machine in MB
+pub fn host_total_mb() -> u32 {
+    let mut system = sysinfo::System::new();
+    system.refresh_memory();
+    (system.total_memory() / 1024 / 1024) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recommended_ram() {
+        assert_eq!(
+            recommended_ram(FlavourKind::Vanilla),
+            RecommendedRam {
+                min_ram: 1024,
+                max_ram: 2048
+            }
+        );
+        assert_eq!(
+            recommended_ram(FlavourKind::Forge),
+            RecommendedRam {
+                min_ram: 2048,
+                max_ram: 6144
+            }
+        );
+        for flavour in [
+            FlavourKind::Vanilla,
+            FlavourKind::Fabric,
+            FlavourKind::Quilt,
+            FlavourKind::Paper,
+            FlavourKind::Spigot,
+        ] {
+            assert!(recommended_ram(flavour).max_ram < recommended_ram(FlavourKind::Forge).max_ram);
+        }
+    }
+
+    #[test]
+    fn test_fit_to_host() {
+        let forge = recommended_ram(FlavourKind::Forge);
+        // plenty of memory, left as is
+        assert_eq!(fit_to_host(forge, 32768), forge);
+        assert_eq!(
+            fit_to_host(forge, 4096),
+            RecommendedRam {
+                min_ram: 2048,
+                max_ram: 3072
+            }
+        );
+        // a tiny host still gets something that can start
+        assert_eq!(
+            fit_to_host(forge, 1024),
+            RecommendedRam {
+                min_ram: 512,
+                max_ram: 512
+            }
+        );
+    }
+
+    #[test]
+    fn test_ram_hint() {
+        let hint = ram_hint(
+            FlavourKind::Forge,
+            fit_to_host(recommended_ram(FlavourKind::Forge), 4096),
+        );
+        assert!(hint.starts_with("3072 MB"));
+    }
+}
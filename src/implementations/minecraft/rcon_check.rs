@@ -0,0 +1,196 @@
+//! Trying the RCON settings of an instance out, telling why RCON doesn't work if it doesn't.
+//! The password is only ever sent to the server, never included in a diagnosis or logged
+
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::eyre;
+use tokio::net::TcpStream;
+
+use crate::error::{Error, ErrorKind};
+use crate::traits::t_server::{RconTestResult, State, TServer};
+
+use super::MinecraftInstance;
+
+/// How long connecting and running the test command may take altogether
+const RCON_TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Harmless, and every flavour has it
+const RCON_TEST_COMMAND: &str = "list";
+
+/// Why RCON can't be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RconDiagnosis {
+    NotRunning,
+    /// `enable-rcon` is off, or the port or password isn't set
+    Disabled,
+    /// Nothing is listening on the port, e.g. the server is bound to another one
+    PortClosed {
+        port: u32,
+    },
+    AuthFailed {
+        port: u32,
+    },
+    /// The port accepted the connection but nothing answered
+    NoResponse {
+        port: u32,
+    },
+    Failed {
+        port: u32,
+        message: String,
+    },
+}
+
+impl Display for RconDiagnosis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RconDiagnosis::NotRunning => write!(f, "The server is not running"),
+            RconDiagnosis::Disabled => write!(
+                f,
+                "RCON is not enabled, set enable-rcon, rcon.port and rcon.password"
+            ),
+            RconDiagnosis::PortClosed { port } => write!(
+                f,
+                "Nothing is listening for RCON on port {}, check rcon.port and restart the server",
+                port
+            ),
+            RconDiagnosis::AuthFailed { port } => write!(
+                f,
+                "The server on port {} rejected the RCON password, check rcon.password",
+                port
+            ),
+            RconDiagnosis::NoResponse { port } => write!(
+                f,
+                "Port {} accepted the connection but didn't answer, it may not be RCON",
+                port
+            ),
+            RconDiagnosis::Failed { port, message } => {
+                write!(f, "RCON on port {} failed: {}", port, message)
+            }
+        }
+    }
+}
+
+impl From<RconDiagnosis> for Error {
+    fn from(diagnosis: RconDiagnosis) -> Self {
+        Error {
+            kind: ErrorKind::RconNotOpen,
+            source: eyre!(diagnosis.to_string()),
+        }
+    }
+}
+
+fn diagnose(port: u32, e: rcon::Error) -> RconDiagnosis {
+    match e {
+        rcon::Error::Auth => RconDiagnosis::AuthFailed { port },
+        rcon::Error::Io(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+            RconDiagnosis::PortClosed { port }
+        }
+        e => RconDiagnosis::Failed {
+            port,
+            message: e.to_string(),
+        },
+    }
+}
+
+/// Connects to RCON on `port` of this host and runs the test command
+pub async fn test_rcon(
+    port: u32,
+    password: &str,
+    timeout: Duration,
+) -> Result<RconTestResult, RconDiagnosis> {
+    let started_at = Instant::now();
+    let run = async {
+        <rcon::Connection<TcpStream>>::builder()
+            .enable_minecraft_quirks(true)
+            .connect(&format!("localhost:{}", port), password)
+            .await?
+            .cmd(RCON_TEST_COMMAND)
+            .await
+    };
+    match tokio::time::timeout(timeout, run).await {
+        Ok(Ok(response)) => Ok(RconTestResult {
+            port,
+            response,
+            latency_ms: started_at.elapsed().as_millis() as u64,
+        }),
+        Ok(Err(e)) => Err(diagnose(port, e)),
+        Err(_) => Err(RconDiagnosis::NoResponse { port }),
+    }
+}
+
+impl MinecraftInstance {
+    /// Tests a connection of its own, the one commands are sent over is left alone
+    pub(super) async fn check_rcon(&self) -> Result<RconTestResult, Error> {
+        if self.state().await != State::Running {
+            return Err(RconDiagnosis::NotRunning.into());
+        }
+        let (port, password) = self.rcon_settings().await.ok_or(RconDiagnosis::Disabled)?;
+        Ok(test_rcon(port, &password, RCON_TEST_TIMEOUT).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    async fn listen() -> (TcpListener, u32) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port() as u32;
+        (listener, port)
+    }
+
+    #[tokio::test]
+    async fn test_port_closed() {
+        let (listener, port) = listen().await;
+        drop(listener);
+        let diagnosis = test_rcon(port, "hunter2", Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert_eq!(diagnosis, RconDiagnosis::PortClosed { port });
+    }
+
+    #[tokio::test]
+    async fn test_auth_failed() {
+        let (listener, port) = listen().await;
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let length = stream.read_i32_le().await.unwrap();
+            let mut auth = vec![0; length as usize];
+            stream.read_exact(&mut auth).await.unwrap();
+            // an auth response with the request id -1 is a rejection
+            let mut response = Vec::new();
+            response.extend(10_i32.to_le_bytes());
+            response.extend((-1_i32).to_le_bytes());
+            response.extend(2_i32.to_le_bytes());
+            response.extend([0, 0]);
+            stream.write_all(&response).await.unwrap();
+            // hold the connection open until the client is done
+            let _ = stream.read(&mut [0; 1]).await;
+        });
+        let diagnosis = test_rcon(port, "hunter2", Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert_eq!(diagnosis, RconDiagnosis::AuthFailed { port });
+        assert!(!Error::from(diagnosis)
+            .source
+            .to_string()
+            .contains("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn test_no_response() {
+        let (listener, port) = listen().await;
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+        let diagnosis = test_rcon(port, "hunter2", Duration::from_millis(500))
+            .await
+            .unwrap_err();
+        assert_eq!(diagnosis, RconDiagnosis::NoResponse { port });
+    }
+}
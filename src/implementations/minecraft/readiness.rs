@@ -0,0 +1,294 @@
+//! Checks for whether a starting server is ready to take players, and the watchdog that kills
+//! a server that doesn't get there in time
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tracing::{error, warn};
+use ts_rs::TS;
+
+use crate::error::Error;
+use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::traits::t_server::{State, TServer};
+use crate::types::Snowflake;
+
+use super::MinecraftInstance;
+
+/// How often the server is probed while waiting for it to be ready
+const PROBE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What counts as the server being ready
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub enum ReadinessProbe {
+    /// The server printed its "Done" line
+    #[default]
+    ConsoleLine,
+    /// The server answers a server list ping
+    Ping,
+    /// An RCON connection to the server can be opened
+    Rcon,
+}
+
+fn write_varint(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value & !0x7F == 0 {
+            buf.push(value as u8);
+            return;
+        }
+        buf.push((value & 0x7F | 0x80) as u8);
+        value >>= 7;
+    }
+}
+
+async fn read_varint(stream: &mut TcpStream) -> Result<i32, Error> {
+    let mut value = 0_u32;
+    for i in 0..5 {
+        let byte = stream
+            .read_u8()
+            .await
+            .context("Failed to read server list ping response")?;
+        value |= ((byte & 0x7F) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    Err(eyre!("Invalid VarInt in server list ping response").into())
+}
+
+fn packet(id: i32, data: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_varint(&mut body, id);
+    body.extend_from_slice(data);
+    let mut packet = Vec::new();
+    write_varint(&mut packet, body.len() as i32);
+    packet.extend(body);
+    packet
+}
+
+/// Sends a server list ping to the server on `port`, succeeding if it answers with its status
+pub async fn ping(port: u16) -> Result<(), Error> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port))
+        .await
+        .context(format!("Failed to connect to port {port}"))?;
+    let host = "localhost";
+    let mut handshake = Vec::new();
+    // -1 as the protocol version, as the server answers a status request whatever it is
+    write_varint(&mut handshake, -1);
+    write_varint(&mut handshake, host.len() as i32);
+    handshake.extend_from_slice(host.as_bytes());
+    handshake.extend_from_slice(&port.to_be_bytes());
+    // next state: status
+    write_varint(&mut handshake, 1);
+    stream
+        .write_all(&packet(0x00, &handshake))
+        .await
+        .context("Failed to send server list ping")?;
+    stream
+        .write_all(&packet(0x00, &[]))
+        .await
+        .context("Failed to send server list ping")?;
+
+    let length = read_varint(&mut stream).await?;
+    let id = read_varint(&mut stream).await?;
+    if length <= 1 || id != 0x00 {
+        return Err(eyre!("Unexpected server list ping response (packet {id:#x})").into());
+    }
+    Ok(())
+}
+
+async fn probe_once(
+    probe: ReadinessProbe,
+    state: &Mutex<State>,
+    port: u16,
+    rcon: Option<&(u32, String)>,
+) -> bool {
+    match probe {
+        ReadinessProbe::ConsoleLine => *state.lock().await == State::Running,
+        ReadinessProbe::Ping => matches!(
+            tokio::time::timeout(PROBE_INTERVAL, ping(port)).await,
+            Ok(Ok(()))
+        ),
+        ReadinessProbe::Rcon => match rcon {
+            Some((rcon_port, rcon_psw)) => matches!(
+                tokio::time::timeout(
+                    PROBE_INTERVAL,
+                    <rcon::Connection<TcpStream>>::builder()
+                        .enable_minecraft_quirks(true)
+                        .connect(&format!("localhost:{}", rcon_port), rcon_psw),
+                )
+                .await,
+                Ok(Ok(_))
+            ),
+            None => false,
+        },
+    }
+}
+
+/// Waits for `probe` to pass, failing if it doesn't within `timeout` or the server stops first
+pub async fn wait_until_ready(
+    probe: ReadinessProbe,
+    state: Arc<Mutex<State>>,
+    port: u16,
+    rcon: Option<(u32, String)>,
+    timeout: Duration,
+) -> Result<(), Error> {
+    let wait = async {
+        loop {
+            if probe_once(probe, &state, port, rcon.as_ref()).await {
+                return Ok(());
+            }
+            if *state.lock().await == State::Stopped {
+                return Err(eyre!("Server stopped before it was ready"));
+            }
+            tokio::time::sleep(PROBE_INTERVAL).await;
+        }
+    };
+    match tokio::time::timeout(timeout, wait).await {
+        Ok(res) => res.map_err(Error::from),
+        Err(_) => Err(eyre!(
+            "Server wasn't ready within {} seconds ({:?} probe)",
+            timeout.as_secs(),
+            probe
+        )
+        .into()),
+    }
+}
+
+impl MinecraftInstance {
+    /// Kills the server started as `pid` if it isn't ready within the configured readiness timeout
+    pub(super) fn spawn_readiness_watchdog(&self, pid: Option<u32>) {
+        let mut instance = self.clone();
+        tokio::spawn(async move {
+            let config = instance.config.lock().await.clone();
+            let timeout_secs = match config.readiness_timeout_secs {
+                Some(timeout_secs) => timeout_secs,
+                None => return,
+            };
+            let probe = config.readiness_probe.unwrap_or_default();
+            let rcon = if probe == ReadinessProbe::Rcon {
+                instance.rcon_settings().await
+            } else {
+                None
+            };
+            let e = match wait_until_ready(
+                probe,
+                instance.state.clone(),
+                config.port as u16,
+                rcon,
+                Duration::from_secs(timeout_secs as u64),
+            )
+            .await
+            {
+                Ok(()) => return,
+                Err(e) => e,
+            };
+            // the server may have exited, or been restarted, in the meantime
+            let current_pid = instance.process.lock().await.as_ref().and_then(|p| p.id());
+            if current_pid.is_none() || current_pid != pid {
+                return;
+            }
+            warn!("[{}] {}, killing it", config.name, e.source);
+            instance.event_broadcaster.send(Event {
+                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                    instance_uuid: instance.uuid.clone(),
+                    instance_name: config.name.clone(),
+                    instance_event_inner: InstanceEventInner::InstanceError {
+                        message: format!("{}, so it was stopped", e.source),
+                    },
+                }),
+                snowflake: Snowflake::default(),
+                details: "Server wasn't ready in time".to_string(),
+                caused_by: CausedBy::System,
+            });
+            if let Err(e) = instance.kill(CausedBy::System).await {
+                error!(
+                    "[{}] Failed to kill server that wasn't ready: {}",
+                    config.name, e
+                );
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_readiness_timeout() {
+        // accepts connections but never answers, like a server that hangs while loading
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+        let state = Arc::new(Mutex::new(State::Starting));
+        assert!(wait_until_ready(
+            ReadinessProbe::Ping,
+            state.clone(),
+            port,
+            None,
+            Duration::from_millis(500)
+        )
+        .await
+        .is_err());
+        assert!(wait_until_ready(
+            ReadinessProbe::ConsoleLine,
+            state.clone(),
+            port,
+            None,
+            Duration::from_millis(500)
+        )
+        .await
+        .is_err());
+
+        *state.lock().await = State::Running;
+        assert!(wait_until_ready(
+            ReadinessProbe::ConsoleLine,
+            state,
+            port,
+            None,
+            Duration::from_millis(500)
+        )
+        .await
+        .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0_u8; 64];
+            let _ = stream.read(&mut buf).await.unwrap();
+            let status = br#"{"version":{"name":"1.20.1","protocol":763}}"#;
+            let mut data = Vec::new();
+            write_varint(&mut data, status.len() as i32);
+            data.extend_from_slice(status);
+            stream.write_all(&packet(0x00, &data)).await.unwrap();
+        });
+        assert!(ping(port).await.is_ok());
+    }
+
+    #[test]
+    fn test_write_varint() {
+        let mut buf = Vec::new();
+        write_varint(&mut buf, 300);
+        assert_eq!(buf, vec![0xAC, 0x02]);
+        buf.clear();
+        write_varint(&mut buf, -1);
+        assert_eq!(buf, vec![0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+    }
+}
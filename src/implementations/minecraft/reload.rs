@@ -0,0 +1,286 @@
+//! Picking up edits made to the config and properties of an instance outside of the core, e.g.
+//! through the file manager, which would otherwise be overwritten with what the core has in memory
+
+use std::path::Path;
+use std::sync::atomic;
+
+use color_eyre::eyre::{eyre, Context};
+use indexmap::IndexMap;
+use tracing::{info, warn};
+
+use crate::error::{Error, ErrorKind};
+use crate::traits::t_configurable::PropertyChange;
+use crate::traits::t_server::State;
+use crate::util::validate_env;
+
+use super::args::migrate_cmd_args;
+use super::configurable::{validate_ram, ServerPropertySetting};
+use super::crash_report::validate_upload_url;
+use super::util::{diff_properties, live_property_command};
+use super::{BackupInstruction, MinecraftInstance, RestoreConfig};
+
+/// Reads the config of an instance, splitting the command line arguments of configs written
+/// before JVM and server arguments were kept apart
+pub(super) fn read_restore_config(path_to_config: &Path) -> Result<RestoreConfig, Error> {
+    let mut restore_config: serde_json::Value = serde_json::from_reader(
+        std::fs::File::open(path_to_config).context(format!(
+            "Failed to open config file at {}",
+            path_to_config.display()
+        ))?,
+    )
+    .context("Failed to deserialize config from string. Was the config file modified manually?")?;
+    migrate_cmd_args(&mut restore_config);
+    Ok(serde_json::from_value(restore_config).context(
+        "Failed to deserialize config from string. Was the config file modified manually?",
+    )?)
+}
+
+impl MinecraftInstance {
+    /// The properties as the core has them in memory
+    async fn property_values(&self) -> IndexMap<String, String> {
+        self.configurable_manifest
+            .lock()
+            .await
+            .get_section(ServerPropertySetting::get_section_id())
+            .map(|section| {
+                section
+                    .all_settings()
+                    .iter()
+                    .filter_map(|(key, setting)| {
+                        Some((key.clone(), setting.get_value()?.to_string()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Applies the changed properties that can be to the running server, the rest take effect on
+    /// the next start
+    pub(super) async fn apply_live_properties(&self, changes: &[PropertyChange]) {
+        if *self.state.lock().await != State::Running {
+            return;
+        }
+        for change in changes {
+            let command = match change
+                .new_value
+                .as_deref()
+                .and_then(|value| live_property_command(&change.key, value))
+            {
+                Some(command) => command,
+                None => continue,
+            };
+            if let Err(e) = self.query_rcon(&command).await {
+                warn!(
+                    "[{}] Failed to apply {} to the running server: {}",
+                    self.config.lock().await.name,
+                    change.key,
+                    e.source
+                );
+            }
+        }
+    }
+
+    /// Runs the checks the setters run on a config read from disk, which could have been edited to
+    /// anything
+    pub(super) async fn validate_config(&self, config: &RestoreConfig) -> Result<(), Error> {
+        validate_ram(config.min_ram, config.max_ram, self.host_total_mb().await)?;
+        if config.max_backups == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("At least one backup must be kept"),
+            });
+        }
+        if let Some(url) = &config.crash_report_upload_url {
+            validate_upload_url(url)?;
+        }
+        validate_env(&config.env)?;
+        config.hooks.validate(&self.path_to_macros)?;
+        config.launch_mode.validate(&self.path_to_instance)
+    }
+
+    /// Re-reads the config and `server.properties` from disk without restarting the server,
+    /// returning the properties that changed. The identity of the instance in `.lodestone_config`
+    /// is left as is. Nothing is reloaded if the config fails the checks its setters run
+    pub(super) async fn reload_from_disk(&mut self) -> Result<Vec<PropertyChange>, Error> {
+        self.ensure_not_missing().await?;
+        let config = read_restore_config(&self.path_to_config)?;
+        self.validate_config(&config).await?;
+        let old_properties = self.property_values().await;
+
+        self.auto_start
+            .store(config.auto_start, atomic::Ordering::Relaxed);
+        self.restart_on_crash
+            .store(config.restart_on_crash, atomic::Ordering::Relaxed);
+        if config.backup_period != self.config.lock().await.backup_period {
+            self.backup_period = config.backup_period;
+            self.backup_sender
+                .send(BackupInstruction::SetPeriod(config.backup_period))
+                .context("Backup task is not running")?;
+        }
+        if config.max_backups != self.config.lock().await.max_backups {
+            self.backup_sender
+                .send(BackupInstruction::SetMaxBackups(config.max_backups))
+                .context("Backup task is not running")?;
+        }
+        if config.backups_paused != self.config.lock().await.backups_paused {
+            self.backup_sender
+                .send(if config.backups_paused {
+                    BackupInstruction::Pause
+                } else {
+                    BackupInstruction::Resume
+                })
+                .context("Backup task is not running")?;
+        }
+        let java_cmd = self.java_path(&config).to_string_lossy().to_string();
+        *self.configurable_manifest.lock().await =
+            Self::init_configurable_manifest(&config, java_cmd);
+        *self.config.lock().await = config;
+        self.read_properties().await?;
+        // the port is in both files, server.properties wins as it's what the server reads
+        self.sync_configurable_to_restore_config().await;
+        self.write_config_to_file().await?;
+
+        let changes = diff_properties(&old_properties, &self.property_values().await);
+        info!(
+            "[{}] Reloaded config from disk, {} properties changed",
+            self.config.lock().await.name,
+            changes.len()
+        );
+        self.apply_live_properties(&changes).await;
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_broadcaster::EventBroadcaster;
+    use crate::macro_executor::MacroExecutor;
+    use crate::prelude::init_paths;
+    use crate::traits::t_configurable::{GameType, TConfigurable};
+    use crate::types::{DotLodestoneConfig, InstanceUuid};
+
+    fn write_config(path_to_instance: &Path, name: &str, max_ram: u32, backup_period: Option<u32>) {
+        std::fs::write(
+            path_to_instance.join(".lodestone_minecraft_config.json"),
+            serde_json::json!({
+                "name": name,
+                "version": "1.20.1",
+                "flavour": "vanilla",
+                "description": "",
+                "jvm_args": ["-XX:+UseG1GC"],
+                "program_args": ["nogui"],
+                "java_cmd": null,
+                "port": 25565,
+                "min_ram": 256,
+                "max_ram": max_ram,
+                "auto_start": false,
+                "restart_on_crash": false,
+                "backup_period": backup_period,
+                "jre_major_version": 17,
+                "has_started": true,
+            })
+            .to_string(),
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reload_picks_up_edits_on_disk() {
+        let temp_lodestone_path = tempfile::tempdir().unwrap();
+        init_paths(temp_lodestone_path.path().to_path_buf(), None);
+        let temp_dir = tempdir::TempDir::new("test_reload").unwrap();
+        let path_to_instance = temp_dir.path();
+        let dot_lodestone_config =
+            DotLodestoneConfig::new(InstanceUuid::default(), GameType::MinecraftJava);
+        std::fs::write(
+            path_to_instance.join(".lodestone_config"),
+            serde_json::to_string(&dot_lodestone_config).unwrap(),
+        )
+        .unwrap();
+        write_config(path_to_instance, "before", 512, None);
+        std::fs::write(
+            path_to_instance.join("server.properties"),
+            "server-port=25565\nmotd=Hello\ndifficulty=easy\n",
+        )
+        .unwrap();
+        let (event_broadcaster, _rx) = EventBroadcaster::new(64);
+        let mut instance = MinecraftInstance::restore(
+            path_to_instance.to_path_buf(),
+            dot_lodestone_config,
+            event_broadcaster.clone(),
+            MacroExecutor::new(event_broadcaster),
+        )
+        .await
+        .unwrap();
+
+        // edited through the file manager
+        write_config(path_to_instance, "after", 1024, Some(3600));
+        std::fs::write(
+            path_to_instance.join("server.properties"),
+            "server-port=25570\nmotd=Hello\ndifficulty=hard\nmax-players=5\n",
+        )
+        .unwrap();
+        let changes = instance.reload_from_disk().await.unwrap();
+
+        assert_eq!(
+            changes,
+            vec![
+                PropertyChange {
+                    key: "server-port".to_string(),
+                    old_value: Some("25565".to_string()),
+                    new_value: Some("25570".to_string()),
+                },
+                PropertyChange {
+                    key: "difficulty".to_string(),
+                    old_value: Some("easy".to_string()),
+                    new_value: Some("hard".to_string()),
+                },
+                PropertyChange {
+                    key: "max-players".to_string(),
+                    old_value: None,
+                    new_value: Some("5".to_string()),
+                },
+            ]
+        );
+        assert_eq!(instance.name().await, "after");
+        {
+            let config = instance.config.lock().await;
+            assert_eq!(config.backup_period, Some(3600));
+            assert_eq!(config.max_ram, 1024);
+            assert_eq!(config.jvm_args, vec!["-XX:+UseG1GC".to_string()]);
+            // taken from server.properties
+            assert_eq!(config.port, 25570);
+        }
+        let manifest = instance.configurable_manifest().await;
+        assert_eq!(
+            manifest
+                .get_unique_setting_key("max_ram")
+                .and_then(|setting| setting.get_value())
+                .map(|value| value.to_string()),
+            Some("1024".to_string())
+        );
+
+        // nothing edited since
+        assert!(instance.reload_from_disk().await.unwrap().is_empty());
+
+        // edited to something the setters would refuse, nothing is reloaded
+        write_config(path_to_instance, "invalid", 0, None);
+        assert!(instance.reload_from_disk().await.is_err());
+        let mut config: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(path_to_instance.join(".lodestone_minecraft_config.json"))
+                .unwrap(),
+        )
+        .unwrap();
+        config["max_ram"] = serde_json::json!(1024);
+        config["max_backups"] = serde_json::json!(0);
+        std::fs::write(
+            path_to_instance.join(".lodestone_minecraft_config.json"),
+            config.to_string(),
+        )
+        .unwrap();
+        assert!(instance.reload_from_disk().await.is_err());
+        assert_eq!(instance.name().await, "after");
+        assert_eq!(instance.config.lock().await.max_backups, None);
+    }
+}
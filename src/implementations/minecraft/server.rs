@@ -1,38 +1,84 @@
+use std::collections::VecDeque;
+use std::ffi::OsString;
 use std::path::PathBuf;
 use std::process::Stdio;
-use std::time::Duration;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use color_eyre::eyre::{eyre, Context};
-use sysinfo::{Pid, PidExt, ProcessExt, SystemExt};
+use sysinfo::{Pid, PidExt, ProcessExt, Signal, SystemExt};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::Command;
 
+use crate::command_history::CommandHistoryEntry;
+use crate::console_buffer::ConsoleLog;
 use crate::error::{Error, ErrorKind};
 use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
 use crate::implementations::minecraft::line_parser::{
-    parse_player_joined, parse_player_left, parse_player_msg, parse_server_started,
-    parse_system_msg, PlayerMessage,
+    parse_out_of_memory, parse_player_joined, parse_player_left, parse_player_msg,
+    parse_server_started, parse_system_msg, PlayerMessage,
 };
 use crate::implementations::minecraft::player::MinecraftPlayer;
-use crate::implementations::minecraft::util::name_to_uuid;
+use crate::implementations::minecraft::util::{
+    name_to_uuid, process_liveness, state_has_drifted, suggest_max_ram, ProcessLiveness,
+};
+use crate::instance_debug::{debug_detail, instance_span};
 use crate::macro_executor::SpawnResult;
+use crate::start_limiter::{start_limiter, StartPermit};
 use crate::traits::t_configurable::TConfigurable;
 use crate::traits::t_macro::TaskEntry;
-use crate::traits::t_server::{MonitorReport, State, StateAction, TServer};
+use crate::traits::t_server::{
+    CrashInfo, MonitorReport, RconTestResult, State, StateAction, StopCommand, TServer,
+    TickPerformance, VerifyReport, DEFAULT_STOP_TIMEOUT_SECS,
+};
 
+use crate::process_scheduling::ProcessScheduling;
 use crate::types::Snowflake;
-use crate::util::{dont_spawn_terminal, list_dir};
+use crate::util::{dont_spawn_terminal, find_orphaned_process, list_dir, redact_env};
 
+use super::args::launch_jvm_args;
+use super::configurable::CmdArgSetting;
+use super::console_matcher::{parse_pattern, wait_for_console_line};
+use super::crash_report::newest_crash_report;
+use super::hooks::LifecycleHook;
+use super::launch::{LaunchCommand, LaunchMode};
 use super::r#macro::{resolve_macro_invocation, MinecraftMainWorkerGenerator};
-use super::{Flavour, ForgeBuildVersion, MinecraftInstance};
-use tracing::{error, info, warn};
+use super::shutdown::{
+    escalate_stop, write_stop_sequence, SAVED_PATTERN, SAVE_BEFORE_STOP_TIMEOUT,
+};
+use super::{Flavour, ForgeBuildVersion, MinecraftInstance, RestoreConfig};
+use tracing::{error, info, warn, Instrument};
+
+/// Number of console lines kept around to be attached to a crash report
+const CRASH_CONSOLE_TAIL_LINES: usize = 50;
 
 #[async_trait::async_trait]
 impl TServer for MinecraftInstance {
     async fn start(&mut self, cause_by: CausedBy, block: bool) -> Result<(), Error> {
+        self.start_checked(cause_by, block, false).await
+    }
+    async fn start_allowing_downgrade(
+        &mut self,
+        cause_by: CausedBy,
+        block: bool,
+    ) -> Result<(), Error> {
+        self.start_checked(cause_by, block, true).await
+    }
+    async fn stop(&mut self, cause_by: CausedBy, block: bool) -> Result<(), Error> {
+        // the hook runs under the lifecycle lock too, so a start can't slip in while it runs
+        let lifecycle_guard = self.lifecycle_lock.clone().lock_owned().await;
+        // rejected up front, so that the hook only runs for a stop that goes ahead
+        self.state
+            .lock()
+            .await
+            .try_new_state(StateAction::UserStop, None)?;
+        // a failing hook doesn't keep the server from stopping, and is reported by itself
+        let _ = self.run_hook(LifecycleHook::BeforeStop).await;
         let config = self.config.lock().await.clone();
+        let stop_command = self.stop_command().await;
+
         self.state.lock().await.try_transition(
-            StateAction::UserStart,
+            StateAction::UserStop,
             Some(&|state| {
                 self.event_broadcaster.send(Event {
                     event_inner: EventInner::InstanceEvent(InstanceEvent {
@@ -41,18 +87,590 @@ impl TServer for MinecraftInstance {
                         instance_event_inner: InstanceEventInner::StateTransition { to: state },
                     }),
                     snowflake: Snowflake::default(),
-                    details: "Starting server".to_string(),
+                    details: "Stopping server".to_string(),
                     caused_by: cause_by.clone(),
                 });
             }),
         )?;
+        let name = config.name.clone();
+        let _uuid = self.uuid.clone();
+        let pid = self.process.lock().await.as_ref().and_then(|p| p.id());
+        match &stop_command.command {
+            Some(command) => {
+                // listening before the save is sent, so that a quick save isn't missed
+                let event_broadcaster = self.event_broadcaster.clone();
+                let instance_uuid = self.uuid.clone();
+                let saved = config.save_before_stop.unwrap_or(true).then(|| {
+                    let event_receiver = event_broadcaster.subscribe();
+                    async move {
+                        wait_for_console_line(
+                            &event_broadcaster,
+                            event_receiver,
+                            &instance_uuid,
+                            &parse_pattern(SAVED_PATTERN)?,
+                            SAVE_BEFORE_STOP_TIMEOUT,
+                        )
+                        .await
+                    }
+                });
+                write_stop_sequence(&self.stdin, command, saved)
+                    .await
+                    .context("Failed to write to stdin")
+                    .map_err(|e| {
+                        error!("[{}] Failed to stop instance: {}", name, e);
+                        e
+                    })?;
+            }
+            None => {
+                if !self.terminate_process(pid).await {
+                    return Err(
+                        eyre!("Failed to stop instance: failed to terminate the process").into(),
+                    );
+                }
+            }
+        }
+        self.rcon_conn.lock().await.take();
+        if let Some(pid) = pid {
+            self.spawn_stop_escalation(pid, stop_command.timeout_secs);
+        }
+        let mut rx = self.event_broadcaster.subscribe();
+        let instance_uuid = self.uuid.clone();
+        // the stop is under way, waiting for it mustn't hold up a kill
+        drop(lifecycle_guard);
+
+        if block {
+            while let Ok(event) = rx.recv().await {
+                if let EventInner::InstanceEvent(InstanceEvent {
+                    instance_uuid: event_instance_uuid,
+                    instance_event_inner: InstanceEventInner::StateTransition { to },
+                    ..
+                }) = event.event_inner
+                {
+                    if instance_uuid == event_instance_uuid && to == State::Stopped {
+                        return Ok(());
+                    }
+                }
+            }
+            Err(eyre!("Sender shutdown").into())
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn stop_command(&self) -> StopCommand {
+        StopCommand {
+            command: Some("stop".to_string()),
+            timeout_secs: self
+                .config
+                .lock()
+                .await
+                .stop_timeout_secs
+                .unwrap_or(DEFAULT_STOP_TIMEOUT_SECS),
+        }
+    }
+
+    async fn restart(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error> {
+        if block {
+            self.stop(caused_by.clone(), block).await?;
+            self.start(caused_by, block).await
+        } else {
+            self.state
+                .lock()
+                .await
+                .try_new_state(StateAction::UserStop, None)?;
+
+            let mut __self = self.clone();
+            tokio::task::spawn(async move {
+                if let Err(e) = self.stop(caused_by.clone(), true).await {
+                    error!(
+                        "[{}] Failed to stop instance for restart: {}",
+                        self.name().await,
+                        e
+                    );
+                    return;
+                }
+                if let Err(e) = self.start(caused_by, block).await {
+                    error!(
+                        "[{}] Failed to start instance for restart: {}",
+                        self.name().await,
+                        e
+                    );
+                }
+            });
+            Ok(())
+        }
+    }
+
+    async fn kill(&mut self, cause_by: CausedBy) -> Result<(), Error> {
+        let _lifecycle_guard = self.lifecycle_lock.clone().lock_owned().await;
+        let config = self.config.lock().await.clone();
+
+        if let Some(pid) = self.orphaned_pid().await {
+            info!(
+                "[{}] Killing server process (PID {}) left over from a previous session",
+                config.name.clone(),
+                pid
+            );
+            let killed = self
+                .system
+                .lock()
+                .await
+                .process(pid)
+                .map(|p| p.kill())
+                .unwrap_or(false);
+            if !killed {
+                return Err(eyre!("Failed to kill process {}", pid).into());
+            }
+            self.persist_pid(None).await?;
+            return Ok(());
+        }
+
+        if self.state().await == State::Stopped {
+            warn!("[{}] Instance is already stopped", config.name.clone());
+            return Err(eyre!("Instance is already stopped").into());
+        }
+        // the exit is intentional, so it must not be recorded as a crash.
+        // a stop that hangs can still be killed, as the instance is already stopping
+        let mut state = self.state.lock().await;
+        if *state != State::Stopping {
+            state.try_transition(
+                StateAction::UserStop,
+                Some(&|state| {
+                    self.event_broadcaster.send(Event {
+                        event_inner: EventInner::InstanceEvent(InstanceEvent {
+                            instance_name: config.name.clone(),
+                            instance_uuid: self.uuid.clone(),
+                            instance_event_inner: InstanceEventInner::StateTransition { to: state },
+                        }),
+                        snowflake: Snowflake::default(),
+                        details: "Killing server".to_string(),
+                        caused_by: cause_by.clone(),
+                    });
+                }),
+            )?;
+        }
+        drop(state);
+        self.killed.store(true, Ordering::Relaxed);
+        self.process
+            .lock()
+            .await
+            .as_mut()
+            .ok_or_else(|| {
+                error!(
+                    "[{}] Failed to kill instance: process not available",
+                    config.name.clone()
+                );
+                eyre!("Failed to kill instance: process not available")
+            })?
+            .kill()
+            .await
+            .context("Failed to kill process")
+            .map_err(|e| {
+                error!("[{}] Failed to kill instance: {}", config.name.clone(), e);
+                e
+            })?;
+        Ok(())
+    }
+
+    async fn force_stop(&mut self, caused_by: CausedBy) -> Result<(), Error> {
+        // deliberately doesn't take the lifecycle lock, as it's the way out of a start or stop that hangs
+        let name = self.config.lock().await.name.clone();
+        let previous_state = std::mem::replace(&mut *self.state.lock().await, State::Stopping);
+        warn!(
+            "[{}] Force stopping instance (was {})",
+            name,
+            previous_state.to_string()
+        );
+        let send_transition = |state: State| {
+            self.event_broadcaster.send(Event {
+                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                    instance_name: name.clone(),
+                    instance_uuid: self.uuid.clone(),
+                    instance_event_inner: InstanceEventInner::StateTransition { to: state },
+                }),
+                snowflake: Snowflake::default(),
+                details: "Instance was force stopped".to_string(),
+                caused_by: caused_by.clone(),
+            });
+        };
+        send_transition(State::Stopping);
+        self.killed.store(true, Ordering::Relaxed);
+
+        // the process monitor sees the instance stopping, so the exit isn't recorded as a crash
+        let process = self.process.lock().await.take();
+        if let Some(mut process) = process {
+            if let Err(e) = process.kill().await {
+                warn!("[{}] Failed to kill server process: {}", name, e);
+            }
+        }
+        let pid = self.dot_lodestone_config.lock().await.pid();
+        let orphaned = match pid {
+            Some(pid) => {
+                find_orphaned_process(&mut *self.system.lock().await, pid, &self.path_to_instance)
+            }
+            None => None,
+        };
+        if let Some(pid) = orphaned {
+            let killed = self
+                .system
+                .lock()
+                .await
+                .process(pid)
+                .map(|p| p.kill())
+                .unwrap_or(false);
+            if !killed {
+                warn!("[{}] Failed to kill server process {}", name, pid);
+            }
+        }
+        self.stdin.lock().await.take();
+        self.rcon_conn.lock().await.take();
+        if let Err(e) = self.persist_pid(None).await {
+            error!("[{}] Failed to clear pid: {}", name, e);
+        }
+        self.players_manager.lock().await.clear(name.clone());
+
+        *self.state.lock().await = State::Stopped;
+        send_transition(State::Stopped);
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_name: name.clone(),
+                instance_uuid: self.uuid.clone(),
+                instance_event_inner: InstanceEventInner::InstanceWarning {
+                    message: format!(
+                        "Instance was force stopped while {}",
+                        previous_state.to_string().to_lowercase()
+                    ),
+                },
+            }),
+            snowflake: Snowflake::default(),
+            details: "".to_string(),
+            caused_by,
+        });
+        Ok(())
+    }
+
+    async fn state(&self) -> State {
+        *self.state.lock().await
+    }
+
+    async fn send_command(&self, command: &str, cause_by: CausedBy) -> Result<(), Error> {
+        let config = self.config.lock().await.clone();
+        if self.state().await == State::Stopped {
+            Err(eyre!("Instance is stopped").into())
+        } else {
+            match self.stdin.lock().await.as_mut() {
+                Some(stdin) => match {
+                    if command == "stop" {
+                        self.state.lock().await.try_new_state(
+                            StateAction::UserStop,
+                            Some(&|state| {
+                                self.event_broadcaster.send(Event {
+                                    event_inner: EventInner::InstanceEvent(InstanceEvent {
+                                        instance_name: config.name.clone(),
+                                        instance_uuid: self.uuid.clone(),
+                                        instance_event_inner: InstanceEventInner::StateTransition {
+                                            to: state,
+                                        },
+                                    }),
+                                    snowflake: Snowflake::default(),
+                                    details: "Starting server".to_string(),
+                                    caused_by: cause_by.clone(),
+                                });
+                            }),
+                        )?;
+                    }
+                    stdin.write_all(format!("{}\n", command).as_bytes()).await
+                } {
+                    Ok(_) => {
+                        self.record_command(command, cause_by).await;
+                        Ok(())
+                    }
+                    Err(e) => {
+                        warn!(
+                            "[{}] Failed to send command to instance: {}",
+                            config.name.clone(),
+                            e
+                        );
+                        Err(e).context("Failed to send command to instance")?;
+                        unreachable!()
+                    }
+                },
+                None => {
+                    let err_msg =
+                        "Failed to write to stdin because stdin is None. Please report this bug.";
+                    error!("[{}] {}", config.name.clone(), err_msg);
+                    Err(eyre!(err_msg).into())
+                }
+            }
+        }
+    }
+    async fn console_history(&self) -> Vec<Event> {
+        if !self.persist_console().await {
+            return Vec::new();
+        }
+        let name = self.config.lock().await.name.clone();
+        ConsoleLog::read_tail(&self.path_to_instance, self.console_buffer_lines().await)
+            .await
+            .into_iter()
+            .map(|line| Event::new_instance_output(self.uuid.clone(), name.clone(), line))
+            .collect()
+    }
+
+    async fn command_history(&self) -> Vec<CommandHistoryEntry> {
+        self.command_history.lock().await.entries()
+    }
+
+    async fn last_crash(&self) -> Option<CrashInfo> {
+        self.config.lock().await.last_crash.clone()
+    }
+
+    async fn last_started(&self) -> Option<i64> {
+        self.config.lock().await.last_started
+    }
+
+    async fn get_launch_command(&self) -> Result<String, Error> {
+        let config = self.config.lock().await.clone();
+        let command = self.server_start_command(&config).await?;
+        let rcon_password = self
+            .configurable_manifest
+            .lock()
+            .await
+            .get_unique_setting_key("rcon.password")
+            .and_then(|v| v.get_value().map(|v| v.try_as_string().ok()))
+            .flatten()
+            .cloned()
+            .filter(|password| !password.is_empty());
+        let command = command.as_std();
+        Ok(std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|arg| {
+                let arg = arg.to_string_lossy();
+                let arg = match &rcon_password {
+                    Some(password) => arg.replace(password.as_str(), "<redacted>"),
+                    None => arg.to_string(),
+                };
+                if arg.is_empty() || arg.contains(char::is_whitespace) {
+                    format!("\"{}\"", arg)
+                } else {
+                    arg
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" "))
+    }
+
+    async fn reconcile_state(&self) -> Result<(), Error> {
+        // only marks the instance as missing, which isn't an error of reconciling
+        let _ = self.ensure_not_missing().await;
+        // a start or stop is under way, e.g. starting has no process until the prelaunch script is done,
+        // the state is checked again next round
+        let _lifecycle_guard = match self.lifecycle_lock.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                self.state_drift_suspected.store(false, Ordering::Relaxed);
+                return Ok(());
+            }
+        };
+        let state = *self.state.lock().await;
+        let liveness = process_liveness(&mut *self.process.lock().await);
+        if !state_has_drifted(state, &liveness) {
+            self.state_drift_suspected.store(false, Ordering::Relaxed);
+            return Ok(());
+        }
+        // give the process monitor a round to handle the exit itself
+        if !self.state_drift_suspected.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.state_drift_suspected.store(false, Ordering::Relaxed);
+
+        let name = self.config.lock().await.name.clone();
+        warn!(
+            "[{}] Instance is {} but its server process is gone, marking it as stopped",
+            name,
+            state.to_string().to_lowercase()
+        );
+        self.process.lock().await.take();
+        self.stdin.lock().await.take();
+        self.rcon_conn.lock().await.take();
+        if let Err(e) = self.persist_pid(None).await {
+            error!("[{}] Failed to clear pid: {}", name, e);
+        }
+        if let ProcessLiveness::Exited(exit_status) = liveness {
+            if !exit_status.success() && state != State::Stopping {
+                // it was running, so it started at least as long ago as it finished starting
+                let since = self
+                    .config
+                    .lock()
+                    .await
+                    .last_started
+                    .map(|last_started| UNIX_EPOCH + Duration::from_secs(last_started as u64));
+                self.record_crash(exit_status, Vec::new(), since).await;
+            }
+        }
+        self.players_manager.lock().await.clear(name.clone());
+        self.state.lock().await.try_transition(
+            StateAction::InstanceStop,
+            Some(&|state| {
+                self.event_broadcaster.send(Event {
+                    event_inner: EventInner::InstanceEvent(InstanceEvent {
+                        instance_name: name.clone(),
+                        instance_uuid: self.uuid.clone(),
+                        instance_event_inner: InstanceEventInner::StateTransition { to: state },
+                    }),
+                    snowflake: Snowflake::default(),
+                    details: "Server process exited without being noticed".to_string(),
+                    caused_by: CausedBy::System,
+                });
+            }),
+        )
+    }
+
+    async fn verify_installation(&self) -> Result<VerifyReport, Error> {
+        self.verify().await
+    }
+
+    async fn repair_installation(&mut self) -> Result<VerifyReport, Error> {
+        self.repair().await
+    }
+
+    async fn check_for_update(&mut self) -> Result<(), Error> {
+        self.check_for_jar_update().await
+    }
+
+    async fn monitor(&self) -> MonitorReport {
+        let mut sys = self.system.lock().await;
+        sys.refresh_memory();
+        if let Some(pid) = self.process.lock().await.as_ref().and_then(|p| p.id()) {
+            sys.refresh_process(Pid::from_u32(pid));
+            let proc = (*sys).process(Pid::from_u32(pid));
+            if let Some(proc) = proc {
+                let cpu_usage =
+                    sys.process(Pid::from_u32(pid)).unwrap().cpu_usage() / sys.cpus().len() as f32;
+
+                let memory_usage = proc.memory();
+                let disk_usage = proc.disk_usage();
+                let start_time = proc.start_time();
+                MonitorReport {
+                    memory_usage: Some(memory_usage),
+                    disk_usage: Some(disk_usage.into()),
+                    cpu_usage: Some(cpu_usage),
+                    start_time: Some(start_time),
+                }
+            } else {
+                MonitorReport::default()
+            }
+        } else {
+            MonitorReport::default()
+        }
+    }
 
+    async fn tick_performance(&self) -> Result<TickPerformance, Error> {
+        self.cached_tick_performance().await
+    }
+
+    async fn test_rcon(&self) -> Result<RconTestResult, Error> {
+        self.check_rcon().await
+    }
+}
+
+impl MinecraftInstance {
+    /// Starts the server, refusing to if that would downgrade its world unless `allow_downgrade`
+    async fn start_checked(
+        &mut self,
+        cause_by: CausedBy,
+        block: bool,
+        allow_downgrade: bool,
+    ) -> Result<(), Error> {
+        if start_limiter().is_queued(&self.uuid) {
+            return Err(Error {
+                kind: ErrorKind::InvalidInstanceState,
+                source: eyre!("Instance is already queued to start"),
+            });
+        }
+        self.ensure_not_missing().await?;
+        // rejected up front, rather than once a queued start gets its turn
+        self.state
+            .lock()
+            .await
+            .try_new_state(StateAction::UserStart, None)?;
+        let start_permit = match start_limiter().try_acquire() {
+            Some(start_permit) => start_permit,
+            None if block => start_limiter().acquire(&self.uuid).await,
+            None => {
+                info!(
+                    "[{}] Too many instances are starting, queued to start",
+                    self.name().await
+                );
+                let mut instance = self.clone();
+                let span = instance_span(&self.uuid);
+                tokio::spawn(
+                    async move {
+                        let start_permit = start_limiter().acquire(&instance.uuid).await;
+                        if let Err(e) = instance
+                            .start_with_permit(cause_by, false, allow_downgrade, start_permit)
+                            .await
+                        {
+                            error!(
+                                "[{}] Failed to start queued instance: {}",
+                                instance.name().await,
+                                e
+                            );
+                        }
+                    }
+                    .instrument(span),
+                );
+                return Ok(());
+            }
+        };
+        self.start_with_permit(cause_by, block, allow_downgrade, start_permit)
+            .instrument(instance_span(&self.uuid))
+            .await
+    }
+
+    /// Starts the server in a slot of the start limiter, which is freed once the server is up or exits
+    async fn start_with_permit(
+        &mut self,
+        cause_by: CausedBy,
+        block: bool,
+        allow_downgrade: bool,
+        start_permit: StartPermit,
+    ) -> Result<(), Error> {
+        let lifecycle_guard = self.lifecycle_lock.clone().lock_owned().await;
+        let config = self.config.lock().await.clone();
+        if let Some(pid) = self.orphaned_pid().await {
+            return Err(Error {
+                kind: ErrorKind::Internal,
+                source: eyre!(
+                    "A server process (PID {}) from a previous session is still running for this instance. Kill the instance to terminate it before starting",
+                    pid
+                ),
+            });
+        }
+        self.check_world_downgrade(&config.version, allow_downgrade)
+            .await?;
         if !port_scanner::local_port_available(config.port as u16) {
             return Err(Error {
                 kind: ErrorKind::Internal,
                 source: eyre!("Port {} is already in use", config.port),
             });
         }
+        // left over if the last kill didn't make it to the process exit
+        self.killed.store(false, Ordering::Relaxed);
+        self.state.lock().await.try_transition(
+            StateAction::UserStart,
+            Some(&|state| {
+                self.event_broadcaster.send(Event {
+                    event_inner: EventInner::InstanceEvent(InstanceEvent {
+                        instance_name: config.name.clone(),
+                        instance_uuid: self.uuid.clone(),
+                        instance_event_inner: InstanceEventInner::StateTransition { to: state },
+                    }),
+                    snowflake: Snowflake::default(),
+                    details: "Starting server".to_string(),
+                    caused_by: cause_by.clone(),
+                });
+            }),
+        )?;
 
         let prelaunch = resolve_macro_invocation(&self.path_to_instance, "prelaunch");
         if let Some(prelaunch) = prelaunch {
@@ -125,118 +743,96 @@ impl TServer for MinecraftInstance {
             );
         }
 
-        let jre = if let Some(jre) = &config.java_cmd {
-            PathBuf::from(jre)
-        } else {
-            self.path_to_runtimes
-                .join("java")
-                .join(format!("jre{}", config.jre_major_version))
-                .join(if std::env::consts::OS == "macos" {
-                    "Contents/Home/bin"
-                } else {
-                    "bin"
-                })
-                .join("java")
-        };
-
-        let mut server_start_command = Command::new(&jre);
-        let server_start_command = server_start_command
-            .arg(format!("-Xmx{}M", config.max_ram))
-            .arg(format!("-Xms{}M", config.min_ram))
-            .args(
-                &config
-                    .cmd_args
-                    .iter()
-                    .filter(|s| !s.is_empty())
-                    .collect::<Vec<&String>>(),
-            );
-
-        let server_start_command = match &config.flavour {
-            Flavour::Forge { build_version } => {
-                let ForgeBuildVersion(build_version) = build_version
-                    .as_ref()
-                    .ok_or_else(|| eyre!("Forge version not found"))?;
-                let version_parts: Vec<&str> = config.version.split('.').collect();
-                let major_version: i32 = version_parts[1]
-                    .parse()
-                    .context("Unable to parse major Minecraft version for Forge")?;
-
-                if 17 <= major_version {
-                    let forge_args = match std::env::consts::OS {
-                        "windows" => "win_args.txt",
-                        _ => "unix_args.txt",
-                    };
-
-                    let mut full_forge_args = std::ffi::OsString::from("@");
-                    full_forge_args.push(
-                        self.path_to_instance
-                            .join("libraries")
-                            .join("net")
-                            .join("minecraftforge")
-                            .join("forge")
-                            .join(build_version.as_str())
-                            .join(forge_args)
-                            .into_os_string()
-                            .as_os_str(),
-                    );
+        let before_start = self.run_hook(LifecycleHook::BeforeStart).await;
+        if let Err(e) = config.hooks.gate_start(before_start) {
+            self.state.lock().await.try_transition(
+                StateAction::InstanceStop,
+                Some(&|state| {
+                    self.event_broadcaster.send(Event {
+                        event_inner: EventInner::InstanceEvent(InstanceEvent {
+                            instance_name: config.name.clone(),
+                            instance_uuid: self.uuid.clone(),
+                            instance_event_inner: InstanceEventInner::StateTransition { to: state },
+                        }),
+                        snowflake: Snowflake::default(),
+                        details: "Start aborted as the before start hook failed".to_string(),
+                        caused_by: cause_by.clone(),
+                    });
+                }),
+            )?;
+            return Err(e);
+        }
 
-                    server_start_command.arg(full_forge_args)
-                } else if (7..=16).contains(&major_version) {
-                    let files = list_dir(&self.path_to_instance, Some(false))
-                        .await
-                        .context("Failed to find forge.jar")?;
-                    let forge_jar_name = files
-                        .iter()
-                        .find(|p| {
-                            p.extension().unwrap_or_default() == "jar"
-                                && p.file_name()
-                                    .unwrap_or_default()
-                                    .to_str()
-                                    .unwrap_or_default()
-                                    .starts_with(format!("forge-{}-", config.version,).as_str())
-                        })
-                        .ok_or_else(|| eyre!("Failed to find forge.jar"))?;
-                    server_start_command
-                        .arg("-jar")
-                        .arg(&self.path_to_instance.join(forge_jar_name))
-                } else {
-                    // 1.5 doesn't work due to JRE issues
-                    // 1.4 doesn't work since forge doesn't provide an installer
-                    let files = list_dir(&self.path_to_instance, Some(false))
-                        .await
-                        .context("Failed to find minecraftforge.jar")?;
-                    let server_jar_name = files
-                        .iter()
-                        .find(|p| {
-                            p.extension().unwrap_or_default() == "jar"
-                                && p.file_name()
-                                    .unwrap_or_default()
-                                    .to_str()
-                                    .unwrap_or_default()
-                                    .starts_with("minecraftforge")
-                        })
-                        .ok_or_else(|| eyre!("Failed to find minecraftforge.jar"))?;
-                    server_start_command
-                        .arg("-jar")
-                        .arg(&self.path_to_instance.join(server_jar_name))
-                }
+        let mut server_start_command = match self.server_start_command(&config).await {
+            Ok(command) => command,
+            Err(e) => {
+                // otherwise the instance would be stuck starting
+                self.state.lock().await.try_transition(
+                    StateAction::InstanceStop,
+                    Some(&|state| {
+                        self.event_broadcaster.send(Event {
+                            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                                instance_name: config.name.clone(),
+                                instance_uuid: self.uuid.clone(),
+                                instance_event_inner: InstanceEventInner::StateTransition {
+                                    to: state,
+                                },
+                            }),
+                            snowflake: Snowflake::default(),
+                            details: "Failed to start server".to_string(),
+                            caused_by: cause_by.clone(),
+                        });
+                    }),
+                )?;
+                return Err(e);
             }
-            _ => server_start_command
-                .arg("-jar")
-                .arg(&self.path_to_instance.join("server.jar")),
         };
-
-        let server_start_command = server_start_command
-            .arg("nogui")
-            .current_dir(&self.path_to_instance);
-
-        match dont_spawn_terminal(server_start_command)
+        debug_detail(&self.event_broadcaster, &self.uuid, &config.name, || {
+            let command = server_start_command.as_std();
+            format!(
+                "Launch command: {} {}",
+                command.get_program().to_string_lossy(),
+                command
+                    .get_args()
+                    .map(|arg| arg.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            )
+        });
+        if !config.env.is_empty() {
+            info!(
+                "[{}] Launching with environment: {}",
+                config.name,
+                redact_env(&config.env)
+            );
+        }
+        let scheduling = ProcessScheduling {
+            priority: config.process_priority,
+            cpu_affinity: config.cpu_affinity,
+        };
+        scheduling.configure(&mut server_start_command);
+        // crash reports written from here on are of this run
+        let spawned_at = SystemTime::now();
+        match dont_spawn_terminal(&mut server_start_command)
             .stdout(Stdio::piped())
             .stdin(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
         {
             Ok(mut proc) => {
+                for message in scheduling.apply(&proc) {
+                    warn!("[{}] {}", config.name, message);
+                    self.event_broadcaster.send(Event {
+                        event_inner: EventInner::InstanceEvent(InstanceEvent {
+                            instance_name: config.name.clone(),
+                            instance_uuid: self.uuid.clone(),
+                            instance_event_inner: InstanceEventInner::InstanceWarning { message },
+                        }),
+                        snowflake: Snowflake::default(),
+                        details: "".to_string(),
+                        caused_by: CausedBy::System,
+                    });
+                }
                 let stdin = proc.stdin.take().ok_or_else(|| {
                     error!(
                         "[{}] Failed to take stdin during startup",
@@ -259,15 +855,35 @@ impl TServer for MinecraftInstance {
                     );
                     eyre!("Failed to take stderr during startup")
                 })?;
+                let pid = proc.id();
                 *self.process.lock().await = Some(proc);
+                if let Err(e) = self.persist_pid(pid).await {
+                    error!("[{}] Failed to persist pid: {}", config.name.clone(), e);
+                }
+                self.spawn_readiness_watchdog(pid);
+                self.spawn_tick_reporter(pid);
                 tokio::task::spawn({
                     let event_broadcaster = self.event_broadcaster.clone();
                     let uuid = self.uuid.clone();
                     let name = config.name.clone();
                     let players_manager = self.players_manager.clone();
-                    let mut __self = self.clone();
+                    let __self = self.clone();
+                    let span = instance_span(&self.uuid);
                     async move {
+                        let mut start_permit = Some(start_permit);
                         let mut did_start = false;
+                        let mut console_tail: VecDeque<String> =
+                            VecDeque::with_capacity(CRASH_CONSOLE_TAIL_LINES);
+                        let mut console_log = if __self.persist_console().await {
+                            ConsoleLog::open(&__self.path_to_instance)
+                                .await
+                                .map_err(|e| {
+                                    error!("[{}] Failed to open console log: {}", name, e);
+                                })
+                                .ok()
+                        } else {
+                            None
+                        };
 
                         let mut stdout_reader = BufReader::new(stdout);
                         let mut stderr_reader = BufReader::new(stderr);
@@ -309,6 +925,18 @@ impl TServer for MinecraftInstance {
                                         // info!("[{}] {}", name, line);
                                         warn!("[{}] {}", name, line);
                                     }
+                                    if console_tail.len() == CRASH_CONSOLE_TAIL_LINES {
+                                        console_tail.pop_front();
+                                    }
+                                    console_tail.push_back(line.clone());
+                                    if let Some(console_log) = console_log.as_mut() {
+                                        if let Err(e) = console_log.append(&line).await {
+                                            error!(
+                                                "[{}] Failed to persist console output: {}",
+                                                name, e
+                                            );
+                                        }
+                                    }
                                     event_broadcaster.send(Event {
                                         event_inner: EventInner::InstanceEvent(InstanceEvent {
                                             instance_uuid: uuid.clone(),
@@ -324,18 +952,26 @@ impl TServer for MinecraftInstance {
                                     });
 
                                     if parse_server_started(&line) && !did_start {
+                                        debug_detail(&event_broadcaster, &uuid, &name, || {
+                                            format!(
+                                                "Detected that the server started from: {}",
+                                                line.trim_end()
+                                            )
+                                        });
                                         did_start = true;
-                                        self.state
+                                        start_permit.take();
+                                        __self
+                                            .state
                                             .lock()
                                             .await
                                             .try_transition(
                                                 StateAction::InstanceStart,
                                                 Some(&|state| {
-                                                    self.event_broadcaster.send(Event {
+                                                    __self.event_broadcaster.send(Event {
                                                 event_inner: EventInner::InstanceEvent(
                                                     InstanceEvent {
                                                         instance_name: config.name.clone(),
-                                                        instance_uuid: self.uuid.clone(),
+                                                        instance_uuid: __self.uuid.clone(),
                                                         instance_event_inner:
                                                             InstanceEventInner::StateTransition {
                                                                 to: state,
@@ -349,34 +985,25 @@ impl TServer for MinecraftInstance {
                                                 }),
                                             )
                                             .unwrap();
+                                        // runs in the background
+                                        let _ = __self.run_hook(LifecycleHook::AfterStart).await;
+
+                                        {
+                                            let mut config = __self.config.lock().await;
+                                            // uptime counts from here, so it also resets when
+                                            // the server is restarted after a crash
+                                            config.last_started =
+                                                Some(chrono::Utc::now().timestamp());
+                                            if let CausedBy::User { .. } = cause_by {
+                                                // a clean manual start clears the last crash
+                                                config.last_crash = None;
+                                            }
+                                        }
+                                        let _ = __self.write_config_to_file().await;
 
-                                        if let (Some(true), Some(rcon_psw), Some(rcon_port)) = {
-                                            let lock = self.configurable_manifest.lock().await;
-
-                                            let a = lock
-                                                .get_unique_setting_key("enable-rcon")
-                                                .and_then(|v| {
-                                                    v.get_value().map(|v| v.try_as_boolean().ok())
-                                                })
-                                                .flatten();
-
-                                            let b = lock
-                                                .get_unique_setting_key("rcon.password")
-                                                .and_then(|v| {
-                                                    v.get_value().map(|v| v.try_as_string().ok())
-                                                })
-                                                .flatten()
-                                                .cloned();
-
-                                            let c = lock
-                                                .get_unique_setting_key("rcon.port")
-                                                .and_then(|v| {
-                                                    v.get_value()
-                                                        .map(|v| v.try_as_unsigned_integer().ok())
-                                                })
-                                                .flatten();
-                                            (a, b, c)
-                                        } {
+                                        if let Some((rcon_port, rcon_psw)) =
+                                            __self.rcon_settings().await
+                                        {
                                             let max_retry = 3;
                                             for i in 0..max_retry {
                                                 let rcon =
@@ -397,7 +1024,7 @@ impl TServer for MinecraftInstance {
                                                 });
                                                 if let Ok(rcon) = rcon {
                                                     info!("Connected to RCON");
-                                                    self.rcon_conn.lock().await.replace(rcon);
+                                                    __self.rcon_conn.lock().await.replace(rcon);
                                                     break;
                                                 }
                                                 tokio::time::sleep(Duration::from_secs(
@@ -407,10 +1034,13 @@ impl TServer for MinecraftInstance {
                                             }
                                         } else {
                                             warn!("RCON is not enabled or misconfigured, skipping");
-                                            self.rcon_conn.lock().await.take();
+                                            __self.rcon_conn.lock().await.take();
                                         }
                                     }
                                     if let Some(system_msg) = parse_system_msg(&line) {
+                                        debug_detail(&event_broadcaster, &uuid, &name, || {
+                                            format!("Parsed system message: {}", system_msg)
+                                        });
                                         let _ = event_broadcaster.send(Event {
                                             event_inner: EventInner::InstanceEvent(InstanceEvent {
                                                 instance_uuid: uuid.clone(),
@@ -426,24 +1056,33 @@ impl TServer for MinecraftInstance {
                                         });
                                         if let Some(player_name) = parse_player_joined(&system_msg)
                                         {
+                                            debug_detail(&event_broadcaster, &uuid, &name, || {
+                                                format!("Detected {} joining", player_name)
+                                            });
                                             players_manager.lock().await.add_player(
                                                 MinecraftPlayer {
                                                     name: player_name.clone(),
                                                     uuid: name_to_uuid(&player_name).await,
                                                 },
-                                                self.name().await,
+                                                __self.name().await,
                                             );
                                         } else if let Some(player_name) =
                                             parse_player_left(&system_msg)
                                         {
+                                            debug_detail(&event_broadcaster, &uuid, &name, || {
+                                                format!("Detected {} leaving", player_name)
+                                            });
                                             players_manager
                                                 .lock()
                                                 .await
-                                                .remove_by_name(&player_name, self.name().await);
+                                                .remove_by_name(&player_name, __self.name().await);
                                         }
                                     } else if let Some(PlayerMessage { player, message }) =
                                         parse_player_msg(&line)
                                     {
+                                        debug_detail(&event_broadcaster, &uuid, &name, || {
+                                            format!("Parsed a chat message from {}", player)
+                                        });
                                         let _ = event_broadcaster.send(Event {
                                             event_inner: EventInner::InstanceEvent(InstanceEvent {
                                                 instance_uuid: uuid.clone(),
@@ -465,16 +1104,37 @@ impl TServer for MinecraftInstance {
                             }
                         }
                         info!("Instance {} process shutdown", name);
-                        self.state
+                        if let Err(e) = __self.persist_pid(None).await {
+                            error!("[{}] Failed to clear pid: {}", name, e);
+                        }
+                        let proc = __self.process.lock().await.take();
+                        let exit_status = match proc {
+                            Some(mut proc) => proc.wait().await.ok(),
+                            None => None,
+                        };
+                        let is_stopping = __self.state().await == State::Stopping;
+                        if let Some(exit_status) = exit_status {
+                            if !exit_status.success() && !is_stopping {
+                                __self
+                                    .record_crash(
+                                        exit_status,
+                                        console_tail.into_iter().collect(),
+                                        Some(spawned_at),
+                                    )
+                                    .await;
+                            }
+                        }
+                        __self
+                            .state
                             .lock()
                             .await
                             .try_transition(
                                 StateAction::InstanceStop,
                                 Some(&|state| {
-                                    self.event_broadcaster.send(Event {
+                                    __self.event_broadcaster.send(Event {
                                         event_inner: EventInner::InstanceEvent(InstanceEvent {
                                             instance_name: config.name.clone(),
-                                            instance_uuid: self.uuid.clone(),
+                                            instance_uuid: __self.uuid.clone(),
                                             instance_event_inner:
                                                 InstanceEventInner::StateTransition { to: state },
                                         }),
@@ -486,13 +1146,21 @@ impl TServer for MinecraftInstance {
                                 }),
                             )
                             .unwrap();
-                        self.players_manager.lock().await.clear(name);
+                        __self.players_manager.lock().await.clear(name);
+                        // runs in the background
+                        let _ = __self.run_hook(LifecycleHook::AfterStop).await;
+                        if !__self.killed.swap(false, Ordering::Relaxed) {
+                            __self.backup_on_stop().await;
+                        }
                     }
+                    .instrument(span)
                 });
                 self.config.lock().await.has_started = true;
                 self.write_config_to_file().await?;
                 let instance_uuid = self.uuid.clone();
                 let mut rx = self.event_broadcaster.subscribe();
+                // the process is up, waiting for it to finish starting mustn't hold up a kill
+                drop(lifecycle_guard);
 
                 if block {
                     while let Ok(event) = rx.recv().await {
@@ -547,187 +1215,343 @@ impl TServer for MinecraftInstance {
             }
         }
     }
-    async fn stop(&mut self, cause_by: CausedBy, block: bool) -> Result<(), Error> {
-        let config = self.config.lock().await.clone();
-
-        self.state.lock().await.try_transition(
-            StateAction::UserStop,
-            Some(&|state| {
-                self.event_broadcaster.send(Event {
-                    event_inner: EventInner::InstanceEvent(InstanceEvent {
-                        instance_name: config.name.clone(),
-                        instance_uuid: self.uuid.clone(),
-                        instance_event_inner: InstanceEventInner::StateTransition { to: state },
-                    }),
-                    snowflake: Snowflake::default(),
-                    details: "Stopping server".to_string(),
-                    caused_by: cause_by.clone(),
-                });
-            }),
-        )?;
-        let name = config.name.clone();
-        let _uuid = self.uuid.clone();
-        self.stdin
-            .lock()
-            .await
-            .as_mut()
-            .ok_or_else(|| {
-                error!("[{}] Failed to stop instance: stdin not available", name);
-                eyre!("Failed to stop instance: stdin not available")
-            })?
-            .write_all(b"stop\n")
-            .await
-            .context("Failed to write to stdin")
-            .map_err(|e| {
-                error!("[{}] Failed to stop instance: {}", name, e);
-                e
-            })?;
-        self.rcon_conn.lock().await.take();
-        let mut rx = self.event_broadcaster.subscribe();
-        let instance_uuid = self.uuid.clone();
 
-        if block {
-            while let Ok(event) = rx.recv().await {
-                if let EventInner::InstanceEvent(InstanceEvent {
-                    instance_uuid: event_instance_uuid,
-                    instance_event_inner: InstanceEventInner::StateTransition { to },
-                    ..
-                }) = event.event_inner
-                {
-                    if instance_uuid == event_instance_uuid && to == State::Stopped {
-                        return Ok(());
-                    }
-                }
-            }
-            Err(eyre!("Sender shutdown").into())
+    /// Composes the command used to launch the server from the current config
+    pub(super) fn java_path(&self, config: &RestoreConfig) -> PathBuf {
+        if let Some(jre) = &config.java_cmd {
+            PathBuf::from(jre)
         } else {
-            Ok(())
+            self.path_to_runtimes
+                .join("java")
+                .join(format!("jre{}", config.jre_major_version))
+                .join(if std::env::consts::OS == "macos" {
+                    "Contents/Home/bin"
+                } else {
+                    "bin"
+                })
+                .join("java")
         }
     }
 
-    async fn restart(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error> {
-        if block {
-            self.stop(caused_by.clone(), block).await?;
-            self.start(caused_by, block).await
-        } else {
-            self.state
-                .lock()
-                .await
-                .try_new_state(StateAction::UserStop, None)?;
+    /// Sends SIGTERM to the server process `pid`, if the platform has it
+    async fn terminate_process(&self, pid: Option<u32>) -> bool {
+        let pid = match pid {
+            Some(pid) => Pid::from_u32(pid),
+            None => return false,
+        };
+        let mut system = self.system.lock().await;
+        system.refresh_process(pid);
+        system
+            .process(pid)
+            .and_then(|process| process.kill_with(Signal::Term))
+            .unwrap_or(false)
+    }
 
-            let mut __self = self.clone();
-            tokio::task::spawn(async move {
-                self.stop(caused_by.clone(), true).await.unwrap();
-                self.start(caused_by, block).await.unwrap()
-            });
-            Ok(())
-        }
+    /// Waits up to `timeout` for the server to stop
+    async fn wait_for_stopped(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, async {
+            while *self.state.lock().await != State::Stopped {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        })
+        .await
+        .is_ok()
     }
 
-    async fn kill(&mut self, _cause_by: CausedBy) -> Result<(), Error> {
-        let config = self.config.lock().await.clone();
+    /// Terminates the server process `pid` if it hasn't stopped `timeout_secs` after being asked to,
+    /// and kills it if it still hasn't as long again after that
+    fn spawn_stop_escalation(&self, pid: u32, timeout_secs: u32) {
+        let instance = self.clone();
+        tokio::spawn(async move {
+            let timeout = Duration::from_secs(timeout_secs as u64);
+            let name = instance.config.lock().await.name.clone();
+            let stopped = |timeout| {
+                let instance = instance.clone();
+                async move {
+                    // it may have stopped and been started again in the meantime
+                    instance.wait_for_stopped(timeout).await
+                        || instance.process.lock().await.as_ref().and_then(|p| p.id()) != Some(pid)
+                }
+            };
+            let terminate = || {
+                let instance = instance.clone();
+                let name = name.clone();
+                async move {
+                    warn!(
+                        "[{}] Server didn't stop within {} seconds, terminating it",
+                        name, timeout_secs
+                    );
+                    instance.terminate_process(Some(pid)).await
+                }
+            };
+            let kill = || {
+                let mut instance = instance.clone();
+                let name = name.clone();
+                async move {
+                    warn!(
+                        "[{}] Server didn't stop after being terminated, killing it",
+                        name
+                    );
+                    if let Err(e) = instance.kill(CausedBy::System).await {
+                        error!("[{}] Failed to kill server that didn't stop: {}", name, e);
+                    }
+                }
+            };
+            escalate_stop(timeout, stopped, terminate, kill).await;
+        });
+    }
 
-        if self.state().await == State::Stopped {
-            warn!("[{}] Instance is already stopped", config.name.clone());
-            return Err(eyre!("Instance is already stopped").into());
+    /// The RCON port and password, if RCON is enabled
+    pub(super) async fn rcon_settings(&self) -> Option<(u32, String)> {
+        let lock = self.configurable_manifest.lock().await;
+        let enabled = lock
+            .get_unique_setting_key("enable-rcon")
+            .and_then(|v| v.get_value().map(|v| v.try_as_boolean().ok()))
+            .flatten();
+        let password = lock
+            .get_unique_setting_key("rcon.password")
+            .and_then(|v| v.get_value().map(|v| v.try_as_string().ok()))
+            .flatten()
+            .cloned();
+        let port = lock
+            .get_unique_setting_key("rcon.port")
+            .and_then(|v| v.get_value().map(|v| v.try_as_unsigned_integer().ok()))
+            .flatten();
+        match (enabled, password, port) {
+            (Some(true), Some(password), Some(port)) => Some((port, password)),
+            _ => None,
         }
-        self.process
-            .lock()
-            .await
-            .as_mut()
-            .ok_or_else(|| {
-                error!(
-                    "[{}] Failed to kill instance: process not available",
-                    config.name.clone()
-                );
-                eyre!("Failed to kill instance: process not available")
-            })?
-            .kill()
-            .await
-            .context("Failed to kill process")
-            .map_err(|e| {
-                error!("[{}] Failed to kill instance: {}", config.name.clone(), e);
-                e
-            })?;
-        Ok(())
     }
 
-    async fn state(&self) -> State {
-        *self.state.lock().await
+    async fn server_start_command(&self, config: &RestoreConfig) -> Result<Command, Error> {
+        let jvm_args = launch_jvm_args(&config.jvm_args, config.min_ram, config.max_ram);
+        let program_args = config
+            .program_args
+            .iter()
+            .filter(|s| !s.is_empty())
+            .cloned()
+            .collect();
+        // scripts find the server jar themselves
+        let jar_args = match config.launch_mode {
+            LaunchMode::JavaJar => self.jar_args(config).await?,
+            _ => Vec::new(),
+        };
+        let launch_command = LaunchCommand::new(
+            &config.launch_mode,
+            &self.path_to_instance,
+            &self.java_path(config),
+            config.jre_major_version,
+            jvm_args,
+            jar_args,
+            program_args,
+        )?;
+
+        let mut server_start_command = Command::new(launch_command.program);
+        server_start_command
+            .args(launch_command.args)
+            .envs(launch_command.envs)
+            .envs(&config.env)
+            .current_dir(&self.path_to_instance);
+        Ok(server_start_command)
     }
 
-    async fn send_command(&self, command: &str, cause_by: CausedBy) -> Result<(), Error> {
-        let config = self.config.lock().await.clone();
-        if self.state().await == State::Stopped {
-            Err(eyre!("Instance is stopped").into())
-        } else {
-            match self.stdin.lock().await.as_mut() {
-                Some(stdin) => match {
-                    if command == "stop" {
-                        self.state.lock().await.try_new_state(
-                            StateAction::UserStop,
-                            Some(&|state| {
-                                self.event_broadcaster.send(Event {
-                                    event_inner: EventInner::InstanceEvent(InstanceEvent {
-                                        instance_name: config.name.clone(),
-                                        instance_uuid: self.uuid.clone(),
-                                        instance_event_inner: InstanceEventInner::StateTransition {
-                                            to: state,
-                                        },
-                                    }),
-                                    snowflake: Snowflake::default(),
-                                    details: "Starting server".to_string(),
-                                    caused_by: cause_by.clone(),
-                                });
-                            }),
-                        )?;
-                    }
-                    stdin.write_all(format!("{}\n", command).as_bytes()).await
-                } {
-                    Ok(_) => Ok(()),
-                    Err(e) => {
-                        warn!(
-                            "[{}] Failed to send command to instance: {}",
-                            config.name.clone(),
-                            e
-                        );
-                        Err(e).context("Failed to send command to instance")?;
-                        unreachable!()
-                    }
-                },
-                None => {
-                    let err_msg =
-                        "Failed to write to stdin because stdin is None. Please report this bug.";
-                    error!("[{}] {}", config.name.clone(), err_msg);
-                    Err(eyre!(err_msg).into())
+    /// What `java` is told to run to start the server jar of the flavour
+    async fn jar_args(&self, config: &RestoreConfig) -> Result<Vec<OsString>, Error> {
+        match &config.flavour {
+            Flavour::Forge { build_version } => {
+                let ForgeBuildVersion(build_version) = build_version
+                    .as_ref()
+                    .ok_or_else(|| eyre!("Forge version not found"))?;
+                let version_parts: Vec<&str> = config.version.split('.').collect();
+                let major_version: i32 = version_parts[1]
+                    .parse()
+                    .context("Unable to parse major Minecraft version for Forge")?;
+
+                if 17 <= major_version {
+                    let forge_args = match std::env::consts::OS {
+                        "windows" => "win_args.txt",
+                        _ => "unix_args.txt",
+                    };
+
+                    let mut full_forge_args = OsString::from("@");
+                    full_forge_args.push(
+                        self.path_to_instance
+                            .join("libraries")
+                            .join("net")
+                            .join("minecraftforge")
+                            .join("forge")
+                            .join(build_version.as_str())
+                            .join(forge_args)
+                            .into_os_string()
+                            .as_os_str(),
+                    );
+
+                    Ok(vec![full_forge_args])
+                } else if (7..=16).contains(&major_version) {
+                    let files = list_dir(&self.path_to_instance, Some(false))
+                        .await
+                        .context("Failed to find forge.jar")?;
+                    let forge_jar_name = files
+                        .iter()
+                        .find(|p| {
+                            p.extension().unwrap_or_default() == "jar"
+                                && p.file_name()
+                                    .unwrap_or_default()
+                                    .to_str()
+                                    .unwrap_or_default()
+                                    .starts_with(format!("forge-{}-", config.version,).as_str())
+                        })
+                        .ok_or_else(|| eyre!("Failed to find forge.jar"))?;
+                    Ok(vec![
+                        "-jar".into(),
+                        self.path_to_instance.join(forge_jar_name).into(),
+                    ])
+                } else {
+                    // 1.5 doesn't work due to JRE issues
+                    // 1.4 doesn't work since forge doesn't provide an installer
+                    let files = list_dir(&self.path_to_instance, Some(false))
+                        .await
+                        .context("Failed to find minecraftforge.jar")?;
+                    let server_jar_name = files
+                        .iter()
+                        .find(|p| {
+                            p.extension().unwrap_or_default() == "jar"
+                                && p.file_name()
+                                    .unwrap_or_default()
+                                    .to_str()
+                                    .unwrap_or_default()
+                                    .starts_with("minecraftforge")
+                        })
+                        .ok_or_else(|| eyre!("Failed to find minecraftforge.jar"))?;
+                    Ok(vec![
+                        "-jar".into(),
+                        self.path_to_instance.join(server_jar_name).into(),
+                    ])
                 }
             }
+            // the launcher loads quilt, then starts server.jar itself
+            Flavour::Quilt { .. } => Ok(vec![
+                "-jar".into(),
+                self.path_to_instance
+                    .join(super::quilt::QUILT_SERVER_LAUNCH_JAR)
+                    .into(),
+            ]),
+            _ => Ok(vec![
+                "-jar".into(),
+                self.path_to_instance.join("server.jar").into(),
+            ]),
         }
     }
-    async fn monitor(&self) -> MonitorReport {
-        let mut sys = self.system.lock().await;
-        sys.refresh_memory();
-        if let Some(pid) = self.process.lock().await.as_ref().and_then(|p| p.id()) {
-            sys.refresh_process(Pid::from_u32(pid));
-            let proc = (*sys).process(Pid::from_u32(pid));
-            if let Some(proc) = proc {
-                let cpu_usage =
-                    sys.process(Pid::from_u32(pid)).unwrap().cpu_usage() / sys.cpus().len() as f32;
 
-                let memory_usage = proc.memory();
-                let disk_usage = proc.disk_usage();
-                let start_time = proc.start_time();
-                MonitorReport {
-                    memory_usage: Some(memory_usage),
-                    disk_usage: Some(disk_usage.into()),
-                    cpu_usage: Some(cpu_usage),
-                    start_time: Some(start_time),
-                }
-            } else {
-                MonitorReport::default()
-            }
+    /// Records a crash of the server process and tells the user about it, with the crash report
+    /// written since `since` if there is one
+    async fn record_crash(
+        &self,
+        exit_status: std::process::ExitStatus,
+        console_tail: Vec<String>,
+        since: Option<SystemTime>,
+    ) {
+        let (name, upload_url) = {
+            let config = self.config.lock().await;
+            (config.name.clone(), config.crash_report_upload_url.clone())
+        };
+        error!("[{}] Server process crashed ({})", name, exit_status);
+        let mut crash_info = CrashInfo::from_exit_status(exit_status, console_tail);
+        crash_info.is_oom |= crash_info
+            .console_tail
+            .iter()
+            .any(|line| parse_out_of_memory(line));
+        let is_oom = crash_info.is_oom;
+        if let Some(since) = since {
+            crash_info.crash_report = newest_crash_report(&self.path_to_instance, since).await;
+        }
+        let crash_report = crash_info.crash_report.clone();
+        let mut message = if is_oom {
+            format!(
+                "Server crashed ({}), most likely ran out of memory",
+                exit_status
+            )
         } else {
-            MonitorReport::default()
+            format!("Server crashed ({})", exit_status)
+        };
+        if let Some(crash_report) = &crash_report {
+            message.push_str(&format!(", see crash report {}", crash_report.file_name));
+        }
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_name: name.clone(),
+                instance_uuid: self.uuid.clone(),
+                instance_event_inner: InstanceEventInner::InstanceError { message },
+            }),
+            snowflake: Snowflake::default(),
+            details: "".to_string(),
+            caused_by: CausedBy::System,
+        });
+        let crash_time = crash_info.time;
+        self.config.lock().await.last_crash = Some(crash_info);
+        if let Err(e) = self.write_config_to_file().await {
+            error!("[{}] Failed to save crash info: {}", name, e);
         }
+        if let (Some(upload_url), Some(crash_report)) = (upload_url, crash_report) {
+            self.spawn_crash_report_upload(upload_url, crash_time, crash_report);
+        }
+        if is_oom {
+            self.handle_out_of_memory().await;
+        }
+    }
+
+    /// Suggests a higher max RAM after an out of memory crash,
+    /// and applies it right away if the instance has a ceiling configured for automatic bumps
+    async fn handle_out_of_memory(&self) {
+        let host_total_mb = self.host_total_mb().await;
+        let (name, max_ram, ceiling) = {
+            let config = self.config.lock().await;
+            (
+                config.name.clone(),
+                config.max_ram,
+                config.oom_max_ram_ceiling,
+            )
+        };
+        let message = match ceiling
+            .and_then(|ceiling| suggest_max_ram(max_ram, ceiling, host_total_mb))
+        {
+            Some(new_max_ram) => {
+                self.config.lock().await.max_ram = new_max_ram;
+                if let Err(e) = self.configurable_manifest.lock().await.set_setting(
+                    CmdArgSetting::get_section_id(),
+                    CmdArgSetting::MaxRam(new_max_ram).into(),
+                ) {
+                    error!("[{}] Failed to update the max RAM setting: {}", name, e);
+                }
+                if let Err(e) = self.write_config_to_file().await {
+                    error!("[{}] Failed to save new max RAM: {}", name, e);
+                }
+                format!(
+                    "Server ran out of memory, max RAM was raised from {} MB to {} MB",
+                    max_ram, new_max_ram
+                )
+            }
+            None => match suggest_max_ram(max_ram, host_total_mb, host_total_mb) {
+                Some(suggested) => format!(
+                    "Server ran out of memory, consider raising max RAM from {} MB to {} MB",
+                    max_ram, suggested
+                ),
+                None => format!(
+                    "Server ran out of memory, max RAM ({} MB) cannot be raised any further on this machine",
+                    max_ram
+                ),
+            },
+        };
+        warn!("[{}] {}", name, message);
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_name: name,
+                instance_uuid: self.uuid.clone(),
+                instance_event_inner: InstanceEventInner::InstanceWarning { message },
+            }),
+            snowflake: Snowflake::default(),
+            details: "".to_string(),
+            caused_by: CausedBy::System,
+        });
     }
 }
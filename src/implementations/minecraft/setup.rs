@@ -0,0 +1,503 @@
+//! Checkpoints of the setup of a Minecraft instance, so that a setup that failed part way through
+//! can be retried without redoing the phases that already succeeded
+
+use std::path::Path;
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::user_id::UserId;
+use crate::error::Error;
+use crate::port_manager::InstancePorts;
+
+use super::backup::hash_file;
+use super::quilt::QUILT_SERVER_LAUNCH_JAR;
+use super::{Flavour, SetupConfig};
+
+/// Kept in the instance directory until the setup succeeds
+pub const SETUP_CHECKPOINT_FILE: &str = ".lodestone_setup.json";
+
+/// Phases of the setup, in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SetupPhase {
+    /// Directories, `eula.txt` and `server.properties`
+    Files,
+    Jre,
+    /// The server jar, or the installer that produces it
+    ServerJar,
+    /// The Forge or Quilt installer, or BuildTools, nothing for the other flavours
+    Install,
+}
+
+impl SetupPhase {
+    pub const ALL: [SetupPhase; 4] = [
+        SetupPhase::Files,
+        SetupPhase::Jre,
+        SetupPhase::ServerJar,
+        SetupPhase::Install,
+    ];
+}
+
+/// The jar downloaded in the `ServerJar` phase for `flavour`
+pub fn server_jar_name(flavour: &Flavour) -> &'static str {
+    match flavour {
+        Flavour::Forge { .. } => "forge-installer.jar",
+        Flavour::Spigot => "BuildTools.jar",
+        Flavour::Quilt { .. } => super::quilt::QUILT_INSTALLER_JAR,
+        _ => "server.jar",
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetupCheckpoint {
+    pub setup_config: SetupConfig,
+    /// `None` if the setup wasn't started by a user
+    #[serde(default)]
+    pub created_by: Option<UserId>,
+    #[serde(default)]
+    pub completed: Vec<SetupPhase>,
+    /// The flavour the server jar was downloaded for, with its versions resolved
+    #[serde(default)]
+    pub flavour: Option<Flavour>,
+    #[serde(default)]
+    pub server_jar_sha256: Option<String>,
+}
+
+impl SetupCheckpoint {
+    pub fn new(setup_config: SetupConfig, created_by: Option<UserId>) -> Self {
+        Self {
+            setup_config,
+            created_by,
+            completed: Vec::new(),
+            flavour: None,
+            server_jar_sha256: None,
+        }
+    }
+
+    /// The checkpoint of an instance whose setup hasn't finished, `None` if there is none
+    pub async fn load(path_to_instance: &Path) -> Result<Option<Self>, Error> {
+        let path = path_to_instance.join(SETUP_CHECKPOINT_FILE);
+        match tokio::fs::read_to_string(&path).await {
+            Ok(content) => Ok(Some(serde_json::from_str(&content).context(format!(
+                "Failed to parse setup checkpoint at {}",
+                path.display()
+            ))?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(eyre!(e)
+                .wrap_err(format!(
+                    "Failed to read setup checkpoint at {}",
+                    path.display()
+                ))
+                .into()),
+        }
+    }
+
+    pub async fn save(&self, path_to_instance: &Path) -> Result<(), Error> {
+        let path = path_to_instance.join(SETUP_CHECKPOINT_FILE);
+        tokio::fs::write(
+            &path,
+            serde_json::to_string_pretty(self).context(
+                "Failed to serialize setup checkpoint. This is a bug, please report it.",
+            )?,
+        )
+        .await
+        .context(format!(
+            "Failed to write setup checkpoint to {}",
+            path.display()
+        ))?;
+        Ok(())
+    }
+
+    pub async fn remove(path_to_instance: &Path) -> Result<(), Error> {
+        let path = path_to_instance.join(SETUP_CHECKPOINT_FILE);
+        match tokio::fs::remove_file(&path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(eyre!(e)
+                .wrap_err(format!(
+                    "Failed to remove setup checkpoint at {}",
+                    path.display()
+                ))
+                .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// The ports allocated to the instance when its setup started
+    pub fn ports(&self) -> InstancePorts {
+        InstancePorts {
+            game: self.setup_config.port,
+            rcon: self.setup_config.rcon_port,
+            query: self.setup_config.query_port,
+        }
+    }
+
+    pub fn is_completed(&self, phase: SetupPhase) -> bool {
+        self.completed.contains(&phase)
+    }
+
+    /// Records `phase` as completed, so that a retry skips it
+    pub async fn complete(
+        &mut self,
+        phase: SetupPhase,
+        path_to_instance: &Path,
+    ) -> Result<(), Error> {
+        if !self.is_completed(phase) {
+            self.completed.push(phase);
+        }
+        self.save(path_to_instance).await
+    }
+
+    /// The phases that have to run, given whether the artifacts of each are still valid.
+    ///
+    /// A phase runs if it didn't complete or its artifacts are gone. Installing runs whenever the
+    /// server jar is downloaded again, as it works from the jar
+    pub fn phases_to_run(&self, artifacts_valid: impl Fn(SetupPhase) -> bool) -> Vec<SetupPhase> {
+        let mut to_run = Vec::new();
+        for phase in SetupPhase::ALL {
+            if !self.is_completed(phase)
+                || !artifacts_valid(phase)
+                || (phase == SetupPhase::Install && to_run.contains(&SetupPhase::ServerJar))
+            {
+                to_run.push(phase);
+            }
+        }
+        to_run
+    }
+
+    /// The phases whose artifacts are still in place for an instance of `flavour` at
+    /// `path_to_instance`. `files` are the files the `Files` phase writes, `jre` the java
+    /// executable of the JRE the instance uses
+    pub async fn valid_phases(
+        &self,
+        path_to_instance: &Path,
+        flavour: &Flavour,
+        files: &[&str],
+        jre: &Path,
+    ) -> Vec<SetupPhase> {
+        let mut valid_phases = Vec::new();
+        if files
+            .iter()
+            .all(|file| path_to_instance.join(file).is_file())
+        {
+            valid_phases.push(SetupPhase::Files);
+        }
+        if jre.is_file() {
+            valid_phases.push(SetupPhase::Jre);
+        }
+        let installed = match flavour {
+            Flavour::Forge { .. } => path_to_instance.join("libraries").is_dir(),
+            Flavour::Spigot => path_to_instance.join("server.jar").is_file(),
+            Flavour::Quilt { .. } => path_to_instance.join(QUILT_SERVER_LAUNCH_JAR).is_file(),
+            _ => true,
+        };
+        // BuildTools and the quilt installer are removed once they have installed the server
+        if self.server_jar_valid(path_to_instance).await
+            || (matches!(flavour, Flavour::Spigot | Flavour::Quilt { .. })
+                && self.is_completed(SetupPhase::Install)
+                && installed)
+        {
+            valid_phases.push(SetupPhase::ServerJar);
+        }
+        if installed {
+            valid_phases.push(SetupPhase::Install);
+        }
+        valid_phases
+    }
+
+    /// Whether the jar downloaded in the `ServerJar` phase is still the one that was downloaded
+    pub async fn server_jar_valid(&self, path_to_instance: &Path) -> bool {
+        let (flavour, expected) = match (&self.flavour, &self.server_jar_sha256) {
+            (Some(flavour), Some(expected)) => (flavour, expected.clone()),
+            _ => return false,
+        };
+        let path = path_to_instance.join(server_jar_name(flavour));
+        if !path.is_file() {
+            return false;
+        }
+        matches!(
+            tokio::task::spawn_blocking(move || hash_file(&path)).await,
+            Ok(Ok(actual)) if actual == expected
+        )
+    }
+
+    /// Records the jar downloaded for `flavour`
+    pub async fn record_server_jar(
+        &mut self,
+        flavour: Flavour,
+        path_to_instance: &Path,
+    ) -> Result<(), Error> {
+        let path = path_to_instance.join(server_jar_name(&flavour));
+        self.server_jar_sha256 = Some(
+            tokio::task::spawn_blocking(move || hash_file(&path))
+                .await
+                .context("Failed to hash the server jar")??,
+        );
+        self.flavour = Some(flavour);
+        self.complete(SetupPhase::ServerJar, path_to_instance).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+
+    use super::*;
+
+    fn setup_config() -> SetupConfig {
+        SetupConfig {
+            name: "test".to_string(),
+            version: "1.20.1".to_string(),
+            flavour: Flavour::Vanilla,
+            port: 25565,
+            cmd_args: Vec::new(),
+            description: None,
+            min_ram: None,
+            max_ram: None,
+            auto_start: None,
+            restart_on_crash: None,
+            backup_period: None,
+            max_backups: None,
+            forge_installer_timeout_secs: None,
+            server_properties: IndexMap::new(),
+            jre_vendor: None,
+            jre_version_override: None,
+            rcon_port: None,
+            query_port: None,
+            setup_timeouts: None,
+        }
+    }
+
+    #[test]
+    fn test_retry_skips_completed_phases() {
+        // a failure in each phase leaves the phases before it completed
+        for (failed_at, phase) in SetupPhase::ALL.into_iter().enumerate() {
+            let mut checkpoint = SetupCheckpoint::new(setup_config(), None);
+            checkpoint.completed = SetupPhase::ALL[..failed_at].to_vec();
+            assert_eq!(
+                checkpoint.phases_to_run(|_| true),
+                SetupPhase::ALL[failed_at..].to_vec(),
+                "failed at {:?}",
+                phase
+            );
+        }
+
+        let mut checkpoint = SetupCheckpoint::new(setup_config(), None);
+        checkpoint.completed = SetupPhase::ALL.to_vec();
+        assert!(checkpoint.phases_to_run(|_| true).is_empty());
+    }
+
+    #[test]
+    fn test_retry_redoes_invalid_phases() {
+        let mut checkpoint = SetupCheckpoint::new(setup_config(), None);
+        checkpoint.completed = SetupPhase::ALL.to_vec();
+        // the JRE was removed in the meantime, nothing depends on it being downloaded again
+        assert_eq!(
+            checkpoint.phases_to_run(|phase| phase != SetupPhase::Jre),
+            vec![SetupPhase::Jre]
+        );
+        // a corrupt jar has to be installed again once it is downloaded again
+        assert_eq!(
+            checkpoint.phases_to_run(|phase| phase != SetupPhase::ServerJar),
+            vec![SetupPhase::ServerJar, SetupPhase::Install]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_persists() {
+        let temp_dir = tempdir::TempDir::new("test_setup_checkpoint").unwrap();
+        let path = temp_dir.path();
+        assert!(SetupCheckpoint::load(path).await.unwrap().is_none());
+
+        let mut checkpoint = SetupCheckpoint::new(setup_config(), None);
+        checkpoint.complete(SetupPhase::Files, path).await.unwrap();
+        checkpoint.complete(SetupPhase::Jre, path).await.unwrap();
+        tokio::fs::write(path.join("server.jar"), "jar")
+            .await
+            .unwrap();
+        checkpoint
+            .record_server_jar(Flavour::Vanilla, path)
+            .await
+            .unwrap();
+
+        // as a retry after a failure while installing would see it
+        let checkpoint = SetupCheckpoint::load(path).await.unwrap().unwrap();
+        assert!(checkpoint.server_jar_valid(path).await);
+        assert_eq!(
+            checkpoint.phases_to_run(|_| true),
+            vec![SetupPhase::Install]
+        );
+
+        tokio::fs::write(path.join("server.jar"), "corrupt")
+            .await
+            .unwrap();
+        assert!(!checkpoint.server_jar_valid(path).await);
+
+        SetupCheckpoint::remove(path).await.unwrap();
+        assert!(SetupCheckpoint::load(path).await.unwrap().is_none());
+        // removing it again is fine
+        SetupCheckpoint::remove(path).await.unwrap();
+    }
+
+    /// Leaves `path` as a setup of Forge that failed in `failed_at` would, with the phases before
+    /// it completed and whatever `failed_at` got through before failing
+    async fn fail_setup_at(path: &Path, jre: &Path, failed_at: SetupPhase) -> SetupCheckpoint {
+        let forge = Flavour::Forge {
+            build_version: None,
+        };
+        // saved before the setup starts
+        let mut checkpoint = SetupCheckpoint::new(setup_config(), None);
+        checkpoint.save(path).await.unwrap();
+        for phase in SetupPhase::ALL {
+            let completed = phase != failed_at;
+            match phase {
+                SetupPhase::Files => {
+                    tokio::fs::write(path.join("eula.txt"), "eula=true")
+                        .await
+                        .unwrap();
+                    if completed {
+                        tokio::fs::write(path.join("server.properties"), "")
+                            .await
+                            .unwrap();
+                    }
+                }
+                SetupPhase::Jre => {
+                    // the archive is only extracted once it's fully downloaded
+                    if completed {
+                        tokio::fs::create_dir_all(jre.parent().unwrap())
+                            .await
+                            .unwrap();
+                        tokio::fs::write(jre, "java").await.unwrap();
+                    }
+                }
+                SetupPhase::ServerJar => {
+                    tokio::fs::write(path.join(server_jar_name(&forge)), "forge")
+                        .await
+                        .unwrap();
+                    if completed {
+                        checkpoint
+                            .record_server_jar(forge.clone(), path)
+                            .await
+                            .unwrap();
+                    }
+                }
+                SetupPhase::Install => {
+                    tokio::fs::create_dir_all(path.join("libraries"))
+                        .await
+                        .unwrap();
+                }
+            }
+            if !completed {
+                break;
+            }
+            checkpoint.complete(phase, path).await.unwrap();
+        }
+        checkpoint
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_failure_in_each_phase() {
+        let forge = Flavour::Forge {
+            build_version: None,
+        };
+        for (failed_at, phase) in SetupPhase::ALL.into_iter().enumerate() {
+            let temp_dir = tempdir::TempDir::new("test_setup_failure").unwrap();
+            let path = temp_dir.path();
+            let jre = path.join("runtimes").join("bin").join("java");
+            fail_setup_at(path, &jre, phase).await;
+
+            // as the retry finds it on disk
+            let checkpoint = SetupCheckpoint::load(path).await.unwrap().unwrap();
+            let valid_phases = checkpoint
+                .valid_phases(path, &forge, &["eula.txt", "server.properties"], &jre)
+                .await;
+            assert_eq!(
+                checkpoint.phases_to_run(|phase| valid_phases.contains(&phase)),
+                SetupPhase::ALL[failed_at..].to_vec(),
+                "failed at {:?}",
+                phase
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_artifacts_removed() {
+        let forge = Flavour::Forge {
+            build_version: None,
+        };
+        let temp_dir = tempdir::TempDir::new("test_setup_failure").unwrap();
+        let path = temp_dir.path();
+        let jre = path.join("runtimes").join("bin").join("java");
+        let mut checkpoint = fail_setup_at(path, &jre, SetupPhase::Install).await;
+        checkpoint
+            .complete(SetupPhase::Install, path)
+            .await
+            .unwrap();
+        let files = ["eula.txt", "server.properties"];
+        assert!(checkpoint
+            .valid_phases(path, &forge, &files, &jre)
+            .await
+            .contains(&SetupPhase::Install));
+
+        tokio::fs::remove_file(path.join("server.properties"))
+            .await
+            .unwrap();
+        tokio::fs::remove_dir_all(path.join("libraries"))
+            .await
+            .unwrap();
+        let valid_phases = checkpoint.valid_phases(path, &forge, &files, &jre).await;
+        assert_eq!(
+            checkpoint.phases_to_run(|phase| valid_phases.contains(&phase)),
+            vec![SetupPhase::Files, SetupPhase::Install]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_quilt_installer_removed() {
+        let quilt = Flavour::Quilt {
+            loader_version: None,
+            installer_version: None,
+        };
+        let temp_dir = tempdir::TempDir::new("test_setup_quilt").unwrap();
+        let path = temp_dir.path();
+        let jre = path.join("runtimes").join("bin").join("java");
+        tokio::fs::create_dir_all(jre.parent().unwrap())
+            .await
+            .unwrap();
+        tokio::fs::write(&jre, "java").await.unwrap();
+        let mut checkpoint = SetupCheckpoint::new(setup_config(), None);
+        checkpoint.complete(SetupPhase::Files, path).await.unwrap();
+        checkpoint.complete(SetupPhase::Jre, path).await.unwrap();
+        tokio::fs::write(path.join(server_jar_name(&quilt)), "quilt")
+            .await
+            .unwrap();
+        checkpoint
+            .record_server_jar(quilt.clone(), path)
+            .await
+            .unwrap();
+        // as the installer leaves the instance once it has run
+        tokio::fs::write(path.join(QUILT_SERVER_LAUNCH_JAR), "launch")
+            .await
+            .unwrap();
+        tokio::fs::remove_file(path.join(server_jar_name(&quilt)))
+            .await
+            .unwrap();
+        checkpoint
+            .complete(SetupPhase::Install, path)
+            .await
+            .unwrap();
+
+        let valid_phases = checkpoint.valid_phases(path, &quilt, &[], &jre).await;
+        assert!(checkpoint
+            .phases_to_run(|phase| valid_phases.contains(&phase))
+            .is_empty());
+
+        // the installer has to be downloaded again to install the server again
+        tokio::fs::remove_file(path.join(QUILT_SERVER_LAUNCH_JAR))
+            .await
+            .unwrap();
+        let valid_phases = checkpoint.valid_phases(path, &quilt, &[], &jre).await;
+        assert_eq!(
+            checkpoint.phases_to_run(|phase| valid_phases.contains(&phase)),
+            vec![SetupPhase::ServerJar, SetupPhase::Install]
+        );
+    }
+}
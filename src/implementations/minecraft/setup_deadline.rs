@@ -0,0 +1,245 @@
+//! Bounds on how long the setup of a Minecraft instance may take, so that a hung download or
+//! installer fails the setup instead of leaving the instance pending forever
+
+use std::future::Future;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+use super::setup::SetupPhase;
+
+pub const DEFAULT_SETUP_TIMEOUT_SECS: u64 = 60 * 60;
+pub const DEFAULT_JRE_DOWNLOAD_TIMEOUT_SECS: u64 = 20 * 60;
+pub const DEFAULT_JAR_DOWNLOAD_TIMEOUT_SECS: u64 = 20 * 60;
+
+/// The forge installer has its own timeout, `forge_installer_timeout_secs`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct SetupTimeouts {
+    /// Seconds the whole setup gets to finish, including the steps with their own timeout
+    pub setup_timeout_secs: u64,
+    /// Seconds to download and unpack the JRE
+    pub jre_download_timeout_secs: u64,
+    /// Seconds to download the server jar, or the installer that produces it
+    pub jar_download_timeout_secs: u64,
+}
+
+impl Default for SetupTimeouts {
+    fn default() -> Self {
+        Self {
+            setup_timeout_secs: DEFAULT_SETUP_TIMEOUT_SECS,
+            jre_download_timeout_secs: DEFAULT_JRE_DOWNLOAD_TIMEOUT_SECS,
+            jar_download_timeout_secs: DEFAULT_JAR_DOWNLOAD_TIMEOUT_SECS,
+        }
+    }
+}
+
+impl SetupTimeouts {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.setup_timeout_secs == 0
+            || self.jre_download_timeout_secs == 0
+            || self.jar_download_timeout_secs == 0
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Setup timeouts must be at least one second"),
+            });
+        }
+        Ok(())
+    }
+}
+
+fn describe(phase: SetupPhase) -> &'static str {
+    match phase {
+        SetupPhase::Files => "creating the instance files",
+        SetupPhase::Jre => "downloading the JRE",
+        SetupPhase::ServerJar => "downloading the server jar",
+        SetupPhase::Install => "installing the server",
+    }
+}
+
+/// Tracks the phase a setup is in, so that running out of time reports the step that hung
+pub struct SetupDeadline {
+    timeouts: SetupTimeouts,
+    started_at: Instant,
+    phase: Mutex<SetupPhase>,
+}
+
+impl SetupDeadline {
+    /// Starts counting down the overall timeout
+    pub fn new(timeouts: SetupTimeouts) -> Self {
+        Self {
+            timeouts,
+            started_at: Instant::now(),
+            phase: Mutex::new(SetupPhase::Files),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SetupPhase> {
+        self.phase
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn remaining(&self) -> Duration {
+        Duration::from_secs(self.timeouts.setup_timeout_secs)
+            .saturating_sub(self.started_at.elapsed())
+    }
+
+    fn timed_out(phase: SetupPhase, message: String) -> Error {
+        Error {
+            kind: match phase {
+                SetupPhase::Jre | SetupPhase::ServerJar => ErrorKind::FailedToDownload,
+                SetupPhase::Files | SetupPhase::Install => ErrorKind::Internal,
+            },
+            source: eyre!(message),
+        }
+    }
+
+    fn setup_timed_out(&self) -> Error {
+        let phase = self.phase();
+        Self::timed_out(
+            phase,
+            format!(
+                "Setup didn't finish within {} seconds, it timed out while {}",
+                self.timeouts.setup_timeout_secs,
+                describe(phase)
+            ),
+        )
+    }
+
+    pub fn phase(&self) -> SetupPhase {
+        *self.lock()
+    }
+
+    /// Marks the setup as being in `phase`, for a timeout to report
+    pub fn enter(&self, phase: SetupPhase) {
+        *self.lock() = phase;
+    }
+
+    /// The own timeout of the steps of `phase`, `None` for phases only bounded by the overall one
+    fn step_timeout(&self, phase: SetupPhase) -> Option<Duration> {
+        match phase {
+            SetupPhase::Jre => Some(self.timeouts.jre_download_timeout_secs),
+            SetupPhase::ServerJar => Some(self.timeouts.jar_download_timeout_secs),
+            SetupPhase::Files | SetupPhase::Install => None,
+        }
+        .map(Duration::from_secs)
+    }
+
+    /// Enters `phase` and runs `step` of it, failing with the step that timed out if it doesn't
+    /// finish within the timeout of the phase or the time left of the setup
+    pub async fn step<T>(
+        &self,
+        phase: SetupPhase,
+        step: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        self.enter(phase);
+        let remaining = self.remaining();
+        let (timeout, step_timed_out) = match self.step_timeout(phase) {
+            Some(step_timeout) if step_timeout < remaining => (step_timeout, true),
+            _ => (remaining, false),
+        };
+        match tokio::time::timeout(timeout, step).await {
+            Ok(result) => result,
+            Err(_) if step_timed_out => Err(Self::timed_out(
+                phase,
+                format!(
+                    "Setup timed out {}, it didn't finish within {} seconds",
+                    describe(phase),
+                    timeout.as_secs()
+                ),
+            )),
+            Err(_) => Err(self.setup_timed_out()),
+        }
+    }
+
+    /// Runs the whole `setup`, failing with the phase it was in if it doesn't finish within the
+    /// overall timeout. Dropping the setup kills the installers it runs
+    pub async fn run<T>(&self, setup: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+        match tokio::time::timeout(self.remaining(), setup).await {
+            Ok(result) => result,
+            Err(_) => Err(self.setup_timed_out()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timeouts(setup_timeout_secs: u64, download_timeout_secs: u64) -> SetupTimeouts {
+        SetupTimeouts {
+            setup_timeout_secs,
+            jre_download_timeout_secs: download_timeout_secs,
+            jar_download_timeout_secs: download_timeout_secs,
+        }
+    }
+
+    /// A download that never finishes
+    async fn hung_step() -> Result<(), Error> {
+        std::future::pending().await
+    }
+
+    #[tokio::test]
+    async fn test_hung_step_times_out() {
+        let deadline = SetupDeadline::new(timeouts(60, 1));
+        deadline
+            .step(SetupPhase::Jre, async { Ok(()) })
+            .await
+            .unwrap();
+        let e = deadline
+            .step(SetupPhase::ServerJar, hung_step())
+            .await
+            .unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::FailedToDownload));
+        assert!(e.source.to_string().contains("downloading the server jar"));
+        assert_eq!(deadline.phase(), SetupPhase::ServerJar);
+    }
+
+    #[tokio::test]
+    async fn test_hung_setup_reports_phase() {
+        let deadline = SetupDeadline::new(timeouts(1, 60));
+        let e = deadline
+            .run(async {
+                deadline.step(SetupPhase::Jre, async { Ok(()) }).await?;
+                deadline.enter(SetupPhase::Install);
+                hung_step().await
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::Internal));
+        assert!(e.source.to_string().contains("installing the server"));
+    }
+
+    #[tokio::test]
+    async fn test_step_is_bounded_by_time_left() {
+        // the step's own timeout is longer than what is left of the setup
+        let deadline = SetupDeadline::new(timeouts(1, 60));
+        let started_at = Instant::now();
+        let e = deadline
+            .step(SetupPhase::Jre, hung_step())
+            .await
+            .unwrap_err();
+        assert!(started_at.elapsed() < Duration::from_secs(10));
+        assert!(e
+            .source
+            .to_string()
+            .contains("Setup didn't finish within 1 seconds"));
+        assert!(e.source.to_string().contains("downloading the JRE"));
+    }
+
+    #[test]
+    fn test_validate_timeouts() {
+        assert!(SetupTimeouts::default().validate().is_ok());
+        assert!(matches!(
+            timeouts(0, 60).validate().unwrap_err().kind,
+            ErrorKind::BadRequest
+        ));
+    }
+}
@@ -0,0 +1,289 @@
+//! Saving the world before the server is told to stop, so that chunk changes a plugin held back
+//! from saving aren't lost to an abrupt `stop`, and escalating a stop the server doesn't honor
+
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::error::Error;
+
+/// Flushes every loaded chunk to disk
+pub(super) const SAVE_COMMAND: &str = "save-all flush";
+
+/// Logged by the server once the save is done, by vanilla as `[12:00:00] [Server thread/INFO]:`
+/// and by Paper as `[12:00:00 INFO]:`. Anchored so that a player saying it in chat doesn't count
+pub(super) const SAVED_PATTERN: &str =
+    r"(\[Server thread/INFO\]|\[[0-9:]+ INFO\]): Saved the game\s*$";
+
+/// How long the server has to finish saving before it's told to stop regardless
+pub(super) const SAVE_BEFORE_STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn write_line<W: AsyncWrite + Unpin>(
+    stdin: &Mutex<Option<W>>,
+    line: &str,
+) -> std::io::Result<()> {
+    stdin
+        .lock()
+        .await
+        .as_mut()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "stdin not available"))?
+        .write_all(format!("{}\n", line).as_bytes())
+        .await
+}
+
+/// Writes `stop_command` to the server's stdin, first saving the world if `saved` is given.
+/// `saved` resolves once the server reports the save done, it has to be listening before it's
+/// passed in. Stdin isn't held while the server saves, so commands can still be sent. A save that
+/// fails or times out doesn't keep the server from stopping
+pub(super) async fn write_stop_sequence<W: AsyncWrite + Unpin>(
+    stdin: &Mutex<Option<W>>,
+    stop_command: &str,
+    saved: Option<impl Future<Output = Result<String, Error>>>,
+) -> std::io::Result<()> {
+    if let Some(saved) = saved {
+        write_line(stdin, SAVE_COMMAND).await?;
+        if let Err(e) = saved.await {
+            warn!(
+                "World may not be saved, stopping the server anyway: {}",
+                e.source
+            );
+        }
+    }
+    write_line(stdin, stop_command).await
+}
+
+/// How far a stop had to be escalated before the server went down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum StopEscalation {
+    /// It stopped when asked to
+    None,
+    /// It stopped after being sent SIGTERM
+    Terminated,
+    /// It had to be killed
+    Killed,
+}
+
+/// Waits `timeout` for a server that was asked to stop, terminating it if it hasn't stopped by
+/// then and killing it if it still hasn't as long again after that.
+/// `stopped` resolves to whether the server stopped within the duration it's given,
+/// `terminate` to whether SIGTERM could be sent, the server is killed right away if it couldn't
+pub(super) async fn escalate_stop<S, SF, T, TF, K, KF>(
+    timeout: Duration,
+    mut stopped: S,
+    terminate: T,
+    kill: K,
+) -> StopEscalation
+where
+    S: FnMut(Duration) -> SF,
+    SF: Future<Output = bool>,
+    T: FnOnce() -> TF,
+    TF: Future<Output = bool>,
+    K: FnOnce() -> KF,
+    KF: Future<Output = ()>,
+{
+    if stopped(timeout).await {
+        return StopEscalation::None;
+    }
+    if terminate().await && stopped(timeout).await {
+        return StopEscalation::Terminated;
+    }
+    kill().await;
+    StopEscalation::Killed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use color_eyre::eyre::eyre;
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::event_broadcaster::EventBroadcaster;
+    use crate::events::Event;
+    use crate::implementations::minecraft::console_matcher::{
+        parse_pattern, wait_for_console_line,
+    };
+    use crate::types::InstanceUuid;
+
+    #[tokio::test]
+    async fn test_save_precedes_stop() {
+        let (event_broadcaster, _rx) = EventBroadcaster::new(64);
+        let instance_uuid = InstanceUuid::default();
+        let pattern = parse_pattern(SAVED_PATTERN).unwrap();
+        let saved = wait_for_console_line(
+            &event_broadcaster,
+            event_broadcaster.subscribe(),
+            &instance_uuid,
+            &pattern,
+            Duration::from_secs(5),
+        );
+        event_broadcaster.send(Event::new_instance_output(
+            instance_uuid.clone(),
+            "test".to_string(),
+            "[Server thread/INFO]: Saved the game".to_string(),
+        ));
+        let stdin = Mutex::new(Some(Vec::new()));
+        write_stop_sequence(&stdin, "stop", Some(saved))
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(stdin.into_inner().unwrap()).unwrap(),
+            "save-all flush\nstop\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stdin_released_while_saving() {
+        let stdin = Mutex::new(Some(Vec::new()));
+        let saved = async {
+            // e.g. a command sent from the console while the server saves
+            stdin
+                .try_lock()
+                .expect("stdin is held while saving")
+                .as_mut()
+                .unwrap()
+                .extend_from_slice(b"say Stopping\n");
+            Ok("[Server thread/INFO]: Saved the game".to_string())
+        };
+        write_stop_sequence(&stdin, "stop", Some(saved))
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(stdin.into_inner().unwrap()).unwrap(),
+            "save-all flush\nsay Stopping\nstop\n"
+        );
+    }
+
+    #[test]
+    fn test_saved_pattern() {
+        let pattern = parse_pattern(SAVED_PATTERN).unwrap();
+        assert!(pattern.is_match("[12:00:00] [Server thread/INFO]: Saved the game"));
+        assert!(pattern.is_match("[12:00:00 INFO]: Saved the game"));
+        assert!(!pattern.is_match("[12:00:00] [Server thread/INFO]: <Steve> Saved the game"));
+        assert!(!pattern.is_match("[12:00:00] [Server thread/INFO]: Saved the game? not yet"));
+    }
+
+    #[tokio::test]
+    async fn test_stops_when_save_fails() {
+        let stdin = Mutex::new(Some(Vec::new()));
+        let saved = async { Err(eyre!("No console line matched").into()) };
+        write_stop_sequence(&stdin, "stop", Some(saved))
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(stdin.into_inner().unwrap()).unwrap(),
+            "save-all flush\nstop\n"
+        );
+
+        // turned off
+        let stdin = Mutex::new(Some(Vec::new()));
+        write_stop_sequence(
+            &stdin,
+            "stop",
+            None::<std::future::Ready<Result<String, Error>>>,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(stdin.into_inner().unwrap()).unwrap(),
+            "stop\n"
+        );
+    }
+
+    /// A server that stops `stops_after` after being asked to, or after being terminated if
+    /// `honors_term`, checked the way the instance polls its state
+    struct FakeServer {
+        asked_at: Instant,
+        stops_after: Option<Duration>,
+        honors_term: bool,
+        terminated_at: Arc<std::sync::Mutex<Option<Instant>>>,
+        killed: Arc<AtomicBool>,
+    }
+
+    impl FakeServer {
+        fn new(stops_after: Option<Duration>, honors_term: bool) -> Self {
+            Self {
+                asked_at: Instant::now(),
+                stops_after,
+                honors_term,
+                terminated_at: Arc::new(std::sync::Mutex::new(None)),
+                killed: Arc::new(AtomicBool::new(false)),
+            }
+        }
+
+        fn is_stopped(&self) -> bool {
+            let now = Instant::now();
+            self.stops_after
+                .map_or(false, |after| now >= self.asked_at + after)
+                || (self.honors_term && self.terminated_at.lock().unwrap().is_some())
+        }
+
+        async fn escalate(&self, timeout: Duration, can_terminate: bool) -> StopEscalation {
+            escalate_stop(
+                timeout,
+                |timeout| async move {
+                    tokio::time::timeout(timeout, async {
+                        while !self.is_stopped() {
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                        }
+                    })
+                    .await
+                    .is_ok()
+                },
+                || async move {
+                    if can_terminate {
+                        *self.terminated_at.lock().unwrap() = Some(Instant::now());
+                    }
+                    can_terminate
+                },
+                || async move { self.killed.store(true, Ordering::SeqCst) },
+            )
+            .await
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stops_within_timeout() {
+        let server = FakeServer::new(Some(Duration::from_secs(5)), true);
+        let escalation = server.escalate(Duration::from_secs(60), true).await;
+        assert_eq!(escalation, StopEscalation::None);
+        assert!(server.terminated_at.lock().unwrap().is_none());
+        assert!(!server.killed.load(Ordering::SeqCst));
+        assert!(server.asked_at.elapsed() < Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_terminated_after_timeout() {
+        let server = FakeServer::new(None, true);
+        let escalation = server.escalate(Duration::from_secs(60), true).await;
+        assert_eq!(escalation, StopEscalation::Terminated);
+        let terminated_at = server.terminated_at.lock().unwrap().unwrap();
+        let waited = terminated_at.duration_since(server.asked_at);
+        assert!(waited >= Duration::from_secs(60) && waited < Duration::from_secs(61));
+        assert!(!server.killed.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_killed_when_term_is_ignored() {
+        let server = FakeServer::new(None, false);
+        let escalation = server.escalate(Duration::from_secs(60), true).await;
+        assert_eq!(escalation, StopEscalation::Killed);
+        assert!(server.killed.load(Ordering::SeqCst));
+        // as long again after the SIGTERM
+        let waited = server.asked_at.elapsed();
+        assert!(waited >= Duration::from_secs(120) && waited < Duration::from_secs(121));
+
+        // a server that can't be sent SIGTERM is killed right away
+        let server = FakeServer::new(None, true);
+        let escalation = server.escalate(Duration::from_secs(60), false).await;
+        assert_eq!(escalation, StopEscalation::Killed);
+        assert!(server.killed.load(Ordering::SeqCst));
+        let waited = server.asked_at.elapsed();
+        assert!(waited >= Duration::from_secs(60) && waited < Duration::from_secs(61));
+    }
+}
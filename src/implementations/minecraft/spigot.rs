@@ -0,0 +1,338 @@
+use std::cmp::Ordering;
+use std::path::Path;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use tokio::process::Command;
+use tracing::warn;
+
+use super::installer::run_installer;
+use super::Flavour;
+use crate::error::{Error, ErrorKind};
+use crate::upstream_cache::cached_get_text;
+
+/// Spigot can't be redistributed, so servers are compiled locally with BuildTools
+pub const BUILD_TOOLS_URL: &str =
+    "https://hub.spigotmc.org/jenkins/job/BuildTools/lastSuccessfulBuild/artifact/target/BuildTools.jar";
+/// Default time BuildTools gets to finish before it's killed, compiling takes a while
+pub const DEFAULT_BUILD_TOOLS_TIMEOUT_SECS: u64 = 1800;
+
+/// Compares minecraft release versions like `1.19.4` numerically
+fn compare_release_versions(a: &str, b: &str) -> Ordering {
+    let parse = |version: &str| -> Vec<u64> {
+        version
+            .split('.')
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
+    };
+    parse(a).cmp(&parse(b))
+}
+
+/// Parses the versions BuildTools can build from the listing at hub.spigotmc.org/versions
+fn parse_spigot_versions(listing: &str) -> Vec<String> {
+    let mut versions: Vec<String> = listing
+        .split("href=\"")
+        .skip(1)
+        .filter_map(|link| link.split('"').next())
+        .filter_map(|file| file.strip_suffix(".json"))
+        .filter(|version| {
+            // the listing also has build numbers and snapshots, only keep releases
+            version.split('.').count() >= 2
+                && version.starts_with("1.")
+                && version.split('.').all(|part| part.parse::<u64>().is_ok())
+        })
+        .map(|version| version.to_string())
+        .collect();
+    versions.sort_by(|a, b| compare_release_versions(b, a));
+    versions.dedup();
+    versions
+}
+
+pub async fn get_spigot_minecraft_versions() -> Result<Vec<String>, Error> {
+    let versions = parse_spigot_versions(
+        &cached_get_text("https://hub.spigotmc.org/versions/")
+            .await
+            .context("Failed to get spigot versions")?,
+    );
+    if versions.is_empty() {
+        return Err(eyre!("Failed to get spigot versions, the version listing is empty").into());
+    }
+    Ok(versions)
+}
+
+/// There is no spigot jar to download, this is the URL of the BuildTools that compiles it
+pub async fn get_spigot_jar_url(version: &str) -> Option<(String, Flavour)> {
+    get_spigot_minecraft_versions()
+        .await
+        .ok()?
+        .iter()
+        .any(|v| v == version)
+        .then(|| (BUILD_TOOLS_URL.to_string(), Flavour::Spigot))
+}
+
+/// A milestone of a BuildTools run, parsed from its output
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BuildToolsStep {
+    Cloning(String),
+    Downloading(String),
+    Decompiling,
+    Patching,
+    Compiling(String),
+    Done,
+}
+
+impl BuildToolsStep {
+    /// Parses a line of output from BuildTools
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if let Some(repo) = line.strip_prefix("Starting clone of ") {
+            Some(Self::Cloning(repo.to_string()))
+        } else if let Some(url) = line.strip_prefix("Starting download of ") {
+            Some(Self::Downloading(url.to_string()))
+        } else if line.starts_with("Decompiling") {
+            Some(Self::Decompiling)
+        } else if line.starts_with("Patching with ") {
+            Some(Self::Patching)
+        } else if let Some(project) = line.strip_prefix("Compiling ") {
+            Some(Self::Compiling(project.to_string()))
+        } else if line.starts_with("Success! Everything completed successfully") {
+            Some(Self::Done)
+        } else {
+            None
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Self::Cloning(repo) => format!("Cloning {}", repo),
+            Self::Downloading(url) => format!("Downloading {}", url),
+            Self::Decompiling => "Decompiling minecraft server".to_string(),
+            Self::Patching => "Applying patches".to_string(),
+            Self::Compiling(project) => format!("Compiling {}", project),
+            Self::Done => "Copying the compiled server".to_string(),
+        }
+    }
+
+    /// Roughly how far along the build is once this step is reached, between 0 and 1
+    fn fraction(&self) -> f64 {
+        match self {
+            Self::Cloning(_) => 0.05,
+            Self::Downloading(_) => 0.15,
+            Self::Decompiling => 0.25,
+            Self::Patching => 0.4,
+            Self::Compiling(project) if project.starts_with("Bukkit") => 0.55,
+            Self::Compiling(project) if project.starts_with("CraftBukkit") => 0.7,
+            Self::Compiling(_) => 0.85,
+            Self::Done => 1.0,
+        }
+    }
+}
+
+/// Turns BuildTools steps into progress increments adding up to at most `total`
+struct BuildToolsProgress {
+    total: f64,
+    reported: f64,
+}
+
+impl BuildToolsProgress {
+    fn new(total: f64) -> Self {
+        Self {
+            total,
+            reported: 0.0,
+        }
+    }
+
+    fn advance(&mut self, step: &BuildToolsStep) -> f64 {
+        let reached = step.fraction() * self.total;
+        if reached <= self.reported {
+            return 0.0;
+        }
+        let increment = reached - self.reported;
+        self.reported = reached;
+        increment
+    }
+}
+
+/// BuildTools reports a version it has no build data for with a line like
+/// `Could not get version 1.7.10 does it exist? Try another version or use 'latest'`
+fn is_unsupported_version_line(line: &str) -> bool {
+    line.trim_start().starts_with("Could not get version ")
+}
+
+/// Runs `BuildTools.jar` from `path_to_instance` to compile spigot `version` into `server.jar`,
+/// killing it if it doesn't finish within `timeout`.
+///
+/// BuildTools works in a scratch directory that is removed afterwards, along with `BuildTools.jar`.
+/// `on_progress` is called with a message and a progress increment for every milestone BuildTools reports,
+/// the increments add up to at most `total_progress`.
+/// `on_heartbeat` is called periodically with the time elapsed, so that the caller can show the build is still going.
+/// The tail of BuildTools' output is included in the error if it fails.
+pub async fn run_build_tools(
+    jre: &Path,
+    path_to_instance: &Path,
+    version: &str,
+    timeout: Duration,
+    total_progress: f64,
+    on_progress: impl Fn(&str, f64),
+    on_heartbeat: impl Fn(Duration),
+) -> Result<(), Error> {
+    let path_to_build_tools = path_to_instance.join("BuildTools.jar");
+    let path_to_work_dir = path_to_instance.join("build_tools");
+    tokio::fs::create_dir_all(&path_to_work_dir)
+        .await
+        .context("Failed to create BuildTools directory")?;
+    let result = async {
+        run_build_tools_in(
+            jre,
+            &path_to_build_tools,
+            &path_to_work_dir,
+            version,
+            timeout,
+            total_progress,
+            on_progress,
+            on_heartbeat,
+        )
+        .await?;
+        // BuildTools puts the compiled server in its working directory
+        let path_to_spigot_jar = path_to_work_dir.join(format!("spigot-{}.jar", version));
+        tokio::fs::rename(&path_to_spigot_jar, path_to_instance.join("server.jar"))
+            .await
+            .context(format!(
+                "BuildTools finished but {} could not be moved to server.jar",
+                path_to_spigot_jar.display()
+            ))?;
+        Ok::<(), Error>(())
+    }
+    .await;
+    // BuildTools leaves gigabytes of sources behind
+    if let Err(e) = crate::util::fs::remove_dir_all(&path_to_work_dir).await {
+        warn!("Failed to remove BuildTools directory: {}", e);
+    }
+    let _ = tokio::fs::remove_file(&path_to_build_tools).await;
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run_build_tools_in(
+    jre: &Path,
+    path_to_build_tools: &Path,
+    path_to_work_dir: &Path,
+    version: &str,
+    timeout: Duration,
+    total_progress: f64,
+    on_progress: impl Fn(&str, f64),
+    on_heartbeat: impl Fn(Duration),
+) -> Result<(), Error> {
+    let mut progress = BuildToolsProgress::new(total_progress);
+    let mut unsupported_version = false;
+    let run = run_installer(
+        "BuildTools",
+        Command::new(jre)
+            .arg("-jar")
+            .arg(path_to_build_tools)
+            .arg("--rev")
+            .arg(version)
+            .current_dir(path_to_work_dir),
+        timeout,
+        |line, is_stdout| {
+            if is_stdout {
+                if let Some(step) = BuildToolsStep::parse(line) {
+                    on_progress(&step.message(), progress.advance(&step));
+                }
+            }
+            unsupported_version |= is_unsupported_version_line(line);
+        },
+        on_heartbeat,
+    )
+    .await?;
+    if run.status.success() {
+        Ok(())
+    } else if unsupported_version {
+        Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "BuildTools cannot build spigot {}, pick another version",
+                version
+            ),
+        })
+    } else {
+        Err(Error {
+            kind: ErrorKind::Internal,
+            source: eyre!(
+                "Failed to build spigot {}, BuildTools exited with {}. Last output:\n{}",
+                version,
+                run.status,
+                run.output_tail
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_spigot_versions() {
+        let listing = r#"<html><body><pre>
+<a href="../">../</a>
+<a href="1.19.4.json">1.19.4.json</a>
+<a href="1.8.json">1.8.json</a>
+<a href="1.20.1.json">1.20.1.json</a>
+<a href="3745.json">3745.json</a>
+<a href="1.20-pre1.json">1.20-pre1.json</a>
+<a href="latest.json">latest.json</a>
+</pre></body></html>"#;
+        assert_eq!(
+            parse_spigot_versions(listing),
+            vec![
+                "1.20.1".to_string(),
+                "1.19.4".to_string(),
+                "1.8".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_tools_progress() {
+        let output = [
+            "Attempting to build version: '1.20.1' use --rev <version> to override",
+            "Starting clone of https://hub.spigotmc.org/stash/scm/spigot/bukkit.git to Bukkit",
+            "Starting download of https://piston-data.mojang.com/v1/objects/server.jar",
+            "Decompiling class files",
+            "Patching with Block.patch",
+            "Patching with Blocks.patch",
+            "Compiling Bukkit",
+            "Compiling CraftBukkit",
+            "Compiling Spigot & Spigot-API",
+            "Success! Everything completed successfully. Copying final .jar files now.",
+        ];
+        let steps: Vec<BuildToolsStep> = output
+            .iter()
+            .filter_map(|line| BuildToolsStep::parse(line))
+            .collect();
+        assert_eq!(steps.len(), 9);
+        assert_eq!(
+            steps[6],
+            BuildToolsStep::Compiling("CraftBukkit".to_string())
+        );
+
+        let mut progress = BuildToolsProgress::new(3.0);
+        let increments: Vec<f64> = steps.iter().map(|step| progress.advance(step)).collect();
+        assert!(increments.iter().all(|increment| *increment >= 0.0));
+        // patching is reported once no matter how many files are patched
+        assert_eq!(increments[4], 0.0);
+        let total: f64 = increments.iter().sum();
+        assert!((total - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_unsupported_version_line() {
+        assert!(is_unsupported_version_line(
+            "Could not get version 1.7.10 does it exist? Try another version or use 'latest'"
+        ));
+        assert!(!is_unsupported_version_line(
+            "Attempting to build version: '1.7.10' use --rev <version> to override"
+        ));
+    }
+}
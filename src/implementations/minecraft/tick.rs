@@ -0,0 +1,266 @@
+//! Tick performance (TPS and MSPT) of a running server, queried over RCON with whichever command
+//! the flavour has for it
+
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use tracing::warn;
+
+use crate::error::{Error, ErrorKind};
+use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::traits::t_server::{State, TickPerformance};
+use crate::types::Snowflake;
+
+use super::{Flavour, MinecraftInstance};
+
+/// Queries made sooner than this after the last one are answered from the cache
+const MIN_QUERY_INTERVAL_SECS: i64 = 5;
+
+/// How often tick performance is reported as an event while the server is running
+const REPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The rate vanilla ticks at when it isn't lagging
+const TARGET_TPS: f64 = 20.0;
+
+/// Removes `§` formatting codes, which plugins colour their output with
+fn strip_formatting(s: &str) -> String {
+    let mut stripped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+fn parse_number(s: &str) -> Option<f64> {
+    // paper marks TPS capped at 20 with a `*`
+    let s = s.trim().trim_start_matches('*');
+    let end = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    s[..end].parse().ok()
+}
+
+fn number_after(s: &str, label: &str) -> Option<f64> {
+    s.find(label)
+        .and_then(|i| parse_number(&s[i + label.len()..]))
+}
+
+/// Parses the output of the `tps` command of Paper and Spigot:
+/// `TPS from last 1m, 5m, 15m: 20.0, 19.8, 19.9`
+pub fn parse_tps(output: &str) -> Option<[Option<f64>; 3]> {
+    let output = strip_formatting(output);
+    let values = &output[output.find("TPS from last")?..];
+    let values = &values[values.find(':')? + 1..];
+    let mut tps = values.split(',').map(parse_number);
+    let tps = [
+        tps.next().flatten(),
+        tps.next().flatten(),
+        tps.next().flatten(),
+    ];
+    tps[0].map(|_| tps)
+}
+
+/// Parses the average tick time over the shortest period from the output of Paper's `mspt` command:
+/// `Server tick times (avg/min/max) from last 5s, 10s, 1m: ◴ 2.1/1.0/5.2, 2.0/1.0/5.2, 2.0/0.9/5.4`
+pub fn parse_mspt(output: &str) -> Option<f64> {
+    let output = strip_formatting(output);
+    let values = &output[output.find("tick times")?..];
+    let values = &values[values.find(':')? + 1..];
+    values
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .find(|token| token.contains('/'))
+        .and_then(|token| token.split('/').next())
+        .and_then(parse_number)
+}
+
+/// Parses the overall line of the output of Forge's `forge tps` command, which is either
+/// `Overall: Mean tick time: 2.345 ms. Mean TPS: 20.000` or `Overall: 20.000 TPS (2.345 ms/tick)`
+pub fn parse_forge_tps(output: &str) -> Option<(f64, f64)> {
+    let output = strip_formatting(output);
+    let overall = output.lines().find(|line| line.contains("Overall"))?;
+    let overall = &overall[overall.find(':')? + 1..];
+    match (
+        number_after(overall, "Mean TPS:"),
+        number_after(overall, "Mean tick time:"),
+    ) {
+        (Some(tps), Some(mspt)) => Some((tps, mspt)),
+        _ => Some((parse_number(overall)?, number_after(overall, "(")?)),
+    }
+}
+
+/// Parses the output of the vanilla `tick query` command, available since 1.20.3:
+/// `Target tick rate: 20.0 per second. Average time per tick: 2.3ms (Target: 50.0ms)`
+pub fn parse_tick_query(output: &str) -> Option<(f64, f64)> {
+    let output = strip_formatting(output);
+    let mspt = number_after(&output, "Average time per tick:")?;
+    let target_tps = number_after(&output, "Target tick rate:").unwrap_or(TARGET_TPS);
+    // the server can't tick faster than its target, however little time a tick takes
+    let tps = if mspt > 0.0 {
+        (1000.0 / mspt).min(target_tps)
+    } else {
+        target_tps
+    };
+    Some((tps, mspt))
+}
+
+impl MinecraftInstance {
+    async fn query_tick_performance(&self) -> Result<TickPerformance, Error> {
+        if self.rcon_conn.lock().await.is_none() {
+            return Err(Error {
+                kind: ErrorKind::RconNotOpen,
+                source: eyre!("The server must be running with RCON connected"),
+            });
+        }
+        let flavour = self.config.lock().await.flavour.clone();
+        let mut performance = TickPerformance {
+            measured_at: chrono::Utc::now().timestamp(),
+            ..Default::default()
+        };
+        match flavour {
+            Flavour::Paper { .. } | Flavour::Spigot => {
+                let [tps, tps_5m, tps_15m] = parse_tps(&self.query_rcon("tps").await?)
+                    .ok_or_else(|| eyre!("Failed to parse the output of the tps command"))?;
+                performance.tps = tps;
+                performance.tps_5m = tps_5m;
+                performance.tps_15m = tps_15m;
+                // only paper has mspt
+                if let Flavour::Paper { .. } = flavour {
+                    performance.mspt = parse_mspt(&self.query_rcon("mspt").await?);
+                }
+            }
+            Flavour::Vanilla
+            | Flavour::Fabric { .. }
+            | Flavour::Quilt { .. }
+            | Flavour::Forge { .. } => {
+                let forge_tps = match flavour {
+                    Flavour::Forge { .. } => self
+                        .query_rcon("forge tps")
+                        .await
+                        .ok()
+                        .and_then(|output| parse_forge_tps(&output)),
+                    _ => None,
+                };
+                let (tps, mspt) = match forge_tps {
+                    Some(forge_tps) => forge_tps,
+                    None => parse_tick_query(&self.query_rcon("tick query").await?).ok_or_else(
+                        || Error {
+                            kind: ErrorKind::UnsupportedOperation,
+                            source: eyre!(
+                                "This server has no command reporting tick performance, it needs Minecraft 1.20.3 or later"
+                            ),
+                        },
+                    )?,
+                };
+                performance.tps = Some(tps);
+                performance.mspt = Some(mspt);
+            }
+        }
+        Ok(performance)
+    }
+
+    /// The tick performance of the server, measured at most every [`MIN_QUERY_INTERVAL_SECS`]
+    pub(super) async fn cached_tick_performance(&self) -> Result<TickPerformance, Error> {
+        // held while querying, so that concurrent requests share a single query
+        let mut cache = self.tick_performance.lock().await;
+        if let Some(performance) = cache.as_ref() {
+            if chrono::Utc::now().timestamp() - performance.measured_at < MIN_QUERY_INTERVAL_SECS {
+                return Ok(performance.clone());
+            }
+        }
+        let performance = self.query_tick_performance().await?;
+        cache.replace(performance.clone());
+        Ok(performance)
+    }
+
+    /// Reports the tick performance of the server started as `pid` as an event every
+    /// [`REPORT_INTERVAL`], until it exits
+    pub(super) fn spawn_tick_reporter(&self, pid: Option<u32>) {
+        let instance = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(REPORT_INTERVAL).await;
+                let current_pid = instance.process.lock().await.as_ref().and_then(|p| p.id());
+                if current_pid.is_none() || current_pid != pid {
+                    return;
+                }
+                if *instance.state.lock().await != State::Running {
+                    continue;
+                }
+                let tick_performance = match instance.cached_tick_performance().await {
+                    Ok(tick_performance) => tick_performance,
+                    Err(e) if matches!(e.kind, ErrorKind::UnsupportedOperation) => return,
+                    // e.g. RCON isn't connected yet
+                    Err(e) => {
+                        let name = instance.config.lock().await.name.clone();
+                        warn!("[{}] Failed to query tick performance: {}", name, e.source);
+                        continue;
+                    }
+                };
+                instance.event_broadcaster.send(Event {
+                    event_inner: EventInner::InstanceEvent(InstanceEvent {
+                        instance_uuid: instance.uuid.clone(),
+                        instance_name: instance.config.lock().await.name.clone(),
+                        instance_event_inner: InstanceEventInner::TickPerformance {
+                            tick_performance,
+                        },
+                    }),
+                    snowflake: Snowflake::default(),
+                    details: "".to_string(),
+                    caused_by: CausedBy::System,
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tps() {
+        assert_eq!(
+            parse_tps("§6TPS from last 1m, 5m, 15m: §a*20.0, §a19.87, §e17.5"),
+            Some([Some(20.0), Some(19.87), Some(17.5)])
+        );
+        assert_eq!(parse_tps("Unknown command. Type \"/help\" for help."), None);
+    }
+
+    #[test]
+    fn test_parse_mspt() {
+        assert_eq!(
+            parse_mspt("§6Server tick times §e(§7avg§e/§7min§e/§7max§e)§6 from last 5s§7,§6 10s§7,§6 1m§e:\n§6◴ §a2.1§7/§a1.0§7/§a5.2§7, §a2.0§7/§a1.0§7/§a5.2§7, §a2.0§7/§a0.9§7/§a5.4"),
+            Some(2.1)
+        );
+        assert_eq!(parse_mspt("Unknown command"), None);
+    }
+
+    #[test]
+    fn test_parse_forge_tps() {
+        let output = "Dim minecraft:overworld (minecraft:overworld): Mean tick time: 1.000 ms. Mean TPS: 20.000\nOverall: Mean tick time: 2.345 ms. Mean TPS: 19.500";
+        assert_eq!(parse_forge_tps(output), Some((19.5, 2.345)));
+        assert_eq!(
+            parse_forge_tps("Overall: 20.000 TPS (2.345 ms/tick)"),
+            Some((20.0, 2.345))
+        );
+    }
+
+    #[test]
+    fn test_parse_tick_query() {
+        let output = "The game is running normally\nTarget tick rate: 20.0 per second.\nAverage time per tick: 2.3ms (Target: 50.0ms)";
+        assert_eq!(parse_tick_query(output), Some((20.0, 2.3)));
+        // lagging
+        let output =
+            "Target tick rate: 20.0 per second.\nAverage time per tick: 100.0ms (Target: 50.0ms)";
+        assert_eq!(parse_tick_query(output), Some((10.0, 100.0)));
+        assert_eq!(
+            parse_tick_query("Unknown or incomplete command, see below for error"),
+            None
+        );
+    }
+}
@@ -0,0 +1,400 @@
+use std::cmp::Ordering as CmpOrdering;
+
+use chrono::Timelike;
+use color_eyre::eyre::eyre;
+use tracing::{info, warn};
+
+use crate::error::{Error, ErrorKind};
+use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::global_settings::core_timezone;
+use crate::traits::t_configurable::{
+    AutoUpdateMode, AvailableUpdate, MaintenanceWindow, UpdateStatus,
+};
+use crate::traits::t_server::{State, TServer};
+use crate::types::Snowflake;
+use crate::util::download_file;
+
+use super::fabric::get_fabric_minecraft_versions;
+use super::forge::get_forge_minecraft_versions;
+use super::paper::{
+    get_paper_builds, get_paper_minecraft_versions, latest_stable_paper_build, PaperBuild,
+};
+use super::quilt::get_quilt_minecraft_versions;
+use super::spigot::get_spigot_minecraft_versions;
+use super::util::{get_fabric_jar_url, get_paper_jar_url};
+use super::vanilla::get_vanilla_minecraft_versions;
+use super::{FabricLoaderVersion, Flavour, MinecraftInstance, PaperBuildVersion};
+
+/// Compares dotted versions such as `0.14.21` part by part, numerically where both parts are numbers
+pub(super) fn compare_dotted_versions(a: &str, b: &str) -> CmpOrdering {
+    let mut a_parts = a.split('.');
+    let mut b_parts = b.split('.');
+    loop {
+        match (a_parts.next(), b_parts.next()) {
+            (Some(a_part), Some(b_part)) => {
+                let ordering = match (a_part.parse::<u64>(), b_part.parse::<u64>()) {
+                    (Ok(a_num), Ok(b_num)) => a_num.cmp(&b_num),
+                    _ => a_part.cmp(b_part),
+                };
+                if ordering != CmpOrdering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(_), None) => return CmpOrdering::Greater,
+            (None, Some(_)) => return CmpOrdering::Less,
+            (None, None) => return CmpOrdering::Equal,
+        }
+    }
+}
+
+/// The latest stable paper build, if it is newer than `current`
+fn newer_paper_build(current: i64, builds: &[PaperBuild]) -> Option<&PaperBuild> {
+    latest_stable_paper_build(builds).filter(|latest| latest.build > current)
+}
+
+fn is_newer_fabric_loader(current: &str, latest: &str) -> bool {
+    compare_dotted_versions(latest, current) == CmpOrdering::Greater
+}
+
+/// Whether `version` is a release such as `1.19.2`, not a snapshot or pre-release
+pub(super) fn is_release_version(version: &str) -> bool {
+    version
+        .split('.')
+        .all(|part| !part.is_empty() && part.parse::<u64>().is_ok())
+}
+
+/// The newest release in `versions`, if it is newer than `current`
+fn newer_release(current: &str, versions: &[String]) -> Option<String> {
+    versions
+        .iter()
+        .filter(|version| is_release_version(version))
+        .max_by(|a, b| compare_dotted_versions(a, b))
+        .filter(|latest| compare_dotted_versions(latest, current) == CmpOrdering::Greater)
+        .cloned()
+}
+
+async fn get_minecraft_versions(flavour: &Flavour) -> Result<Vec<String>, Error> {
+    match flavour {
+        Flavour::Vanilla => get_vanilla_minecraft_versions().await,
+        Flavour::Fabric { .. } => get_fabric_minecraft_versions().await,
+        Flavour::Paper { .. } => get_paper_minecraft_versions().await,
+        Flavour::Spigot => get_spigot_minecraft_versions().await,
+        Flavour::Forge { .. } => get_forge_minecraft_versions().await,
+        Flavour::Quilt { .. } => get_quilt_minecraft_versions().await,
+    }
+}
+
+/// A newer server jar for the same minecraft version
+struct JarUpdate {
+    url: String,
+    flavour: Flavour,
+    info: AvailableUpdate,
+}
+
+/// Finds a newer build of the server jar of `version`.
+///
+/// Vanilla, Spigot and Forge jars are tied to the minecraft version, so they never have one.
+/// Neither do Quilt servers, which are installed rather than downloaded as a jar
+async fn find_jar_update(version: &str, flavour: &Flavour) -> Result<Option<JarUpdate>, Error> {
+    match flavour {
+        Flavour::Paper {
+            build_version: Some(PaperBuildVersion(current)),
+        } => {
+            let builds = get_paper_builds(version).await?;
+            let latest = match newer_paper_build(*current, &builds) {
+                Some(latest) => latest.build,
+                None => return Ok(None),
+            };
+            let (url, flavour) =
+                get_paper_jar_url(version, &Some(PaperBuildVersion(latest))).await?;
+            Ok(Some(JarUpdate {
+                url,
+                flavour,
+                info: AvailableUpdate {
+                    current: format!("build {}", current),
+                    available: format!("build {}", latest),
+                },
+            }))
+        }
+        Flavour::Fabric {
+            loader_version: Some(FabricLoaderVersion(current)),
+            ..
+        } => {
+            let (url, flavour) = get_fabric_jar_url(version, &None, &None)
+                .await
+                .ok_or_else(|| eyre!("Failed to get the latest fabric loader"))?;
+            let latest = match &flavour {
+                Flavour::Fabric {
+                    loader_version: Some(FabricLoaderVersion(latest)),
+                    ..
+                } if is_newer_fabric_loader(current, latest) => latest.clone(),
+                _ => return Ok(None),
+            };
+            Ok(Some(JarUpdate {
+                url,
+                flavour,
+                info: AvailableUpdate {
+                    current: format!("loader {}", current),
+                    available: format!("loader {}", latest),
+                },
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn in_maintenance_window(window: &Option<MaintenanceWindow>) -> bool {
+    match window {
+        Some(window) => window.contains(chrono::Utc::now().with_timezone(&core_timezone()).hour()),
+        None => true,
+    }
+}
+
+impl MinecraftInstance {
+    pub(super) async fn update_status(&self) -> Result<UpdateStatus, Error> {
+        let config = self.config.lock().await.clone();
+        let patch = find_jar_update(&config.version, &config.flavour)
+            .await?
+            .map(|update| update.info);
+        let major = newer_release(
+            &config.version,
+            &get_minecraft_versions(&config.flavour).await?,
+        );
+        Ok(UpdateStatus {
+            current_version: config.version,
+            patch,
+            major,
+        })
+    }
+
+    async fn send_update_event(&self, instance_event_inner: InstanceEventInner) {
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_name: self.config.lock().await.name.clone(),
+                instance_uuid: self.uuid.clone(),
+                instance_event_inner,
+            }),
+            snowflake: Snowflake::default(),
+            details: "".to_string(),
+            caused_by: CausedBy::System,
+        });
+    }
+
+    pub(super) async fn check_for_jar_update(&mut self) -> Result<(), Error> {
+        let config = self.config.lock().await.clone();
+        if config.auto_update.mode == AutoUpdateMode::Off {
+            *self.available_update.lock().await = None;
+            return Ok(());
+        }
+        let update = match find_jar_update(&config.version, &config.flavour).await? {
+            Some(update) => update,
+            None => {
+                *self.available_update.lock().await = None;
+                return Ok(());
+            }
+        };
+        let newly_found = self
+            .available_update
+            .lock()
+            .await
+            .replace(update.info.clone())
+            .as_ref()
+            != Some(&update.info);
+        if config.auto_update.mode == AutoUpdateMode::Notify
+            || !in_maintenance_window(&config.auto_update.maintenance_window)
+        {
+            if newly_found {
+                self.send_update_event(InstanceEventInner::SystemMessage {
+                    message: format!(
+                        "An update of the server is available: {} -> {}",
+                        update.info.current, update.info.available
+                    ),
+                })
+                .await;
+            }
+            return Ok(());
+        }
+        self.apply_jar_update(update).await
+    }
+
+    /// Swaps in the updated jar, stopping the server for it if it's running
+    async fn apply_jar_update(&mut self, update: JarUpdate) -> Result<(), Error> {
+        let was_running = match self.state().await {
+            State::Stopped => false,
+            State::Running => true,
+            // tried again on the next check
+            _ => return Ok(()),
+        };
+        info!(
+            "[{}] Updating server from {} to {}",
+            self.config.lock().await.name,
+            update.info.current,
+            update.info.available
+        );
+        self.send_update_event(InstanceEventInner::SystemMessage {
+            message: format!(
+                "Updating the server from {} to {}",
+                update.info.current, update.info.available
+            ),
+        })
+        .await;
+        if was_running {
+            self.stop(CausedBy::System, true).await?;
+        }
+        let result = {
+            // keeps the server from being started halfway through the swap
+            let _lifecycle_guard = self.lifecycle_lock.clone().lock_owned().await;
+            if *self.state.lock().await == State::Stopped {
+                self.backup_and_swap_jar(&update).await
+            } else {
+                Err(Error {
+                    kind: ErrorKind::InvalidInstanceState,
+                    source: eyre!("The server was started before the update could be applied"),
+                })
+            }
+        };
+        if was_running && self.state().await == State::Stopped {
+            if let Err(e) = self.start(CausedBy::System, false).await {
+                warn!("Failed to restart instance after updating: {}", e);
+            }
+        }
+        match result {
+            Ok(()) => {
+                *self.available_update.lock().await = None;
+                self.send_update_event(InstanceEventInner::SystemMessage {
+                    message: format!("Updated the server to {}", update.info.available),
+                })
+                .await;
+                Ok(())
+            }
+            Err(e) => {
+                self.send_update_event(InstanceEventInner::InstanceWarning {
+                    message: format!(
+                        "Failed to update the server to {}: {}",
+                        update.info.available, e
+                    ),
+                })
+                .await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn backup_and_swap_jar(&self, update: &JarUpdate) -> Result<(), Error> {
+        self.backup_before("update").await?;
+        // downloaded to a temporary file first, so a failed download leaves the old jar in place
+        download_file(
+            &update.url,
+            &self.path_to_instance,
+            Some("server.jar"),
+            &|_| {},
+            true,
+        )
+        .await?;
+        self.config.lock().await.flavour = update.flavour.clone();
+        self.write_config_to_file().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::implementations::minecraft::paper::PaperBuildChannel;
+
+    fn paper_build(build: i64, channel: PaperBuildChannel) -> PaperBuild {
+        PaperBuild {
+            build,
+            channel,
+            time: String::new(),
+            download_name: format!("paper-{}.jar", build),
+        }
+    }
+
+    #[test]
+    fn test_newer_paper_build() {
+        // newest first, like the api returns them
+        let builds = vec![
+            paper_build(12, PaperBuildChannel::Experimental),
+            paper_build(11, PaperBuildChannel::Default),
+            paper_build(10, PaperBuildChannel::Default),
+        ];
+        assert_eq!(newer_paper_build(10, &builds).map(|b| b.build), Some(11));
+        assert_eq!(newer_paper_build(11, &builds), None);
+        // experimental builds are never updated to
+        assert_eq!(newer_paper_build(12, &builds), None);
+        assert_eq!(newer_paper_build(10, &[]), None);
+    }
+
+    #[test]
+    fn test_is_newer_fabric_loader() {
+        assert!(is_newer_fabric_loader("0.14.8", "0.14.21"));
+        assert!(is_newer_fabric_loader("0.14.21", "0.15.0"));
+        assert!(is_newer_fabric_loader("0.14", "0.14.1"));
+        assert!(!is_newer_fabric_loader("0.14.21", "0.14.21"));
+        assert!(!is_newer_fabric_loader("0.15.0", "0.14.21"));
+    }
+
+    #[test]
+    fn test_newer_release() {
+        let versions = vec![
+            "23w03a".to_string(),
+            "1.19.3".to_string(),
+            "1.19.3-pre1".to_string(),
+            "1.19.2".to_string(),
+            "1.9".to_string(),
+        ];
+        assert_eq!(
+            newer_release("1.19.2", &versions),
+            Some("1.19.3".to_string())
+        );
+        assert_eq!(newer_release("1.19.3", &versions), None);
+        assert_eq!(newer_release("1.9", &versions), Some("1.19.3".to_string()));
+        // snapshots are not compared against releases
+        assert_eq!(newer_release("23w03a", &versions), None);
+    }
+
+    #[tokio::test]
+    async fn test_find_jar_update_pinned_flavours() {
+        // these never hit the network
+        assert!(find_jar_update("1.19.2", &Flavour::Vanilla)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(find_jar_update("1.19.2", &Flavour::Spigot)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(find_jar_update(
+            "1.19.2",
+            &Flavour::Forge {
+                build_version: None
+            }
+        )
+        .await
+        .unwrap()
+        .is_none());
+    }
+
+    #[test]
+    fn test_maintenance_window() {
+        let window = MaintenanceWindow {
+            start_hour: 2,
+            end_hour: 5,
+        };
+        assert!(window.contains(2));
+        assert!(window.contains(4));
+        assert!(!window.contains(5));
+        let overnight = MaintenanceWindow {
+            start_hour: 23,
+            end_hour: 3,
+        };
+        assert!(overnight.contains(23));
+        assert!(overnight.contains(1));
+        assert!(!overnight.contains(12));
+        let all_day = MaintenanceWindow {
+            start_hour: 0,
+            end_hour: 0,
+        };
+        assert!(all_day.contains(12));
+    }
+}
@@ -1,84 +1,243 @@
 use color_eyre::eyre::{eyre, Context, ContextCompat};
+use color_eyre::Report;
 use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
 use serde_json::{self, Value};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
 use std::{collections::BTreeMap, path::Path, str::FromStr};
-use tokio::io::AsyncBufReadExt;
 
+use super::configurable::ServerPropertySetting;
+use super::paper::{get_paper_builds, latest_stable_paper_build};
+use super::quilt::get_quilt_jar_url;
+use super::spigot::get_spigot_jar_url;
 use super::{
     FabricInstallerVersion, FabricLoaderVersion, Flavour, ForgeBuildVersion, PaperBuildVersion,
 };
-use crate::error::Error;
+use crate::error::{Error, ErrorKind, FieldErrors};
+use crate::traits::t_configurable::PropertyChange;
+use crate::traits::t_server::State;
+use crate::upstream_cache::cached_get_text;
+use crate::upstream_http;
+use crate::util::{download_file, unzip_file_async, DownloadProgress, UnzipOption};
 
 pub async fn read_properties_from_path(
     path_to_properties: &Path,
 ) -> Result<IndexMap<String, String>, Error> {
-    let properties_file = tokio::fs::File::open(path_to_properties)
+    let properties = tokio::fs::read_to_string(path_to_properties)
         .await
         .context(format!(
-            "Failed to open properties file at {}",
+            "Failed to read properties file at {}",
             path_to_properties.display()
         ))?;
-    let buf_reader = tokio::io::BufReader::new(properties_file);
-    let mut stream = buf_reader.lines();
-    let mut ret = IndexMap::new();
+    Ok(split_properties(&properties))
+}
 
-    while let Some(line) = stream
-        .next_line()
-        .await
-        .context("Failed to read line from properties file")?
-    {
-        if line.is_empty() {
+/// A line of a properties file that isn't a valid property
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidPropertyLine {
+    pub line: String,
+    pub reason: String,
+}
+
+/// Parses a properties file without failing on bad lines, as a user edit shouldn't stop the instance from loading.
+///
+/// Unknown properties are kept like any other. Lines that aren't a property, or whose value is invalid,
+/// are returned separately so they can be written back as they were
+pub fn parse_properties_lenient(
+    properties: &str,
+) -> (Vec<ServerPropertySetting>, Vec<InvalidPropertyLine>) {
+    let mut settings = Vec::new();
+    let mut invalid = Vec::new();
+    for line in properties.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let reason = match trimmed.split_once('=') {
+            Some((key, _)) if key.trim().is_empty() => "missing key".to_string(),
+            Some((key, value)) => {
+                match ServerPropertySetting::from_key_val(key.trim(), value.trim()) {
+                    Ok(setting) => {
+                        settings.push(setting);
+                        continue;
+                    }
+                    Err(e) => e.source.to_string(),
+                }
+            }
+            None => "expected key=value".to_string(),
+        };
+        invalid.push(InvalidPropertyLine {
+            line: line.to_string(),
+            reason,
+        });
+    }
+    (settings, invalid)
+}
+
+/// Parses the text of a properties file, validating every property.
+///
+/// Fails with every invalid line if any is invalid
+pub fn parse_raw_properties(properties: &str) -> Result<IndexMap<String, String>, Error> {
+    let mut ret = IndexMap::new();
+    let mut errors = Vec::new();
+    for (line_number, line) in properties.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
             continue;
         }
-        // if a line starts with '#', it is a comment, skip it
-        if line.starts_with('#') {
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => {
+                errors.push(format!("Line {}: expected key=value", line_number + 1));
+                continue;
+            }
+        };
+        if key.is_empty() {
+            errors.push(format!("Line {}: missing key", line_number + 1));
             continue;
         }
-        // split the line into key and value
-        let mut split = line.split('=');
-        let key = split
-            .next()
-            .ok_or_else(|| eyre!("Failed to read key from properties file"))?
-            .trim();
-        let value = split
-            .next()
-            .ok_or_else(|| eyre!("Failed to read value from properties file for key {}", key))?
-            .trim();
-
-        ret.insert(key.to_string(), value.to_string());
+        if let Err(e) = ServerPropertySetting::from_key_val(key, value) {
+            errors.push(format!("Line {}: {}", line_number + 1, e));
+            continue;
+        }
+        if ret.insert(key.to_string(), value.to_string()).is_some() {
+            errors.push(format!("Line {}: duplicate key {}", line_number + 1, key));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid properties file:\n{}", errors.join("\n")),
+        });
     }
     Ok(ret)
 }
 
+/// Checks every property of a batch against the schema, reporting each invalid one rather than
+/// stopping at the first
+pub fn validate_properties(properties: &IndexMap<String, String>) -> Result<(), Error> {
+    let mut errors = IndexMap::new();
+    for (key, value) in properties {
+        if key.trim().is_empty() || key.contains(['=', '\n', '\r']) || key.starts_with('#') {
+            errors.insert(key.clone(), "Invalid key".to_string());
+            continue;
+        }
+        if value.contains(['\n', '\r']) {
+            errors.insert(key.clone(), "Value cannot span multiple lines".to_string());
+            continue;
+        }
+        if let Err(e) = ServerPropertySetting::from_key_val(key, value) {
+            errors.insert(key.clone(), e.source.to_string());
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: Report::new(FieldErrors(errors)).wrap_err("Invalid properties"),
+        });
+    }
+    Ok(())
+}
+
+/// The command that applies a property to a running server, for the few that can be changed
+/// without a restart
+pub fn live_property_command(key: &str, value: &str) -> Option<String> {
+    match key {
+        "difficulty" => Some(format!("difficulty {value}")),
+        "gamemode" => Some(format!("defaultgamemode {value}")),
+        "white-list" => match value {
+            "true" => Some("whitelist on".to_string()),
+            "false" => Some("whitelist off".to_string()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Key-value pairs of a properties file, skipping lines that aren't one
+pub fn split_properties(properties: &str) -> IndexMap<String, String> {
+    properties
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+/// Properties that differ between `old` and `new`, changed and added ones first in the order of `new`
+pub fn diff_properties(
+    old: &IndexMap<String, String>,
+    new: &IndexMap<String, String>,
+) -> Vec<PropertyChange> {
+    let mut changes: Vec<PropertyChange> = new
+        .iter()
+        .filter(|(key, value)| old.get(*key) != Some(*value))
+        .map(|(key, value)| PropertyChange {
+            key: key.clone(),
+            old_value: old.get(key).cloned(),
+            new_value: Some(value.clone()),
+        })
+        .collect();
+    changes.extend(
+        old.iter()
+            .filter(|(key, _)| !new.contains_key(*key))
+            .map(|(key, value)| PropertyChange {
+                key: key.clone(),
+                old_value: Some(value.clone()),
+                new_value: None,
+            }),
+    );
+    changes
+}
+
 // Returns the jar url and the updated flavour with version information
-pub async fn get_server_jar_url(version: &str, flavour: &Flavour) -> Option<(String, Flavour)> {
+pub async fn get_server_jar_url(
+    version: &str,
+    flavour: &Flavour,
+) -> Result<(String, Flavour), Error> {
+    let not_found = || {
+        eyre!(
+            "Could not find a {} server.jar for version {}",
+            flavour,
+            version
+        )
+    };
     match flavour {
         Flavour::Vanilla => get_vanilla_jar_url(version).await,
         Flavour::Fabric {
             loader_version,
             installer_version,
-        } => get_fabric_jar_url(version, loader_version, installer_version).await,
+        } => Ok(
+            get_fabric_jar_url(version, loader_version, installer_version)
+                .await
+                .ok_or_else(not_found)?,
+        ),
         Flavour::Paper { build_version } => get_paper_jar_url(version, build_version).await,
-        Flavour::Spigot => todo!(),
-        Flavour::Forge { build_version } => get_forge_jar_url(version, build_version).await.ok(),
+        Flavour::Spigot => Ok(get_spigot_jar_url(version).await.ok_or_else(not_found)?),
+        Flavour::Forge { build_version } => get_forge_jar_url(version, build_version).await,
+        Flavour::Quilt {
+            loader_version,
+            installer_version,
+        } => Ok(
+            get_quilt_jar_url(version, loader_version, installer_version)
+                .await
+                .ok_or_else(not_found)?,
+        ),
     }
 }
 
-pub async fn get_vanilla_jar_url(version: &str) -> Option<(String, Flavour)> {
-    let client = reqwest::Client::new();
-    let response_text = client
-        .get("https://launchermeta.mojang.com/mc/game/version_manifest.json")
-        .send()
-        .await
-        .ok()?
-        .text()
-        .await
-        .ok()?;
-    let response: serde_json::Value = serde_json::from_str(&response_text).ok()?;
+async fn get_vanilla_version_json(version: &str) -> Result<serde_json::Value, Error> {
+    let response_text =
+        cached_get_text("https://launchermeta.mojang.com/mc/game/version_manifest.json").await?;
+    let response: serde_json::Value = serde_json::from_str(&response_text)
+        .context("Failed to get minecraft versions, response is not valid json")?;
 
     let url = response
-        .get("versions")?
-        .as_array()?
+        .get("versions")
+        .and_then(|versions| versions.as_array())
+        .context("Failed to get minecraft versions, response does not contain versions")?
         .iter()
         .find(|version_json| {
             version_json
@@ -87,16 +246,28 @@ pub async fn get_vanilla_jar_url(version: &str) -> Option<(String, Flavour)> {
                 .as_str()
                 .unwrap()
                 .eq(version)
+        })
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Minecraft version {} not found", version),
         })?
-        .get("url")?
-        .as_str()?;
-    let response: serde_json::Value =
-        serde_json::from_str(&client.get(url).send().await.ok()?.text().await.ok()?).ok()?;
+        .get("url")
+        .and_then(|url| url.as_str())
+        .context("Failed to get minecraft version, version does not have a url")?;
+    Ok(serde_json::from_str(&cached_get_text(url).await?)
+        .context("Failed to get minecraft version, response is not valid json")?)
+}
+
+pub async fn get_vanilla_jar_url(version: &str) -> Result<(String, Flavour), Error> {
+    let response = get_vanilla_version_json(version).await?;
     if response["downloads"]["server"]["url"] == serde_json::Value::Null {
-        return None;
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Minecraft version {} does not have a server jar", version),
+        });
     }
 
-    Some((
+    Ok((
         response["downloads"]["server"]["url"]
             .to_string()
             .replace('\"', ""),
@@ -111,7 +282,6 @@ pub async fn get_fabric_jar_url(
 ) -> Option<(String, Flavour)> {
     let mut loader_version = String::new();
     let mut installer_version = String::new();
-    let client = reqwest::Client::new();
 
     if let (Some(FabricLoaderVersion(l)), Some(FabricInstallerVersion(i))) =
         (fabric_loader_version, fabric_installer_version)
@@ -132,18 +302,13 @@ pub async fn get_fabric_jar_url(
 
     if fabric_loader_version.is_none() {
         loader_version = serde_json::Value::from_str(
-            client
-                .get(format!(
-                    "https://meta.fabricmc.net/v2/versions/loader/{}",
-                    version
-                ))
-                .send()
-                .await
-                .ok()?
-                .text()
-                .await
-                .ok()?
-                .as_str(),
+            cached_get_text(&format!(
+                "https://meta.fabricmc.net/v2/versions/loader/{}",
+                version
+            ))
+            .await
+            .ok()?
+            .as_str(),
         )
         .ok()?
         .as_array()?
@@ -198,12 +363,7 @@ pub async fn get_fabric_jar_url(
 
     if fabric_installer_version.is_none() {
         installer_version = serde_json::Value::from_str(
-            client
-                .get("https://meta.fabricmc.net/v2/versions/installer")
-                .send()
-                .await
-                .ok()?
-                .text()
+            cached_get_text("https://meta.fabricmc.net/v2/versions/installer")
                 .await
                 .ok()?
                 .as_str(),
@@ -260,77 +420,102 @@ pub async fn get_fabric_jar_url(
 pub async fn get_paper_jar_url(
     version: &str,
     paper_build_version: &Option<PaperBuildVersion>,
-) -> Option<(String, Flavour)> {
-    let client = reqwest::Client::new();
-
-    let builds_text = client
-        .get(format!(
-            "https://api.papermc.io/v2/projects/paper/versions/{}/builds/",
-            version
-        ))
-        .send()
-        .await
-        .ok()?
-        .text()
-        .await
-        .ok()?;
-    let builds: serde_json::Value = serde_json::from_str(&builds_text).ok()?;
-    let mut builds = builds.get("builds")?.as_array()?.iter();
+) -> Result<(String, Flavour), Error> {
+    let builds = get_paper_builds(version).await?;
 
+    // no build selected means the latest stable one
     let build = if let Some(PaperBuildVersion(b)) = paper_build_version {
-        builds.find(|build| build.get("build").unwrap().as_i64().unwrap().eq(b))?
-    } else {
         builds
-            .filter(|build| {
-                build
-                    .get("channel")
-                    .unwrap()
-                    .as_str()
-                    .unwrap()
-                    .to_string()
-                    .eq("default")
-            })
-            .max_by(|a, b| {
-                let a = a.get("build").unwrap().as_i64().unwrap();
-                let b = b.get("build").unwrap().as_i64().unwrap();
-                a.cmp(&b)
+            .iter()
+            .find(|build| build.build == *b)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Paper build {} not found for version {}", b, version),
             })?
+    } else {
+        latest_stable_paper_build(&builds).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("No stable paper build found for version {}", version),
+        })?
     };
-    let build_version = build.get("build")?.as_i64()?;
 
-    Some((
+    Ok((
         format!(
             "https://api.papermc.io/v2/projects/paper/versions/{}/builds/{}/downloads/{}",
-            version,
-            build_version,
-            build
-                .get("downloads")?
-                .get("application")?
-                .get("name")?
-                .as_str()?,
+            version, build.build, build.download_name,
         ),
         Flavour::Paper {
-            build_version: Some(PaperBuildVersion(build_version)),
+            build_version: Some(PaperBuildVersion(build.build)),
         },
     ))
 }
 
+/// Checksum of a server jar as published upstream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JarChecksum {
+    Sha1(String),
+    Sha256(String),
+}
+
+/// Gets the published checksum of the server jar of a version, if the upstream publishes one
+pub async fn get_server_jar_checksum(version: &str, flavour: &Flavour) -> Option<JarChecksum> {
+    match flavour {
+        Flavour::Vanilla => Some(JarChecksum::Sha1(
+            get_vanilla_version_json(version).await.ok()?["downloads"]["server"]["sha1"]
+                .as_str()?
+                .to_string(),
+        )),
+        Flavour::Paper {
+            build_version: Some(PaperBuildVersion(build_version)),
+        } => {
+            let build: serde_json::Value = serde_json::from_str(
+                &cached_get_text(&format!(
+                    "https://api.papermc.io/v2/projects/paper/versions/{}/builds/{}",
+                    version, build_version
+                ))
+                .await
+                .ok()?,
+            )
+            .ok()?;
+            Some(JarChecksum::Sha256(
+                build["downloads"]["application"]["sha256"]
+                    .as_str()?
+                    .to_string(),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Checks a file against a checksum. This blocks, run it with `spawn_blocking`
+pub fn checksum_matches(path: &Path, checksum: &JarChecksum) -> Result<bool, Error> {
+    let mut file =
+        std::fs::File::open(path).context(format!("Failed to open file at {}", path.display()))?;
+    Ok(match checksum {
+        JarChecksum::Sha1(expected) => {
+            let mut hasher = Sha1::new();
+            std::io::copy(&mut file, &mut hasher)
+                .context(format!("Failed to read file at {}", path.display()))?;
+            format!("{:x}", hasher.finalize()).eq_ignore_ascii_case(expected)
+        }
+        JarChecksum::Sha256(expected) => {
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut file, &mut hasher)
+                .context(format!("Failed to read file at {}", path.display()))?;
+            format!("{:x}", hasher.finalize()).eq_ignore_ascii_case(expected)
+        }
+    })
+}
+
 pub async fn get_forge_jar_url(
     version: &str,
     forge_build_version: &Option<ForgeBuildVersion>,
 ) -> Result<(String, Flavour), Error> {
-    let client = reqwest::Client::new();
-
     let response: BTreeMap<String, Vec<String>> = serde_json::from_str(
-        client
-            .get("https://files.minecraftforge.net/net/minecraftforge/forge/maven-metadata.json")
-            .send()
-            .await
-            .context("Failed to get forge versions, http request failed")?
-            .text()
-            .await
-            .context("Failed to get forge versions, text conversion failed")?
-            .as_str(),
+        &upstream_http::get_text(
+            "https://files.minecraftforge.net/net/minecraftforge/forge/maven-metadata.json",
+        )
+        .await?,
     )
     .context("Failed to get forge versions, json is not a map")?;
 
@@ -355,8 +540,7 @@ pub async fn get_forge_jar_url(
     ))
 }
 
-pub async fn get_jre_url(version: &str) -> Option<(String, u64)> {
-    let client = reqwest::Client::new();
+pub async fn get_jre_url(version: &str) -> Result<(String, u64), Error> {
     let os = if std::env::consts::OS == "macos" {
         "mac"
     } else {
@@ -369,40 +553,11 @@ pub async fn get_jre_url(version: &str) -> Option<(String, u64)> {
     };
 
     let major_java_version = {
-        let val = match serde_json::Value::from_str(
-            client
-                .get(
-                    serde_json::Value::from_str(
-                        client
-                            .get("https://launchermeta.mojang.com/mc/game/version_manifest.json")
-                            .send()
-                            .await
-                            .ok()?
-                            .text()
-                            .await
-                            .ok()?
-                            .as_str(),
-                    )
-                    .ok()?
-                    .get("versions")?
-                    .as_array()?
-                    .iter()
-                    .find(|v| v.get("id").unwrap().as_str().unwrap().eq(version))?
-                    .get("url")?
-                    .as_str()?,
-                )
-                .send()
-                .await
-                .ok()?
-                .text()
-                .await
-                .ok()?
-                .as_str(),
-        )
-        .ok()?
-        .get("javaVersion")
-        {
-            Some(java_version) => java_version.get("majorVersion")?.as_u64()?,
+        let val = match get_vanilla_version_json(version).await?.get("javaVersion") {
+            Some(java_version) => java_version
+                .get("majorVersion")
+                .and_then(|major_version| major_version.as_u64())
+                .context("Failed to get the java version, majorVersion is not a number")?,
             None => 8,
         };
         // Ddoptium won't provide java 16 for some reason
@@ -414,7 +569,7 @@ pub async fn get_jre_url(version: &str) -> Option<(String, u64)> {
         }
     };
 
-    Some((
+    Ok((
         format!(
             "https://api.adoptium.net/v3/binary/latest/{}/ga/{}/{}/jre/hotspot/normal/eclipse",
             major_java_version, os, arch
@@ -423,10 +578,324 @@ pub async fn get_jre_url(version: &str) -> Option<(String, u64)> {
     ))
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum JreVendor {
+    Temurin,
+    Zulu,
+}
+
+impl JreVendor {
+    pub const ALL: [JreVendor; 2] = [JreVendor::Temurin, JreVendor::Zulu];
+}
+
+impl std::fmt::Display for JreVendor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JreVendor::Temurin => write!(f, "Temurin"),
+            JreVendor::Zulu => write!(f, "Zulu"),
+        }
+    }
+}
+
+impl FromStr for JreVendor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        JreVendor::ALL
+            .into_iter()
+            .find(|vendor| vendor.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Unknown JRE vendor {}", s),
+            })
+    }
+}
+
+/// A resolved JRE download
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JreDownload {
+    pub url: String,
+    pub major_version: u64,
+    /// Name of the directory under `path_to_runtimes/java` the JRE is unpacked to
+    pub dir_name: String,
+}
+
+/// The major version of a Java version or Temurin release name,
+/// e.g. `17`, `17.0.7`, `jdk-17.0.7+7` or `jdk8u372-b07`
+pub fn jre_override_major_version(version_override: &str) -> Option<u64> {
+    let version = version_override
+        .trim()
+        .trim_start_matches("jdk")
+        .trim_start_matches('-');
+    let digits: String = version.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok().filter(|major| *major > 0)
+}
+
+/// Resolves the JRE to download for a minecraft version.
+///
+/// Without a vendor or a version override, this is the auto-detected Temurin build shared by all instances.
+/// Otherwise the override is checked against the vendor so that a typo fails setup instead of the first start.
+pub async fn resolve_jre_download(
+    version: &str,
+    vendor: Option<JreVendor>,
+    version_override: Option<&str>,
+) -> Result<JreDownload, Error> {
+    let (default_url, default_major_version) = get_jre_url(version).await?;
+    let version_override = version_override.map(str::trim).filter(|v| !v.is_empty());
+    let vendor = vendor.unwrap_or(JreVendor::Temurin);
+    if vendor == JreVendor::Temurin && version_override.is_none() {
+        return Ok(JreDownload {
+            url: default_url,
+            major_version: default_major_version,
+            dir_name: format!("jre{}", default_major_version),
+        });
+    }
+    let requested_version = version_override
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| default_major_version.to_string());
+    let major_version = jre_override_major_version(&requested_version).ok_or_else(|| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("{} is not a valid Java version", requested_version),
+    })?;
+    let not_found = || Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!(
+            "Could not find a {} JRE for Java {} on {}/{}",
+            vendor,
+            requested_version,
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        ),
+    };
+    let url = match vendor {
+        JreVendor::Temurin => {
+            let os = if std::env::consts::OS == "macos" {
+                "mac"
+            } else {
+                std::env::consts::OS
+            };
+            let arch = if std::env::consts::ARCH == "x86_64" {
+                "x64"
+            } else {
+                std::env::consts::ARCH
+            };
+            let url = if requested_version.chars().all(|c| c.is_ascii_digit()) {
+                format!(
+                    "https://api.adoptium.net/v3/binary/latest/{}/ga/{}/{}/jre/hotspot/normal/eclipse",
+                    major_version, os, arch
+                )
+            } else {
+                format!(
+                    "https://api.adoptium.net/v3/binary/version/{}/{}/{}/jre/hotspot/normal/eclipse",
+                    requested_version.replace('+', "%2B"),
+                    os,
+                    arch
+                )
+            };
+            // the binary endpoints redirect to the archive, or 404 if there is no such release
+            upstream_http::client()
+                .head(&url)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|_| not_found())?;
+            url
+        }
+        JreVendor::Zulu => {
+            let arch = if std::env::consts::ARCH == "x86_64" {
+                "x64"
+            } else {
+                std::env::consts::ARCH
+            };
+            let archive_type = if std::env::consts::OS == "windows" {
+                "zip"
+            } else {
+                "tar.gz"
+            };
+            let packages: Value = upstream_http::client()
+                .get(format!(
+                    "https://api.azul.com/metadata/v1/zulu/packages/?java_version={}&os={}&arch={}&archive_type={}&java_package_type=jre&javafx_bundled=false&release_status=ga&latest=true&page_size=1",
+                    requested_version,
+                    std::env::consts::OS,
+                    arch,
+                    archive_type
+                ))
+                .send()
+                .await
+                .context("Failed to reach the Azul metadata API")?
+                .error_for_status()
+                .map_err(|_| not_found())?
+                .json()
+                .await
+                .context("Failed to parse the Azul metadata API response")?;
+            packages
+                .as_array()
+                .and_then(|packages| packages.first())
+                .and_then(|package| package.get("download_url"))
+                .and_then(|url| url.as_str())
+                .ok_or_else(not_found)?
+                .to_string()
+        }
+    };
+    Ok(JreDownload {
+        url,
+        major_version,
+        dir_name: sanitize_filename::sanitize(format!(
+            "{}-{}",
+            vendor.to_string().to_lowercase(),
+            requested_version
+        )),
+    })
+}
+
+/// The java executable of a JRE unpacked by `install_jre`
+pub fn jre_java_path(path_to_runtimes: &Path, dir_name: &str) -> std::path::PathBuf {
+    let path_to_jre = path_to_runtimes.join("java").join(dir_name);
+    // Temurin ships a macOS bundle, while other vendors also link bin at the top level
+    let bin = if std::env::consts::OS == "macos" && !path_to_jre.join("bin").exists() {
+        "Contents/Home/bin"
+    } else {
+        "bin"
+    };
+    path_to_jre.join(bin).join("java")
+}
+
+/// Whether JREs can be downloaded into `path_to_runtimes`
+async fn is_runtimes_dir_writable(path_to_runtimes: &Path) -> bool {
+    let path_to_java = path_to_runtimes.join("java");
+    if tokio::fs::create_dir_all(&path_to_java).await.is_err() {
+        return false;
+    }
+    let probe = path_to_java.join(".lodestone_write_probe");
+    let writable = tokio::fs::write(&probe, b"").await.is_ok();
+    let _ = tokio::fs::remove_file(&probe).await;
+    writable
+}
+
+/// Fails if the JRE `jre_dir_name` can't be downloaded because `path_to_runtimes` is read-only
+pub async fn ensure_runtimes_dir_writable(
+    path_to_runtimes: &Path,
+    jre_dir_name: &str,
+) -> Result<(), Error> {
+    if is_runtimes_dir_writable(path_to_runtimes).await {
+        return Ok(());
+    }
+    Err(Error {
+        kind: ErrorKind::Internal,
+        source: eyre!(
+            "JRE {} has to be downloaded, but the runtimes directory {} is read-only. Mount the JRE at {} or use a writable runtimes directory",
+            jre_dir_name,
+            path_to_runtimes.display(),
+            path_to_runtimes.join("java").join(jre_dir_name).display()
+        ),
+    })
+}
+
+/// Whether the JRE `jre_dir_name` has to be downloaded into `path_to_runtimes`.
+///
+/// A JRE already there is used as is, so a read-only runtimes directory, e.g. mounted into an immutable deployment,
+/// works as long as it contains every JRE the instances need
+pub async fn jre_needs_download(
+    path_to_runtimes: &Path,
+    jre_dir_name: &str,
+) -> Result<bool, Error> {
+    if path_to_runtimes.join("java").join(jre_dir_name).exists() {
+        return Ok(false);
+    }
+    ensure_runtimes_dir_writable(path_to_runtimes, jre_dir_name).await?;
+    Ok(true)
+}
+
+/// Downloads the JRE at `url` and unpacks it to `jre_dir_name` under `path_to_runtimes/java`
+pub async fn install_jre(
+    url: &str,
+    jre_dir_name: &str,
+    path_to_runtimes: &Path,
+    on_download: &(dyn Fn(DownloadProgress) + Send + Sync),
+) -> Result<(), Error> {
+    let downloaded =
+        download_file(url, &path_to_runtimes.join("java"), None, on_download, true).await?;
+
+    let unzipped_content = unzip_file_async(
+        &downloaded,
+        UnzipOption::ToDir(path_to_runtimes.join("java")),
+    )
+    .await?;
+    if unzipped_content.len() != 1 {
+        return Err(eyre!(
+            "Expected only one file in the JRE archive, got {}",
+            unzipped_content.len()
+        )
+        .into());
+    }
+
+    tokio::fs::remove_file(&downloaded).await.context(format!(
+        "Could not remove downloaded JRE file {}",
+        downloaded.display()
+    ))?;
+
+    tokio::fs::rename(
+        unzipped_content.iter().last().unwrap(),
+        path_to_runtimes.join("java").join(jre_dir_name),
+    )
+    .await
+    .context(format!(
+        "Could not rename JRE directory {}",
+        unzipped_content.iter().last().unwrap().display()
+    ))?;
+    Ok(())
+}
+
+/// Whether a tracked server process is still running
+#[derive(Debug)]
+pub enum ProcessLiveness {
+    Alive,
+    Exited(std::process::ExitStatus),
+    /// No process is tracked
+    Missing,
+}
+
+pub fn process_liveness(process: &mut Option<tokio::process::Child>) -> ProcessLiveness {
+    match process.as_mut().map(|process| process.try_wait()) {
+        None => ProcessLiveness::Missing,
+        Some(Ok(None)) => ProcessLiveness::Alive,
+        Some(Ok(Some(exit_status))) => ProcessLiveness::Exited(exit_status),
+        // can't tell, better to leave a running server alone
+        Some(Err(_)) => ProcessLiveness::Alive,
+    }
+}
+
+/// Whether the state of an instance disagrees with the liveness of its process
+pub fn state_has_drifted(state: State, liveness: &ProcessLiveness) -> bool {
+    match state {
+        State::Stopped | State::Error | State::SettingUp | State::Unavailable | State::Missing => {
+            false
+        }
+        State::Starting | State::Running | State::Stopping => {
+            !matches!(liveness, ProcessLiveness::Alive)
+        }
+    }
+}
+
+/// Suggests a higher max RAM (in MB) for a server that ran out of memory.
+///
+/// Grows the current value by half, but never past `ceiling` or the total memory of the host.
+/// Returns `None` if there is no room to grow.
+pub fn suggest_max_ram(current: u32, ceiling: u32, host_total_mb: u32) -> Option<u32> {
+    let suggested = current
+        .saturating_add(current / 2)
+        .min(ceiling)
+        .min(host_total_mb);
+    if suggested > current {
+        Some(suggested)
+    } else {
+        None
+    }
+}
+
 pub async fn name_to_uuid(name: impl AsRef<str>) -> Option<String> {
     // GET https://api.mojang.com/users/profiles/minecraft/<username>
-    let client = reqwest::Client::new();
-    let res: Value = client
+    let res: Value = upstream_http::client()
         .get(format!(
             "https://api.mojang.com/users/profiles/minecraft/{}",
             name.as_ref()
@@ -442,19 +911,27 @@ pub async fn name_to_uuid(name: impl AsRef<str>) -> Option<String> {
 
 #[cfg(test)]
 mod tests {
+    use indexmap::IndexMap;
+
+    use crate::error::ErrorKind;
     use crate::minecraft::{
-        util::{get_forge_jar_url, get_server_jar_url},
+        util::{
+            checksum_matches, diff_properties, get_forge_jar_url, get_server_jar_url,
+            jre_needs_download, live_property_command, parse_properties_lenient,
+            parse_raw_properties, process_liveness, state_has_drifted, suggest_max_ram,
+            validate_properties, JarChecksum, ProcessLiveness,
+        },
         FabricInstallerVersion, FabricLoaderVersion, Flavour, ForgeBuildVersion, PaperBuildVersion,
     };
     use tokio;
 
     #[tokio::test]
     async fn test_get_vanilla_jar_url() {
-        assert_eq!(super::get_vanilla_jar_url("1.18.2").await, Some(("https://piston-data.mojang.com/v1/objects/c8f83c5655308435b3dcf03c06d9fe8740a77469/server.jar".to_string(), Flavour::Vanilla)));
-        assert_eq!(super::get_vanilla_jar_url("21w44a").await, Some(("https://piston-data.mojang.com/v1/objects/ae583fd57a8c07f2d6fbadce1ce1e1379bf4b32d/server.jar".to_string(), Flavour::Vanilla)));
-        assert_eq!(super::get_vanilla_jar_url("1.8.4").await, Some(("https://launcher.mojang.com/v1/objects/dd4b5eba1c79500390e0b0f45162fa70d38f8a3d/server.jar".to_string(), Flavour::Vanilla)));
+        assert_eq!(super::get_vanilla_jar_url("1.18.2").await.unwrap(), ("https://piston-data.mojang.com/v1/objects/c8f83c5655308435b3dcf03c06d9fe8740a77469/server.jar".to_string(), Flavour::Vanilla));
+        assert_eq!(super::get_vanilla_jar_url("21w44a").await.unwrap(), ("https://piston-data.mojang.com/v1/objects/ae583fd57a8c07f2d6fbadce1ce1e1379bf4b32d/server.jar".to_string(), Flavour::Vanilla));
+        assert_eq!(super::get_vanilla_jar_url("1.8.4").await.unwrap(), ("https://launcher.mojang.com/v1/objects/dd4b5eba1c79500390e0b0f45162fa70d38f8a3d/server.jar".to_string(), Flavour::Vanilla));
 
-        assert_eq!(super::get_vanilla_jar_url("1.8.4asdasd").await, None);
+        assert!(super::get_vanilla_jar_url("1.8.4asdasd").await.is_err());
     }
     #[tokio::test]
     async fn test_get_jre_url() {
@@ -463,11 +940,11 @@ mod tests {
         } else {
             std::env::consts::OS
         };
-        assert_eq!(super::get_jre_url("1.18.2").await, Some((format!("https://api.adoptium.net/v3/binary/latest/17/ga/{os_str}/x64/jre/hotspot/normal/eclipse"), 17)));
-        assert_eq!(super::get_jre_url("21w44a").await, Some((format!("https://api.adoptium.net/v3/binary/latest/17/ga/{os_str}/x64/jre/hotspot/normal/eclipse"), 17)));
-        assert_eq!(super::get_jre_url("1.8.4").await, Some((format!("https://api.adoptium.net/v3/binary/latest/8/ga/{os_str}/x64/jre/hotspot/normal/eclipse"), 8)));
+        assert_eq!(super::get_jre_url("1.18.2").await.unwrap(), (format!("https://api.adoptium.net/v3/binary/latest/17/ga/{os_str}/x64/jre/hotspot/normal/eclipse"), 17));
+        assert_eq!(super::get_jre_url("21w44a").await.unwrap(), (format!("https://api.adoptium.net/v3/binary/latest/17/ga/{os_str}/x64/jre/hotspot/normal/eclipse"), 17));
+        assert_eq!(super::get_jre_url("1.8.4").await.unwrap(), (format!("https://api.adoptium.net/v3/binary/latest/8/ga/{os_str}/x64/jre/hotspot/normal/eclipse"), 8));
 
-        assert_eq!(super::get_jre_url("1.8.4asdasd").await, None);
+        assert!(super::get_jre_url("1.8.4asdasd").await.is_err());
     }
 
     /// Test subject to fail if fabric updates their installer or loader
@@ -496,20 +973,20 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_paper_jar_url() {
-        assert_eq!(super::get_paper_jar_url("1.19.3", &Some(PaperBuildVersion(308))).await, Some((
+        assert_eq!(super::get_paper_jar_url("1.19.3", &Some(PaperBuildVersion(308))).await.unwrap(), (
             "https://api.papermc.io/v2/projects/paper/versions/1.19.3/builds/308/downloads/paper-1.19.3-308.jar".to_string(),
             Flavour::Paper { build_version: Some(PaperBuildVersion(308)) }
-        )));
-        assert_eq!(super::get_paper_jar_url("1.13-pre7", &Some(PaperBuildVersion(1))).await, Some((
+        ));
+        assert_eq!(super::get_paper_jar_url("1.13-pre7", &Some(PaperBuildVersion(1))).await.unwrap(), (
             "https://api.papermc.io/v2/projects/paper/versions/1.13-pre7/builds/1/downloads/paper-1.13-pre7-1.jar".to_string(),
             Flavour::Paper { build_version: Some(PaperBuildVersion(1)) }
-        )));
-        assert_eq!(super::get_paper_jar_url("1.19", &None).await, Some((
+        ));
+        assert_eq!(super::get_paper_jar_url("1.19", &None).await.unwrap(), (
             "https://api.papermc.io/v2/projects/paper/versions/1.19/builds/81/downloads/paper-1.19-81.jar".to_string(),
             Flavour::Paper { build_version: Some(PaperBuildVersion(81)) }
-        )));
+        ));
 
-        assert_eq!(super::get_paper_jar_url("1.19.3bruh", &None).await, None);
+        assert!(super::get_paper_jar_url("1.19.3bruh", &None).await.is_err());
     }
 
     #[tokio::test]
@@ -520,28 +997,256 @@ mod tests {
     #[tokio::test]
     async fn test_get_server_jar_url() {
         assert_eq!(
-            get_server_jar_url("1.7.10", &Flavour::Forge { build_version: None }).await,
-            Some((
+            get_server_jar_url("1.7.10", &Flavour::Forge { build_version: None })
+                .await
+                .unwrap(),
+            (
                 "https://maven.minecraftforge.net/net/minecraftforge/forge/1.7.10-10.13.4.1614-1.7.10/forge-1.7.10-10.13.4.1614-1.7.10-installer.jar".to_string(),
                 Flavour::Forge { build_version: Some(ForgeBuildVersion("1.7.10-10.13.4.1614-1.7.10".to_string())) }
-            ))
+            )
         );
         assert_eq!(
-            get_server_jar_url("1.7.10_pre4", &Flavour::Forge { build_version: None }).await,
-            Some((
+            get_server_jar_url("1.7.10_pre4", &Flavour::Forge { build_version: None })
+                .await
+                .unwrap(),
+            (
                 "https://maven.minecraftforge.net/net/minecraftforge/forge/1.7.10_pre4-10.12.2.1149-prerelease/forge-1.7.10_pre4-10.12.2.1149-prerelease-installer.jar".to_string(),
                 Flavour::Forge { build_version: Some(ForgeBuildVersion("1.7.10_pre4-10.12.2.1149-prerelease".to_string())) }
-            ))
+            )
         );
-        assert_eq!(
-            get_server_jar_url(
-                "1.19.3bruh",
-                &Flavour::Forge {
-                    build_version: None
+        assert!(get_server_jar_url(
+            "1.19.3bruh",
+            &Flavour::Forge {
+                build_version: None
+            }
+        )
+        .await
+        .is_err());
+    }
+
+    #[test]
+    fn test_suggest_max_ram() {
+        assert_eq!(suggest_max_ram(2048, 8192, 16384), Some(3072));
+        // capped by the ceiling
+        assert_eq!(suggest_max_ram(2048, 2560, 16384), Some(2560));
+        // capped by the host memory
+        assert_eq!(suggest_max_ram(2048, 8192, 2304), Some(2304));
+        assert_eq!(suggest_max_ram(4096, 4096, 16384), None);
+        assert_eq!(suggest_max_ram(4096, 8192, 4000), None);
+    }
+
+    #[tokio::test]
+    async fn test_process_liveness() {
+        use crate::traits::t_server::State;
+
+        let mut process = None;
+        let liveness = process_liveness(&mut process);
+        assert!(matches!(liveness, ProcessLiveness::Missing));
+        assert!(state_has_drifted(State::Running, &liveness));
+        assert!(!state_has_drifted(State::Stopped, &liveness));
+        if !cfg!(unix) {
+            return;
+        }
+
+        process = Some(
+            tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg("sleep 10")
+                .kill_on_drop(true)
+                .spawn()
+                .unwrap(),
+        );
+        let liveness = process_liveness(&mut process);
+        assert!(matches!(liveness, ProcessLiveness::Alive));
+        assert!(!state_has_drifted(State::Running, &liveness));
+
+        // nothing waits on the process, as if its exit was never noticed
+        process = Some(
+            tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg("exit 3")
+                .spawn()
+                .unwrap(),
+        );
+        let exit_status = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                if let ProcessLiveness::Exited(exit_status) = process_liveness(&mut process) {
+                    return exit_status;
                 }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(exit_status.code(), Some(3));
+        assert!(state_has_drifted(
+            State::Running,
+            &ProcessLiveness::Exited(exit_status)
+        ));
+        assert!(state_has_drifted(
+            State::Stopping,
+            &ProcessLiveness::Exited(exit_status)
+        ));
+    }
+
+    #[test]
+    fn test_jre_override_major_version() {
+        assert_eq!(super::jre_override_major_version("17"), Some(17));
+        assert_eq!(super::jre_override_major_version("17.0.7"), Some(17));
+        assert_eq!(super::jre_override_major_version("jdk-17.0.7+7"), Some(17));
+        assert_eq!(super::jre_override_major_version("jdk8u372-b07"), Some(8));
+        assert_eq!(super::jre_override_major_version("latest"), None);
+        assert_eq!(super::jre_override_major_version("0"), None);
+    }
+
+    #[test]
+    fn test_checksum_matches() {
+        let temp_dir = tempdir::TempDir::new("test_checksum_matches").unwrap();
+        let path = temp_dir.path().join("server.jar");
+        std::fs::write(&path, "hello").unwrap();
+        assert!(checksum_matches(
+            &path,
+            &JarChecksum::Sha1("aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d".to_string())
+        )
+        .unwrap());
+        assert!(checksum_matches(
+            &path,
+            &JarChecksum::Sha256(
+                "2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824".to_string()
             )
-            .await,
-            None
+        )
+        .unwrap());
+        assert!(!checksum_matches(
+            &path,
+            &JarChecksum::Sha1("0000000000000000000000000000000000000000".to_string())
+        )
+        .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_jre_needs_download() {
+        let temp_dir = tempdir::TempDir::new("test_jre_needs_download").unwrap();
+        let path_to_runtimes = temp_dir.path();
+        assert!(jre_needs_download(path_to_runtimes, "jre17").await.unwrap());
+        std::fs::create_dir_all(path_to_runtimes.join("java").join("jre17")).unwrap();
+        assert!(!jre_needs_download(path_to_runtimes, "jre17").await.unwrap());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_jre_needs_download_skips_read_only_runtimes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir::TempDir::new("test_jre_read_only_runtimes").unwrap();
+        let path_to_runtimes = temp_dir.path().join("runtimes");
+        let path_to_java = path_to_runtimes.join("java");
+        std::fs::create_dir_all(path_to_java.join("jre17")).unwrap();
+        let set_mode = |mode| {
+            for path in [&path_to_java, &path_to_runtimes] {
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+            }
+        };
+        set_mode(0o555);
+        // a mounted JRE is used without writing to the directory
+        let needs_download = jre_needs_download(&path_to_runtimes, "jre17").await;
+        let entries = std::fs::read_dir(&path_to_java).unwrap().count();
+        set_mode(0o755);
+        assert!(!needs_download.unwrap());
+        assert_eq!(entries, 1);
+    }
+
+    #[test]
+    fn test_parse_raw_properties() {
+        let properties = parse_raw_properties("#comment\nmotd=A = B\n\nmax-players=10\n").unwrap();
+        assert_eq!(properties.get("motd").unwrap(), "A = B");
+        assert_eq!(properties.get("max-players").unwrap(), "10");
+
+        // one invalid line rejects the whole file
+        let e = parse_raw_properties("max-players=ten\nmotd=hi\nno separator\nmotd=again")
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("Line 1"));
+        assert!(e.contains("Line 3"));
+        assert!(e.contains("Line 4"));
+    }
+
+    #[test]
+    fn test_parse_properties_lenient() {
+        let (settings, invalid) = parse_properties_lenient(
+            "#Minecraft server properties\nmotd=A = B\nmax-players=ten\nsome-plugin-option=1\nno separator\n=orphan\n\ndifficulty=hard\n",
+        );
+        // the unknown property is kept like the valid ones
+        assert_eq!(
+            settings
+                .iter()
+                .map(|setting| setting.get_identifier())
+                .collect::<Vec<_>>(),
+            vec!["motd", "some-plugin-option", "difficulty"]
+        );
+        assert_eq!(
+            invalid
+                .iter()
+                .map(|line| line.line.as_str())
+                .collect::<Vec<_>>(),
+            vec!["max-players=ten", "no separator", "=orphan"]
+        );
+        assert!(invalid[0].reason.contains("max-players"));
+    }
+
+    #[test]
+    fn test_validate_properties() {
+        let mut properties = IndexMap::new();
+        properties.insert("motd".to_string(), "A = B".to_string());
+        properties.insert("max-players".to_string(), "10".to_string());
+        assert!(validate_properties(&properties).is_ok());
+
+        // one invalid value rejects the whole batch, with an error for each
+        properties.insert("difficulty".to_string(), "impossible".to_string());
+        properties.insert("pvp".to_string(), "true".to_string());
+        properties.insert("server-port".to_string(), "-1".to_string());
+        properties.insert("motd".to_string(), "two\nlines".to_string());
+        let e = validate_properties(&properties).unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::BadRequest));
+        let FieldErrors(errors) = e.source.downcast_ref::<FieldErrors>().unwrap();
+        assert_eq!(
+            errors.keys().collect::<Vec<_>>(),
+            vec!["motd", "difficulty", "server-port"]
+        );
+        assert_eq!(errors["motd"], "Value cannot span multiple lines");
+    }
+
+    #[test]
+    fn test_live_property_command() {
+        assert_eq!(
+            live_property_command("difficulty", "hard").as_deref(),
+            Some("difficulty hard")
+        );
+        assert_eq!(
+            live_property_command("white-list", "false").as_deref(),
+            Some("whitelist off")
+        );
+        assert_eq!(live_property_command("max-players", "10"), None);
+    }
+
+    #[test]
+    fn test_diff_properties() {
+        let old = parse_raw_properties("motd=old\npvp=true\nmax-players=10").unwrap();
+        let new = parse_raw_properties("max-players=10\nmotd=new\ndifficulty=hard").unwrap();
+        let changes = diff_properties(&old, &new);
+        assert_eq!(
+            changes
+                .iter()
+                .map(|change| (
+                    change.key.as_str(),
+                    change.old_value.as_deref(),
+                    change.new_value.as_deref()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("motd", Some("old"), Some("new")),
+                ("difficulty", None, Some("hard")),
+                ("pvp", Some("true"), None),
+            ]
         );
     }
 }
@@ -2,18 +2,12 @@ use color_eyre::eyre::{eyre, Context, ContextCompat};
 use serde_json::Value;
 
 use crate::error::Error;
+use crate::upstream_cache::cached_get_text;
 
 pub async fn get_vanilla_minecraft_versions() -> Result<Vec<String>, Error> {
-    let http = reqwest::Client::new();
-
     let response: Value = serde_json::from_str(
-        http.get("https://launchermeta.mojang.com/mc/game/version_manifest.json")
-            .send()
-            .await
-            .context("Failed to get vanilla versions")?
-            .text()
-            .await
-            .context("Failed to get vanilla versions")?
+        cached_get_text("https://launchermeta.mojang.com/mc/game/version_manifest.json")
+            .await?
             .as_str(),
     )
     .context("Failed to get vanilla versions")?;
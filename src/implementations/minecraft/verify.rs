@@ -0,0 +1,397 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+
+use crate::error::{Error, ErrorKind};
+use crate::traits::t_server::{InstallationComponent, InstallationIssue, State, VerifyReport};
+use crate::util::{download_file, list_dir};
+
+use super::configurable::ServerPropertySetting;
+use super::jre::check_java_runs;
+use super::spigot::{run_build_tools, DEFAULT_BUILD_TOOLS_TIMEOUT_SECS};
+use super::util::{
+    checksum_matches, ensure_runtimes_dir_writable, get_server_jar_checksum, get_server_jar_url,
+    install_jre, read_properties_from_path, resolve_jre_download,
+};
+use super::{Flavour, ForgeBuildVersion, MinecraftInstance, RestoreConfig};
+
+/// Number of missing libraries listed in an issue before the rest are summarized
+const MAX_LISTED_LIBRARIES: usize = 5;
+
+fn issue(
+    component: InstallationComponent,
+    message: impl Into<String>,
+    suggested_fix: impl Into<String>,
+    repairable: bool,
+) -> InstallationIssue {
+    InstallationIssue {
+        component,
+        message: message.into(),
+        suggested_fix: suggested_fix.into(),
+        repairable,
+    }
+}
+
+/// Finds a jar directly in `dir` whose name starts with `prefix`
+async fn find_jar(dir: &Path, prefix: &str) -> Option<PathBuf> {
+    list_dir(dir, Some(false))
+        .await
+        .ok()?
+        .into_iter()
+        .find(|p| {
+            p.extension().unwrap_or_default() == "jar"
+                && p.file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .starts_with(prefix)
+        })
+}
+
+impl MinecraftInstance {
+    /// Whether the JRE is one Lodestone downloaded, and so can download again
+    fn is_managed_jre(&self, config: &RestoreConfig) -> bool {
+        self.java_path(config)
+            .starts_with(self.path_to_runtimes.join("java"))
+    }
+
+    async fn verify_server_jar(&self, config: &RestoreConfig) -> Vec<InstallationIssue> {
+        let path_to_jar = self.path_to_instance.join("server.jar");
+        if !path_to_jar.is_file() {
+            return vec![issue(
+                InstallationComponent::ServerJar,
+                "server.jar is missing",
+                "Repair the instance to download it again",
+                true,
+            )];
+        }
+        let checksum = match get_server_jar_checksum(&config.version, &config.flavour).await {
+            Some(checksum) => checksum,
+            // nothing to check against
+            None => return Vec::new(),
+        };
+        match tokio::task::spawn_blocking(move || checksum_matches(&path_to_jar, &checksum)).await
+        {
+            Ok(Ok(true)) => Vec::new(),
+            Ok(Ok(false)) => vec![issue(
+                InstallationComponent::ServerJar,
+                format!(
+                    "server.jar doesn't match the published checksum for {} {}, it may be corrupted or replaced",
+                    config.flavour.to_string(),
+                    config.version
+                ),
+                "Repair the instance to download it again",
+                true,
+            )],
+            Ok(Err(e)) => vec![issue(
+                InstallationComponent::ServerJar,
+                format!("server.jar could not be read: {}", e),
+                "Check the permissions of server.jar",
+                false,
+            )],
+            Err(e) => vec![issue(
+                InstallationComponent::ServerJar,
+                format!("server.jar could not be checked: {}", e),
+                "Try again",
+                false,
+            )],
+        }
+    }
+
+    async fn verify_forge(
+        &self,
+        config: &RestoreConfig,
+        build_version: &Option<ForgeBuildVersion>,
+    ) -> Vec<InstallationIssue> {
+        let reinstall = "Create a new instance to reinstall Forge";
+        let build_version = match build_version {
+            Some(ForgeBuildVersion(build_version)) => build_version,
+            None => {
+                return vec![issue(
+                    InstallationComponent::ServerJar,
+                    "The Forge version of this instance is unknown",
+                    reinstall,
+                    false,
+                )]
+            }
+        };
+        let major_version: Option<i32> = config
+            .version
+            .split('.')
+            .nth(1)
+            .and_then(|v| v.parse().ok());
+        match major_version {
+            Some(major_version) if major_version >= 17 => {
+                let args_file = self
+                    .path_to_instance
+                    .join("libraries")
+                    .join("net")
+                    .join("minecraftforge")
+                    .join("forge")
+                    .join(build_version)
+                    .join(match std::env::consts::OS {
+                        "windows" => "win_args.txt",
+                        _ => "unix_args.txt",
+                    });
+                let args = match tokio::fs::read_to_string(&args_file).await {
+                    Ok(args) => args,
+                    Err(_) => {
+                        return vec![issue(
+                            InstallationComponent::Libraries,
+                            format!(
+                                "Forge launch arguments at {} are missing",
+                                args_file.display()
+                            ),
+                            reinstall,
+                            false,
+                        )]
+                    }
+                };
+                let missing: Vec<&str> = args
+                    .split_whitespace()
+                    .flat_map(|arg| arg.split([':', ';']))
+                    .filter(|part| part.starts_with("libraries/"))
+                    .filter(|part| !self.path_to_instance.join(part).exists())
+                    .collect();
+                if missing.is_empty() {
+                    return Vec::new();
+                }
+                let mut listed = missing
+                    .iter()
+                    .take(MAX_LISTED_LIBRARIES)
+                    .copied()
+                    .collect::<Vec<&str>>()
+                    .join(", ");
+                if missing.len() > MAX_LISTED_LIBRARIES {
+                    listed.push_str(&format!(
+                        " and {} more",
+                        missing.len() - MAX_LISTED_LIBRARIES
+                    ));
+                }
+                vec![issue(
+                    InstallationComponent::Libraries,
+                    format!("{} Forge libraries are missing: {}", missing.len(), listed),
+                    reinstall,
+                    false,
+                )]
+            }
+            Some(major_version) if major_version >= 7 => {
+                if find_jar(
+                    &self.path_to_instance,
+                    &format!("forge-{}-", config.version),
+                )
+                .await
+                .is_none()
+                {
+                    vec![issue(
+                        InstallationComponent::ServerJar,
+                        "The Forge server jar is missing",
+                        reinstall,
+                        false,
+                    )]
+                } else {
+                    Vec::new()
+                }
+            }
+            _ => {
+                if find_jar(&self.path_to_instance, "minecraftforge")
+                    .await
+                    .is_none()
+                {
+                    vec![issue(
+                        InstallationComponent::ServerJar,
+                        "The Forge server jar is missing",
+                        reinstall,
+                        false,
+                    )]
+                } else {
+                    Vec::new()
+                }
+            }
+        }
+    }
+
+    async fn verify_java(&self, config: &RestoreConfig) -> Vec<InstallationIssue> {
+        let java = self.java_path(config);
+        let managed = self.is_managed_jre(config);
+        let fix = if managed {
+            "Repair the instance to download Java again"
+        } else {
+            "Check the Java path configured for this instance"
+        };
+        if !java.exists() {
+            return vec![issue(
+                InstallationComponent::Java,
+                format!("Java is missing at {}", java.display()),
+                fix,
+                managed,
+            )];
+        }
+        let failure = match check_java_runs(&java).await {
+            Ok(()) => return Vec::new(),
+            Err(failure) => failure,
+        };
+        vec![issue(
+            InstallationComponent::Java,
+            format!("Java at {} failed to run: {}", java.display(), failure),
+            fix,
+            managed,
+        )]
+    }
+
+    async fn verify_eula(&self) -> Vec<InstallationIssue> {
+        let accepted = tokio::fs::read_to_string(self.path_to_instance.join("eula.txt"))
+            .await
+            .map(|eula| eula.lines().any(|line| line.trim() == "eula=true"))
+            .unwrap_or(false);
+        if accepted {
+            Vec::new()
+        } else {
+            vec![issue(
+                InstallationComponent::Eula,
+                "The Minecraft EULA has not been accepted in eula.txt",
+                "Repair the instance to accept the EULA again",
+                true,
+            )]
+        }
+    }
+
+    async fn verify_properties(&self) -> Vec<InstallationIssue> {
+        if !self.path_to_properties.exists() {
+            // the server generates it on first boot
+            return Vec::new();
+        }
+        match read_properties_from_path(&self.path_to_properties).await {
+            Ok(properties) => properties
+                .iter()
+                .filter_map(|(key, value)| {
+                    ServerPropertySetting::from_key_val(key, value)
+                        .err()
+                        .map(|e| {
+                            issue(
+                                InstallationComponent::Properties,
+                                e.to_string(),
+                                format!("Correct the value of {} in server.properties", key),
+                                false,
+                            )
+                        })
+                })
+                .collect(),
+            Err(e) => vec![issue(
+                InstallationComponent::Properties,
+                format!("server.properties could not be read: {}", e),
+                "Check that server.properties is readable, or delete it to have it regenerated",
+                false,
+            )],
+        }
+    }
+
+    pub(super) async fn verify(&self) -> Result<VerifyReport, Error> {
+        let config = self.config.lock().await.clone();
+        let mut issues = match &config.flavour {
+            Flavour::Forge { build_version } => self.verify_forge(&config, build_version).await,
+            _ => self.verify_server_jar(&config).await,
+        };
+        issues.extend(self.verify_java(&config).await);
+        issues.extend(self.verify_eula().await);
+        issues.extend(self.verify_properties().await);
+        Ok(VerifyReport { issues })
+    }
+
+    pub(super) async fn repair(&mut self) -> Result<VerifyReport, Error> {
+        // keeps the server from being started halfway through
+        let _lifecycle_guard = self.lifecycle_lock.clone().lock_owned().await;
+        if *self.state.lock().await != State::Stopped {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Cannot repair an instance while it is running"),
+            });
+        }
+        let config = self.config.lock().await.clone();
+        let report = self.verify().await?;
+        let needs_repair = |component: InstallationComponent| {
+            report
+                .issues
+                .iter()
+                .any(|issue| issue.repairable && issue.component == component)
+        };
+        if needs_repair(InstallationComponent::Java) {
+            let jre_download = resolve_jre_download(
+                &config.version,
+                config.jre_vendor,
+                config.jre_version_override.as_deref(),
+            )
+            .await?;
+            let path_to_jre = self
+                .path_to_runtimes
+                .join("java")
+                .join(&jre_download.dir_name);
+            if path_to_jre.exists() {
+                // the runtime is shared with other instances, which may be running on it,
+                // so it's verified rather than deleted and downloaded again
+                if let Err(failure) = check_java_runs(&self.java_path(&config)).await {
+                    return Err(Error {
+                        kind: ErrorKind::InvalidInstanceState,
+                        source: eyre!(
+                            "The Java runtime at {} is shared with other instances and failed to run: {}. Delete it once no instance is running on it, then repair again",
+                            path_to_jre.display(),
+                            failure
+                        ),
+                    });
+                }
+            } else {
+                ensure_runtimes_dir_writable(&self.path_to_runtimes, &jre_download.dir_name)
+                    .await?;
+                install_jre(
+                    &jre_download.url,
+                    &jre_download.dir_name,
+                    &self.path_to_runtimes,
+                    &|_| {},
+                )
+                .await?;
+            }
+        }
+        // spigot is rebuilt with the JRE, so it's repaired after java
+        if needs_repair(InstallationComponent::ServerJar) {
+            let (url, _) = get_server_jar_url(&config.version, &config.flavour).await?;
+            if let Flavour::Spigot = config.flavour {
+                download_file(
+                    &url,
+                    &self.path_to_instance,
+                    Some("BuildTools.jar"),
+                    &|_| {},
+                    true,
+                )
+                .await?;
+                run_build_tools(
+                    &self.java_path(&config),
+                    &self.path_to_instance,
+                    &config.version,
+                    Duration::from_secs(DEFAULT_BUILD_TOOLS_TIMEOUT_SECS),
+                    0.0,
+                    |_, _| {},
+                    |_| {},
+                )
+                .await?;
+            } else {
+                download_file(
+                    &url,
+                    &self.path_to_instance,
+                    Some("server.jar"),
+                    &|_| {},
+                    true,
+                )
+                .await?;
+            }
+        }
+        if needs_repair(InstallationComponent::Eula) {
+            tokio::fs::write(
+                self.path_to_instance.join("eula.txt"),
+                "#generated by Lodestone\neula=true",
+            )
+            .await
+            .context("Failed to write eula.txt")?;
+        }
+        self.verify().await
+    }
+}
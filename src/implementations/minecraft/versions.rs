@@ -4,6 +4,7 @@ use serde_json::Value;
 use ts_rs::TS;
 
 use crate::error::Error;
+use crate::upstream_http;
 
 #[derive(Serialize, Deserialize, Debug, TS)]
 #[ts(export)]
@@ -14,16 +15,9 @@ pub struct MinecraftVersions {
 }
 
 pub async fn get_vanilla_versions() -> Result<MinecraftVersions, Error> {
-    let http = reqwest::Client::new();
     let response: Value = serde_json::from_str(
-        http.get("https://launchermeta.mojang.com/mc/game/version_manifest.json")
-            .send()
-            .await
-            .context("Failed to get vanilla versions")?
-            .text()
-            .await
-            .context("Failed to get vanilla versions")?
-            .as_str(),
+        &upstream_http::get_text("https://launchermeta.mojang.com/mc/game/version_manifest.json")
+            .await?,
     )
     .context("Failed to get vanilla versions")?;
 
@@ -98,17 +92,8 @@ pub async fn group_minecraft_versions(versions: &Vec<&str>) -> Result<MinecraftV
 }
 
 pub async fn get_fabric_versions() -> Result<MinecraftVersions, Error> {
-    let http = reqwest::Client::new();
-
     let response: Value = serde_json::from_str(
-        http.get("https://meta.fabricmc.net/v2/versions")
-            .send()
-            .await
-            .context("Failed to get fabric versions")?
-            .text()
-            .await
-            .context("Failed to get fabric versions")?
-            .as_str(),
+        &upstream_http::get_text("https://meta.fabricmc.net/v2/versions").await?,
     )
     .context("Failed to get fabric versions")?;
 
@@ -127,17 +112,8 @@ pub async fn get_fabric_versions() -> Result<MinecraftVersions, Error> {
 }
 
 pub async fn get_paper_versions() -> Result<MinecraftVersions, Error> {
-    let http = reqwest::Client::new();
-
     let response: Value = serde_json::from_str(
-        http.get("https://api.papermc.io/v2/projects/paper")
-            .send()
-            .await
-            .context("Failed to get paper versions")?
-            .text()
-            .await
-            .context("Failed to get paper versions")?
-            .as_str(),
+        &upstream_http::get_text("https://api.papermc.io/v2/projects/paper").await?,
     )
     .context("Failed to get paper versions")?;
 
@@ -158,17 +134,11 @@ pub async fn get_paper_versions() -> Result<MinecraftVersions, Error> {
 }
 
 pub async fn get_forge_versions() -> Result<MinecraftVersions, Error> {
-    let http = reqwest::Client::new();
-
     let response: Value = serde_json::from_str(
-        http.get("https://files.minecraftforge.net/net/minecraftforge/forge/maven-metadata.json")
-            .send()
-            .await
-            .context("Failed to get forge versions")?
-            .text()
-            .await
-            .context("Failed to get forge versions")?
-            .as_str(),
+        &upstream_http::get_text(
+            "https://files.minecraftforge.net/net/minecraftforge/forge/maven-metadata.json",
+        )
+        .await?,
     )
     .context("Failed to get forge versions")?;
 
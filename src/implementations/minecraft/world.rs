@@ -0,0 +1,636 @@
+use std::cmp::Ordering;
+use std::path::{Component, Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use tracing::{error, info, warn};
+
+use crate::disk_quota::{disk_usage_cache, mb_to_bytes, measure_dir};
+use crate::error::{Error, ErrorKind};
+use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::global_settings::core_timezone;
+use crate::prelude::path_to_tmp;
+use crate::traits::t_configurable::{AvailableWorld, WorldInfo};
+use crate::traits::t_server::State;
+use crate::types::Snowflake;
+use crate::util::{format_local_timestamp, resolve_path_conflict, unzip_file_async, UnzipOption};
+
+use super::configurable::ServerPropertySetting;
+use super::nbt::{read_gzip_nbt_file, Tag};
+use super::update::{compare_dotted_versions, is_release_version};
+use super::util::read_properties_from_path;
+use super::MinecraftInstance;
+
+/// The world the server loads if `level-name` isn't set
+const DEFAULT_LEVEL_NAME: &str = "world";
+
+/// Data version of each release since they were introduced, increasing with every version of the game
+const RELEASE_DATA_VERSIONS: &[(&str, i32)] = &[
+    ("1.9", 169),
+    ("1.9.1", 175),
+    ("1.9.2", 176),
+    ("1.9.3", 183),
+    ("1.9.4", 184),
+    ("1.10", 510),
+    ("1.10.1", 511),
+    ("1.10.2", 512),
+    ("1.11", 819),
+    ("1.11.1", 921),
+    ("1.11.2", 922),
+    ("1.12", 1139),
+    ("1.12.1", 1241),
+    ("1.12.2", 1343),
+    ("1.13", 1519),
+    ("1.13.1", 1628),
+    ("1.13.2", 1631),
+    ("1.14", 1952),
+    ("1.14.1", 1957),
+    ("1.14.2", 1963),
+    ("1.14.3", 1968),
+    ("1.14.4", 1976),
+    ("1.15", 2225),
+    ("1.15.1", 2227),
+    ("1.15.2", 2230),
+    ("1.16", 2566),
+    ("1.16.1", 2567),
+    ("1.16.2", 2578),
+    ("1.16.3", 2580),
+    ("1.16.4", 2584),
+    ("1.16.5", 2586),
+    ("1.17", 2724),
+    ("1.17.1", 2730),
+    ("1.18", 2860),
+    ("1.18.1", 2865),
+    ("1.18.2", 2975),
+    ("1.19", 3105),
+    ("1.19.1", 3117),
+    ("1.19.2", 3120),
+    ("1.19.3", 3218),
+    ("1.19.4", 3337),
+    ("1.20", 3463),
+    ("1.20.1", 3465),
+    ("1.20.2", 3578),
+    ("1.20.3", 3698),
+    ("1.20.4", 3700),
+    ("1.20.5", 3837),
+    ("1.20.6", 3839),
+    ("1.21", 3953),
+    ("1.21.1", 3955),
+    ("1.21.2", 4080),
+    ("1.21.3", 4082),
+    ("1.21.4", 4189),
+];
+
+/// Data version of a release, `None` for snapshots and releases older than data versions
+pub(super) fn release_data_version(version: &str) -> Option<i32> {
+    RELEASE_DATA_VERSIONS
+        .iter()
+        .find(|(release, _)| *release == version)
+        .map(|(_, data_version)| *data_version)
+}
+
+/// Whether a world is newer than `server_version`, by data version if the server's is known
+/// and by name otherwise. `None` if it can't be told, e.g. for an unknown snapshot
+fn is_world_newer(
+    world_data_version: Option<i32>,
+    world_version: Option<&str>,
+    server_version: &str,
+) -> Option<bool> {
+    if let (Some(world), Some(server)) = (world_data_version, release_data_version(server_version))
+    {
+        return Some(world > server);
+    }
+    let world_version = world_version?;
+    if !is_release_version(world_version) || !is_release_version(server_version) {
+        return None;
+    }
+    Some(compare_dotted_versions(world_version, server_version) == Ordering::Greater)
+}
+
+/// Whether a world can be loaded by a server of `server_version`, returning whether it was saved by a newer version.
+///
+/// Such a world is rejected unless `allow_downgrade`, as the server would corrupt it
+pub(super) fn check_world_downgrade(
+    world_info: &WorldInfo,
+    server_version: &str,
+    allow_downgrade: bool,
+) -> Result<bool, Error> {
+    let newer = is_world_newer(
+        world_info.data_version,
+        world_info.version.as_deref(),
+        server_version,
+    )
+    .unwrap_or(false);
+    if newer && !allow_downgrade {
+        return Err(Error {
+            kind: ErrorKind::InvalidInstanceState,
+            source: eyre!(
+                "The world was saved by {}, which is newer than {}. Loading it with an older server corrupts it. Restore a backup of the world from {} or older, or explicitly allow the downgrade",
+                world_info.version.as_deref().unwrap_or("a newer version"),
+                server_version,
+                server_version
+            ),
+        });
+    }
+    Ok(newer)
+}
+
+/// Extracts the metadata of a world from its parsed `level.dat`
+pub(super) fn parse_world_info(level_dat: &Tag, server_version: &str) -> Result<WorldInfo, Error> {
+    let data = level_dat.get("Data").ok_or_else(|| Error {
+        kind: ErrorKind::Internal,
+        source: eyre!("level.dat has no Data compound"),
+    })?;
+    let int = |key: &str| data.get(key).and_then(Tag::as_i64);
+    let data_version = int("DataVersion").map(|data_version| data_version as i32);
+    let version = data
+        .get("Version")
+        .and_then(|version| version.get("Name"))
+        .and_then(Tag::as_str)
+        .map(str::to_string);
+    let spawn = match (int("SpawnX"), int("SpawnY"), int("SpawnZ")) {
+        (Some(x), Some(y), Some(z)) => Some((x as i32, y as i32, z as i32)),
+        _ => None,
+    };
+    let game_rules = match data.get("GameRules") {
+        Some(Tag::Compound(rules)) => rules
+            .iter()
+            .filter_map(|(rule, value)| Some((rule.clone(), value.as_str()?.to_string())))
+            .collect(),
+        _ => Default::default(),
+    };
+    let difficulty = int("Difficulty").and_then(|difficulty| match difficulty {
+        0 => Some("peaceful".to_string()),
+        1 => Some("easy".to_string()),
+        2 => Some("normal".to_string()),
+        3 => Some("hard".to_string()),
+        _ => None,
+    });
+    Ok(WorldInfo {
+        level_name: data
+            .get("LevelName")
+            .and_then(Tag::as_str)
+            .map(str::to_string),
+        newer_than_server: is_world_newer(data_version, version.as_deref(), server_version)
+            .unwrap_or(false),
+        version,
+        data_version,
+        last_played: int("LastPlayed"),
+        spawn,
+        game_rules,
+        hardcore: int("hardcore") == Some(1),
+        difficulty,
+    })
+}
+
+/// The directory holding `level.dat` in an extracted world archive,
+/// either the root of the archive or its only top level directory
+fn find_world_root(path_to_extracted: &Path) -> Result<PathBuf, Error> {
+    if path_to_extracted.join("level.dat").is_file() {
+        return Ok(path_to_extracted.to_path_buf());
+    }
+    let candidates = std::fs::read_dir(path_to_extracted)
+        .context(format!(
+            "Failed to read directory {}",
+            path_to_extracted.display()
+        ))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.join("level.dat").is_file())
+        .collect::<Vec<_>>();
+    match candidates.as_slice() {
+        [world] => Ok(world.clone()),
+        [] => Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The archive doesn't contain a world, there is no level.dat in it"),
+        }),
+        _ => Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The archive contains more than one world"),
+        }),
+    }
+}
+
+/// The directory of the world the server in `path_to_instance` loads, as set by `level-name`
+pub(super) async fn path_to_active_world(
+    path_to_instance: &Path,
+    path_to_properties: &Path,
+) -> PathBuf {
+    let level_name = read_properties_from_path(path_to_properties)
+        .await
+        .ok()
+        .and_then(|properties| properties.get("level-name").cloned())
+        .filter(|level_name| !level_name.is_empty())
+        .unwrap_or_else(|| DEFAULT_LEVEL_NAME.to_string());
+    path_to_instance.join(level_name)
+}
+
+/// The generated worlds directly in `path_to_instance`, sorted by name
+fn list_worlds_in(
+    path_to_instance: &Path,
+    active_level_name: &str,
+) -> Result<Vec<AvailableWorld>, Error> {
+    let mut worlds = std::fs::read_dir(path_to_instance)
+        .context(format!(
+            "Failed to read directory {}",
+            path_to_instance.display()
+        ))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.join("level.dat").is_file())
+        .filter_map(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().to_string())
+        })
+        .map(|level_name| AvailableWorld {
+            active: level_name == active_level_name,
+            level_name,
+        })
+        .collect::<Vec<_>>();
+    worlds.sort_by(|a, b| a.level_name.cmp(&b.level_name));
+    Ok(worlds)
+}
+
+/// Reads the metadata of the world at `path_to_world`. This blocks, run it with `spawn_blocking`
+fn read_world_info(path_to_world: &Path, server_version: &str) -> Result<WorldInfo, Error> {
+    let path_to_level_dat = path_to_world.join("level.dat");
+    if !path_to_level_dat.is_file() {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("The world hasn't been generated yet"),
+        });
+    }
+    parse_world_info(&read_gzip_nbt_file(&path_to_level_dat)?, server_version)
+}
+
+impl MinecraftInstance {
+    /// The directory of the world the server loads, as set by `level-name`
+    pub(super) async fn path_to_world(&self) -> PathBuf {
+        path_to_active_world(&self.path_to_instance, &self.path_to_properties).await
+    }
+
+    pub(super) async fn list_available_worlds(&self) -> Result<Vec<AvailableWorld>, Error> {
+        let path_to_world = self.path_to_world().await;
+        let active_level_name = path_to_world
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let path_to_instance = self.path_to_instance.clone();
+        tokio::task::spawn_blocking(move || list_worlds_in(&path_to_instance, &active_level_name))
+            .await
+            .map_err(|e| eyre!("Listing worlds panicked: {}", e))?
+    }
+
+    /// Points `level-name` at the world in `level_name`, which has to be a generated world
+    /// directly in the instance directory. Only while the server is stopped
+    pub(super) async fn switch_world(&self, level_name: &str) -> Result<(), Error> {
+        let _lifecycle_guard = self.lifecycle_lock.clone().lock_owned().await;
+        if *self.state.lock().await != State::Stopped {
+            return Err(Error {
+                kind: ErrorKind::InvalidInstanceState,
+                source: eyre!("The world can only be switched while the server is stopped"),
+            });
+        }
+        // a single directory, not a path out of the instance directory
+        let mut components = Path::new(level_name).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid world name \"{}\"", level_name),
+            });
+        }
+        if !self
+            .path_to_instance
+            .join(level_name)
+            .join("level.dat")
+            .is_file()
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "There is no world named \"{}\" in the instance directory",
+                    level_name
+                ),
+            });
+        }
+        self.configurable_manifest.lock().await.set_setting(
+            ServerPropertySetting::get_section_id(),
+            ServerPropertySetting::LevelName(level_name.to_string()).into(),
+        )?;
+        self.write_properties_to_file().await?;
+        info!(
+            "[{}] Switched the active world to {}",
+            self.config.lock().await.name,
+            level_name
+        );
+        Ok(())
+    }
+
+    pub(super) async fn read_world_info(&self) -> Result<WorldInfo, Error> {
+        let path_to_world = self.path_to_world().await;
+        let server_version = self.config.lock().await.version.clone();
+        tokio::task::spawn_blocking(move || read_world_info(&path_to_world, &server_version))
+            .await
+            .map_err(|e| eyre!("Reading world info panicked: {}", e))?
+    }
+
+    /// Fails if the world was saved by a newer version than `server_version`, as loading it would corrupt it.
+    ///
+    /// If `allow_downgrade` it only warns
+    pub(super) async fn check_world_downgrade(
+        &self,
+        server_version: &str,
+        allow_downgrade: bool,
+    ) -> Result<(), Error> {
+        let world_info = match self.read_world_info().await {
+            Ok(world_info) => world_info,
+            // a world that hasn't been generated can't be corrupted
+            Err(e) if matches!(e.kind, ErrorKind::NotFound) => return Ok(()),
+            Err(e) => {
+                warn!(
+                    "[{}] Failed to read the world, can't check whether it's newer than the server: {}",
+                    self.config.lock().await.name,
+                    e
+                );
+                return Ok(());
+            }
+        };
+        self.check_world_info_downgrade(&world_info, server_version, allow_downgrade)
+            .await
+    }
+
+    /// Like `check_world_downgrade`, for the world described by `world_info`
+    async fn check_world_info_downgrade(
+        &self,
+        world_info: &WorldInfo,
+        server_version: &str,
+        allow_downgrade: bool,
+    ) -> Result<(), Error> {
+        if !check_world_downgrade(world_info, server_version, allow_downgrade)? {
+            return Ok(());
+        }
+        let name = self.config.lock().await.name.clone();
+        let message = format!(
+            "The world was saved by {}, which is newer than {}. The downgrade was explicitly allowed, the world may be corrupted",
+            world_info.version.as_deref().unwrap_or("a newer version"),
+            server_version
+        );
+        warn!("[{}] {}", name, message);
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_name: name,
+                instance_uuid: self.uuid.clone(),
+                instance_event_inner: InstanceEventInner::InstanceWarning { message },
+            }),
+            snowflake: Snowflake::default(),
+            details: "".to_string(),
+            caused_by: CausedBy::System,
+        });
+        Ok(())
+    }
+
+    /// Replaces the world with the one in the archive at `path_to_archive`, keeping the current
+    /// one next to it. Only while the server is stopped
+    pub(super) async fn swap_in_world(
+        &self,
+        path_to_archive: &Path,
+        allow_downgrade: bool,
+    ) -> Result<WorldInfo, Error> {
+        // keeps the server from being started halfway through
+        let _lifecycle_guard = self.lifecycle_lock.clone().lock_owned().await;
+        if *self.state.lock().await != State::Stopped {
+            return Err(Error {
+                kind: ErrorKind::InvalidInstanceState,
+                source: eyre!("The world can only be replaced while the server is stopped"),
+            });
+        }
+        let config = self.config.lock().await.clone();
+
+        let path_to_extracted =
+            tempfile::tempdir_in(path_to_tmp()).context("Failed to create temporary directory")?;
+        unzip_file_async(
+            path_to_archive,
+            UnzipOption::ToDir(path_to_extracted.path().to_path_buf()),
+        )
+        .await
+        .map_err(|e| Error {
+            kind: ErrorKind::BadRequest,
+            source: e.source.wrap_err("Failed to extract the uploaded world"),
+        })?;
+        let (path_to_new_world, world_info) = tokio::task::spawn_blocking({
+            let path_to_extracted = path_to_extracted.path().to_path_buf();
+            let server_version = config.version.clone();
+            move || -> Result<(PathBuf, WorldInfo), Error> {
+                let path_to_new_world = find_world_root(&path_to_extracted)?;
+                let world_info =
+                    read_world_info(&path_to_new_world, &server_version).map_err(|e| Error {
+                        kind: ErrorKind::BadRequest,
+                        source: e
+                            .source
+                            .wrap_err("The uploaded world's level.dat is invalid"),
+                    })?;
+                Ok((path_to_new_world, world_info))
+            }
+        })
+        .await
+        .map_err(|e| eyre!("Reading world info panicked: {}", e))??;
+        self.check_world_info_downgrade(&world_info, &config.version, allow_downgrade)
+            .await?;
+        // the previous world is kept, so the new one adds to the usage in full
+        if let Some(quota_mb) = config.disk_quota_mb {
+            disk_usage_cache()
+                .ensure_fits(
+                    &self.path_to_instance,
+                    Some(mb_to_bytes(quota_mb)),
+                    measure_dir(&path_to_new_world).await?,
+                )
+                .await?;
+        }
+
+        let path_to_world = self.path_to_world().await;
+        if path_to_world.exists() {
+            self.backup_before("world-replace").await?;
+        }
+        let path_to_previous_world = if path_to_world.exists() {
+            let level_name = path_to_world
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| DEFAULT_LEVEL_NAME.to_string());
+            let path_to_previous_world = resolve_path_conflict(
+                self.path_to_instance.join(format!(
+                    "{}-before-upload-{}",
+                    level_name,
+                    format_local_timestamp(chrono::Utc::now().timestamp(), &core_timezone())
+                )),
+                None,
+            );
+            crate::util::fs::rename(&path_to_world, &path_to_previous_world).await?;
+            Some(path_to_previous_world)
+        } else {
+            None
+        };
+        if let Err(e) = crate::util::fs::rename(&path_to_new_world, &path_to_world).await {
+            if let Some(path_to_previous_world) = &path_to_previous_world {
+                if let Err(e) =
+                    crate::util::fs::rename(path_to_previous_world, &path_to_world).await
+                {
+                    error!(
+                        "[{}] Failed to restore the previous world from {}: {}",
+                        config.name,
+                        path_to_previous_world.display(),
+                        e
+                    );
+                }
+            }
+            return Err(e);
+        }
+        disk_usage_cache().invalidate(&self.path_to_instance);
+        match path_to_previous_world {
+            Some(path_to_previous_world) => info!(
+                "[{}] Replaced the world, the previous one was kept at {}",
+                config.name,
+                path_to_previous_world.display()
+            ),
+            None => info!("[{}] Replaced the world", config.name),
+        }
+        Ok(world_info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+
+    use super::*;
+
+    fn level_dat(version: &str, data_version: i32) -> Tag {
+        let mut game_rules = IndexMap::new();
+        game_rules.insert("keepInventory".to_string(), Tag::String("true".to_string()));
+        let mut data = IndexMap::new();
+        data.insert("LevelName".to_string(), Tag::String("world".to_string()));
+        data.insert("DataVersion".to_string(), Tag::Int(data_version));
+        data.insert("LastPlayed".to_string(), Tag::Long(1690000000000));
+        data.insert("SpawnX".to_string(), Tag::Int(16));
+        data.insert("SpawnY".to_string(), Tag::Int(64));
+        data.insert("SpawnZ".to_string(), Tag::Int(-32));
+        data.insert("GameRules".to_string(), Tag::Compound(game_rules));
+        data.insert("hardcore".to_string(), Tag::Byte(0));
+        data.insert("Difficulty".to_string(), Tag::Byte(3));
+        let mut version_tag = IndexMap::new();
+        version_tag.insert("Name".to_string(), Tag::String(version.to_string()));
+        data.insert("Version".to_string(), Tag::Compound(version_tag));
+        let mut root = IndexMap::new();
+        root.insert("Data".to_string(), Tag::Compound(data));
+        Tag::Compound(root)
+    }
+
+    #[test]
+    fn test_parse_world_info() {
+        let world_info = parse_world_info(&level_dat("1.20.1", 3465), "1.20.1").unwrap();
+        assert_eq!(world_info.level_name.as_deref(), Some("world"));
+        assert_eq!(world_info.version.as_deref(), Some("1.20.1"));
+        assert_eq!(world_info.data_version, Some(3465));
+        assert_eq!(world_info.last_played, Some(1690000000000));
+        assert_eq!(world_info.spawn, Some((16, 64, -32)));
+        assert_eq!(
+            world_info
+                .game_rules
+                .get("keepInventory")
+                .map(String::as_str),
+            Some("true")
+        );
+        assert!(!world_info.hardcore);
+        assert_eq!(world_info.difficulty.as_deref(), Some("hard"));
+        assert!(!world_info.newer_than_server);
+
+        assert!(
+            parse_world_info(&level_dat("1.20.1", 3465), "1.19.4")
+                .unwrap()
+                .newer_than_server
+        );
+        assert!(parse_world_info(&Tag::Compound(IndexMap::new()), "1.20.1").is_err());
+    }
+
+    #[test]
+    fn test_check_world_downgrade() {
+        let world_info = |version: &str, data_version: i32| {
+            parse_world_info(&level_dat(version, data_version), "1.20.1").unwrap()
+        };
+        // same version and upgrades are fine
+        assert!(!check_world_downgrade(&world_info("1.20.1", 3465), "1.20.1", false).unwrap());
+        assert!(!check_world_downgrade(&world_info("1.19.4", 3337), "1.20.1", false).unwrap());
+
+        // downgrades are blocked unless explicitly allowed
+        let newer = world_info("1.20.2", 3578);
+        assert!(matches!(
+            check_world_downgrade(&newer, "1.20.1", false)
+                .unwrap_err()
+                .kind,
+            ErrorKind::InvalidInstanceState
+        ));
+        assert!(check_world_downgrade(&newer, "1.20.1", true).unwrap());
+
+        // a snapshot is compared by its data version
+        let snapshot = world_info("23w31a", 3567);
+        assert!(check_world_downgrade(&snapshot, "1.20.1", false).is_err());
+        assert!(!check_world_downgrade(&snapshot, "1.20.2", false).unwrap());
+
+        // servers missing from the table fall back to comparing names
+        assert!(check_world_downgrade(&newer, "1.8.9", false).is_err());
+        assert!(!check_world_downgrade(&snapshot, "1.8.9", false).unwrap());
+    }
+
+    #[test]
+    fn test_find_world_root() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            find_world_root(dir.path()).unwrap_err().kind,
+            ErrorKind::BadRequest
+        ));
+
+        // singleplayer worlds are usually zipped as their folder
+        std::fs::create_dir(dir.path().join("My World")).unwrap();
+        std::fs::write(dir.path().join("My World").join("level.dat"), b"").unwrap();
+        assert_eq!(
+            find_world_root(dir.path()).unwrap(),
+            dir.path().join("My World")
+        );
+
+        std::fs::create_dir(dir.path().join("Other World")).unwrap();
+        std::fs::write(dir.path().join("Other World").join("level.dat"), b"").unwrap();
+        assert!(find_world_root(dir.path()).is_err());
+
+        std::fs::write(dir.path().join("level.dat"), b"").unwrap();
+        assert_eq!(find_world_root(dir.path()).unwrap(), dir.path());
+    }
+
+    #[test]
+    fn test_list_worlds_in() {
+        let dir = tempfile::tempdir().unwrap();
+        for level_name in ["world", "summer", "winter"] {
+            std::fs::create_dir(dir.path().join(level_name)).unwrap();
+            std::fs::write(dir.path().join(level_name).join("level.dat"), b"").unwrap();
+        }
+        // not a world
+        std::fs::create_dir(dir.path().join("mods")).unwrap();
+
+        let worlds = list_worlds_in(dir.path(), "summer").unwrap();
+        assert_eq!(
+            worlds,
+            vec![
+                AvailableWorld {
+                    level_name: "summer".to_string(),
+                    active: true,
+                },
+                AvailableWorld {
+                    level_name: "winter".to_string(),
+                    active: false,
+                },
+                AvailableWorld {
+                    level_name: "world".to_string(),
+                    active: false,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! Debug logging scoped to a single instance, so that a problem instance can be investigated
+//! without restarting the core with a more verbose `RUST_LOG`
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::subscriber::Interest;
+use tracing::{debug, Level, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Filter};
+use tracing_subscriber::registry::LookupSpan;
+use ts_rs::TS;
+
+use crate::event_broadcaster::EventBroadcaster;
+use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::types::{InstanceUuid, Snowflake};
+
+/// Debug logging turns itself off after this long, so that a forgotten toggle doesn't spam the logs
+const DEBUG_TIMEOUT_SECS: i64 = 30 * 60;
+
+/// Instances with debug logging enabled, with when it expires as a unix timestamp
+static DEBUGGED_INSTANCES: Lazy<Mutex<HashMap<InstanceUuid, i64>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct InstanceDebugStatus {
+    pub enabled: bool,
+    /// Unix timestamp in seconds at which debug logging turns itself off
+    pub expires_at: Option<i64>,
+}
+
+fn lock() -> std::sync::MutexGuard<'static, HashMap<InstanceUuid, i64>> {
+    DEBUGGED_INSTANCES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Turns debug logging of an instance on for [`DEBUG_TIMEOUT_SECS`], or off
+pub fn set_debug(uuid: &InstanceUuid, enabled: bool) -> InstanceDebugStatus {
+    if enabled {
+        lock().insert(
+            uuid.clone(),
+            chrono::Utc::now().timestamp() + DEBUG_TIMEOUT_SECS,
+        );
+    } else {
+        lock().remove(uuid);
+    }
+    debug_status(uuid)
+}
+
+pub fn debug_status(uuid: &InstanceUuid) -> InstanceDebugStatus {
+    let mut debugged_instances = lock();
+    match debugged_instances.get(uuid) {
+        Some(expires_at) if *expires_at > chrono::Utc::now().timestamp() => InstanceDebugStatus {
+            enabled: true,
+            expires_at: Some(*expires_at),
+        },
+        Some(_) => {
+            debugged_instances.remove(uuid);
+            InstanceDebugStatus {
+                enabled: false,
+                expires_at: None,
+            }
+        }
+        None => InstanceDebugStatus {
+            enabled: false,
+            expires_at: None,
+        },
+    }
+}
+
+pub fn is_debug_enabled(uuid: &InstanceUuid) -> bool {
+    debug_status(uuid).enabled
+}
+
+/// A span scoping tracing events to an instance, whose debug and trace events are logged to stdout while debugging is enabled
+pub fn instance_span(uuid: &InstanceUuid) -> tracing::Span {
+    tracing::info_span!("instance", instance_uuid = %uuid)
+}
+
+/// Records detail that is only worth the noise while debugging an instance, to the logs and the event log
+pub fn debug_detail(
+    event_broadcaster: &EventBroadcaster,
+    uuid: &InstanceUuid,
+    instance_name: &str,
+    message: impl FnOnce() -> String,
+) {
+    if !is_debug_enabled(uuid) {
+        return;
+    }
+    let message = message();
+    debug!("[{}] {}", instance_name, message);
+    event_broadcaster.send(Event {
+        event_inner: EventInner::InstanceEvent(InstanceEvent {
+            instance_uuid: uuid.clone(),
+            instance_name: instance_name.to_string(),
+            instance_event_inner: InstanceEventInner::InstanceDebug { message },
+        }),
+        snowflake: Snowflake::default(),
+        details: "".to_string(),
+        caused_by: CausedBy::System,
+    });
+}
+
+/// The instance a span was created for by [`instance_span`]
+struct InstanceSpan(InstanceUuid);
+
+#[derive(Default)]
+struct InstanceUuidVisitor(Option<String>);
+
+impl Visit for InstanceUuidVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "instance_uuid" {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+/// Lets debug and trace events through if they happen in the span of an instance with debug logging enabled.
+///
+/// Meant to be combined with the filter of a layer that otherwise drops them
+pub struct InstanceDebugFilter;
+
+impl<S> Filter<S> for InstanceDebugFilter
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn enabled(&self, metadata: &Metadata<'_>, cx: &Context<'_, S>) -> bool {
+        if !metadata.is_event() || *metadata.level() < Level::DEBUG {
+            return false;
+        }
+        cx.lookup_current()
+            .map(|span| {
+                span.scope().any(|span| {
+                    span.extensions()
+                        .get::<InstanceSpan>()
+                        .map(|instance| is_debug_enabled(&instance.0))
+                        .unwrap_or(false)
+                })
+            })
+            .unwrap_or(false)
+    }
+
+    fn callsite_enabled(&self, metadata: &'static Metadata<'static>) -> Interest {
+        // whether a debug event is let through changes as debugging is toggled
+        if metadata.is_event() && *metadata.level() >= Level::DEBUG {
+            Interest::sometimes()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = InstanceUuidVisitor::default();
+        attrs.record(&mut visitor);
+        if let (Some(uuid), Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut()
+                .insert(InstanceSpan(InstanceUuid::from(uuid)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_debug() {
+        let uuid = InstanceUuid::from("test_set_debug".to_string());
+        assert!(!is_debug_enabled(&uuid));
+
+        let status = set_debug(&uuid, true);
+        assert!(status.enabled);
+        assert!(status.expires_at.unwrap() > chrono::Utc::now().timestamp());
+        assert!(is_debug_enabled(&uuid));
+
+        assert!(!set_debug(&uuid, false).enabled);
+        assert!(!is_debug_enabled(&uuid));
+
+        // expired debugging turns itself off
+        lock().insert(uuid.clone(), chrono::Utc::now().timestamp() - 1);
+        assert_eq!(
+            debug_status(&uuid),
+            InstanceDebugStatus {
+                enabled: false,
+                expires_at: None,
+            }
+        );
+        assert!(!lock().contains_key(&uuid));
+    }
+}
@@ -1,6 +1,8 @@
 #![allow(clippy::comparison_chain, clippy::type_complexity)]
 
-use crate::event_broadcaster::EventBroadcaster;
+use crate::event_broadcaster::{
+    parse_event_channel_capacity, EventBroadcaster, DEFAULT_EVENT_CHANNEL_CAPACITY,
+};
 use crate::migration::migrate;
 use crate::prelude::{
     init_paths, lodestone_path, path_to_global_settings, path_to_stores, path_to_users, VERSION,
@@ -8,23 +10,29 @@ use crate::prelude::{
 use crate::traits::t_configurable::GameType;
 use crate::traits::t_server::State;
 use crate::{
-    db::write::write_event_to_db_task,
+    db::{
+        audit::{init_audit_log_table, write_audit_log_task},
+        write::write_event_to_db_task,
+    },
     global_settings::GlobalSettingsData,
     handlers::{
-        checks::get_checks_routes, core_info::get_core_info_routes, events::get_events_routes,
-        gateway::get_gateway_routes, global_fs::get_global_fs_routes,
-        global_settings::get_global_settings_routes, instance::*,
+        audit::get_audit_routes, checks::get_checks_routes, core_info::get_core_info_routes,
+        events::get_events_routes, gateway::get_gateway_routes, global_fs::get_global_fs_routes,
+        global_settings::get_global_settings_routes, health::get_health_routes, instance::*,
+        instance_backup::get_instance_backup_routes, instance_bundle::get_instance_bundle_routes,
         instance_config::get_instance_config_routes, instance_fs::get_instance_fs_routes,
-        instance_macro::get_instance_macro_routes, instance_players::get_instance_players_routes,
-        instance_server::get_instance_server_routes,
+        instance_generic::get_instance_generic_routes, instance_macro::get_instance_macro_routes,
+        instance_players::get_instance_players_routes, instance_server::get_instance_server_routes,
         instance_setup_configs::get_instance_setup_config_routes, monitor::get_monitor_routes,
+        progress::get_progress_routes, remote_core::get_remote_core_routes, roles::get_role_routes,
         setup::get_setup_route, system::get_system_routes, users::get_user_routes,
     },
     util::rand_alphanumeric,
 };
 
-use auth::user::UsersManager;
+use auth::{rate_limiter::LoginRateLimiter, user::UsersManager};
 use axum::Router;
+use console_buffer::ConsoleBuffer;
 
 use axum_server::tls_rustls::RustlsConfig;
 use clap::Parser;
@@ -33,11 +41,16 @@ use color_eyre::Report;
 use error::Error;
 use events::{CausedBy, Event};
 use futures::Future;
-use global_settings::GlobalSettings;
+use global_settings::{GlobalSettings, ShutdownBehaviour};
+use implementations::minecraft::setup::{SetupCheckpoint, SETUP_CHECKPOINT_FILE};
 use implementations::{generic, minecraft};
 use macro_executor::MacroExecutor;
+use notification::webhook_notification_task;
+use pending_instances::PendingInstances;
 use port_manager::PortManager;
 use prelude::GameInstance;
+use progress_registry::ProgressRegistry;
+use remote_core::RemoteCores;
 use reqwest::{header, Method};
 use ringbuffer::{AllocRingBuffer, RingBufferWrite};
 
@@ -61,36 +74,55 @@ use tower_http::{
     trace::TraceLayer,
 };
 use tracing::{debug, error, info, warn};
+use tracing_subscriber::filter::FilterExt;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, EnvFilter};
 use traits::{t_configurable::TConfigurable, t_server::MonitorReport, t_server::TServer};
-use types::{DotLodestoneConfig, InstanceUuid};
+use types::{DotLodestoneConfig, InstanceUuid, Snowflake};
 use uuid::Uuid;
 pub mod auth;
+mod backup_history;
+mod command_history;
+mod console_buffer;
 pub mod db;
 mod deno_ops;
+mod disk_quota;
 pub mod error;
 mod event_broadcaster;
 mod events;
 pub mod global_settings;
 mod handlers;
 pub mod implementations;
+mod instance_debug;
 pub mod macro_executor;
 mod migration;
+mod notification;
 mod output_types;
+mod pending_instances;
 mod port_manager;
 pub mod prelude;
+mod process_scheduling;
+mod progress_registry;
+mod public_url;
+mod remote_core;
+mod s3;
+mod start_limiter;
 pub mod tauri_export;
 mod traits;
 pub mod types;
+mod upstream_cache;
+mod upstream_http;
 pub mod util;
 
 #[derive(Clone)]
 pub struct AppState {
     instances: Arc<Mutex<HashMap<InstanceUuid, GameInstance>>>,
+    pending_instances: PendingInstances,
+    progress_registry: ProgressRegistry,
     users_manager: Arc<RwLock<UsersManager>>,
+    login_rate_limiter: Arc<Mutex<LoginRateLimiter>>,
     events_buffer: Arc<Mutex<AllocRingBuffer<Event>>>,
-    console_out_buffer: Arc<Mutex<HashMap<InstanceUuid, AllocRingBuffer<Event>>>>,
+    console_out_buffer: Arc<Mutex<HashMap<InstanceUuid, ConsoleBuffer>>>,
     monitor_buffer: Arc<Mutex<HashMap<InstanceUuid, AllocRingBuffer<MonitorReport>>>>,
     event_broadcaster: EventBroadcaster,
     uuid: String,
@@ -102,6 +134,21 @@ pub struct AppState {
     download_urls: Arc<Mutex<HashMap<String, PathBuf>>>,
     macro_executor: MacroExecutor,
     sqlite_pool: sqlx::SqlitePool,
+    remote_cores: RemoteCores,
+}
+
+impl AppState {
+    /// Adds a newly created instance, with a console buffer sized from its config
+    async fn insert_instance(&self, uuid: InstanceUuid, instance: GameInstance) {
+        let capacity = instance.console_buffer_lines().await;
+        self.console_out_buffer
+            .lock()
+            .await
+            .entry(uuid.clone())
+            .and_modify(|buffer| buffer.set_capacity(capacity))
+            .or_insert_with(|| ConsoleBuffer::new(capacity));
+        self.instances.lock().await.insert(uuid, instance);
+    }
 }
 async fn restore_instances(
     instances_path: &Path,
@@ -138,6 +185,13 @@ async fn restore_instances(
             }
         };
         debug!("restoring instance: {}", path.display());
+        if path.join(SETUP_CHECKPOINT_FILE).exists() {
+            debug!(
+                "Setup of {} didn't finish, not restoring it",
+                path.display()
+            );
+            continue;
+        }
         if let GameType::MinecraftJava = dot_lodestone_config.game_type() {
             let instance = match minecraft::MinecraftInstance::restore(
                 path.to_owned(),
@@ -160,6 +214,66 @@ async fn restore_instances(
     Ok(ret)
 }
 
+/// Setups of Minecraft instances that didn't finish, as the core stopped during them or they failed,
+/// so that they can be listed for a retry
+async fn find_failed_setups(
+    instances_path: &Path,
+) -> Vec<(DotLodestoneConfig, PathBuf, SetupCheckpoint)> {
+    let mut ret = Vec::new();
+    let entries = match instances_path.read_dir() {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed to read instances directory : {e}");
+            return ret;
+        }
+    };
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        let checkpoint = match SetupCheckpoint::load(&path).await {
+            Ok(Some(checkpoint)) => checkpoint,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("Error while finding failed setup {} : {e}", path.display());
+                continue;
+            }
+        };
+        let dot_lodestone_config: DotLodestoneConfig =
+            match std::fs::read_to_string(path.join(".lodestone_config"))
+                .context("Failed to read .lodestone_config file")
+                .and_then(|content| {
+                    serde_json::from_str(&content).context("Failed to parse .lodestone_config file")
+                }) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("Error while finding failed setup {} : {e}", path.display());
+                    continue;
+                }
+            };
+        ret.push((dot_lodestone_config, path, checkpoint));
+    }
+    ret
+}
+
+/// How long to wait for instances to stop when the core shuts down
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Resolves on Ctrl+C, or SIGTERM on unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler");
+        select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = sigterm.recv() => {},
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
 fn setup_tracing() -> tracing_appender::non_blocking::WorkerGuard {
     let file_appender =
         tracing_appender::rolling::hourly(lodestone_path().join("log"), "lodestone_core.log");
@@ -180,7 +294,10 @@ fn setup_tracing() -> tracing_appender::non_blocking::WorkerGuard {
             .with_thread_ids(false)
             // Don't display the event's target (module path)
             .with_target(true)
-            .with_writer(std::io::stdout);
+            .with_writer(std::io::stdout)
+            .with_filter(
+                EnvFilter::from("lodestone_core=debug").or(instance_debug::InstanceDebugFilter),
+            );
         let fmt_layer_file = tracing_subscriber::fmt::layer()
             // Use a more compact, abbreviated log format
             .compact()
@@ -193,12 +310,12 @@ fn setup_tracing() -> tracing_appender::non_blocking::WorkerGuard {
             // Don't display the event's target (module path)
             .with_target(true)
             .with_ansi(false)
-            .with_writer(non_blocking);
+            .with_writer(non_blocking)
+            .with_filter(EnvFilter::from("lodestone_core=debug"));
 
         tracing_subscriber::registry()
             .with(fmt_layer_stdout)
             .with(fmt_layer_file)
-            .with(EnvFilter::from("lodestone_core=debug"))
             .init();
     }
 
@@ -216,7 +333,9 @@ fn setup_tracing() -> tracing_appender::non_blocking::WorkerGuard {
             // Don't display the event's target (module path)
             .with_target(false)
             .with_writer(std::io::stdout)
-            .with_filter(EnvFilter::from("lodestone_core=info"));
+            .with_filter(
+                EnvFilter::from("lodestone_core=info").or(instance_debug::InstanceDebugFilter),
+            );
 
         let fmt_layer_file = tracing_subscriber::fmt::layer()
             // Use a more compact, abbreviated log format
@@ -272,9 +391,7 @@ async fn check_for_core_update() {
     pub async fn get_latest_release() -> Result<Version, Report> {
         let release_url =
             "https://api.github.com/repos/Lodestone-Team/lodestone_core/releases/latest";
-        let client = reqwest::Client::new();
-
-        let response = client
+        let response = upstream_http::client()
             .get(release_url)
             .header("User-Agent", "lodestone_cli")
             .send()
@@ -322,6 +439,13 @@ pub struct Args {
     pub is_desktop: bool,
     #[arg(short, long)]
     pub lodestone_path: Option<PathBuf>,
+    /// Where runtimes such as JREs are stored, `bin` under the lodestone path by default.
+    /// May be read-only if it already contains every runtime the instances need
+    #[arg(long)]
+    pub runtimes_path: Option<PathBuf>,
+    /// Number of events a slow subscriber, e.g. a websocket client, can fall behind by before it misses events
+    #[arg(long, default_value_t = DEFAULT_EVENT_CHANNEL_CAPACITY, value_parser = parse_event_channel_capacity)]
+    pub event_channel_capacity: usize,
 }
 
 pub async fn run(
@@ -349,7 +473,12 @@ pub async fn run(
                 .to_string(),
         })
     };
-    init_paths(lodestone_path_);
+    let runtimes_path = args.runtimes_path.or_else(|| {
+        std::env::var("LODESTONE_RUNTIMES_PATH")
+            .ok()
+            .map(PathBuf::from)
+    });
+    init_paths(lodestone_path_, runtimes_path);
     let lodestone_path = lodestone_path();
     info!("Lodestone path: {}", lodestone_path.display());
     std::env::set_current_dir(lodestone_path).unwrap();
@@ -369,7 +498,7 @@ pub async fn run(
     });
     let path_to_instances = lodestone_path.join("instances");
 
-    let (tx, _rx) = EventBroadcaster::new(512);
+    let (tx, _rx) = EventBroadcaster::new(args.event_channel_capacity);
 
     let mut users_manager = UsersManager::new(tx.clone(), HashMap::new(), path_to_users().clone());
 
@@ -382,6 +511,9 @@ pub async fn run(
     );
 
     global_settings.load_from_file().await.unwrap();
+    upstream_cache::set_upstream_cache_ttl(global_settings.upstream_cache_ttl());
+    upstream_http::set_http_timeouts(global_settings.http_timeouts());
+    start_limiter::start_limiter().set_limit(global_settings.max_concurrent_starts());
 
     let first_time_setup_key = if !users_manager.as_ref().iter().any(|(_, user)| user.is_owner) {
         let key = rand_alphanumeric(16);
@@ -422,14 +554,48 @@ pub async fn run(
         }
     }
     let mut allocated_ports = HashSet::new();
-    for (_, instance) in instances.iter() {
-        allocated_ports.insert(instance.port().await);
+    let progress_registry = ProgressRegistry::default();
+    let pending_instances = PendingInstances::new(progress_registry.clone());
+    for (dot_lodestone_config, path, checkpoint) in find_failed_setups(&path_to_instances).await {
+        let uuid = dot_lodestone_config.uuid().to_owned();
+        warn!(
+            "Setup of instance {} didn't finish, it can be retried or deleted",
+            checkpoint.setup_config.name
+        );
+        allocated_ports.extend(checkpoint.ports().all());
+        pending_instances
+            .insert(
+                handlers::instance::minecraft_setup_info(&uuid, &path, &checkpoint.setup_config),
+                checkpoint.created_by,
+                Snowflake::default(),
+            )
+            .await;
+        pending_instances
+            .fail(
+                &uuid,
+                "Setup didn't finish, retry it or delete the instance".to_string(),
+            )
+            .await;
+    }
+    let mut console_out_buffer = HashMap::new();
+    for (uuid, instance) in instances.iter() {
+        allocated_ports.extend(instance.ports().await.all());
+        let mut buffer = ConsoleBuffer::new(instance.console_buffer_lines().await);
+        for event in instance.console_history().await {
+            buffer.push(event);
+        }
+        console_out_buffer.insert(uuid.clone(), buffer);
     }
     let shared_state = AppState {
         instances: Arc::new(Mutex::new(instances)),
+        pending_instances,
+        progress_registry,
         users_manager: Arc::new(RwLock::new(users_manager)),
+        login_rate_limiter: Arc::new(Mutex::new(LoginRateLimiter::new(
+            global_settings.login_rate_limit(),
+        ))),
         events_buffer: Arc::new(Mutex::new(AllocRingBuffer::with_capacity(512))),
-        console_out_buffer: Arc::new(Mutex::new(HashMap::new())),
+        console_out_buffer: Arc::new(Mutex::new(console_out_buffer)),
         monitor_buffer: Arc::new(Mutex::new(HashMap::new())),
         event_broadcaster: tx.clone(),
         uuid: Uuid::new_v4().to_string(),
@@ -450,19 +616,23 @@ pub async fn run(
         )
         .await
         .unwrap(),
+        remote_cores: RemoteCores::load(path_to_stores().join("remote_cores.json"))
+            .await
+            .unwrap(),
     };
 
     let event_buffer_task = {
         let event_buffer = shared_state.events_buffer.clone();
         let console_out_buffer = shared_state.console_out_buffer.clone();
+        let event_broadcaster = tx.clone();
         let mut event_receiver = tx.subscribe();
         async move {
             loop {
                 let result = event_receiver.recv().await;
                 if let Err(error) = result.as_ref() {
                     match error {
-                        RecvError::Lagged(_) => {
-                            warn!("Event buffer lagged");
+                        RecvError::Lagged(missed) => {
+                            event_broadcaster.record_lag("Event buffer", *missed);
                             continue;
                         }
                         RecvError::Closed => {
@@ -477,7 +647,7 @@ pub async fn run(
                         .lock()
                         .await
                         .entry(event.get_instance_uuid().unwrap())
-                        .or_insert_with(|| AllocRingBuffer::with_capacity(1024))
+                        .or_insert_with(ConsoleBuffer::default)
                         .push(event.clone());
                 } else {
                     event_buffer.lock().await.push(event.clone());
@@ -487,6 +657,29 @@ pub async fn run(
     };
 
     let write_to_db_task = write_event_to_db_task(tx.subscribe(), shared_state.sqlite_pool.clone());
+    // created before the router serves, so that reading the audit log can't race its creation.
+    // not awaited with the other tasks, the core keeps running if the audit log fails
+    match init_audit_log_table(&shared_state.sqlite_pool).await {
+        Ok(()) => {
+            tokio::spawn(write_audit_log_task(
+                tx.subscribe(),
+                shared_state.sqlite_pool.clone(),
+            ));
+        }
+        Err(e) => error!("Failed to initialize audit log table: {}", e),
+    }
+
+    tokio::spawn(webhook_notification_task(
+        tx.subscribe(),
+        shared_state.users_manager.clone(),
+    ));
+
+    tokio::spawn(
+        shared_state
+            .progress_registry
+            .clone()
+            .track_progress(tx.subscribe()),
+    );
 
     let monitor_report_task = {
         let monitor_buffer = shared_state.monitor_buffer.clone();
@@ -508,6 +701,46 @@ pub async fn run(
         }
     };
 
+    // corrects instances whose state drifted from their process, e.g. after a missed exit, and flags
+    // those whose directory was deleted. The first pass runs at startup
+    tokio::spawn({
+        let instances = shared_state.instances.clone();
+        async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(10));
+            loop {
+                interval.tick().await;
+                for (uuid, instance) in instances.lock().await.iter() {
+                    if let Err(e) = instance.reconcile_state().await {
+                        error!("Failed to reconcile state of instance {}: {}", uuid, e);
+                    }
+                }
+            }
+        }
+    });
+
+    // looks for newer server builds, applying them for instances set to auto update
+    tokio::spawn({
+        let instances = shared_state.instances.clone();
+        async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+            loop {
+                interval.tick().await;
+                // updating may stop and back up the instance, so the map isn't held meanwhile
+                let instances: Vec<(InstanceUuid, GameInstance)> = instances
+                    .lock()
+                    .await
+                    .iter()
+                    .map(|(uuid, instance)| (uuid.clone(), instance.clone()))
+                    .collect();
+                for (uuid, mut instance) in instances {
+                    if let Err(e) = instance.check_for_update().await {
+                        error!("Failed to check for updates of instance {}: {}", uuid, e);
+                    }
+                }
+            }
+        }
+    });
+
     let tls_config_result = RustlsConfig::from_pem_file(
         lodestone_path.join("tls").join("cert.pem"),
         lodestone_path.join("tls").join("key.pem"),
@@ -537,19 +770,27 @@ pub async fn run(
                     .merge(get_instance_setup_config_routes(shared_state.clone()))
                     .merge(get_instance_server_routes(shared_state.clone()))
                     .merge(get_instance_config_routes(shared_state.clone()))
+                    .merge(get_instance_backup_routes(shared_state.clone()))
+                    .merge(get_instance_bundle_routes(shared_state.clone()))
                     .merge(get_instance_players_routes(shared_state.clone()))
                     .merge(get_instance_routes(shared_state.clone()))
                     .merge(get_system_routes(shared_state.clone()))
                     .merge(get_checks_routes(shared_state.clone()))
                     .merge(get_user_routes(shared_state.clone()))
+                    .merge(get_role_routes(shared_state.clone()))
+                    .merge(get_audit_routes(shared_state.clone()))
                     .merge(get_core_info_routes(shared_state.clone()))
                     .merge(get_setup_route(shared_state.clone()))
                     .merge(get_monitor_routes(shared_state.clone()))
                     .merge(get_instance_macro_routes(shared_state.clone()))
                     .merge(get_instance_fs_routes(shared_state.clone()))
+                    .merge(get_instance_generic_routes(shared_state.clone()))
                     .merge(get_global_fs_routes(shared_state.clone()))
                     .merge(get_global_settings_routes(shared_state.clone()))
                     .merge(get_gateway_routes(shared_state.clone()))
+                    .merge(get_health_routes(shared_state.clone()))
+                    .merge(get_remote_core_routes(shared_state.clone()))
+                    .merge(get_progress_routes(shared_state.clone()))
                     .layer(cors)
                     .layer(trace);
                 let app = Router::new().nest("/api/v1", api_routes);
@@ -577,7 +818,7 @@ pub async fn run(
                                 info!("Note that Lodestone Core does not host the web dashboard itself. Please visit https://www.lodestone.cc for setup instructions.");
                                 axum_server::bind_rustls(addr, config)
                                     .handle(axum_server_handle)
-                                    .serve(app.into_make_service())
+                                    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
                                     .await
                             }
                             Err(e) => {
@@ -586,7 +827,7 @@ pub async fn run(
                                 info!("Note that Lodestone Core does not host the web dashboard itself. Please visit https://www.lodestone.cc for setup instructions.");
                                 axum_server::bind(addr)
                                     .handle(axum_server_handle)
-                                    .serve(app.into_make_service())
+                                    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
                                     .await
                             }
                         }
@@ -597,23 +838,46 @@ pub async fn run(
                     _ = write_to_db_task => info!("Write to db task exited"),
                     _ = event_buffer_task => info!("Event buffer task exited"),
                     _ = monitor_report_task => info!("Monitor report task exited"),
-                    _ = tokio::signal::ctrl_c() => info!("Ctrl+C received"),
+                    _ = shutdown_signal() => info!("Shutdown signal received"),
                 }
                 info!("Shutting down web server");
                 axum_server_handle.shutdown();
-                info!("Signalling all instances to stop");
                 // cleanup
+                let shutdown_behaviour = shared_state
+                    .global_settings
+                    .lock()
+                    .await
+                    .shutdown_behaviour();
                 let mut instances = shared_state.instances.lock().await;
-                for (_, instance) in instances.iter_mut() {
-                    if instance.state().await == State::Stopped {
-                        continue;
+                match shutdown_behaviour {
+                    ShutdownBehaviour::Detach => {
+                        info!("Detaching from all instances, running instances are left running");
                     }
-                    if let Err(e) = instance.stop(CausedBy::System, false).await {
-                        error!(
-                            "Failed to stop instance {} : {}. Instance may need manual cleanup",
-                            instance.uuid().await,
-                            e
-                        );
+                    ShutdownBehaviour::Stop => {
+                        info!("Signalling all instances to stop");
+                        for (_, instance) in instances.iter_mut() {
+                            if instance.state().await == State::Stopped {
+                                continue;
+                            }
+                            if let Err(e) = instance.stop(CausedBy::System, false).await {
+                                error!(
+                                    "Failed to stop instance {} : {}. Instance may need manual cleanup",
+                                    instance.uuid().await,
+                                    e
+                                );
+                            }
+                        }
+                        // give the servers a chance to save their worlds before we exit
+                        let deadline = tokio::time::Instant::now() + SHUTDOWN_TIMEOUT;
+                        'wait: for (_, instance) in instances.iter() {
+                            while instance.state().await != State::Stopped {
+                                if tokio::time::Instant::now() >= deadline {
+                                    warn!("Timed out waiting for instances to stop, some instances may need manual cleanup");
+                                    break 'wait;
+                                }
+                                tokio::time::sleep(Duration::from_millis(500)).await;
+                            }
+                        }
                     }
                 }
             }
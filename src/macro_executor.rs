@@ -48,6 +48,10 @@ use futures::FutureExt;
 
 pub trait WorkerOptionGenerator: Send + Sync {
     fn generate(&self) -> deno_runtime::worker::WorkerOptions;
+    /// Bytes of heap the macro may use before it's terminated, unlimited if `None`
+    fn heap_limit(&self) -> Option<usize> {
+        None
+    }
 }
 pub struct TypescriptModuleLoader {
     http: reqwest::Client,
@@ -85,7 +89,7 @@ impl Display for MacroPID {
 impl Default for TypescriptModuleLoader {
     fn default() -> Self {
         Self {
-            http: reqwest::Client::new(),
+            http: crate::upstream_http::client(),
         }
     }
 }
@@ -290,6 +294,11 @@ impl MacroExecutor {
                     let mut worker_option = worker_options_generator.generate();
                     register_all_event_ops(&mut worker_option, event_broadcaster.clone());
                     worker_option.bootstrap.args = args;
+                    let heap_limit = worker_options_generator.heap_limit();
+                    if let Some(heap_limit) = heap_limit {
+                        worker_option.create_params =
+                            Some(deno_core::v8::CreateParams::default().heap_limits(0, heap_limit));
+                    }
 
                     let mut main_worker = deno_runtime::worker::MainWorker::from_options(
                         main_module,
@@ -301,6 +310,22 @@ impl MacroExecutor {
 
                     let isolate_handle = main_worker.js_runtime.v8_isolate().thread_safe_handle();
 
+                    if let Some(heap_limit) = heap_limit {
+                        // without this callback, reaching the limit aborts the whole core
+                        let isolate_handle = isolate_handle.clone();
+                        main_worker.js_runtime.add_near_heap_limit_callback(
+                            move |current_limit, _| {
+                                warn!(
+                                    "Macro {} reached its heap limit of {} bytes, terminating it",
+                                    pid, heap_limit
+                                );
+                                isolate_handle.terminate_execution();
+                                // leave room for the termination to unwind
+                                current_limit * 2
+                            },
+                        );
+                    }
+
                     process_table.insert(pid, isolate_handle);
 
                     let main_module = match deno_core::resolve_path(
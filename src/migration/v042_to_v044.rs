@@ -1,21 +1,30 @@
+use std::collections::HashMap;
 use std::path::Path;
 
 use color_eyre::eyre::Context;
 use serde_json::{json, Value};
 use tracing::error;
 
-use crate::{error::Error, implementations::minecraft::RestoreConfig};
+use crate::{
+    error::Error,
+    implementations::minecraft::{
+        args::split_cmd_args, hooks::LifecycleHooks, launch::LaunchMode, RestoreConfig,
+    },
+    traits::t_configurable::AutoUpdateConfig,
+};
 
 use super::RestoreConfigV042;
 
 impl From<RestoreConfigV042> for RestoreConfig {
     fn from(config: RestoreConfigV042) -> Self {
+        let (jvm_args, program_args) = split_cmd_args(&config.cmd_args);
         Self {
             name: config.name,
             version: config.version,
             flavour: config.flavour,
             description: config.description,
-            cmd_args: config.cmd_args,
+            jvm_args,
+            program_args,
             port: config.port,
             min_ram: config.min_ram,
             max_ram: config.max_ram,
@@ -25,6 +34,35 @@ impl From<RestoreConfigV042> for RestoreConfig {
             jre_major_version: config.jre_major_version,
             has_started: config.has_started,
             java_cmd: None,
+            last_crash: None,
+            last_started: None,
+            oom_max_ram_ceiling: None,
+            console_buffer_lines: None,
+            persist_console: None,
+            env: HashMap::new(),
+            backup_io_limit: None,
+            tags: Vec::new(),
+            jre_vendor: None,
+            jre_version_override: None,
+            auto_update: AutoUpdateConfig::default(),
+            backup_destination: None,
+            s3_backup: None,
+            backup_on_stop: None,
+            backup_on_stop_debounce_secs: None,
+            readiness_probe: None,
+            readiness_timeout_secs: None,
+            process_priority: None,
+            cpu_affinity: None,
+            stop_timeout_secs: None,
+            backup_before_risky_ops: None,
+            hooks: LifecycleHooks::default(),
+            disk_quota_mb: None,
+            launch_mode: LaunchMode::default(),
+            crash_report_upload_url: None,
+            save_before_stop: None,
+            max_backups: None,
+            compress_backups: None,
+            backups_paused: false,
         }
     }
 }
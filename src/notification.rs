@@ -0,0 +1,305 @@
+//! Which events each user is pushed, and through which channel
+
+use std::sync::Arc;
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    RwLock,
+};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UsersManager,
+    error::{Error, ErrorKind},
+    events::{Event, EventInner, EventLevel, EventType, InstanceEventKind},
+    output_types::ClientEvent,
+    public_url::check_public_url,
+    upstream_http,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+#[serde(tag = "type")]
+pub enum NotificationChannel {
+    /// The event streams of the dashboard
+    InApp,
+    /// POSTed as JSON to `url`
+    Webhook { url: String },
+}
+
+/// Events matching every condition set are pushed through `channel`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct NotificationRule {
+    pub channel: NotificationChannel,
+    /// All types if `None`
+    #[serde(default)]
+    pub event_types: Option<Vec<EventType>>,
+    /// All instance events if `None`, no instance events otherwise if set
+    #[serde(default)]
+    pub instance_event_types: Option<Vec<InstanceEventKind>>,
+    #[serde(default)]
+    pub min_level: Option<EventLevel>,
+}
+
+impl NotificationRule {
+    pub fn matches(&self, event: &ClientEvent) -> bool {
+        if let Some(event_types) = &self.event_types {
+            if !event_types.contains(&event.event_inner.as_ref().into()) {
+                return false;
+            }
+        }
+        if let Some(instance_event_types) = &self.instance_event_types {
+            match &event.event_inner {
+                EventInner::InstanceEvent(instance_event) => {
+                    if !instance_event_types
+                        .contains(&instance_event.instance_event_inner.as_ref().into())
+                    {
+                        return false;
+                    }
+                }
+                _ => return false,
+            }
+        }
+        match &self.min_level {
+            Some(min_level) => event.level >= *min_level,
+            None => true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct NotificationPreferences {
+    pub rules: Vec<NotificationRule>,
+}
+
+impl Default for NotificationPreferences {
+    /// Every event in-app, as before preferences existed
+    fn default() -> Self {
+        Self {
+            rules: vec![NotificationRule {
+                channel: NotificationChannel::InApp,
+                event_types: None,
+                instance_event_types: None,
+                min_level: None,
+            }],
+        }
+    }
+}
+
+impl NotificationPreferences {
+    pub fn validate(&self) -> Result<(), Error> {
+        for rule in &self.rules {
+            if let NotificationChannel::Webhook { url } = &rule.channel {
+                let parsed = url::Url::parse(url).map_err(|e| Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Invalid webhook URL {}: {}", url, e),
+                })?;
+                if !matches!(parsed.scheme(), "http" | "https") {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Webhook URL {} must be http or https", url),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects webhooks on this host or a private network, see [`check_public_url`]
+    pub async fn check_webhook_addresses(&self) -> Result<(), Error> {
+        for rule in &self.rules {
+            if let NotificationChannel::Webhook { url } = &rule.channel {
+                check_public_url(url).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `event` is pushed to the user's event streams
+    pub fn in_app(&self, event: &ClientEvent) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.channel == NotificationChannel::InApp && rule.matches(event))
+    }
+
+    /// The webhooks `event` is sent to, each once even if several rules match
+    pub fn webhooks(&self, event: &ClientEvent) -> Vec<String> {
+        let mut urls: Vec<String> = Vec::new();
+        for rule in &self.rules {
+            if let NotificationChannel::Webhook { url } = &rule.channel {
+                if !urls.contains(url) && rule.matches(event) {
+                    urls.push(url.clone());
+                }
+            }
+        }
+        urls
+    }
+}
+
+/// Sends events to the webhooks of the users who can view them and asked for them
+pub async fn webhook_notification_task(
+    mut event_receiver: Receiver<Event>,
+    users_manager: Arc<RwLock<UsersManager>>,
+) {
+    loop {
+        let event = match event_receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                warn!("Webhook notifications lagged behind and missed {missed} events");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        if event.is_event_console_message() {
+            continue;
+        }
+        let client_event = ClientEvent::from(&event);
+        // the owner may send to webhooks on the local network, others only to public ones
+        let mut urls: Vec<(String, bool)> = Vec::new();
+        for user in users_manager.read().await.as_ref().values() {
+            if user.can_view_event(&event) {
+                for url in user.notification_preferences.webhooks(&client_event) {
+                    match urls.iter_mut().find(|(other, _)| *other == url) {
+                        Some((_, allow_internal)) => *allow_internal |= user.is_owner,
+                        None => urls.push((url, user.is_owner)),
+                    }
+                }
+            }
+        }
+        for (url, allow_internal) in urls {
+            let client_event = client_event.clone();
+            // a slow webhook doesn't hold up the others
+            tokio::spawn(async move {
+                // resolved again for every event, the host may have moved since it was set
+                if !allow_internal {
+                    if let Err(e) = check_public_url(&url).await {
+                        warn!("Not sending notification to webhook {}: {}", url, e.source);
+                        return;
+                    }
+                }
+                let request = upstream_http::client_without_redirects()
+                    .post(&url)
+                    .json(&client_event);
+                if let Err(e) = upstream_http::send(request, &url).await {
+                    warn!(
+                        "Failed to send notification to webhook {}: {}",
+                        url, e.source
+                    );
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{CausedBy, InstanceEvent, InstanceEventInner, ProgressionEventID};
+    use crate::traits::t_server::State;
+    use crate::types::{InstanceUuid, Snowflake};
+
+    /// A bit of everything the core sends while running an instance
+    fn firehose() -> Vec<ClientEvent> {
+        let uuid = InstanceUuid::from("instance-uuid".to_string());
+        let (progression_start, event_id): (Event, ProgressionEventID) =
+            Event::new_progression_event_start("Backing up", Some(10.0), None, CausedBy::System);
+        vec![
+            Event::new_instance_state_transition(uuid.clone(), "test".to_string(), State::Running),
+            progression_start,
+            Event::new_progression_event_update(&event_id, "Copying", 5.0),
+            Event::new_progression_event_end(event_id, true, None::<&str>, None),
+            Event {
+                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                    instance_uuid: uuid,
+                    instance_name: "test".to_string(),
+                    instance_event_inner: InstanceEventInner::InstanceError {
+                        message: "The server crashed".to_string(),
+                    },
+                }),
+                details: "".to_string(),
+                snowflake: Snowflake::default(),
+                caused_by: CausedBy::System,
+            },
+        ]
+        .iter()
+        .map(ClientEvent::from)
+        .collect()
+    }
+
+    fn rule(channel: NotificationChannel) -> NotificationRule {
+        NotificationRule {
+            channel,
+            event_types: None,
+            instance_event_types: None,
+            min_level: None,
+        }
+    }
+
+    #[test]
+    fn test_default_preferences_push_everything_in_app() {
+        let preferences = NotificationPreferences::default();
+        for event in firehose() {
+            assert!(preferences.in_app(&event));
+            assert!(preferences.webhooks(&event).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_filter_firehose() {
+        let webhook = NotificationChannel::Webhook {
+            url: "https://example.com/hook".to_string(),
+        };
+        // everything but progression updates in-app, crashes to a webhook
+        let preferences = NotificationPreferences {
+            rules: vec![
+                NotificationRule {
+                    event_types: Some(vec![EventType::InstanceEvent]),
+                    ..rule(NotificationChannel::InApp)
+                },
+                NotificationRule {
+                    min_level: Some(EventLevel::Error),
+                    ..rule(webhook.clone())
+                },
+                NotificationRule {
+                    instance_event_types: Some(vec![InstanceEventKind::InstanceError]),
+                    ..rule(webhook)
+                },
+            ],
+        };
+        preferences.validate().unwrap();
+        let events = firehose();
+        let in_app: Vec<bool> = events.iter().map(|e| preferences.in_app(e)).collect();
+        assert_eq!(in_app, vec![true, false, false, false, true]);
+        let webhooks: Vec<usize> = events
+            .iter()
+            .map(|e| preferences.webhooks(e).len())
+            .collect();
+        assert_eq!(webhooks, vec![0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_no_in_app_rule_mutes_streams() {
+        let preferences = NotificationPreferences { rules: Vec::new() };
+        assert!(firehose().iter().all(|e| !preferences.in_app(e)));
+    }
+
+    #[test]
+    fn test_validate_webhook_url() {
+        for url in ["not a url", "ftp://example.com/hook"] {
+            let preferences = NotificationPreferences {
+                rules: vec![rule(NotificationChannel::Webhook {
+                    url: url.to_string(),
+                })],
+            };
+            assert!(matches!(
+                preferences.validate().unwrap_err().kind,
+                ErrorKind::BadRequest
+            ));
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use crate::auth::user_id::UserId;
+use crate::progress_registry::ProgressRegistry;
+use crate::traits::t_server::State;
+use crate::traits::{InstanceInfo, InstanceSetupProgress};
+use crate::types::{InstanceUuid, Snowflake};
+
+struct PendingInstance {
+    info: InstanceInfo,
+    /// `None` for setups found on disk at startup, whose creator isn't known
+    created_by: Option<UserId>,
+    event_id: Snowflake,
+    /// Shown while the setup isn't in the progress registry, i.e. before it starts or after it
+    /// failed
+    message: String,
+}
+
+/// Instances that are still being set up, so they can be listed before they exist.
+///
+/// Their progress is that of their setup in the progress registry. A failed setup stays listed
+/// with the error state until it is retried or deleted
+#[derive(Clone)]
+pub struct PendingInstances {
+    pending: Arc<Mutex<HashMap<InstanceUuid, PendingInstance>>>,
+    progress_registry: ProgressRegistry,
+}
+
+impl PendingInstances {
+    pub fn new(progress_registry: ProgressRegistry) -> Self {
+        Self {
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            progress_registry,
+        }
+    }
+
+    /// Tracks an instance whose setup reports its progress under `event_id`
+    pub async fn insert(
+        &self,
+        info: InstanceInfo,
+        created_by: Option<UserId>,
+        event_id: Snowflake,
+    ) {
+        self.pending.lock().await.insert(
+            info.uuid.clone(),
+            PendingInstance {
+                info,
+                created_by,
+                event_id,
+                message: "Waiting to start".to_string(),
+            },
+        );
+    }
+
+    pub async fn remove(&self, uuid: &InstanceUuid) {
+        self.pending.lock().await.remove(uuid);
+    }
+
+    /// Marks the setup of an instance as failed, keeping it listed until it is retried or deleted
+    pub async fn fail(&self, uuid: &InstanceUuid, message: String) {
+        if let Some(pending) = self.pending.lock().await.get_mut(uuid) {
+            pending.info.state = State::Error;
+            pending.message = message;
+        }
+    }
+
+    /// Info of an instance whose setup failed
+    pub async fn failed(&self, uuid: &InstanceUuid) -> Option<InstanceInfo> {
+        self.pending
+            .lock()
+            .await
+            .get(uuid)
+            .filter(|pending| pending.info.state == State::Error)
+            .map(|pending| pending.info.clone())
+    }
+
+    /// Stops tracking an instance whose setup failed, returning its info
+    pub async fn remove_failed(&self, uuid: &InstanceUuid) -> Option<InstanceInfo> {
+        let mut pending = self.pending.lock().await;
+        if pending.get(uuid)?.info.state != State::Error {
+            return None;
+        }
+        pending.remove(uuid).map(|pending| pending.info)
+    }
+
+    /// Puts a failed setup back to setting up, now reporting its progress under `event_id`.
+    /// Returns false if the setup didn't fail, e.g. it is already being retried
+    pub async fn retry(&self, uuid: &InstanceUuid, event_id: Snowflake) -> bool {
+        match self.pending.lock().await.get_mut(uuid) {
+            Some(pending) if pending.info.state == State::Error => {
+                pending.info.state = State::SettingUp;
+                pending.event_id = event_id;
+                pending.message = "Waiting to start".to_string();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Info of the pending instances `filter` accepts, given the instance and its creator
+    pub async fn list(
+        &self,
+        filter: impl Fn(&InstanceUuid, Option<&UserId>) -> bool,
+    ) -> Vec<InstanceInfo> {
+        let pending = self.pending.lock().await;
+        let mut infos = Vec::new();
+        for pending in pending
+            .values()
+            .filter(|pending| filter(&pending.info.uuid, pending.created_by.as_ref()))
+        {
+            let operation = match pending.info.state {
+                State::Error => None,
+                _ => self.progress_registry.get(&pending.event_id).await,
+            };
+            let setup_progress = match operation {
+                Some(operation) => InstanceSetupProgress {
+                    progress: match operation.total {
+                        Some(total) if total > 0.0 => (operation.progress / total).clamp(0.0, 1.0),
+                        _ => 0.0,
+                    },
+                    message: operation.message.unwrap_or_else(|| pending.message.clone()),
+                },
+                None => InstanceSetupProgress {
+                    progress: 0.0,
+                    message: pending.message.clone(),
+                },
+            };
+            infos.push(InstanceInfo {
+                setup_progress: Some(setup_progress),
+                ..pending.info.clone()
+            });
+        }
+        infos
+    }
+}
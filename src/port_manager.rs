@@ -2,13 +2,88 @@ use std::{collections::HashSet, net::SocketAddrV4};
 
 use color_eyre::eyre::{eyre, Context};
 use serde::{Deserialize, Serialize};
+use ts_rs::TS;
 
-use crate::error::Error;
+use crate::error::{Error, ErrorKind};
+
+/// Where allocating the RCON port of a new instance starts, the port Minecraft defaults to
+pub const DEFAULT_RCON_PORT: u32 = 25575;
 
 pub struct PortManager {
     allocated_ports: HashSet<u32>,
 }
 
+/// Every port an instance listens on
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct InstancePorts {
+    pub game: u32,
+    pub rcon: Option<u32>,
+    pub query: Option<u32>,
+}
+
+impl InstancePorts {
+    pub fn all(&self) -> Vec<u32> {
+        std::iter::once(self.game)
+            .chain(self.rcon)
+            .chain(self.query)
+            .collect()
+    }
+
+    /// Rejects ports of the instance that are the same as one another
+    pub fn check_distinct(&self) -> Result<(), Error> {
+        let named = [
+            ("game", Some(self.game)),
+            ("RCON", self.rcon),
+            ("query", self.query),
+        ];
+        for (i, (name, port)) in named.iter().enumerate() {
+            for (other_name, other_port) in &named[i + 1..] {
+                if port.is_some() && port == other_port {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!(
+                            "The {} port and the {} port are both {}",
+                            name,
+                            other_name,
+                            port.unwrap_or_default()
+                        ),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The ports with the server property `key` set to `value`, if it's one of the port properties
+    pub fn with_property(mut self, key: &str, value: &str) -> InstancePorts {
+        let port = value.trim().parse().ok();
+        match key {
+            "server-port" => self.game = port.unwrap_or(self.game),
+            "rcon.port" => self.rcon = port,
+            "query.port" => self.query = port,
+            _ => {}
+        }
+        self
+    }
+
+    /// The ports once the server properties are replaced with `properties`, the game port stays
+    /// as it is if they don't set it
+    pub fn with_properties<'a>(
+        &self,
+        properties: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> InstancePorts {
+        properties.into_iter().fold(
+            InstancePorts {
+                game: self.game,
+                rcon: None,
+                query: None,
+            },
+            |ports, (key, value)| ports.with_property(key, value),
+        )
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub struct PortStatus {
     pub is_in_use: bool,
@@ -43,12 +118,85 @@ impl PortManager {
         }
     }
 
-    pub fn add_port(&mut self, port: u32) {
-        self.allocated_ports.insert(port);
+    /// Rejects `ports` if they collide with one another or with ports allocated to other instances
+    pub fn check_available(&self, ports: &InstancePorts) -> Result<(), Error> {
+        self.check_collisions(ports, &[])
+    }
+
+    /// Rejects moving an instance from its `old` ports to `new` ones if they collide with one
+    /// another or with ports allocated to other instances
+    pub fn check_reallocation(
+        &self,
+        old: &InstancePorts,
+        new: &InstancePorts,
+    ) -> Result<(), Error> {
+        self.check_collisions(new, &old.all())
+    }
+
+    /// Rejects `ports` if they collide with one another or with allocated ports other than `own`
+    fn check_collisions(&self, ports: &InstancePorts, own: &[u32]) -> Result<(), Error> {
+        ports.check_distinct()?;
+        match ports
+            .all()
+            .into_iter()
+            .find(|port| !own.contains(port) && self.allocated_ports.contains(port))
+        {
+            Some(port) => Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Port {} is already used by another instance", port),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Allocates the game port of a new instance, along with an RCON port from `rcon_start` and a query port after the game port
+    pub fn allocate_instance_ports(
+        &mut self,
+        game: u32,
+        rcon_start: u32,
+    ) -> Result<InstancePorts, Error> {
+        self.check_available(&InstancePorts {
+            game,
+            rcon: None,
+            query: None,
+        })?;
+        self.allocated_ports.insert(game);
+        let rcon = self.allocate(rcon_start);
+        let query = self.allocate(game);
+        Ok(InstancePorts {
+            game,
+            rcon: Some(rcon),
+            query: Some(query),
+        })
     }
 
-    pub fn deallocate(&mut self, port: u32) {
-        self.allocated_ports.remove(&port);
+    /// Allocates the ports of an instance moved from another host, moving the game port up from
+    /// `game_start` if it's taken here
+    pub fn allocate_free_instance_ports(
+        &mut self,
+        game_start: u32,
+        rcon_start: u32,
+    ) -> InstancePorts {
+        let game = self.allocate(game_start);
+        let rcon = self.allocate(rcon_start);
+        let query = self.allocate(game);
+        InstancePorts {
+            game,
+            rcon: Some(rcon),
+            query: Some(query),
+        }
+    }
+
+    pub fn deallocate_ports(&mut self, ports: &InstancePorts) {
+        for port in ports.all() {
+            self.allocated_ports.remove(&port);
+        }
+    }
+
+    /// Moves the allocation of an instance from its `old` ports to `new` ones
+    pub fn reallocate_ports(&mut self, old: &InstancePorts, new: &InstancePorts) {
+        self.deallocate_ports(old);
+        self.allocated_ports.extend(new.all());
     }
 
     pub async fn open_port(&self, port: u16) -> Result<(), Error> {
@@ -80,3 +228,125 @@ impl PortManager {
         .unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocate_instance_ports() {
+        let mut port_manager = PortManager::new(HashSet::new());
+        let first = port_manager
+            .allocate_instance_ports(25565, DEFAULT_RCON_PORT)
+            .unwrap();
+        assert_eq!(first.game, 25565);
+        assert_eq!(first.rcon, Some(DEFAULT_RCON_PORT));
+        assert!(first.check_distinct().is_ok());
+
+        // the game port of another instance can't be taken again
+        assert!(port_manager
+            .allocate_instance_ports(25565, DEFAULT_RCON_PORT)
+            .is_err());
+        assert!(port_manager
+            .allocate_instance_ports(first.query.unwrap(), DEFAULT_RCON_PORT)
+            .is_err());
+
+        let second = port_manager
+            .allocate_instance_ports(25600, DEFAULT_RCON_PORT)
+            .unwrap();
+        let mut all = first.all();
+        all.extend(second.all());
+        let distinct: HashSet<u32> = all.iter().copied().collect();
+        assert_eq!(distinct.len(), 6);
+
+        port_manager.deallocate_ports(&first);
+        for port in first.all() {
+            assert!(!port_manager.port_status(port).is_allocated);
+        }
+        for port in second.all() {
+            assert!(port_manager.port_status(port).is_allocated);
+        }
+        assert!(port_manager
+            .allocate_instance_ports(25565, DEFAULT_RCON_PORT)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_reallocate_ports() {
+        let mut port_manager = PortManager::new(HashSet::new());
+        let first = port_manager
+            .allocate_instance_ports(25565, DEFAULT_RCON_PORT)
+            .unwrap();
+        let second = port_manager
+            .allocate_instance_ports(25600, DEFAULT_RCON_PORT)
+            .unwrap();
+
+        // onto a port of the other instance
+        let taken = first.with_property("rcon.port", &second.game.to_string());
+        assert!(port_manager.check_reallocation(&first, &taken).is_err());
+        // onto another port of its own
+        let own = first.with_property("query.port", &first.game.to_string());
+        assert!(port_manager.check_reallocation(&first, &own).is_err());
+        // swapping its own ports around is fine
+        let swapped = InstancePorts {
+            game: first.rcon.unwrap(),
+            rcon: Some(first.game),
+            query: first.query,
+        };
+        assert!(port_manager.check_reallocation(&first, &swapped).is_ok());
+
+        let moved = first.with_property("server-port", "26000");
+        assert!(port_manager.check_reallocation(&first, &moved).is_ok());
+        port_manager.reallocate_ports(&first, &moved);
+        assert!(!port_manager.port_status(25565).is_allocated);
+        assert!(port_manager.port_status(26000).is_allocated);
+        for port in second.all() {
+            assert!(port_manager.port_status(port).is_allocated);
+        }
+    }
+
+    #[test]
+    fn test_with_properties() {
+        let ports = InstancePorts {
+            game: 25565,
+            rcon: Some(25575),
+            query: Some(25566),
+        };
+        assert_eq!(ports.with_property("motd", "25000"), ports);
+        assert_eq!(ports.with_property("rcon.port", "25580").rcon, Some(25580));
+        // a port property that isn't a port doesn't take the game port away
+        assert_eq!(ports.with_property("server-port", "abc").game, 25565);
+
+        let replaced = ports.with_properties([("server-port", "25570"), ("motd", "hi")]);
+        assert_eq!(
+            replaced,
+            InstancePorts {
+                game: 25570,
+                rcon: None,
+                query: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_check_distinct() {
+        let ports = InstancePorts {
+            game: 25565,
+            rcon: Some(25565),
+            query: None,
+        };
+        assert!(ports.check_distinct().is_err());
+        let ports = InstancePorts {
+            game: 25565,
+            rcon: None,
+            query: None,
+        };
+        assert!(ports.check_distinct().is_ok());
+        let ports = InstancePorts {
+            game: 25565,
+            rcon: Some(25575),
+            query: Some(25575),
+        };
+        assert!(ports.check_distinct().is_err());
+    }
+}
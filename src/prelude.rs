@@ -49,17 +49,19 @@ pub fn path_to_tmp() -> &'static PathBuf {
 /// Initialize the paths for the lodestone instance.
 /// This function should only be called once.
 ///
+/// Runtimes, e.g. JREs, are stored in `path_to_binaries` if given, otherwise in `bin` under `lodestone_path`.
+///
 /// Also creates the directories if they don't exist.
-pub fn init_paths(lodestone_path: PathBuf) {
+pub fn init_paths(lodestone_path: PathBuf, path_to_binaries: Option<PathBuf>) {
     let path_to_instances = lodestone_path.join("instances");
-    let path_to_binaries = lodestone_path.join("bin");
+    let path_to_binaries = path_to_binaries.unwrap_or_else(|| lodestone_path.join("bin"));
     let path_to_stores = lodestone_path.join("stores");
     let path_to_global_settings = lodestone_path.join("global_settings.json");
     let path_to_users = lodestone_path.join("stores").join("users.json");
     let path_to_tmp = lodestone_path.join("tmp");
 
     std::fs::create_dir_all(&path_to_instances).unwrap();
-    std::fs::create_dir_all(&path_to_binaries).unwrap();
+    create_binaries_dir(&path_to_binaries);
     std::fs::create_dir_all(&path_to_stores).unwrap();
     std::fs::create_dir_all(&path_to_tmp).unwrap();
     // std::fs::File::create(&path_to_global_settings).unwrap();
@@ -75,6 +77,20 @@ pub fn init_paths(lodestone_path: PathBuf) {
     let _ = PATH_TO_TMP.set(path_to_tmp);
 }
 
+/// Creates the runtimes directory, readable by the user the servers run as.
+///
+/// An existing one is left as is, as it may be a read-only mount shared between deployments
+fn create_binaries_dir(path_to_binaries: &std::path::Path) {
+    if path_to_binaries.exists() {
+        return;
+    }
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o755);
+    builder.create(path_to_binaries).unwrap();
+}
+
 thread_local! {
     pub static VERSION: semver::Version = semver::Version {
         major: 0,
@@ -101,6 +117,7 @@ use crate::generic::GenericInstance;
 use crate::minecraft::MinecraftInstance;
 #[enum_dispatch::enum_dispatch(
     TInstance,
+    TBackup,
     TConfigurable,
     TMacro,
     TPlayerManagement,
@@ -0,0 +1,237 @@
+//! Priority and CPU affinity of the processes instances launch, so that background servers can be
+//! niced down or pinned to some cores on a busy host.
+//!
+//! Neither is worth failing a launch over, so whatever can't be applied is reported as a warning
+
+use color_eyre::eyre::eyre;
+use tokio::process::{Child, Command};
+
+use crate::error::{Error, ErrorKind};
+
+/// Nice levels, from the highest priority to the lowest
+pub const PRIORITY_RANGE: std::ops::RangeInclusive<i32> = -20..=19;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcessScheduling {
+    /// Nice level
+    pub priority: Option<i32>,
+    /// Bit `n` set allows the process to run on core `n`
+    pub cpu_affinity: Option<u64>,
+}
+
+pub fn validate_priority(priority: i32) -> Result<(), Error> {
+    if !PRIORITY_RANGE.contains(&priority) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Process priority must be between {} and {}",
+                PRIORITY_RANGE.start(),
+                PRIORITY_RANGE.end()
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Checks that `cpu_affinity` allows at least one core, and only cores the host has
+pub fn validate_cpu_affinity(cpu_affinity: u64, cores: usize) -> Result<(), Error> {
+    if cpu_affinity == 0 {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("CPU affinity must allow at least one core"),
+        });
+    }
+    if cores < 64 && cpu_affinity >> cores != 0 {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "CPU affinity {:#b} includes cores the host doesn't have, it has {} cores",
+                cpu_affinity,
+                cores
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Cores available to the core, and so to the processes it launches
+pub fn host_cores() -> usize {
+    std::thread::available_parallelism()
+        .map(|cores| cores.get())
+        .unwrap_or(1)
+}
+
+impl ProcessScheduling {
+    /// Sets up `command` so that the process starts with this priority and affinity, where that
+    /// has to happen before it runs. Failures only surface in `apply`
+    pub fn configure(&self, command: &mut Command) {
+        #[cfg(unix)]
+        {
+            let scheduling = *self;
+            // safety: only makes syscalls, which are safe to make between fork and exec
+            unsafe {
+                command.pre_exec(move || {
+                    if let Some(priority) = scheduling.priority {
+                        libc::setpriority(libc::PRIO_PROCESS, 0, priority);
+                    }
+                    #[cfg(target_os = "linux")]
+                    if let Some(cpu_affinity) = scheduling.cpu_affinity {
+                        let mut set: libc::cpu_set_t = std::mem::zeroed();
+                        for core in 0..64 {
+                            if cpu_affinity & (1 << core) != 0 {
+                                libc::CPU_SET(core, &mut set);
+                            }
+                        }
+                        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set);
+                    }
+                    Ok(())
+                });
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = command;
+    }
+
+    /// Applies what couldn't be set up before the process started and checks the rest took,
+    /// returning a warning for each that didn't
+    pub fn apply(&self, child: &Child) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.priority.is_none() && self.cpu_affinity.is_none() {
+            return warnings;
+        }
+        let pid = match child.id() {
+            Some(pid) => pid,
+            None => return warnings,
+        };
+        #[cfg(unix)]
+        {
+            if let Some(priority) = self.priority {
+                // safety: getpriority only reads the priority of the process
+                let actual = unsafe { libc::getpriority(libc::PRIO_PROCESS, pid as libc::id_t) };
+                if actual != priority {
+                    warnings.push(format!(
+                        "Failed to set the process priority to {}, it is {}. Raising the priority of a process usually requires elevated privileges",
+                        priority, actual
+                    ));
+                }
+            }
+            #[cfg(target_os = "linux")]
+            if let Some(cpu_affinity) = self.cpu_affinity {
+                // safety: the set is zeroed before being filled in by the syscall
+                let actual = unsafe {
+                    let mut set: libc::cpu_set_t = std::mem::zeroed();
+                    if libc::sched_getaffinity(
+                        pid as libc::pid_t,
+                        std::mem::size_of::<libc::cpu_set_t>(),
+                        &mut set,
+                    ) == 0
+                    {
+                        Some((0..64).fold(0_u64, |mask, core| {
+                            if libc::CPU_ISSET(core, &set) {
+                                mask | 1 << core
+                            } else {
+                                mask
+                            }
+                        }))
+                    } else {
+                        None
+                    }
+                };
+                if actual != Some(cpu_affinity) {
+                    warnings.push(format!(
+                        "Failed to set the CPU affinity to {:#b}",
+                        cpu_affinity
+                    ));
+                }
+            }
+            #[cfg(not(target_os = "linux"))]
+            if self.cpu_affinity.is_some() {
+                warnings.push("CPU affinity isn't supported on this platform".to_string());
+            }
+        }
+        #[cfg(windows)]
+        {
+            use windows_sys::Win32::System::Threading::{
+                SetPriorityClass, SetProcessAffinityMask, ABOVE_NORMAL_PRIORITY_CLASS,
+                BELOW_NORMAL_PRIORITY_CLASS, HIGH_PRIORITY_CLASS, IDLE_PRIORITY_CLASS,
+                NORMAL_PRIORITY_CLASS,
+            };
+            let _ = pid;
+            let handle = match child.raw_handle() {
+                Some(handle) => handle as isize,
+                None => return warnings,
+            };
+            if let Some(priority) = self.priority {
+                // windows has priority classes rather than nice levels
+                let class = match priority {
+                    i32::MIN..=-11 => HIGH_PRIORITY_CLASS,
+                    -10..=-1 => ABOVE_NORMAL_PRIORITY_CLASS,
+                    0 => NORMAL_PRIORITY_CLASS,
+                    1..=10 => BELOW_NORMAL_PRIORITY_CLASS,
+                    _ => IDLE_PRIORITY_CLASS,
+                };
+                // safety: the handle is of the child, which is still alive
+                if unsafe { SetPriorityClass(handle, class) } == 0 {
+                    warnings.push(format!(
+                        "Failed to set the process priority to {}: {}",
+                        priority,
+                        std::io::Error::last_os_error()
+                    ));
+                }
+            }
+            if let Some(cpu_affinity) = self.cpu_affinity {
+                // safety: the handle is of the child, which is still alive
+                if unsafe { SetProcessAffinityMask(handle, cpu_affinity as usize) } == 0 {
+                    warnings.push(format!(
+                        "Failed to set the CPU affinity to {:#b}: {}",
+                        cpu_affinity,
+                        std::io::Error::last_os_error()
+                    ));
+                }
+            }
+        }
+        #[cfg(not(any(unix, windows)))]
+        {
+            let _ = pid;
+            warnings.push(
+                "Process priority and CPU affinity aren't supported on this platform".to_string(),
+            );
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(validate_priority(10).is_ok());
+        assert!(validate_priority(-20).is_ok());
+        assert!(validate_priority(20).is_err());
+        assert!(validate_cpu_affinity(0b11, 4).is_ok());
+        assert!(validate_cpu_affinity(0, 4).is_err());
+        assert!(validate_cpu_affinity(0b10000, 4).is_err());
+        assert!(validate_cpu_affinity(u64::MAX, 64).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_priority_is_applied() {
+        // lowering the priority never needs privileges
+        let scheduling = ProcessScheduling {
+            priority: Some(10),
+            cpu_affinity: None,
+        };
+        let mut command = Command::new("sleep");
+        command.arg("5");
+        scheduling.configure(&mut command);
+        let mut child = command.spawn().unwrap();
+        let pid = child.id().unwrap();
+        let priority = unsafe { libc::getpriority(libc::PRIO_PROCESS, pid as libc::id_t) };
+        assert!(scheduling.apply(&child).is_empty());
+        child.kill().await.unwrap();
+        assert_eq!(priority, 10);
+    }
+}
@@ -0,0 +1,176 @@
+//! The latest progress of the operations in flight, e.g. setups, backups and upgrades, so that a
+//! client connecting part way through one doesn't have to wait for its next progression event
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::sync::Mutex;
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::events::{CausedBy, Event, EventInner, ProgressionEventInner, ProgressionStartValue};
+use crate::types::Snowflake;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct OperationProgress {
+    /// The `event_id` of the progression events of the operation
+    pub event_id: Snowflake,
+    pub progression_name: String,
+    pub total: Option<f64>,
+    /// The sum of the progress reported so far, out of `total`
+    pub progress: f64,
+    /// The message of the latest update, `None` before the first one
+    pub message: Option<String>,
+    pub inner: Option<ProgressionStartValue>,
+    pub caused_by: CausedBy,
+    /// Unix timestamp of when the operation started
+    pub started_at: i64,
+}
+
+/// Operations are added on their `ProgressionStart` and removed on their `ProgressionEnd`
+#[derive(Clone, Default)]
+pub struct ProgressRegistry {
+    operations: Arc<Mutex<HashMap<Snowflake, OperationProgress>>>,
+}
+
+impl ProgressRegistry {
+    /// The operations in flight, oldest first
+    pub async fn list(&self) -> Vec<OperationProgress> {
+        let mut operations: Vec<OperationProgress> =
+            self.operations.lock().await.values().cloned().collect();
+        operations.sort_by_key(|operation| operation.started_at);
+        operations
+    }
+
+    pub async fn get(&self, event_id: &Snowflake) -> Option<OperationProgress> {
+        self.operations.lock().await.get(event_id).cloned()
+    }
+
+    async fn on_event(&self, event: &Event) {
+        let progression_event = match &event.event_inner {
+            EventInner::ProgressionEvent(progression_event) => progression_event,
+            _ => return,
+        };
+        let event_id = progression_event.event_id();
+        let mut operations = self.operations.lock().await;
+        match progression_event.progression_event_inner() {
+            ProgressionEventInner::ProgressionStart {
+                progression_name,
+                total,
+                inner,
+            } => {
+                operations.insert(
+                    event_id,
+                    OperationProgress {
+                        event_id,
+                        progression_name: progression_name.clone(),
+                        total: *total,
+                        progress: 0.0,
+                        message: None,
+                        inner: inner.clone(),
+                        caused_by: event.caused_by.clone(),
+                        started_at: chrono::Utc::now().timestamp(),
+                    },
+                );
+            }
+            ProgressionEventInner::ProgressionUpdate {
+                progress_message,
+                progress,
+            } => {
+                if let Some(operation) = operations.get_mut(&event_id) {
+                    operation.progress += progress;
+                    operation.message = Some(progress_message.clone());
+                }
+            }
+            ProgressionEventInner::ProgressionEnd { .. } => {
+                operations.remove(&event_id);
+            }
+        }
+    }
+
+    /// Keeps the registry up to date with the progression events
+    pub async fn track_progress(self, mut event_receiver: Receiver<Event>) {
+        loop {
+            match event_receiver.recv().await {
+                Ok(event) => self.on_event(&event).await,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Progress registry lagged behind and missed {missed} events, progress may be off");
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_operation_lifecycle() {
+        let registry = ProgressRegistry::default();
+        let (start, event_id) =
+            Event::new_progression_event_start("Backing up", Some(10.0), None, CausedBy::System);
+        let id = event_id.snowflake();
+        registry.on_event(&start).await;
+        let operation = registry.get(&id).await.unwrap();
+        assert_eq!(operation.progression_name, "Backing up");
+        assert_eq!(operation.progress, 0.0);
+        assert_eq!(operation.message, None);
+
+        registry
+            .on_event(&Event::new_progression_event_update(
+                &event_id, "Copying", 3.0,
+            ))
+            .await;
+        registry
+            .on_event(&Event::new_progression_event_update(
+                &event_id,
+                "Compressing",
+                4.0,
+            ))
+            .await;
+        // as a client connecting now would see it
+        let operation = registry.get(&id).await.unwrap();
+        assert_eq!(operation.progress, 7.0);
+        assert_eq!(operation.message.as_deref(), Some("Compressing"));
+        assert_eq!(registry.list().await, vec![operation]);
+
+        registry
+            .on_event(&Event::new_progression_event_end(
+                event_id,
+                true,
+                Some("Backed up"),
+                None,
+            ))
+            .await;
+        assert!(registry.get(&id).await.is_none());
+        assert!(registry.list().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_operation_is_ignored() {
+        let registry = ProgressRegistry::default();
+        // started before the registry was tracking
+        let (_, event_id) =
+            Event::new_progression_event_start("Upgrading", None, None, CausedBy::System);
+        registry
+            .on_event(&Event::new_progression_event_update(
+                &event_id, "Halfway", 1.0,
+            ))
+            .await;
+        assert!(registry.list().await.is_empty());
+        registry
+            .on_event(&Event::new_progression_event_end(
+                event_id,
+                false,
+                None::<&str>,
+                None,
+            ))
+            .await;
+        assert!(registry.list().await.is_empty());
+    }
+}
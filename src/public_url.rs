@@ -0,0 +1,134 @@
+//! Keeping requests the core makes on behalf of users, e.g. to webhooks, off this host and the
+//! networks it's on
+
+use std::net::IpAddr;
+
+use color_eyre::eyre::eyre;
+use url::{Host, Url};
+
+use crate::error::{Error, ErrorKind};
+
+/// Whether `ip` is on this host or a private or link-local network, which users mustn't be able
+/// to reach through the core
+pub fn is_internal_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // shared address space of carrier-grade NAT
+                || (a == 100 && (64..128).contains(&b))
+        }
+        IpAddr::V6(ip) => {
+            let first_segment = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                // unique local
+                || (first_segment & 0xfe00) == 0xfc00
+                // link-local
+                || (first_segment & 0xffc0) == 0xfe80
+                || ip
+                    .to_ipv4_mapped()
+                    .map_or(false, |ip| is_internal_ip(&IpAddr::V4(ip)))
+        }
+    }
+}
+
+/// Rejects `url` unless it's http or https and its host resolves only to public addresses,
+/// see [`is_internal_ip`]
+pub async fn check_public_url(url: &str) -> Result<(), Error> {
+    let parsed = Url::parse(url).map_err(|e| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Invalid URL {}: {}", url, e),
+    })?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("URL {} must be http or https", url),
+        });
+    }
+    let port = parsed.port_or_known_default().unwrap_or(80);
+    let ips: Vec<IpAddr> = match parsed.host() {
+        Some(Host::Ipv4(ip)) => vec![IpAddr::V4(ip)],
+        Some(Host::Ipv6(ip)) => vec![IpAddr::V6(ip)],
+        Some(Host::Domain(domain)) => tokio::net::lookup_host((domain, port))
+            .await
+            .map_err(|e| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Failed to resolve the host of {}: {}", url, e),
+            })?
+            .map(|addr| addr.ip())
+            .collect(),
+        None => Vec::new(),
+    };
+    if ips.is_empty() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("URL {} has no host", url),
+        });
+    }
+    match ips.iter().find(|ip| is_internal_ip(ip)) {
+        Some(ip) => Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "URL {} resolves to {}, which is on this host or a private network",
+                url,
+                ip
+            ),
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_internal_ip() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(is_internal_ip(&ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["93.184.216.34", "1.1.1.1", "2606:4700::1111", "100.128.0.1"] {
+            assert!(!is_internal_ip(&ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_public_url() {
+        for url in [
+            "http://127.0.0.1:3000/hook",
+            "http://[::1]/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://localhost/hook",
+            "ftp://93.184.216.34/hook",
+            "not a url",
+        ] {
+            assert!(
+                matches!(
+                    check_public_url(url).await.unwrap_err().kind,
+                    ErrorKind::BadRequest
+                ),
+                "{}",
+                url
+            );
+        }
+        assert!(check_public_url("https://93.184.216.34/hook").await.is_ok());
+    }
+}
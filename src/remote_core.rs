@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::traits::t_server::State;
+use crate::traits::InstanceInfo;
+
+/// How long a remote core may take to answer before it's considered unreachable
+const REMOTE_CORE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Set on requests to remote cores to the number of cores the request went through,
+/// so that cores registered with each other don't list each other's instances forever
+pub const REMOTE_CORE_DEPTH_HEADER: &str = "x-lodestone-remote-depth";
+/// Past this many hops, remote cores aren't asked for their instances
+const MAX_REMOTE_CORE_DEPTH: u32 = 3;
+
+/// How many cores the request went through, 0 if it came from a client
+pub fn remote_core_depth(headers: &HeaderMap) -> u32 {
+    headers
+        .get(REMOTE_CORE_DEPTH_HEADER)
+        .and_then(|depth| depth.to_str().ok())
+        .and_then(|depth| depth.parse().ok())
+        .unwrap_or(0)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct RemoteCore {
+    id: String,
+    name: String,
+    /// Base URL of the core, e.g. `https://node2.example.com:16662`
+    url: String,
+    token: String,
+}
+
+/// A registered remote core, without its token
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[ts(export)]
+pub struct RemoteCoreInfo {
+    pub id: String,
+    pub name: String,
+    pub url: String,
+}
+
+impl From<&RemoteCore> for RemoteCoreInfo {
+    fn from(core: &RemoteCore) -> Self {
+        Self {
+            id: core.id.clone(),
+            name: core.name.clone(),
+            url: core.url.clone(),
+        }
+    }
+}
+
+/// The error body returned by another core
+#[derive(Deserialize)]
+struct RemoteError {
+    kind: ErrorKind,
+    causes: Vec<String>,
+}
+
+/// Other cores this core can proxy instance operations to
+#[derive(Clone)]
+pub struct RemoteCores {
+    path: PathBuf,
+    cores: Arc<Mutex<Vec<RemoteCore>>>,
+    /// Instances of each core as of the last time it could be reached
+    last_known_instances: Arc<Mutex<HashMap<String, Vec<InstanceInfo>>>>,
+    http: reqwest::Client,
+}
+
+impl RemoteCores {
+    /// Loads the registered cores from `path`, starting with none if the file doesn't exist
+    pub async fn load(path: PathBuf) -> Result<Self, Error> {
+        let cores = match tokio::fs::read(&path).await {
+            Ok(content) => {
+                serde_json::from_slice(&content).context("Failed to parse remote cores")?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => Err(e).context("Failed to read remote cores")?,
+        };
+        Ok(Self {
+            path,
+            cores: Arc::new(Mutex::new(cores)),
+            last_known_instances: Arc::new(Mutex::new(HashMap::new())),
+            http: reqwest::Client::builder()
+                .timeout(REMOTE_CORE_TIMEOUT)
+                .build()
+                .context("Failed to build HTTP client")?,
+        })
+    }
+
+    async fn save(&self, cores: &[RemoteCore]) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path,
+            serde_json::to_vec_pretty(cores).context("Failed to serialize remote cores")?,
+        )
+        .await
+        .context("Failed to write remote cores")?;
+        Ok(())
+    }
+
+    pub async fn list(&self) -> Vec<RemoteCoreInfo> {
+        self.cores.lock().await.iter().map(Into::into).collect()
+    }
+
+    /// Registers a core, which has to be reachable with the given token
+    pub async fn add(
+        &self,
+        name: String,
+        url: String,
+        token: String,
+    ) -> Result<RemoteCoreInfo, Error> {
+        let parsed = url::Url::parse(&url).map_err(|e| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid remote core URL: {}", e),
+        })?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Remote core URL must be http or https"),
+            });
+        }
+        let core = RemoteCore {
+            id: uuid::Uuid::new_v4().to_string(),
+            name,
+            url: url.trim_end_matches('/').to_string(),
+            token,
+        };
+        self.fetch_instance_list(&core, 0)
+            .await
+            .map_err(|e| Error {
+                kind: ErrorKind::BadRequest,
+                source: e.source.wrap_err("Failed to reach the remote core"),
+            })?;
+        let mut cores = self.cores.lock().await;
+        cores.push(core.clone());
+        self.save(&cores).await?;
+        Ok((&core).into())
+    }
+
+    pub async fn remove(&self, id: &str) -> Result<(), Error> {
+        let mut cores = self.cores.lock().await;
+        let len = cores.len();
+        cores.retain(|core| core.id != id);
+        if cores.len() == len {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Remote core not found"),
+            });
+        }
+        self.save(&cores).await?;
+        drop(cores);
+        self.last_known_instances.lock().await.remove(id);
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<RemoteCore, Error> {
+        self.cores
+            .lock()
+            .await
+            .iter()
+            .find(|core| core.id == id)
+            .cloned()
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Remote core not found"),
+            })
+    }
+
+    /// `depth` is the number of cores the request that led to this one went through
+    async fn send(
+        &self,
+        core: &RemoteCore,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<Value>,
+        depth: u32,
+    ) -> Result<Value, Error> {
+        let mut request = self
+            .http
+            .request(method, format!("{}/api/v1{}", core.url, path))
+            .bearer_auth(&core.token)
+            .header(REMOTE_CORE_DEPTH_HEADER, depth + 1);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await.context(format!(
+            "Failed to reach remote core {} at {}",
+            core.name, core.url
+        ))?;
+        let status = response.status();
+        let body = response
+            .bytes()
+            .await
+            .context("Failed to read response from remote core")?;
+        if !status.is_success() {
+            return Err(match serde_json::from_slice::<RemoteError>(&body) {
+                // a token the remote core rejects is a problem of this core, not of the requester
+                Ok(RemoteError {
+                    kind: ErrorKind::Unauthorized,
+                    ..
+                }) => Error {
+                    kind: ErrorKind::Internal,
+                    source: eyre!("Remote core {} rejected its token", core.name),
+                },
+                Ok(remote_error) => Error {
+                    kind: remote_error.kind,
+                    source: eyre!("{}", remote_error.causes.join(": ")),
+                },
+                Err(_) => Error {
+                    kind: ErrorKind::Internal,
+                    source: eyre!("Remote core {} responded with {}", core.name, status),
+                },
+            });
+        }
+        if body.is_empty() {
+            return Ok(Value::Null);
+        }
+        Ok(serde_json::from_slice(&body).context("Failed to parse response from remote core")?)
+    }
+
+    async fn fetch_instance_list(
+        &self,
+        core: &RemoteCore,
+        depth: u32,
+    ) -> Result<Vec<InstanceInfo>, Error> {
+        let list = self
+            .send(core, reqwest::Method::GET, "/instance/list", None, depth)
+            .await?;
+        let mut list: Vec<InstanceInfo> =
+            serde_json::from_value(list).context("Failed to parse instance list of remote core")?;
+        for info in list.iter_mut() {
+            info.core_id = Some(core.id.clone());
+        }
+        Ok(list)
+    }
+
+    /// Instances of every remote core, tagged with their core.
+    ///
+    /// The instances of a core that can't be reached are its last known ones, marked `Unavailable`.
+    /// `depth` is the number of cores the request for the list went through, none are asked past
+    /// `MAX_REMOTE_CORE_DEPTH`
+    pub async fn instance_list(&self, depth: u32) -> Vec<InstanceInfo> {
+        if depth >= MAX_REMOTE_CORE_DEPTH {
+            return Vec::new();
+        }
+        let cores = self.cores.lock().await.clone();
+        let lists = futures::future::join_all(
+            cores
+                .iter()
+                .map(|core| async move { (core, self.fetch_instance_list(core, depth).await) }),
+        )
+        .await;
+        let mut last_known_instances = self.last_known_instances.lock().await;
+        let mut ret = Vec::new();
+        for (core, list) in lists {
+            match list {
+                Ok(list) => {
+                    last_known_instances.insert(core.id.clone(), list.clone());
+                    ret.extend(list);
+                }
+                Err(e) => {
+                    warn!("Remote core {} is unavailable: {}", core.name, e);
+                    ret.extend(
+                        last_known_instances
+                            .get(&core.id)
+                            .cloned()
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|info| InstanceInfo {
+                                state: State::Unavailable,
+                                ..info
+                            }),
+                    );
+                }
+            }
+        }
+        ret
+    }
+
+    /// Sends a request to the API of a remote core, `path` is relative to `/api/v1`
+    pub async fn request(
+        &self,
+        core_id: &str,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> Result<Value, Error> {
+        let core = self.get(core_id).await?;
+        self.send(&core, method, path, body, 0).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_remote_cores_persistence() {
+        let temp_dir = tempdir::TempDir::new("test_remote_cores").unwrap();
+        let path = temp_dir.path().join("remote_cores.json");
+        let cores = RemoteCores::load(path.clone()).await.unwrap();
+        assert!(cores.list().await.is_empty());
+        assert!(cores
+            .add(
+                "unreachable".to_string(),
+                // nothing listens on port 1
+                "http://127.0.0.1:1".to_string(),
+                "token".to_string()
+            )
+            .await
+            .is_err());
+        assert!(cores
+            .add(
+                "invalid".to_string(),
+                "ftp://example.com".to_string(),
+                "token".to_string()
+            )
+            .await
+            .is_err());
+
+        let core = RemoteCore {
+            id: "id".to_string(),
+            name: "node".to_string(),
+            url: "http://127.0.0.1:1".to_string(),
+            token: "token".to_string(),
+        };
+        cores.cores.lock().await.push(core.clone());
+        cores.save(&[core]).await.unwrap();
+        let reloaded = RemoteCores::load(path).await.unwrap();
+        assert_eq!(reloaded.list().await.len(), 1);
+        // an unreachable core with no known instances contributes none
+        assert!(reloaded.instance_list(0).await.is_empty());
+        reloaded.remove("id").await.unwrap();
+        assert!(reloaded.remove("id").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_remote_core_depth_limit() {
+        let temp_dir = tempdir::TempDir::new("test_remote_core_depth").unwrap();
+        let cores = RemoteCores::load(temp_dir.path().join("remote_cores.json"))
+            .await
+            .unwrap();
+        let core = RemoteCore {
+            id: "id".to_string(),
+            name: "node".to_string(),
+            url: "http://127.0.0.1:1".to_string(),
+            token: "token".to_string(),
+        };
+        cores.cores.lock().await.push(core);
+        // past the limit the core isn't contacted, so there's nothing to wait for
+        assert!(tokio::time::timeout(
+            Duration::from_millis(100),
+            cores.instance_list(MAX_REMOTE_CORE_DEPTH)
+        )
+        .await
+        .unwrap()
+        .is_empty());
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(remote_core_depth(&headers), 0);
+        headers.insert(REMOTE_CORE_DEPTH_HEADER, "2".parse().unwrap());
+        assert_eq!(remote_core_depth(&headers), 2);
+    }
+}
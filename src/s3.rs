@@ -0,0 +1,350 @@
+//! A minimal client for S3 compatible object stores, enough to upload, list and download backups.
+//!
+//! Requests are signed with AWS Signature Version 4 and use path style URLs,
+//! which AWS as well as self hosted stores like MinIO accept.
+
+use std::path::Path;
+use std::time::SystemTime;
+
+use aws_sigv4::http_request::{
+    sign, PayloadChecksumKind, PercentEncodingMode, SignableBody, SignableRequest, SigningParams,
+    SigningSettings, UriPathNormalizationMode,
+};
+use color_eyre::eyre::{eyre, Context};
+use futures::StreamExt;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::upstream_http;
+
+/// Largest object a single PUT can upload
+const MAX_SINGLE_UPLOAD_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct S3Config {
+    /// e.g. `https://s3.us-east-1.amazonaws.com`, or the URL of a self hosted store
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    /// Prepended to the key of every object, e.g. `lodestone/`
+    #[serde(default)]
+    pub prefix: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Delete the local copy of a backup once it's uploaded
+    #[serde(default)]
+    pub delete_local_copy: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct S3Object {
+    pub key: String,
+    pub size: u64,
+    /// As reported by the store, in RFC 3339
+    pub last_modified: String,
+}
+
+/// Everything but unreserved characters is percent encoded, as signature version 4 requires
+const S3_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListBucketResult {
+    #[serde(default)]
+    contents: Vec<ListedObject>,
+    #[serde(default)]
+    is_truncated: bool,
+    next_continuation_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListedObject {
+    key: String,
+    size: u64,
+    last_modified: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ErrorResponse {
+    message: String,
+}
+
+/// Path style URL of `key` in the configured bucket, `key` is empty for requests on the bucket itself
+fn object_url(config: &S3Config, key: &str, query: &[(&str, &str)]) -> Result<url::Url, Error> {
+    let mut url = url::Url::parse(&config.endpoint).context("Invalid S3 endpoint")?;
+    if url.host_str().is_none() {
+        return Err(eyre!("S3 endpoint {} has no host", config.endpoint).into());
+    }
+    let path = std::iter::once(config.bucket.as_str())
+        .chain(key.split('/'))
+        .map(|segment| utf8_percent_encode(segment, S3_ENCODE_SET).to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+    url.set_path(&format!("{}/{}", url.path().trim_end_matches('/'), path));
+    let query = query
+        .iter()
+        .map(|(k, v)| {
+            format!(
+                "{}={}",
+                utf8_percent_encode(k, S3_ENCODE_SET),
+                utf8_percent_encode(v, S3_ENCODE_SET)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    url.set_query((!query.is_empty()).then_some(query.as_str()));
+    Ok(url)
+}
+
+/// Builds a request on `client` for `key`, signed with signature version 4
+fn signed_request(
+    client: &reqwest::Client,
+    config: &S3Config,
+    method: reqwest::Method,
+    key: &str,
+    query: &[(&str, &str)],
+    body: SignableBody,
+) -> Result<reqwest::RequestBuilder, Error> {
+    let url = object_url(config, key, query)?;
+    let uri: http::Uri = url.as_str().parse().context("Invalid S3 URL")?;
+    let mut settings = SigningSettings::default();
+    // S3 expects the path to be encoded once and exactly as sent
+    settings.percent_encoding_mode = PercentEncodingMode::Single;
+    settings.uri_path_normalization_mode = UriPathNormalizationMode::Disabled;
+    settings.payload_checksum_kind = PayloadChecksumKind::XAmzSha256;
+    let params = SigningParams::builder()
+        .access_key(&config.access_key_id)
+        .secret_key(&config.secret_access_key)
+        .region(&config.region)
+        .service_name("s3")
+        .time(SystemTime::now())
+        .settings(settings)
+        .build()
+        .context("Invalid S3 credentials")?;
+    let (mut instructions, _signature) = sign(
+        SignableRequest::new(&method, &uri, &http::HeaderMap::new(), body),
+        &params,
+    )
+    .context("Failed to sign S3 request")?
+    .into_parts();
+    Ok(client
+        .request(method, url)
+        .headers(instructions.take_headers().unwrap_or_default()))
+}
+
+/// Fails with the error the store responded with, if it did
+async fn check_response(
+    response: reqwest::Response,
+) -> Result<reqwest::Response, color_eyre::Report> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    Err(eyre!(
+        "S3 responded with {}: {}",
+        status,
+        quick_xml::de::from_str::<ErrorResponse>(&body)
+            .map(|error| error.message)
+            .unwrap_or(body)
+    ))
+}
+
+/// Uploads the file at `path` as `key`, streaming it rather than reading it into memory
+pub async fn put_object_from_file(config: &S3Config, key: &str, path: &Path) -> Result<(), Error> {
+    let upload_failed = |source: color_eyre::Report| Error {
+        kind: ErrorKind::FailedToUpload,
+        source: source.wrap_err(format!("Failed to upload {} to S3", key)),
+    };
+    let file = tokio::fs::File::open(path)
+        .await
+        .context(format!("Failed to open {}", path.display()))?;
+    let size = file
+        .metadata()
+        .await
+        .context(format!("Failed to read metadata of {}", path.display()))?
+        .len();
+    if size > MAX_SINGLE_UPLOAD_SIZE {
+        return Err(upload_failed(eyre!(
+            "{} is larger than the 5 GiB a single upload can be",
+            path.display()
+        )));
+    }
+    // the payload isn't hashed up front, that would mean reading the file twice
+    let response = signed_request(
+        &upstream_http::download_client(),
+        config,
+        reqwest::Method::PUT,
+        &format!("{}{}", config.prefix, key),
+        &[],
+        SignableBody::UnsignedPayload,
+    )?
+    .header(reqwest::header::CONTENT_LENGTH, size)
+    .body(reqwest::Body::wrap_stream(ReaderStream::new(file)))
+    .send()
+    .await
+    .map_err(|e| upload_failed(e.into()))?;
+    check_response(response).await.map_err(upload_failed)?;
+    Ok(())
+}
+
+/// Lists the objects whose key starts with `prefix`, after the configured prefix
+pub async fn list_objects(config: &S3Config, prefix: &str) -> Result<Vec<S3Object>, Error> {
+    let full_prefix = format!("{}{}", config.prefix, prefix);
+    let mut objects = Vec::new();
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let mut query = vec![("list-type", "2"), ("prefix", full_prefix.as_str())];
+        if let Some(token) = &continuation_token {
+            query.push(("continuation-token", token.as_str()));
+        }
+        let response = signed_request(
+            &upstream_http::client(),
+            config,
+            reqwest::Method::GET,
+            "",
+            &query,
+            SignableBody::Bytes(b""),
+        )?
+        .send()
+        .await
+        .context("Failed to list S3 objects")?;
+        let body = check_response(response)
+            .await
+            .context("Failed to list S3 objects")?
+            .text()
+            .await
+            .context("Failed to list S3 objects")?;
+        let (listed, next_continuation_token) = parse_list_objects(&body, &config.prefix)?;
+        objects.extend(listed);
+        continuation_token = next_continuation_token;
+        if continuation_token.is_none() {
+            break;
+        }
+    }
+    Ok(objects)
+}
+
+/// Downloads `key` to `dest`, streaming it to disk
+pub async fn get_object_to_file(config: &S3Config, key: &str, dest: &Path) -> Result<(), Error> {
+    let response = signed_request(
+        &upstream_http::download_client(),
+        config,
+        reqwest::Method::GET,
+        &format!("{}{}", config.prefix, key),
+        &[],
+        SignableBody::Bytes(b""),
+    )?
+    .send()
+    .await
+    .context(format!("Failed to download {} from S3", key))?;
+    let mut stream = check_response(response)
+        .await
+        .context(format!("Failed to download {} from S3", key))?
+        .bytes_stream();
+    let mut file = tokio::fs::File::create(dest)
+        .await
+        .context(format!("Failed to create {}", dest.display()))?;
+    while let Some(chunk) = stream.next().await {
+        file.write_all(&chunk.context(format!("Failed to download {} from S3", key))?)
+            .await
+            .context(format!("Failed to write {}", dest.display()))?;
+    }
+    file.flush()
+        .await
+        .context(format!("Failed to write {}", dest.display()))?;
+    Ok(())
+}
+
+/// Parses a ListObjectsV2 response, stripping `prefix` from the keys.
+/// Also returns the token to continue from if the listing is truncated
+fn parse_list_objects(xml: &str, prefix: &str) -> Result<(Vec<S3Object>, Option<String>), Error> {
+    let result: ListBucketResult =
+        quick_xml::de::from_str(xml).context("Failed to parse S3 object listing")?;
+    let objects = result
+        .contents
+        .into_iter()
+        .map(|object| S3Object {
+            key: object
+                .key
+                .strip_prefix(prefix)
+                .unwrap_or(&object.key)
+                .to_string(),
+            size: object.size,
+            last_modified: object.last_modified,
+        })
+        .collect();
+    Ok((
+        objects,
+        result
+            .next_continuation_token
+            .filter(|_| result.is_truncated),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_url() {
+        let config = S3Config {
+            endpoint: "https://minio.example.com:9000/".to_string(),
+            region: "us-east-1".to_string(),
+            bucket: "backups".to_string(),
+            prefix: String::new(),
+            access_key_id: String::new(),
+            secret_access_key: String::new(),
+            delete_local_copy: false,
+        };
+        assert_eq!(
+            object_url(&config, "abc/a b+c.zip", &[]).unwrap().as_str(),
+            "https://minio.example.com:9000/backups/abc/a%20b%2Bc.zip"
+        );
+        assert_eq!(
+            object_url(&config, "", &[("list-type", "2"), ("prefix", "a/b")])
+                .unwrap()
+                .as_str(),
+            "https://minio.example.com:9000/backups/?list-type=2&prefix=a%2Fb"
+        );
+    }
+
+    #[test]
+    fn test_parse_list_objects() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated><NextContinuationToken>next</NextContinuationToken>
+<Contents><Key>lodestone/abc/backup-1.zip</Key><LastModified>2023-01-01T00:00:00.000Z</LastModified><Size>1024</Size></Contents>
+<Contents><Key>lodestone/abc/a&amp;b.zip</Key><LastModified>2023-01-02T00:00:00.000Z</LastModified><Size>2048</Size></Contents>
+</ListBucketResult>"#;
+        assert_eq!(
+            parse_list_objects(xml, "lodestone/").unwrap(),
+            (
+                vec![
+                    S3Object {
+                        key: "abc/backup-1.zip".to_string(),
+                        size: 1024,
+                        last_modified: "2023-01-01T00:00:00.000Z".to_string(),
+                    },
+                    S3Object {
+                        key: "abc/a&b.zip".to_string(),
+                        size: 2048,
+                        last_modified: "2023-01-02T00:00:00.000Z".to_string(),
+                    },
+                ],
+                Some("next".to_string())
+            )
+        );
+    }
+}
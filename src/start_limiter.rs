@@ -0,0 +1,223 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use once_cell::sync::Lazy;
+use tokio::sync::Notify;
+
+use crate::types::InstanceUuid;
+
+static START_LIMITER: Lazy<StartLimiter> = Lazy::new(StartLimiter::default);
+
+/// The limiter shared by every instance of the core
+pub fn start_limiter() -> &'static StartLimiter {
+    &START_LIMITER
+}
+
+#[derive(Default)]
+struct LimiterState {
+    /// Unlimited if `None`
+    limit: Option<usize>,
+    starting: usize,
+    queue: VecDeque<InstanceUuid>,
+}
+
+impl LimiterState {
+    fn has_free_slot(&self) -> bool {
+        self.limit
+            .map(|limit| self.starting < limit)
+            .unwrap_or(true)
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    state: Mutex<LimiterState>,
+    notify: Notify,
+}
+
+/// A semaphore bounding how many instances can be starting at once, so that starting many
+/// instances, e.g. on boot, doesn't spin up all their JVMs at the same time.
+/// Instances waiting for a slot are served in order
+#[derive(Clone, Default)]
+pub struct StartLimiter {
+    inner: Arc<Inner>,
+}
+
+/// A slot to start an instance in, freed when dropped
+pub struct StartPermit {
+    inner: Arc<Inner>,
+}
+
+impl Drop for StartPermit {
+    fn drop(&mut self) {
+        lock(&self.inner).starting -= 1;
+        self.inner.notify.notify_waiters();
+    }
+}
+
+/// Takes an instance out of the queue if it stops waiting, e.g. because the start was cancelled
+struct QueueGuard<'a> {
+    inner: &'a Arc<Inner>,
+    uuid: &'a InstanceUuid,
+}
+
+impl Drop for QueueGuard<'_> {
+    fn drop(&mut self) {
+        let mut state = lock(self.inner);
+        if let Some(position) = state.queue.iter().position(|uuid| uuid == self.uuid) {
+            state.queue.remove(position);
+            drop(state);
+            self.inner.notify.notify_waiters();
+        }
+    }
+}
+
+fn lock(inner: &Inner) -> MutexGuard<'_, LimiterState> {
+    inner
+        .state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl StartLimiter {
+    pub fn new(limit: Option<usize>) -> Self {
+        let limiter = Self::default();
+        limiter.set_limit(limit);
+        limiter
+    }
+
+    /// Changes how many instances can be starting at once, `None` for no limit.
+    /// Instances already starting keep their slot
+    pub fn set_limit(&self, limit: Option<usize>) {
+        lock(&self.inner).limit = limit;
+        self.inner.notify.notify_waiters();
+    }
+
+    fn permit(&self, state: &mut LimiterState) -> StartPermit {
+        state.starting += 1;
+        StartPermit {
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// A slot if one is free and no instance is queued for one
+    pub fn try_acquire(&self) -> Option<StartPermit> {
+        let mut state = lock(&self.inner);
+        if state.queue.is_empty() && state.has_free_slot() {
+            Some(self.permit(&mut state))
+        } else {
+            None
+        }
+    }
+
+    /// Waits in the queue until a slot is free
+    pub async fn acquire(&self, uuid: &InstanceUuid) -> StartPermit {
+        if let Some(permit) = self.try_acquire() {
+            return permit;
+        }
+        lock(&self.inner).queue.push_back(uuid.clone());
+        let _guard = QueueGuard {
+            inner: &self.inner,
+            uuid,
+        };
+        loop {
+            // created before checking, so a slot freed in between isn't missed
+            let notified = self.inner.notify.notified();
+            {
+                let mut state = lock(&self.inner);
+                if state.queue.front() == Some(uuid) && state.has_free_slot() {
+                    state.queue.pop_front();
+                    let permit = self.permit(&mut state);
+                    drop(state);
+                    // the next instance in line may fit too
+                    self.inner.notify.notify_waiters();
+                    return permit;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Position of the instance in the queue, starting from 1, `None` if it isn't queued
+    pub fn queue_position(&self, uuid: &InstanceUuid) -> Option<usize> {
+        lock(&self.inner)
+            .queue
+            .iter()
+            .position(|queued| queued == uuid)
+            .map(|position| position + 1)
+    }
+
+    pub fn is_queued(&self, uuid: &InstanceUuid) -> bool {
+        self.queue_position(uuid).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_start_limiter_bounds_concurrent_starts() {
+        let limiter = StartLimiter::new(Some(2));
+        let starting = Arc::new(AtomicUsize::new(0));
+        let max_starting = Arc::new(AtomicUsize::new(0));
+        let mut handles = Vec::new();
+        for i in 0..6 {
+            let limiter = limiter.clone();
+            let starting = starting.clone();
+            let max_starting = max_starting.clone();
+            handles.push(tokio::spawn(async move {
+                let uuid = InstanceUuid::from(format!("instance_{i}"));
+                let _permit = limiter.acquire(&uuid).await;
+                let now_starting = starting.fetch_add(1, Ordering::SeqCst) + 1;
+                max_starting.fetch_max(now_starting, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                starting.fetch_sub(1, Ordering::SeqCst);
+            }));
+        }
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(max_starting.load(Ordering::SeqCst), 2);
+        assert!(limiter.try_acquire().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_start_limiter_queue() {
+        let limiter = StartLimiter::new(Some(1));
+        let first = limiter.try_acquire().unwrap();
+        assert!(limiter.try_acquire().is_none());
+
+        let queued = InstanceUuid::from("queued".to_string());
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            let queued = queued.clone();
+            async move { limiter.acquire(&queued).await }
+        });
+        while !limiter.is_queued(&queued) {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(limiter.queue_position(&queued), Some(1));
+
+        drop(first);
+        let _second = waiting.await.unwrap();
+        assert_eq!(limiter.queue_position(&queued), None);
+
+        // a cancelled start leaves the queue
+        let cancelled = InstanceUuid::from("cancelled".to_string());
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            let cancelled = cancelled.clone();
+            async move { limiter.acquire(&cancelled).await }
+        });
+        while !limiter.is_queued(&cancelled) {
+            tokio::task::yield_now().await;
+        }
+        waiting.abort();
+        let _ = waiting.await;
+        assert!(!limiter.is_queued(&cancelled));
+    }
+}
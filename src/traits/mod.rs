@@ -6,20 +6,30 @@ use serde::{Deserialize, Serialize};
 
 use ts_rs::TS;
 
-use self::t_configurable::Game;
+use self::t_configurable::{AvailableUpdate, Game};
 use self::t_player::Player;
-use self::t_server::State;
+use self::t_server::{CrashInfo, State};
 use self::{
-    t_configurable::TConfigurable, t_macro::TMacro, t_player::TPlayerManagement,
-    t_resource::TResourceManagement, t_server::TServer,
+    t_backup::TBackup, t_configurable::TConfigurable, t_macro::TMacro,
+    t_player::TPlayerManagement, t_resource::TResourceManagement, t_server::TServer,
 };
 
+pub mod t_backup;
 pub mod t_configurable;
 pub mod t_macro;
 pub mod t_player;
 pub mod t_resource;
 pub mod t_server;
 
+/// Progress of an instance that is still being set up
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[ts(export)]
+pub struct InstanceSetupProgress {
+    /// Fraction of the setup done, between 0 and 1
+    pub progress: f64,
+    pub message: String,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
 #[ts(export)]
 pub struct InstanceInfo {
@@ -37,15 +47,45 @@ pub struct InstanceInfo {
     pub player_count: Option<u32>,
     pub max_player_count: Option<u32>,
     pub player_list: Option<HashSet<Player>>,
+    pub last_crash: Option<CrashInfo>,
+    pub tags: Vec<String>,
+    /// Only set while the instance is being set up
+    pub setup_progress: Option<InstanceSetupProgress>,
+    pub available_update: Option<AvailableUpdate>,
+    /// The remote core the instance is on, `None` for instances of this core
+    #[serde(default)]
+    pub core_id: Option<String>,
+    /// Position in the queue of instances waiting for a slot to start in
+    #[serde(default)]
+    pub start_queue_position: Option<usize>,
+    /// Seconds since the server last finished starting, `None` while it isn't up
+    #[serde(default)]
+    pub uptime_seconds: Option<u64>,
+    /// Unix timestamp of when the server last finished starting
+    #[serde(default)]
+    pub last_started: Option<i64>,
+    /// Usage of the instance directory against its quota, `None` until it has been measured
+    #[serde(default)]
+    pub disk_usage: Option<InstanceDiskUsage>,
 }
+use crate::disk_quota::{disk_usage_cache, mb_to_bytes, InstanceDiskUsage};
 use crate::minecraft::MinecraftInstance;
 use crate::generic::GenericInstance;
 use crate::prelude::GameInstance;
+use crate::start_limiter::start_limiter;
 use crate::types::InstanceUuid;
 #[async_trait]
 #[enum_dispatch::enum_dispatch]
 pub trait TInstance:
-    TConfigurable + TMacro + TPlayerManagement + TResourceManagement + TServer + Sync + Send + Clone
+    TBackup
+    + TConfigurable
+    + TMacro
+    + TPlayerManagement
+    + TResourceManagement
+    + TServer
+    + Sync
+    + Send
+    + Clone
 {
     async fn get_instance_info(&self) -> InstanceInfo {
         InstanceInfo {
@@ -63,6 +103,24 @@ pub trait TInstance:
             player_count: self.get_player_count().await.ok(),
             max_player_count: self.get_max_player_count().await.ok(),
             player_list: self.get_player_list().await.ok(),
+            last_crash: self.last_crash().await,
+            tags: self.tags().await,
+            setup_progress: None,
+            available_update: self.available_update().await,
+            core_id: None,
+            start_queue_position: start_limiter().queue_position(&self.uuid().await),
+            uptime_seconds: self.uptime_seconds().await,
+            last_started: self.last_started().await,
+            // the directory is only walked for instances with a quota
+            disk_usage: match self.disk_quota_mb().await.map(mb_to_bytes) {
+                Some(quota_bytes) => disk_usage_cache()
+                    .cached(&self.path().await)
+                    .map(|used_bytes| InstanceDiskUsage {
+                        used_bytes,
+                        quota_bytes: Some(quota_bytes),
+                    }),
+                None => None,
+            },
         }
     }
 }
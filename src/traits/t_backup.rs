@@ -0,0 +1,120 @@
+use async_trait::async_trait;
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::backup_history::BackupHistoryEntry;
+use crate::error::{Error, ErrorKind};
+use crate::events::CausedBy;
+use crate::s3::S3Object;
+use crate::types::Snowflake;
+
+/// A file of a backup that failed verification
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct BackupIssue {
+    /// Relative to the backup
+    pub path: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct BackupVerifyReport {
+    pub name: String,
+    pub files_checked: u64,
+    /// Whether the files were compared against the hashes recorded when the backup was taken
+    pub hashes_checked: bool,
+    /// Empty if the backup is intact
+    pub issues: Vec<BackupIssue>,
+}
+
+/// A backup that can be restored, stored locally, in S3 or both
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct BackupInfo {
+    /// The directory or zip archive holding the backup
+    pub name: String,
+    /// Parsed from the name, `None` if it doesn't start with a timestamp
+    pub creation_time: Option<i64>,
+    /// In bytes, as stored on disk, or as uploaded if only stored in S3
+    pub size: u64,
+    pub stored_locally: bool,
+    /// Restoring a backup only stored in S3 downloads it first
+    pub stored_remotely: bool,
+}
+
+#[async_trait]
+#[enum_dispatch::enum_dispatch]
+pub trait TBackup {
+    /// Backups attempted for the instance, oldest first, including the ones that failed or were skipped
+    async fn backup_history(&self) -> Result<Vec<BackupHistoryEntry>, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support backups"),
+        })
+    }
+    /// Stops automatic backups, and rejects backups before risky operations, until resumed
+    async fn pause_backups(&self) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support backups"),
+        })
+    }
+    async fn resume_backups(&self) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support backups"),
+        })
+    }
+    /// Queues a backup, returning the id of the progression event it reports to
+    async fn backup_now(&self, _caused_by: CausedBy) -> Result<Snowflake, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support backups"),
+        })
+    }
+    /// Aborts the backup in progress, discarding the partial backup
+    async fn cancel_backup(&self) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support backups"),
+        })
+    }
+    /// Backups uploaded to S3, keyed by backup name
+    async fn list_remote_backups(&self) -> Result<Vec<S3Object>, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support remote backups"),
+        })
+    }
+    /// Downloads a backup from S3 next to the local ones, so it can be restored
+    async fn fetch_remote_backup(&self, _name: &str) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support remote backups"),
+        })
+    }
+    /// Backups stored locally and in S3, newest first
+    async fn list_backups(&self) -> Result<Vec<BackupInfo>, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support backups"),
+        })
+    }
+    /// Replaces the world with the backup `name`, keeping the current world beside it.
+    /// A backup only stored in S3 is downloaded first. Only while the server is stopped
+    async fn restore_backup(&self, _name: &str, _caused_by: CausedBy) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support backups"),
+        })
+    }
+    /// Checks a local backup for corruption
+    async fn verify_backup(&self, _name: &str) -> Result<BackupVerifyReport, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support backups"),
+        })
+    }
+}
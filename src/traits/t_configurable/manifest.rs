@@ -9,6 +9,31 @@ use ts_rs::TS;
 use crate::error::Error;
 use crate::error::ErrorKind;
 
+/// Bumped whenever the manifests change in a way that makes values built against an older
+/// manifest invalid, e.g. a setting is added, removed or changes type
+pub const MANIFEST_SCHEMA_VERSION: u32 = 1;
+
+fn default_schema_version() -> u32 {
+    MANIFEST_SCHEMA_VERSION
+}
+
+/// Fails with `ApiChanged` if a value was built against another version of the manifest, so
+/// that the client knows to refetch it. Values that don't say which version they were built
+/// against are let through
+fn check_schema_version(manifest_version: u32, value_version: Option<u32>) -> Result<(), Error> {
+    match value_version {
+        Some(value_version) if value_version != manifest_version => Err(Error {
+            kind: ErrorKind::ApiChanged,
+            source: eyre!(
+                "The value was built against schema version {} of the manifest, but it is at version {} now. Refetch the manifest",
+                value_version,
+                manifest_version
+            ),
+        }),
+        _ => Ok(()),
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq)]
 #[ts(export)]
 #[serde(tag = "type", content = "value")]
@@ -291,6 +316,9 @@ pub struct SettingManifest {
     is_secret: bool,                          // ??
     is_required: bool,                        // ??
     is_mutable: bool,                         // CAN change at runtime
+    /// Guidance for filling the setting in, e.g. a recommended value
+    #[serde(default)]
+    hint: Option<String>,
 }
 
 impl SettingManifest {
@@ -327,6 +355,7 @@ impl SettingManifest {
             is_secret,
             is_required: true,
             is_mutable,
+            hint: None,
         }
     }
     pub fn new_optional_value(
@@ -354,6 +383,7 @@ impl SettingManifest {
             is_secret,
             is_required: false,
             is_mutable,
+            hint: None,
         }
     }
 
@@ -381,6 +411,7 @@ impl SettingManifest {
                 is_secret,
                 is_required: true,
                 is_mutable,
+                hint: None,
             }
         } else {
             Self {
@@ -393,10 +424,20 @@ impl SettingManifest {
                 default_value,
                 is_secret,
                 is_mutable,
+                hint: None,
             }
         }
     }
 
+    pub fn with_hint(mut self, hint: String) -> Self {
+        self.hint = Some(hint);
+        self
+    }
+
+    pub fn get_hint(&self) -> Option<&String> {
+        self.hint.as_ref()
+    }
+
     fn set_value_type_safe(&mut self, value: ConfigurableValue) -> Result<(), Error> {
         self.value_type
             .type_check(&value)
@@ -516,14 +557,30 @@ impl SectionManifest {
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[ts(export)]
 pub struct SetupManifest {
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
     pub setting_sections: IndexMap<String, SectionManifest>,
 }
 
 impl SetupManifest {
     pub fn validate_setup_value(&self, value: &SetupValue) -> Result<(), Error> {
+        check_schema_version(self.schema_version, value.schema_version)?;
         for (section_id, section_value) in value.setting_sections.iter() {
             if let Some(section) = self.setting_sections.get(section_id) {
-                section.validate_section(section_value)?;
+                if let Some(invalid) = section
+                    .validate_section(section_value)
+                    .into_iter()
+                    .find(|validation| !validation.valid)
+                {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!(
+                            "Invalid value for setting {}: {}",
+                            invalid.setting_id,
+                            invalid.message.unwrap_or_default()
+                        ),
+                    });
+                }
             } else {
                 return Err(Error {
                     kind: ErrorKind::BadRequest,
@@ -534,13 +591,15 @@ impl SetupManifest {
         Ok(())
     }
 
+    /// Validates every setting of a section, failing only if the section doesn't exist
     pub fn validate_section(
         &self,
         section_key: &str,
         section: &SectionManifestValue,
-    ) -> Result<(), Error> {
+    ) -> Result<Vec<SettingValidation>, Error> {
+        check_schema_version(self.schema_version, section.schema_version)?;
         if let Some(manifest_section) = self.setting_sections.get(section_key) {
-            manifest_section.validate_section(section)
+            Ok(manifest_section.validate_section(section))
         } else {
             Err(Error {
                 kind: ErrorKind::BadRequest,
@@ -553,6 +612,9 @@ impl SetupManifest {
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[ts(export)]
 pub struct SetupValue {
+    /// The `schema_version` of the manifest the value was built against
+    #[serde(default)]
+    pub schema_version: Option<u32>,
     pub name: String,
     pub description: Option<String>,
     pub auto_start: bool,
@@ -578,6 +640,8 @@ impl SetupValue {
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[ts(export)]
 pub struct ConfigurableManifest {
+    #[serde(default = "default_schema_version")]
+    schema_version: u32,
     auto_start: bool,
     restart_on_crash: bool,
     setting_sections: IndexMap<String, SectionManifest>,
@@ -590,6 +654,7 @@ impl ConfigurableManifest {
         setting_sections: IndexMap<String, SectionManifest>,
     ) -> Self {
         Self {
+            schema_version: MANIFEST_SCHEMA_VERSION,
             auto_start,
             restart_on_crash,
             setting_sections,
@@ -741,6 +806,9 @@ impl SettingManifestValue {
 #[derive(Clone, Debug, Serialize, Deserialize, TS)]
 #[ts(export)]
 pub struct SectionManifestValue {
+    /// The `schema_version` of the manifest the value was built against
+    #[serde(default)]
+    pub schema_version: Option<u32>,
     pub(super) settings: IndexMap<String, SettingManifestValue>,
 }
 
@@ -765,18 +833,208 @@ impl SettingManifest {
     }
 }
 
+/// Whether the value given for a setting is valid, and why not if it isn't
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct SettingValidation {
+    pub setting_id: String,
+    pub valid: bool,
+    pub message: Option<String>,
+}
+
 impl SectionManifest {
-    pub fn validate_section(&self, value: &SectionManifestValue) -> Result<(), Error> {
-        for (setting_id, setting_value) in value.settings.iter() {
-            if let Some(setting) = self.settings.get(setting_id) {
-                setting.validate_setting(&setting_value.value)?;
-            } else {
-                return Err(Error {
-                    kind: ErrorKind::BadRequest,
-                    source: eyre!("Setting not found"),
-                });
-            }
+    /// Validates each setting of the section value, in order, so that every invalid one can be reported at once
+    pub fn validate_section(&self, value: &SectionManifestValue) -> Vec<SettingValidation> {
+        value
+            .settings
+            .iter()
+            .map(|(setting_id, setting_value)| {
+                let result = match self.settings.get(setting_id) {
+                    Some(setting) => setting.validate_setting(&setting_value.value),
+                    None => Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Setting not found"),
+                    }),
+                };
+                SettingValidation {
+                    setting_id: setting_id.clone(),
+                    valid: result.is_ok(),
+                    message: result.err().map(|e| e.source.to_string()),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setting_value(value: Option<ConfigurableValue>) -> SettingManifestValue {
+        SettingManifestValue { value }
+    }
+
+    #[test]
+    fn test_validate_section_reports_every_invalid_setting() {
+        let mut settings = IndexMap::new();
+        for setting in [
+            SettingManifest::new_optional_value(
+                "port".to_string(),
+                "Port".to_string(),
+                "".to_string(),
+                None,
+                ConfigurableValueType::UnsignedInteger {
+                    min: Some(1),
+                    max: Some(65535),
+                },
+                None,
+                false,
+                true,
+            ),
+            SettingManifest::new_required_value(
+                "name".to_string(),
+                "Name".to_string(),
+                "".to_string(),
+                ConfigurableValue::String("server".to_string()),
+                None,
+                false,
+                true,
+            ),
+            SettingManifest::new_optional_value(
+                "motd".to_string(),
+                "MOTD".to_string(),
+                "".to_string(),
+                None,
+                ConfigurableValueType::String { regex: None },
+                None,
+                false,
+                true,
+            ),
+        ] {
+            settings.insert(setting.get_identifier().clone(), setting);
         }
-        Ok(())
+        let section = SectionManifest::new(
+            "section".to_string(),
+            "Section".to_string(),
+            "".to_string(),
+            settings,
+        );
+
+        let value = SectionManifestValue {
+            schema_version: None,
+            settings: IndexMap::from([
+                (
+                    "port".to_string(),
+                    setting_value(Some(ConfigurableValue::UnsignedInteger(0))),
+                ),
+                ("name".to_string(), setting_value(None)),
+                (
+                    "motd".to_string(),
+                    setting_value(Some(ConfigurableValue::String("hello".to_string()))),
+                ),
+                (
+                    "unknown".to_string(),
+                    setting_value(Some(ConfigurableValue::Boolean(true))),
+                ),
+            ]),
+        };
+        let validations = section.validate_section(&value);
+        assert_eq!(
+            validations
+                .iter()
+                .map(|validation| (validation.setting_id.as_str(), validation.valid))
+                .collect::<Vec<_>>(),
+            vec![
+                ("port", false),
+                ("name", false),
+                ("motd", true),
+                ("unknown", false)
+            ]
+        );
+        assert_eq!(
+            validations[0].message.as_deref(),
+            Some("Value is too small")
+        );
+        assert_eq!(
+            validations[1].message.as_deref(),
+            Some("Setting is required")
+        );
+        assert_eq!(validations[2].message, None);
+        assert_eq!(validations[3].message.as_deref(), Some("Setting not found"));
+
+        let manifest = SetupManifest {
+            schema_version: MANIFEST_SCHEMA_VERSION,
+            setting_sections: IndexMap::from([("section".to_string(), section)]),
+        };
+        assert_eq!(
+            manifest.validate_section("section", &value).unwrap(),
+            validations
+        );
+        assert!(matches!(
+            manifest
+                .validate_section("missing", &value)
+                .unwrap_err()
+                .kind,
+            ErrorKind::BadRequest
+        ));
+    }
+
+    #[test]
+    fn test_schema_version_mismatch_is_rejected() {
+        let motd = SettingManifest::new_optional_value(
+            "motd".to_string(),
+            "MOTD".to_string(),
+            "".to_string(),
+            None,
+            ConfigurableValueType::String { regex: None },
+            None,
+            false,
+            true,
+        );
+        let section = SectionManifest::new(
+            "section".to_string(),
+            "Section".to_string(),
+            "".to_string(),
+            IndexMap::from([(motd.get_identifier().clone(), motd)]),
+        );
+        // the manifest changed since the client fetched it
+        let manifest = SetupManifest {
+            schema_version: MANIFEST_SCHEMA_VERSION + 1,
+            setting_sections: IndexMap::from([("section".to_string(), section)]),
+        };
+        let section_value = |schema_version| SectionManifestValue {
+            schema_version,
+            settings: IndexMap::from([(
+                "motd".to_string(),
+                setting_value(Some(ConfigurableValue::String("hello".to_string()))),
+            )]),
+        };
+        let setup_value = |schema_version| SetupValue {
+            schema_version,
+            name: "test".to_string(),
+            description: None,
+            auto_start: false,
+            restart_on_crash: false,
+            setting_sections: IndexMap::from([("section".to_string(), section_value(None))]),
+        };
+
+        let e = manifest
+            .validate_section("section", &section_value(Some(MANIFEST_SCHEMA_VERSION)))
+            .unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::ApiChanged));
+        let e = manifest
+            .validate_setup_value(&setup_value(Some(MANIFEST_SCHEMA_VERSION)))
+            .unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::ApiChanged));
+        assert!(e.source.to_string().contains("Refetch the manifest"));
+
+        // built against the current manifest, or not saying which
+        assert!(manifest
+            .validate_section("section", &section_value(Some(MANIFEST_SCHEMA_VERSION + 1)))
+            .is_ok());
+        assert!(manifest
+            .validate_setup_value(&setup_value(Some(MANIFEST_SCHEMA_VERSION + 1)))
+            .is_ok());
+        assert!(manifest.validate_setup_value(&setup_value(None)).is_ok());
     }
 }
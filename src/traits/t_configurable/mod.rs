@@ -1,18 +1,27 @@
 pub mod manifest;
+use std::collections::HashMap;
 pub use std::path::PathBuf;
+use std::path::Path;
 
 use async_trait::async_trait;
 use color_eyre::eyre::eyre;
 use enum_kinds::EnumKind;
+use indexmap::IndexMap;
 pub use serde::{Deserialize, Serialize};
 pub use serde_json;
 use ts_rs::TS;
 
 use self::manifest::ConfigurableManifest;
 use self::manifest::ConfigurableValue;
+use crate::console_buffer::DEFAULT_CONSOLE_BUFFER_LINES;
 use crate::error::Error;
 use crate::error::ErrorKind;
+use crate::implementations::minecraft::hooks::LifecycleHooks;
+use crate::implementations::minecraft::launch::LaunchMode;
+use crate::implementations::minecraft::readiness::ReadinessProbe;
 use crate::implementations::minecraft::Flavour;
+use crate::port_manager::InstancePorts;
+use crate::s3::S3Config;
 use crate::traits::GameInstance;
 use crate::traits::GenericInstance;
 use crate::traits::MinecraftInstance;
@@ -28,6 +37,7 @@ pub enum MinecraftVariant {
     Fabric,
     Paper,
     Spigot,
+    Quilt,
     Other { name: String },
 }
 
@@ -72,10 +82,134 @@ impl From<Flavour> for Game {
             Flavour::Forge { .. } => Self::MinecraftJava {
                 variant: MinecraftVariant::Forge,
             },
+            Flavour::Quilt { .. } => Self::MinecraftJava {
+                variant: MinecraftVariant::Quilt,
+            },
         }
     }
 }
 
+/// Whether newer builds of the server within the same game version are looked for and applied
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum AutoUpdateMode {
+    #[default]
+    Off,
+    /// Only report available updates
+    Notify,
+    /// Apply available updates during the maintenance window
+    Apply,
+}
+
+/// Hours of the day, in the core timezone, during which updates may be applied
+///
+/// The window wraps around midnight if `end_hour` is before `start_hour`,
+/// and spans the whole day if they are equal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct MaintenanceWindow {
+    pub start_hour: u32,
+    pub end_hour: u32,
+}
+
+impl MaintenanceWindow {
+    pub fn contains(&self, hour: u32) -> bool {
+        if self.start_hour < self.end_hour {
+            self.start_hour <= hour && hour < self.end_hour
+        } else if self.start_hour > self.end_hour {
+            hour >= self.start_hour || hour < self.end_hour
+        } else {
+            true
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct AutoUpdateConfig {
+    #[serde(default)]
+    pub mode: AutoUpdateMode,
+    /// Updates are applied at any time if not set
+    #[serde(default)]
+    pub maintenance_window: Option<MaintenanceWindow>,
+}
+
+/// A newer build of the server than the one installed, for the same game version
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct AvailableUpdate {
+    pub current: String,
+    pub available: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct UpdateStatus {
+    pub current_version: String,
+    /// A newer build for the current game version, safe to apply to existing worlds
+    pub patch: Option<AvailableUpdate>,
+    /// The newest game version the server supports, if newer than the current one
+    pub major: Option<String>,
+}
+
+/// Metadata of a world, as stored in its `level.dat`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct WorldInfo {
+    pub level_name: Option<String>,
+    /// Name of the game version the world was last played on, e.g. `1.20.1`
+    pub version: Option<String>,
+    /// Increases with every game version, including snapshots
+    pub data_version: Option<i32>,
+    /// Unix timestamp in milliseconds
+    pub last_played: Option<i64>,
+    pub spawn: Option<(i32, i32, i32)>,
+    pub game_rules: IndexMap<String, String>,
+    pub hardcore: bool,
+    pub difficulty: Option<String>,
+    /// Whether the world was last played on a newer version than the server's, which would corrupt it
+    pub newer_than_server: bool,
+}
+
+/// A world in the instance directory that the server can be switched to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct AvailableWorld {
+    /// Name of the world's directory, which `level-name` is set to to load it
+    pub level_name: String,
+    /// Whether it's the world the server loads
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum GameRuleType {
+    Bool,
+    Int,
+}
+
+/// A gamerule of the world and its current value
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct GameRule {
+    pub name: String,
+    pub rule_type: GameRuleType,
+    pub value: String,
+}
+
+/// A property added, changed or removed by a write of the raw properties file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct PropertyChange {
+    pub key: String,
+    /// `None` if the property was added
+    pub old_value: Option<String>,
+    /// `None` if the property was removed
+    pub new_value: Option<String>,
+}
+
 #[async_trait]
 #[enum_dispatch::enum_dispatch]
 pub trait TConfigurable {
@@ -86,11 +220,76 @@ pub trait TConfigurable {
     async fn version(&self) -> String;
     async fn description(&self) -> String;
     async fn port(&self) -> u32;
+    /// Every port the instance listens on, which only the game port for most
+    async fn ports(&self) -> InstancePorts {
+        InstancePorts {
+            game: self.port().await,
+            rcon: None,
+            query: None,
+        }
+    }
     async fn creation_time(&self) -> i64;
     async fn path(&self) -> PathBuf;
     /// does start when lodestone starts
     async fn auto_start(&self) -> bool;
     async fn restart_on_crash(&self) -> bool;
+    /// number of console lines kept in memory
+    async fn console_buffer_lines(&self) -> usize {
+        DEFAULT_CONSOLE_BUFFER_LINES
+    }
+    /// does console output get saved to disk, surviving a restart of lodestone
+    async fn persist_console(&self) -> bool {
+        false
+    }
+    /// extra environment variables passed to the server process
+    async fn env(&self) -> HashMap<String, String> {
+        HashMap::new()
+    }
+    /// labels used to group and filter instances
+    async fn tags(&self) -> Vec<String> {
+        Vec::new()
+    }
+    /// the most RAM in MB the instance may use, if it has a limit
+    async fn max_ram(&self) -> Option<u32> {
+        None
+    }
+    async fn auto_update(&self) -> AutoUpdateConfig {
+        AutoUpdateConfig::default()
+    }
+    /// the macros run before and after the instance starts and stops
+    async fn lifecycle_hooks(&self) -> LifecycleHooks {
+        LifecycleHooks::default()
+    }
+    /// the most disk in MB the instance directory may take up, if it has a limit
+    async fn disk_quota_mb(&self) -> Option<u64> {
+        None
+    }
+    async fn launch_mode(&self) -> LaunchMode {
+        LaunchMode::default()
+    }
+    /// the paste API crash reports are uploaded to, if they're uploaded
+    async fn crash_report_upload_url(&self) -> Option<String> {
+        None
+    }
+    /// whether the world is saved before the server is told to stop
+    async fn save_before_stop(&self) -> bool {
+        false
+    }
+    /// the java executable the instance is launched with, if it runs on Java
+    async fn java_executable(&self) -> Option<PathBuf> {
+        None
+    }
+    /// the update found by the last update check that hasn't been applied
+    async fn available_update(&self) -> Option<AvailableUpdate> {
+        None
+    }
+    /// looks up the newest builds and versions upstream, without applying anything
+    async fn check_for_updates(&self) -> Result<UpdateStatus, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support checking for updates"),
+        })
+    }
     // setters
     async fn set_name(&mut self, name: String) -> Result<(), Error>;
     async fn set_description(&mut self, description: String) -> Result<(), Error>;
@@ -118,14 +317,299 @@ pub trait TConfigurable {
             source: eyre!("This instance does not support setting backup period"),
         })
     }
+    /// The oldest backups are deleted once there are more than `max_backups`, `None` keeps all.
+    /// Backups taken before risky operations don't count towards it and are never deleted
+    async fn set_max_backups(&mut self, _max_backups: Option<u32>) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support limiting the number of backups"),
+        })
+    }
+    /// Backups taken from now on are stored as zip archives, existing ones are left as they are
+    async fn set_compress_backups(&mut self, _enabled: bool) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support compressing backups"),
+        })
+    }
+
+    /// `debounce_secs` is how recent a backup has to be for the backup on stop to be skipped
+    async fn set_backup_on_stop(
+        &mut self,
+        _enabled: bool,
+        _debounce_secs: Option<u32>,
+    ) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support backing up on stop"),
+        })
+    }
+
+    /// Whether to back up the world before operations that risk it, like changing the version
+    async fn set_backup_before_risky_ops(&mut self, _enabled: bool) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support backing up before risky operations"),
+        })
+    }
+
+    /// Whether to save the world before the server is told to stop
+    async fn set_save_before_stop(&mut self, _enabled: bool) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support saving before stopping"),
+        })
+    }
+
+    /// Nice level of the server process from its next start, the default if `None`
+    async fn set_process_priority(&mut self, _priority: Option<i32>) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support setting process priority"),
+        })
+    }
+
+    /// Cores the server process may run on from its next start, bit `n` for core `n`. Any if `None`
+    async fn set_cpu_affinity(&mut self, _cpu_affinity: Option<u64>) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support setting CPU affinity"),
+        })
+    }
 
-    async fn change_version(&mut self, _version: String) -> Result<(), Error> {
+    /// Seconds the server has to stop gracefully before it's terminated, the default if `None`
+    async fn set_stop_timeout(&mut self, _timeout_secs: Option<u32>) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support setting a stop timeout"),
+        })
+    }
+
+    /// The server is killed if `probe` doesn't pass within `timeout_secs` of launching
+    async fn set_readiness_probe(
+        &mut self,
+        _probe: ReadinessProbe,
+        _timeout_secs: Option<u32>,
+    ) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support readiness probes"),
+        })
+    }
+
+    async fn set_backup_io_limit(&mut self, _bytes_per_sec: Option<u64>) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support limiting backup IO"),
+        })
+    }
+
+    async fn set_backup_destination(&mut self, _destination: Option<PathBuf>) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support setting backup destination"),
+        })
+    }
+
+    async fn set_s3_backup(&mut self, _s3_backup: Option<S3Config>) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support uploading backups to S3"),
+        })
+    }
+
+    /// in MB
+    async fn set_min_ram(&mut self, _min_ram: u32) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support setting min RAM"),
+        })
+    }
+
+    /// in MB
+    async fn set_max_ram(&mut self, _max_ram: u32) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support setting max RAM"),
+        })
+    }
+
+    async fn set_oom_max_ram_ceiling(&mut self, _ceiling: Option<u32>) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support setting max RAM ceiling"),
+        })
+    }
+
+    async fn set_console_buffer_lines(&mut self, _lines: Option<usize>) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support setting console buffer size"),
+        })
+    }
+    async fn set_persist_console(&mut self, _persist_console: bool) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support persisting console output"),
+        })
+    }
+    async fn set_env(&mut self, _env: HashMap<String, String>) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support setting environment variables"),
+        })
+    }
+    async fn set_tags(&mut self, _tags: Vec<String>) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support tags"),
+        })
+    }
+    async fn set_auto_update(&mut self, _auto_update: AutoUpdateConfig) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support automatic updates"),
+        })
+    }
+    async fn set_lifecycle_hooks(&mut self, _hooks: LifecycleHooks) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support lifecycle hooks"),
+        })
+    }
+    async fn set_disk_quota_mb(&mut self, _quota_mb: Option<u64>) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support disk quotas"),
+        })
+    }
+    async fn set_launch_mode(&mut self, _launch_mode: LaunchMode) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support changing how it is launched"),
+        })
+    }
+    async fn set_crash_report_upload_url(&mut self, _url: Option<String>) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support uploading crash reports"),
+        })
+    }
+
+    /// Switches the server to `version`, refusing a version older than the world unless `allow_downgrade`
+    async fn change_version(
+        &mut self,
+        _version: String,
+        _allow_downgrade: bool,
+    ) -> Result<(), Error> {
         Err(Error {
             kind: ErrorKind::UnsupportedOperation,
             source: eyre!("This instance does not support changing version"),
         })
     }
 
+    /// Metadata of the world the server loads
+    async fn world_info(&self) -> Result<WorldInfo, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not have a world"),
+        })
+    }
+
+    /// Replaces the world with the one in the zip at `path_to_archive`, backing up the current one.
+    ///
+    /// Refuses a world saved by a newer version than the server's unless `allow_downgrade`
+    async fn replace_world(
+        &mut self,
+        _path_to_archive: &Path,
+        _allow_downgrade: bool,
+    ) -> Result<WorldInfo, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not have a world"),
+        })
+    }
+
+    /// The worlds in the instance directory
+    async fn list_worlds(&self) -> Result<Vec<AvailableWorld>, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not have a world"),
+        })
+    }
+
+    /// Makes the server load the world in `level_name` from its next start. Only while it's stopped
+    async fn set_active_world(&mut self, _level_name: String) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not have a world"),
+        })
+    }
+
+    /// The gamerules the server's version has and their values in the world
+    async fn game_rules(&self) -> Result<Vec<GameRule>, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not have gamerules"),
+        })
+    }
+
+    /// Sets a gamerule, live if the server is running and in the world's files otherwise
+    async fn set_game_rule(&mut self, _rule: String, _value: String) -> Result<GameRule, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not have gamerules"),
+        })
+    }
+
+    /// The properties file of the instance as is, including comments
+    async fn raw_properties(&self) -> Result<String, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not have a properties file"),
+        })
+    }
+
+    /// Replaces the properties file, only if every line of it is valid
+    async fn set_raw_properties(
+        &mut self,
+        _properties: String,
+    ) -> Result<Vec<PropertyChange>, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not have a properties file"),
+        })
+    }
+
+    /// Replaces the properties with `properties`, only if every one of them is valid, applying
+    /// what can be to the running server
+    async fn set_properties(
+        &mut self,
+        _properties: IndexMap<String, String>,
+    ) -> Result<Vec<PropertyChange>, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not have a properties file"),
+        })
+    }
+
+    /// Re-reads the config and properties from disk after they were edited outside of the core,
+    /// without restarting the server, applying what can be to it
+    async fn reload_config(&mut self) -> Result<Vec<PropertyChange>, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support reloading its config"),
+        })
+    }
+
+    /// Writes a portable bundle of the instance to `dest`, which another core can import
+    async fn export_bundle(&self, _dest: &Path) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support exporting"),
+        })
+    }
+
     async fn configurable_manifest(&mut self) -> ConfigurableManifest;
 
     async fn update_configurable(
@@ -4,9 +4,11 @@ use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
 use crate::error::{Error, ErrorKind};
+use crate::events::CausedBy;
 use crate::implementations::generic::player::GenericPlayer;
 use crate::minecraft::player::MinecraftPlayer;
 use crate::traits::GameInstance;
+use crate::types::InstanceUuid;
 #[enum_dispatch::enum_dispatch]
 pub trait TPlayer {
     fn get_id(&self) -> String;
@@ -35,6 +37,102 @@ impl Hash for Player {
     }
 }
 
+/// State of an online player, as far as the game can report it
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, TS)]
+#[ts(export)]
+pub struct PlayerDetails {
+    pub position: Option<(f64, f64, f64)>,
+    pub dimension: Option<String>,
+    pub health: Option<f32>,
+    pub game_mode: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, TS)]
+#[ts(export)]
+pub struct OnlinePlayer {
+    pub player: Player,
+    /// `None` if the instance has no operators
+    pub is_op: Option<bool>,
+    /// `None` if the instance has no whitelist
+    pub is_whitelisted: Option<bool>,
+    /// `None` if the details can't be queried, e.g. because RCON isn't connected
+    pub details: Option<PlayerDetails>,
+}
+
+/// Players online on one running instance
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, TS)]
+#[ts(export)]
+pub struct InstancePlayers {
+    pub instance_uuid: InstanceUuid,
+    pub instance_name: String,
+    pub player_count: u32,
+    /// `None` if the instance didn't report it
+    pub max_player_count: Option<u32>,
+    pub players: Vec<String>,
+}
+
+/// Players online across the instances a user can view
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, TS)]
+#[ts(export)]
+pub struct PlayersOnline {
+    pub total: u32,
+    pub instances: Vec<InstancePlayers>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum GameMode {
+    Survival,
+    Creative,
+    Adventure,
+    Spectator,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum TeleportDestination {
+    Coordinates { x: f64, y: f64, z: f64 },
+    Player { name: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum Weather {
+    Clear,
+    Rain,
+    Thunder,
+}
+
+/// A moderation action on an online player or on the world of an instance
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum ModerationAction {
+    SetGameMode {
+        player: String,
+        game_mode: GameMode,
+    },
+    Teleport {
+        player: String,
+        destination: TeleportDestination,
+    },
+    Give {
+        player: String,
+        item: String,
+        count: u32,
+    },
+    SetTime {
+        ticks: u32,
+    },
+    SetWeather {
+        weather: Weather,
+        duration_secs: Option<u32>,
+    },
+}
+
 #[async_trait]
 #[enum_dispatch::enum_dispatch]
 pub trait TPlayerManagement {
@@ -56,6 +154,24 @@ pub trait TPlayerManagement {
             source: eyre!("Getting player list is unsupported for this instance"),
         })
     }
+    /// The online players, with whatever details the instance can provide
+    async fn get_online_players(&self) -> Result<Vec<OnlinePlayer>, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Getting online players is unsupported for this instance"),
+        })
+    }
+    /// Validates and performs a moderation action, returns the response of the server
+    async fn moderate(
+        &self,
+        _action: ModerationAction,
+        _caused_by: CausedBy,
+    ) -> Result<String, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Moderation is unsupported for this instance"),
+        })
+    }
 
     async fn set_max_player_count(&mut self, _max_player_count: u32) -> Result<(), Error> {
         Err(Error {
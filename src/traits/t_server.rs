@@ -4,7 +4,9 @@ use serde::{Deserialize, Serialize};
 
 use ts_rs::TS;
 
-use crate::events::CausedBy;
+use crate::command_history::CommandHistoryEntry;
+use crate::error::ErrorKind;
+use crate::events::{CausedBy, Event};
 use crate::Error;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS, Copy)]
@@ -16,6 +18,12 @@ pub enum State {
     Stopping,
     Stopped,
     Error,
+    /// The instance is still being created and can't be started yet
+    SettingUp,
+    /// The instance is on a remote core that can't be reached
+    Unavailable,
+    /// The directory of the instance was deleted from under the core
+    Missing,
 }
 
 pub enum StateAction {
@@ -23,6 +31,10 @@ pub enum StateAction {
     UserStop,
     InstanceStart,
     InstanceStop,
+    /// The directory of the instance was found deleted
+    DirectoryGone,
+    /// The directory of a missing instance is back
+    DirectoryRestored,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
@@ -54,6 +66,84 @@ pub struct MonitorReport {
     pub start_time: Option<u64>,
 }
 
+/// How fast the server is ticking, 20 ticks per second being full speed for Minecraft
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq, Default)]
+#[ts(export)]
+pub struct TickPerformance {
+    /// Ticks per second, over the shortest period the server reports
+    pub tps: Option<f64>,
+    /// Ticks per second over the last 5 minutes, only some servers report it
+    pub tps_5m: Option<f64>,
+    /// Ticks per second over the last 15 minutes, only some servers report it
+    pub tps_15m: Option<f64>,
+    /// Average milliseconds a tick takes
+    pub mspt: Option<f64>,
+    /// Unix timestamp in seconds of when it was measured
+    pub measured_at: i64,
+}
+
+/// A command answered over a new RCON connection, opened with the current RCON settings
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq, Eq)]
+#[ts(export)]
+pub struct RconTestResult {
+    pub port: u32,
+    /// What the server answered to the test command
+    pub response: String,
+    /// Milliseconds it took to connect, authenticate and get the answer
+    pub latency_ms: u64,
+}
+
+/// The crash report the server wrote when it crashed
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq, Eq)]
+#[ts(export)]
+pub struct CrashReport {
+    /// Name of the report in the `crash-reports` directory of the instance, where it can be read
+    /// through the instance files
+    pub file_name: String,
+    /// Shareable link to the report, if it was uploaded
+    pub url: Option<String>,
+}
+
+/// Information about the last time the server process exited unexpectedly
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq)]
+#[ts(export)]
+pub struct CrashInfo {
+    pub time: i64,
+    pub exit_code: Option<i32>,
+    /// The signal that terminated the process, only available on unix
+    pub signal: Option<i32>,
+    /// Whether the process was most likely killed for running out of memory
+    pub is_oom: bool,
+    /// The last lines of console output before the crash
+    pub console_tail: Vec<String>,
+    /// `None` if the server didn't write a crash report, e.g. when it was killed
+    #[serde(default)]
+    pub crash_report: Option<CrashReport>,
+}
+
+impl CrashInfo {
+    pub fn new(exit_code: Option<i32>, signal: Option<i32>, console_tail: Vec<String>) -> Self {
+        // 137 is 128 + SIGKILL, which is what the OOM killer sends
+        let is_oom = exit_code == Some(137) || signal == Some(9);
+        Self {
+            time: chrono::Utc::now().timestamp(),
+            exit_code,
+            signal,
+            is_oom,
+            console_tail,
+            crash_report: None,
+        }
+    }
+
+    pub fn from_exit_status(status: std::process::ExitStatus, console_tail: Vec<String>) -> Self {
+        #[cfg(unix)]
+        let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+        #[cfg(not(unix))]
+        let signal = None;
+        Self::new(status.code(), signal, console_tail)
+    }
+}
+
 impl ToString for State {
     fn to_string(&self) -> String {
         match self {
@@ -62,6 +152,9 @@ impl ToString for State {
             State::Stopping => "Stopping".to_string(),
             State::Stopped => "Stopped".to_string(),
             State::Error => "Error".to_string(),
+            State::SettingUp => "SettingUp".to_string(),
+            State::Unavailable => "Unavailable".to_string(),
+            State::Missing => "Missing".to_string(),
         }
     }
 }
@@ -73,6 +166,15 @@ impl State {
         on_transit: Option<&dyn Fn(State)>,
     ) -> Result<State, Error> {
         let state = match (*self, action) {
+            (State::Stopped | State::Error, StateAction::DirectoryGone) => Ok(State::Missing),
+            (State::Missing, StateAction::DirectoryGone) => {
+                Err(eyre!("The instance is already missing"))
+            }
+            (_, StateAction::DirectoryGone) => Err(eyre!(
+                "Cannot mark an instance as missing while its server is up"
+            )),
+            (State::Missing, StateAction::DirectoryRestored) => Ok(State::Stopped),
+            (_, StateAction::DirectoryRestored) => Err(eyre!("The instance isn't missing")),
             (State::Starting, StateAction::UserStart) => {
                 Err(eyre!("Cannot start an instance that is already starting"))
             }
@@ -97,7 +199,29 @@ impl State {
             }
             (State::Error, StateAction::UserStart) => todo!(),
             (State::Error, StateAction::UserStop) => todo!(),
-        }?;
+            (State::SettingUp, StateAction::UserStart) => {
+                Err(eyre!("Cannot start an instance that is still being set up"))
+            }
+            (State::SettingUp, StateAction::UserStop) => {
+                Err(eyre!("Cannot stop an instance that is still being set up"))
+            }
+            (State::Unavailable, StateAction::UserStart) => {
+                Err(eyre!("Cannot start an instance that is unavailable"))
+            }
+            (State::Unavailable, StateAction::UserStop) => {
+                Err(eyre!("Cannot stop an instance that is unavailable"))
+            }
+            (State::Missing, StateAction::UserStart) => {
+                Err(eyre!("Cannot start an instance whose directory is gone"))
+            }
+            (State::Missing, StateAction::UserStop) => {
+                Err(eyre!("Cannot stop an instance whose directory is gone"))
+            }
+        }
+        .map_err(|source| Error {
+            kind: ErrorKind::InvalidInstanceState,
+            source,
+        })?;
         if let Some(on_transit) = on_transit {
             on_transit(state);
         }
@@ -115,16 +239,387 @@ impl State {
     }
 }
 
+/// A part of an installation checked by `verify_installation`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub enum InstallationComponent {
+    ServerJar,
+    Java,
+    Libraries,
+    Eula,
+    Properties,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct InstallationIssue {
+    pub component: InstallationComponent,
+    pub message: String,
+    pub suggested_fix: String,
+    /// Whether `repair_installation` can fix this issue
+    pub repairable: bool,
+}
+
+/// Issues that would keep an instance from launching
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct VerifyReport {
+    pub issues: Vec<InstallationIssue>,
+}
+
+impl VerifyReport {
+    pub fn is_launchable(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Default for how long a server has to stop after being asked to, before it's terminated
+pub const DEFAULT_STOP_TIMEOUT_SECS: u32 = 60;
+
+/// How an instance is stopped gracefully
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct StopCommand {
+    /// Sent to the server's console to stop it, `None` if it has no such command
+    pub command: Option<String>,
+    /// Seconds the server has to stop before it's sent SIGTERM, and as long again before it's killed
+    pub timeout_secs: u32,
+}
+
+impl Default for StopCommand {
+    fn default() -> Self {
+        Self {
+            command: None,
+            timeout_secs: DEFAULT_STOP_TIMEOUT_SECS,
+        }
+    }
+}
+
+/// Seconds the server has been up, given when it last finished starting. Only a running or
+/// stopping server is up
+pub fn uptime_seconds(state: State, last_started: Option<i64>, now: i64) -> Option<u64> {
+    match state {
+        State::Running | State::Stopping => {
+            last_started.map(|last_started| (now - last_started).max(0) as u64)
+        }
+        _ => None,
+    }
+}
+
 use crate::traits::GameInstance;
 
 #[async_trait]
 #[enum_dispatch::enum_dispatch]
 pub trait TServer {
     async fn start(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error>;
+    /// Starts the instance even if its world was saved by a newer version of the game,
+    /// which the server may corrupt
+    async fn start_allowing_downgrade(
+        &mut self,
+        caused_by: CausedBy,
+        block: bool,
+    ) -> Result<(), Error> {
+        self.start(caused_by, block).await
+    }
     async fn stop(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error>;
+    /// How `stop` asks the server to stop, and how long it waits before escalating
+    async fn stop_command(&self) -> StopCommand {
+        StopCommand::default()
+    }
     async fn restart(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error>;
     async fn kill(&mut self, caused_by: CausedBy) -> Result<(), Error>;
+    /// Kills any process of the instance and forces it to `Stopped`, whatever state it is in.
+    ///
+    /// An escape hatch for when the instance is stuck in a state it can't leave normally
+    async fn force_stop(&mut self, _caused_by: CausedBy) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support force stopping"),
+        })
+    }
     async fn state(&self) -> State;
     async fn send_command(&self, command: &str, caused_by: CausedBy) -> Result<(), Error>;
     async fn monitor(&self) -> MonitorReport;
+    /// How fast the server is ticking, measured at most every few seconds
+    async fn tick_performance(&self) -> Result<TickPerformance, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not report tick performance"),
+        })
+    }
+    /// Connects to RCON with the current settings and runs a harmless command, failing with why
+    /// it couldn't if it doesn't work
+    async fn test_rcon(&self) -> Result<RconTestResult, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support RCON"),
+        })
+    }
+    async fn last_crash(&self) -> Option<CrashInfo> {
+        None
+    }
+    /// Unix timestamp of when the server last finished starting
+    async fn last_started(&self) -> Option<i64> {
+        None
+    }
+    /// Seconds since the server last finished starting, `None` while it isn't up
+    async fn uptime_seconds(&self) -> Option<u64> {
+        uptime_seconds(
+            self.state().await,
+            self.last_started().await,
+            chrono::Utc::now().timestamp(),
+        )
+    }
+    /// Console output saved to disk in previous sessions, oldest first
+    async fn console_history(&self) -> Vec<Event> {
+        Vec::new()
+    }
+    /// Commands sent to the instance, oldest first
+    async fn command_history(&self) -> Vec<CommandHistoryEntry> {
+        Vec::new()
+    }
+    /// The command line the server would be launched with, without launching it
+    async fn get_launch_command(&self) -> Result<String, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not expose its launch command"),
+        })
+    }
+    /// Corrects the state if it disagrees with whether the server process is alive,
+    /// e.g. when the process exited without the exit being noticed. Called periodically
+    async fn reconcile_state(&self) -> Result<(), Error> {
+        Ok(())
+    }
+    /// Checks that the server can be launched, without launching it
+    async fn verify_installation(&self) -> Result<VerifyReport, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support verifying its installation"),
+        })
+    }
+    /// Fixes the repairable issues `verify_installation` finds, returns the report after repairing
+    async fn repair_installation(&mut self) -> Result<VerifyReport, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support repairing its installation"),
+        })
+    }
+    /// Looks for a newer build of the server and applies it if the instance is set to.
+    /// Called periodically
+    async fn check_for_update(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use tokio::sync::Mutex;
+
+    use super::{uptime_seconds, CrashInfo, State, StateAction};
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn test_crash_info_oom() {
+        assert!(CrashInfo::new(Some(137), None, vec![]).is_oom);
+        assert!(CrashInfo::new(None, Some(9), vec![]).is_oom);
+        assert!(!CrashInfo::new(Some(1), None, vec![]).is_oom);
+    }
+
+    #[test]
+    fn test_uptime_across_restarts() {
+        let mut state = State::Stopped;
+        let mut last_started = None;
+        assert_eq!(uptime_seconds(state, last_started, 100), None);
+
+        state.try_transition(StateAction::UserStart, None).unwrap();
+        // not up until it finished starting
+        assert_eq!(uptime_seconds(state, last_started, 100), None);
+        state
+            .try_transition(StateAction::InstanceStart, None)
+            .unwrap();
+        last_started = Some(110);
+        assert_eq!(uptime_seconds(state, last_started, 200), Some(90));
+        state.try_transition(StateAction::UserStop, None).unwrap();
+        assert_eq!(uptime_seconds(state, last_started, 205), Some(95));
+        state
+            .try_transition(StateAction::InstanceStop, None)
+            .unwrap();
+        // the last start is kept, but a stopped server has no uptime
+        assert_eq!(uptime_seconds(state, last_started, 210), None);
+
+        // crashed and restarted on crash, uptime counts from the restart
+        state
+            .try_transition(StateAction::InstanceStart, None)
+            .unwrap();
+        last_started = Some(300);
+        state
+            .try_transition(StateAction::InstanceStop, None)
+            .unwrap();
+        assert_eq!(uptime_seconds(state, last_started, 400), None);
+        state.try_transition(StateAction::UserStart, None).unwrap();
+        state
+            .try_transition(StateAction::InstanceStart, None)
+            .unwrap();
+        last_started = Some(410);
+        assert_eq!(uptime_seconds(state, last_started, 420), Some(10));
+
+        // a clock that went backwards doesn't underflow
+        assert_eq!(uptime_seconds(state, last_started, 400), Some(0));
+    }
+
+    #[test]
+    fn test_missing_instance_cannot_start_or_stop() {
+        for action in [StateAction::UserStart, StateAction::UserStop] {
+            let e = State::Missing.try_new_state(action, None).unwrap_err();
+            assert!(matches!(e.kind, ErrorKind::InvalidInstanceState));
+        }
+    }
+
+    #[test]
+    fn test_directory_gone_and_restored() {
+        for state in [State::Stopped, State::Error] {
+            let mut state = state;
+            let transitioned = std::cell::Cell::new(None);
+            state
+                .try_transition(
+                    StateAction::DirectoryGone,
+                    Some(&|new_state| transitioned.set(Some(new_state))),
+                )
+                .unwrap();
+            assert_eq!(state, State::Missing);
+            assert_eq!(transitioned.get(), Some(State::Missing));
+        }
+
+        // a server that's up keeps its state, as does an instance that's already missing
+        for state in [
+            State::Starting,
+            State::Running,
+            State::Stopping,
+            State::SettingUp,
+            State::Unavailable,
+            State::Missing,
+        ] {
+            let mut new_state = state;
+            let e = new_state
+                .try_transition(StateAction::DirectoryGone, None)
+                .unwrap_err();
+            assert!(matches!(e.kind, ErrorKind::InvalidInstanceState));
+            assert_eq!(new_state, state);
+        }
+
+        let mut state = State::Missing;
+        state
+            .try_transition(StateAction::DirectoryRestored, None)
+            .unwrap();
+        assert_eq!(state, State::Stopped);
+        assert!(state
+            .try_transition(StateAction::DirectoryRestored, None)
+            .is_err());
+        assert_eq!(state, State::Stopped);
+    }
+
+    #[tokio::test]
+    async fn test_overlapping_start_stop() {
+        let state = Arc::new(Mutex::new(State::Stopped));
+        let processes = Arc::new(AtomicUsize::new(0));
+        let mut tasks = Vec::new();
+        for i in 0..16 {
+            let state = state.clone();
+            let processes = processes.clone();
+            tasks.push(tokio::spawn(async move {
+                let action = if i % 2 == 0 {
+                    StateAction::UserStart
+                } else {
+                    StateAction::UserStop
+                };
+                let is_start = matches!(action, StateAction::UserStart);
+                state.lock().await.try_transition(action, None)?;
+                if is_start {
+                    processes.fetch_add(1, Ordering::SeqCst);
+                    tokio::task::yield_now().await;
+                    state
+                        .lock()
+                        .await
+                        .try_transition(StateAction::InstanceStart, None)?;
+                }
+                Ok::<bool, crate::Error>(is_start)
+            }));
+        }
+        let mut started = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                Ok(true) => started += 1,
+                Ok(false) => {}
+                Err(e) => assert!(matches!(e.kind, ErrorKind::InvalidInstanceState)),
+            }
+        }
+        assert_eq!(started, 1);
+        assert_eq!(processes.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            *state.lock().await,
+            State::Running | State::Stopping
+        ));
+    }
+
+    /// Starts and stops interleaved the way `MinecraftInstance` runs them under its lifecycle lock:
+    /// the lock is held from the transition until the process is spawned or told to stop, and
+    /// the server reports that it's up or has exited after the lock is released
+    #[tokio::test]
+    async fn test_concurrent_start_stop_ordering() {
+        let lifecycle_lock = Arc::new(Mutex::new(()));
+        let state = Arc::new(Mutex::new(State::Stopped));
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut tasks = Vec::new();
+        for i in 0..32 {
+            let lifecycle_lock = lifecycle_lock.clone();
+            let state = state.clone();
+            let log = log.clone();
+            tasks.push(tokio::spawn(async move {
+                let is_start = i % 2 == 0;
+                let lifecycle_guard = lifecycle_lock.lock().await;
+                if is_start {
+                    state
+                        .lock()
+                        .await
+                        .try_transition(StateAction::UserStart, None)?;
+                    // prelaunch and building the command happen between the transition and the spawn
+                    tokio::task::yield_now().await;
+                    log.lock().unwrap().push("spawn");
+                } else {
+                    state
+                        .lock()
+                        .await
+                        .try_transition(StateAction::UserStop, None)?;
+                    tokio::task::yield_now().await;
+                    log.lock().unwrap().push("exit");
+                }
+                drop(lifecycle_guard);
+                tokio::task::yield_now().await;
+                let reported = if is_start {
+                    StateAction::InstanceStart
+                } else {
+                    StateAction::InstanceStop
+                };
+                state.lock().await.try_transition(reported, None)?;
+                Ok::<(), crate::Error>(())
+            }));
+        }
+        for task in tasks {
+            if let Err(e) = task.await.unwrap() {
+                assert!(matches!(e.kind, ErrorKind::InvalidInstanceState));
+            }
+        }
+        let log = log.lock().unwrap();
+        assert!(!log.is_empty());
+        // a process is never spawned while another runs, nor stopped before it's spawned
+        for (i, step) in log.iter().enumerate() {
+            assert_eq!(*step, if i % 2 == 0 { "spawn" } else { "exit" });
+        }
+        let running = log.last() == Some(&"spawn");
+        assert_eq!(*state.lock().await == State::Running, running);
+        assert_eq!(*state.lock().await == State::Stopped, !running);
+    }
 }
@@ -130,6 +130,9 @@ pub struct DotLodestoneConfig {
     game_type: GameType,
     uuid: InstanceUuid,
     creation_time: i64,
+    /// PID of the server process spawned for this instance, if it is (or was) running
+    #[serde(default)]
+    pid: Option<u32>,
 }
 
 impl From<RestoreConfigV042> for DotLodestoneConfig {
@@ -145,6 +148,7 @@ impl From<RestoreConfigV042> for DotLodestoneConfig {
             game_type,
             uuid: config.uuid,
             creation_time: config.creation_time,
+            pid: None,
         }
     }
 }
@@ -155,6 +159,7 @@ impl From<DotLodestoneConfigV043> for DotLodestoneConfig {
             game_type: config.game_type,
             uuid: config.uuid,
             creation_time: config.creation_time,
+            pid: None,
         }
     }
 }
@@ -165,6 +170,7 @@ impl DotLodestoneConfig {
             game_type,
             uuid,
             creation_time: chrono::Utc::now().timestamp(),
+            pid: None,
         }
     }
 
@@ -178,6 +184,14 @@ impl DotLodestoneConfig {
     pub fn game_type(&self) -> &GameType {
         &self.game_type
     }
+
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    pub fn set_pid(&mut self, pid: Option<u32>) {
+        self.pid = pid;
+    }
 }
 
 #[test]
@@ -190,3 +204,12 @@ fn test_instance_uuid() {
     let uuid2: InstanceUuid = serde_json::from_str(&uuid_str).unwrap();
     assert_eq!(uuid1, uuid2);
 }
+
+#[test]
+fn test_dot_lodestone_config_without_pid() {
+    let config: DotLodestoneConfig = serde_json::from_str(
+        r#"{"game_type":"MinecraftJava","uuid":"INSTANCE_test","creation_time":0}"#,
+    )
+    .unwrap();
+    assert_eq!(config.pid(), None);
+}
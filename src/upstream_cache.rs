@@ -0,0 +1,134 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::eyre;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::error::Error;
+use crate::prelude::path_to_stores;
+use crate::upstream_http;
+
+/// Default number of seconds a cached upstream response is considered fresh
+pub const DEFAULT_UPSTREAM_CACHE_TTL_SECS: u64 = 600;
+
+static UPSTREAM_CACHE_TTL_SECS: AtomicU64 = AtomicU64::new(DEFAULT_UPSTREAM_CACHE_TTL_SECS);
+
+pub fn set_upstream_cache_ttl(ttl: Duration) {
+    UPSTREAM_CACHE_TTL_SECS.store(ttl.as_secs(), Ordering::Relaxed);
+}
+
+fn upstream_cache_ttl() -> Duration {
+    Duration::from_secs(UPSTREAM_CACHE_TTL_SECS.load(Ordering::Relaxed))
+}
+
+/// GETs `url` as text, caching the response on disk.
+///
+/// A fresh cached response is returned without hitting the network.
+/// If the upstream can't be reached, a stale cached response is returned instead,
+/// so that setups can proceed during a transient outage.
+pub async fn cached_get_text(url: &str) -> Result<String, Error> {
+    cached_get_text_in(
+        &path_to_stores().join("upstream_cache"),
+        url,
+        upstream_cache_ttl(),
+    )
+    .await
+}
+
+/// Like `cached_get_text`, but with its own TTL for data that changes more often
+pub async fn cached_get_text_with_ttl(url: &str, ttl: Duration) -> Result<String, Error> {
+    cached_get_text_in(&path_to_stores().join("upstream_cache"), url, ttl).await
+}
+
+/// The file a response is cached in, named by the hash of the url
+/// since sanitizing the url itself maps different urls to the same name
+fn cache_file_name(url: &str) -> String {
+    format!("{:x}", Sha256::digest(url.as_bytes()))
+}
+
+async fn cached_get_text_in(cache_dir: &Path, url: &str, ttl: Duration) -> Result<String, Error> {
+    let cache_path = cache_dir.join(cache_file_name(url));
+    let cache_age = tokio::fs::metadata(&cache_path)
+        .await
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+    if cache_age.map(|age| age < ttl).unwrap_or(false) {
+        if let Ok(cached) = tokio::fs::read_to_string(&cache_path).await {
+            return Ok(cached);
+        }
+    }
+    match upstream_http::get_text(url).await {
+        Ok(text) => {
+            let _ = tokio::fs::create_dir_all(cache_dir).await;
+            if let Err(e) = tokio::fs::write(&cache_path, &text).await {
+                warn!("Failed to cache response from {}: {}", url, e);
+            }
+            Ok(text)
+        }
+        Err(e) => match tokio::fs::read_to_string(&cache_path).await {
+            Ok(cached) => {
+                warn!(
+                    "Failed to reach {}, using a cached response instead: {}",
+                    url, e.source
+                );
+                Ok(cached)
+            }
+            Err(_) => Err(Error {
+                kind: e.kind,
+                source: eyre!(
+                    "Failed to download {}, the service may be down and there is no cached copy to fall back on: {}",
+                    url,
+                    e.source
+                ),
+            }),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cached_get_text_fallback() {
+        let temp_dir = tempdir::TempDir::new("test_upstream_cache").unwrap();
+        // nothing listens on port 1
+        let url = "http://127.0.0.1:1/version_manifest.json";
+
+        assert!(
+            cached_get_text_in(temp_dir.path(), url, Duration::from_secs(600))
+                .await
+                .is_err()
+        );
+
+        tokio::fs::write(temp_dir.path().join(cache_file_name(url)), "cached")
+            .await
+            .unwrap();
+        // fresh cache is used without hitting the network
+        assert_eq!(
+            cached_get_text_in(temp_dir.path(), url, Duration::from_secs(600))
+                .await
+                .unwrap(),
+            "cached"
+        );
+        // stale cache is used when the upstream is down
+        assert_eq!(
+            cached_get_text_in(temp_dir.path(), url, Duration::ZERO)
+                .await
+                .unwrap(),
+            "cached"
+        );
+    }
+
+    #[test]
+    fn test_cache_file_name_distinct() {
+        // these sanitize to the same file name
+        assert_ne!(
+            cache_file_name("https://meta.example.com/v2/versions?a=1"),
+            cache_file_name("https://meta.example.com/v2/versions/a=1")
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! Timeouts for requests to upstreams (Mojang, Paper, Adoptium, ...), so that a stalled connection
+//! fails the setup or download instead of hanging it
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use futures_util::{Stream, StreamExt};
+use reqwest::{Client, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+pub const DEFAULT_READ_TIMEOUT_SECS: u64 = 30;
+
+static CONNECT_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(DEFAULT_CONNECT_TIMEOUT_SECS);
+static READ_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(DEFAULT_READ_TIMEOUT_SECS);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct HttpTimeouts {
+    /// Seconds to wait for a connection to an upstream
+    pub connect_timeout_secs: u64,
+    /// Seconds to wait for a response, and then between chunks of a download
+    pub read_timeout_secs: u64,
+}
+
+impl Default for HttpTimeouts {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: DEFAULT_CONNECT_TIMEOUT_SECS,
+            read_timeout_secs: DEFAULT_READ_TIMEOUT_SECS,
+        }
+    }
+}
+
+pub fn set_http_timeouts(timeouts: HttpTimeouts) {
+    CONNECT_TIMEOUT_SECS.store(timeouts.connect_timeout_secs, Ordering::Relaxed);
+    READ_TIMEOUT_SECS.store(timeouts.read_timeout_secs, Ordering::Relaxed);
+}
+
+fn connect_timeout() -> Duration {
+    Duration::from_secs(CONNECT_TIMEOUT_SECS.load(Ordering::Relaxed))
+}
+
+fn read_timeout() -> Duration {
+    Duration::from_secs(READ_TIMEOUT_SECS.load(Ordering::Relaxed))
+}
+
+/// A client for requests whose response is read in full, which time out if it doesn't arrive
+/// within the read timeout
+pub fn client() -> Client {
+    Client::builder()
+        .connect_timeout(connect_timeout())
+        .timeout(connect_timeout() + read_timeout())
+        .build()
+        .unwrap_or_else(|_| Client::new())
+}
+
+/// Like [`client`], but doesn't follow redirects, for URLs users give which are checked with
+/// [`crate::public_url::check_public_url`] before each request, so a redirect can't get around it
+pub fn client_without_redirects() -> Client {
+    Client::builder()
+        .connect_timeout(connect_timeout())
+        .timeout(connect_timeout() + read_timeout())
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap_or_else(|_| Client::new())
+}
+
+/// A client for downloads, which can take as long as they need as long as data keeps arriving,
+/// see [`next_chunk`]
+pub fn download_client() -> Client {
+    Client::builder()
+        .connect_timeout(connect_timeout())
+        .build()
+        .unwrap_or_else(|_| Client::new())
+}
+
+fn timed_out(url: &str, waiting_for: &str, timeout: Duration) -> Error {
+    Error {
+        kind: ErrorKind::FailedToDownload,
+        source: eyre!(
+            "Timed out waiting {}s for {} from {}, the service may be down or the network too slow",
+            timeout.as_secs_f64(),
+            waiting_for,
+            url
+        ),
+    }
+}
+
+/// Maps timeouts to `FailedToDownload`, anything else is internal
+fn request_error(url: &str, e: reqwest::Error) -> Error {
+    if e.is_timeout() {
+        let (waiting_for, timeout) = if e.is_connect() {
+            ("a connection", connect_timeout())
+        } else {
+            ("a response", read_timeout())
+        };
+        return timed_out(url, waiting_for, timeout);
+    }
+    Error {
+        kind: ErrorKind::Internal,
+        source: eyre!(e).wrap_err(format!("Failed to request {url}")),
+    }
+}
+
+async fn send_in(request: RequestBuilder, url: &str, timeout: Duration) -> Result<Response, Error> {
+    match tokio::time::timeout(timeout, request.send()).await {
+        Ok(response) => response
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| request_error(url, e)),
+        Err(_) => Err(timed_out(url, "a response", timeout)),
+    }
+}
+
+/// Sends a request to `url`, failing if the response doesn't start within the read timeout or
+/// has an error status
+pub async fn send(request: RequestBuilder, url: &str) -> Result<Response, Error> {
+    send_in(request, url, read_timeout()).await
+}
+
+/// GETs `url` as text
+pub async fn get_text(url: &str) -> Result<String, Error> {
+    send(client().get(url), url)
+        .await?
+        .text()
+        .await
+        .map_err(|e| request_error(url, e))
+}
+
+async fn next_chunk_in<T>(
+    stream: &mut (impl Stream<Item = reqwest::Result<T>> + Unpin),
+    url: &str,
+    timeout: Duration,
+) -> Result<Option<T>, Error> {
+    match tokio::time::timeout(timeout, stream.next()).await {
+        Ok(Some(chunk)) => chunk.map(Some).map_err(|e| request_error(url, e)),
+        Ok(None) => Ok(None),
+        Err(_) => Err(timed_out(url, "more data", timeout)),
+    }
+}
+
+/// The next chunk of a download from `url`, failing if none arrives within the read timeout.
+///
+/// The timeout starts over with every chunk, so a long download isn't cut off as long as it keeps
+/// making progress
+pub async fn next_chunk<T>(
+    stream: &mut (impl Stream<Item = reqwest::Result<T>> + Unpin),
+    url: &str,
+) -> Result<Option<T>, Error> {
+    next_chunk_in(stream, url, read_timeout()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Serves a single response with a `body_len` byte body, sending a byte every `byte_interval`
+    /// after the first `sent` bytes, or nothing more if `byte_interval` is `None`
+    async fn slow_server(body_len: usize, sent: usize, byte_interval: Option<Duration>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0_u8; 1024];
+            let _ = stream.read(&mut buf).await.unwrap();
+            stream
+                .write_all(
+                    format!("HTTP/1.1 200 OK\r\nContent-Length: {body_len}\r\n\r\n").as_bytes(),
+                )
+                .await
+                .unwrap();
+            stream.write_all(&vec![b'a'; sent]).await.unwrap();
+            match byte_interval {
+                Some(interval) => {
+                    for _ in sent..body_len {
+                        tokio::time::sleep(interval).await;
+                        stream.write_all(b"a").await.unwrap();
+                    }
+                }
+                // keep the connection open without sending anything
+                None => tokio::time::sleep(Duration::from_secs(60)).await,
+            }
+        });
+        url
+    }
+
+    async fn download(url: &str, timeout: Duration) -> Result<usize, Error> {
+        let response = send_in(Client::new().get(url), url, timeout).await?;
+        let mut stream = response.bytes_stream();
+        let mut downloaded = 0;
+        while let Some(chunk) = next_chunk_in(&mut stream, url, timeout).await? {
+            downloaded += chunk.len();
+        }
+        Ok(downloaded)
+    }
+
+    #[tokio::test]
+    async fn test_stalled_download_times_out() {
+        let url = slow_server(10, 5, None).await;
+        let e = download(&url, Duration::from_millis(300))
+            .await
+            .unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::FailedToDownload));
+    }
+
+    #[tokio::test]
+    async fn test_slow_download_is_not_cut_off() {
+        // takes longer than the timeout in total, but never waits that long for a chunk
+        let url = slow_server(8, 2, Some(Duration::from_millis(100))).await;
+        assert_eq!(download(&url, Duration::from_millis(300)).await.unwrap(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_response_times_out() {
+        // accepts the connection but never responds
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+        let e = send_in(Client::new().get(&url), &url, Duration::from_millis(300))
+            .await
+            .unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::FailedToDownload));
+    }
+}
@@ -1,5 +1,5 @@
 use color_eyre::eyre::{eyre, Context, ContextCompat};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::io::{Read, Write};
 
@@ -8,8 +8,6 @@ use rand::{thread_rng, Rng};
 use std::path::{Path, PathBuf};
 use tokio::io::AsyncWriteExt;
 
-use futures_util::StreamExt;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
@@ -22,8 +20,9 @@ pub struct Authentication {
     password: String,
 }
 
-use crate::error::Error;
+use crate::error::{Error, ErrorKind};
 use crate::prelude::path_to_tmp;
+use crate::upstream_http;
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[ts(export)]
 pub struct SetupProgress {
@@ -56,17 +55,7 @@ pub async fn download_file(
     let mut temp_file = tokio::fs::File::create(&temp_file_path)
         .await
         .context("Failed to create temporary file")?;
-    let client = Client::new();
-    let response = client
-        .get(url)
-        .send()
-        .await
-        .context("Failed to send GET request")?;
-    response.error_for_status_ref().context(
-        "
-        Failed to download file
-    ",
-    )?;
+    let response = upstream_http::send(upstream_http::download_client().get(url), url).await?;
     tokio::fs::create_dir_all(path)
         .await
         .context(format!("Failed to create dir {}", &path.display()))?;
@@ -103,8 +92,7 @@ pub async fn download_file(
     let mut new_downloaded: u64 = 0;
     let threshold = total_size.unwrap_or(500000) / 100;
     let mut stream = response.bytes_stream();
-    while let Some(item) = stream.next().await {
-        let chunk = item.context("Failed to read response")?;
+    while let Some(chunk) = upstream_http::next_chunk(&mut stream, url).await? {
         temp_file
             .write_all(&chunk)
             .await
@@ -472,10 +460,10 @@ pub fn scoped_join_win_safe<R: AsRef<Path>, U: AsRef<Path>>(
 pub mod fs {
     use std::path::Path;
 
-    use color_eyre::eyre::Context;
+    use color_eyre::eyre::{eyre, Context};
     use tokio::fs::File;
 
-    use crate::error::Error;
+    use crate::error::{Error, ErrorKind};
 
     pub async fn remove_file(file: impl AsRef<Path>) -> Result<(), Error> {
         let file = file.as_ref();
@@ -514,12 +502,73 @@ pub mod fs {
         Ok(())
     }
 
+    /// Removes `dir` without traversing symlinks, so a link to somewhere else is removed
+    /// rather than the files it points to
+    fn remove_dir_all_no_follow(dir: &Path) -> std::io::Result<()> {
+        if !std::fs::symlink_metadata(dir)?.is_dir() {
+            return std::fs::remove_file(dir);
+        }
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            // the file type of an entry is that of the link itself, not its target
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                remove_dir_all_no_follow(&entry.path())?;
+            } else if file_type.is_symlink() {
+                // symlinks to directories are removed like directories on windows
+                std::fs::remove_file(entry.path())
+                    .or_else(|_| std::fs::remove_dir(entry.path()))?;
+            } else {
+                std::fs::remove_file(entry.path())?;
+            }
+        }
+        std::fs::remove_dir(dir)
+    }
+
     pub async fn remove_dir_all(dir: impl AsRef<Path>) -> Result<(), Error> {
+        let dir = dir.as_ref().to_owned();
+        tokio::task::spawn_blocking({
+            let dir = dir.clone();
+            move || remove_dir_all_no_follow(&dir)
+        })
+        .await
+        .context("Failed to spawn blocking task")?
+        .context(format!("Failed to remove directory at {}", dir.display()))?;
+        Ok(())
+    }
+
+    /// Like `remove_dir_all`, but refuses to remove anything that isn't strictly inside `root`
+    pub async fn remove_dir_all_within(
+        dir: impl AsRef<Path>,
+        root: impl AsRef<Path>,
+    ) -> Result<(), Error> {
         let dir = dir.as_ref();
-        tokio::fs::remove_dir_all(dir)
+        let root = root.as_ref();
+        let outside_root = || Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!(
+                "Refusing to remove {}, it is not inside {}",
+                dir.display(),
+                root.display()
+            ),
+        };
+        // only the parent is resolved, so that `..` or a symlinked parent can't escape the root,
+        // while `dir` itself being a symlink only gets the link removed
+        let (parent, name) = match (dir.parent(), dir.file_name()) {
+            (Some(parent), Some(name)) => (parent, name),
+            _ => return Err(outside_root()),
+        };
+        let canonical_parent = tokio::fs::canonicalize(parent).await.context(format!(
+            "Failed to resolve directory at {}",
+            parent.display()
+        ))?;
+        let canonical_root = tokio::fs::canonicalize(root)
             .await
-            .context(format!("Failed to remove directory at {}", dir.display()))?;
-        Ok(())
+            .context(format!("Failed to resolve directory at {}", root.display()))?;
+        if !canonical_parent.starts_with(&canonical_root) {
+            return Err(outside_root());
+        }
+        remove_dir_all(canonical_parent.join(name)).await
     }
 
     pub async fn read_to_string(file: impl AsRef<Path>) -> Result<String, Error> {
@@ -545,6 +594,115 @@ pub fn dont_spawn_terminal(cmd: &mut tokio::process::Command) -> &mut tokio::pro
     cmd
 }
 
+/// Checks that environment variables can be passed to a process
+pub fn validate_env(env: &HashMap<String, String>) -> Result<(), Error> {
+    for (key, value) in env {
+        if key.is_empty() || key.contains('=') || key.contains('\0') {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid environment variable name {:?}", key),
+            });
+        }
+        if value.contains('\0') {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Value of environment variable {} contains a null byte", key),
+            });
+        }
+    }
+    Ok(())
+}
+
+const MAX_TAG_LEN: usize = 32;
+const MAX_TAGS: usize = 20;
+
+/// Checks that tags are short and only made of ascii alphanumerics, '-' and '_', removing duplicates
+pub fn validate_tags(tags: Vec<String>) -> Result<Vec<String>, Error> {
+    let mut validated: Vec<String> = Vec::new();
+    for tag in tags {
+        if tag.is_empty() || tag.len() > MAX_TAG_LEN {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Tags must be between 1 and {} characters long", MAX_TAG_LEN),
+            });
+        }
+        if !tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Invalid tag {:?}, tags can only contain letters, numbers, '-' and '_'",
+                    tag
+                ),
+            });
+        }
+        if !validated.contains(&tag) {
+            validated.push(tag);
+        }
+    }
+    if validated.len() > MAX_TAGS {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("An instance cannot have more than {} tags", MAX_TAGS),
+        });
+    }
+    Ok(validated)
+}
+
+/// Lists environment variables for logging, values are left out since they may contain secrets
+pub fn redact_env(env: &HashMap<String, String>) -> String {
+    let mut keys: Vec<&String> = env.keys().collect();
+    keys.sort();
+    keys.iter()
+        .map(|key| format!("{}=<redacted>", key))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Formats a UTC timestamp (in seconds) in the given timezone, for use in human readable names.
+///
+/// The UTC offset is part of the output, so that the repeated hour at the end of daylight saving time
+/// doesn't produce the same name twice.
+pub fn format_local_timestamp(timestamp: i64, timezone: &chrono_tz::Tz) -> String {
+    use chrono::TimeZone;
+    timezone
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .unwrap_or_else(|| timezone.timestamp_opt(0, 0).unwrap())
+        .format("%Y-%m-%d_%H-%M-%S%z")
+        .to_string()
+}
+
+/// Checks if a process spawned by a previous run of Lodestone is still alive.
+///
+/// Since the OS is free to reuse pids, the process is only considered to belong to the instance
+/// if its working directory is the instance directory.
+/// A working directory that cannot be read doesn't match, so an unrelated process is never adopted.
+pub fn find_orphaned_process(
+    system: &mut sysinfo::System,
+    pid: u32,
+    path_to_instance: &Path,
+) -> Option<sysinfo::Pid> {
+    use sysinfo::{PidExt, ProcessExt, SystemExt};
+    let pid = sysinfo::Pid::from_u32(pid);
+    if !system.refresh_process(pid) {
+        return None;
+    }
+    let process = system.process(pid)?;
+    let cwd = process.cwd();
+    if cwd.as_os_str().is_empty() {
+        return None;
+    }
+    let canonicalize = |p: &Path| std::fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+    if canonicalize(cwd) == canonicalize(path_to_instance) {
+        Some(pid)
+    } else {
+        None
+    }
+}
+
 pub fn format_byte_download(mut bytes: u64, mut total: u64) -> String {
     let mut unit = "B";
     if bytes > 1024 {
@@ -630,8 +788,11 @@ pub fn format_byte(mut bytes: u64) -> String {
 #[cfg(test)]
 mod tests {
     use crate::prelude::init_paths;
-    use crate::util::{resolve_path_conflict, unzip_file, zip_files, UnzipOption};
-    use std::collections::HashSet;
+    use crate::util::{
+        find_orphaned_process, format_local_timestamp, redact_env, resolve_path_conflict,
+        unzip_file, validate_env, validate_tags, zip_files, UnzipOption,
+    };
+    use std::collections::{HashMap, HashSet};
     use std::io::Read;
     use std::path::PathBuf;
     use tokio;
@@ -640,7 +801,7 @@ mod tests {
     async fn test_unzip_file() {
         let temp_lodestone_path = tempfile::tempdir().unwrap();
         let temp_lodestone_path = temp_lodestone_path.path();
-        init_paths(temp_lodestone_path.to_path_buf());
+        init_paths(temp_lodestone_path.to_path_buf(), None);
         let temp = tempdir::TempDir::new("test_unzip_file").unwrap();
         let temp_path = temp.path();
         let zip = PathBuf::from("testdata/sample.zip");
@@ -670,7 +831,7 @@ mod tests {
     async fn test_unzip_file_3() {
         let temp_lodestone_path = tempfile::tempdir().unwrap();
         let temp_lodestone_path = temp_lodestone_path.path();
-        init_paths(temp_lodestone_path.to_path_buf());
+        init_paths(temp_lodestone_path.to_path_buf(), None);
         let temp = tempdir::TempDir::new("test_unzip_file").unwrap();
         let dest_path = temp.path().to_path_buf();
         let tar_gz = PathBuf::from("testdata/sample.gz");
@@ -702,7 +863,7 @@ mod tests {
     fn test_resolve_path_conflict() {
         let temp_lodestone_path = tempfile::tempdir().unwrap();
         let temp_lodestone_path = temp_lodestone_path.path();
-        init_paths(temp_lodestone_path.to_path_buf());
+        init_paths(temp_lodestone_path.to_path_buf(), None);
         let temp = tempdir::TempDir::new("test_unzip_file").unwrap();
         let temp_path = temp.path();
         let txt_path = temp_path.join("test.txt");
@@ -818,4 +979,141 @@ mod tests {
         buf_reader.read_to_string(&mut contents).unwrap();
         assert_eq!(contents.trim(), "test2_test2_test1");
     }
+
+    #[test]
+    fn test_find_orphaned_process() {
+        use sysinfo::SystemExt;
+        let mut system = sysinfo::System::new();
+        let cwd = std::env::current_dir().unwrap();
+        let pid = std::process::id();
+
+        // a live process running in a different directory is not ours
+        if cfg!(target_os = "linux") {
+            assert!(find_orphaned_process(&mut system, pid, &cwd).is_some());
+            let temp = tempfile::tempdir().unwrap();
+            assert!(find_orphaned_process(&mut system, pid, temp.path()).is_none());
+        }
+
+        // a process that has exited is not an orphan
+        let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+            .arg("--help")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let child_pid = child.id();
+        child.wait().unwrap();
+        assert!(find_orphaned_process(&mut system, child_pid, &cwd).is_none());
+    }
+
+    #[test]
+    fn test_instance_env() {
+        let mut env = HashMap::new();
+        env.insert("LODESTONE_TEST_ENV".to_string(), "secret_value".to_string());
+        assert!(validate_env(&env).is_ok());
+        assert_eq!(redact_env(&env), "LODESTONE_TEST_ENV=<redacted>");
+
+        // the env is passed through to the spawned process
+        if cfg!(unix) {
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg("printf %s \"$LODESTONE_TEST_ENV\"")
+                .envs(&env)
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "secret_value");
+        }
+
+        env.insert("BAD\0KEY".to_string(), "value".to_string());
+        assert!(validate_env(&env).is_err());
+        env.clear();
+        env.insert("KEY".to_string(), "bad\0value".to_string());
+        assert!(validate_env(&env).is_err());
+        env.clear();
+        env.insert("KEY=".to_string(), "value".to_string());
+        assert!(validate_env(&env).is_err());
+    }
+
+    #[test]
+    fn test_validate_tags() {
+        assert_eq!(
+            validate_tags(vec![
+                "survival".to_string(),
+                "event_2023-summer".to_string(),
+                "survival".to_string()
+            ])
+            .unwrap(),
+            vec!["survival".to_string(), "event_2023-summer".to_string()]
+        );
+        assert!(validate_tags(vec!["".to_string()]).is_err());
+        assert!(validate_tags(vec!["has space".to_string()]).is_err());
+        assert!(validate_tags(vec!["a".repeat(33)]).is_err());
+        assert!(validate_tags((0..21).map(|i| i.to_string()).collect()).is_err());
+    }
+
+    #[test]
+    fn test_format_local_timestamp() {
+        let tz: chrono_tz::Tz = "America/Toronto".parse().unwrap();
+        assert_eq!(
+            format_local_timestamp(1_700_000_000, &tz),
+            "2023-11-14_17-13-20-0500"
+        );
+        // 2023-11-05 01:30 happens twice in Toronto
+        let first = format_local_timestamp(1_699_162_200, &tz);
+        let second = format_local_timestamp(1_699_165_800, &tz);
+        assert_ne!(first, second);
+        assert!("Not/AZone".parse::<chrono_tz::Tz>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_remove_dir_all_does_not_follow_symlinks() {
+        let temp = tempfile::tempdir().unwrap();
+        let instances = temp.path().join("instances");
+        let instance = instances.join("instance");
+        let outside = temp.path().join("outside");
+        std::fs::create_dir_all(instance.join("world")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("precious.txt"), "keep me").unwrap();
+        std::os::unix::fs::symlink(&outside, instance.join("world").join("escape")).unwrap();
+        std::os::unix::fs::symlink(
+            outside.join("precious.txt"),
+            instance.join("file_escape.txt"),
+        )
+        .unwrap();
+
+        crate::util::fs::remove_dir_all_within(&instance, &instances)
+            .await
+            .unwrap();
+        assert!(!instance.exists());
+        assert_eq!(
+            std::fs::read_to_string(outside.join("precious.txt")).unwrap(),
+            "keep me"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_dir_all_within_root() {
+        let temp = tempfile::tempdir().unwrap();
+        let instances = temp.path().join("instances");
+        let outside = temp.path().join("outside");
+        std::fs::create_dir_all(&instances).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+
+        assert!(crate::util::fs::remove_dir_all_within(&outside, &instances)
+            .await
+            .is_err());
+        assert!(crate::util::fs::remove_dir_all_within(
+            instances.join("..").join("outside"),
+            &instances
+        )
+        .await
+        .is_err());
+        assert!(
+            crate::util::fs::remove_dir_all_within(&instances, &instances)
+                .await
+                .is_err()
+        );
+        assert!(outside.exists());
+        assert!(instances.exists());
+    }
 }